@@ -66,7 +66,8 @@ CREATE TABLE IF NOT EXISTS api."user"
     create_at  timestamp with time zone                           NOT NULL,
     update_at  timestamp with time zone                           NOT NULL,
     delete_at  timestamp with time zone                           DEFAULT '1970-01-01 00:00:00 +00:00:00',
-    CONSTRAINT user_pkey PRIMARY KEY (id)
+    CONSTRAINT user_pkey PRIMARY KEY (id),
+    CONSTRAINT user_account_id_delete_at UNIQUE (account_id, delete_at)
 )
     TABLESPACE pg_default;
 
@@ -141,7 +142,7 @@ CREATE INDEX IF NOT EXISTS msg_history_index
 -- DROP TYPE IF EXISTS api.user_relationship_status;
 
 CREATE TYPE api.user_relationship_status AS ENUM
-    ('normal', 'lover', 'best_friend', 'deleting', 'deleted', 'blocked');
+    ('normal', 'lover', 'best_friend', 'deleting', 'deleted', 'blocked', 'pending');
 
 ALTER TYPE api.user_relationship_status
     OWNER TO prim;
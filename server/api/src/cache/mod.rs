@@ -1,31 +1,27 @@
 use crate::config::config;
-use lib::cache::redis_ops::RedisOps;
+use lib::cache::redis_ops::{RedisOps, RedisPool};
 use tokio::sync::OnceCell;
 
-/// use singleton instance by it's all clones to share connection between Tasks.
-pub(crate) static REDIS_OPS: OnceCell<RedisOps> = OnceCell::const_new();
+/// shared pool of connections between Tasks, broken connections are replaced on borrowing.
+pub(crate) static REDIS_POOL: OnceCell<RedisPool> = OnceCell::const_new();
 
 pub(super) async fn get_redis_ops() -> RedisOps {
-    (REDIS_OPS
+    REDIS_POOL
         .get_or_init(|| async {
             let passwords = if config().redis.passwords.is_empty() {
                 None
             } else {
                 Some(config().redis.passwords.clone())
             };
-            RedisOps::connect(config().redis.addresses.clone(), passwords)
-                .await
-                .unwrap()
+            RedisPool::new(
+                config().redis.addresses.clone(),
+                passwords,
+                config().redis.pool_size,
+            )
+            .await
+            .unwrap()
         })
-        .await)
-        .clone()
+        .await
+        .get()
+        .await
 }
-
-pub(crate) static USER_TOKEN: &str = "USER_TOKEN_";
-pub(crate) static JOIN_GROUP: &str = "JOIN_GROUP_";
-pub(crate) static CHECK_CODE : &str = "CHECK_CODE_";
-pub(crate) static LAST_ONLINE_TIME: &str = "LAST_ONLINE_TIME_";
-pub(crate) static LAST_READ: &str = "LAST_READ_";
-pub(crate) static USER_INBOX: &str = "USER_INBOX_";
-pub(crate) static MSG_CACHE: &str = "MSG_CACHE_";
-pub(crate) static ADD_FRIEND: &str = "ADD_FRIEND_";
@@ -1,6 +1,14 @@
-use std::{fs, net::{ToSocketAddrs, SocketAddr}, path::PathBuf};
+use std::{fs, net::{ToSocketAddrs, SocketAddr}, path::PathBuf, time::Duration};
 
 use anyhow::Context;
+use lib::{
+    util::{
+        env::{load_config_table, ENV_PREFIX},
+        parse_log_level,
+        time::set_clock_offset,
+    },
+    Result,
+};
 use tracing::Level;
 
 #[derive(serde::Deserialize, Debug)]
@@ -10,6 +18,8 @@ struct Config0 {
     redis: Option<Redis0>,
     rpc: Option<Rpc0>,
     sql: Option<Sql0>,
+    gateway: Option<Gateway0>,
+    api: Option<Api0>,
 }
 
 #[derive(Debug)]
@@ -19,6 +29,9 @@ pub(crate) struct Config {
     pub(crate) redis: Redis,
     pub(crate) rpc: Rpc,
     pub(crate) sql: Sql,
+    /// websocket gateway to the message nodes, disabled if not configured.
+    pub(crate) gateway: Option<Gateway>,
+    pub(crate) api: Api,
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -26,6 +39,7 @@ struct Server0 {
     service_address: Option<String>,
     cert_path: Option<String>,
     key_path: Option<String>,
+    clock_offset: Option<i64>,
 }
 
 #[derive(Debug)]
@@ -33,18 +47,22 @@ pub(crate) struct Server {
     pub(crate) service_address: SocketAddr,
     pub(crate) cert: rustls::Certificate,
     pub(crate) key: rustls::PrivateKey,
+    /// in milliseconds, added to the local clock to correct its skew from ntp.
+    pub(crate) clock_offset: i64,
 }
 
 #[derive(serde::Deserialize, Debug)]
 struct Redis0 {
     addresses: Option<Vec<String>>,
     passwords: Option<Vec<String>>,
+    pool_size: Option<usize>,
 }
 
 #[derive(Debug)]
 pub(crate) struct Redis {
     pub(crate) addresses: Vec<SocketAddr>,
     pub(crate) passwords: Vec<String>,
+    pub(crate) pool_size: usize,
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -52,6 +70,10 @@ struct RpcScheduler0 {
     address: Option<String>,
     domain: Option<String>,
     cert_path: Option<String>,
+    deadline: Option<u64>,
+    retries: Option<usize>,
+    backoff: Option<u64>,
+    stale_ttl: Option<u64>,
 }
 
 #[derive(Debug)]
@@ -59,6 +81,14 @@ pub(crate) struct RpcScheduler {
     pub(crate) address: String,
     pub(crate) domain: String,
     pub(crate) cert: tonic::transport::Certificate,
+    /// bound of every single attempt.
+    pub(crate) deadline: Duration,
+    /// extra attempts of idempotent calls on transient failures.
+    pub(crate) retries: usize,
+    /// wait before the first retry, doubled for each of the next.
+    pub(crate) backoff: Duration,
+    /// how long a lookup result may still be served when scheduler is unreachable.
+    pub(crate) stale_ttl: Duration,
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -84,6 +114,8 @@ struct Sql0 {
     username: Option<String>,
     password: Option<String>,
     max_connections: Option<u32>,
+    retries: Option<usize>,
+    backoff: Option<u64>,
 }
 
 #[derive(Debug)]
@@ -93,24 +125,60 @@ pub(crate) struct Sql {
     pub(crate) username: String,
     pub(crate) password: String,
     pub(crate) max_connections: u32,
+    /// extra attempts of a write failed by a deadlock or a serialization failure.
+    pub(crate) retries: usize,
+    pub(crate) backoff: Duration,
 }
 
+#[derive(serde::Deserialize, Debug)]
+struct Gateway0 {
+    domain: Option<String>,
+    cert_path: Option<String>,
+    keep_alive_interval: Option<u64>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Gateway {
+    pub(crate) domain: String,
+    pub(crate) cert: rustls::Certificate,
+    pub(crate) keep_alive_interval: Duration,
+}
+
+#[derive(serde::Deserialize, Debug, Default)]
+struct Api0 {
+    bind_address: Option<String>,
+    worker_threads: Option<usize>,
+    max_body_size: Option<usize>,
+}
+
+/// the http server, all optional so an old config file still works.
+#[derive(Debug)]
+pub(crate) struct Api {
+    /// `server.service_address` by default.
+    pub(crate) bind_address: SocketAddr,
+    /// number of cpus by default.
+    pub(crate) worker_threads: usize,
+    /// requests with a larger body are rejected with 413.
+    pub(crate) max_body_size: usize,
+}
+
+/// bodies end up in msgs at most, whose payload length field is 14 bits, so 16 KiB of payload,
+/// the rest is room for the json around it, a larger limit is useless.
+pub(crate) const MAX_BODY_SIZE: usize = 64 * 1024;
+
 impl Config {
     fn from_config0(config0: Config0) -> Config {
-        let log_level = match config0.log_level.unwrap().as_str() {
-            "trace" => Level::TRACE,
-            "debug" => Level::DEBUG,
-            "info" => Level::INFO,
-            "warn" => Level::WARN,
-            "error" => Level::ERROR,
-            _ => Level::INFO,
-        };
+        let log_level = parse_log_level(config0.log_level);
+        let server = Server::from_server0(config0.server.unwrap());
+        let api = Api::from_api0(config0.api.unwrap_or_default(), &server);
         Config {
             log_level,
-            server: Server::from_server0(config0.server.unwrap()),
+            server,
             redis: Redis::from_redis0(config0.redis.unwrap()),
             rpc: Rpc::from_rpc0(config0.rpc.unwrap()),
             sql: Sql::from_sql0(config0.sql.unwrap()),
+            gateway: config0.gateway.map(Gateway::from_gateway0),
+            api,
         }
     }
 }
@@ -132,6 +200,7 @@ impl Server {
                 .collect::<Vec<SocketAddr>>()[0],
             cert: rustls::Certificate(cert),
             key: rustls::PrivateKey(key),
+            clock_offset: server0.clock_offset.unwrap_or(0),
         }
     }
 }
@@ -145,7 +214,11 @@ impl Redis {
                 .expect("parse redis address failed")
                 .collect::<Vec<SocketAddr>>()[0]);
         }
-        Redis { addresses: addr, passwords: redis0.passwords.unwrap_or(vec![]) }
+        Redis {
+            addresses: addr,
+            passwords: redis0.passwords.unwrap_or(vec![]),
+            pool_size: redis0.pool_size.unwrap_or(4),
+        }
     }
 }
 
@@ -160,6 +233,10 @@ impl RpcScheduler {
                     .unwrap()
                     .as_slice(),
             ),
+            deadline: Duration::from_millis(rpc_balancer0.deadline.unwrap_or(3000)),
+            retries: rpc_balancer0.retries.unwrap_or(2),
+            backoff: Duration::from_millis(rpc_balancer0.backoff.unwrap_or(100)),
+            stale_ttl: Duration::from_millis(rpc_balancer0.stale_ttl.unwrap_or(60000)),
         }
     }
 }
@@ -192,14 +269,84 @@ impl Sql {
             username: sql0.username.unwrap(),
             password: sql0.password.unwrap(),
             max_connections: sql0.max_connections.unwrap(),
+            retries: sql0.retries.unwrap_or(2),
+            backoff: Duration::from_millis(sql0.backoff.unwrap_or(50)),
         }
     }
 }
 
-pub(crate) fn load_config(config_path: &str) {
-    let toml_str = fs::read_to_string(config_path).unwrap();
-    let config0: Config0 = toml::from_str(&toml_str).unwrap();
-    unsafe { CONFIG.replace(Config::from_config0(config0)) };
+impl Gateway {
+    fn from_gateway0(gateway0: Gateway0) -> Self {
+        let cert = fs::read(PathBuf::from(gateway0.cert_path.as_ref().unwrap()))
+            .context("read cert file failed.")
+            .unwrap();
+        Gateway {
+            domain: gateway0.domain.unwrap(),
+            cert: rustls::Certificate(cert),
+            keep_alive_interval: Duration::from_millis(
+                gateway0.keep_alive_interval.unwrap_or(3000),
+            ),
+        }
+    }
+}
+
+impl Api {
+    fn from_api0(api0: Api0, server: &Server) -> Self {
+        let bind_address = match api0.bind_address {
+            Some(bind_address) => bind_address
+                .to_socket_addrs()
+                .expect("parse bind address failed")
+                .collect::<Vec<SocketAddr>>()[0],
+            None => server.service_address,
+        };
+        let max_body_size = api0.max_body_size.unwrap_or(MAX_BODY_SIZE);
+        if max_body_size == 0 || max_body_size > MAX_BODY_SIZE {
+            panic!(
+                "max_body_size should be in 1..={}, got {}",
+                MAX_BODY_SIZE, max_body_size
+            );
+        }
+        Api {
+            bind_address,
+            worker_threads: api0.worker_threads.filter(|n| *n > 0).unwrap_or_else(|| {
+                std::thread::available_parallelism()
+                    .map(|n| n.get())
+                    .unwrap_or(1)
+            }),
+            max_body_size,
+        }
+    }
+}
+
+/// fields overridable by an env var named after them, e.g. `sql.address` by `PRIM_SQL_ADDRESS`,
+/// see `apply_env_overrides`.
+pub(self) const ENV_KEYS: [&str; 12] = [
+    "log_level",
+    "server.service_address",
+    "redis.addresses[]",
+    "redis.passwords[]",
+    "rpc.address",
+    "rpc.scheduler.address",
+    "rpc.scheduler.domain",
+    "sql.address",
+    "sql.database",
+    "sql.username",
+    "sql.password",
+    "api.bind_address",
+];
+
+/// env > file > default.
+pub(crate) fn load_config(config_path: &str) -> Result<()> {
+    let table = load_config_table(config_path, ENV_PREFIX, &ENV_KEYS, |name| {
+        std::env::var(name).ok()
+    })?;
+    let config0: Config0 = toml::Value::Table(table)
+        .try_into()
+        .with_context(|| format!("parse config file {} failed.", config_path))?;
+    let config = Config::from_config0(config0);
+    set_clock_offset(config.server.clock_offset);
+    unsafe { CONFIG.replace(config) };
+    Ok(())
 }
 
 pub(self) static mut CONFIG: Option<Config> = None;
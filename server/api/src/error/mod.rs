@@ -2,7 +2,7 @@ use async_trait::async_trait;
 use chrono::Local;
 use salvo::{prelude::StatusCode, Depot, Request, Response, Writer};
 
-use crate::handler::ResponseResult;
+use crate::handler::ApiResponse;
 
 pub(crate) enum HandlerError {
     /// used for leak of request parameters.
@@ -13,37 +13,48 @@ pub(crate) enum HandlerError {
     InternalError(String),
 }
 
+impl HandlerError {
+    pub(crate) fn code(&self) -> u32 {
+        match self {
+            HandlerError::ParameterMismatch(_) => 400,
+            HandlerError::RequestMismatch(code, _) => *code,
+            HandlerError::InternalError(_) => 500,
+        }
+    }
+
+    /// what kind of error it is, for the clients to match on instead of the message.
+    pub(crate) fn error_code(&self) -> &'static str {
+        match self {
+            HandlerError::ParameterMismatch(_) => "parameter_mismatch",
+            HandlerError::RequestMismatch(_, _) => "request_mismatch",
+            HandlerError::InternalError(_) => "internal_error",
+        }
+    }
+}
+
+impl<T> From<HandlerError> for ApiResponse<T> {
+    fn from(err: HandlerError) -> Self {
+        let code = err.code();
+        let error_code = err.error_code().to_string();
+        let message = match err {
+            HandlerError::ParameterMismatch(msg) => msg,
+            HandlerError::RequestMismatch(_, msg) => msg,
+            HandlerError::InternalError(msg) => msg,
+        };
+        ApiResponse::Err {
+            code,
+            message,
+            timestamp: Local::now(),
+            error_code,
+            data: (),
+        }
+    }
+}
+
 #[async_trait]
 impl Writer for HandlerError {
     async fn write(self, _req: &mut Request, _depot: &mut Depot, resp: &mut Response) {
-        match self {
-            HandlerError::ParameterMismatch(msg) => {
-                resp.status_code(StatusCode::OK);
-                resp.render(ResponseResult {
-                    code: 400,
-                    message: &msg,
-                    timestamp: Local::now(),
-                    data: (),
-                })
-            }
-            HandlerError::RequestMismatch(code, msg) => {
-                resp.status_code(StatusCode::OK);
-                resp.render(ResponseResult {
-                    code,
-                    message: &msg,
-                    timestamp: Local::now(),
-                    data: (),
-                })
-            }
-            HandlerError::InternalError(msg) => {
-                resp.status_code(StatusCode::OK);
-                resp.render(ResponseResult {
-                    code: 500,
-                    message: &msg,
-                    timestamp: Local::now(),
-                    data: (),
-                })
-            }
-        }
+        resp.status_code(StatusCode::OK);
+        resp.render(ApiResponse::<()>::from(self))
     }
 }
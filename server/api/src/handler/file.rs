@@ -1,6 +1,6 @@
 use salvo::{handler, Request, Response};
 
-use super::{HandlerResult, ResponseResult};
+use super::{ApiResponse, HandlerResult};
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 struct UploadReq {
@@ -11,23 +11,20 @@ struct UploadReq {
 }
 
 #[handler]
-pub(crate) async fn upload(_req: &mut Request, _res: &mut Response) -> HandlerResult<'static, ()> {
-    Ok(ResponseResult {
+pub(crate) async fn upload(_req: &mut Request, _res: &mut Response) -> HandlerResult<()> {
+    Ok(ApiResponse::Ok {
         code: 200,
-        message: "success",
+        message: "success".to_string(),
         timestamp: chrono::Local::now(),
         data: (),
     })
 }
 
 #[handler]
-pub(crate) async fn download(
-    _req: &mut Request,
-    _res: &mut Response,
-) -> HandlerResult<'static, ()> {
-    Ok(ResponseResult {
+pub(crate) async fn download(_req: &mut Request, _res: &mut Response) -> HandlerResult<()> {
+    Ok(ApiResponse::Ok {
         code: 200,
-        message: "success",
+        message: "success".to_string(),
         timestamp: chrono::Local::now(),
         data: (),
     })
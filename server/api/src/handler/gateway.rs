@@ -0,0 +1,347 @@
+use std::{fmt::Display, net::ToSocketAddrs, sync::Arc};
+
+use anyhow::anyhow;
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use lib::{
+    entity::{Head, Msg, EXTENSION_THRESHOLD, HEAD_LEN, PAYLOAD_THRESHOLD},
+    net::client::ClientConfigBuilder,
+    Result,
+};
+use lib_net_tokio::net::{client::Client, MsgMpmcSender, MsgMpscReceiver};
+use salvo::{
+    handler,
+    hyper::header,
+    websocket::{Message, WebSocketUpgrade},
+    Request, Response,
+};
+use tokio::select;
+use tracing::{debug, error};
+
+use crate::{cache::get_redis_ops, config::config, error::HandlerError, rpc::get_rpc_client};
+
+use super::{middleware::request_id_of, verify_user_token};
+
+/// browsers can't set headers on websocket, so they offer this subprotocol along with the token
+/// instead, as `Sec-WebSocket-Protocol: prim, <token>`.
+pub(self) const GATEWAY_PROTOCOL: &str = "prim";
+/// a whole msg is carried by one websocket message, so nothing larger is accepted.
+pub(self) const MAX_MESSAGE_SIZE: usize = HEAD_LEN + EXTENSION_THRESHOLD + PAYLOAD_THRESHOLD;
+
+pub(self) const CLOSE_GOING_AWAY: u16 = 1001;
+pub(self) const CLOSE_UNSUPPORTED_DATA: u16 = 1003;
+pub(self) const CLOSE_INVALID_PAYLOAD: u16 = 1007;
+pub(self) const CLOSE_POLICY_VIOLATION: u16 = 1008;
+pub(self) const CLOSE_INTERNAL_ERROR: u16 = 1011;
+
+/// bridge a websocket client, such as a browser, to the message node the user belongs to.
+/// every binary websocket message carries exactly one `Msg`, in both directions.
+/// the token is taken from the `Authorization` header, or the `Sec-WebSocket-Protocol` one, see
+/// [`GATEWAY_PROTOCOL`].
+#[handler]
+pub(crate) async fn gateway(
+    req: &mut Request,
+    resp: &mut Response,
+) -> std::result::Result<(), HandlerError> {
+    let gateway_config = match config().gateway.as_ref() {
+        Some(gateway_config) => gateway_config,
+        None => {
+            return Err(HandlerError::RequestMismatch(
+                404,
+                "gateway is not enabled.".to_string(),
+            ))
+        }
+    };
+    let (token, by_protocol) = match token_of(req) {
+        Some(token) => token,
+        None => {
+            return Err(HandlerError::RequestMismatch(
+                401,
+                "unauthorized.".to_string(),
+            ))
+        }
+    };
+    let mut redis_ops = get_redis_ops().await;
+    let user_id = match verify_user_token(&token, &mut redis_ops).await {
+        Ok(user_id) => user_id,
+        Err(_err) => {
+            return Err(HandlerError::RequestMismatch(
+                401,
+                "unauthorized.".to_string(),
+            ))
+        }
+    };
+    let request_id = request_id_of(req);
+    let mut rpc_client = get_rpc_client().await;
+    let node_id = match rpc_client
+        .call_which_node_or_recent(user_id, request_id.as_deref())
+        .await
+    {
+        Ok((node_id, _age)) => node_id,
+        Err(err) => {
+            error!("which_node error: {}", err.to_string());
+            return Err(HandlerError::RequestMismatch(
+                503,
+                "scheduler is unavailable.".to_string(),
+            ));
+        }
+    };
+    let address = match rpc_client
+        .call_which_to_connect_or_recent(user_id, request_id.as_deref())
+        .await
+    {
+        Ok((address, _age)) => address,
+        Err(err) => {
+            error!("which_address error: {}", err.to_string());
+            return Err(HandlerError::RequestMismatch(
+                503,
+                "scheduler is unavailable.".to_string(),
+            ));
+        }
+    };
+    let address = match address.to_socket_addrs().ok().and_then(|mut a| a.next()) {
+        Some(address) => address,
+        None => {
+            return Err(HandlerError::InternalError(format!(
+                "invalid node address: {}.",
+                address
+            )))
+        }
+    };
+    let mut config_builder = ClientConfigBuilder::default();
+    config_builder
+        .with_remote_address(address)
+        .with_ipv4_type(address.is_ipv4())
+        .with_domain(gateway_config.domain.clone())
+        .with_cert(gateway_config.cert.clone())
+        .with_keep_alive_interval(gateway_config.keep_alive_interval)
+        .with_max_bi_streams(1);
+    let client_config = match config_builder.build() {
+        Ok(client_config) => client_config,
+        Err(err) => return Err(HandlerError::InternalError(err.to_string())),
+    };
+    let upgrade = WebSocketUpgrade::new()
+        .max_message_size(MAX_MESSAGE_SIZE)
+        .max_frame_size(MAX_MESSAGE_SIZE);
+    let res = upgrade
+        .upgrade(req, resp, move |socket| async move {
+            let (mut sink, stream) = socket.split();
+            let mut client = Client::new(client_config);
+            let channel = match client.run().await {
+                Ok(_) => client.io_channel_token(user_id, 0, node_id, &token).await,
+                Err(err) => Err(err),
+            };
+            let (io_sender, io_receiver) = match channel {
+                Ok(channel) => channel,
+                Err(err) => {
+                    error!("connect to node {} failed: {}", address, err);
+                    _ = sink
+                        .send(Message::close_with(
+                            CLOSE_INTERNAL_ERROR,
+                            "node is unavailable.",
+                        ))
+                        .await;
+                    return;
+                }
+            };
+            if let Err(err) = bridge(sink, stream, user_id, io_sender, io_receiver).await {
+                debug!("gateway of user {} closed: {}", user_id, err);
+            }
+            // closing the websocket tears down the connection to the node.
+            drop(client);
+        })
+        .await;
+    if let Err(err) = res {
+        return Err(HandlerError::ParameterMismatch(err.brief));
+    }
+    if by_protocol {
+        resp.headers_mut().insert(
+            header::SEC_WEBSOCKET_PROTOCOL,
+            header::HeaderValue::from_static(GATEWAY_PROTOCOL),
+        );
+    }
+    Ok(())
+}
+
+/// the token from `Authorization`, or the one offered along with [`GATEWAY_PROTOCOL`], in which
+/// case the subprotocol should be accepted.
+pub(self) fn token_of(req: &Request) -> Option<(String, bool)> {
+    if let Some(token) = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|token| token.to_str().ok())
+    {
+        return Some((token.to_string(), false));
+    }
+    let protocols = req
+        .headers()
+        .get_all(header::SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|protocol| protocol.trim())
+        .collect::<Vec<&str>>();
+    if !protocols.contains(&GATEWAY_PROTOCOL) {
+        return None;
+    }
+    protocols
+        .into_iter()
+        .find(|protocol| *protocol != GATEWAY_PROTOCOL && !protocol.is_empty())
+        .map(|token| (token.to_string(), true))
+}
+
+/// relay msgs between the websocket and the io channel of a node connection,
+/// return when either side is closed. pings are answered by the websocket itself.
+pub(crate) async fn bridge<Si, St, E>(
+    mut sink: Si,
+    mut stream: St,
+    user_id: u64,
+    io_sender: MsgMpmcSender,
+    mut io_receiver: MsgMpscReceiver,
+) -> Result<()>
+where
+    Si: Sink<Message> + Unpin,
+    Si::Error: std::error::Error + Send + Sync + 'static,
+    St: Stream<Item = std::result::Result<Message, E>> + Unpin,
+    E: Display,
+{
+    let code = loop {
+        select! {
+            msg = stream.next() => match msg {
+                Some(Ok(msg)) if msg.is_binary() => {
+                    let msg = match parse_msg(msg.as_bytes()) {
+                        Some(msg) => msg,
+                        None => break CLOSE_INVALID_PAYLOAD,
+                    };
+                    if msg.sender() != user_id {
+                        break CLOSE_POLICY_VIOLATION;
+                    }
+                    if io_sender.send(Arc::new(msg)).await.is_err() {
+                        break CLOSE_GOING_AWAY;
+                    }
+                }
+                Some(Ok(msg)) if msg.is_text() => break CLOSE_UNSUPPORTED_DATA,
+                Some(Ok(msg)) if msg.is_close() => {
+                    // flush the close reply queued by the websocket.
+                    sink.close().await?;
+                    return Ok(());
+                }
+                Some(Ok(_)) => {}
+                Some(Err(err)) => return Err(anyhow!("websocket error: {}", err)),
+                None => return Ok(()),
+            },
+            msg = io_receiver.recv() => match msg {
+                Some(msg) => sink.send(Message::binary(msg.as_slice().to_vec())).await?,
+                None => break CLOSE_GOING_AWAY,
+            },
+        }
+    };
+    sink.send(Message::close_with(code, "")).await?;
+    Ok(())
+}
+
+/// the data should be exactly one msg of a known head version with sane lengths.
+pub(self) fn parse_msg(data: &[u8]) -> Option<Msg> {
+    if data.len() < HEAD_LEN || Head::head_len(data).is_err() {
+        return None;
+    }
+    let extension_length = Head::extension_length(data);
+    let payload_length = Head::payload_length(data);
+    if extension_length > EXTENSION_THRESHOLD
+        || payload_length > PAYLOAD_THRESHOLD
+        || HEAD_LEN + extension_length + payload_length != data.len()
+    {
+        return None;
+    }
+    Some(Msg::from(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use futures::{channel::mpsc, SinkExt, StreamExt};
+    use lib::entity::Msg;
+    use salvo::{hyper::header, websocket::Message, Request};
+
+    use super::{bridge, token_of, CLOSE_POLICY_VIOLATION};
+
+    #[test]
+    fn test_token_of() {
+        let mut req = Request::new();
+        req.headers_mut().insert(
+            header::SEC_WEBSOCKET_PROTOCOL,
+            "prim, abc.def".parse().unwrap(),
+        );
+        assert_eq!(token_of(&req), Some(("abc.def".to_string(), true)));
+        req.headers_mut()
+            .insert(header::AUTHORIZATION, "ghi.jkl".parse().unwrap());
+        assert_eq!(token_of(&req), Some(("ghi.jkl".to_string(), false)));
+
+        let mut req = Request::new();
+        req.headers_mut()
+            .insert(header::SEC_WEBSOCKET_PROTOCOL, "chat, abc".parse().unwrap());
+        assert_eq!(token_of(&req), None);
+        req.headers_mut()
+            .insert(header::SEC_WEBSOCKET_PROTOCOL, "prim".parse().unwrap());
+        assert_eq!(token_of(&req), None);
+    }
+
+    #[tokio::test]
+    async fn test_gateway_bridge() {
+        let (mut browser_sender, stream) = mpsc::channel::<salvo::Result<Message>>(16);
+        let (sink, mut browser_receiver) = mpsc::channel::<Message>(16);
+        let (io_sender, node_receiver) = async_channel::bounded(16);
+        let (node_sender, io_receiver) = tokio::sync::mpsc::channel(16);
+        let bridge = tokio::spawn(bridge(sink, stream, 1, io_sender, io_receiver));
+
+        let msg = Msg::text(1, 2, 0, "hello from browser");
+        browser_sender
+            .send(Ok(Message::binary(msg.as_slice().to_vec())))
+            .await
+            .unwrap();
+        let received = tokio::time::timeout(Duration::from_secs(1), node_receiver.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(received.as_slice(), msg.as_slice());
+
+        let msg = Msg::text(2, 1, 0, "hello from node");
+        node_sender.send(Arc::new(msg.clone())).await.unwrap();
+        let received = browser_receiver.next().await.unwrap();
+        assert!(received.is_binary());
+        assert_eq!(received.as_bytes(), msg.as_slice());
+
+        browser_sender.send(Ok(Message::close())).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(1), bridge)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert!(browser_receiver.next().await.is_none());
+        // the node side sees the bridge gone.
+        assert!(node_receiver.recv().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_gateway_bridge_impersonation() {
+        let (mut browser_sender, stream) = mpsc::channel::<salvo::Result<Message>>(16);
+        let (sink, mut browser_receiver) = mpsc::channel::<Message>(16);
+        let (io_sender, node_receiver) = async_channel::bounded(16);
+        let (_node_sender, io_receiver) = tokio::sync::mpsc::channel(16);
+        let bridge = tokio::spawn(bridge(sink, stream, 1, io_sender, io_receiver));
+
+        // user 1 sending as user 3.
+        let msg = Msg::text(3, 2, 0, "hello from browser");
+        browser_sender
+            .send(Ok(Message::binary(msg.as_slice().to_vec())))
+            .await
+            .unwrap();
+        let received = browser_receiver.next().await.unwrap();
+        assert_eq!(
+            received.close_frame().map(|(code, _)| code),
+            Some(CLOSE_POLICY_VIOLATION)
+        );
+        bridge.await.unwrap().unwrap();
+        assert!(node_receiver.try_recv().is_err());
+    }
+}
@@ -1,13 +1,16 @@
 use std::time::Duration;
 
 use chrono::Local;
-use lib::entity::{Msg, Type, GROUP_ID_THRESHOLD};
+use lib::{
+    cache::keys::{check_code_key, join_group_key},
+    entity::{Msg, Type, GROUP_ID_THRESHOLD},
+};
 use salvo::{handler, Request, Response};
 use serde_json::json;
 use tracing::error;
 
 use crate::{
-    cache::{get_redis_ops, CHECK_CODE, JOIN_GROUP},
+    cache::get_redis_ops,
     error::HandlerError,
     model::{
         group::{Group, GroupStatus},
@@ -18,7 +21,7 @@ use crate::{
     sql::DELETE_AT,
 };
 
-use super::{verify_user, HandlerResult, ResponseResult};
+use super::{require_query, verify_user, ApiResponse, HandlerResult};
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 struct JoinGroupReq {
@@ -28,10 +31,7 @@ struct JoinGroupReq {
 
 /// invoked by someone who wants to join a group
 #[handler]
-pub(crate) async fn join_group(
-    req: &mut Request,
-    _resp: &mut Response,
-) -> HandlerResult<'static, ()> {
+pub(crate) async fn join_group(req: &mut Request, _resp: &mut Response) -> HandlerResult<()> {
     let mut redis_ops = get_redis_ops().await;
     let user_id = match verify_user(req, &mut redis_ops).await {
         Ok(user_id) => user_id,
@@ -42,7 +42,7 @@ pub(crate) async fn join_group(
         Err(e) => return Err(HandlerError::ParameterMismatch(e.to_string())),
     };
     let check_code = match redis_ops
-        .get::<String>(&format!("{}{}", CHECK_CODE, form.group_id))
+        .get::<String>(&check_code_key(form.group_id))
         .await
     {
         Ok(check_code) => check_code,
@@ -57,7 +57,7 @@ pub(crate) async fn join_group(
             "check code mismatch".to_string(),
         ));
     }
-    let join_group_key = format!("{}{}-{}", JOIN_GROUP, user_id, form.group_id);
+    let join_group_key = join_group_key(user_id, form.group_id);
     match redis_ops.get::<String>(&join_group_key).await {
         Ok(_) => {
             return Err(HandlerError::RequestMismatch(
@@ -112,9 +112,9 @@ pub(crate) async fn join_group(
                     }
                 }
             }
-            Ok(ResponseResult {
+            Ok(ApiResponse::Ok {
                 code: 200,
-                message: "ok.",
+                message: "ok.".to_string(),
                 timestamp: Local::now(),
                 data: (),
             })
@@ -125,23 +125,13 @@ pub(crate) async fn join_group(
 /// invoked by someone who wants to leave a group
 /// the leave msg will be pushed to every admin of the group
 #[handler]
-pub(crate) async fn leave_group(
-    req: &mut Request,
-    _resp: &mut Response,
-) -> HandlerResult<'static, ()> {
+pub(crate) async fn leave_group(req: &mut Request, _resp: &mut Response) -> HandlerResult<()> {
     let mut redis_ops = get_redis_ops().await;
     let user_id = match verify_user(req, &mut redis_ops).await {
         Ok(user_id) => user_id,
         Err(e) => return Err(HandlerError::RequestMismatch(401, e.to_string())),
     };
-    let group_id = match req.query::<u64>("group_id") {
-        Some(group_id) => group_id,
-        None => {
-            return Err(HandlerError::ParameterMismatch(
-                "leave group parameter mismatch.".to_string(),
-            ))
-        }
-    };
+    let group_id = require_query::<u64>(req, "group_id")?;
     let user_relationship =
         match UserRelationship::get_user_id_peer_id(user_id as i64, group_id as i64).await {
             Ok(user_relationship) => user_relationship,
@@ -229,9 +219,9 @@ pub(crate) async fn leave_group(
             ));
         }
     };
-    Ok(ResponseResult {
+    Ok(ApiResponse::Ok {
         code: 200,
-        message: "ok.",
+        message: "ok.".to_string(),
         timestamp: Local::now(),
         data: (),
     })
@@ -244,10 +234,7 @@ struct CreateGroupReq {
 }
 
 #[handler]
-pub(crate) async fn create_group(
-    req: &mut Request,
-    _resp: &mut Response,
-) -> HandlerResult<'static, u64> {
+pub(crate) async fn create_group(req: &mut Request, _resp: &mut Response) -> HandlerResult<u64> {
     let mut redis_ops = get_redis_ops().await;
     let user_id = match verify_user(req, &mut redis_ops).await {
         Ok(user_id) => user_id,
@@ -271,7 +258,7 @@ pub(crate) async fn create_group(
         }
     }
     if let Err(e) = redis_ops
-        .set(&format!("{}{}", CHECK_CODE, group_id), &form.check_code)
+        .set(&check_code_key(group_id), &form.check_code)
         .await
     {
         error!("redis set check code error: {}.", e.to_string());
@@ -322,9 +309,9 @@ pub(crate) async fn create_group(
             "internal server error.".to_string(),
         ));
     }
-    Ok(ResponseResult {
+    Ok(ApiResponse::Ok {
         code: 200,
-        message: "ok.",
+        message: "ok.".to_string(),
         timestamp: Local::now(),
         data: group_id,
     })
@@ -351,15 +338,8 @@ struct GroupInfoResp {
 pub(crate) async fn get_group_info(
     req: &mut Request,
     _resp: &mut Response,
-) -> HandlerResult<'static, GroupInfoResp> {
-    let group_id = match req.query::<u64>("group_id") {
-        Some(group_id) => group_id,
-        None => {
-            return Err(HandlerError::ParameterMismatch(
-                "group id is required.".to_string(),
-            ))
-        }
-    };
+) -> HandlerResult<GroupInfoResp> {
+    let group_id = require_query::<u64>(req, "group_id")?;
     let group = match Group::get_group_id(group_id as i64).await {
         Ok(group) => group,
         Err(e) => {
@@ -369,9 +349,9 @@ pub(crate) async fn get_group_info(
             ));
         }
     };
-    Ok(ResponseResult {
+    Ok(ApiResponse::Ok {
         code: 200,
-        message: "ok.",
+        message: "ok.".to_string(),
         timestamp: Local::now(),
         data: GroupInfoResp {
             name: group.name,
@@ -396,7 +376,7 @@ struct UpdateGroupInfoReq {
 pub(crate) async fn update_group_info(
     req: &mut Request,
     _resp: &mut Response,
-) -> HandlerResult<'static, ()> {
+) -> HandlerResult<()> {
     let mut redis_ops = get_redis_ops().await;
     let user_id = match verify_user(req, &mut redis_ops).await {
         Ok(user_id) => user_id,
@@ -464,9 +444,9 @@ pub(crate) async fn update_group_info(
             "internal server error.".to_string(),
         ));
     }
-    Ok(ResponseResult {
+    Ok(ApiResponse::Ok {
         code: 200,
-        message: "ok.",
+        message: "ok.".to_string(),
         timestamp: Local::now(),
         data: (),
     })
@@ -476,39 +456,11 @@ pub(crate) async fn update_group_info(
 pub(crate) async fn get_group_user_list(
     req: &mut Request,
     _resp: &mut Response,
-) -> HandlerResult<'static, Vec<serde_json::Value>> {
-    let group_id = match req.query::<u64>("group_id") {
-        Some(group_id) => group_id,
-        None => {
-            return Err(HandlerError::ParameterMismatch(
-                "group id is required.".to_string(),
-            ))
-        }
-    };
-    let user_role = match req.query::<String>("user_role") {
-        Some(user_role) => user_role,
-        None => {
-            return Err(HandlerError::ParameterMismatch(
-                "user role is required.".to_string(),
-            ))
-        }
-    };
-    let offset = match req.query::<u32>("offset") {
-        Some(offset) => offset,
-        None => {
-            return Err(HandlerError::ParameterMismatch(
-                "offset is required.".to_string(),
-            ))
-        }
-    };
-    let limit = match req.query::<u32>("limit") {
-        Some(limit) => limit,
-        None => {
-            return Err(HandlerError::ParameterMismatch(
-                "limit is required.".to_string(),
-            ))
-        }
-    };
+) -> HandlerResult<Vec<serde_json::Value>> {
+    let group_id = require_query::<u64>(req, "group_id")?;
+    let user_role = require_query::<String>(req, "user_role")?;
+    let offset = require_query::<u32>(req, "offset")?;
+    let limit = require_query::<u32>(req, "limit")?;
     let group = match Group::get_group_id(group_id as i64).await {
         Ok(group) => group,
         Err(e) => {
@@ -528,9 +480,9 @@ pub(crate) async fn get_group_user_list(
             } else {
                 limit as usize + offset
             };
-            Ok(ResponseResult {
+            Ok(ApiResponse::Ok {
                 code: 200,
-                message: "ok.",
+                message: "ok.".to_string(),
                 timestamp: Local::now(),
                 data: group.admin_list.as_slice()[offset..limit].to_vec(),
             })
@@ -546,28 +498,22 @@ pub(crate) async fn get_group_user_list(
             } else {
                 limit as usize + offset
             };
-            Ok(ResponseResult {
+            Ok(ApiResponse::Ok {
                 code: 200,
-                message: "ok.",
+                message: "ok.".to_string(),
                 timestamp: Local::now(),
                 data: group.member_list.as_slice()[offset..limit].to_vec(),
             })
         }
-        _ => Ok(ResponseResult {
-            code: 400,
-            message: "user role is invalid.",
-            timestamp: Local::now(),
-            data: vec![],
-        }),
+        _ => Err(HandlerError::ParameterMismatch(
+            "user role is invalid.".to_string(),
+        )),
     }
 }
 
 /// invoked for admin user to kick some unfortunate man out of the group.
 #[handler]
-pub(crate) async fn remove_member(
-    req: &mut Request,
-    _resp: &mut Response,
-) -> HandlerResult<'static, ()> {
+pub(crate) async fn remove_member(req: &mut Request, _resp: &mut Response) -> HandlerResult<()> {
     let mut redis_ops = get_redis_ops().await;
     let user_id = match verify_user(req, &mut redis_ops).await {
         Ok(user_id) => user_id,
@@ -578,30 +524,9 @@ pub(crate) async fn remove_member(
             ));
         }
     };
-    let peer_id = match req.query::<u64>("user_id") {
-        Some(peer_id) => peer_id,
-        None => {
-            return Err(HandlerError::ParameterMismatch(
-                "peer id is required.".to_string(),
-            ))
-        }
-    };
-    let group_id = match req.query::<u64>("group_id") {
-        Some(group_id) => group_id,
-        None => {
-            return Err(HandlerError::ParameterMismatch(
-                "group id is required.".to_string(),
-            ))
-        }
-    };
-    let reason = match req.query("reason") {
-        Some(reason) => reason,
-        None => {
-            return Err(HandlerError::ParameterMismatch(
-                "reason is required.".to_string(),
-            ))
-        }
-    };
+    let peer_id = require_query::<u64>(req, "user_id")?;
+    let group_id = require_query::<u64>(req, "group_id")?;
+    let reason = require_query::<String>(req, "reason")?;
     let user_relationship =
         match UserRelationship::get_user_id_peer_id(user_id as i64, group_id as i64).await {
             Ok(user_relationship) => user_relationship,
@@ -694,9 +619,9 @@ pub(crate) async fn remove_member(
             ));
         }
     };
-    Ok(ResponseResult {
+    Ok(ApiResponse::Ok {
         code: 200,
-        message: "ok.",
+        message: "ok.".to_string(),
         timestamp: Local::now(),
         data: (),
     })
@@ -711,10 +636,7 @@ struct ApproveJoinReq {
 
 /// invoked for admin user to approve someone to join the group.
 #[handler]
-pub(crate) async fn approve_join(
-    req: &mut Request,
-    _resp: &mut Response,
-) -> HandlerResult<'static, ()> {
+pub(crate) async fn approve_join(req: &mut Request, _resp: &mut Response) -> HandlerResult<()> {
     let mut redis_ops = get_redis_ops().await;
     let user_id = match verify_user(req, &mut redis_ops).await {
         Ok(user_id) => user_id,
@@ -830,9 +752,9 @@ pub(crate) async fn approve_join(
             ));
         }
     }
-    Ok(ResponseResult {
+    Ok(ApiResponse::Ok {
         code: 200,
-        message: "ok.",
+        message: "ok.".to_string(),
         timestamp: Local::now(),
         data: (),
     })
@@ -847,10 +769,7 @@ struct SetAdminReq {
 
 /// invoked for admin user to set/unset the admin of the group.
 #[handler]
-pub(crate) async fn set_admin(
-    req: &mut Request,
-    _resp: &mut Response,
-) -> HandlerResult<'static, ()> {
+pub(crate) async fn set_admin(req: &mut Request, _resp: &mut Response) -> HandlerResult<()> {
     let mut redis_ops = get_redis_ops().await;
     let user_id = match verify_user(req, &mut redis_ops).await {
         Ok(user_id) => user_id,
@@ -998,9 +917,9 @@ pub(crate) async fn set_admin(
             "internal server error.".to_string(),
         ));
     }
-    Ok(ResponseResult {
+    Ok(ApiResponse::Ok {
         code: 200,
-        message: "ok.",
+        message: "ok.".to_string(),
         timestamp: Local::now(),
         data: (),
     })
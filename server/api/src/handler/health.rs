@@ -0,0 +1,47 @@
+use std::time::Duration;
+
+use chrono::Local;
+use lib::util::health::{current_health, set_redis_reachable, set_registered, Health};
+use salvo::{handler, prelude::StatusCode, Request, Response};
+
+use crate::{
+    cache::get_redis_ops,
+    rpc::{node::ResilientRpcClient, NODE_CLIENT},
+};
+
+use super::{ApiResponse, HandlerResult};
+
+/// api has nothing to register, so it is ready once scheduler is connected.
+pub(crate) async fn update_health(interval: Duration) {
+    loop {
+        set_redis_reachable(get_redis_ops().await.ping().await.is_ok());
+        if !NODE_CLIENT.initialized() {
+            if let Ok(client) = ResilientRpcClient::new().await {
+                _ = NODE_CLIENT.set(client);
+            }
+        }
+        set_registered(NODE_CLIENT.initialized());
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// readiness probe, answers 503 until the node is ready.
+#[handler]
+pub(crate) async fn health(_req: &mut Request, resp: &mut Response) -> HandlerResult<Health> {
+    let status = current_health();
+    if !status.ready {
+        resp.status_code(StatusCode::SERVICE_UNAVAILABLE);
+        return Ok(ApiResponse::Ok {
+            code: 503,
+            message: "not ready.".to_string(),
+            timestamp: Local::now(),
+            data: status,
+        });
+    }
+    Ok(ApiResponse::Ok {
+        code: 200,
+        message: "ok.".to_string(),
+        timestamp: Local::now(),
+        data: status,
+    })
+}
@@ -0,0 +1,128 @@
+use salvo::{
+    handler,
+    http::StatusError,
+    hyper::header::{HeaderValue, CONTENT_LENGTH},
+    Depot, FlowCtrl, Request, Response,
+};
+use tracing::{info_span, Instrument};
+
+pub(crate) const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// correlation id of a request, stored in the request extensions.
+#[derive(Debug, Clone)]
+pub(crate) struct RequestId(pub(crate) String);
+
+pub(crate) fn request_id_of(req: &Request) -> Option<String> {
+    req.extensions().get::<RequestId>().map(|id| id.0.clone())
+}
+
+/// reuse the id given by upstream if it looks sane, otherwise generate a new one.
+pub(self) fn extract_or_generate(req: &Request) -> String {
+    match req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+    {
+        Some(id) if !id.is_empty() && id.len() <= 128 => id.to_string(),
+        _ => uuid::Uuid::new_v4().to_string(),
+    }
+}
+
+/// tag all logs of a request with the same id, and echo it back in the response header.
+#[handler]
+pub(crate) async fn request_id(
+    req: &mut Request,
+    depot: &mut Depot,
+    resp: &mut Response,
+    ctrl: &mut FlowCtrl,
+) {
+    let id = extract_or_generate(req);
+    req.extensions_mut().insert(RequestId(id.clone()));
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        resp.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    let span = info_span!("request", request_id = %id, path = %req.uri().path());
+    ctrl.call_next(req, depot, resp).instrument(span).await;
+}
+
+/// reject the request whose body is declared larger than the limit before reading it.
+///
+/// bodies without a length are bounded by `salvo::http::request::set_secure_max_size` when parsed.
+pub(crate) struct BodyLimit {
+    max_size: usize,
+}
+
+impl BodyLimit {
+    pub(crate) fn new(max_size: usize) -> Self {
+        Self { max_size }
+    }
+}
+
+#[handler]
+impl BodyLimit {
+    async fn handle(&self, req: &mut Request, resp: &mut Response, ctrl: &mut FlowCtrl) {
+        let length = req
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<usize>().ok());
+        if let Some(length) = length {
+            if length > self.max_size {
+                resp.render(StatusError::payload_too_large());
+                ctrl.skip_rest();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use salvo::{
+        handler,
+        http::StatusCode,
+        hyper::header::CONTENT_LENGTH,
+        test::{ResponseExt, TestClient},
+        Router, Service,
+    };
+
+    use super::{request_id, BodyLimit, REQUEST_ID_HEADER};
+
+    #[handler]
+    async fn hello() -> &'static str {
+        "hello"
+    }
+
+    #[tokio::test]
+    async fn test_request_id() {
+        let service = Service::new(Router::with_hoop(request_id).get(hello));
+        let mut resp = TestClient::get("http://127.0.0.1/").send(&service).await;
+        let generated = resp.headers().get(REQUEST_ID_HEADER).unwrap().clone();
+        assert!(!generated.is_empty());
+        assert_eq!(resp.take_string().await.unwrap(), "hello");
+
+        let resp = TestClient::get("http://127.0.0.1/")
+            .add_header(REQUEST_ID_HEADER, "abc-123", true)
+            .send(&service)
+            .await;
+        assert_eq!(resp.headers().get(REQUEST_ID_HEADER).unwrap(), "abc-123");
+    }
+
+    #[tokio::test]
+    async fn test_body_limit() {
+        let service = Service::new(Router::with_hoop(BodyLimit::new(16)).post(hello));
+        let resp = TestClient::post("http://127.0.0.1/")
+            .add_header(CONTENT_LENGTH, 32, true)
+            .text("a".repeat(32))
+            .send(&service)
+            .await;
+        assert_eq!(resp.status_code, Some(StatusCode::PAYLOAD_TOO_LARGE));
+
+        let mut resp = TestClient::post("http://127.0.0.1/")
+            .add_header(CONTENT_LENGTH, 8, true)
+            .text("a".repeat(8))
+            .send(&service)
+            .await;
+        assert_eq!(resp.status_code, Some(StatusCode::OK));
+        assert_eq!(resp.take_string().await.unwrap(), "hello");
+    }
+}
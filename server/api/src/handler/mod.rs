@@ -1,34 +1,75 @@
 use anyhow::anyhow;
 use chrono::{DateTime, Local};
 use lib::{
-    cache::redis_ops::RedisOps,
+    cache::{
+        keys::user_token_key,
+        redis_ops::{redis_retry, RedisOps},
+    },
     util::jwt::{audience_of_token, verify_token},
     Result,
 };
 use salvo::{writing::Json, Piece, Request, Response};
+use serde::de::DeserializeOwned;
 
-use crate::{cache::USER_TOKEN, error::HandlerError};
+use crate::error::HandlerError;
 
 pub(crate) mod file;
+pub(crate) mod gateway;
 pub(crate) mod group;
+pub(crate) mod health;
+pub(crate) mod middleware;
 pub(crate) mod msg;
 pub(crate) mod relationship;
 pub(crate) mod user;
 
-pub(crate) type HandlerResult<'a, T> = std::result::Result<ResponseResult<'a, T>, HandlerError>;
+/// how many times the auth critical redis calls are tried on a broken link.
+pub(crate) const REDIS_ATTEMPTS: usize = 3;
 
+pub(crate) type HandlerResult<T> = std::result::Result<ApiResponse<T>, HandlerError>;
+
+/// the body of every answer, `status` tells whether `data` is there.
+///
+/// `code`, `message` and `timestamp` are kept by both arms, so the clients reading only them
+/// work as before.
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
-pub(crate) struct ResponseResult<'a, T>
-where
-    T: Send + Sync + 'static,
-{
-    pub(crate) code: u32,
-    pub(crate) message: &'a str,
-    pub(crate) timestamp: DateTime<Local>,
-    pub(crate) data: T,
+#[serde(tag = "status", rename_all = "snake_case")]
+pub(crate) enum ApiResponse<T> {
+    Ok {
+        code: u32,
+        message: String,
+        timestamp: DateTime<Local>,
+        data: T,
+    },
+    Err {
+        code: u32,
+        message: String,
+        timestamp: DateTime<Local>,
+        /// stable across wording changes of `message`, see [`HandlerError::error_code`].
+        error_code: String,
+        /// always `null`, for the clients reading `data` of every answer.
+        #[serde(default)]
+        data: (),
+    },
+}
+
+#[cfg(test)]
+impl<T> ApiResponse<T> {
+    pub(crate) fn code(&self) -> u32 {
+        match self {
+            ApiResponse::Ok { code, .. } | ApiResponse::Err { code, .. } => *code,
+        }
+    }
+
+    /// `None` for the error arm.
+    pub(crate) fn data(self) -> Option<T> {
+        match self {
+            ApiResponse::Ok { data, .. } => Some(data),
+            ApiResponse::Err { .. } => None,
+        }
+    }
 }
 
-impl<'a, T: Send + Sync + 'static + serde::Serialize> Piece for ResponseResult<'a, T> {
+impl<T: Send + Sync + 'static + serde::Serialize> Piece for ApiResponse<T> {
     fn render(self, res: &mut Response) {
         res.render(Json(self));
     }
@@ -39,12 +80,21 @@ pub(crate) async fn verify_user(req: &mut Request, redis_ops: &mut RedisOps) ->
         Some(token) => token.to_str().unwrap(),
         None => return Err(anyhow!("token is required.")),
     };
+    verify_user_token(token, redis_ops).await
+}
+
+pub(crate) async fn verify_user_token(token: &str, redis_ops: &mut RedisOps) -> Result<u64> {
     let user_id = match audience_of_token(token) {
         Ok(user_id) => user_id,
         Err(err) => return Err(anyhow!("token is invalid: {}.", err)),
     };
-    let redis_key = format!("{}{}", USER_TOKEN, user_id);
-    let token_key = match redis_ops.get::<String>(&redis_key).await {
+    let redis_key = user_token_key(user_id);
+    let get_token_key = || {
+        let mut redis_ops = redis_ops.clone();
+        let redis_key = redis_key.clone();
+        async move { redis_ops.get::<String>(&redis_key).await }
+    };
+    let token_key = match redis_retry(get_token_key, REDIS_ATTEMPTS).await {
         Ok(token_key) => token_key,
         Err(_err) => return Err(anyhow!("user not login.")),
     };
@@ -54,3 +104,80 @@ pub(crate) async fn verify_user(req: &mut Request, redis_ops: &mut RedisOps) ->
     };
     Ok(user_id)
 }
+
+/// the query param `name` parsed as `T`, a missing or malformed one is a 400 telling its name.
+pub(crate) fn require_query<T: DeserializeOwned>(
+    req: &Request,
+    name: &str,
+) -> std::result::Result<T, HandlerError> {
+    req.query::<T>(name).ok_or_else(|| {
+        HandlerError::ParameterMismatch(format!("{} is required.", name.replace('_', " ")))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Local;
+    use salvo::test::TestClient;
+    use serde_json::json;
+
+    use super::{require_query, ApiResponse};
+
+    #[test]
+    fn test_api_response_ok() {
+        let resp = ApiResponse::Ok {
+            code: 200,
+            message: "ok.".to_string(),
+            timestamp: Local::now(),
+            data: vec![1u64, 2],
+        };
+        let value = serde_json::to_value(&resp).unwrap();
+        assert_eq!(value["status"], "ok");
+        assert_eq!(value["code"], 200);
+        assert_eq!(value["message"], "ok.");
+        assert_eq!(value["data"], json!([1, 2]));
+        assert!(value.get("error_code").is_none());
+        match serde_json::from_value::<ApiResponse<Vec<u64>>>(value).unwrap() {
+            ApiResponse::Ok { data, .. } => assert_eq!(data, vec![1, 2]),
+            ApiResponse::Err { .. } => panic!("should be ok"),
+        }
+    }
+
+    #[test]
+    fn test_api_response_err() {
+        let resp: ApiResponse<Vec<u64>> = ApiResponse::Err {
+            code: 401,
+            message: "unauthorized.".to_string(),
+            timestamp: Local::now(),
+            error_code: "request_mismatch".to_string(),
+            data: (),
+        };
+        let value = serde_json::to_value(&resp).unwrap();
+        assert_eq!(value["status"], "err");
+        assert_eq!(value["code"], 401);
+        assert_eq!(value["message"], "unauthorized.");
+        assert_eq!(value["error_code"], "request_mismatch");
+        // as before the envelope, the clients reading `data` anyway see `null`.
+        assert!(value["data"].is_null());
+        match serde_json::from_value::<ApiResponse<Vec<u64>>>(value).unwrap() {
+            ApiResponse::Err { code, .. } => assert_eq!(code, 401),
+            ApiResponse::Ok { .. } => panic!("should be err"),
+        }
+    }
+
+    #[test]
+    fn test_require_query() {
+        let req = TestClient::get("http://127.0.0.1/?user_id=42&peer_id=x").build();
+        assert_eq!(require_query::<u64>(&req, "user_id").ok(), Some(42));
+        for name in ["group_id", "peer_id"] {
+            let resp = ApiResponse::<()>::from(require_query::<u64>(&req, name).unwrap_err());
+            let value = serde_json::to_value(&resp).unwrap();
+            assert_eq!(value["code"], 400);
+            assert_eq!(value["error_code"], "parameter_mismatch");
+            assert_eq!(
+                value["message"],
+                format!("{} is required.", name.replace('_', " "))
+            );
+        }
+    }
+}
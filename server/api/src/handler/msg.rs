@@ -1,21 +1,17 @@
 use base64::Engine;
 use chrono::Local;
 use lib::{
+    cache::keys::{last_online_time_key, last_read_key, msg_cache_key, user_inbox_key},
     entity::{Msg, Type, GROUP_ID_THRESHOLD},
-    util::{timestamp, who_we_are},
+    util::timestamp,
     Result,
 };
 use salvo::handler;
 use tracing::error;
 
-use crate::{
-    cache::{get_redis_ops, LAST_ONLINE_TIME, LAST_READ, MSG_CACHE, USER_INBOX},
-    error::HandlerError,
-    model::msg::Message,
-    rpc::get_rpc_client,
-};
+use crate::{cache::get_redis_ops, error::HandlerError, model::msg::Message, rpc::get_rpc_client};
 
-use super::{verify_user, HandlerResult, ResponseResult};
+use super::{require_query, verify_user, ApiResponse, HandlerResult};
 
 /// depends on certain client.
 /// this method will return all users who have sent message to this user when the user is offline.
@@ -25,7 +21,7 @@ use super::{verify_user, HandlerResult, ResponseResult};
 pub(crate) async fn inbox(
     req: &mut salvo::Request,
     _resp: &mut salvo::Response,
-) -> HandlerResult<'static, Vec<u64>> {
+) -> HandlerResult<Vec<u64>> {
     let mut redis_ops = get_redis_ops().await;
     let user_id = match verify_user(req, &mut redis_ops).await {
         Ok(v) => v,
@@ -37,16 +33,13 @@ pub(crate) async fn inbox(
         }
     };
     // todo device dependency
-    let last_online_time = match redis_ops
-        .get::<u64>(&format!("{}{}", LAST_ONLINE_TIME, user_id))
-        .await
-    {
+    let last_online_time = match redis_ops.get::<u64>(&last_online_time_key(user_id)).await {
         Ok(v) => v,
         Err(_) => timestamp() - 5 * 365 * 24 * 60 * 60 * 1000,
     };
     let user_list = match redis_ops
         .peek_sort_queue_more::<u64>(
-            &format!("{}{}", USER_INBOX, user_id),
+            &user_inbox_key(user_id),
             0,
             u32::MAX as usize,
             last_online_time as f64,
@@ -61,9 +54,9 @@ pub(crate) async fn inbox(
             return Err(HandlerError::InternalError("internal error".to_string()));
         }
     };
-    Ok(ResponseResult {
+    Ok(ApiResponse::Ok {
         code: 200,
-        message: "ok.",
+        message: "ok.".to_string(),
         timestamp: Local::now(),
         data: user_list,
     })
@@ -74,7 +67,7 @@ pub(crate) async fn inbox(
 pub(crate) async fn unread(
     req: &mut salvo::Request,
     _resp: &mut salvo::Response,
-) -> HandlerResult<'static, u64> {
+) -> HandlerResult<u64> {
     let mut redis_ops = get_redis_ops().await;
     let user_id = match verify_user(req, &mut redis_ops).await {
         Ok(v) => v,
@@ -85,24 +78,14 @@ pub(crate) async fn unread(
             ))
         }
     };
-    let peer_id = match req.query::<u64>("peer_id") {
-        Some(v) => v,
-        None => {
-            return Err(HandlerError::ParameterMismatch(
-                "peer id is required.".to_string(),
-            ))
-        }
-    };
-    let last_read_seq_num = match redis_ops
-        .get::<u64>(&format!("{}{}-{}", LAST_READ, user_id, peer_id))
-        .await
-    {
+    let peer_id = require_query::<u64>(req, "peer_id")?;
+    let last_read_seq_num = match redis_ops.get::<u64>(&last_read_key(user_id, peer_id)).await {
         Ok(v) => v,
         Err(_) => 0,
     };
-    Ok(ResponseResult {
+    Ok(ApiResponse::Ok {
         code: 200,
-        message: "ok.",
+        message: "ok.".to_string(),
         timestamp: Local::now(),
         data: last_read_seq_num,
     })
@@ -112,7 +95,7 @@ pub(crate) async fn unread(
 pub(crate) async fn update_unread(
     req: &mut salvo::Request,
     _resp: &mut salvo::Response,
-) -> HandlerResult<'static, ()> {
+) -> HandlerResult<()> {
     let mut redis_ops = get_redis_ops().await;
     let user_id = match verify_user(req, &mut redis_ops).await {
         Ok(v) => v,
@@ -123,36 +106,19 @@ pub(crate) async fn update_unread(
             ))
         }
     };
-    let peer_id = match req.query::<u64>("peer_id") {
-        Some(v) => v,
-        None => {
-            return Err(HandlerError::ParameterMismatch(
-                "peer id is required.".to_string(),
-            ))
-        }
-    };
+    let peer_id = require_query::<u64>(req, "peer_id")?;
     // todo update other client's last_read.
-    let last_read_seq = match req.query::<u64>("last_read_seq") {
-        Some(v) => v,
-        None => {
-            return Err(HandlerError::ParameterMismatch(
-                "last read seq is required.".to_string(),
-            ))
-        }
-    };
+    let last_read_seq = require_query::<u64>(req, "last_read_seq")?;
     if let Err(_) = redis_ops
-        .set(
-            &format!("{}{}-{}", LAST_READ, user_id, peer_id),
-            &last_read_seq,
-        )
+        .set(&last_read_key(user_id, peer_id), &last_read_seq)
         .await
     {
         error!("update unread failed.");
         return Err(HandlerError::InternalError("internal error".to_string()));
     }
-    Ok(ResponseResult {
+    Ok(ApiResponse::Ok {
         code: 200,
-        message: "ok.",
+        message: "ok.".to_string(),
         timestamp: Local::now(),
         data: (),
     })
@@ -172,7 +138,7 @@ pub(crate) async fn update_unread(
 pub(crate) async fn history_msg(
     req: &mut salvo::Request,
     _resp: &mut salvo::Response,
-) -> HandlerResult<'static, Vec<Msg>> {
+) -> HandlerResult<Vec<Msg>> {
     let mut redis_ops = get_redis_ops().await;
     let user_id = match verify_user(req, &mut redis_ops).await {
         Ok(v) => v,
@@ -183,30 +149,9 @@ pub(crate) async fn history_msg(
             ))
         }
     };
-    let peer_id = match req.query::<u64>("peer_id") {
-        Some(v) => v,
-        None => {
-            return Err(HandlerError::ParameterMismatch(
-                "peer id is required.".to_string(),
-            ))
-        }
-    };
-    let from_seq_num = match req.query::<u64>("from_seq_num") {
-        Some(v) => v,
-        None => {
-            return Err(HandlerError::ParameterMismatch(
-                "from_seq_num is required.".to_string(),
-            ))
-        }
-    };
-    let to_seq_num = match req.query::<u64>("to_seq_num") {
-        Some(v) => v,
-        None => {
-            return Err(HandlerError::ParameterMismatch(
-                "to_seq_num is required.".to_string(),
-            ))
-        }
-    };
+    let peer_id = require_query::<u64>(req, "peer_id")?;
+    let from_seq_num = require_query::<u64>(req, "from_seq_num")?;
+    let to_seq_num = require_query::<u64>(req, "to_seq_num")?;
     let expected_size = if to_seq_num == 0 {
         100
     } else {
@@ -218,10 +163,10 @@ pub(crate) async fn history_msg(
             "expected size is too large.".to_string(),
         ));
     }
-    let id_key = if peer_id >= GROUP_ID_THRESHOLD {
-        who_we_are(peer_id, peer_id)
+    let cache_key = if peer_id >= GROUP_ID_THRESHOLD {
+        msg_cache_key(peer_id, peer_id)
     } else {
-        who_we_are(user_id, peer_id)
+        msg_cache_key(user_id, peer_id)
     };
     let cache_from_seq_num = from_seq_num as f64;
     let mut cache_to_seq_num = to_seq_num as f64;
@@ -233,7 +178,7 @@ pub(crate) async fn history_msg(
     }
     let cache_list = redis_ops
         .peek_sort_queue_more::<Msg>(
-            &format!("{}{}", MSG_CACHE, id_key),
+            &cache_key,
             0,
             expected_size,
             cache_from_seq_num,
@@ -247,9 +192,9 @@ pub(crate) async fn history_msg(
     }
     let cache_list = cache_list.unwrap();
     if cache_list.len() == expected_size {
-        return Ok(ResponseResult {
+        return Ok(ApiResponse::Ok {
             code: 200,
-            message: "ok.",
+            message: "ok.".to_string(),
             timestamp: Local::now(),
             data: cache_list,
         });
@@ -278,9 +223,9 @@ pub(crate) async fn history_msg(
     let db_list = db_list.unwrap();
     let mut list = db_list.iter().map(|x| x.into()).collect::<Vec<Msg>>();
     list.extend(cache_list);
-    Ok(ResponseResult {
+    Ok(ApiResponse::Ok {
         code: 200,
-        message: "ok.",
+        message: "ok.".to_string(),
         timestamp: Local::now(),
         data: list,
     })
@@ -290,7 +235,7 @@ pub(crate) async fn history_msg(
 pub(crate) async fn withdraw(
     req: &mut salvo::Request,
     _resp: &mut salvo::Response,
-) -> HandlerResult<'static, ()> {
+) -> HandlerResult<()> {
     let mut redis_ops = get_redis_ops().await;
     let user_id = match verify_user(req, &mut redis_ops).await {
         Ok(v) => v,
@@ -301,24 +246,9 @@ pub(crate) async fn withdraw(
             ))
         }
     };
-    let peer_id = match req.query::<u64>("peer_id") {
-        Some(v) => v,
-        None => {
-            return Err(HandlerError::ParameterMismatch(
-                "peer id is required.".to_string(),
-            ))
-        }
-    };
-    let seq_num = match req.query::<u64>("old_seq_num") {
-        Some(v) => v,
-        None => {
-            return Err(HandlerError::ParameterMismatch(
-                "old_seq_num is required.".to_string(),
-            ))
-        }
-    };
-    let id_key = who_we_are(user_id, peer_id);
-    let user_peer_key = format!("{}{}", MSG_CACHE, id_key);
+    let peer_id = require_query::<u64>(req, "peer_id")?;
+    let seq_num = require_query::<u64>(req, "old_seq_num")?;
+    let user_peer_key = msg_cache_key(user_id, peer_id);
     let res: Result<Vec<Msg>> = redis_ops
         .peek_sort_queue_more(&user_peer_key, 0, 1, seq_num as f64, seq_num as f64, true)
         .await;
@@ -334,9 +264,9 @@ pub(crate) async fn withdraw(
             _ = redis_ops
                 .push_sort_queue(&user_peer_key, &new_msg, new_msg.seqnum() as f64)
                 .await;
-            return Ok(ResponseResult {
+            return Ok(ApiResponse::Ok {
                 code: 200,
-                message: "ok.",
+                message: "ok.".to_string(),
                 timestamp: Local::now(),
                 data: (),
             });
@@ -368,9 +298,9 @@ pub(crate) async fn withdraw(
         error!("rpc call push msg error: {}", e);
         return Err(HandlerError::InternalError("internal error".to_string()));
     }
-    Ok(ResponseResult {
+    Ok(ApiResponse::Ok {
         code: 200,
-        message: "ok.",
+        message: "ok.".to_string(),
         timestamp: Local::now(),
         data: (),
     })
@@ -388,7 +318,7 @@ struct EditReq {
 pub(crate) async fn edit(
     req: &mut salvo::Request,
     _resp: &mut salvo::Response,
-) -> HandlerResult<'static, ()> {
+) -> HandlerResult<()> {
     let mut redis_ops = get_redis_ops().await;
     let user_id = match verify_user(req, &mut redis_ops).await {
         Ok(v) => v,
@@ -407,8 +337,7 @@ pub(crate) async fn edit(
             ))
         }
     };
-    let id_key = who_we_are(user_id, edit_req.peer_id);
-    let user_peer_key = format!("{}{}", MSG_CACHE, id_key);
+    let user_peer_key = msg_cache_key(user_id, edit_req.peer_id);
     let res: Result<Vec<Msg>> = redis_ops
         .peek_sort_queue_more(
             &user_peer_key,
@@ -436,9 +365,9 @@ pub(crate) async fn edit(
             _ = redis_ops
                 .push_sort_queue(&user_peer_key, &new_msg, new_msg.seqnum() as f64)
                 .await;
-            return Ok(ResponseResult {
+            return Ok(ApiResponse::Ok {
                 code: 200,
-                message: "ok.",
+                message: "ok.".to_string(),
                 timestamp: Local::now(),
                 data: (),
             });
@@ -472,9 +401,9 @@ pub(crate) async fn edit(
         error!("rpc call push msg error: {}", e);
         return Err(HandlerError::InternalError("internal error".to_string()));
     }
-    Ok(ResponseResult {
+    Ok(ApiResponse::Ok {
         code: 200,
-        message: "ok.",
+        message: "ok.".to_string(),
         timestamp: Local::now(),
         data: (),
     })
@@ -1,20 +1,26 @@
-use std::time::Duration;
-
+use async_trait::async_trait;
 use chrono::Local;
-use lib::entity::{Msg, Type};
+use lib::{
+    cache::keys::contacts_key,
+    entity::{Msg, Type},
+    Result,
+};
 use salvo::handler;
 use serde_json::json;
 use tracing::error;
 
 use crate::{
-    cache::{get_redis_ops, ADD_FRIEND},
+    cache::get_redis_ops,
     error::HandlerError,
-    model::relationship::{UserRelationship, UserRelationshipStatus},
+    model::{
+        relationship::{UserRelationship, UserRelationshipStatus},
+        user::User,
+    },
     rpc::get_rpc_client,
     sql::DELETE_AT,
 };
 
-use super::{verify_user, HandlerResult, ResponseResult};
+use super::{require_query, verify_user, ApiResponse, HandlerResult};
 
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 struct AddFriendReq {
@@ -26,7 +32,7 @@ struct AddFriendReq {
 pub(crate) async fn add_friend(
     req: &mut salvo::Request,
     _resp: &mut salvo::Response,
-) -> HandlerResult<'static, ()> {
+) -> HandlerResult<()> {
     let mut redis_ops = get_redis_ops().await;
     let user_id = match verify_user(req, &mut redis_ops).await {
         Ok(user_id) => user_id,
@@ -46,39 +52,11 @@ pub(crate) async fn add_friend(
             ))
         }
     };
-    let key = format!("{}{}-{}", ADD_FRIEND, user_id, form.peer_id);
-    let _res = match redis_ops.get::<String>(&key).await {
-        Ok(_res) => {
-            return Err(HandlerError::RequestMismatch(
-                400,
-                "already request for new friend.".to_string(),
-            ))
-        },
-        Err(_err) => {
-        }
-    };
-    _ = redis_ops
-        .set_exp(&key, &form.remark, Duration::from_secs(60 * 60 * 24 * 7))
-        .await;
-    let mut msg = Msg::text(user_id, form.peer_id, 0, &form.remark);
-    msg.set_type(Type::AddFriend);
-    let mut rpc_client = get_rpc_client().await;
-    let _res = match rpc_client.call_push_msg(&msg).await {
-        Ok(res) => res,
-        Err(err) => {
-            error!("rpc call push msg error: {}", err);
-            if let Err(e) = redis_ops.del(&key).await {
-                error!("redis del error: {} + {}", e, key);
-            }
-            return Err(HandlerError::RequestMismatch(
-                500,
-                "internal server error.".to_string(),
-            ));
-        }
-    };
-    Ok(ResponseResult {
+    add_contact(&SqlContactStore, user_id, form.peer_id, &form.remark).await?;
+    notify_peer(user_id, form.peer_id, &form.remark, Type::AddFriend).await;
+    Ok(ApiResponse::Ok {
         code: 200,
-        message: "ok.",
+        message: "ok.".to_string(),
         timestamp: Local::now(),
         data: (),
     })
@@ -94,7 +72,7 @@ struct ConfirmAddFriendReq {
 pub(crate) async fn confirm_add_friend(
     req: &mut salvo::Request,
     _resp: &mut salvo::Response,
-) -> HandlerResult<'static, ()> {
+) -> HandlerResult<()> {
     let mut redis_ops = get_redis_ops().await;
     let user_id = match verify_user(req, &mut redis_ops).await {
         Ok(user_id) => user_id,
@@ -114,65 +92,25 @@ pub(crate) async fn confirm_add_friend(
             ))
         }
     };
-    let key = format!("{}{}-{}", ADD_FRIEND, form.peer_id, user_id);
-    let res = match redis_ops.get::<String>(&key).await {
-        Ok(res) => res,
-        Err(err) => {
-            error!("redis get error: {}", err);
-            return Err(HandlerError::RequestMismatch(
-                500,
-                "no add friend request.".to_string(),
-            ));
-        }
-    };
-    _ = redis_ops.del(&key).await;
-    let user_relationship1 = UserRelationship {
-        id: 0,
-        user_id: user_id as i64,
-        peer_id: form.peer_id as i64,
-        remark: "".to_string(),
-        status: UserRelationshipStatus::Normal,
-        classification: "".to_string(),
-        tag_list: vec![],
-        info: json!(null),
-        create_at: Local::now(),
-        update_at: Local::now(),
-        delete_at: DELETE_AT.clone(),
-    };
-    let user_relationship2 = UserRelationship {
-        id: 0,
-        user_id: form.peer_id as i64,
-        peer_id: user_id as i64,
-        remark: "".to_string(),
-        status: UserRelationshipStatus::Normal,
-        classification: "".to_string(),
-        tag_list: vec![],
-        info: json!(null),
-        create_at: Local::now(),
-        update_at: Local::now(),
-        delete_at: DELETE_AT.clone(),
-    };
-    let _res1 = match user_relationship1.insert().await {
-        Ok(res) => res,
-        Err(err) => {
-            error!("insert user relationship error: {}", err);
-            return Err(HandlerError::RequestMismatch(
-                500,
-                "internal server error.".to_string(),
-            ));
-        }
-    };
-    let _res2 = match user_relationship2.insert().await {
-        Ok(res) => res,
-        Err(err) => {
-            error!("insert user relationship error: {}", err);
-            return Err(HandlerError::RequestMismatch(
-                500,
-                "internal server error.".to_string(),
-            ));
-        }
+    let remark = match SqlContactStore
+        .get(form.peer_id, user_id)
+        .await
+        .map_err(internal_error)?
+    {
+        Some(theirs) => theirs.remark,
+        None => "".to_string(),
     };
-    let remark = res;
+    if form.passed {
+        set_contact_status(
+            &SqlContactStore,
+            user_id,
+            form.peer_id,
+            ContactStatus::Friend,
+        )
+        .await?;
+    } else {
+        decline_contact(&SqlContactStore, user_id, form.peer_id).await?;
+    }
     let mut msg = Msg::text2(user_id, form.peer_id, 0, &remark, &form.passed.to_string());
     msg.set_type(Type::AddFriend);
     let mut rpc_client = get_rpc_client().await;
@@ -186,9 +124,9 @@ pub(crate) async fn confirm_add_friend(
             ));
         }
     };
-    Ok(ResponseResult {
+    Ok(ApiResponse::Ok {
         code: 200,
-        message: "ok.",
+        message: "ok.".to_string(),
         timestamp: Local::now(),
         data: (),
     })
@@ -207,7 +145,7 @@ struct FriendListResp {
 pub(crate) async fn get_friend_list(
     req: &mut salvo::Request,
     _resp: &mut salvo::Response,
-) -> HandlerResult<'static, Vec<FriendListResp>> {
+) -> HandlerResult<Vec<FriendListResp>> {
     let mut redis_ops = get_redis_ops().await;
     let user_id = match verify_user(req, &mut redis_ops).await {
         Ok(user_id) => user_id,
@@ -218,24 +156,8 @@ pub(crate) async fn get_friend_list(
             ))
         }
     };
-    let number = match req.query::<u64>("number") {
-        Some(number) => number,
-        None => {
-            return Err(HandlerError::RequestMismatch(
-                400,
-                "number is required.".to_string(),
-            ))
-        }
-    };
-    let offset = match req.query::<u64>("offset") {
-        Some(offset) => offset,
-        None => {
-            return Err(HandlerError::RequestMismatch(
-                400,
-                "offset is required.".to_string(),
-            ))
-        }
-    };
+    let number = require_query::<u64>(req, "number")?;
+    let offset = require_query::<u64>(req, "offset")?;
     if number > 100 {
         return Err(HandlerError::RequestMismatch(
             400,
@@ -263,20 +185,20 @@ pub(crate) async fn get_friend_list(
             tag_list: item.tag_list,
         });
     }
-    Ok(ResponseResult {
+    Ok(ApiResponse::Ok {
         code: 200,
-        message: "ok.",
+        message: "ok.".to_string(),
         timestamp: Local::now(),
         data: list,
     })
 }
 
-/// only both sides all invoke this method, the relationship will be dropped.
+/// the same as `remove_relationship`, kept for the clients using it.
 #[handler]
 pub(crate) async fn delete_friend(
     req: &mut salvo::Request,
     _resp: &mut salvo::Response,
-) -> HandlerResult<'static, ()> {
+) -> HandlerResult<()> {
     let mut redis_ops = get_redis_ops().await;
     let user_id = match verify_user(req, &mut redis_ops).await {
         Ok(user_id) => user_id,
@@ -287,36 +209,8 @@ pub(crate) async fn delete_friend(
             ))
         }
     };
-    let peer_id = match req.query::<u64>("peer_id") {
-        Some(peer_id) => peer_id,
-        None => {
-            return Err(HandlerError::ParameterMismatch(
-                "peer_id is required.".to_string(),
-            ))
-        }
-    };
-    let res1 = match UserRelationship::get_user_id_peer_id(user_id as i64, peer_id as i64).await {
-        Ok(res) => res,
-        Err(e) => {
-            error!("get user relationship error: {}", e);
-            return Err(HandlerError::RequestMismatch(
-                400,
-                "relationship already unlocked.".to_string(),
-            ));
-        }
-    };
-    let res2 = match UserRelationship::get_user_id_peer_id(peer_id as i64, user_id as i64).await {
-        Ok(res) => res,
-        Err(e) => {
-            error!("get user relationship error: {}", e);
-            return Err(HandlerError::RequestMismatch(
-                400,
-                "relationship already unlocked.".to_string(),
-            ));
-        }
-    };
-    _ = res1.delete().await;
-    _ = res2.delete().await;
+    let peer_id = require_query::<u64>(req, "peer_id")?;
+    remove_contact(&SqlContactStore, user_id, peer_id).await?;
     let mut msg = Msg::text(user_id, peer_id, 0, "we have broken up.");
     msg.set_type(Type::RemoveFriend);
     let mut rpc_client = get_rpc_client().await;
@@ -330,9 +224,9 @@ pub(crate) async fn delete_friend(
             ));
         }
     };
-    Ok(ResponseResult {
+    Ok(ApiResponse::Ok {
         code: 200,
-        message: "ok.",
+        message: "ok.".to_string(),
         timestamp: Local::now(),
         data: (),
     })
@@ -344,7 +238,7 @@ pub(crate) async fn delete_friend(
 pub(crate) async fn get_peer_relationship(
     req: &mut salvo::Request,
     _resp: &mut salvo::Response,
-) -> HandlerResult<'static, u8> {
+) -> HandlerResult<u8> {
     let mut redis_ops = get_redis_ops().await;
     let user_id = match verify_user(req, &mut redis_ops).await {
         Ok(user_id) => user_id,
@@ -355,14 +249,7 @@ pub(crate) async fn get_peer_relationship(
             ))
         }
     };
-    let peer_id = match req.query::<u64>("peer_id") {
-        Some(peer_id) => peer_id,
-        None => {
-            return Err(HandlerError::ParameterMismatch(
-                "peer_id is required.".to_string(),
-            ))
-        }
-    };
+    let peer_id = require_query::<u64>(req, "peer_id")?;
     let res = match UserRelationship::get_user_id_peer_id(user_id as i64, peer_id as i64).await {
         Ok(res) => res,
         Err(e) => {
@@ -373,9 +260,9 @@ pub(crate) async fn get_peer_relationship(
             ));
         }
     };
-    Ok(ResponseResult {
+    Ok(ApiResponse::Ok {
         code: 200,
-        message: "ok.",
+        message: "ok.".to_string(),
         timestamp: Local::now(),
         data: res.status as u8,
     })
@@ -390,12 +277,12 @@ struct UpdateRelationshipReq {
     tag_list: Option<Vec<String>>,
 }
 
-/// only work on user and peer is already friend.
+/// only work on user and peer is already friend, or to block the peer.
 #[handler]
 pub(crate) async fn update_relationship(
     req: &mut salvo::Request,
     _resp: &mut salvo::Response,
-) -> HandlerResult<'static, ()> {
+) -> HandlerResult<()> {
     let mut redis_ops = get_redis_ops().await;
     let user_id = match verify_user(req, &mut redis_ops).await {
         Ok(user_id) => user_id,
@@ -416,78 +303,743 @@ pub(crate) async fn update_relationship(
             ));
         }
     };
-    let mut res1 =
-        match UserRelationship::get_user_id_peer_id(user_id as i64, req.peer_id as i64).await {
-            Ok(res) => res,
-            Err(e) => {
-                error!("get user relationship error: {}", e);
+    let update = ContactUpdate {
+        remark: req.remark,
+        status: req.status.map(UserRelationshipStatus::from),
+        classification: req.classification,
+        tag_list: req.tag_list,
+    };
+    update_contact(&SqlContactStore, user_id, req.peer_id, update).await?;
+    let mut msg = Msg::text(user_id, req.peer_id, 0, "relationship updated");
+    msg.set_type(Type::SetRelationship);
+    let mut rpc_client = get_rpc_client().await;
+    let _res = match rpc_client.call_push_msg(&msg).await {
+        Ok(res) => res,
+        Err(err) => {
+            error!("rpc call push msg error: {}", err);
+            return Err(HandlerError::RequestMismatch(
+                500,
+                "internal server error.".to_string(),
+            ));
+        }
+    };
+    Ok(ApiResponse::Ok {
+        code: 200,
+        message: "ok.".to_string(),
+        timestamp: Local::now(),
+        data: (),
+    })
+}
+
+/// where the contacts are kept, split out so the rules below can be tested without a database.
+///
+/// the friends of a user are mirrored to [`contacts_key`] as well, so the message nodes tell
+/// presence to them only.
+#[async_trait]
+pub(crate) trait ContactStore: Send + Sync {
+    async fn peer_exists(&self, peer_id: u64) -> Result<bool>;
+
+    async fn get(&self, user_id: u64, peer_id: u64) -> Result<Option<UserRelationship>>;
+
+    /// insert the relationship if its id is 0, update it otherwise.
+    async fn save(&self, relationship: &UserRelationship) -> Result<()>;
+
+    async fn delete(&self, relationship: &UserRelationship) -> Result<()>;
+}
+
+pub(crate) struct SqlContactStore;
+
+#[async_trait]
+impl ContactStore for SqlContactStore {
+    async fn peer_exists(&self, peer_id: u64) -> Result<bool> {
+        match User::get_account_id(peer_id as i64).await {
+            Ok(_) => Ok(true),
+            Err(e) if is_row_not_found(&e) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn get(&self, user_id: u64, peer_id: u64) -> Result<Option<UserRelationship>> {
+        match UserRelationship::get_user_id_peer_id(user_id as i64, peer_id as i64).await {
+            Ok(res) => Ok(Some(res)),
+            Err(e) if is_row_not_found(&e) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn save(&self, relationship: &UserRelationship) -> Result<()> {
+        if relationship.id == 0 {
+            relationship.insert().await?;
+        } else {
+            relationship.update().await?;
+        }
+        let key = contacts_key(relationship.user_id as u64);
+        let peer_id = relationship.peer_id as u64;
+        if is_friend(&relationship.status) {
+            get_redis_ops().await.push_set(&key, &peer_id).await
+        } else {
+            get_redis_ops().await.remove_set(&key, &peer_id).await
+        }
+    }
+
+    async fn delete(&self, relationship: &UserRelationship) -> Result<()> {
+        relationship.delete().await?;
+        get_redis_ops()
+            .await
+            .remove_set(
+                &contacts_key(relationship.user_id as u64),
+                &(relationship.peer_id as u64),
+            )
+            .await
+    }
+}
+
+#[inline]
+fn is_row_not_found(e: &anyhow::Error) -> bool {
+    matches!(
+        e.downcast_ref::<sqlx::Error>(),
+        Some(sqlx::Error::RowNotFound)
+    )
+}
+
+/// the statuses a user can set on a contact by `set_relationship_status`.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ContactStatus {
+    Friend,
+    Blocked,
+    Pending,
+}
+
+/// lover and best friend are friends with a label.
+#[inline]
+fn is_friend(status: &UserRelationshipStatus) -> bool {
+    matches!(
+        status,
+        UserRelationshipStatus::Normal
+            | UserRelationshipStatus::Lover
+            | UserRelationshipStatus::BestFriend
+    )
+}
+
+fn new_relationship(
+    user_id: u64,
+    peer_id: u64,
+    remark: &str,
+    status: UserRelationshipStatus,
+) -> UserRelationship {
+    UserRelationship {
+        id: 0,
+        user_id: user_id as i64,
+        peer_id: peer_id as i64,
+        remark: remark.to_string(),
+        status,
+        classification: "".to_string(),
+        tag_list: vec![],
+        info: json!(null),
+        create_at: Local::now(),
+        update_at: Local::now(),
+        delete_at: DELETE_AT.clone(),
+    }
+}
+
+fn internal_error(e: anyhow::Error) -> HandlerError {
+    error!("contact store error: {}", e);
+    HandlerError::RequestMismatch(500, "internal server error.".to_string())
+}
+
+async fn check_peer(
+    store: &dyn ContactStore,
+    user_id: u64,
+    peer_id: u64,
+) -> std::result::Result<(), HandlerError> {
+    if user_id == peer_id {
+        return Err(HandlerError::RequestMismatch(
+            400,
+            "can not be a contact of yourself.".to_string(),
+        ));
+    }
+    if !store.peer_exists(peer_id).await.map_err(internal_error)? {
+        return Err(HandlerError::RequestMismatch(
+            404,
+            "peer not found.".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// request `peer_id` as a contact, or accept its pending request to the user.
+///
+/// the status from the user to the peer is returned: pending, or normal once both sides agree.
+pub(crate) async fn add_contact(
+    store: &dyn ContactStore,
+    user_id: u64,
+    peer_id: u64,
+    remark: &str,
+) -> HandlerResult<u8> {
+    check_peer(store, user_id, peer_id).await?;
+    let mine = store.get(user_id, peer_id).await.map_err(internal_error)?;
+    let theirs = store.get(peer_id, user_id).await.map_err(internal_error)?;
+    if let Some(theirs) = theirs.as_ref() {
+        if theirs.status == UserRelationshipStatus::Blocked {
+            return Err(HandlerError::RequestMismatch(
+                403,
+                "blocked by peer.".to_string(),
+            ));
+        }
+    }
+    if let Some(mine) = mine.as_ref() {
+        if is_friend(&mine.status) {
+            return Err(HandlerError::RequestMismatch(
+                409,
+                "already friends.".to_string(),
+            ));
+        }
+        match mine.status {
+            UserRelationshipStatus::Pending => {
                 return Err(HandlerError::RequestMismatch(
-                    400,
-                    "not friend.".to_string(),
-                ));
+                    409,
+                    "already requested.".to_string(),
+                ))
             }
-        };
-    let mut res2 =
-        match UserRelationship::get_user_id_peer_id(req.peer_id as i64, user_id as i64).await {
-            Ok(res) => res,
-            Err(e) => {
-                error!("get user relationship error: {}", e);
+            UserRelationshipStatus::Blocked => {
                 return Err(HandlerError::RequestMismatch(
-                    400,
-                    "not friend.".to_string(),
-                ));
+                    409,
+                    "peer blocked, remove it first.".to_string(),
+                ))
             }
-        };
-    if req.remark.is_some() {
-        res1.remark = req.remark.unwrap();
+            _ => {}
+        }
     }
-    if req.status.is_some() {
-        res1.status = UserRelationshipStatus::from(req.status.unwrap());
-        res2.status = UserRelationshipStatus::from(req.status.unwrap());
+    let status = match theirs.as_ref() {
+        Some(theirs) if theirs.status == UserRelationshipStatus::Pending => {
+            UserRelationshipStatus::Normal
+        }
+        _ => UserRelationshipStatus::Pending,
+    };
+    let mine = match mine {
+        Some(mut mine) => {
+            mine.remark = remark.to_string();
+            mine.status = status.clone();
+            mine
+        }
+        None => new_relationship(user_id, peer_id, remark, status.clone()),
+    };
+    store.save(&mine).await.map_err(internal_error)?;
+    if let Some(mut theirs) = theirs {
+        if status == UserRelationshipStatus::Normal {
+            theirs.status = UserRelationshipStatus::Normal;
+            store.save(&theirs).await.map_err(internal_error)?;
+        }
     }
-    if req.classification.is_some() {
-        res1.classification = req.classification.unwrap();
+    Ok(ApiResponse::Ok {
+        code: 200,
+        message: "ok.".to_string(),
+        timestamp: Local::now(),
+        data: status as u8,
+    })
+}
+
+/// drop `peer_id` from the contacts, on both sides unless the peer has blocked the user.
+pub(crate) async fn remove_contact(
+    store: &dyn ContactStore,
+    user_id: u64,
+    peer_id: u64,
+) -> HandlerResult<()> {
+    let mine = match store.get(user_id, peer_id).await.map_err(internal_error)? {
+        Some(mine) => mine,
+        None => {
+            return Err(HandlerError::RequestMismatch(
+                404,
+                "not a contact.".to_string(),
+            ))
+        }
+    };
+    store.delete(&mine).await.map_err(internal_error)?;
+    if let Some(theirs) = store.get(peer_id, user_id).await.map_err(internal_error)? {
+        if theirs.status != UserRelationshipStatus::Blocked {
+            store.delete(&theirs).await.map_err(internal_error)?;
+        }
     }
-    if req.tag_list.is_some() {
-        res1.tag_list = req.tag_list.unwrap();
+    Ok(ApiResponse::Ok {
+        code: 200,
+        message: "ok.".to_string(),
+        timestamp: Local::now(),
+        data: (),
+    })
+}
+
+/// blocking works on anyone and ends the friendship, becoming a friend needs the peer to have
+/// requested it first, and pending is the same as `add_contact`.
+pub(crate) async fn set_contact_status(
+    store: &dyn ContactStore,
+    user_id: u64,
+    peer_id: u64,
+    status: ContactStatus,
+) -> HandlerResult<u8> {
+    if status == ContactStatus::Pending {
+        let remark = match store.get(user_id, peer_id).await.map_err(internal_error)? {
+            Some(mine) => mine.remark,
+            None => "".to_string(),
+        };
+        return add_contact(store, user_id, peer_id, &remark).await;
     }
-    let _res1 = match res1.update().await {
-        Ok(res) => res,
-        Err(e) => {
-            error!("update user relationship error: {}", e);
+    check_peer(store, user_id, peer_id).await?;
+    let mine = store.get(user_id, peer_id).await.map_err(internal_error)?;
+    let theirs = store.get(peer_id, user_id).await.map_err(internal_error)?;
+    let status = match status {
+        ContactStatus::Blocked => {
+            if let Some(theirs) = theirs {
+                if theirs.status != UserRelationshipStatus::Blocked {
+                    store.delete(&theirs).await.map_err(internal_error)?;
+                }
+            }
+            UserRelationshipStatus::Blocked
+        }
+        _ => {
+            if let Some(mine) = mine.as_ref() {
+                if is_friend(&mine.status) {
+                    return Err(HandlerError::RequestMismatch(
+                        409,
+                        "already friends.".to_string(),
+                    ));
+                }
+            }
+            match theirs {
+                Some(mut theirs)
+                    if theirs.status == UserRelationshipStatus::Pending
+                        || is_friend(&theirs.status) =>
+                {
+                    theirs.status = UserRelationshipStatus::Normal;
+                    store.save(&theirs).await.map_err(internal_error)?;
+                }
+                _ => {
+                    return Err(HandlerError::RequestMismatch(
+                        409,
+                        "no request from peer.".to_string(),
+                    ))
+                }
+            }
+            UserRelationshipStatus::Normal
+        }
+    };
+    let mine = match mine {
+        Some(mut mine) => {
+            mine.status = status.clone();
+            mine
+        }
+        None => new_relationship(user_id, peer_id, "", status.clone()),
+    };
+    store.save(&mine).await.map_err(internal_error)?;
+    Ok(ApiResponse::Ok {
+        code: 200,
+        message: "ok.".to_string(),
+        timestamp: Local::now(),
+        data: status as u8,
+    })
+}
+
+/// turn down the pending request of `peer_id` to the user.
+pub(crate) async fn decline_contact(
+    store: &dyn ContactStore,
+    user_id: u64,
+    peer_id: u64,
+) -> HandlerResult<()> {
+    match store.get(peer_id, user_id).await.map_err(internal_error)? {
+        Some(theirs) if theirs.status == UserRelationshipStatus::Pending => {
+            store.delete(&theirs).await.map_err(internal_error)?;
+        }
+        _ => {
+            return Err(HandlerError::RequestMismatch(
+                404,
+                "no add friend request.".to_string(),
+            ))
+        }
+    }
+    Ok(ApiResponse::Ok {
+        code: 200,
+        message: "ok.".to_string(),
+        timestamp: Local::now(),
+        data: (),
+    })
+}
+
+/// the fields of a contact `update_contact` changes, the ones left none are kept.
+pub(crate) struct ContactUpdate {
+    pub(crate) remark: Option<String>,
+    pub(crate) status: Option<UserRelationshipStatus>,
+    pub(crate) classification: Option<String>,
+    pub(crate) tag_list: Option<Vec<String>>,
+}
+
+/// change a contact of the user. blocking is the same as `set_contact_status`, the other
+/// statuses are the labels of friends, set on both sides and only while both are friends.
+pub(crate) async fn update_contact(
+    store: &dyn ContactStore,
+    user_id: u64,
+    peer_id: u64,
+    update: ContactUpdate,
+) -> HandlerResult<()> {
+    let not_friend = || HandlerError::RequestMismatch(400, "not friend.".to_string());
+    if store
+        .get(user_id, peer_id)
+        .await
+        .map_err(internal_error)?
+        .is_none()
+    {
+        return Err(not_friend());
+    }
+    if let Some(status) = update.status.as_ref() {
+        if *status == UserRelationshipStatus::Blocked {
+            set_contact_status(store, user_id, peer_id, ContactStatus::Blocked).await?;
+        } else if is_friend(status) {
+            let mine = store.get(user_id, peer_id).await.map_err(internal_error)?;
+            let theirs = store.get(peer_id, user_id).await.map_err(internal_error)?;
+            match (mine, theirs) {
+                (Some(mut mine), Some(mut theirs))
+                    if is_friend(&mine.status) && is_friend(&theirs.status) =>
+                {
+                    mine.status = status.clone();
+                    theirs.status = status.clone();
+                    store.save(&mine).await.map_err(internal_error)?;
+                    store.save(&theirs).await.map_err(internal_error)?;
+                }
+                _ => {
+                    return Err(HandlerError::RequestMismatch(
+                        409,
+                        "not friends.".to_string(),
+                    ))
+                }
+            }
+        } else {
             return Err(HandlerError::RequestMismatch(
                 400,
-                "internal server error.".to_string(),
+                "status can not be set here.".to_string(),
             ));
         }
+    }
+    if update.remark.is_some() || update.classification.is_some() || update.tag_list.is_some() {
+        let mut mine = match store.get(user_id, peer_id).await.map_err(internal_error)? {
+            Some(mine) => mine,
+            None => return Err(not_friend()),
+        };
+        if let Some(remark) = update.remark {
+            mine.remark = remark;
+        }
+        if let Some(classification) = update.classification {
+            mine.classification = classification;
+        }
+        if let Some(tag_list) = update.tag_list {
+            mine.tag_list = tag_list;
+        }
+        store.save(&mine).await.map_err(internal_error)?;
+    }
+    Ok(ApiResponse::Ok {
+        code: 200,
+        message: "ok.".to_string(),
+        timestamp: Local::now(),
+        data: (),
+    })
+}
+
+/// tell the peer about a contact change, the change is already saved so a failure is only logged.
+async fn notify_peer(user_id: u64, peer_id: u64, text: &str, typ: Type) {
+    let mut msg = Msg::text(user_id, peer_id, 0, text);
+    msg.set_type(typ);
+    let mut rpc_client = get_rpc_client().await;
+    if let Err(err) = rpc_client.call_push_msg(&msg).await {
+        error!("rpc call push msg error: {}", err);
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+struct AddRelationshipReq {
+    peer_id: u64,
+    #[serde(default)]
+    remark: String,
+}
+
+#[handler]
+pub(crate) async fn add_relationship(
+    req: &mut salvo::Request,
+    _resp: &mut salvo::Response,
+) -> HandlerResult<u8> {
+    let mut redis_ops = get_redis_ops().await;
+    let user_id = match verify_user(req, &mut redis_ops).await {
+        Ok(user_id) => user_id,
+        Err(_) => {
+            return Err(HandlerError::RequestMismatch(
+                401,
+                "unauthorized.".to_string(),
+            ))
+        }
     };
-    let _res2 = match res2.update().await {
-        Ok(res) => res,
-        Err(e) => {
-            error!("update user relationship error: {}", e);
+    let form = match req.parse_json::<AddRelationshipReq>().await {
+        Ok(form) => form,
+        Err(_) => {
             return Err(HandlerError::RequestMismatch(
                 400,
-                "internal server error.".to_string(),
-            ));
+                "request parameter missing or invalid.".to_string(),
+            ))
         }
     };
-    let mut msg = Msg::text(user_id, req.peer_id, 0, "relationship updated");
-    msg.set_type(Type::SetRelationship);
-    let mut rpc_client = get_rpc_client().await;
-    let _res = match rpc_client.call_push_msg(&msg).await {
-        Ok(res) => res,
-        Err(err) => {
-            error!("rpc call push msg error: {}", err);
+    let res = add_contact(&SqlContactStore, user_id, form.peer_id, &form.remark).await?;
+    notify_peer(user_id, form.peer_id, &form.remark, Type::AddFriend).await;
+    Ok(res)
+}
+
+#[handler]
+pub(crate) async fn remove_relationship(
+    req: &mut salvo::Request,
+    _resp: &mut salvo::Response,
+) -> HandlerResult<()> {
+    let mut redis_ops = get_redis_ops().await;
+    let user_id = match verify_user(req, &mut redis_ops).await {
+        Ok(user_id) => user_id,
+        Err(_) => {
             return Err(HandlerError::RequestMismatch(
-                500,
-                "internal server error.".to_string(),
-            ));
+                401,
+                "unauthorized.".to_string(),
+            ))
         }
     };
-    Ok(ResponseResult {
-        code: 200,
-        message: "ok.",
-        timestamp: Local::now(),
-        data: (),
-    })
+    let peer_id = require_query::<u64>(req, "peer_id")?;
+    let res = remove_contact(&SqlContactStore, user_id, peer_id).await?;
+    notify_peer(user_id, peer_id, "contact removed.", Type::RemoveFriend).await;
+    Ok(res)
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+struct SetRelationshipStatusReq {
+    peer_id: u64,
+    status: ContactStatus,
+}
+
+#[handler]
+pub(crate) async fn set_relationship_status(
+    req: &mut salvo::Request,
+    _resp: &mut salvo::Response,
+) -> HandlerResult<u8> {
+    let mut redis_ops = get_redis_ops().await;
+    let user_id = match verify_user(req, &mut redis_ops).await {
+        Ok(user_id) => user_id,
+        Err(_) => {
+            return Err(HandlerError::RequestMismatch(
+                401,
+                "unauthorized.".to_string(),
+            ))
+        }
+    };
+    let form = match req.parse_json::<SetRelationshipStatusReq>().await {
+        Ok(form) => form,
+        Err(_) => {
+            return Err(HandlerError::RequestMismatch(
+                400,
+                "request parameter missing or invalid.".to_string(),
+            ))
+        }
+    };
+    let res = set_contact_status(&SqlContactStore, user_id, form.peer_id, form.status).await?;
+    // a block is not told to the peer.
+    if form.status != ContactStatus::Blocked {
+        notify_peer(
+            user_id,
+            form.peer_id,
+            "relationship updated",
+            Type::SetRelationship,
+        )
+        .await;
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{HashMap, HashSet},
+        sync::Mutex,
+    };
+
+    use async_trait::async_trait;
+    use lib::Result;
+
+    use super::{
+        add_contact, decline_contact, remove_contact, set_contact_status, update_contact,
+        ContactStatus, ContactStore, ContactUpdate,
+    };
+    use crate::{
+        error::HandlerError,
+        model::relationship::{UserRelationship, UserRelationshipStatus},
+    };
+
+    #[derive(Default)]
+    struct MemStore {
+        users: HashSet<u64>,
+        relationships: Mutex<HashMap<(i64, i64), UserRelationship>>,
+        next_id: Mutex<i64>,
+    }
+
+    impl MemStore {
+        fn with_users(users: &[u64]) -> Self {
+            Self {
+                users: users.iter().copied().collect(),
+                ..Default::default()
+            }
+        }
+
+        fn status(&self, user_id: i64, peer_id: i64) -> Option<UserRelationshipStatus> {
+            self.relationships
+                .lock()
+                .unwrap()
+                .get(&(user_id, peer_id))
+                .map(|x| x.status.clone())
+        }
+    }
+
+    #[async_trait]
+    impl ContactStore for MemStore {
+        async fn peer_exists(&self, peer_id: u64) -> Result<bool> {
+            Ok(self.users.contains(&peer_id))
+        }
+
+        async fn get(&self, user_id: u64, peer_id: u64) -> Result<Option<UserRelationship>> {
+            Ok(self
+                .relationships
+                .lock()
+                .unwrap()
+                .get(&(user_id as i64, peer_id as i64))
+                .cloned())
+        }
+
+        async fn save(&self, relationship: &UserRelationship) -> Result<()> {
+            let mut relationship = relationship.clone();
+            if relationship.id == 0 {
+                let mut next_id = self.next_id.lock().unwrap();
+                *next_id += 1;
+                relationship.id = *next_id;
+            }
+            self.relationships
+                .lock()
+                .unwrap()
+                .insert((relationship.user_id, relationship.peer_id), relationship);
+            Ok(())
+        }
+
+        async fn delete(&self, relationship: &UserRelationship) -> Result<()> {
+            self.relationships
+                .lock()
+                .unwrap()
+                .remove(&(relationship.user_id, relationship.peer_id));
+            Ok(())
+        }
+    }
+
+    fn code<T: Send + Sync + 'static>(res: super::HandlerResult<T>) -> u32 {
+        match res {
+            Ok(res) => res.code(),
+            Err(HandlerError::RequestMismatch(code, _)) => code,
+            Err(HandlerError::ParameterMismatch(_)) => 400,
+            Err(HandlerError::InternalError(_)) => 500,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_then_duplicate() {
+        let store = MemStore::with_users(&[1, 2]);
+        assert_eq!(code(add_contact(&store, 1, 3, "").await), 404);
+
+        let res = add_contact(&store, 1, 2, "hi").await.ok().unwrap();
+        assert_eq!(res.data(), Some(UserRelationshipStatus::Pending as u8));
+        assert_eq!(code(add_contact(&store, 1, 2, "hi").await), 409);
+
+        // the peer adding back accepts the request.
+        let res = add_contact(&store, 2, 1, "").await.ok().unwrap();
+        assert_eq!(res.data(), Some(UserRelationshipStatus::Normal as u8));
+        assert_eq!(store.status(1, 2), Some(UserRelationshipStatus::Normal));
+        assert_eq!(store.status(2, 1), Some(UserRelationshipStatus::Normal));
+        assert_eq!(code(add_contact(&store, 1, 2, "").await), 409);
+        assert_eq!(code(add_contact(&store, 2, 1, "").await), 409);
+    }
+
+    #[tokio::test]
+    async fn test_block() {
+        let store = MemStore::with_users(&[1, 2]);
+        add_contact(&store, 1, 2, "").await.ok().unwrap();
+        set_contact_status(&store, 2, 1, ContactStatus::Friend)
+            .await
+            .ok()
+            .unwrap();
+
+        set_contact_status(&store, 2, 1, ContactStatus::Blocked)
+            .await
+            .ok()
+            .unwrap();
+        assert_eq!(store.status(2, 1), Some(UserRelationshipStatus::Blocked));
+        assert_eq!(store.status(1, 2), None);
+        assert_eq!(code(add_contact(&store, 1, 2, "").await), 403);
+        assert_eq!(
+            code(set_contact_status(&store, 1, 2, ContactStatus::Friend).await),
+            409
+        );
+
+        // the block survives the blocked side removing it, and is lifted by the blocker only.
+        assert_eq!(code(remove_contact(&store, 1, 2).await), 404);
+        remove_contact(&store, 2, 1).await.ok().unwrap();
+        assert_eq!(store.status(2, 1), None);
+        let res = add_contact(&store, 1, 2, "").await.ok().unwrap();
+        assert_eq!(res.data(), Some(UserRelationshipStatus::Pending as u8));
+    }
+
+    fn status_update(status: UserRelationshipStatus) -> ContactUpdate {
+        ContactUpdate {
+            remark: None,
+            status: Some(status),
+            classification: None,
+            tag_list: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_update_keeps_the_rules() {
+        let store = MemStore::with_users(&[1, 2]);
+        add_contact(&store, 1, 2, "").await.ok().unwrap();
+        // a pending request can't be turned into a friendship by a label.
+        assert_eq!(
+            code(update_contact(&store, 1, 2, status_update(UserRelationshipStatus::Lover)).await),
+            409
+        );
+        decline_contact(&store, 2, 1).await.ok().unwrap();
+        assert_eq!(store.status(1, 2), None);
+        assert_eq!(code(decline_contact(&store, 2, 1).await), 404);
+
+        add_contact(&store, 1, 2, "").await.ok().unwrap();
+        add_contact(&store, 2, 1, "").await.ok().unwrap();
+        update_contact(&store, 1, 2, status_update(UserRelationshipStatus::Lover))
+            .await
+            .ok()
+            .unwrap();
+        assert_eq!(store.status(2, 1), Some(UserRelationshipStatus::Lover));
+
+        update_contact(&store, 2, 1, status_update(UserRelationshipStatus::Blocked))
+            .await
+            .ok()
+            .unwrap();
+        assert_eq!(store.status(1, 2), None);
+        // the blocker can't be made a friend again by the old route, and a block lifts by removal only.
+        assert_eq!(
+            code(update_contact(&store, 1, 2, status_update(UserRelationshipStatus::Normal)).await),
+            400
+        );
+        assert_eq!(
+            code(update_contact(&store, 2, 1, status_update(UserRelationshipStatus::Normal)).await),
+            409
+        );
+        assert_eq!(
+            code(
+                update_contact(&store, 2, 1, status_update(UserRelationshipStatus::Pending)).await
+            ),
+            400
+        );
+        assert_eq!(store.status(2, 1), Some(UserRelationshipStatus::Blocked));
+    }
 }
@@ -1,44 +1,97 @@
+use std::{future::Future, time::Duration};
+
+use anyhow::anyhow;
 use chrono::Local;
 use hmac::{Hmac, Mac};
 use lib::{
+    cache::{
+        keys::{signup_idempotency_key, user_token_key, ACCOUNT_ID_COUNTER},
+        redis_ops::{redis_retry, RedisOps},
+    },
     entity::GROUP_ID_THRESHOLD,
-    util::{jwt::simple_token, salt},
+    util::{
+        jwt::{
+            audience_of_token, refresh_access_token, refresh_token, simple_token,
+            REFRESH_TOKEN_TTL,
+        },
+        salt,
+    },
+    Result,
 };
-use salvo::{handler, Request, Response};
+use salvo::{handler, hyper::header, Request, Response};
 use serde_json::json;
 use sha2::Sha256;
 use tracing::{error, warn, info};
 
 use crate::{
-    cache::{get_redis_ops, USER_TOKEN},
+    cache::get_redis_ops,
+    config::config,
     error::HandlerError,
     model::{
         group::Group,
         relationship::UserRelationship,
-        user::{User, UserStatus},
+        user::{normalize_avatar, normalize_nickname, normalize_signature, User, UserStatus},
     },
     rpc::get_rpc_client,
-    sql::DELETE_AT,
+    sql::{retry_transient, sql_error_kind, SqlErrorKind, DELETE_AT},
 };
 
-use super::{verify_user, HandlerResult, ResponseResult};
+use super::{
+    middleware::request_id_of, require_query, verify_user, ApiResponse, HandlerResult,
+    REDIS_ATTEMPTS,
+};
 
 type HmacSha256 = Hmac<Sha256>;
 
+pub(crate) const NICKNAME_AVATAR_BATCH_LIMIT: usize = 100;
+/// the refresh token is carried by a header to keep the login response body unchanged.
+pub(crate) const REFRESH_TOKEN_HEADER: &str = "x-refresh-token";
+/// set by clients retrying a signup, see [`signup`].
+pub(crate) const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// how long a signup is remembered by its idempotency key.
+pub(self) const SIGNUP_IDEMPOTENCY_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+pub(self) const IDEMPOTENCY_PENDING: &str = "pending";
+pub(self) const IDEMPOTENCY_DONE: &str = "done";
+
+/// the smallest account id, ids below it are reserved.
+pub(self) const ACCOUNT_ID_BASE: u64 = 1 << 33;
+
+/// map the value of the counter into `ACCOUNT_ID_BASE + 1 .. GROUP_ID_THRESHOLD`,
+/// `None` means the range is used up.
+pub(self) fn account_id_of(counter: u64) -> Option<u64> {
+    let id = ACCOUNT_ID_BASE.checked_add(counter)?;
+    if counter == 0 || id >= GROUP_ID_THRESHOLD {
+        None
+    } else {
+        Some(id)
+    }
+}
+
+/// `INCR` is atomic, so concurrent callers never get the same id.
+pub(self) async fn next_account_id(redis_ops: &mut RedisOps) -> Result<u64> {
+    let counter = redis_ops.atomic_increment(ACCOUNT_ID_COUNTER).await?;
+    account_id_of(counter).ok_or_else(|| anyhow!("account id range is used up"))
+}
+
 #[handler]
-pub(crate) async fn new_account_id(
-    _: &mut Request,
-    _resp: &mut Response,
-) -> HandlerResult<'static, u64> {
-    // todo optimization
+pub(crate) async fn new_account_id(_: &mut Request, _resp: &mut Response) -> HandlerResult<u64> {
+    let mut redis_ops = get_redis_ops().await;
     loop {
-        // todo threshold range
-        let id: u64 = fastrand::u64((1 << 33) + 1..GROUP_ID_THRESHOLD);
-        let res = User::get_account_id(id as i64).await;
-        if res.is_err() {
-            break Ok(ResponseResult {
+        let id = match next_account_id(&mut redis_ops).await {
+            Ok(id) => id,
+            Err(e) => {
+                error!("allocate account id error: {}", e);
+                return Err(HandlerError::InternalError(
+                    "internal server error.".to_string(),
+                ));
+            }
+        };
+        // ids handed out randomly before the counter existed are skipped.
+        if User::get_account_id(id as i64).await.is_err() {
+            break Ok(ApiResponse::Ok {
                 code: 200,
-                message: "ok.",
+                message: "ok.".to_string(),
                 timestamp: Local::now(),
                 data: id,
             });
@@ -53,17 +106,14 @@ struct LoginReq {
 }
 
 #[handler]
-pub(crate) async fn login(
-    req: &mut Request,
-    _resp: &mut Response,
-) -> HandlerResult<'static, String> {
+pub(crate) async fn login(req: &mut Request, resp: &mut Response) -> HandlerResult<String> {
     let mut redis_ops = get_redis_ops().await;
     match verify_user(req, &mut redis_ops).await {
         Ok(_user_id) => {
             return Ok(
-                ResponseResult {
+                ApiResponse::Ok {
                     code: 200,
-                    message: "ok.",
+                    message: "ok.".to_string(),
                     timestamp: Local::now(),
                     data: req.header::<String>("Authorization").unwrap(),
                 }
@@ -101,20 +151,79 @@ pub(crate) async fn login(
         ));
     }
     let key = salt(12);
-    let mut redis_ops = get_redis_ops().await;
-    if let Err(_) = redis_ops
-        .set(&format!("{}{}", USER_TOKEN, form.account_id), &key)
-        .await
-    {
-        error!("redis set error");
+    let set_token_key = || async {
+        get_redis_ops()
+            .await
+            .set_exp(&user_token_key(form.account_id), &key, REFRESH_TOKEN_TTL)
+            .await
+    };
+    if let Err(e) = redis_retry(set_token_key, REDIS_ATTEMPTS).await {
+        error!("redis set error: {}", e);
         return Err(HandlerError::InternalError(
             "internal server error.".to_string(),
         ));
     }
     let token = simple_token(key.as_bytes(), form.account_id);
-    Ok(ResponseResult {
+    let refresh_token = refresh_token(key.as_bytes(), form.account_id);
+    if let Ok(value) = refresh_token.parse() {
+        resp.headers_mut().insert(REFRESH_TOKEN_HEADER, value);
+    }
+    Ok(ApiResponse::Ok {
+        code: 200,
+        message: "ok.".to_string(),
+        timestamp: Local::now(),
+        data: token,
+    })
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+struct RefreshReq {
+    refresh_token: String,
+}
+
+/// issue a new access token from the refresh token given at login, without checking credentials again.
+#[handler]
+pub(crate) async fn refresh(req: &mut Request, _resp: &mut Response) -> HandlerResult<String> {
+    let form = match req.parse_json::<RefreshReq>().await {
+        Ok(form) => form,
+        Err(_err) => {
+            return Err(HandlerError::ParameterMismatch(
+                "refresh parameters mismatch.".to_string(),
+            ));
+        }
+    };
+    let user_id = match audience_of_token(&form.refresh_token) {
+        Ok(user_id) => user_id,
+        Err(_err) => {
+            return Err(HandlerError::RequestMismatch(
+                401,
+                "refresh token is invalid.".to_string(),
+            ));
+        }
+    };
+    let mut redis_ops = get_redis_ops().await;
+    let key = match redis_ops.get::<String>(&user_token_key(user_id)).await {
+        Ok(key) => key,
+        Err(_err) => {
+            return Err(HandlerError::RequestMismatch(
+                401,
+                "user not login.".to_string(),
+            ));
+        }
+    };
+    let token = match refresh_access_token(&form.refresh_token, key.as_bytes(), user_id) {
+        Ok(token) => token,
+        Err(err) => {
+            info!("refresh token rejected: {}.", err);
+            return Err(HandlerError::RequestMismatch(
+                401,
+                "refresh token is invalid.".to_string(),
+            ));
+        }
+    };
+    Ok(ApiResponse::Ok {
         code: 200,
-        message: "ok.",
+        message: "ok.".to_string(),
         timestamp: Local::now(),
         data: token,
     })
@@ -124,12 +233,10 @@ pub(crate) async fn login(
 pub(crate) async fn logout(req: &mut Request, resp: &mut Response) {
     let token = req.header::<String>("Authentication");
     if token.is_none() {
-        resp.render(ResponseResult {
-            code: 401,
-            message: "unauthorized.",
-            timestamp: Local::now(),
-            data: (),
-        });
+        resp.render(ApiResponse::<()>::from(HandlerError::RequestMismatch(
+            401,
+            "unauthorized.".to_string(),
+        )));
         return;
     }
     todo!("logout");
@@ -141,8 +248,9 @@ struct SignupReq {
     credential: String,
 }
 
+/// a retried signup carrying the same key gets the result of the first one.
 #[handler]
-pub(crate) async fn signup(req: &mut Request, _resp: &mut Response) -> HandlerResult<'static, ()> {
+pub(crate) async fn signup(req: &mut Request, _resp: &mut Response) -> HandlerResult<()> {
     let form = match req.parse_json::<SignupReq>().await {
         Ok(form) => form,
         Err(_) => {
@@ -151,6 +259,72 @@ pub(crate) async fn signup(req: &mut Request, _resp: &mut Response) -> HandlerRe
             ));
         }
     };
+    let idempotency_key = req.header::<String>(IDEMPOTENCY_KEY_HEADER);
+    let mut redis_ops = get_redis_ops().await;
+    signup_idempotent(
+        &mut redis_ops,
+        form.account_id,
+        idempotency_key.as_deref(),
+        || signup_account(&form),
+    )
+    .await
+}
+
+/// run `create` once for an idempotency key. the retries get 200 once it succeeded, rather than
+/// a 409 made by the account it created, and a failed one can be retried with the same key.
+pub(self) async fn signup_idempotent<F, Fut>(
+    redis_ops: &mut RedisOps,
+    account_id: u64,
+    idempotency_key: Option<&str>,
+    create: F,
+) -> HandlerResult<()>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = HandlerResult<()>>,
+{
+    let key = match idempotency_key {
+        Some(idempotency_key) => signup_idempotency_key(account_id, idempotency_key),
+        None => return create().await,
+    };
+    let claimed = match redis_ops
+        .set_nx_exp(&key, &IDEMPOTENCY_PENDING, SIGNUP_IDEMPOTENCY_TTL)
+        .await
+    {
+        Ok(claimed) => claimed,
+        Err(e) => {
+            warn!("claim idempotency key error: {}", e);
+            return create().await;
+        }
+    };
+    if !claimed {
+        return match redis_ops.get::<String>(&key).await {
+            Ok(state) if state == IDEMPOTENCY_DONE => Ok(ApiResponse::Ok {
+                code: 200,
+                message: "ok.".to_string(),
+                timestamp: Local::now(),
+                data: (),
+            }),
+            _ => Err(HandlerError::RequestMismatch(
+                409,
+                "signup with the same idempotency key is in progress.".to_string(),
+            )),
+        };
+    }
+    let res = create().await;
+    let saved = if res.is_ok() {
+        redis_ops
+            .set_exp(&key, &IDEMPOTENCY_DONE, SIGNUP_IDEMPOTENCY_TTL)
+            .await
+    } else {
+        redis_ops.del(&key).await
+    };
+    if let Err(e) = saved {
+        error!("save idempotency key error: {}", e);
+    }
+    res
+}
+
+pub(self) async fn signup_account(form: &SignupReq) -> HandlerResult<()> {
     let user = User::get_account_id(form.account_id as i64).await;
     if user.is_ok() {
         error!("account already signed.");
@@ -180,16 +354,35 @@ pub(crate) async fn signup(req: &mut Request, _resp: &mut Response) -> HandlerRe
         update_at: Local::now(),
         delete_at: DELETE_AT.clone(),
     };
-    let user = user.insert().await;
-    if user.is_err() {
-        error!("insert error: {}", user.err().unwrap());
-        return Err(HandlerError::InternalError(
-            "internal server error.".to_string(),
-        ));
+    insert_user(|| user.insert(), config().sql.retries, config().sql.backoff).await
+}
+
+/// `insert` is retried on deadlocks and serialization failures, which are common when many
+/// accounts sign up at once, a concurrent signup of the same account ends up with 409.
+pub(self) async fn insert_user<F, Fut>(
+    insert: F,
+    retries: usize,
+    backoff: Duration,
+) -> HandlerResult<()>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    if let Err(e) = retry_transient(retries, backoff, insert).await {
+        error!("insert error: {}", e);
+        return match sql_error_kind(&e) {
+            SqlErrorKind::UniqueViolation => Err(HandlerError::RequestMismatch(
+                409,
+                "account already signed.".to_string(),
+            )),
+            _ => Err(HandlerError::InternalError(
+                "internal server error.".to_string(),
+            )),
+        };
     }
-    Ok(ResponseResult {
+    Ok(ApiResponse::Ok {
         code: 200,
-        message: "ok.",
+        message: "ok.".to_string(),
         timestamp: Local::now(),
         data: (),
     })
@@ -201,10 +394,7 @@ pub(crate) async fn sign_out(_req: &mut Request, _resp: &mut Response) {
 }
 
 #[handler]
-pub(crate) async fn which_node(
-    req: &mut Request,
-    _resp: &mut Response,
-) -> HandlerResult<'static, u32> {
+pub(crate) async fn which_node(req: &mut Request, resp: &mut Response) -> HandlerResult<u32> {
     let mut redis_ops = get_redis_ops().await;
     let _user_id = match verify_user(req, &mut redis_ops).await {
         Ok(user_id) => user_id,
@@ -215,34 +405,32 @@ pub(crate) async fn which_node(
             ));
         }
     };
-    let user_id = match req.query::<u64>("user_id") {
-        Some(user_id) => user_id,
-        None => {
-            return Err(HandlerError::ParameterMismatch(
-                "user id is required.".to_string(),
-            ));
-        }
-    };
-    let res = match get_rpc_client().await.call_which_node(user_id).await {
+    let user_id = require_query::<u64>(req, "user_id")?;
+    let request_id = request_id_of(req);
+    let (res, age) = match get_rpc_client()
+        .await
+        .call_which_node_or_recent(user_id, request_id.as_deref())
+        .await
+    {
         Ok(res) => res,
         Err(err) => {
             error!("which_node error: {}", err.to_string());
-            return Err(HandlerError::InternalError(err.to_string()));
+            return Err(HandlerError::RequestMismatch(
+                503,
+                "scheduler is unavailable.".to_string(),
+            ));
         }
     };
-    Ok(ResponseResult {
+    Ok(ApiResponse::Ok {
         code: 200,
-        message: "ok.",
+        message: staleness(resp, age).to_string(),
         timestamp: Local::now(),
         data: res,
     })
 }
 
 #[handler]
-pub(crate) async fn which_address(
-    req: &mut Request,
-    _resp: &mut Response,
-) -> HandlerResult<'static, String> {
+pub(crate) async fn which_address(req: &mut Request, resp: &mut Response) -> HandlerResult<String> {
     let mut redis_ops = get_redis_ops().await;
     let user_id = match verify_user(req, &mut redis_ops).await {
         Ok(user_id) => user_id,
@@ -254,21 +442,42 @@ pub(crate) async fn which_address(
             ));
         }
     };
-    let res = match get_rpc_client().await.call_which_to_connect(user_id).await {
+    let request_id = request_id_of(req);
+    let (res, age) = match get_rpc_client()
+        .await
+        .call_which_to_connect_or_recent(user_id, request_id.as_deref())
+        .await
+    {
         Ok(res) => res,
         Err(err) => {
             error!("which_address error: {}", err.to_string());
-            return Err(HandlerError::InternalError(err.to_string()));
+            return Err(HandlerError::RequestMismatch(
+                503,
+                "scheduler is unavailable.".to_string(),
+            ));
         }
     };
-    Ok(ResponseResult {
+    Ok(ApiResponse::Ok {
         code: 200,
-        message: "ok.",
+        message: staleness(resp, age).to_string(),
         timestamp: Local::now(),
         data: res,
     })
 }
 
+/// a result remembered from before scheduler became unreachable is told by the message,
+/// and its age in seconds by the `Age` header.
+pub(self) fn staleness(resp: &mut Response, age: Option<Duration>) -> &'static str {
+    match age {
+        Some(age) => {
+            resp.headers_mut()
+                .insert(header::AGE, header::HeaderValue::from(age.as_secs()));
+            "stale."
+        }
+        None => "ok.",
+    }
+}
+
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 struct UserInfoResp {
     account_id: i64,
@@ -283,7 +492,7 @@ struct UserInfoResp {
 pub(crate) async fn get_user_info(
     req: &mut Request,
     _resp: &mut Response,
-) -> HandlerResult<'static, UserInfoResp> {
+) -> HandlerResult<UserInfoResp> {
     let mut redis_ops = get_redis_ops().await;
     let _user_id = match verify_user(req, &mut redis_ops).await {
         Ok(user_id) => user_id,
@@ -294,14 +503,7 @@ pub(crate) async fn get_user_info(
             ));
         }
     };
-    let peer_id = match req.query::<u64>("peer_id") {
-        Some(peer_id) => peer_id,
-        None => {
-            return Err(HandlerError::ParameterMismatch(
-                "peer id is required.".to_string(),
-            ));
-        }
-    };
+    let peer_id = require_query::<u64>(req, "peer_id")?;
     let user = match User::get_account_id(peer_id as i64).await {
         Ok(user) => user,
         Err(err) => {
@@ -320,9 +522,9 @@ pub(crate) async fn get_user_info(
         status: user.status as u8,
         info: user.info,
     };
-    Ok(ResponseResult {
+    Ok(ApiResponse::Ok {
         code: 200,
-        message: "ok.",
+        message: "ok.".to_string(),
         timestamp: Local::now(),
         data: res,
     })
@@ -338,10 +540,7 @@ struct UserInfoUpdateReq {
 }
 
 #[handler]
-pub(crate) async fn update_user_info(
-    req: &mut Request,
-    _resp: &mut Response,
-) -> HandlerResult<'static, ()> {
+pub(crate) async fn update_user_info(req: &mut Request, _resp: &mut Response) -> HandlerResult<()> {
     let mut redis_ops = get_redis_ops().await;
     let user_id = match verify_user(req, &mut redis_ops).await {
         Ok(user_id) => user_id,
@@ -365,14 +564,14 @@ pub(crate) async fn update_user_info(
             ));
         }
     };
-    if req.nickname.is_some() {
-        user.nickname = req.nickname.unwrap();
+    if let Some(nickname) = req.nickname.as_deref() {
+        user.nickname = normalize_nickname(nickname).map_err(HandlerError::ParameterMismatch)?;
     }
-    if req.avatar.is_some() {
-        user.avatar = req.avatar.unwrap();
+    if let Some(avatar) = req.avatar.as_deref() {
+        user.avatar = normalize_avatar(avatar).map_err(HandlerError::ParameterMismatch)?;
     }
-    if req.signature.is_some() {
-        user.signature = req.signature.unwrap();
+    if let Some(signature) = req.signature.as_deref() {
+        user.signature = normalize_signature(signature).map_err(HandlerError::ParameterMismatch)?;
     }
     if req.status.is_some() {
         user.status = UserStatus::from(req.status.unwrap());
@@ -395,9 +594,9 @@ pub(crate) async fn update_user_info(
             return Err(HandlerError::InternalError(err.to_string()));
         }
     };
-    Ok(ResponseResult {
+    Ok(ApiResponse::Ok {
         code: 200,
-        message: "ok.",
+        message: "ok.".to_string(),
         timestamp: Local::now(),
         data: (),
     })
@@ -407,7 +606,7 @@ pub(crate) async fn update_user_info(
 pub(crate) async fn get_remark_avatar(
     req: &mut Request,
     _resp: &mut Response,
-) -> HandlerResult<'static, serde_json::Value> {
+) -> HandlerResult<serde_json::Value> {
     let mut redis_ops = get_redis_ops().await;
     let user_id = match verify_user(req, &mut redis_ops).await {
         Ok(user_id) => user_id,
@@ -418,14 +617,7 @@ pub(crate) async fn get_remark_avatar(
             ));
         }
     };
-    let peer_id = match req.query::<u64>("peer_id") {
-        Some(peer_id) => peer_id,
-        None => {
-            return Err(HandlerError::ParameterMismatch(
-                "peer id is required.".to_string(),
-            ));
-        }
-    };
+    let peer_id = require_query::<u64>(req, "peer_id")?;
     let avatar = if peer_id >= GROUP_ID_THRESHOLD {
         let group = match Group::get_group_id(peer_id as i64).await {
             Ok(group) => group,
@@ -462,9 +654,9 @@ pub(crate) async fn get_remark_avatar(
                 ));
             }
         };
-    Ok(ResponseResult {
+    Ok(ApiResponse::Ok {
         code: 200,
-        message: "ok.",
+        message: "ok.".to_string(),
         timestamp: Local::now(),
         data: json!({
             "remark": relationship.remark,
@@ -477,15 +669,8 @@ pub(crate) async fn get_remark_avatar(
 pub(crate) async fn get_nickname_avatar(
     req: &mut Request,
     _resp: &mut Response,
-) -> HandlerResult<'static, serde_json::Value> {
-    let peer_id = match req.query::<u64>("peer_id") {
-        Some(peer_id) => peer_id,
-        None => {
-            return Err(HandlerError::ParameterMismatch(
-                "peer id is required.".to_string(),
-            ));
-        }
-    };
+) -> HandlerResult<serde_json::Value> {
+    let peer_id = require_query::<u64>(req, "peer_id")?;
     let (nickname, avatar) = if peer_id >= GROUP_ID_THRESHOLD {
         let group = match Group::get_group_id(peer_id as i64).await {
             Ok(group) => group,
@@ -511,9 +696,9 @@ pub(crate) async fn get_nickname_avatar(
         };
         (user.nickname, user.avatar)
     };
-    Ok(ResponseResult {
+    Ok(ApiResponse::Ok {
         code: 200,
-        message: "ok.",
+        message: "ok.".to_string(),
         timestamp: Local::now(),
         data: json!({
             "nickname": nickname,
@@ -521,3 +706,249 @@ pub(crate) async fn get_nickname_avatar(
         }),
     })
 }
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct NicknameAvatarBatchReq {
+    peer_ids: Vec<u64>,
+}
+
+/// unknown ids are omitted rather than failing the whole request.
+pub(self) fn nickname_avatar_map(
+    peer_ids: &[u64],
+    entries: Vec<(u64, String, String)>,
+) -> serde_json::Map<String, serde_json::Value> {
+    let mut map = serde_json::Map::new();
+    for (id, nickname, avatar) in entries {
+        if peer_ids.contains(&id) {
+            map.insert(
+                id.to_string(),
+                json!({
+                    "nickname": nickname,
+                    "avatar": avatar,
+                }),
+            );
+        }
+    }
+    map
+}
+
+#[handler]
+pub(crate) async fn get_nickname_avatar_batch(
+    req: &mut Request,
+    _resp: &mut Response,
+) -> HandlerResult<serde_json::Value> {
+    let form = match req.parse_json::<NicknameAvatarBatchReq>().await {
+        Ok(form) => form,
+        Err(e) => return Err(HandlerError::ParameterMismatch(e.to_string())),
+    };
+    if form.peer_ids.len() > NICKNAME_AVATAR_BATCH_LIMIT {
+        return Err(HandlerError::ParameterMismatch(format!(
+            "at most {} peer ids are allowed.",
+            NICKNAME_AVATAR_BATCH_LIMIT
+        )));
+    }
+    let user_ids = form
+        .peer_ids
+        .iter()
+        .filter(|id| **id < GROUP_ID_THRESHOLD)
+        .map(|id| *id as i64)
+        .collect::<Vec<i64>>();
+    let mut entries = match User::get_account_ids(&user_ids).await {
+        Ok(users) => users
+            .into_iter()
+            .map(|user| (user.account_id as u64, user.nickname, user.avatar))
+            .collect::<Vec<(u64, String, String)>>(),
+        Err(err) => {
+            error!("get nickname avatar batch error: {}", err.to_string());
+            return Err(HandlerError::InternalError("internal error".to_string()));
+        }
+    };
+    // groups are rare in a contact list, so look them up one by one.
+    for group_id in form.peer_ids.iter().filter(|id| **id >= GROUP_ID_THRESHOLD) {
+        if let Ok(group) = Group::get_group_id(*group_id as i64).await {
+            entries.push((group.group_id as u64, group.name, group.avatar));
+        }
+    }
+    Ok(ApiResponse::Ok {
+        code: 200,
+        message: "ok.".to_string(),
+        timestamp: Local::now(),
+        data: serde_json::Value::Object(nickname_avatar_map(&form.peer_ids, entries)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        borrow::Cow,
+        collections::HashSet,
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use chrono::Local;
+    use lib::{cache::redis_ops::RedisOps, entity::GROUP_ID_THRESHOLD, util::salt, Result};
+
+    use super::{
+        account_id_of, insert_user, next_account_id, nickname_avatar_map, signup_idempotent,
+        ACCOUNT_ID_BASE,
+    };
+    use crate::{error::HandlerError, handler::ApiResponse};
+
+    /// what postgres reports, only the code matters.
+    #[derive(Debug)]
+    struct StubDbError(&'static str);
+
+    impl std::fmt::Display for StubDbError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "stub db error {}", self.0)
+        }
+    }
+
+    impl std::error::Error for StubDbError {}
+
+    impl sqlx::error::DatabaseError for StubDbError {
+        fn message(&self) -> &str {
+            "stub db error"
+        }
+
+        fn code(&self) -> Option<Cow<'_, str>> {
+            Some(Cow::Borrowed(self.0))
+        }
+
+        fn as_error(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn as_error_mut(&mut self) -> &mut (dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn into_error(self: Box<Self>) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+            self
+        }
+    }
+
+    #[tokio::test]
+    async fn test_insert_user_retry() {
+        let attempts = AtomicUsize::new(0);
+        // deadlocked once, then succeeds.
+        let insert = || async {
+            if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                Err(sqlx::Error::Database(Box::new(StubDbError("40P01"))).into())
+            } else {
+                Ok(())
+            }
+        };
+        let resp = insert_user(insert, 2, Duration::from_millis(1)).await;
+        assert_eq!(resp.ok().unwrap().code(), 200);
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+
+        let attempts = AtomicUsize::new(0);
+        let insert = || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err(sqlx::Error::Database(Box::new(StubDbError("23505"))).into())
+        };
+        let resp = insert_user(insert, 2, Duration::from_millis(1)).await;
+        assert!(matches!(resp, Err(HandlerError::RequestMismatch(409, _))));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_account_id_of() {
+        assert_eq!(account_id_of(0), None);
+        assert_eq!(account_id_of(1), Some(ACCOUNT_ID_BASE + 1));
+        assert_eq!(
+            account_id_of(GROUP_ID_THRESHOLD - ACCOUNT_ID_BASE - 1),
+            Some(GROUP_ID_THRESHOLD - 1)
+        );
+        assert_eq!(account_id_of(GROUP_ID_THRESHOLD - ACCOUNT_ID_BASE), None);
+        assert_eq!(account_id_of(u64::MAX), None);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the redis cluster of docker-compose"]
+    async fn test_next_account_id_concurrent() -> Result<()> {
+        let addresses = vec!["127.0.0.1:16379", "127.0.0.1:16380", "127.0.0.1:16381"]
+            .iter()
+            .map(|x| x.parse().expect("parse error"))
+            .collect();
+        let redis_ops =
+            RedisOps::connect(addresses, Some(vec!["Redis.123456".to_string(); 3])).await?;
+        let mut tasks = Vec::new();
+        for _ in 0..64 {
+            let mut redis_ops = redis_ops.clone();
+            tasks.push(tokio::spawn(async move {
+                let mut ids = Vec::new();
+                for _ in 0..16 {
+                    ids.push(next_account_id(&mut redis_ops).await?);
+                }
+                Result::Ok(ids)
+            }));
+        }
+        let mut set = HashSet::new();
+        for task in tasks {
+            for id in task.await?? {
+                assert!(id > ACCOUNT_ID_BASE && id < GROUP_ID_THRESHOLD);
+                assert!(set.insert(id), "duplicated account id {}", id);
+            }
+        }
+        assert_eq!(set.len(), 64 * 16);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the redis cluster of docker-compose"]
+    async fn test_signup_idempotent() -> Result<()> {
+        let addresses = vec!["127.0.0.1:16379", "127.0.0.1:16380", "127.0.0.1:16381"]
+            .iter()
+            .map(|x| x.parse().expect("parse error"))
+            .collect();
+        let mut redis_ops =
+            RedisOps::connect(addresses, Some(vec!["Redis.123456".to_string(); 3])).await?;
+        let created = AtomicUsize::new(0);
+        // what signing up an account already there does.
+        let signup = || async {
+            if created.fetch_add(1, Ordering::SeqCst) == 0 {
+                Ok(ApiResponse::Ok {
+                    code: 200,
+                    message: "ok.".to_string(),
+                    timestamp: Local::now(),
+                    data: (),
+                })
+            } else {
+                Err(HandlerError::RequestMismatch(
+                    409,
+                    "account already signed.".to_string(),
+                ))
+            }
+        };
+        let key = salt(12);
+        let first = signup_idempotent(&mut redis_ops, 1, Some(&key), signup).await;
+        assert_eq!(first.ok().unwrap().code(), 200);
+        // the retry doesn't sign up again.
+        let retry = signup_idempotent(&mut redis_ops, 1, Some(&key), signup).await;
+        assert_eq!(retry.ok().unwrap().code(), 200);
+        assert_eq!(created.load(Ordering::SeqCst), 1);
+        // without the key, it's an ordinary second signup.
+        let again = signup_idempotent(&mut redis_ops, 1, None, signup).await;
+        assert!(matches!(again, Err(HandlerError::RequestMismatch(409, _))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_nickname_avatar_map() {
+        let peer_ids = vec![1, 2, 3];
+        // id 2 is unknown, id 4 is not requested.
+        let entries = vec![
+            (1, "alice".to_string(), "a.png".to_string()),
+            (3, "bob".to_string(), "b.png".to_string()),
+            (4, "eve".to_string(), "e.png".to_string()),
+        ];
+        let map = nickname_avatar_map(&peer_ids, entries);
+        assert_eq!(map.len(), 2);
+        assert_eq!(map["1"]["nickname"], "alice");
+        assert_eq!(map["3"]["avatar"], "b.png");
+        assert!(map.get("2").is_none());
+    }
+}
@@ -31,14 +31,13 @@ pub(crate) struct Opt {
     pub(crate) config: String,
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
+fn main() -> Result<()> {
     let opt: Opt = Opt::from_args();
     let config_path = match std::env::var("CONFIG_PATH") {
         Ok(config_path) => config_path,
         Err(_) => opt.config,
     };
-    load_config(&config_path);
+    load_config(&config_path)?;
     tracing_subscriber::fmt()
         .event_format(
             tracing_subscriber::fmt::format()
@@ -49,8 +48,23 @@ async fn main() -> Result<()> {
         .with_max_level(config().log_level)
         .try_init()
         .unwrap();
+    // the runtime is built by hand, as the number of workers comes from the config.
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(config().api.worker_threads)
+        .enable_all()
+        .build()?
+        .block_on(serve())
+}
+
+async fn serve() -> Result<()> {
     println!("{}", joy::banner());
-    info!("prim api running on {}", config().server.service_address);
+    info!(
+        "prim api running on {} with {} workers",
+        config().api.bind_address,
+        config().api.worker_threads
+    );
+    salvo::http::request::set_secure_max_size(config().api.max_body_size);
+    tokio::spawn(handler::health::update_health(std::time::Duration::from_secs(5)));
     tokio::spawn(async move {
         if let Err(e) = rpc::start().await {
             tracing::error!("rpc server error: {}", e);
@@ -81,13 +95,21 @@ async fn main() -> Result<()> {
             HeaderName::from_static("content-type"),
             HeaderName::from_static("authorization"),
         ])
+        // the refresh token given by `/user/login` is only readable by the page with it exposed.
+        .expose_headers(vec![HeaderName::from_static(
+            handler::user::REFRESH_TOKEN_HEADER,
+        )])
         .into_handler();
     let router = Router::with_hoop(cors)
+        .hoop(handler::middleware::request_id)
+        .hoop(handler::middleware::BodyLimit::new(config().api.max_body_size))
         .push(
             Router::with_path("/which_node")
                 .get(handler::user::which_node)
                 .options(salvo::prelude::handler::empty()),
         )
+        .push(Router::with_path("/gateway").get(handler::gateway::gateway))
+        .push(Router::with_path("/health").get(handler::health::health))
         .push(
             Router::with_path("/which_address")
                 .get(handler::user::which_address)
@@ -109,6 +131,11 @@ async fn main() -> Result<()> {
                         .delete(handler::user::logout)
                         .options(salvo::prelude::handler::empty()),
                 )
+                .push(
+                    Router::with_path("/refresh")
+                        .put(handler::user::refresh)
+                        .options(salvo::prelude::handler::empty()),
+                )
                 .push(
                     Router::with_path("/info")
                         .get(handler::user::get_user_info)
@@ -125,6 +152,11 @@ async fn main() -> Result<()> {
                         .get(handler::user::get_nickname_avatar)
                         .options(salvo::prelude::handler::empty()),
                 )
+                .push(
+                    Router::with_path("/s-info-n-batch")
+                        .post(handler::user::get_nickname_avatar_batch)
+                        .options(salvo::prelude::handler::empty()),
+                )
                 .push(
                     Router::with_path("/account")
                         .delete(handler::user::sign_out)
@@ -206,6 +238,13 @@ async fn main() -> Result<()> {
                         .put(handler::relationship::update_relationship)
                         .get(handler::relationship::get_friend_list)
                         .options(salvo::prelude::handler::empty()),
+                )
+                .push(
+                    Router::with_path("/contact")
+                        .post(handler::relationship::add_relationship)
+                        .put(handler::relationship::set_relationship_status)
+                        .delete(handler::relationship::remove_relationship)
+                        .options(salvo::prelude::handler::empty()),
                 ),
         )
         .options(salvo::prelude::handler::empty());
@@ -214,11 +253,11 @@ async fn main() -> Result<()> {
             .cert(config().server.cert.0.clone())
             .key(config().server.key.0.clone()),
     );
-    let mut version1_address = config().server.service_address.clone();
+    let mut version1_address = config().api.bind_address;
     version1_address.set_port(version1_address.port() + 2);
     let listener = TcpListener::new(version1_address);
-    let acceptor = TcpListener::new(config().server.service_address).rustls(rustls_config.clone());
-    let acceptor = QuinnListener::new(rustls_config, config().server.service_address)
+    let acceptor = TcpListener::new(config().api.bind_address).rustls(rustls_config.clone());
+    let acceptor = QuinnListener::new(rustls_config, config().api.bind_address)
         .join(acceptor)
         .join(listener)
         .bind()
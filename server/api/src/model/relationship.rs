@@ -32,6 +32,8 @@ pub(crate) enum UserRelationshipStatus {
     Deleting = 4,
     Deleted = 5,
     Blocked = 6,
+    /// requested by the user, not yet accepted by the peer.
+    Pending = 7,
 }
 
 impl From<u8> for UserRelationshipStatus {
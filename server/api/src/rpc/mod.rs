@@ -1,18 +1,18 @@
 use lib::Result;
 use tokio::sync::OnceCell;
 
-use self::node::Client;
+use self::node::ResilientRpcClient;
 
 pub(crate) mod node;
 mod node_proto;
 
 #[allow(unused)]
-pub(crate) static NODE_CLIENT: OnceCell<Client> = OnceCell::const_new();
+pub(crate) static NODE_CLIENT: OnceCell<ResilientRpcClient> = OnceCell::const_new();
 
 #[allow(unused)]
-pub(crate) async fn get_rpc_client() -> Client {
+pub(crate) async fn get_rpc_client() -> ResilientRpcClient {
     (NODE_CLIENT
-        .get_or_init(|| async { Client::new().await.unwrap() })
+        .get_or_init(|| async { ResilientRpcClient::new().await.unwrap() })
         .await)
         .clone()
 }
@@ -1,11 +1,18 @@
+use std::{
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
 use async_trait::async_trait;
 use base64::Engine;
+use dashmap::DashMap;
 use lib::{entity::Msg, Result};
 use tonic::{
     transport::{Channel, ClientTlsConfig, Server, ServerTlsConfig},
-    Request, Response, Status,
+    Code, Request, Response, Status,
 };
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 use super::node_proto::{
     api_server::{Api, ApiServer},
@@ -13,14 +20,53 @@ use super::node_proto::{
     GroupUserListReq, GroupUserListResp, PushMsgReq, WhichNodeReq,
 };
 use crate::rpc::node_proto::WhichToConnectReq;
-use crate::{config::config, model::group::Group};
+use crate::{config::config, handler::middleware::REQUEST_ID_HEADER, model::group::Group};
+
+/// deadline of every attempt, and how idempotent calls are retried.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RetryPolicy {
+    pub(crate) deadline: Duration,
+    pub(crate) retries: usize,
+    pub(crate) backoff: Duration,
+    /// how long a result is kept to answer with when the calls fail.
+    pub(crate) stale_ttl: Duration,
+}
+
+/// the last results of a lookup keyed by user id, shared by the clones of the client.
+pub(self) type RecentResults<V> = Arc<DashMap<u64, (V, Instant)>>;
+
+/// at most so many results are kept for a lookup, the expired ones are swept when it's reached.
+pub(self) const RECENT_RESULTS_CAPACITY: usize = 100_000;
 
+/// keep `value` as the latest result of `user_id`. once `capacity` is reached the results older
+/// than `stale_ttl` are swept, and a new user is not remembered if there are still too many.
+pub(self) fn remember<V>(
+    results: &RecentResults<V>,
+    user_id: u64,
+    value: V,
+    stale_ttl: Duration,
+    capacity: usize,
+) {
+    if results.len() >= capacity && !results.contains_key(&user_id) {
+        results.retain(|_, (_, at)| at.elapsed() <= stale_ttl);
+        if results.len() >= capacity {
+            return;
+        }
+    }
+    results.insert(user_id, (value, Instant::now()));
+}
+
+/// scheduler client which bounds every call by a deadline,
+/// and retries the idempotent ones when scheduler is momentarily unavailable.
 #[derive(Clone)]
-pub(crate) struct Client {
+pub(crate) struct ResilientRpcClient {
     scheduler_client: SchedulerClient<Channel>,
+    policy: RetryPolicy,
+    which_node_results: RecentResults<u32>,
+    which_to_connect_results: RecentResults<String>,
 }
 
-impl Client {
+impl ResilientRpcClient {
     pub(crate) async fn new() -> Result<Self> {
         let tls = ClientTlsConfig::new()
             .ca_certificate(config().rpc.scheduler.cert.clone())
@@ -30,17 +76,144 @@ impl Client {
             .tls_config(tls)?
             .connect()
             .await?;
-        let scheduler_client = SchedulerClient::new(scheduler_channel);
-        Ok(Self { scheduler_client })
+        let policy = RetryPolicy {
+            deadline: config().rpc.scheduler.deadline,
+            retries: config().rpc.scheduler.retries,
+            backoff: config().rpc.scheduler.backoff,
+            stale_ttl: config().rpc.scheduler.stale_ttl,
+        };
+        Ok(Self::with_channel(scheduler_channel, policy))
     }
 
-    #[allow(unused)]
-    pub(crate) async fn call_which_node(&mut self, user_id: u64) -> Result<u32> {
-        let request = Request::new(WhichNodeReq { user_id });
-        let response = self.scheduler_client.which_node(request).await?;
+    pub(self) fn with_channel(channel: Channel, policy: RetryPolicy) -> Self {
+        Self {
+            scheduler_client: SchedulerClient::new(channel),
+            policy,
+            which_node_results: Arc::new(DashMap::new()),
+            which_to_connect_results: Arc::new(DashMap::new()),
+        }
+    }
+
+    /// remember the result of a successful call, or fall back to the one remembered within `stale_ttl`
+    /// when the call fails. the age of the result is returned when it's a fallback.
+    pub(self) fn or_recent<V: Clone>(
+        &self,
+        results: &RecentResults<V>,
+        user_id: u64,
+        res: Result<V>,
+    ) -> Result<(V, Option<Duration>)> {
+        match res {
+            Ok(value) => {
+                remember(
+                    results,
+                    user_id,
+                    value.clone(),
+                    self.policy.stale_ttl,
+                    RECENT_RESULTS_CAPACITY,
+                );
+                Ok((value, None))
+            }
+            Err(err) => {
+                let recent = results
+                    .get(&user_id)
+                    .map(|entry| (entry.0.clone(), entry.1.elapsed()));
+                match recent {
+                    Some((value, age)) if age <= self.policy.stale_ttl => {
+                        warn!(
+                            "scheduler call failed: {}, answer with result of {:?} ago.",
+                            err, age
+                        );
+                        Ok((value, Some(age)))
+                    }
+                    Some(_) => {
+                        results.remove(&user_id);
+                        Err(err)
+                    }
+                    None => Err(err),
+                }
+            }
+        }
+    }
+
+    /// bound a single attempt by the deadline, both on the wire and locally.
+    pub(self) async fn once<T, R, F, Fut>(
+        &self,
+        mut request: Request<T>,
+        call: F,
+    ) -> std::result::Result<R, Status>
+    where
+        F: FnOnce(SchedulerClient<Channel>, Request<T>) -> Fut,
+        Fut: Future<Output = std::result::Result<R, Status>>,
+    {
+        request.set_timeout(self.policy.deadline);
+        match tokio::time::timeout(
+            self.policy.deadline,
+            call(self.scheduler_client.clone(), request),
+        )
+        .await
+        {
+            Ok(res) => res,
+            Err(_) => Err(Status::deadline_exceeded("scheduler call timed out.")),
+        }
+    }
+
+    /// only for calls which are safe to be repeated, `request` builds a fresh request for every attempt.
+    pub(self) async fn idempotent<T, R, B, F, Fut>(
+        &self,
+        request: B,
+        call: F,
+    ) -> std::result::Result<R, Status>
+    where
+        B: Fn() -> Request<T>,
+        F: Fn(SchedulerClient<Channel>, Request<T>) -> Fut,
+        Fut: Future<Output = std::result::Result<R, Status>>,
+    {
+        let mut backoff = self.policy.backoff;
+        let mut attempt = 0;
+        loop {
+            match self.once(request(), &call).await {
+                Err(status)
+                    if attempt < self.policy.retries
+                        && matches!(status.code(), Code::Unavailable | Code::DeadlineExceeded) =>
+                {
+                    warn!("scheduler call failed: {}, retry in {:?}.", status, backoff);
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    pub(crate) async fn call_which_node(
+        &mut self,
+        user_id: u64,
+        request_id: Option<&str>,
+    ) -> Result<u32> {
+        let response = self
+            .idempotent(
+                || {
+                    let mut request = Request::new(WhichNodeReq { user_id });
+                    with_request_id(&mut request, request_id);
+                    request
+                },
+                |mut client, request| async move { client.which_node(request).await },
+            )
+            .await?;
         Ok(response.into_inner().node_id)
     }
 
+    /// like [`Self::call_which_node`], but a recent result is answered with when scheduler is unreachable.
+    pub(crate) async fn call_which_node_or_recent(
+        &mut self,
+        user_id: u64,
+        request_id: Option<&str>,
+    ) -> Result<(u32, Option<Duration>)> {
+        let res = self.call_which_node(user_id, request_id).await;
+        self.or_recent(&self.which_node_results, user_id, res)
+    }
+
     #[allow(unused)]
     pub(crate) async fn call_push_msg(&mut self, msg: &Msg) -> Result<()> {
         let engine = base64::engine::GeneralPurpose::new(
@@ -56,7 +229,12 @@ impl Client {
             payload: engine.encode(msg.payload()),
             extension: engine.encode(msg.extension()),
         });
-        let response = self.scheduler_client.push_msg(request).await?;
+        // pushing twice may deliver twice, so no retry here.
+        let response = self
+            .once(request, |mut client, request| async move {
+                client.push_msg(request).await
+            })
+            .await?;
         let resp = response.into_inner();
         if resp.success {
             Ok(())
@@ -65,11 +243,40 @@ impl Client {
         }
     }
 
-    pub(crate) async fn call_which_to_connect(&mut self, user_id: u64) -> Result<String> {
-        let request = Request::new(WhichToConnectReq { user_id });
-        let response = self.scheduler_client.which_to_connect(request).await?;
+    pub(crate) async fn call_which_to_connect(
+        &mut self,
+        user_id: u64,
+        request_id: Option<&str>,
+    ) -> Result<String> {
+        let response = self
+            .idempotent(
+                || {
+                    let mut request = Request::new(WhichToConnectReq { user_id });
+                    with_request_id(&mut request, request_id);
+                    request
+                },
+                |mut client, request| async move { client.which_to_connect(request).await },
+            )
+            .await?;
         Ok(response.into_inner().address)
     }
+
+    /// like [`Self::call_which_to_connect`], but a recent result is answered with when scheduler is unreachable.
+    pub(crate) async fn call_which_to_connect_or_recent(
+        &mut self,
+        user_id: u64,
+        request_id: Option<&str>,
+    ) -> Result<(String, Option<Duration>)> {
+        let res = self.call_which_to_connect(user_id, request_id).await;
+        self.or_recent(&self.which_to_connect_results, user_id, res)
+    }
+}
+
+/// propagate the http request id so the scheduler side logs can be correlated.
+pub(self) fn with_request_id<T>(request: &mut Request<T>, request_id: Option<&str>) {
+    if let Some(Ok(value)) = request_id.map(|id| id.parse()) {
+        request.metadata_mut().insert(REQUEST_ID_HEADER, value);
+    }
 }
 
 pub(crate) struct RpcServer {}
@@ -125,3 +332,224 @@ impl Api for RpcServer {
         res
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use async_trait::async_trait;
+    use dashmap::DashMap;
+    use lib::entity::Msg;
+    use tonic::{
+        transport::{Channel, Server},
+        Request, Response, Status,
+    };
+
+    use super::{remember, RecentResults, ResilientRpcClient, RetryPolicy};
+    use crate::rpc::node_proto::{
+        scheduler_server::{Scheduler, SchedulerServer},
+        CurrNodeGroupIdUserListReq, CurrNodeGroupIdUserListResp, PushMsgReq, PushMsgResp,
+        RecorderListReq, RecorderListResp, WhichNodeReq, WhichNodeResp, WhichToConnectReq,
+        WhichToConnectResp,
+    };
+
+    /// unavailable for the first call of `which_node`, and always for `push_msg`.
+    struct FlakyScheduler {
+        which_node_calls: Arc<AtomicUsize>,
+        push_msg_calls: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl Scheduler for FlakyScheduler {
+        async fn curr_node_group_id_user_list(
+            &self,
+            _request: Request<CurrNodeGroupIdUserListReq>,
+        ) -> Result<Response<CurrNodeGroupIdUserListResp>, Status> {
+            Err(Status::unimplemented(""))
+        }
+
+        async fn which_node(
+            &self,
+            _request: Request<WhichNodeReq>,
+        ) -> Result<Response<WhichNodeResp>, Status> {
+            if self.which_node_calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                return Err(Status::unavailable("not ready"));
+            }
+            Ok(Response::new(WhichNodeResp { node_id: 7 }))
+        }
+
+        async fn push_msg(
+            &self,
+            _request: Request<PushMsgReq>,
+        ) -> Result<Response<PushMsgResp>, Status> {
+            self.push_msg_calls.fetch_add(1, Ordering::SeqCst);
+            Err(Status::unavailable("not ready"))
+        }
+
+        async fn recorder_list(
+            &self,
+            _request: Request<RecorderListReq>,
+        ) -> Result<Response<RecorderListResp>, Status> {
+            Err(Status::unimplemented(""))
+        }
+
+        async fn which_to_connect(
+            &self,
+            _request: Request<WhichToConnectReq>,
+        ) -> Result<Response<WhichToConnectResp>, Status> {
+            Err(Status::unimplemented(""))
+        }
+    }
+
+    /// answer `which_node` until it's down.
+    struct DownScheduler {
+        down: Arc<AtomicBool>,
+    }
+
+    #[async_trait]
+    impl Scheduler for DownScheduler {
+        async fn curr_node_group_id_user_list(
+            &self,
+            _request: Request<CurrNodeGroupIdUserListReq>,
+        ) -> Result<Response<CurrNodeGroupIdUserListResp>, Status> {
+            Err(Status::unimplemented(""))
+        }
+
+        async fn which_node(
+            &self,
+            _request: Request<WhichNodeReq>,
+        ) -> Result<Response<WhichNodeResp>, Status> {
+            if self.down.load(Ordering::SeqCst) {
+                return Err(Status::unavailable("down"));
+            }
+            Ok(Response::new(WhichNodeResp { node_id: 7 }))
+        }
+
+        async fn push_msg(
+            &self,
+            _request: Request<PushMsgReq>,
+        ) -> Result<Response<PushMsgResp>, Status> {
+            Err(Status::unimplemented(""))
+        }
+
+        async fn recorder_list(
+            &self,
+            _request: Request<RecorderListReq>,
+        ) -> Result<Response<RecorderListResp>, Status> {
+            Err(Status::unimplemented(""))
+        }
+
+        async fn which_to_connect(
+            &self,
+            _request: Request<WhichToConnectReq>,
+        ) -> Result<Response<WhichToConnectResp>, Status> {
+            Err(Status::unimplemented(""))
+        }
+    }
+
+    #[test]
+    fn test_remember_bounded() {
+        let results: RecentResults<u32> = Arc::new(DashMap::new());
+        let ttl = Duration::from_millis(20);
+        remember(&results, 1, 1, ttl, 2);
+        remember(&results, 2, 2, ttl, 2);
+        // full of fresh results, a new user is not remembered but a known one is updated.
+        remember(&results, 3, 3, ttl, 2);
+        assert!(!results.contains_key(&3));
+        remember(&results, 2, 20, ttl, 2);
+        assert_eq!(results.get(&2).unwrap().0, 20);
+        assert_eq!(results.len(), 2);
+
+        std::thread::sleep(ttl * 2);
+        // the expired are swept to make room.
+        remember(&results, 3, 3, ttl, 2);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results.get(&3).unwrap().0, 3);
+    }
+
+    #[tokio::test]
+    async fn test_which_node_or_recent() {
+        let address = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let down = Arc::new(AtomicBool::new(false));
+        let scheduler = DownScheduler { down: down.clone() };
+        tokio::spawn(
+            Server::builder()
+                .add_service(SchedulerServer::new(scheduler))
+                .serve(address),
+        );
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let channel = Channel::from_shared(format!("http://{}", address))
+            .unwrap()
+            .connect_lazy();
+        let mut client = ResilientRpcClient::with_channel(
+            channel,
+            RetryPolicy {
+                deadline: Duration::from_secs(1),
+                retries: 0,
+                backoff: Duration::from_millis(10),
+                stale_ttl: Duration::from_secs(60),
+            },
+        );
+        assert_eq!(
+            client.call_which_node_or_recent(1, None).await.unwrap(),
+            (7, None)
+        );
+
+        down.store(true, Ordering::SeqCst);
+        // remembered by the clones too, as every handler takes a clone.
+        let mut clone = client.clone();
+        let (node_id, age) = clone.call_which_node_or_recent(1, None).await.unwrap();
+        assert_eq!(node_id, 7);
+        assert!(age.is_some());
+        // nothing to fall back to.
+        assert!(client.call_which_node_or_recent(2, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_retry_idempotent_only() {
+        let address = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let which_node_calls = Arc::new(AtomicUsize::new(0));
+        let push_msg_calls = Arc::new(AtomicUsize::new(0));
+        let scheduler = FlakyScheduler {
+            which_node_calls: which_node_calls.clone(),
+            push_msg_calls: push_msg_calls.clone(),
+        };
+        tokio::spawn(
+            Server::builder()
+                .add_service(SchedulerServer::new(scheduler))
+                .serve(address),
+        );
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let channel = Channel::from_shared(format!("http://{}", address))
+            .unwrap()
+            .connect_lazy();
+        let mut client = ResilientRpcClient::with_channel(
+            channel,
+            RetryPolicy {
+                deadline: Duration::from_secs(1),
+                retries: 2,
+                backoff: Duration::from_millis(10),
+                stale_ttl: Duration::from_secs(60),
+            },
+        );
+        assert_eq!(client.call_which_node(1, None).await.unwrap(), 7);
+        assert_eq!(which_node_calls.load(Ordering::SeqCst), 2);
+        assert!(client
+            .call_push_msg(&Msg::text(1, 2, 0, "hello"))
+            .await
+            .is_err());
+        assert_eq!(push_msg_calls.load(Ordering::SeqCst), 1);
+    }
+}
@@ -1,13 +1,19 @@
-use std::{str::FromStr, time::SystemTime};
+use std::{
+    future::Future,
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
 
 use crate::config::config;
 use chrono::{DateTime, Local};
 use lazy_static::lazy_static;
+use lib::{util::backoff::Backoff, Result};
 use sqlx::{
     postgres::{PgConnectOptions, PgPoolOptions},
     ConnectOptions, Pool, Postgres,
 };
 use tokio::sync::OnceCell;
+use tracing::warn;
 
 pub(self) static SQL_POOL: OnceCell<Pool<Postgres>> = OnceCell::const_new();
 
@@ -34,3 +40,64 @@ pub(super) async fn get_sql_pool() -> &'static Pool<Postgres> {
         })
         .await
 }
+
+/// postgres error codes worth telling apart, see appendix a of its manual.
+pub(self) const DEADLOCK_DETECTED: &str = "40P01";
+pub(self) const SERIALIZATION_FAILURE: &str = "40001";
+pub(self) const UNIQUE_VIOLATION: &str = "23505";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SqlErrorKind {
+    /// lost a race with another transaction, the same statement may succeed later.
+    Retryable,
+    /// the row exists already.
+    UniqueViolation,
+    Other,
+}
+
+pub(crate) fn sql_error_kind(e: &anyhow::Error) -> SqlErrorKind {
+    let code = e
+        .downcast_ref::<sqlx::Error>()
+        .and_then(|e| e.as_database_error())
+        .and_then(|e| e.code());
+    match code.as_deref() {
+        Some(DEADLOCK_DETECTED) | Some(SERIALIZATION_FAILURE) => SqlErrorKind::Retryable,
+        Some(UNIQUE_VIOLATION) => SqlErrorKind::UniqueViolation,
+        _ => SqlErrorKind::Other,
+    }
+}
+
+/// run `op` again on [`SqlErrorKind::Retryable`] errors, at most `retries` times, other errors
+/// are returned at once. the delay starts from `backoff` and is doubled with jitter by
+/// [`Backoff`].
+pub(crate) async fn retry_transient<T, F, Fut>(
+    retries: usize,
+    backoff: Duration,
+    op: F,
+) -> Result<T>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    // the attempts are bounded by `retries` rather than by time.
+    let mut backoff = Backoff::new(
+        backoff,
+        backoff.saturating_mul(1 << retries.min(16)),
+        Duration::MAX,
+    );
+    let mut attempt = 0;
+    loop {
+        match op().await {
+            Err(e) if attempt < retries && sql_error_kind(&e) == SqlErrorKind::Retryable => {
+                let delay = match backoff.next_delay() {
+                    Some(delay) => delay,
+                    None => return Err(e),
+                };
+                warn!("sql failed: {}, retry in {:?}.", e, delay);
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            res => return res,
+        }
+    }
+}
@@ -1,4 +1,5 @@
 pub mod scheduler;
+pub mod shutdown;
 
 #[cfg(test)]
 mod tests {
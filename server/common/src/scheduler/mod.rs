@@ -1,9 +1,10 @@
-use std::{sync::Arc, time::Duration};
+use std::{future::Future, sync::Arc, time::Duration};
 
 use async_trait::async_trait;
 use lib::{
     entity::{ReqwestMsg, ReqwestResourceID, ServerInfo, ServerType},
     net::{client::ClientConfig, InnerStates},
+    util::{backoff::Backoff, health},
     Result,
 };
 use lib_net_tokio::net::{
@@ -12,7 +13,7 @@ use lib_net_tokio::net::{
 };
 
 use tokio::sync::mpsc;
-use tracing::error;
+use tracing::{error, warn};
 
 pub async fn connect2scheduler(
     client_config: ClientConfig,
@@ -22,8 +23,6 @@ pub async fn connect2scheduler(
     states_gen: Box<dyn Fn() -> InnerStates + Send + Sync + 'static>,
     reqwest_request_id: ReqwestResourceID,
 ) -> Result<ReqwestOperatorManager> {
-    let mut client = ClientReqwest::new(client_config, timeout);
-
     struct ReqwestMessageHandler {
         handler_map: ReqwestHandlerMap,
         states: InnerStates,
@@ -79,17 +78,352 @@ pub async fn connect2scheduler(
             })
         });
     let generator = Arc::new(generator);
-    let operator = client.build(generator).await?;
-    // for client, we only need the operator manager returned, so leak client for drop on exit.
-    Box::leak(Box::new(client));
+    // scheduler may be down for a moment when the whole cluster starts.
+    retry("register to scheduler", Backoff::default(), || {
+        register(
+            client_config.clone(),
+            timeout,
+            generator.clone(),
+            &self_info,
+            reqwest_request_id,
+        )
+    })
+    .await
+}
 
+pub(self) async fn register(
+    client_config: ClientConfig,
+    timeout: Duration,
+    generator: Arc<ReqwestHandlerGenerator>,
+    self_info: &ServerInfo,
+    reqwest_request_id: ReqwestResourceID,
+) -> Result<ReqwestOperatorManager> {
+    let mut client = ClientReqwest::new(client_config, timeout);
+    let operator = client.build(generator).await?;
     let mut auth_info = self_info.clone();
     auth_info.typ = ServerType::SchedulerClient;
     let auth_msg =
         ReqwestMsg::with_resource_id_payload(ReqwestResourceID::NodeAuth, &auth_info.to_bytes());
     let _resp = operator.call(auth_msg).await?;
+    health::set_scheduler_connected(true);
     let register_msg =
         ReqwestMsg::with_resource_id_payload(reqwest_request_id, &self_info.to_bytes());
     let _resp = operator.call(register_msg).await?;
+    health::set_registered(true);
+    // for client, we only need the operator manager returned, so leak client for drop on exit.
+    // a failed attempt drops it to close the connection.
+    Box::leak(Box::new(client));
     Ok(operator)
 }
+
+/// tell scheduler that this node is leaving, so no more clients or peers are routed to it.
+pub async fn deregister(
+    operator: &ReqwestOperatorManager,
+    self_info: &ServerInfo,
+    unregister_id: ReqwestResourceID,
+) -> Result<()> {
+    let unregister_msg = ReqwestMsg::with_resource_id_payload(unregister_id, &self_info.to_bytes());
+    let _resp = operator.call(unregister_msg).await?;
+    Ok(())
+}
+
+/// tell scheduler every `interval` that this node is alive with the heartbeat `heartbeat_id` of
+/// `self_info()`, until `stopped()`. a scheduler answering with `register_id` has lost the
+/// registration, e.g. after a restart, so the node registers again.
+pub async fn heartbeat(
+    operator: &ReqwestOperatorManager,
+    self_info: impl Fn() -> ServerInfo,
+    heartbeat_id: ReqwestResourceID,
+    register_id: ReqwestResourceID,
+    interval: Duration,
+    stopped: impl Fn() -> bool,
+) {
+    loop {
+        tokio::time::sleep(interval).await;
+        // never register again once deregistering may have started.
+        if stopped() {
+            break;
+        }
+        let server_info = self_info();
+        let heartbeat_msg =
+            ReqwestMsg::with_resource_id_payload(heartbeat_id, &server_info.heartbeat().to_bytes());
+        match operator.call(heartbeat_msg).await {
+            Ok(resp) if resp.resource_id() == register_id => {
+                warn!("scheduler has lost the registration, register again.");
+                let register_msg =
+                    ReqwestMsg::with_resource_id_payload(register_id, &server_info.to_bytes());
+                if let Err(e) = operator.call(register_msg).await {
+                    error!("register to scheduler error: {}", e);
+                }
+            }
+            Ok(_) => {}
+            Err(e) => warn!("heartbeat to scheduler error: {}", e),
+        }
+    }
+}
+
+/// call `f` until it succeeds or `backoff` gives up, every failed attempt is logged.
+pub async fn retry<T, F, Fut>(what: &str, backoff: Backoff, f: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    retry_with(what, backoff, f, tokio::time::sleep).await
+}
+
+/// like [`retry`], but waits between the attempts by `sleep`, so it works on any runtime.
+pub async fn retry_with<T, F, Fut, S, SFut>(
+    what: &str,
+    mut backoff: Backoff,
+    mut f: F,
+    mut sleep: S,
+) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+    S: FnMut(Duration) -> SFut,
+    SFut: Future<Output = ()>,
+{
+    let mut attempt = 1;
+    loop {
+        match f().await {
+            Ok(res) => return Ok(res),
+            Err(e) => match backoff.next_delay() {
+                Some(delay) => {
+                    warn!(
+                        "{} failed at attempt {}: {}, retry in {:?}.",
+                        what, attempt, e, delay
+                    );
+                    sleep(delay).await;
+                    attempt += 1;
+                }
+                None => {
+                    error!("{} failed after {} attempts: {}", what, attempt, e);
+                    return Err(e);
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        time::Duration,
+    };
+
+    use ahash::AHashMap;
+    use async_trait::async_trait;
+    use lib::{
+        entity::{ReqwestMsg, ReqwestResourceID, ServerInfo},
+        net::InnerStates,
+        util::{backoff::Backoff, health},
+        Result,
+    };
+    use lib_net_tokio::net::{
+        client::ClientReqwest,
+        mock::config_pair,
+        server::{ReqwestCaller, ServerReqwest},
+        NewReqwestConnectionHandler, ReqwestHandlerGenerator, ReqwestHandlerMap,
+    };
+    use tokio::sync::{mpsc, oneshot};
+
+    use super::{connect2scheduler, deregister, retry, retry_with};
+
+    /// answer every request with an empty body, as scheduler does for auth and register.
+    struct FakeScheduler;
+
+    #[async_trait]
+    impl NewReqwestConnectionHandler for FakeScheduler {
+        async fn handle(
+            &mut self,
+            msg_operators: (mpsc::Sender<ReqwestMsg>, mpsc::Receiver<ReqwestMsg>),
+        ) -> Result<()> {
+            let (send, mut recv) = msg_operators;
+            while let Some(req) = recv.recv().await {
+                let mut resp = ReqwestMsg::with_resource_id_payload(req.resource_id(), b"");
+                resp.set_req_id(req.req_id());
+                _ = send.send(resp).await;
+            }
+            Ok(())
+        }
+
+        fn set_reqwest_caller(&mut self, _reqwest_caller: ReqwestCaller) {}
+    }
+
+    #[tokio::test]
+    async fn test_ready_after_register() {
+        let (server_config, client_config) = config_pair();
+        let mut server = ServerReqwest::new(server_config.build().unwrap(), Duration::from_secs(3));
+        let generator: ReqwestHandlerGenerator = Box::new(|| Box::new(FakeScheduler));
+        tokio::spawn(async move { server.run(Arc::new(generator)).await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // the flags are process wide, so start from a known state whatever ran before.
+        health::set_registered(false);
+        health::set_scheduler_connected(false);
+        assert!(!health::current_health().ready);
+        let _operator = connect2scheduler(
+            client_config.build().unwrap(),
+            Duration::from_secs(3),
+            ReqwestHandlerMap::new(AHashMap::new()),
+            ServerInfo::default(),
+            Box::new(InnerStates::new),
+            ReqwestResourceID::MessageNodeRegister,
+        )
+        .await
+        .unwrap();
+        let health = health::current_health();
+        health::set_registered(false);
+        health::set_scheduler_connected(false);
+        assert!(health.ready);
+        assert!(health.scheduler_connected);
+    }
+
+    /// like `FakeScheduler`, but remember the resource id of every request.
+    struct RecordingScheduler {
+        received: Arc<Mutex<Vec<ReqwestResourceID>>>,
+    }
+
+    #[async_trait]
+    impl NewReqwestConnectionHandler for RecordingScheduler {
+        async fn handle(
+            &mut self,
+            msg_operators: (mpsc::Sender<ReqwestMsg>, mpsc::Receiver<ReqwestMsg>),
+        ) -> Result<()> {
+            let (send, mut recv) = msg_operators;
+            while let Some(req) = recv.recv().await {
+                self.received.lock().unwrap().push(req.resource_id());
+                let mut resp = ReqwestMsg::with_resource_id_payload(req.resource_id(), b"");
+                resp.set_req_id(req.req_id());
+                _ = send.send(resp).await;
+            }
+            Ok(())
+        }
+
+        fn set_reqwest_caller(&mut self, _reqwest_caller: ReqwestCaller) {}
+    }
+
+    #[tokio::test]
+    async fn test_deregister_on_shutdown() {
+        let (server_config, client_config) = config_pair();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let received0 = received.clone();
+        let mut server = ServerReqwest::new(server_config.build().unwrap(), Duration::from_secs(3));
+        let generator: ReqwestHandlerGenerator = Box::new(move || {
+            Box::new(RecordingScheduler {
+                received: received0.clone(),
+            })
+        });
+        tokio::spawn(async move { server.run(Arc::new(generator)).await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // connect without `connect2scheduler`, the readiness flag is shared with other tests.
+        let mut client = ClientReqwest::new(client_config.build().unwrap(), Duration::from_secs(3));
+        let generator: ReqwestHandlerGenerator = Box::new(|| Box::new(FakeScheduler));
+        let operator = client.build(Arc::new(generator)).await.unwrap();
+
+        // stands for `shutdown_signal`, a real SIGTERM would hit the whole test process.
+        let (stop, signal) = oneshot::channel::<()>();
+        let shutdown = tokio::spawn(async move {
+            _ = signal.await;
+            deregister(
+                &operator,
+                &ServerInfo::default(),
+                ReqwestResourceID::MessageNodeUnregister,
+            )
+            .await
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(received.lock().unwrap().is_empty());
+        stop.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(3), shutdown)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            received.lock().unwrap().as_slice(),
+            &[ReqwestResourceID::MessageNodeUnregister]
+        );
+    }
+
+    /// stands for the scheduler operator, which is not up until the third call.
+    struct FlakyOperator {
+        calls: AtomicUsize,
+    }
+
+    impl FlakyOperator {
+        async fn call(&self) -> Result<ReqwestMsg> {
+            if self.calls.fetch_add(1, Ordering::AcqRel) < 2 {
+                return Err(anyhow::anyhow!("scheduler unavailable"));
+            }
+            Ok(ReqwestMsg::with_resource_id_payload(
+                ReqwestResourceID::MessageNodeRegister,
+                b"",
+            ))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_register() {
+        let operator = FlakyOperator {
+            calls: AtomicUsize::new(0),
+        };
+        let backoff = Backoff::new(
+            Duration::from_millis(10),
+            Duration::from_millis(40),
+            Duration::from_secs(3),
+        );
+        let resp = retry("register", backoff, || operator.call())
+            .await
+            .unwrap();
+        assert_eq!(resp.resource_id(), ReqwestResourceID::MessageNodeRegister);
+        assert_eq!(operator.calls.load(Ordering::Acquire), 3);
+
+        // gives up once the elapsed time is used up.
+        let operator = FlakyOperator {
+            calls: AtomicUsize::new(0),
+        };
+        let backoff = Backoff::new(
+            Duration::from_millis(10),
+            Duration::from_millis(40),
+            Duration::from_millis(5),
+        );
+        assert!(retry("register", backoff, || operator.call())
+            .await
+            .is_err());
+        assert_eq!(operator.calls.load(Ordering::Acquire), 1);
+    }
+
+    #[tokio::test]
+    async fn test_retry_with_sleep() {
+        let operator = FlakyOperator {
+            calls: AtomicUsize::new(0),
+        };
+        let backoff = Backoff::new(
+            Duration::from_millis(10),
+            Duration::from_millis(40),
+            Duration::from_secs(3),
+        );
+        let slept = Mutex::new(Vec::new());
+        let resp = retry_with(
+            "register",
+            backoff,
+            || operator.call(),
+            |delay| {
+                slept.lock().unwrap().push(delay);
+                async {}
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(resp.resource_id(), ReqwestResourceID::MessageNodeRegister);
+        // the given sleep runs once before every retry.
+        assert_eq!(slept.lock().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,24 @@
+use std::future::Future;
+
+use lib::Result;
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tracing::info;
+
+/// resolve on the first SIGTERM or SIGINT received after this call.
+///
+/// the handlers are installed before returning, so a signal sent right after is not missed.
+pub fn shutdown_signal() -> Result<impl Future<Output = ()>> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    Ok(async move {
+        tokio::select! {
+            _ = terminate.recv() => info!("SIGTERM received, shutting down."),
+            _ = interrupt.recv() => info!("SIGINT received, shutting down."),
+        }
+    })
+}
+
+/// receive once for every SIGHUP, taken as the ask to reload the certs.
+pub fn reload_signal() -> Result<Signal> {
+    Ok(signal(SignalKind::hangup())?)
+}
@@ -1,7 +1,7 @@
 use std::{time::Duration, task::Waker, sync::Arc};
 
 use futures::{pin_mut, FutureExt};
-use lib::{Result, net::{client::ClientConfig, ALPN_PRIM}, entity::{ReqwestMsg, ReqwestResourceID}, util::map::LocalMap};
+use lib::{Result, net::client::ClientConfig, entity::{ReqwestMsg, ReqwestResourceID}, util::map::LocalMap};
 use local_sync::mpsc;
 use monoio::{net::TcpStream, io::{Splitable, AsyncWriteRent}};
 use monoio_rustls::TlsConnector;
@@ -29,17 +29,24 @@ impl ClientReqwestTcp {
         let ClientConfig {
             remote_address,
             domain,
-            cert,
+            certs,
             keep_alive_interval,
+            client_identity,
+            alpn_protocols,
             ..
         } = self.config.take().unwrap();
         let mut roots = rustls::RootCertStore::empty();
-        roots.add(&cert)?;
-        let mut client_crypto = rustls::ClientConfig::builder()
+        for cert in certs.iter() {
+            roots.add(cert)?;
+        }
+        let builder = rustls::ClientConfig::builder()
             .with_safe_defaults()
-            .with_root_certificates(roots)
-            .with_no_client_auth();
-        client_crypto.alpn_protocols = ALPN_PRIM.iter().map(|&x| x.into()).collect();
+            .with_root_certificates(roots);
+        let mut client_crypto = match client_identity {
+            Some((cert_chain, key)) => builder.with_client_auth_cert(cert_chain, key)?,
+            None => builder.with_no_client_auth(),
+        };
+        client_crypto.alpn_protocols = alpn_protocols;
         let connector = TlsConnector::from(Arc::new(client_crypto));
         let stream = TcpStream::connect(remote_address).await?;
         let domain = rustls::ServerName::try_from(domain.as_str()).unwrap();
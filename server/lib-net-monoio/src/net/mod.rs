@@ -2,7 +2,7 @@ use std::{
     cell::UnsafeCell,
     pin::Pin,
     sync::{
-        atomic::{AtomicU64, Ordering},
+        atomic::{AtomicBool, AtomicU64, Ordering},
         Arc,
     },
     task::{Context, Poll, Waker},
@@ -17,7 +17,8 @@ use futures::{future::LocalBoxFuture, pin_mut, Future, FutureExt};
 use lib::{
     entity::{ReqwestMsg, ReqwestResourceID},
     error::CrashError,
-    net::InnerStates,
+    net::{check_reqwest_length, InnerStates, DEFAULT_MAX_REQWEST_BYTES},
+    util::health::current_health,
     Result,
 };
 use local_sync::mpsc;
@@ -80,11 +81,29 @@ pub struct Reqwest {
         Option<mpsc::bounded::Tx<(ReqwestMsg, Option<(u64, Arc<ResponsePlaceholder>, Waker)>)>>,
     sender_task: Option<LocalBoxFuture<'static, Result<()>>>,
     resp_receiver: Arc<ResponsePlaceholder>,
-    load_counter: Arc<AtomicU64>,
+    load_counter: Arc<OperatorLoad>,
+    load_released: bool,
 }
 
 impl Unpin for Reqwest {}
 
+impl Reqwest {
+    /// give the load back to the operator once, whether the request is answered, failed or
+    /// dropped.
+    fn release_load(&mut self) {
+        if !self.load_released {
+            self.load_released = true;
+            self.load_counter.load.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+}
+
+impl Drop for Reqwest {
+    fn drop(&mut self) {
+        self.release_load();
+    }
+}
+
 impl Future for Reqwest {
     type Output = Result<ReqwestMsg>;
 
@@ -96,47 +115,42 @@ impl Future for Reqwest {
     /// and we also set a timeout notification, if the request is not responded in some mill-seconds.
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         if !self.sender_task_done {
-            match self.sender_task.as_mut() {
-                Some(task) => {
-                    match task.as_mut().poll(cx) {
-                        Poll::Ready(_) => {
-                            self.sender_task_done = true;
-                        }
-                        Poll::Pending => {
-                            return Poll::Pending;
-                        }
-                    };
+            if self.sender_task.is_none() {
+                let req = self.req.take().unwrap();
+                let req_id = self.req_id;
+                let waker = cx.waker().clone();
+                let operator_sender = self.operator_sender.take().unwrap();
+                let tx = self.resp_receiver.clone();
+                let task = async move {
+                    if let Err(_e) = operator_sender.send((req, Some((req_id, tx, waker)))).await {
+                        error!("rx closed.");
+                        return Err(anyhow!("rx closed."));
+                    }
+                    Ok(())
+                };
+                let task: LocalBoxFuture<Result<()>> = Box::pin(task);
+                self.sender_task = Some(task);
+            }
+            match self.sender_task.as_mut().unwrap().as_mut().poll(cx) {
+                Poll::Ready(Ok(_)) => {
+                    self.sender_task_done = true;
                 }
-                None => {
-                    let req = self.req.take().unwrap();
-                    let req_id = self.req_id;
-                    let waker = cx.waker().clone();
-                    let operator_sender = self.operator_sender.take().unwrap();
-                    let tx = self.resp_receiver.clone();
-                    let task = async move {
-                        if let Err(_e) = operator_sender.send((req, Some((req_id, tx, waker)))).await
-                        {
-                            error!("rx closed.");
-                            return Err(anyhow!("rx closed."));
-                        }
-                        Ok(())
-                    };
-                    let task: LocalBoxFuture<Result<()>> = Box::pin(task);
-                    self.sender_task = Some(task);
-                    match self.sender_task.as_mut().unwrap().as_mut().poll(cx) {
-                        Poll::Ready(_) => {
-                            self.sender_task_done = true;
-                        }
-                        Poll::Pending => {
-                            return Poll::Pending;
-                        }
-                    };
+                Poll::Ready(Err(e)) => {
+                    // the connection of the operator is gone, no response will come, and the
+                    // following requests should go to the others.
+                    self.sender_task_done = true;
+                    self.load_counter.broken.store(true, Ordering::Release);
+                    self.release_load();
+                    return Poll::Ready(Err(e));
                 }
-            };
+                Poll::Pending => {
+                    return Poll::Pending;
+                }
+            }
         }
         match self.resp_receiver.get() {
             Some(resp) => {
-                self.load_counter.fetch_sub(1, Ordering::AcqRel);
+                self.release_load();
                 Poll::Ready(resp)
             }
             None => Poll::Pending,
@@ -144,10 +158,17 @@ impl Future for Reqwest {
     }
 }
 
+/// the requests not answered yet of an operator, and whether its connection is gone.
+#[derive(Default)]
+pub(self) struct OperatorLoad {
+    pub(self) load: AtomicU64,
+    pub(self) broken: AtomicBool,
+}
+
 pub struct ReqwestOperatorManager {
     target_mask: u64,
     pub(self) req_id: AtomicU64,
-    pub(self) load_list: UnsafeCell<Vec<Arc<AtomicU64>>>,
+    pub(self) load_list: UnsafeCell<Vec<Arc<OperatorLoad>>>,
     pub(self) operator_list: UnsafeCell<Vec<ReqwestOperator>>,
 }
 
@@ -168,7 +189,7 @@ impl ReqwestOperatorManager {
     fn new_directly(operator_list: Vec<ReqwestOperator>) -> Self {
         let load_list = operator_list
             .iter()
-            .map(|_| Arc::new(AtomicU64::new(0)))
+            .map(|_| Arc::new(OperatorLoad::default()))
             .collect::<Vec<_>>();
         Self {
             target_mask: 0,
@@ -183,20 +204,25 @@ impl ReqwestOperatorManager {
         let operator_list = unsafe { &mut *self.operator_list.get() };
         operator_list.push(operator);
         let load_list = unsafe { &mut *self.load_list.get() };
-        load_list.push(Arc::new(AtomicU64::new(0)));
+        load_list.push(Arc::new(OperatorLoad::default()));
     }
 
     pub fn call(&self, mut req: ReqwestMsg) -> Reqwest {
+        let load_list = unsafe { &*self.load_list.get() };
+        // the broken ones are skipped, if all of them are, the request fails at once.
         let mut min_index = 0;
         let mut min_load = u64::MAX;
-        for (i, load) in unsafe { &mut *self.load_list.get() }.iter().enumerate() {
-            let load_val = load.load(Ordering::Acquire);
+        for (i, load) in load_list.iter().enumerate() {
+            if load.broken.load(Ordering::Acquire) {
+                continue;
+            }
+            let load_val = load.load.load(Ordering::Acquire);
             if load_val < min_load {
                 min_load = load_val;
                 min_index = i;
             }
         }
-        (unsafe { &*self.load_list.get() })[min_index].fetch_add(1, Ordering::AcqRel);
+        load_list[min_index].load.fetch_add(1, Ordering::AcqRel);
         let req_id = self.req_id.fetch_add(1, Ordering::AcqRel);
         let operator = &(unsafe { &*self.operator_list.get() })[min_index];
         let req_sender = operator.1.clone();
@@ -210,7 +236,8 @@ impl ReqwestOperatorManager {
             resp_receiver,
             sender_task_done: false,
             operator_sender: Some(req_sender),
-            load_counter: (unsafe { &*self.load_list.get() })[min_index].clone(),
+            load_counter: load_list[min_index].clone(),
+            load_released: false,
         }
     }
 }
@@ -314,6 +341,7 @@ impl ReqwestMsgIOUtil {
     #[inline(always)]
     pub async fn recv_msgs(
         stream: &mut OwnedReadHalf<ServerTlsStream<TcpStream>>,
+        max_reqwest_bytes: usize,
     ) -> Result<ReqwestMsg> {
         let len_buf: Box<[u8; 2]> = Box::new([0u8; 2]);
         let (res, len_buf) = stream.read_exact(len_buf).await;
@@ -326,6 +354,7 @@ impl ReqwestMsgIOUtil {
             }
         }
         let len = BigEndian::read_u16(len_buf.as_ref());
+        check_reqwest_length(len, max_reqwest_bytes)?;
         let mut msg = ReqwestMsg::pre_alloc(len);
         let body = msg.body_mut().to_owned();
         let (res, body) = stream.read_exact(body).await;
@@ -375,6 +404,7 @@ impl ReqwestMsgIOUtil {
             }
         }
         let len = BigEndian::read_u16(len_buf.as_ref());
+        check_reqwest_length(len, DEFAULT_MAX_REQWEST_BYTES)?;
         let mut msg = ReqwestMsg::pre_alloc(len);
         let body = msg.body_mut().to_owned();
         let (res, body) = stream.read_exact(body).await;
@@ -398,7 +428,11 @@ pub struct ReqwestMsgIOWrapper {
 }
 
 impl ReqwestMsgIOWrapper {
-    pub fn new(stream: ServerTlsStream<TcpStream>, idle_timeout: Duration) -> Self {
+    pub fn new(
+        stream: ServerTlsStream<TcpStream>,
+        idle_timeout: Duration,
+        max_reqwest_bytes: usize,
+    ) -> Self {
         let (send_sender, mut send_receiver): (
             mpsc::bounded::Tx<ReqwestMsg>,
             mpsc::bounded::Rx<ReqwestMsg>,
@@ -424,14 +458,15 @@ impl ReqwestMsgIOWrapper {
             let timer_setter1 = timer_setter.clone();
             let task1 = async {
                 loop {
-                    match ReqwestMsgIOUtil::recv_msgs(&mut recv_stream).await {
+                    match ReqwestMsgIOUtil::recv_msgs(&mut recv_stream, max_reqwest_bytes).await {
                         Ok(msg) => {
                             let new_timeout = Instant::now() + idle_timeout;
                             timer_setter1.set(new_timeout).await;
                             if msg.resource_id() == ReqwestResourceID::Ping {
+                                // the pong carries the health of this node for probes.
                                 let msg = ReqwestMsg::with_resource_id_payload(
                                     ReqwestResourceID::Pong,
-                                    b"",
+                                    &current_health().to_bytes(),
                                 );
                                 _ = send_sender0.send(msg).await;
                                 continue;
@@ -529,4 +564,57 @@ mod tests {
         });
         monoio::time::sleep(Duration::from_secs(5)).await;
     }
+
+    #[monoio::test]
+    async fn test_load_released() {
+        use super::{ReqwestOperator, ReqwestOperatorManager};
+        use lib::entity::{ReqwestMsg, ReqwestResourceID};
+        use local_sync::mpsc;
+        use std::sync::atomic::Ordering;
+
+        fn loads(manager: &ReqwestOperatorManager) -> Vec<u64> {
+            unsafe { &*manager.load_list.get() }
+                .iter()
+                .map(|load| load.load.load(Ordering::Acquire))
+                .collect()
+        }
+
+        let (broken_sender, broken_receiver) = mpsc::bounded::channel(16);
+        drop(broken_receiver);
+        let (sender, mut receiver) = mpsc::bounded::channel(16);
+        // answer every request with itself.
+        monoio::spawn(async move {
+            while let Some((req, Some((_req_id, resp, waker)))) = receiver.recv().await {
+                resp.set(Ok(req));
+                waker.wake();
+            }
+        });
+        let manager = ReqwestOperatorManager::new_directly(vec![
+            ReqwestOperator(0, broken_sender),
+            ReqwestOperator(1, sender),
+        ]);
+        let req = || ReqwestMsg::with_resource_id_payload(ReqwestResourceID::Ping, b"ping");
+
+        // the first request goes to the broken one and fails, the rest go to the other.
+        assert!(manager.call(req()).await.is_err());
+        for _ in 0..8 {
+            let resp = manager.call(req()).await.unwrap();
+            assert_eq!(resp.resource_id(), ReqwestResourceID::Ping);
+        }
+        assert_eq!(loads(&manager), vec![0, 0]);
+
+        // given up before sent.
+        let pending = manager.call(req());
+        assert_eq!(loads(&manager), vec![0, 1]);
+        drop(pending);
+        assert_eq!(loads(&manager), vec![0, 0]);
+
+        let (broken_sender, broken_receiver) = mpsc::bounded::channel(16);
+        drop(broken_receiver);
+        let manager = ReqwestOperatorManager::new_directly(vec![ReqwestOperator(0, broken_sender)]);
+        for _ in 0..4 {
+            assert!(manager.call(req()).await.is_err());
+        }
+        assert_eq!(loads(&manager), vec![0]);
+    }
 }
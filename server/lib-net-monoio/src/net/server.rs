@@ -7,7 +7,7 @@ use std::{
 use async_trait::async_trait;
 use lib::{
     entity::ReqwestMsg,
-    net::{server::ServerConfig, ALPN_PRIM},
+    net::server::ServerConfig,
     Result,
 };
 use local_sync::mpsc;
@@ -49,13 +49,26 @@ impl ServerReqwestTcp {
             key,
             connection_idle_timeout,
             max_connections,
+            client_ca_certs,
+            alpn_protocols,
+            max_reqwest_bytes,
             ..
         } = self.config.take().unwrap();
-        let mut config = rustls::ServerConfig::builder()
-            .with_safe_defaults()
-            .with_no_client_auth()
-            .with_single_cert(vec![cert], key)?;
-        config.alpn_protocols = ALPN_PRIM.iter().map(|&x| x.into()).collect();
+        let builder = rustls::ServerConfig::builder().with_safe_defaults();
+        let builder = match client_ca_certs {
+            Some(client_ca_certs) => {
+                let mut roots = rustls::RootCertStore::empty();
+                for cert in client_ca_certs.iter() {
+                    roots.add(cert)?;
+                }
+                builder.with_client_cert_verifier(
+                    rustls::server::AllowAnyAuthenticatedClient::new(roots).boxed(),
+                )
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let mut config = builder.with_single_cert(vec![cert], key)?;
+        config.alpn_protocols = alpn_protocols;
         let connection_counter = Arc::new(AtomicUsize::new(0));
         let acceptor = TlsAcceptor::from(config);
         let listener = TcpListener::bind(address)?;
@@ -83,6 +96,7 @@ impl ServerReqwestTcp {
                     handler,
                     counter,
                     connection_idle_timeout,
+                    max_reqwest_bytes,
                 )
                 .await;
             });
@@ -96,9 +110,10 @@ impl ServerReqwestTcp {
         mut handler: Box<dyn NewReqwestConnectionHandler>,
         connection_counter: Arc<AtomicUsize>,
         connection_idle_timeout: u64,
+        max_reqwest_bytes: usize,
     ) -> Result<()> {
         let idle_timeout = Duration::from_millis(connection_idle_timeout);
-        let mut io_operators = ReqwestMsgIOWrapper::new(stream, idle_timeout);
+        let mut io_operators = ReqwestMsgIOWrapper::new(stream, idle_timeout, max_reqwest_bytes);
         _ = handler.handle(io_operators.io_channels()).await;
         debug!("connection closed.");
         connection_counter.fetch_sub(1, Ordering::AcqRel);
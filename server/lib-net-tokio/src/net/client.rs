@@ -1,4 +1,12 @@
-use std::{net::SocketAddr, sync::Arc, task::Waker, time::Duration};
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::Waker,
+    time::Duration,
+};
 
 use crate::net::{
     NewReqwestConnectionHandler0, ReqwestMsgIOUtil, ReqwestOperator, ResponsePlaceholder,
@@ -8,88 +16,127 @@ use anyhow::anyhow;
 use async_trait::async_trait;
 use futures::{pin_mut, FutureExt};
 use lib::{
-    entity::{Msg, ReqwestMsg, ReqwestResourceID},
-    net::{client::ClientConfig, ALPN_PRIM},
-    util::map::LocalMap,
+    entity::{Msg, ReqwestMsg, ReqwestResourceID, Type},
+    error::{ClientError, ClientResult},
+    net::{
+        client::{ClientConfig, DEFAULT_CONNECT_TIMEOUT},
+        default_alpn_protocols, DEFAULT_MAX_REQWEST_BYTES,
+    },
+    util::{
+        cipher::{is_encryptable, PayloadCipher},
+        map::LocalMap,
+    },
     Result,
 };
-use quinn::{Connection, Endpoint, RecvStream, SendStream, TransportConfig};
+use quinn::{
+    Connecting, Connection, Endpoint, RecvStream, SendStream, TransportConfig, ZeroRttAccepted,
+};
+use rustls::client::Resumption;
 use tokio::{
     io::{split, AsyncWriteExt},
     net::TcpStream,
     select,
-    sync::mpsc,
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
 };
 use tokio_rustls::{client::TlsStream, TlsConnector};
-use tracing::{debug, error};
+use tracing::{debug, error, warn};
 
 use super::{
-    MsgIOWrapper, MsgIOWrapperTcpC, MsgMpmcReceiver, MsgMpmcSender, MsgMpscReceiver, MsgMpscSender,
-    ReqwestHandlerGenerator, ReqwestHandlerGenerator0, ReqwestOperatorManager,
+    AckTracker, ConnectionActivity, MsgIOWrapper, MsgIOWrapperTcpC, MsgIOWrapperUni,
+    MsgMpmcReceiver, MsgMpmcSender, MsgMpscReceiver, MsgMpscSender, ReqwestHandlerGenerator,
+    ReqwestHandlerGenerator0, ReqwestOperatorManager, TimeoutEvent, TimeoutEventReceiver,
+    TimeoutEventSender,
 };
 
 /// client with no ack promise.
+///
+/// dropping it closes the endpoint at once, so msgs still on the way may be lost, use
+/// [`Client::close`] to wait for them.
 pub struct Client {
     config: Option<ClientConfig>,
     endpoint: Option<Endpoint>,
     connection: Option<Connection>,
     io_channel: Option<(MsgMpmcSender, MsgMpscReceiver)>,
     bridge_channel: Option<(MsgMpscSender, MsgMpmcReceiver)>,
+    /// the bridge task of each stream, with the signal that the stream has been flushed.
+    bridge_tasks: Vec<(JoinHandle<()>, Option<oneshot::Receiver<()>>)>,
+    /// a clone of the sender given by [`Client::io_channel`], used by [`Client::send_with_ack`].
+    msg_sender: Option<MsgMpmcSender>,
+    ack_tracker: AckTracker,
+    /// the bridges still running, the sender of [`Client::io_channel`] is closed once none is
+    /// left, rather than taking msgs nobody will ever send.
+    live_bridges: Arc<AtomicUsize>,
     max_connections: u16,
+    compress_threshold: Option<usize>,
+    max_payload_bytes: usize,
+    /// encrypts the contents sent and decrypts the ones received, `None` to send them in
+    /// plaintext.
+    cipher: Option<Arc<dyn PayloadCipher>>,
+    timeout_sender: TimeoutEventSender,
+    timeout_receiver: Option<TimeoutEventReceiver>,
 }
 
 impl Client {
     pub fn new(config: ClientConfig) -> Self {
         let max_connections = config.max_bi_streams as u16;
+        let compress_threshold = config.compress_threshold;
+        let max_payload_bytes = config.max_payload_bytes;
+        let (timeout_sender, timeout_receiver) = mpsc::channel(16);
         Self {
             config: Some(config),
             endpoint: None,
             connection: None,
             io_channel: None,
             bridge_channel: None,
+            bridge_tasks: Vec::new(),
+            msg_sender: None,
+            ack_tracker: AckTracker::new(),
+            live_bridges: Arc::new(AtomicUsize::new(0)),
             max_connections,
+            compress_threshold,
+            max_payload_bytes,
+            cipher: None,
+            timeout_sender,
+            timeout_receiver: Some(timeout_receiver),
         }
     }
 
-    pub async fn run(&mut self) -> Result<()> {
+    /// encrypt the contents end to end, only the streams opened after this are affected.
+    pub fn set_cipher(&mut self, cipher: Arc<dyn PayloadCipher>) {
+        self.cipher = Some(cipher);
+    }
+
+    /// why each stream stopped receiving, told apart by [`crate::net::StreamEnd`]. can be taken
+    /// only once.
+    pub fn timeout_channel(&mut self) -> Option<TimeoutEventReceiver> {
+        self.timeout_receiver.take()
+    }
+
+    pub async fn run(&mut self) -> ClientResult<()> {
+        let config = self.config.take().unwrap();
+        let endpoint = client_endpoint(&config)?;
         let ClientConfig {
             remote_address,
-            ipv4_type,
             domain,
-            cert,
-            keep_alive_interval,
-            max_bi_streams,
-        } = self.config.take().unwrap();
-        let default_address = if ipv4_type {
-            "0.0.0.0:0".parse().unwrap()
-        } else {
-            "[::]:0".parse().unwrap()
-        };
-        let mut roots = rustls::RootCertStore::empty();
-        roots.add(&cert)?;
-        let mut client_crypto = rustls::ClientConfig::builder()
-            .with_safe_defaults()
-            .with_root_certificates(roots)
-            .with_no_client_auth();
-        client_crypto.alpn_protocols = ALPN_PRIM.iter().map(|&x| x.into()).collect();
-        let mut endpoint = Endpoint::client(default_address)?;
-        let mut client_config = quinn::ClientConfig::new(Arc::new(client_crypto));
-        let mut transport_config = TransportConfig::default();
-        transport_config
-            .max_concurrent_bidi_streams(quinn::VarInt::from_u64(max_bi_streams as u64).unwrap())
-            .keep_alive_interval(Some(keep_alive_interval));
-        client_config.transport_config(Arc::new(transport_config));
-        endpoint.set_default_client_config(client_config);
-        let connection = endpoint
-            .connect(remote_address, domain.as_str())
-            .unwrap()
-            .await
-            .map_err(|e| anyhow!("failed to connect: {:?}", e))?;
+            connect_timeout,
+            alpn_protocols,
+            ..
+        } = config;
+        let connection = quic_connect(
+            &endpoint,
+            remote_address,
+            &domain,
+            connect_timeout,
+            &alpn_protocols,
+        )
+        .await?;
         let (bridge_sender, io_receiver) = tokio::sync::mpsc::channel(64);
         let (io_sender, bridge_receiver) = async_channel::bounded(64);
         self.endpoint = Some(endpoint);
         self.connection = Some(connection);
         self.bridge_channel = Some((bridge_sender, bridge_receiver));
+        self.msg_sender = Some(io_sender.clone());
         self.io_channel = Some((io_sender, io_receiver));
         Ok(())
     }
@@ -99,49 +146,92 @@ impl Client {
         &mut self,
         // every new stream needed to be authenticated.
         auth_msg: Arc<Msg>,
-    ) -> Result<quinn::StreamId> {
-        let mut io_streams = self.connection.as_ref().unwrap().open_bi().await?;
+    ) -> ClientResult<quinn::StreamId> {
+        let mut io_streams = self
+            .connection
+            .as_ref()
+            .unwrap()
+            .open_bi()
+            .await
+            .map_err(|e| ClientError::StreamClosed(e.to_string()))?;
         let stream_id = io_streams.0.id();
         let bridge_channel = self.bridge_channel.as_ref().unwrap();
         let bridge_channel = (bridge_channel.0.clone(), bridge_channel.1.clone());
-        let mut io_operators = MsgIOWrapper::new(io_streams.0, io_streams.1, auth_msg.node_id());
-        let (send_channel, mut recv_channel) = io_operators.channels();
+        let mut io_operators = MsgIOWrapper::new(
+            io_streams.0,
+            io_streams.1,
+            auth_msg.node_id(),
+            self.max_payload_bytes,
+            self.compress_threshold,
+        );
+        let (send_channel, recv_channel) = io_operators.channels();
         if send_channel.send(auth_msg).await.is_err() {
-            return Err(anyhow!("send auth msg failed"));
+            return Err(ClientError::StreamClosed(
+                "send auth msg failed".to_string(),
+            ));
         }
-        tokio::spawn(async move {
-            loop {
-                select! {
-                    msg = recv_channel.recv() => {
-                        match msg {
-                            Some(msg) => {
-                                if bridge_channel.0.send(msg).await.is_err() {
-                                    break;
-                                }
-                            },
-                            None => {
-                                break;
-                            },
-                        }
-                    },
-                    msg = bridge_channel.1.recv() => {
-                        match msg {
-                            Ok(msg) => {
-                                if send_channel.send(msg).await.is_err() {
-                                    break;
-                                }
-                            },
-                            Err(_) => {
-                                break;
-                            },
-                        }
+        if let Some(ended) = io_operators.ended.take() {
+            let timeout_sender = self.timeout_sender.clone();
+            tokio::spawn(async move {
+                if let Ok(end) = ended.await {
+                    // the same as the stale events, never blocks the connection.
+                    if let Err(e) = timeout_sender.try_send(TimeoutEvent::Ended(end)) {
+                        debug!("timeout channel unavailable, {:?} dropped.", e);
                     }
                 }
+            });
+        }
+        let ack_tracker = self.ack_tracker.clone();
+        let cipher = self.cipher.clone();
+        let live_bridges = self.live_bridges.clone();
+        live_bridges.fetch_add(1, Ordering::AcqRel);
+        let task = tokio::spawn(async move {
+            let outbound = bridge_channel.1.clone();
+            bridge_streams(
+                send_channel,
+                recv_channel,
+                bridge_channel,
+                Some(ack_tracker),
+                cipher,
+            )
+            .await;
+            // the connection has gone with the last stream, so the user is told by a send error.
+            if live_bridges.fetch_sub(1, Ordering::AcqRel) == 1 {
+                outbound.close();
             }
         });
+        self.bridge_tasks.push((task, io_operators.flushed.take()));
         Ok(stream_id)
     }
 
+    /// stop taking new msgs, wait until the queued ones are sent and acknowledged by the server,
+    /// and then close the endpoint.
+    ///
+    /// the wait is bounded by `timeout`, and msgs still queued after that are discarded.
+    pub async fn close(mut self, timeout: Duration) -> ClientResult<()> {
+        // the queued msgs can still be taken by the bridges, and they exit after that.
+        if let Some(bridge_channel) = self.bridge_channel.as_ref() {
+            bridge_channel.1.close();
+        }
+        let bridge_tasks = std::mem::take(&mut self.bridge_tasks);
+        let flush = async {
+            for (task, flushed) in bridge_tasks {
+                _ = task.await;
+                if let Some(flushed) = flushed {
+                    _ = flushed.await;
+                }
+            }
+        };
+        let res = tokio::time::timeout(timeout, flush)
+            .await
+            .map_err(|_| ClientError::Timeout(timeout));
+        if let Some(endpoint) = self.endpoint.take() {
+            endpoint.close(0u32.into(), b"it's time to say goodbye.");
+            endpoint.wait_idle().await;
+        }
+        res
+    }
+
     #[allow(unused)]
     pub async fn io_channel_token(
         &mut self,
@@ -149,7 +239,7 @@ impl Client {
         receiver: u64,
         node_id: u32,
         token: &str,
-    ) -> Result<(MsgMpmcSender, MsgMpscReceiver)> {
+    ) -> ClientResult<(MsgMpmcSender, MsgMpscReceiver)> {
         let mut channel = self.io_channel().await?;
         let auth = Msg::auth(sender, receiver, node_id, token);
         for _ in 0..self.max_connections {
@@ -159,10 +249,32 @@ impl Client {
     }
 
     #[allow(unused)]
-    pub async fn io_channel(&mut self) -> Result<(MsgMpmcSender, MsgMpscReceiver)> {
+    pub async fn io_channel(&mut self) -> ClientResult<(MsgMpmcSender, MsgMpscReceiver)> {
         let mut channel = self.io_channel.take().unwrap();
         Ok(channel)
     }
+
+    /// false if all the streams opened by [`Client::io_channel_token`] have gone, sending is
+    /// failed from then on.
+    pub fn is_connected(&self) -> bool {
+        self.live_bridges.load(Ordering::Acquire) > 0
+    }
+
+    /// send `msg` and wait for its ack, see [`AckTracker`] for how they are matched.
+    ///
+    /// the ack is still delivered to the receiver of [`Client::io_channel`] as well.
+    pub async fn send_with_ack(&self, msg: Arc<Msg>, timeout: Duration) -> ClientResult<Arc<Msg>> {
+        let sender = self
+            .msg_sender
+            .as_ref()
+            .ok_or_else(|| ClientError::StreamClosed("client is not running".to_string()))?;
+        let ack = self.ack_tracker.await_ack(&msg, timeout);
+        sender
+            .send(msg)
+            .await
+            .map_err(|e| ClientError::StreamClosed(e.to_string()))?;
+        ack.await
+    }
 }
 
 impl Drop for Client {
@@ -177,106 +289,315 @@ impl Drop for Client {
 /// may be useful on scene that too large client connection is required.
 pub struct ClientMultiConnection {
     endpoint: Endpoint,
+    ipv4_type: bool,
+    connect_timeout: Duration,
+    compress_threshold: Option<usize>,
+    max_payload_bytes: usize,
+    enable_0rtt: bool,
+    alpn_protocols: Vec<Vec<u8>>,
 }
 
 impl ClientMultiConnection {
-    pub fn new(config: ClientConfig) -> Result<Self> {
-        let ClientConfig {
-            ipv4_type,
-            cert,
-            keep_alive_interval,
-            max_bi_streams,
-            ..
-        } = config;
-        let default_address = if ipv4_type {
-            "0.0.0.0:0".parse().unwrap()
-        } else {
-            "[::]:0".parse().unwrap()
-        };
-        let mut roots = rustls::RootCertStore::empty();
-        roots.add(&cert)?;
-        let mut client_crypto = rustls::ClientConfig::builder()
-            .with_safe_defaults()
-            .with_root_certificates(roots)
-            .with_no_client_auth();
-        client_crypto.alpn_protocols = ALPN_PRIM.iter().map(|&x| x.into()).collect();
-        let mut endpoint = Endpoint::client(default_address)?;
-        let mut client_config = quinn::ClientConfig::new(Arc::new(client_crypto));
-        let mut transport_config = TransportConfig::default();
-        transport_config
-            .max_concurrent_bidi_streams(quinn::VarInt::from_u64(max_bi_streams as u64).unwrap())
-            .keep_alive_interval(Some(keep_alive_interval));
-        client_config.transport_config(Arc::new(transport_config));
-        endpoint.set_default_client_config(client_config);
-        Ok(Self { endpoint })
+    pub fn new(config: ClientConfig) -> ClientResult<Self> {
+        let endpoint = client_endpoint(&config)?;
+        Ok(Self {
+            endpoint,
+            ipv4_type: config.ipv4_type,
+            connect_timeout: config.connect_timeout,
+            compress_threshold: config.compress_threshold,
+            max_payload_bytes: config.max_payload_bytes,
+            enable_0rtt: config.enable_0rtt,
+            alpn_protocols: config.alpn_protocols,
+        })
+    }
+
+    /// move all connections onto a new local socket after the network changed,
+    /// they are migrated by quic without a new handshake.
+    pub fn rebind(&self) -> ClientResult<()> {
+        let socket = std::net::UdpSocket::bind(default_address(self.ipv4_type))
+            .map_err(|e| ClientError::Connect(e.to_string()))?;
+        self.endpoint
+            .rebind(socket)
+            .map_err(|e| ClientError::Connect(e.to_string()))
     }
 
     pub async fn new_connection(
         &self,
         config: SubConnectionConfig,
         auth_msg: Arc<Msg>,
-    ) -> Result<SubConnection> {
+    ) -> ClientResult<SubConnection> {
+        if self.enable_0rtt {
+            let (connection, accepted) = quic_connect_0rtt(
+                &self.endpoint,
+                config.remote_address,
+                &config.domain,
+                self.connect_timeout,
+                &self.alpn_protocols,
+            )
+            .await?;
+            let accepted = match accepted {
+                Some(accepted) => accepted,
+                // no ticket yet, the full handshake has been done.
+                None => return self.open_streams(connection, &config, auth_msg).await,
+            };
+            // only the auth msgs are sent before the handshake finishes, the channels for other
+            // msgs are handed out after that.
+            let mut sub_connection = self
+                .open_streams(connection, &config, auth_msg.clone())
+                .await?;
+            let accepted = tokio::time::timeout(self.connect_timeout, accepted)
+                .await
+                .map_err(|_| ClientError::Timeout(self.connect_timeout))?;
+            if accepted {
+                check_quic_alpn(&self.alpn_protocols, &sub_connection.connection)?;
+                sub_connection.zero_rtt = true;
+                return Ok(sub_connection);
+            }
+            // the auth msgs are discarded along with the 0-rtt data, so start over, and the streams
+            // opened on the rejected connection go with it.
+            debug!(
+                "0-rtt rejected by {}, fall back to a full handshake.",
+                config.remote_address
+            );
+            sub_connection
+                .connection
+                .close(0u32.into(), b"0-rtt rejected, reconnect.");
+        }
+        let connection = quic_connect(
+            &self.endpoint,
+            config.remote_address,
+            &config.domain,
+            self.connect_timeout,
+            &self.alpn_protocols,
+        )
+        .await?;
+        self.open_streams(connection, &config, auth_msg).await
+    }
+
+    pub(self) async fn open_streams(
+        &self,
+        connection: Connection,
+        config: &SubConnectionConfig,
+        auth_msg: Arc<Msg>,
+    ) -> ClientResult<SubConnection> {
         let SubConnectionConfig {
-            remote_address,
-            domain,
             opened_bi_streams_number,
+            opened_uni_streams_number,
+            priority,
+            max_stream_replenishments,
             ..
-        } = config;
-        let connection = self
-            .endpoint
-            .connect(remote_address, domain.as_str())
-            .unwrap()
-            .await
-            .map_err(|e| anyhow!("failed to connect: {:?}", e))?;
+        } = *config;
         let (bridge_sender, io_receiver) = tokio::sync::mpsc::channel(64);
-        let (io_sender, bridge_receiver) = async_channel::bounded(64);
-        for _ in 0..opened_bi_streams_number {
-            let io_streams = connection.open_bi().await?;
-            let bridge_channel = (bridge_sender.clone(), bridge_receiver.clone());
-            let mut io_operators = MsgIOWrapper::new(io_streams.0, io_streams.1, auth_msg.node_id());
-            let (send_channel, mut recv_channel) = io_operators.channels();
-            if send_channel.send(auth_msg.clone()).await.is_err() {
-                return Err(anyhow!("send auth msg failed"));
+        let (io_sender, outer_receiver): (MsgMpmcSender, MsgMpmcReceiver) =
+            async_channel::bounded(64);
+        let (control_sender, control_receiver) = async_channel::bounded(64);
+        let (data_sender, data_receiver) = async_channel::bounded(64);
+        // the first stream is reserved for control msgs only when there are other streams for data.
+        let split = opened_bi_streams_number > 1;
+        let mut queues = vec![
+            outer_receiver.clone(),
+            control_receiver.clone(),
+            data_receiver.clone(),
+        ];
+        let stream_queues = Arc::new(std::sync::Mutex::new(Vec::with_capacity(
+            opened_bi_streams_number,
+        )));
+        tokio::spawn(async move {
+            while let Ok(msg) = outer_receiver.recv().await {
+                let res = if split && is_control_msg(&msg) {
+                    control_sender.send(msg).await
+                } else {
+                    data_sender.send(msg).await
+                };
+                if res.is_err() {
+                    break;
+                }
             }
-            tokio::spawn(async move {
-                loop {
-                    select! {
-                        msg = recv_channel.recv() => {
-                            match msg {
-                                Some(msg) => {
-                                    if bridge_channel.0.send(msg).await.is_err() {
-                                        break;
-                                    }
-                                },
-                                None => {
-                                    break;
-                                },
-                            }
-                        },
-                        msg = bridge_channel.1.recv() => {
-                            match msg {
-                                Ok(msg) => {
-                                    if send_channel.send(msg).await.is_err() {
-                                        break;
-                                    }
-                                },
-                                Err(_) => {
-                                    break;
-                                },
-                            }
+        });
+        let (exited_sender, exited_receiver) = mpsc::channel(opened_bi_streams_number.max(1));
+        let bridge_opener = BridgeOpener {
+            connection: connection.clone(),
+            auth_msg: auth_msg.clone(),
+            bridge_sender,
+            control_receiver,
+            data_receiver,
+            split,
+            priority,
+            compress_threshold: self.compress_threshold,
+            max_payload_bytes: self.max_payload_bytes,
+            stream_queues: stream_queues.clone(),
+            exited_sender,
+        };
+        for i in 0..opened_bi_streams_number {
+            bridge_opener.open(i).await?;
+        }
+        let replenished = Arc::new(AtomicUsize::new(0));
+        tokio::spawn(replenish_streams(
+            bridge_opener,
+            exited_receiver,
+            opened_bi_streams_number,
+            max_stream_replenishments,
+            replenished.clone(),
+        ));
+        // uni streams are send-only, they share one queue just like the data streams.
+        let uni_channel = if opened_uni_streams_number > 0 {
+            let (uni_sender, uni_receiver): (MsgMpmcSender, MsgMpmcReceiver) =
+                async_channel::bounded(64);
+            queues.push(uni_receiver.clone());
+            for _ in 0..opened_uni_streams_number {
+                let send_stream = connection
+                    .open_uni()
+                    .await
+                    .map_err(|e| ClientError::StreamClosed(e.to_string()))?;
+                send_stream
+                    .set_priority(priority)
+                    .map_err(|e| ClientError::StreamClosed(e.to_string()))?;
+                let send_channel =
+                    MsgIOWrapperUni::new(send_stream, auth_msg.node_id(), self.compress_threshold)
+                        .channel();
+                stream_queues.lock().unwrap().push(send_channel.downgrade());
+                let uni_receiver = uni_receiver.clone();
+                tokio::spawn(async move {
+                    while let Ok(msg) = uni_receiver.recv().await {
+                        if send_channel.send(msg).await.is_err() {
+                            break;
                         }
                     }
-                }
-            });
-        }
-        // we not implement uni stream
+                });
+            }
+            Some(uni_sender)
+        } else {
+            None
+        };
         Ok(SubConnection {
             connection,
             io_channel: Some((io_sender, io_receiver)),
+            uni_channel,
+            queues,
+            stream_queues,
+            replenished,
+            zero_rtt: false,
         })
     }
 }
 
+/// what opening a bi stream and bridging it to the shared channels takes, kept so a dead
+/// stream can be replaced later.
+pub(self) struct BridgeOpener {
+    connection: Connection,
+    auth_msg: Arc<Msg>,
+    bridge_sender: MsgMpscSender,
+    control_receiver: MsgMpmcReceiver,
+    data_receiver: MsgMpmcReceiver,
+    split: bool,
+    priority: i32,
+    compress_threshold: Option<usize>,
+    max_payload_bytes: usize,
+    stream_queues: Arc<std::sync::Mutex<Vec<mpsc::WeakSender<Arc<Msg>>>>>,
+    /// the index of a bridge is sent here once it exits.
+    exited_sender: mpsc::Sender<usize>,
+}
+
+impl BridgeOpener {
+    /// the `index`th stream, the first one carries control msgs if split.
+    pub(self) async fn open(&self, index: usize) -> ClientResult<()> {
+        let io_streams = self
+            .connection
+            .open_bi()
+            .await
+            .map_err(|e| ClientError::StreamClosed(e.to_string()))?;
+        io_streams
+            .0
+            .set_priority(stream_priority(index, self.split, self.priority))
+            .map_err(|e| ClientError::StreamClosed(e.to_string()))?;
+        let bridge_channel = (self.bridge_sender.clone(), self.receiver_of(index).clone());
+        let mut io_operators = MsgIOWrapper::new(
+            io_streams.0,
+            io_streams.1,
+            self.auth_msg.node_id(),
+            self.max_payload_bytes,
+            self.compress_threshold,
+        );
+        let (send_channel, recv_channel) = io_operators.channels();
+        if send_channel.send(self.auth_msg.clone()).await.is_err() {
+            return Err(ClientError::StreamClosed(
+                "send auth msg failed".to_string(),
+            ));
+        }
+        {
+            let mut stream_queues = self.stream_queues.lock().unwrap();
+            // the streams replaced are of no use to watch.
+            stream_queues.retain(|queue| queue.upgrade().is_some());
+            stream_queues.push(send_channel.downgrade());
+        }
+        let exited_sender = self.exited_sender.clone();
+        tokio::spawn(async move {
+            bridge_streams(send_channel, recv_channel, bridge_channel, None, None).await;
+            _ = exited_sender.send(index).await;
+        });
+        Ok(())
+    }
+
+    /// a bridge gone while the connection and the user are both still there died on its own.
+    pub(self) fn should_replace(&self, index: usize) -> bool {
+        self.connection.close_reason().is_none()
+            && !self.bridge_sender.is_closed()
+            && !self.receiver_of(index).is_closed()
+    }
+
+    pub(self) fn receiver_of(&self, index: usize) -> &MsgMpmcReceiver {
+        if self.split && index == 0 {
+            &self.control_receiver
+        } else {
+            &self.data_receiver
+        }
+    }
+}
+
+/// open a new stream for each bridge exited by a stream failure, at most `max_replenishments`
+/// times over the connection's life.
+///
+/// the shared channels are held until the last bridge has gone, so the user is still told by
+/// the channels closing when nothing is left.
+pub(self) async fn replenish_streams(
+    bridge_opener: BridgeOpener,
+    mut exited_receiver: mpsc::Receiver<usize>,
+    mut live: usize,
+    max_replenishments: usize,
+    replenished: Arc<AtomicUsize>,
+) {
+    while live > 0 {
+        let index = match exited_receiver.recv().await {
+            Some(index) => index,
+            None => break,
+        };
+        live -= 1;
+        if !bridge_opener.should_replace(index) {
+            continue;
+        }
+        if replenished.load(Ordering::Acquire) >= max_replenishments {
+            warn!(
+                "stream {} to {} exited, no replenishment left.",
+                index,
+                bridge_opener.connection.remote_address()
+            );
+            continue;
+        }
+        replenished.fetch_add(1, Ordering::AcqRel);
+        match bridge_opener.open(index).await {
+            Ok(_) => {
+                debug!(
+                    "stream {} to {} replenished.",
+                    index,
+                    bridge_opener.connection.remote_address()
+                );
+                live += 1;
+            }
+            Err(e) => error!("replenish stream {} error: {}", index, e),
+        }
+    }
+}
+
 impl Drop for ClientMultiConnection {
     fn drop(&mut self) {
         self.endpoint
@@ -288,12 +609,25 @@ pub struct SubConnectionConfig {
     pub remote_address: SocketAddr,
     pub domain: String,
     pub opened_bi_streams_number: usize,
+    /// send-only streams for one-way notifications, such as presence updates and typing indicators.
+    pub opened_uni_streams_number: usize,
     pub timeout: Duration,
+    /// priority of data streams, the control stream always goes one level higher.
+    pub priority: i32,
+    /// how many times over the connection's life a bi stream died on its own is replaced by a
+    /// new one, 0 to never replace.
+    pub max_stream_replenishments: usize,
 }
 
 pub struct SubConnection {
     connection: Connection,
     io_channel: Option<(MsgMpmcSender, MsgMpscReceiver)>,
+    uni_channel: Option<MsgMpmcSender>,
+    /// the channels msgs pass through before reaching a stream, only used to watch their length.
+    queues: Vec<MsgMpmcReceiver>,
+    stream_queues: Arc<std::sync::Mutex<Vec<mpsc::WeakSender<Arc<Msg>>>>>,
+    replenished: Arc<AtomicUsize>,
+    zero_rtt: bool,
 }
 
 impl SubConnection {
@@ -301,6 +635,52 @@ impl SubConnection {
         let (outer_sender, outer_receiver) = self.io_channel.take().unwrap();
         (outer_sender, outer_receiver)
     }
+
+    /// true if the auth msgs went with the first flight of a resumed session.
+    pub fn is_0rtt(&self) -> bool {
+        self.zero_rtt
+    }
+
+    /// how many bi streams have been opened to replace the dead ones.
+    pub fn replenished_streams(&self) -> usize {
+        self.replenished.load(Ordering::Acquire)
+    }
+
+    /// msgs sent here go through the uni streams, none if no uni stream is opened.
+    pub fn uni_channel(&mut self) -> Option<MsgMpmcSender> {
+        self.uni_channel.take()
+    }
+
+    /// wait for all buffered msgs to be written to the streams before closing the connection.
+    ///
+    /// the wait is bounded by `timeout`, and msgs still buffered after that are discarded.
+    /// dropping a [`SubConnection`] closes the connection without any wait.
+    pub async fn close_after_flush(self, timeout: Duration) -> ClientResult<()> {
+        let flush = async {
+            while !self.is_flushed() {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+            // give the last written bytes a round trip to reach the peer.
+            tokio::time::sleep(self.connection.rtt()).await;
+        };
+        tokio::time::timeout(timeout, flush)
+            .await
+            .map_err(|_| ClientError::Timeout(timeout))
+    }
+
+    pub(self) fn is_flushed(&self) -> bool {
+        self.queues.iter().all(|queue| queue.is_empty())
+            && self
+                .stream_queues
+                .lock()
+                .unwrap()
+                .iter()
+                .all(|queue| match queue.upgrade() {
+                    Some(sender) => sender.capacity() == sender.max_capacity(),
+                    // the stream has gone, nothing can be flushed anymore.
+                    None => true,
+                })
+    }
 }
 
 impl Drop for SubConnection {
@@ -314,36 +694,34 @@ pub struct ClientTcp {
     config: Option<ClientConfig>,
     connection: Option<TlsStream<TcpStream>>,
     keep_alive_interval: Duration,
+    max_payload_bytes: usize,
+    activity: Arc<ConnectionActivity>,
+    stale_window: Option<Duration>,
+    timeout_sender: TimeoutEventSender,
+    timeout_receiver: Option<TimeoutEventReceiver>,
 }
 
 impl ClientTcp {
     pub fn new(config: ClientConfig) -> Self {
         let keep_live_interval = config.keep_alive_interval;
+        let stale_window = config.stale_window;
+        let max_payload_bytes = config.max_payload_bytes;
+        let (timeout_sender, timeout_receiver) = mpsc::channel(16);
         ClientTcp {
             config: Some(config),
             connection: None,
             keep_alive_interval: keep_live_interval,
+            max_payload_bytes,
+            activity: Arc::new(ConnectionActivity::new()),
+            stale_window,
+            timeout_sender,
+            timeout_receiver: Some(timeout_receiver),
         }
     }
 
-    pub async fn run(&mut self) -> Result<()> {
-        let ClientConfig {
-            remote_address,
-            domain,
-            cert,
-            ..
-        } = self.config.take().unwrap();
-        let mut roots = rustls::RootCertStore::empty();
-        roots.add(&cert)?;
-        let mut client_crypto = rustls::ClientConfig::builder()
-            .with_safe_defaults()
-            .with_root_certificates(roots)
-            .with_no_client_auth();
-        client_crypto.alpn_protocols = ALPN_PRIM.iter().map(|&x| x.into()).collect();
-        let connector = TlsConnector::from(Arc::new(client_crypto));
-        let stream = TcpStream::connect(remote_address).await?;
-        let domain = rustls::ServerName::try_from(domain.as_str()).unwrap();
-        let stream = connector.connect(domain, stream).await?;
+    pub async fn run(&mut self) -> ClientResult<()> {
+        let config = self.config.take().unwrap();
+        let stream = tls_connect(&config).await?;
         self.connection = Some(stream);
         Ok(())
     }
@@ -351,12 +729,26 @@ impl ClientTcp {
     pub(self) async fn new_net_streams(
         &mut self,
         auth_msg: Arc<Msg>,
-    ) -> Result<(MsgMpscSender, MsgMpscReceiver)> {
+    ) -> ClientResult<(MsgMpscSender, MsgMpscReceiver)> {
         let stream = self.connection.take().unwrap();
-        let mut io_operators = MsgIOWrapperTcpC::new(stream, self.keep_alive_interval, auth_msg.node_id());
+        // the connection counts as active from now on, not from when the client was created.
+        self.activity = Arc::new(ConnectionActivity::new());
+        let stale_window = self
+            .stale_window
+            .map(|window| (window, self.timeout_sender.clone()));
+        let mut io_operators = MsgIOWrapperTcpC::new(
+            stream,
+            self.keep_alive_interval,
+            auth_msg.node_id(),
+            self.max_payload_bytes,
+            self.activity.clone(),
+            stale_window,
+        );
         let (send_channel, recv_channel) = io_operators.channels();
         if send_channel.send(auth_msg).await.is_err() {
-            return Err(anyhow!("send auth msg failed"));
+            return Err(ClientError::StreamClosed(
+                "send auth msg failed".to_string(),
+            ));
         }
         Ok((send_channel, recv_channel))
     }
@@ -367,10 +759,25 @@ impl ClientTcp {
         receiver: u64,
         node_id: u32,
         token: &str,
-    ) -> Result<(MsgMpscSender, MsgMpscReceiver)> {
+    ) -> ClientResult<(MsgMpscSender, MsgMpscReceiver)> {
         let auth = Msg::auth(sender, receiver, node_id, token);
         self.new_net_streams(Arc::new(auth)).await
     }
+
+    /// when msgs were last sent and received on the connection.
+    pub fn activity(&self) -> Arc<ConnectionActivity> {
+        self.activity.clone()
+    }
+
+    /// nothing has been received for longer than `threshold`, the caller may reconnect.
+    pub fn is_stale(&self, threshold: Duration) -> bool {
+        self.activity.is_stale(threshold)
+    }
+
+    /// stale events of the connection if `stale_window` is configured, can be taken only once.
+    pub fn timeout_channel(&mut self) -> Option<TimeoutEventReceiver> {
+        self.timeout_receiver.take()
+    }
 }
 
 pub(self) struct ClientReqwest0 {
@@ -392,40 +799,25 @@ impl ClientReqwest0 {
         &'a mut self,
         generator: ReqwestHandlerGenerator0,
         operator_list: &'a mut Vec<ReqwestOperator>,
-    ) -> Result<()> {
+    ) -> ClientResult<()> {
+        let config = self.config.take().unwrap();
+        let endpoint = client_endpoint(&config)?;
         let ClientConfig {
             remote_address,
-            ipv4_type,
             domain,
-            cert,
-            keep_alive_interval,
             max_bi_streams,
-        } = self.config.take().unwrap();
-        let default_address = if ipv4_type {
-            "0.0.0.0:0".parse().unwrap()
-        } else {
-            "[::]:0".parse().unwrap()
-        };
-        let mut roots = rustls::RootCertStore::empty();
-        roots.add(&cert)?;
-        let mut client_crypto = rustls::ClientConfig::builder()
-            .with_safe_defaults()
-            .with_root_certificates(roots)
-            .with_no_client_auth();
-        client_crypto.alpn_protocols = ALPN_PRIM.iter().map(|&x| x.into()).collect();
-        let mut endpoint = Endpoint::client(default_address)?;
-        let mut client_config = quinn::ClientConfig::new(Arc::new(client_crypto));
-        let mut transport_config = TransportConfig::default();
-        transport_config
-            .max_concurrent_bidi_streams(quinn::VarInt::from_u64(max_bi_streams as u64).unwrap())
-            .keep_alive_interval(Some(keep_alive_interval));
-        client_config.transport_config(Arc::new(transport_config));
-        endpoint.set_default_client_config(client_config);
-        let new_connection = endpoint
-            .connect(remote_address, domain.as_str())
-            .unwrap()
-            .await
-            .map_err(|e| anyhow!("failed to connect: {:?}", e))?;
+            connect_timeout,
+            alpn_protocols,
+            ..
+        } = config;
+        let new_connection = quic_connect(
+            &endpoint,
+            remote_address,
+            &domain,
+            connect_timeout,
+            &alpn_protocols,
+        )
+        .await?;
 
         let mut handler = generator();
         for _ in 0..max_bi_streams {
@@ -439,7 +831,7 @@ impl ClientReqwest0 {
             let operator: Option<ReqwestOperator> =
                 handler.handle(streams, None).await.map_err(|e| {
                     error!("handle error: {}", e.to_string());
-                    e
+                    ClientError::StreamClosed(e.to_string())
                 })?;
             operator_list.push(operator.unwrap());
         }
@@ -470,25 +862,10 @@ impl ClientReqwestTcp {
         }
     }
 
-    pub async fn build(&mut self) -> Result<ReqwestOperatorManager> {
-        let ClientConfig {
-            remote_address,
-            domain,
-            cert,
-            keep_alive_interval,
-            ..
-        } = self.config.take().unwrap();
-        let mut roots = rustls::RootCertStore::empty();
-        roots.add(&cert)?;
-        let mut client_crypto = rustls::ClientConfig::builder()
-            .with_safe_defaults()
-            .with_root_certificates(roots)
-            .with_no_client_auth();
-        client_crypto.alpn_protocols = ALPN_PRIM.iter().map(|&x| x.into()).collect();
-        let connector = TlsConnector::from(Arc::new(client_crypto));
-        let stream = TcpStream::connect(remote_address).await?;
-        let domain = rustls::ServerName::try_from(domain.as_str()).unwrap();
-        let stream = connector.connect(domain, stream).await?;
+    pub async fn build(&mut self) -> ClientResult<ReqwestOperatorManager> {
+        let config = self.config.take().unwrap();
+        let stream = tls_connect(&config).await?;
+        let keep_alive_interval = config.keep_alive_interval;
 
         let (sender, mut receiver) =
             mpsc::channel::<(ReqwestMsg, Option<(u64, Arc<ResponsePlaceholder>, Waker)>)>(16384);
@@ -660,7 +1037,7 @@ impl ClientReqwest {
     pub async fn build(
         &mut self,
         generator: Arc<ReqwestHandlerGenerator>,
-    ) -> Result<ReqwestOperatorManager> {
+    ) -> ClientResult<ReqwestOperatorManager> {
         struct Generator0 {
             timeout: Duration,
             generator: Arc<ReqwestHandlerGenerator>,
@@ -735,7 +1112,13 @@ impl ClientReqwest {
 
                     let task2 = async {
                         loop {
-                            match ReqwestMsgIOUtil::recv_msg(&mut recv_stream, None).await {
+                            match ReqwestMsgIOUtil::recv_msg(
+                                &mut recv_stream,
+                                None,
+                                DEFAULT_MAX_REQWEST_BYTES,
+                            )
+                            .await
+                            {
                                 Ok(msg) => {
                                     let req_id = msg.req_id();
                                     // a request from server
@@ -867,7 +1250,7 @@ impl ClientReqwestSub0 {
         &'a mut self,
         generator: Arc<ReqwestHandlerGenerator0>,
         operator_list: &'a mut Vec<ReqwestOperator>,
-    ) -> Result<()> {
+    ) -> ClientResult<()> {
         let mut handler = generator();
         for _ in 0..self.max_bi_streams {
             let streams = match self.connection.open_bi().await {
@@ -880,7 +1263,7 @@ impl ClientReqwestSub0 {
             let operator: Option<ReqwestOperator> =
                 handler.handle(streams, None).await.map_err(|e| {
                     error!("handle error: {}", e.to_string());
-                    e
+                    ClientError::StreamClosed(e.to_string())
                 })?;
             operator_list.push(operator.unwrap());
         }
@@ -893,6 +1276,8 @@ pub(self) struct ClientReqwestShare0 {
     endpoint: Option<Endpoint>,
     domain: String,
     max_bi_streams: usize,
+    connect_timeout: Duration,
+    alpn_protocols: Vec<Vec<u8>>,
 }
 
 impl ClientReqwestShare0 {
@@ -902,56 +1287,41 @@ impl ClientReqwestShare0 {
             endpoint: None,
             domain: "".to_string(),
             max_bi_streams: 1,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            alpn_protocols: default_alpn_protocols(),
         }
     }
 
-    pub(self) async fn build(&mut self) -> Result<()> {
+    pub(self) async fn build(&mut self) -> ClientResult<()> {
+        let config = self.config.take().unwrap();
+        let endpoint = client_endpoint(&config)?;
         let ClientConfig {
-            ipv4_type,
             domain,
-            cert,
-            keep_alive_interval,
             max_bi_streams,
+            connect_timeout,
+            alpn_protocols,
             ..
-        } = self.config.take().unwrap();
-        let default_address = if ipv4_type {
-            "0.0.0.0:0".parse().unwrap()
-        } else {
-            "[::]:0".parse().unwrap()
-        };
-        let mut roots = rustls::RootCertStore::empty();
-        roots.add(&cert)?;
-        let mut client_crypto = rustls::ClientConfig::builder()
-            .with_safe_defaults()
-            .with_root_certificates(roots)
-            .with_no_client_auth();
-        client_crypto.alpn_protocols = ALPN_PRIM.iter().map(|&x| x.into()).collect();
-        let mut endpoint = Endpoint::client(default_address)?;
-        let mut client_config = quinn::ClientConfig::new(Arc::new(client_crypto));
-        let mut transport_config = TransportConfig::default();
-        transport_config
-            .max_concurrent_bidi_streams(quinn::VarInt::from_u64(max_bi_streams as u64).unwrap())
-            .keep_alive_interval(Some(keep_alive_interval));
-        client_config.transport_config(Arc::new(transport_config));
-        endpoint.set_default_client_config(client_config);
+        } = config;
         self.endpoint = Some(endpoint);
         self.domain = domain;
         self.max_bi_streams = max_bi_streams;
+        self.connect_timeout = connect_timeout;
+        self.alpn_protocols = alpn_protocols;
         Ok(())
     }
 
     pub(self) async fn new_connection(
         &self,
         remote_address: SocketAddr,
-    ) -> Result<ClientReqwestSub0> {
-        let connection = self
-            .endpoint
-            .as_ref()
-            .unwrap()
-            .connect(remote_address, self.domain.as_str())
-            .unwrap()
-            .await
-            .map_err(|e| anyhow!("failed to connect with reason: {:?}", e))?;
+    ) -> ClientResult<ClientReqwestSub0> {
+        let connection = quic_connect(
+            self.endpoint.as_ref().unwrap(),
+            remote_address,
+            &self.domain,
+            self.connect_timeout,
+            &self.alpn_protocols,
+        )
+        .await?;
         Ok(ClientReqwestSub0 {
             connection,
             max_bi_streams: self.max_bi_streams as u16,
@@ -965,7 +1335,7 @@ pub struct ClientReqwestSub {
 }
 
 impl ClientReqwestSub {
-    pub async fn build(&mut self) -> Result<ReqwestOperatorManager> {
+    pub async fn build(&mut self) -> ClientResult<ReqwestOperatorManager> {
         let mut operator_list = Vec::new();
         self.sub_conn
             .build(self.generator0.clone(), &mut operator_list)
@@ -990,7 +1360,7 @@ impl ClientReqwestShare {
         }
     }
 
-    pub async fn build(&mut self, generator: ReqwestHandlerGenerator) -> Result<()> {
+    pub async fn build(&mut self, generator: ReqwestHandlerGenerator) -> ClientResult<()> {
         self.client.build().await?;
 
         struct Generator0 {
@@ -1073,7 +1443,13 @@ impl ClientReqwestShare {
 
                     let task2 = async {
                         loop {
-                            match ReqwestMsgIOUtil::recv_msg(&mut recv_stream, None).await {
+                            match ReqwestMsgIOUtil::recv_msg(
+                                &mut recv_stream,
+                                None,
+                                DEFAULT_MAX_REQWEST_BYTES,
+                            )
+                            .await
+                            {
                                 Ok(msg) => {
                                     let req_id = msg.req_id();
                                     // a request from server
@@ -1206,7 +1582,10 @@ impl ClientReqwestShare {
         Ok(())
     }
 
-    pub async fn new_connection(&self, remote_address: SocketAddr) -> Result<ClientReqwestSub> {
+    pub async fn new_connection(
+        &self,
+        remote_address: SocketAddr,
+    ) -> ClientResult<ClientReqwestSub> {
         let sub_conn = self.client.new_connection(remote_address).await?;
         Ok(ClientReqwestSub {
             sub_conn,
@@ -1214,3 +1593,1365 @@ impl ClientReqwestShare {
         })
     }
 }
+
+pub(self) fn root_cert_store(certs: &[rustls::Certificate]) -> ClientResult<rustls::RootCertStore> {
+    let mut roots = rustls::RootCertStore::empty();
+    for cert in certs.iter() {
+        roots
+            .add(cert)
+            .map_err(|e| ClientError::Handshake(e.to_string()))?;
+    }
+    Ok(roots)
+}
+
+/// the idle timeout given to quinn, `None` when it's left to quinn's default.
+pub(self) fn idle_timeout(config: &ClientConfig) -> ClientResult<Option<quinn::IdleTimeout>> {
+    config
+        .idle_timeout
+        .map(|idle_timeout| {
+            quinn::IdleTimeout::try_from(idle_timeout)
+                .map_err(|e| ClientError::Connect(e.to_string()))
+        })
+        .transpose()
+}
+
+pub(self) fn transport_config(config: &ClientConfig) -> ClientResult<TransportConfig> {
+    let mut transport_config = TransportConfig::default();
+    transport_config
+        .max_concurrent_bidi_streams(quinn::VarInt::from_u64(config.max_bi_streams as u64).unwrap())
+        .keep_alive_interval(Some(config.keep_alive_interval));
+    // leave quinn's default when unset.
+    if let Some(idle_timeout) = idle_timeout(config)? {
+        transport_config.max_idle_timeout(Some(idle_timeout));
+    }
+    // checked by the config builder to fit in a varint.
+    if let Some(stream_receive_window) = config.stream_receive_window {
+        transport_config
+            .stream_receive_window(quinn::VarInt::from_u64(stream_receive_window).unwrap());
+    }
+    if let Some(receive_window) = config.receive_window {
+        transport_config.receive_window(quinn::VarInt::from_u64(receive_window).unwrap());
+    }
+    Ok(transport_config)
+}
+
+pub(self) fn default_address(ipv4_type: bool) -> SocketAddr {
+    if ipv4_type {
+        "0.0.0.0:0".parse().unwrap()
+    } else {
+        "[::]:0".parse().unwrap()
+    }
+}
+
+/// shared endpoint builder of all quic clients.
+pub(self) fn client_endpoint(config: &ClientConfig) -> ClientResult<Endpoint> {
+    let mut client_crypto = client_crypto(config)?;
+    if config.enable_0rtt {
+        client_crypto.enable_early_data = true;
+        client_crypto.resumption = Resumption::store(config.session_store.0.clone());
+    }
+    let mut endpoint = Endpoint::client(default_address(config.ipv4_type))
+        .map_err(|e| ClientError::Connect(e.to_string()))?;
+    let mut client_config = quinn::ClientConfig::new(Arc::new(client_crypto));
+    client_config.transport_config(Arc::new(transport_config(config)?));
+    endpoint.set_default_client_config(client_config);
+    Ok(endpoint)
+}
+
+pub(self) fn client_crypto(config: &ClientConfig) -> ClientResult<rustls::ClientConfig> {
+    let builder = if config.tls_versions.is_none() && config.cipher_suites.is_none() {
+        rustls::ClientConfig::builder().with_safe_defaults()
+    } else {
+        let tls_versions = config
+            .tls_versions
+            .as_deref()
+            .unwrap_or(rustls::DEFAULT_VERSIONS);
+        // the defaults which can not be negotiated with `tls_versions` are left out.
+        let cipher_suites = match config.cipher_suites.as_ref() {
+            Some(cipher_suites) => cipher_suites.clone(),
+            None => rustls::DEFAULT_CIPHER_SUITES
+                .iter()
+                .filter(|suite| tls_versions.contains(&suite.version()))
+                .copied()
+                .collect(),
+        };
+        rustls::ClientConfig::builder()
+            .with_cipher_suites(&cipher_suites)
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(tls_versions)
+            .map_err(|e| ClientError::Handshake(e.to_string()))?
+    };
+    let builder = builder.with_root_certificates(root_cert_store(&config.certs)?);
+    let mut client_crypto = match config.client_identity.as_ref() {
+        Some((cert_chain, key)) => builder
+            .with_client_auth_cert(cert_chain.clone(), key.clone())
+            .map_err(|e| ClientError::Handshake(e.to_string()))?,
+        None => builder.with_no_client_auth(),
+    };
+    client_crypto.alpn_protocols = config.alpn_protocols.clone();
+    Ok(client_crypto)
+}
+
+/// acks and heartbeats should never queue behind bulk data.
+pub(self) fn is_control_msg(msg: &Msg) -> bool {
+    matches!(msg.typ(), Type::Ack | Type::Ping | Type::Pong)
+}
+
+pub(self) fn stream_priority(index: usize, split: bool, priority: i32) -> i32 {
+    if split && index == 0 {
+        priority.saturating_add(1)
+    } else {
+        priority
+    }
+}
+
+/// tls alert `no_application_protocol`, see rfc 7301.
+const ALERT_NO_APPLICATION_PROTOCOL: u8 = 120;
+
+/// which side of a stream a msg comes from.
+pub(self) enum BridgeEvent {
+    /// read from the stream, goes to the user.
+    Inbound(Option<Arc<Msg>>),
+    /// given by the user, goes to the stream.
+    Outbound(std::result::Result<Arc<Msg>, async_channel::RecvError>),
+}
+
+/// move msgs between a stream and the channels shared with the user until either side closes,
+/// acks read are shown to `ack_tracker` on the way, and contents are encrypted and decrypted by
+/// `cipher` if given.
+///
+/// the server may ask the stream to pause by [`Msg::flow_control`], no msg is taken for it then
+/// until asked to resume, so the other streams take them instead.
+///
+/// `select!` picks a ready branch at random, so a saturated direction may still win most of the
+/// time, here the direction just served is polled second on the next round instead.
+pub(self) async fn bridge_streams(
+    send_channel: MsgMpscSender,
+    mut recv_channel: MsgMpscReceiver,
+    bridge_channel: (MsgMpscSender, MsgMpmcReceiver),
+    ack_tracker: Option<AckTracker>,
+    cipher: Option<Arc<dyn PayloadCipher>>,
+) {
+    let mut inbound_first = true;
+    let mut paused = false;
+    loop {
+        let event = if paused {
+            BridgeEvent::Inbound(recv_channel.recv().await)
+        } else if inbound_first {
+            select! {
+                biased;
+                msg = recv_channel.recv() => BridgeEvent::Inbound(msg),
+                msg = bridge_channel.1.recv() => BridgeEvent::Outbound(msg),
+            }
+        } else {
+            select! {
+                biased;
+                msg = bridge_channel.1.recv() => BridgeEvent::Outbound(msg),
+                msg = recv_channel.recv() => BridgeEvent::Inbound(msg),
+            }
+        };
+        match event {
+            BridgeEvent::Inbound(msg) => {
+                inbound_first = false;
+                match msg {
+                    Some(msg) => {
+                        // a signal to the stream, not a msg for the user.
+                        if let Some(pause) = msg.flow_control_signal() {
+                            paused = pause;
+                            continue;
+                        }
+                        if let Some(ack_tracker) = ack_tracker.as_ref() {
+                            if msg.typ() == Type::Ack {
+                                ack_tracker.ack(msg.clone());
+                            }
+                        }
+                        let msg = match cipher.as_ref() {
+                            Some(cipher) if msg.is_encrypted() => decrypt_msg(cipher, msg),
+                            _ => msg,
+                        };
+                        if bridge_channel.0.send(msg).await.is_err() {
+                            break;
+                        }
+                    }
+                    None => break,
+                }
+            }
+            BridgeEvent::Outbound(msg) => {
+                inbound_first = true;
+                match msg {
+                    Ok(msg) => {
+                        let msg = match cipher.as_ref() {
+                            Some(cipher) if is_encryptable(&msg) => match cipher.encrypt(&msg) {
+                                Ok(encrypted) => Arc::new(encrypted),
+                                // never fall back to plaintext.
+                                Err(e) => {
+                                    error!("encrypt msg error: {}, dropped.", e);
+                                    continue;
+                                }
+                            },
+                            _ => msg,
+                        };
+                        if send_channel.send(msg).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(_) => break,
+                }
+            }
+        }
+    }
+}
+
+/// a msg failed to decrypt is handed to the user as it is, still marked encrypted.
+pub(self) fn decrypt_msg(cipher: &Arc<dyn PayloadCipher>, msg: Arc<Msg>) -> Arc<Msg> {
+    match cipher.decrypt(&msg) {
+        Ok(decrypted) => Arc::new(decrypted),
+        Err(e) => {
+            error!("decrypt msg error: {}", e);
+            msg
+        }
+    }
+}
+
+pub(self) fn alpn_mismatch(alpn_protocols: &[Vec<u8>], negotiated: Option<&[u8]>) -> ClientError {
+    let expected = alpn_protocols
+        .iter()
+        .map(|x| String::from_utf8_lossy(x).to_string())
+        .collect::<Vec<String>>()
+        .join(",");
+    let negotiated = match negotiated {
+        Some(protocol) => String::from_utf8_lossy(protocol).to_string(),
+        None => "none".to_string(),
+    };
+    ClientError::AlpnMismatch {
+        expected,
+        negotiated,
+    }
+}
+
+/// make sure the protocol negotiated during handshake is one we offered.
+pub(self) fn check_alpn(alpn_protocols: &[Vec<u8>], negotiated: Option<&[u8]>) -> ClientResult<()> {
+    match negotiated {
+        Some(protocol) if alpn_protocols.iter().any(|x| x == protocol) => Ok(()),
+        _ => Err(alpn_mismatch(alpn_protocols, negotiated)),
+    }
+}
+
+pub(self) fn check_quic_alpn(
+    alpn_protocols: &[Vec<u8>],
+    connection: &Connection,
+) -> ClientResult<()> {
+    let negotiated = connection
+        .handshake_data()
+        .and_then(|data| data.downcast::<quinn::crypto::rustls::HandshakeData>().ok())
+        .and_then(|data| data.protocol);
+    check_alpn(alpn_protocols, negotiated.as_deref())
+}
+
+/// connect and check the negotiated alpn, a server not answering in `connect_timeout` is given up.
+pub(self) async fn quic_connect(
+    endpoint: &Endpoint,
+    remote_address: SocketAddr,
+    domain: &str,
+    connect_timeout: Duration,
+    alpn_protocols: &[Vec<u8>],
+) -> ClientResult<Connection> {
+    let connecting = endpoint
+        .connect(remote_address, domain)
+        .map_err(|e| ClientError::Connect(e.to_string()))?;
+    handshake(connecting, connect_timeout, alpn_protocols).await
+}
+
+/// same as [`quic_connect`], but the connection is handed out before the handshake finishes if
+/// a session ticket is there, the returned future tells whether the 0-rtt data is accepted.
+///
+/// the alpn is not known until then, so it is left to the caller to check.
+pub(self) async fn quic_connect_0rtt(
+    endpoint: &Endpoint,
+    remote_address: SocketAddr,
+    domain: &str,
+    connect_timeout: Duration,
+    alpn_protocols: &[Vec<u8>],
+) -> ClientResult<(Connection, Option<ZeroRttAccepted>)> {
+    let connecting = endpoint
+        .connect(remote_address, domain)
+        .map_err(|e| ClientError::Connect(e.to_string()))?;
+    match connecting.into_0rtt() {
+        Ok((connection, accepted)) => Ok((connection, Some(accepted))),
+        Err(connecting) => Ok((
+            handshake(connecting, connect_timeout, alpn_protocols).await?,
+            None,
+        )),
+    }
+}
+
+pub(self) async fn handshake(
+    connecting: Connecting,
+    connect_timeout: Duration,
+    alpn_protocols: &[Vec<u8>],
+) -> ClientResult<Connection> {
+    let connection = tokio::time::timeout(connect_timeout, connecting)
+        .await
+        .map_err(|_| ClientError::Timeout(connect_timeout))?
+        .map_err(|e| map_quic_connect_error(alpn_protocols, e))?;
+    check_quic_alpn(alpn_protocols, &connection)?;
+    Ok(connection)
+}
+
+pub(self) async fn tls_connect(config: &ClientConfig) -> ClientResult<TlsStream<TcpStream>> {
+    let connector = TlsConnector::from(Arc::new(client_crypto(config)?));
+    let domain = rustls::ServerName::try_from(config.domain.as_str())
+        .map_err(|e| ClientError::Connect(e.to_string()))?;
+    let connect_timeout = config.connect_timeout;
+    let connect = async {
+        let stream = TcpStream::connect(config.remote_address)
+            .await
+            .map_err(|e| ClientError::Connect(e.to_string()))?;
+        connector
+            .connect(domain, stream)
+            .await
+            .map_err(|e| map_tls_connect_error(&config.alpn_protocols, e))
+    };
+    let stream = tokio::time::timeout(connect_timeout, connect)
+        .await
+        .map_err(|_| ClientError::Timeout(connect_timeout))??;
+    check_alpn(&config.alpn_protocols, stream.get_ref().1.alpn_protocol())?;
+    Ok(stream)
+}
+
+/// the server rejects the handshake with `no_application_protocol` when none of our alpn is supported.
+pub(self) fn map_quic_connect_error(
+    alpn_protocols: &[Vec<u8>],
+    e: quinn::ConnectionError,
+) -> ClientError {
+    match e {
+        quinn::ConnectionError::ConnectionClosed(ref close)
+            if u64::from(close.error_code) == 0x100 | ALERT_NO_APPLICATION_PROTOCOL as u64 =>
+        {
+            alpn_mismatch(alpn_protocols, None)
+        }
+        // the peer answered but refused to go on.
+        quinn::ConnectionError::VersionMismatch
+        | quinn::ConnectionError::TransportError(_)
+        | quinn::ConnectionError::ConnectionClosed(_)
+        | quinn::ConnectionError::ApplicationClosed(_) => ClientError::Handshake(e.to_string()),
+        _ => ClientError::Connect(e.to_string()),
+    }
+}
+
+pub(self) fn map_tls_connect_error(alpn_protocols: &[Vec<u8>], e: std::io::Error) -> ClientError {
+    let no_application_protocol = e
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<rustls::Error>())
+        .map(|inner| {
+            matches!(
+                inner,
+                rustls::Error::AlertReceived(rustls::AlertDescription::NoApplicationProtocol)
+            )
+        })
+        .unwrap_or(false);
+    if no_application_protocol {
+        alpn_mismatch(alpn_protocols, None)
+    } else {
+        ClientError::Handshake(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use lib::{error::ClientError, net::client::ClientConfigBuilder};
+    use tokio::{io::AsyncReadExt, net::TcpListener};
+    use tokio_rustls::TlsAcceptor;
+
+    use lib::{
+        entity::{Msg, Type, HEAD_LEN, PAYLOAD_THRESHOLD},
+        net::ALPN_PRIM,
+    };
+
+    use super::{
+        bridge_streams, idle_timeout, is_control_msg, root_cert_store, stream_priority,
+        transport_config, Client, ClientMultiConnection, ClientTcp, SubConnectionConfig,
+    };
+    use crate::net::{flow_relay, MsgIOUtil, MsgIOWrapper, StreamEnd, TimeoutEvent};
+
+    fn self_signed() -> (rustls::Certificate, rustls::PrivateKey) {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let key = rustls::PrivateKey(cert.serialize_private_key_der());
+        (rustls::Certificate(cert.serialize_der().unwrap()), key)
+    }
+
+    fn server_crypto(alpn: &[u8]) -> (rustls::Certificate, rustls::ServerConfig) {
+        let (cert, key) = self_signed();
+        let mut config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert.clone()], key)
+            .unwrap();
+        config.alpn_protocols = vec![alpn.to_vec()];
+        (cert, config)
+    }
+
+    fn client_config(
+        remote_address: SocketAddr,
+        cert: rustls::Certificate,
+    ) -> lib::net::client::ClientConfig {
+        let mut builder = ClientConfigBuilder::default();
+        builder
+            .with_remote_address(remote_address)
+            .with_ipv4_type(true)
+            .with_domain("localhost".to_string())
+            .with_cert(cert)
+            .with_keep_alive_interval(Duration::from_secs(3))
+            .with_max_bi_streams(1);
+        builder.build().unwrap()
+    }
+
+    fn assert_alpn_mismatch(e: ClientError) {
+        match e {
+            ClientError::AlpnMismatch { .. } => {}
+            _ => panic!("expected alpn mismatch, got: {}", e),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_quic_alpn_mismatch() {
+        let (cert, crypto) = server_crypto(b"other");
+        let server_config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+        let endpoint =
+            quinn::Endpoint::server(server_config, "127.0.0.1:0".parse().unwrap()).unwrap();
+        let address = endpoint.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Some(connecting) = endpoint.accept().await {
+                _ = connecting.await;
+            }
+        });
+        let mut client = Client::new(client_config(address, cert));
+        assert_alpn_mismatch(client.run().await.unwrap_err());
+    }
+
+    #[tokio::test]
+    async fn test_custom_alpn() {
+        let (cert, crypto) = server_crypto(b"prim/2");
+        let server_config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+        let endpoint =
+            quinn::Endpoint::server(server_config, "127.0.0.1:0".parse().unwrap()).unwrap();
+        let address = endpoint.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Some(connecting) = endpoint.accept().await {
+                tokio::spawn(async move {
+                    if let Ok(connection) = connecting.await {
+                        _ = connection.closed().await;
+                    }
+                });
+            }
+        });
+        let mut config = client_config(address, cert.clone());
+        // a client rolled out with the new version first, still able to talk to old servers.
+        config.alpn_protocols = vec![b"prim/2".to_vec(), ALPN_PRIM[0].to_vec()];
+        let mut client = Client::new(config);
+        client.run().await.unwrap();
+        let negotiated = client
+            .connection
+            .as_ref()
+            .unwrap()
+            .handshake_data()
+            .unwrap()
+            .downcast::<quinn::crypto::rustls::HandshakeData>()
+            .unwrap()
+            .protocol;
+        assert_eq!(negotiated.as_deref(), Some(&b"prim/2"[..]));
+
+        // the default list knows nothing about the new version.
+        let mut client = Client::new(client_config(address, cert));
+        assert_alpn_mismatch(client.run().await.unwrap_err());
+    }
+
+    #[tokio::test]
+    async fn test_tls_alpn_mismatch() {
+        let (cert, crypto) = server_crypto(b"other");
+        let acceptor = TlsAcceptor::from(Arc::new(crypto));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                _ = acceptor.accept(stream).await;
+            }
+        });
+        let mut client = ClientTcp::new(client_config(address, cert));
+        assert_alpn_mismatch(client.run().await.unwrap_err());
+    }
+
+    #[test]
+    fn test_root_cert_store() {
+        let (old_ca, _) = self_signed();
+        let (new_ca, _) = self_signed();
+        let mut builder = ClientConfigBuilder::default();
+        builder
+            .with_remote_address("127.0.0.1:11120".parse().unwrap())
+            .with_ipv4_type(true)
+            .with_domain("localhost".to_string())
+            .with_cert(old_ca)
+            .with_certs(vec![new_ca])
+            .with_keep_alive_interval(Duration::from_secs(3))
+            .with_max_bi_streams(1);
+        let config = builder.build().unwrap();
+        assert_eq!(config.certs.len(), 2);
+        let roots = root_cert_store(&config.certs).unwrap();
+        assert_eq!(roots.len(), 2);
+    }
+
+    /// a tls server accepting only `versions`, it returns the address to connect.
+    async fn tls_server(
+        versions: &[&'static rustls::SupportedProtocolVersion],
+    ) -> (rustls::Certificate, SocketAddr) {
+        let (cert, key) = self_signed();
+        let mut crypto = rustls::ServerConfig::builder()
+            .with_safe_default_cipher_suites()
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(versions)
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert.clone()], key)
+            .unwrap();
+        crypto.alpn_protocols = vec![ALPN_PRIM[0].to_vec()];
+        let acceptor = TlsAcceptor::from(Arc::new(crypto));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                _ = acceptor.accept(stream).await;
+            }
+        });
+        (cert, address)
+    }
+
+    #[tokio::test]
+    async fn test_client_identity() {
+        let (client_cert, client_key) = self_signed();
+        let (cert, key) = self_signed();
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(&client_cert).unwrap();
+        let mut crypto = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_client_cert_verifier(
+                rustls::server::AllowAnyAuthenticatedClient::new(roots).boxed(),
+            )
+            .with_single_cert(vec![cert.clone()], key)
+            .unwrap();
+        crypto.alpn_protocols = vec![ALPN_PRIM[0].to_vec()];
+        let acceptor = TlsAcceptor::from(Arc::new(crypto));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(2);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                _ = sender.send(acceptor.accept(stream).await.is_ok()).await;
+            }
+        });
+
+        let mut builder = ClientConfigBuilder::default();
+        builder
+            .with_remote_address(address)
+            .with_ipv4_type(true)
+            .with_domain("localhost".to_string())
+            .with_cert(cert.clone())
+            .with_keep_alive_interval(Duration::from_secs(3))
+            .with_max_bi_streams(1)
+            .with_client_identity(vec![client_cert], client_key);
+        let mut client = ClientTcp::new(builder.build().unwrap());
+        client.run().await.unwrap();
+        assert!(receiver.recv().await.unwrap());
+
+        // with tls 1.3 the missing certificate is only found by the server.
+        let mut client = ClientTcp::new(client_config(address, cert));
+        _ = client.run().await;
+        assert!(!receiver.recv().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_tls_versions() {
+        let tls13_only = |address, cert| {
+            let mut builder = ClientConfigBuilder::default();
+            builder
+                .with_remote_address(address)
+                .with_ipv4_type(true)
+                .with_domain("localhost".to_string())
+                .with_cert(cert)
+                .with_keep_alive_interval(Duration::from_secs(3))
+                .with_max_bi_streams(1)
+                .with_tls_versions(vec![&rustls::version::TLS13]);
+            builder
+        };
+
+        let (cert, address) = tls_server(rustls::ALL_VERSIONS).await;
+        let mut client = ClientTcp::new(tls13_only(address, cert).build().unwrap());
+        client.run().await.unwrap();
+        let connection = client.connection.as_ref().unwrap().get_ref().1;
+        assert_eq!(
+            connection.protocol_version(),
+            Some(rustls::ProtocolVersion::TLSv1_3)
+        );
+        assert_eq!(
+            connection.negotiated_cipher_suite().unwrap().version(),
+            &rustls::version::TLS13
+        );
+
+        // the default still talks to a server which only knows tls 1.2.
+        let (cert, address) = tls_server(&[&rustls::version::TLS12]).await;
+        let mut client = ClientTcp::new(client_config(address, cert.clone()));
+        client.run().await.unwrap();
+        let mut client = ClientTcp::new(tls13_only(address, cert.clone()).build().unwrap());
+        assert!(matches!(
+            client.run().await.unwrap_err(),
+            ClientError::Handshake(_)
+        ));
+
+        // a tls 1.2 only suite can not be negotiated with tls 1.3.
+        let mut builder = tls13_only(address, cert);
+        builder.with_cipher_suites(vec![
+            rustls::cipher_suite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
+        ]);
+        assert!(builder.build().is_err());
+    }
+
+    #[test]
+    fn test_idle_timeout() {
+        let (cert, _) = self_signed();
+        let mut config = client_config("127.0.0.1:11120".parse().unwrap(), cert);
+        assert!(idle_timeout(&config).unwrap().is_none());
+        config.idle_timeout = Some(Duration::from_millis(3000));
+        assert!(
+            idle_timeout(&config).unwrap()
+                == Some(quinn::IdleTimeout::from(quinn::VarInt::from_u32(3000)))
+        );
+        // beyond what a varint of milliseconds holds.
+        config.idle_timeout = Some(Duration::MAX);
+        assert!(idle_timeout(&config).is_err());
+        assert!(transport_config(&config).is_err());
+    }
+
+    #[test]
+    fn test_receive_windows() {
+        let (cert, _) = self_signed();
+        let mut config = client_config("127.0.0.1:11120".parse().unwrap(), cert);
+        let default = format!("{:?}", transport_config(&config).unwrap());
+        assert!(!default.contains("stream_receive_window: 4194304"));
+        config.stream_receive_window = Some(4194304);
+        config.receive_window = Some(33554432);
+        let transport = format!("{:?}", transport_config(&config).unwrap());
+        assert!(transport.contains("stream_receive_window: 4194304"));
+        assert!(transport.contains(" receive_window: 33554432"));
+    }
+
+    #[test]
+    fn test_stream_priority() {
+        let control = stream_priority(0, true, 0);
+        for i in 1..4 {
+            assert!(control > stream_priority(i, true, 0));
+        }
+        // the only stream carries both control and data msgs.
+        assert_eq!(stream_priority(0, false, 0), 0);
+        assert_eq!(stream_priority(0, true, i32::MAX), i32::MAX);
+        let mut ack = Msg::text(1, 2, 0, "");
+        ack.set_type(Type::Ack);
+        assert!(is_control_msg(&ack));
+        assert!(!is_control_msg(&Msg::text(1, 2, 0, "hello")));
+    }
+
+    #[tokio::test]
+    async fn test_bridge_fairness() {
+        let (send_channel, mut stream_out) = tokio::sync::mpsc::channel(64);
+        let (stream_in, recv_channel) = tokio::sync::mpsc::channel(64);
+        let (user_in, mut user_receiver) = tokio::sync::mpsc::channel(64);
+        let (user_sender, user_out) = async_channel::bounded(64);
+        tokio::spawn(bridge_streams(
+            send_channel,
+            recv_channel,
+            (user_in, user_out),
+            None,
+            None,
+        ));
+        // the stream never runs dry, and the user reads all it gets.
+        tokio::spawn(async move {
+            let msg = Arc::new(Msg::text(2, 1, 0, "inbound"));
+            while stream_in.send(msg.clone()).await.is_ok() {}
+        });
+        let inbound = Arc::new(AtomicUsize::new(0));
+        let counter = inbound.clone();
+        tokio::spawn(async move {
+            while user_receiver.recv().await.is_some() {
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+        let outbound = async {
+            for i in 0..100 {
+                user_sender
+                    .send(Arc::new(Msg::text(1, 2, 0, &format!("outbound {}", i))))
+                    .await
+                    .unwrap();
+                let msg = stream_out.recv().await.unwrap();
+                assert_eq!(msg.payload(), format!("outbound {}", i).as_bytes());
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), outbound)
+            .await
+            .expect("sends starved by the receive side");
+        // the receive side goes on meanwhile.
+        assert!(inbound.load(Ordering::Relaxed) >= 100);
+    }
+
+    #[tokio::test]
+    async fn test_flow_control() {
+        // the stream of the client is wired to the one of a server directly, and buffers little
+        // like a real one.
+        let (send_channel, server_in) = tokio::sync::mpsc::channel(4);
+        let (server_out, recv_channel) = tokio::sync::mpsc::channel(64);
+        let (user_in, mut user_receiver) = tokio::sync::mpsc::channel(64);
+        let (user_sender, user_out) = async_channel::bounded(256);
+        let mut handler_channel = flow_relay(server_in, server_out.downgrade(), 8, 2);
+        tokio::spawn(bridge_streams(
+            send_channel,
+            recv_channel,
+            (user_in, user_out),
+            None,
+            None,
+        ));
+        for i in 0..100 {
+            let msg = Msg::text(1, 2, 0, &format!("outbound {}", i));
+            user_sender.send(Arc::new(msg)).await.unwrap();
+        }
+        // the handler is congested and reads nothing for a while.
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let queued = user_sender.len();
+        assert!(queued > 50, "not throttled: {}", queued);
+        let mut received = 0;
+        while handler_channel.try_recv().is_ok() {
+            received += 1;
+        }
+        assert!(received >= 8 && received < 50, "received: {}", received);
+        // the rest arrive once the handler catches up.
+        tokio::time::timeout(Duration::from_secs(3), async {
+            while received < 100 {
+                handler_channel.recv().await.unwrap();
+                received += 1;
+            }
+        })
+        .await
+        .expect("never resumed");
+        // the signals are never handed to the user.
+        assert!(user_receiver.try_recv().is_err());
+        drop(server_out);
+    }
+
+    #[tokio::test]
+    async fn test_send_with_ack() {
+        let (cert, _) = self_signed();
+        let mut client = Client::new(client_config("127.0.0.1:11120".parse().unwrap(), cert));
+        let (send_channel, mut stream_out) = tokio::sync::mpsc::channel(64);
+        let (stream_in, recv_channel) = tokio::sync::mpsc::channel(64);
+        let (user_in, mut user_receiver) = tokio::sync::mpsc::channel(64);
+        let (user_sender, user_out) = async_channel::bounded(64);
+        client.msg_sender = Some(user_sender);
+        tokio::spawn(bridge_streams(
+            send_channel,
+            recv_channel,
+            (user_in, user_out),
+            Some(client.ack_tracker.clone()),
+            None,
+        ));
+        // the peer acks every msg it gets.
+        tokio::spawn(async move {
+            while let Some(msg) = stream_out.recv().await {
+                let ack = msg.generate_server_ack(9, msg.timestamp(), 3);
+                _ = stream_in.send(Arc::new(ack)).await;
+            }
+        });
+        let msg = Msg::text(1, 2, 0, "hello");
+        let ack = client
+            .send_with_ack(Arc::new(msg), Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(ack.typ(), Type::Ack);
+        assert_eq!((ack.receiver(), ack.seqnum()), (1, 3));
+        assert_eq!(user_receiver.recv().await.unwrap().typ(), Type::Ack);
+        assert!(client.ack_tracker.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_close_after_flush() {
+        let (cert, crypto) = server_crypto(ALPN_PRIM[0]);
+        let server_config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+        let endpoint =
+            quinn::Endpoint::server(server_config, "127.0.0.1:0".parse().unwrap()).unwrap();
+        let address = endpoint.local_addr().unwrap();
+        let (count_sender, count_receiver) = tokio::sync::oneshot::channel();
+        tokio::spawn(async move {
+            let connection = endpoint.accept().await.unwrap().await.unwrap();
+            let (send_stream, recv_stream) = connection.accept_bi().await.unwrap();
+            let mut io_operators =
+                MsgIOWrapper::new(send_stream, recv_stream, 0, PAYLOAD_THRESHOLD, None);
+            let (_send_channel, mut recv_channel) = io_operators.channels();
+            let mut count = 0;
+            while let Some(msg) = recv_channel.recv().await {
+                if msg.typ() == Type::Text {
+                    count += 1;
+                }
+            }
+            _ = count_sender.send(count);
+        });
+        let client = ClientMultiConnection::new(client_config(address, cert)).unwrap();
+        let mut auth = Msg::text(1, 0, 0, "");
+        auth.set_type(Type::Auth);
+        let mut sub_conn = client
+            .new_connection(
+                SubConnectionConfig {
+                    remote_address: address,
+                    domain: "localhost".to_string(),
+                    opened_bi_streams_number: 1,
+                    opened_uni_streams_number: 0,
+                    timeout: Duration::from_secs(3),
+                    priority: 0,
+                    max_stream_replenishments: 0,
+                },
+                Arc::new(auth),
+            )
+            .await
+            .unwrap();
+        let (sender, _receiver) = sub_conn.operation_channel();
+        for i in 0..50 {
+            sender
+                .send(Arc::new(Msg::text(1, 2, 0, &format!("msg-{}", i))))
+                .await
+                .unwrap();
+        }
+        sub_conn
+            .close_after_flush(Duration::from_secs(3))
+            .await
+            .unwrap();
+        assert_eq!(count_receiver.await.unwrap(), 50);
+    }
+
+    #[tokio::test]
+    async fn test_close() {
+        let (cert, crypto) = server_crypto(ALPN_PRIM[0]);
+        let server_config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+        let endpoint =
+            quinn::Endpoint::server(server_config, "127.0.0.1:0".parse().unwrap()).unwrap();
+        let address = endpoint.local_addr().unwrap();
+        let (count_sender, count_receiver) = tokio::sync::oneshot::channel();
+        tokio::spawn(async move {
+            let connection = endpoint.accept().await.unwrap().await.unwrap();
+            let (send_stream, recv_stream) = connection.accept_bi().await.unwrap();
+            let mut io_operators =
+                MsgIOWrapper::new(send_stream, recv_stream, 0, PAYLOAD_THRESHOLD, None);
+            let (_send_channel, mut recv_channel) = io_operators.channels();
+            let mut count = 0;
+            while let Some(msg) = recv_channel.recv().await {
+                if msg.typ() == Type::Text {
+                    count += 1;
+                }
+            }
+            _ = count_sender.send(count);
+        });
+        let mut client = Client::new(client_config(address, cert));
+        client.run().await.unwrap();
+        let (sender, _receiver) = client.io_channel_token(1, 0, 0, "token").await.unwrap();
+        for i in 0..50 {
+            sender
+                .send(Arc::new(Msg::text(1, 2, 0, &format!("msg-{}", i))))
+                .await
+                .unwrap();
+        }
+        client.close(Duration::from_secs(3)).await.unwrap();
+        // nothing is taken after close.
+        assert!(sender
+            .send(Arc::new(Msg::text(1, 2, 0, "late")))
+            .await
+            .is_err());
+        assert_eq!(count_receiver.await.unwrap(), 50);
+    }
+
+    #[tokio::test]
+    async fn test_send_after_disconnected() {
+        let (cert, crypto) = server_crypto(ALPN_PRIM[0]);
+        let server_config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+        let endpoint =
+            quinn::Endpoint::server(server_config, "127.0.0.1:0".parse().unwrap()).unwrap();
+        let address = endpoint.local_addr().unwrap();
+        tokio::spawn(async move {
+            let connection = endpoint.accept().await.unwrap().await.unwrap();
+            let (send_stream, recv_stream) = connection.accept_bi().await.unwrap();
+            let mut io_operators =
+                MsgIOWrapper::new(send_stream, recv_stream, 0, PAYLOAD_THRESHOLD, None);
+            let (_send_channel, mut recv_channel) = io_operators.channels();
+            // the auth msg, and then the server goes away.
+            _ = recv_channel.recv().await;
+            connection.close(0u32.into(), b"bye.");
+            endpoint.wait_idle().await;
+        });
+        let mut client = Client::new(client_config(address, cert));
+        client.run().await.unwrap();
+        let (sender, _receiver) = client.io_channel_token(1, 0, 0, "token").await.unwrap();
+        let disconnected = async {
+            while sender
+                .send(Arc::new(Msg::text(1, 2, 0, "hello")))
+                .await
+                .is_ok()
+            {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), disconnected)
+            .await
+            .expect("msgs are still taken after disconnected");
+        assert!(!client.is_connected());
+        assert!(sender.is_closed());
+    }
+
+    #[tokio::test]
+    async fn test_uni_streams() {
+        let (cert, crypto) = server_crypto(ALPN_PRIM[0]);
+        let server_config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+        let endpoint =
+            quinn::Endpoint::server(server_config, "127.0.0.1:0".parse().unwrap()).unwrap();
+        let address = endpoint.local_addr().unwrap();
+        let (msg_sender, mut msg_receiver) = tokio::sync::mpsc::channel(8);
+        tokio::spawn(async move {
+            let connection = endpoint.accept().await.unwrap().await.unwrap();
+            // which uni stream a msg goes through is not determined, so read all of them.
+            while let Ok(mut recv_stream) = connection.accept_uni().await {
+                let msg_sender = msg_sender.clone();
+                tokio::spawn(async move {
+                    let mut buffer = Box::new([0u8; HEAD_LEN]);
+                    while let Ok(msg) =
+                        MsgIOUtil::recv_msg(&mut buffer, &mut recv_stream, None, PAYLOAD_THRESHOLD)
+                            .await
+                    {
+                        _ = msg_sender.send(msg).await;
+                    }
+                });
+            }
+        });
+        let client = ClientMultiConnection::new(client_config(address, cert)).unwrap();
+        let mut auth = Msg::text(1, 0, 0, "");
+        auth.set_type(Type::Auth);
+        let mut sub_conn = client
+            .new_connection(
+                SubConnectionConfig {
+                    remote_address: address,
+                    domain: "localhost".to_string(),
+                    opened_bi_streams_number: 1,
+                    opened_uni_streams_number: 2,
+                    timeout: Duration::from_secs(3),
+                    priority: 0,
+                    max_stream_replenishments: 0,
+                },
+                Arc::new(auth),
+            )
+            .await
+            .unwrap();
+        let uni_sender = sub_conn.uni_channel().unwrap();
+        assert!(sub_conn.uni_channel().is_none());
+        uni_sender
+            .send(Arc::new(Msg::text(1, 2, 0, "typing")))
+            .await
+            .unwrap();
+        let msg = tokio::time::timeout(Duration::from_secs(3), msg_receiver.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(msg.receiver(), 2);
+        assert_eq!(msg.payload(), b"typing");
+    }
+
+    #[tokio::test]
+    async fn test_replenish_streams() {
+        let (cert, crypto) = server_crypto(ALPN_PRIM[0]);
+        let server_config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+        let endpoint =
+            quinn::Endpoint::server(server_config, "127.0.0.1:0".parse().unwrap()).unwrap();
+        let address = endpoint.local_addr().unwrap();
+        let (msg_sender, mut msg_receiver) = tokio::sync::mpsc::channel(8);
+        tokio::spawn(async move {
+            let connection = endpoint.accept().await.unwrap().await.unwrap();
+            let mut buffer = Box::new([0u8; HEAD_LEN]);
+            // the first stream is killed right after the auth msg.
+            let (mut send_stream, mut recv_stream) = connection.accept_bi().await.unwrap();
+            let auth = MsgIOUtil::recv_msg(&mut buffer, &mut recv_stream, None, PAYLOAD_THRESHOLD)
+                .await
+                .unwrap();
+            _ = msg_sender.send(auth).await;
+            _ = send_stream.finish().await;
+            _ = recv_stream.stop(0u32.into());
+            let (_send_stream, mut recv_stream) = connection.accept_bi().await.unwrap();
+            while let Ok(msg) =
+                MsgIOUtil::recv_msg(&mut buffer, &mut recv_stream, None, PAYLOAD_THRESHOLD).await
+            {
+                _ = msg_sender.send(msg).await;
+            }
+        });
+        let client = ClientMultiConnection::new(client_config(address, cert)).unwrap();
+        let mut auth = Msg::text(1, 0, 0, "");
+        auth.set_type(Type::Auth);
+        let mut sub_conn = client
+            .new_connection(
+                SubConnectionConfig {
+                    remote_address: address,
+                    domain: "localhost".to_string(),
+                    opened_bi_streams_number: 1,
+                    opened_uni_streams_number: 0,
+                    timeout: Duration::from_secs(3),
+                    priority: 0,
+                    max_stream_replenishments: 1,
+                },
+                Arc::new(auth),
+            )
+            .await
+            .unwrap();
+        let (sender, _receiver) = sub_conn.operation_channel();
+        for _ in 0..2 {
+            // the replacement opens with the auth msg too.
+            let msg = tokio::time::timeout(Duration::from_secs(3), msg_receiver.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(msg.typ(), Type::Auth);
+        }
+        assert_eq!(sub_conn.replenished_streams(), 1);
+        sender
+            .send(Arc::new(Msg::text(1, 2, 0, "still here")))
+            .await
+            .unwrap();
+        let msg = tokio::time::timeout(Duration::from_secs(3), msg_receiver.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(msg.payload(), b"still here");
+    }
+
+    #[tokio::test]
+    async fn test_0rtt() {
+        let (cert, mut crypto) = server_crypto(ALPN_PRIM[0]);
+        crypto.max_early_data_size = u32::MAX;
+        let server_config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+        let endpoint =
+            quinn::Endpoint::server(server_config, "127.0.0.1:0".parse().unwrap()).unwrap();
+        let address = endpoint.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Some(connecting) = endpoint.accept().await {
+                tokio::spawn(async move {
+                    let connection = connecting.await.unwrap();
+                    let mut streams = Vec::new();
+                    while let Ok(io_streams) = connection.accept_bi().await {
+                        streams.push(io_streams);
+                    }
+                });
+            }
+        });
+        let mut config = client_config(address, cert.clone());
+        config.enable_0rtt = true;
+        let mut auth = Msg::text(1, 0, 0, "");
+        auth.set_type(Type::Auth);
+        let auth = Arc::new(auth);
+        let sub_config = || SubConnectionConfig {
+            remote_address: address,
+            domain: "localhost".to_string(),
+            opened_bi_streams_number: 1,
+            opened_uni_streams_number: 0,
+            timeout: Duration::from_secs(3),
+            priority: 0,
+            max_stream_replenishments: 0,
+        };
+        let client = ClientMultiConnection::new(config.clone()).unwrap();
+        let first = client
+            .new_connection(sub_config(), auth.clone())
+            .await
+            .unwrap();
+        assert!(!first.is_0rtt());
+        // the ticket is sent by the server after the handshake.
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        // a client built later from the same config resumes with the ticket stored by the first
+        // one.
+        let client2 = ClientMultiConnection::new(config).unwrap();
+        let second = client2
+            .new_connection(sub_config(), auth.clone())
+            .await
+            .unwrap();
+        assert!(second.is_0rtt());
+
+        // but not one built from another config.
+        let mut config = client_config(address, cert);
+        config.enable_0rtt = true;
+        let client3 = ClientMultiConnection::new(config).unwrap();
+        let third = client3.new_connection(sub_config(), auth).await.unwrap();
+        assert!(!third.is_0rtt());
+    }
+
+    #[tokio::test]
+    async fn test_0rtt_rejected() {
+        let (cert, key) = self_signed();
+        let server = |max_early_data_size: u32| {
+            let mut crypto = rustls::ServerConfig::builder()
+                .with_safe_defaults()
+                .with_no_client_auth()
+                .with_single_cert(vec![cert.clone()], key.clone())
+                .unwrap();
+            crypto.alpn_protocols = vec![ALPN_PRIM[0].to_vec()];
+            crypto.max_early_data_size = max_early_data_size;
+            let server_config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+            let endpoint =
+                quinn::Endpoint::server(server_config, "127.0.0.1:0".parse().unwrap()).unwrap();
+            let address = endpoint.local_addr().unwrap();
+            let active = Arc::new(AtomicUsize::new(0));
+            let active0 = active.clone();
+            tokio::spawn(async move {
+                while let Some(connecting) = endpoint.accept().await {
+                    let active = active0.clone();
+                    tokio::spawn(async move {
+                        let connection = match connecting.await {
+                            Ok(connection) => connection,
+                            Err(_) => return,
+                        };
+                        active.fetch_add(1, Ordering::AcqRel);
+                        let mut streams = Vec::new();
+                        while let Ok(io_streams) = connection.accept_bi().await {
+                            streams.push(io_streams);
+                        }
+                        active.fetch_sub(1, Ordering::AcqRel);
+                    });
+                }
+            });
+            (address, active)
+        };
+        let (address, _) = server(u32::MAX);
+        // the same cert and domain, but the tickets of the other one are unknown to it.
+        let (rejecting_address, active) = server(0);
+
+        let mut config = client_config(address, cert);
+        config.enable_0rtt = true;
+        let mut auth = Msg::text(1, 0, 0, "");
+        auth.set_type(Type::Auth);
+        let auth = Arc::new(auth);
+        let sub_config = |remote_address| SubConnectionConfig {
+            remote_address,
+            domain: "localhost".to_string(),
+            opened_bi_streams_number: 1,
+            opened_uni_streams_number: 0,
+            timeout: Duration::from_secs(3),
+            priority: 0,
+            max_stream_replenishments: 0,
+        };
+        let client = ClientMultiConnection::new(config).unwrap();
+        let _first = client
+            .new_connection(sub_config(address), auth.clone())
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let second = client
+            .new_connection(sub_config(rejecting_address), auth)
+            .await
+            .unwrap();
+        assert!(!second.is_0rtt());
+        // only the connection of the full handshake is left.
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(active.load(Ordering::Acquire), 1);
+    }
+
+    #[tokio::test]
+    async fn test_connect_timeout() {
+        // a udp socket never answering the handshake, just like a black-holed server.
+        let black_hole = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let (cert, _) = self_signed();
+        let mut config = client_config(black_hole.local_addr().unwrap(), cert);
+        config.connect_timeout = Duration::from_millis(200);
+        let start = std::time::Instant::now();
+        let e = Client::new(config).run().await.unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(2));
+        match e {
+            ClientError::Timeout(timeout) => assert_eq!(timeout, Duration::from_millis(200)),
+            _ => panic!("expected connect timeout, got: {}", e),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connect_error() {
+        // nobody listens on the port once the listener is dropped.
+        let address = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let (cert, _) = self_signed();
+        let e = ClientTcp::new(client_config(address, cert.clone()))
+            .run()
+            .await
+            .unwrap_err();
+        assert!(matches!(e, ClientError::Connect(_)), "got: {}", e);
+        // quinn refuses to dial an unspecified port before sending anything.
+        let address = "127.0.0.1:0".parse().unwrap();
+        let e = Client::new(client_config(address, cert))
+            .run()
+            .await
+            .unwrap_err();
+        assert!(matches!(e, ClientError::Connect(_)), "got: {}", e);
+    }
+
+    #[tokio::test]
+    async fn test_tls_stale() {
+        let (cert, crypto) = server_crypto(ALPN_PRIM[0]);
+        let acceptor = TlsAcceptor::from(Arc::new(crypto));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        // reads everything but never answers, not even the pings.
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = acceptor.accept(stream).await.unwrap();
+            let mut buf = [0u8; 1024];
+            while let Ok(n) = stream.read(&mut buf).await {
+                if n == 0 {
+                    break;
+                }
+            }
+        });
+        let window = Duration::from_millis(300);
+        let mut config = client_config(address, cert);
+        config.keep_alive_interval = Duration::from_millis(50);
+        config.stale_window = Some(window);
+        let mut client = ClientTcp::new(config);
+        let mut timeout_channel = client.timeout_channel().unwrap();
+        client.run().await.unwrap();
+        let _io_channel = client.io_channel_token(1, 0, 0, "token").await.unwrap();
+        assert!(!client.is_stale(window));
+
+        let event = tokio::time::timeout(Duration::from_secs(3), timeout_channel.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let last_recv = match event {
+            TimeoutEvent::Stale { last_recv } => last_recv,
+            event => panic!("expected stale, got {:?}", event),
+        };
+        assert!(client.is_stale(window));
+        assert_eq!(last_recv, client.activity().last_recv());
+        // the pings still go out, only nothing comes back.
+        assert!(client.activity().last_send() > last_recv);
+        // reported once until something is received again.
+        assert!(
+            tokio::time::timeout(Duration::from_millis(500), timeout_channel.recv())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_timeout_channel_dropped() {
+        let (cert, crypto) = server_crypto(ALPN_PRIM[0]);
+        let acceptor = TlsAcceptor::from(Arc::new(crypto));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let window = Duration::from_millis(200);
+        // silent long enough to go stale, then echoes everything back.
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let stream = acceptor.accept(stream).await.unwrap();
+            tokio::time::sleep(window * 4).await;
+            let (mut recv_stream, mut send_stream) = tokio::io::split(stream);
+            _ = tokio::io::copy(&mut recv_stream, &mut send_stream).await;
+        });
+        let mut config = client_config(address, cert);
+        config.keep_alive_interval = Duration::from_millis(50);
+        config.stale_window = Some(window);
+        let mut client = ClientTcp::new(config);
+        drop(client.timeout_channel().unwrap());
+        client.run().await.unwrap();
+        let (send_channel, mut recv_channel) =
+            client.io_channel_token(1, 0, 0, "token").await.unwrap();
+        tokio::time::timeout(Duration::from_secs(3), async {
+            while !client.is_stale(window) {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .unwrap();
+        // the stale event has nowhere to go by now.
+        tokio::time::sleep(window).await;
+
+        send_channel
+            .send(Arc::new(Msg::text(1, 2, 0, "still alive")))
+            .await
+            .unwrap();
+        let msg = tokio::time::timeout(Duration::from_secs(3), async {
+            loop {
+                let msg = recv_channel.recv().await.unwrap();
+                if msg.typ() == Type::Text {
+                    break msg;
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(msg.payload(), b"still alive");
+        assert!(!client.is_stale(window));
+    }
+
+    #[tokio::test]
+    async fn test_stream_end() {
+        let (cert, crypto) = server_crypto(ALPN_PRIM[0]);
+        let server_config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+        let endpoint =
+            quinn::Endpoint::server(server_config, "127.0.0.1:0".parse().unwrap()).unwrap();
+        let address = endpoint.local_addr().unwrap();
+        let (done_sender, done) = tokio::sync::oneshot::channel::<()>();
+        tokio::spawn(async move {
+            let mut buffer = Box::new([0u8; HEAD_LEN]);
+            // the first connection is closed by the server once authenticated.
+            let closed = endpoint.accept().await.unwrap().await.unwrap();
+            let (_send_stream, mut recv_stream) = closed.accept_bi().await.unwrap();
+            MsgIOUtil::recv_msg(&mut buffer, &mut recv_stream, None, PAYLOAD_THRESHOLD)
+                .await
+                .unwrap();
+            closed.close(7u32.into(), b"bye");
+            // the second one is never answered, until the client gives up.
+            let silent = endpoint.accept().await.unwrap().await.unwrap();
+            let (_send_stream, mut recv_stream) = silent.accept_bi().await.unwrap();
+            MsgIOUtil::recv_msg(&mut buffer, &mut recv_stream, None, PAYLOAD_THRESHOLD)
+                .await
+                .unwrap();
+            _ = done.await;
+        });
+        let mut config = client_config(address, cert);
+        config.idle_timeout = Some(Duration::from_millis(300));
+        let ended = |mut client: Client| async move {
+            let mut timeout_channel = client.timeout_channel().unwrap();
+            client.run().await.unwrap();
+            let _io_channel = client.io_channel_token(1, 0, 0, "token").await.unwrap();
+            let event = tokio::time::timeout(Duration::from_secs(3), timeout_channel.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            match event {
+                TimeoutEvent::Ended(end) => end,
+                event => panic!("expected ended, got {:?}", event),
+            }
+        };
+
+        let closed = ended(Client::new(config.clone())).await;
+        assert_eq!(closed, StreamEnd::ApplicationClosed(7));
+        assert!(closed.is_orderly());
+        let timed_out = ended(Client::new(config)).await;
+        assert_eq!(timed_out, StreamEnd::TimedOut);
+        assert!(!timed_out.is_orderly());
+        assert_ne!(closed.to_string(), timed_out.to_string());
+        _ = done_sender.send(());
+    }
+}
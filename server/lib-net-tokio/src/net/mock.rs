@@ -0,0 +1,101 @@
+use std::{net::UdpSocket, time::Duration};
+
+use lib::net::{client::ClientConfigBuilder, server::ServerConfigBuilder};
+use tokio::sync::mpsc;
+
+use super::{MsgMpscReceiver, MsgMpscSender};
+
+/// the builders of a server on a free local port with a self signed `localhost` cert, and of a
+/// client trusting it, both allowing 4 streams. tweak them before `build()`, the cert and the
+/// address can be read back from the builders.
+pub fn config_pair() -> (ServerConfigBuilder, ClientConfigBuilder) {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let key = rustls::PrivateKey(cert.serialize_private_key_der());
+    let cert = rustls::Certificate(cert.serialize_der().unwrap());
+    let address = UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let mut server_config = ServerConfigBuilder::default();
+    server_config
+        .with_address(address)
+        .with_cert(cert.clone())
+        .with_key(key)
+        .with_max_connections(16)
+        .with_connection_idle_timeout(3000)
+        .with_max_bi_streams(4);
+    let mut client_config = ClientConfigBuilder::default();
+    client_config
+        .with_remote_address(address)
+        .with_ipv4_type(true)
+        .with_domain("localhost".to_string())
+        .with_cert(cert)
+        .with_keep_alive_interval(Duration::from_secs(1))
+        .with_max_bi_streams(4);
+    (server_config, client_config)
+}
+
+/// an in-memory counterpart of [`super::MsgIOWrapper`], the msgs go through local channels instead of
+/// a quic stream, so the handlers can be driven deterministically in tests.
+pub struct MsgIOWrapperMem {
+    pub(self) send_channel: Option<MsgMpscSender>,
+    pub(self) recv_channel: Option<MsgMpscReceiver>,
+}
+
+impl MsgIOWrapperMem {
+    /// two connected ends, what is sent on one end is received on the other one.
+    pub fn pair() -> (Self, Self) {
+        Self::pair_with_capacity(16384)
+    }
+
+    pub fn pair_with_capacity(capacity: usize) -> (Self, Self) {
+        let (sender0, receiver0) = mpsc::channel(capacity);
+        let (sender1, receiver1) = mpsc::channel(capacity);
+        (
+            Self {
+                send_channel: Some(sender0),
+                recv_channel: Some(receiver1),
+            },
+            Self {
+                send_channel: Some(sender1),
+                recv_channel: Some(receiver0),
+            },
+        )
+    }
+
+    pub fn channels(&mut self) -> (MsgMpscSender, MsgMpscReceiver) {
+        let send = self.send_channel.take().unwrap();
+        let recv = self.recv_channel.take().unwrap();
+        (send, recv)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use lib::entity::Msg;
+
+    use super::MsgIOWrapperMem;
+
+    #[tokio::test]
+    async fn test_pair() {
+        let (mut client, mut server) = MsgIOWrapperMem::pair();
+        let (client_sender, mut client_receiver) = client.channels();
+        let (server_sender, mut server_receiver) = server.channels();
+        client_sender
+            .send(Arc::new(Msg::text(1, 2, 0, "ping")))
+            .await
+            .unwrap();
+        let msg = server_receiver.recv().await.unwrap();
+        assert_eq!(msg.payload(), b"ping");
+        server_sender
+            .send(Arc::new(Msg::text(2, 1, 0, "pong")))
+            .await
+            .unwrap();
+        let msg = client_receiver.recv().await.unwrap();
+        assert_eq!(msg.payload(), b"pong");
+        drop(client_sender);
+        assert!(server_receiver.recv().await.is_none());
+    }
+}
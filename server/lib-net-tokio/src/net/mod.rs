@@ -1,6 +1,7 @@
 use std::{
     cell::UnsafeCell,
     io::Write,
+    ops::Deref,
     pin::Pin,
     sync::{
         atomic::{AtomicU64, Ordering},
@@ -10,29 +11,32 @@ use std::{
     time::Duration,
 };
 
-use ahash::AHashMap;
+use ahash::{AHashMap, AHashSet};
 use anyhow::anyhow;
 use async_recursion::async_recursion;
 use async_trait::async_trait;
 use byteorder::{BigEndian, ByteOrder};
+use dashmap::DashMap;
 use futures::{future::BoxFuture, pin_mut, select, Future, FutureExt};
 use lib::{
     entity::{
         Head, Msg, ReqwestMsg, ReqwestResourceID, Type, EXTENSION_THRESHOLD, HEAD_LEN,
         PAYLOAD_THRESHOLD,
     },
-    error::CrashError,
-    net::{GenericParameter, InnerStates},
+    error::{ClientError, CrashError, UnsupportedResource},
+    net::{check_reqwest_length, GenericParameter, InnerStates, DEFAULT_MAX_REQWEST_BYTES},
+    util::{health::current_health, histogram::record_payload, timestamp},
     Result,
 };
-use quinn::{ReadExactError, RecvStream, SendStream};
+use quinn::{ConnectionError, ReadError, ReadExactError, RecvStream, SendStream};
 use tokio::{
-    io::{split, AsyncReadExt, AsyncWriteExt, ReadHalf, WriteHalf},
+    io::{split, AsyncRead, AsyncReadExt, AsyncWriteExt, ReadHalf, WriteHalf},
     net::TcpStream,
-    sync::mpsc,
+    sync::{mpsc, oneshot},
     time::{Instant, Sleep},
 };
 use tokio_rustls::{client as tls_client, server as tls_server};
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info, warn};
 
 use self::server::ReqwestCaller;
@@ -41,6 +45,8 @@ use self::server::ReqwestCaller;
 use lib::entity::msg::MSG_DELIMITER;
 
 pub mod client;
+#[cfg(any(test, feature = "test-util"))]
+pub mod mock;
 pub mod server;
 
 /// the direction is relative to the stream task.
@@ -52,16 +58,48 @@ pub type MsgMpmcReceiver = async_channel::Receiver<Arc<Msg>>;
 pub type MsgMpmcSender = async_channel::Sender<Arc<Msg>>;
 pub type MsgMpscSender = mpsc::Sender<Arc<Msg>>;
 pub type MsgMpscReceiver = mpsc::Receiver<Arc<Msg>>;
+pub type TimeoutEventSender = mpsc::Sender<TimeoutEvent>;
+pub type TimeoutEventReceiver = mpsc::Receiver<TimeoutEvent>;
+
+/// batch operations on [`MsgMpmcSender`], as the sender is a foreign type we extend it by trait.
+#[async_trait]
+pub trait MsgMpmcSenderExt {
+    /// push a whole batch into the channel, the order within the batch is kept.
+    ///
+    /// return the error at the first failed msg, the msgs after it will not be sent.
+    async fn send_many(&self, msgs: Vec<Arc<Msg>>) -> Result<()>;
+}
+
+#[async_trait]
+impl MsgMpmcSenderExt for MsgMpmcSender {
+    async fn send_many(&self, msgs: Vec<Arc<Msg>>) -> Result<()> {
+        for msg in msgs {
+            // try the fast path first, only wait when the buffer is full.
+            match self.try_send(msg) {
+                Ok(_) => {}
+                Err(async_channel::TrySendError::Full(msg)) => {
+                    self.send(msg).await?;
+                }
+                Err(async_channel::TrySendError::Closed(_)) => {
+                    return Err(anyhow!("channel closed."));
+                }
+            }
+        }
+        Ok(())
+    }
+}
 
 pub const BODY_SIZE: usize = EXTENSION_THRESHOLD + PAYLOAD_THRESHOLD;
 
 pub type ReqwestHandlerMap = Arc<AHashMap<ReqwestResourceID, Box<dyn ReqwestHandler>>>;
-pub type HandlerList = Arc<Vec<Box<dyn Handler>>>;
 pub type ReqwestHandlerGenerator =
     Box<dyn Fn() -> Box<dyn NewReqwestConnectionHandler> + Send + Sync + 'static>;
 pub(self) type ReqwestHandlerGenerator0 =
     Box<dyn Fn() -> Box<dyn NewReqwestConnectionHandler0> + Send + Sync + 'static>;
 
+/// priority of handlers authenticating the connection, they must run before any other handler.
+pub const AUTH_HANDLER_PRIORITY: i32 = -100;
+
 #[async_trait]
 pub trait Handler: Send + Sync + 'static {
     /// the [`msg`] can be modified before clone() has been called.
@@ -72,6 +110,75 @@ pub trait Handler: Send + Sync + 'static {
         // this one contains some states corresponding to the quic stream.
         states: &mut InnerStates,
     ) -> Result<Msg>;
+
+    /// the order of this handler in [`HandlerList`], lower priority runs first.
+    /// handlers with the same priority keep their registration order.
+    fn priority(&self) -> i32 {
+        0
+    }
+}
+
+/// handlers sorted by [`Handler::priority`] at construction.
+#[derive(Clone)]
+pub struct HandlerList(Arc<Vec<Box<dyn Handler>>>, InterceptorChain);
+
+impl HandlerList {
+    pub fn new(mut handlers: Vec<Box<dyn Handler>>) -> Self {
+        // stable sort, so registration order still matters within the same priority.
+        handlers.sort_by_key(|handler| handler.priority());
+        Self(Arc::new(handlers), InterceptorChain::default())
+    }
+
+    /// run `interceptors` around the handlers for every msg after auth.
+    pub fn with_interceptors(mut self, interceptors: Vec<Box<dyn Interceptor>>) -> Self {
+        self.1 = InterceptorChain(Arc::new(interceptors));
+        self
+    }
+
+    pub fn interceptors(&self) -> &InterceptorChain {
+        &self.1
+    }
+}
+
+impl Deref for HandlerList {
+    type Target = [Box<dyn Handler>];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// cross-cutting work on every msg regardless of which handler takes it, such as content
+/// moderation, size checks or logging.
+#[async_trait]
+pub trait Interceptor: Send + Sync + 'static {
+    /// an error rejects the msg, no handler and no later interceptor sees it.
+    async fn before(&self, _msg: &Msg) -> Result<()> {
+        Ok(())
+    }
+
+    /// what the handlers made of the msg, not called for the rejected msgs.
+    async fn after(&self, _msg: &Msg, _res: &Result<()>) {}
+}
+
+/// `before` runs in registration order and `after` in reverse, so the first registered one
+/// wraps all the others.
+#[derive(Clone, Default)]
+pub struct InterceptorChain(Arc<Vec<Box<dyn Interceptor>>>);
+
+impl InterceptorChain {
+    pub async fn before(&self, msg: &Msg) -> Result<()> {
+        for interceptor in self.0.iter() {
+            interceptor.before(msg).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn after(&self, msg: &Msg, res: &Result<()>) {
+        for interceptor in self.0.iter().rev() {
+            interceptor.after(msg, res).await;
+        }
+    }
 }
 
 #[async_trait]
@@ -79,6 +186,60 @@ pub trait ReqwestHandler: Send + Sync + 'static {
     async fn run(&self, req: &mut ReqwestMsg, states: &mut InnerStates) -> Result<ReqwestMsg>;
 }
 
+/// routes reqwests to the handlers by resource id, the ids nobody serves fail with
+/// [`UnsupportedResource`], which the caller answers with instead of dropping the reqwest.
+#[derive(Clone)]
+pub struct ReqwestDispatcher {
+    handler_map: ReqwestHandlerMap,
+    unsupported: Arc<AHashSet<ReqwestResourceID>>,
+}
+
+impl ReqwestDispatcher {
+    pub fn new(handler_map: ReqwestHandlerMap) -> Self {
+        Self {
+            handler_map,
+            unsupported: Arc::new(AHashSet::new()),
+        }
+    }
+
+    /// mark the ids not served here on purpose, e.g. the ones this node only sends.
+    pub fn with_unsupported(mut self, resource_ids: &[ReqwestResourceID]) -> Self {
+        self.unsupported = Arc::new(resource_ids.iter().copied().collect());
+        self
+    }
+
+    pub fn get(&self, resource_id: &ReqwestResourceID) -> Option<&Box<dyn ReqwestHandler>> {
+        self.handler_map.get(resource_id)
+    }
+
+    pub fn is_handled(&self, resource_id: ReqwestResourceID) -> bool {
+        self.handler_map.contains_key(&resource_id)
+    }
+
+    pub fn is_unsupported(&self, resource_id: ReqwestResourceID) -> bool {
+        self.unsupported.contains(&resource_id)
+    }
+
+    /// the ids neither handled nor marked unsupported, which should be none.
+    pub fn unclaimed(&self) -> Vec<ReqwestResourceID> {
+        ReqwestResourceID::all()
+            .filter(|id| !self.is_handled(*id) && !self.is_unsupported(*id))
+            .collect()
+    }
+
+    pub async fn dispatch(
+        &self,
+        req: &mut ReqwestMsg,
+        states: &mut InnerStates,
+    ) -> Result<ReqwestMsg> {
+        let resource_id = req.resource_id();
+        match self.handler_map.get(&resource_id) {
+            Some(handler) => handler.run(req, states).await,
+            None => Err(anyhow!(UnsupportedResource(resource_id))),
+        }
+    }
+}
+
 #[async_trait]
 pub trait NewReqwestConnectionHandler: Send + Sync + 'static {
     async fn handle(
@@ -215,10 +376,23 @@ pub struct Reqwest {
     sender_task: Option<BoxFuture<'static, Result<()>>>,
     resp_receiver: Arc<ResponsePlaceholder>,
     load_counter: Arc<AtomicU64>,
+    /// counted in `load_counter` until the response arrives or the request is dropped.
+    in_flight: bool,
+    /// the number of requests in flight when this one was turned down.
+    rejected: Option<u64>,
 }
 
 impl Unpin for Reqwest {}
 
+impl Drop for Reqwest {
+    fn drop(&mut self) {
+        // a request given up by the caller, such as on timeout, should not hold a slot forever.
+        if self.in_flight {
+            self.load_counter.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+}
+
 impl Future for Reqwest {
     type Output = Result<ReqwestMsg>;
 
@@ -229,6 +403,9 @@ impl Future for Reqwest {
     ///
     /// and we also set a timeout notification, if the request is not responded in some mill-seconds.
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Some(in_flight) = self.rejected {
+            return Poll::Ready(Err(anyhow!(ClientError::Overloaded(in_flight))));
+        }
         if !self.sender_task_done {
             match self.sender_task.as_mut() {
                 Some(task) => {
@@ -270,7 +447,10 @@ impl Future for Reqwest {
         }
         match self.resp_receiver.get() {
             Some(resp) => {
-                self.load_counter.fetch_sub(1, Ordering::AcqRel);
+                if self.in_flight {
+                    self.in_flight = false;
+                    self.load_counter.fetch_sub(1, Ordering::AcqRel);
+                }
                 Poll::Ready(resp)
             }
             None => Poll::Pending,
@@ -283,6 +463,8 @@ pub struct ReqwestOperatorManager {
     pub(self) req_id: AtomicU64,
     pub(self) load_list: UnsafeCell<Vec<Arc<AtomicU64>>>,
     pub(self) operator_list: UnsafeCell<Vec<ReqwestOperator>>,
+    /// `None` for no limit.
+    pub(self) max_in_flight: Option<u64>,
 }
 
 unsafe impl Send for ReqwestOperatorManager {}
@@ -295,6 +477,7 @@ impl ReqwestOperatorManager {
             req_id: AtomicU64::new(0),
             load_list: UnsafeCell::new(Vec::new()),
             operator_list: UnsafeCell::new(Vec::new()),
+            max_in_flight: None,
         }
     }
 
@@ -308,6 +491,7 @@ impl ReqwestOperatorManager {
             req_id: AtomicU64::new(0),
             load_list: UnsafeCell::new(load_list),
             operator_list: UnsafeCell::new(operator_list),
+            max_in_flight: None,
         }
     }
 
@@ -318,6 +502,27 @@ impl ReqwestOperatorManager {
         load_list.push(Arc::new(AtomicU64::new(0)));
     }
 
+    /// bound the requests waiting for responses, the calls beyond it fail with
+    /// [`ClientError::Overloaded`] at once instead of queuing up behind a slow peer.
+    ///
+    /// the bound is checked without a lock, so concurrent calls may exceed it slightly.
+    pub fn set_max_in_flight(&mut self, max_in_flight: u64) {
+        self.max_in_flight = Some(max_in_flight);
+    }
+
+    pub fn in_flight(&self) -> u64 {
+        unsafe { &*self.load_list.get() }
+            .iter()
+            .map(|load| load.load(Ordering::Acquire))
+            .sum()
+    }
+
+    /// the streams the calls are spread over, they are all opened when the client builds, so
+    /// no call waits for a stream to be opened.
+    pub fn stream_count(&self) -> usize {
+        unsafe { &*self.operator_list.get() }.len()
+    }
+
     pub fn call(&self, mut req: ReqwestMsg) -> Reqwest {
         let mut min_index = 0;
         let mut min_load = u64::MAX;
@@ -328,7 +533,20 @@ impl ReqwestOperatorManager {
                 min_index = i;
             }
         }
-        (unsafe { &*self.load_list.get() })[min_index].fetch_add(1, Ordering::AcqRel);
+        let rejected = match self.max_in_flight {
+            Some(max_in_flight) => {
+                let in_flight = self.in_flight();
+                if in_flight >= max_in_flight {
+                    Some(in_flight)
+                } else {
+                    None
+                }
+            }
+            None => None,
+        };
+        if rejected.is_none() {
+            (unsafe { &*self.load_list.get() })[min_index].fetch_add(1, Ordering::AcqRel);
+        }
         let req_id = self.req_id.fetch_add(1, Ordering::AcqRel);
         let operator = &(unsafe { &*self.operator_list.get() })[min_index];
         let req_sender = operator.1.clone();
@@ -343,6 +561,8 @@ impl ReqwestOperatorManager {
             sender_task_done: false,
             operator_sender: Some(req_sender),
             load_counter: (unsafe { &*self.load_list.get() })[min_index].clone(),
+            in_flight: rejected.is_none(),
+            rejected,
         }
     }
 }
@@ -357,6 +577,88 @@ impl GenericParameter for ReqwestOperatorManager {
     }
 }
 
+/// waiters for the acks of msgs sent, keyed by the timestamp of the msg.
+///
+/// the server addresses an ack back to the sender with the seq_num it assigned, see
+/// [`Msg::generate_server_ack`], so only the client timestamp it echoes, see
+/// [`Msg::acked_timestamp`], tells which msg is acknowledged. msgs waited for at the same time
+/// should have distinct timestamps.
+#[derive(Clone, Default)]
+pub struct AckTracker {
+    waiter_id: Arc<AtomicU64>,
+    pending: Arc<DashMap<u64, (u64, oneshot::Sender<Arc<Msg>>)>>,
+}
+
+/// removes the waiter when its future is done or dropped, unless a newer one took the key.
+pub(self) struct AckWaiterGuard {
+    key: u64,
+    waiter_id: u64,
+    pending: Arc<DashMap<u64, (u64, oneshot::Sender<Arc<Msg>>)>>,
+}
+
+impl Drop for AckWaiterGuard {
+    fn drop(&mut self) {
+        self.pending
+            .remove_if(&self.key, |_, (waiter_id, _)| *waiter_id == self.waiter_id);
+    }
+}
+
+impl AckTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// the waiter is registered at once, so call it before sending `msg` and await it after,
+    /// or an ack arriving in between is missed.
+    ///
+    /// a later waiter for the same key replaces this one, which fails with
+    /// [`ClientError::StreamClosed`].
+    pub fn await_ack(
+        &self,
+        msg: &Msg,
+        timeout: Duration,
+    ) -> impl Future<Output = std::result::Result<Arc<Msg>, ClientError>> {
+        let key = msg.timestamp();
+        let waiter_id = self.waiter_id.fetch_add(1, Ordering::AcqRel);
+        let (sender, receiver) = oneshot::channel();
+        self.pending.insert(key, (waiter_id, sender));
+        let guard = AckWaiterGuard {
+            key,
+            waiter_id,
+            pending: self.pending.clone(),
+        };
+        async move {
+            let res = tokio::time::timeout(timeout, receiver).await;
+            drop(guard);
+            match res {
+                Ok(Ok(ack)) => Ok(ack),
+                Ok(Err(_)) => Err(ClientError::StreamClosed("ack waiter replaced".to_string())),
+                Err(_) => Err(ClientError::Timeout(timeout)),
+            }
+        }
+    }
+
+    /// wake the waiter matching `ack`, return false if nobody waits for it.
+    pub fn ack(&self, ack: Arc<Msg>) -> bool {
+        let key = match ack.acked_timestamp() {
+            Some(key) => key,
+            None => return false,
+        };
+        match self.pending.remove(&key) {
+            Some((_, (_, sender))) => sender.send(ack).is_ok(),
+            None => false,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
 pub(self) fn crushed_log(list: Vec<Arc<Msg>>, node_id: u32) {
     std::fs::create_dir_all("./crushed_log").unwrap();
     let mut file = std::fs::File::create(format!(
@@ -419,6 +721,28 @@ impl MsgSender {
         Ok(())
     }
 
+    /// true once the stream behind this sender has gone.
+    pub fn is_closed(&self) -> bool {
+        match self {
+            MsgSender::Client(sender) => sender.is_closed(),
+            MsgSender::Server(sender) => sender.is_closed(),
+        }
+    }
+
+    pub async fn send_many(&self, msgs: Vec<Arc<Msg>>) -> Result<()> {
+        match self {
+            MsgSender::Client(sender) => {
+                sender.send_many(msgs).await?;
+            }
+            MsgSender::Server(sender) => {
+                for msg in msgs {
+                    sender.send(msg).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
     pub fn close(self) {
         match self {
             MsgSender::Client(sender) => {
@@ -431,6 +755,95 @@ impl MsgSender {
     }
 }
 
+/// a [`MsgSender`] of a client connection, which asserts in debug builds that the content msgs
+/// from the same sender carry strictly increasing seqnums, to catch seqnum allocation bugs early.
+///
+/// msgs replayed on purpose, such as the offline ones, should not be sent through it.
+/// the check is compiled out in release builds.
+#[derive(Clone)]
+pub struct SeqnumCheckedSender {
+    sender: MsgSender,
+    #[cfg(debug_assertions)]
+    last_seqnums: Arc<std::sync::Mutex<AHashMap<u64, u64>>>,
+}
+
+impl SeqnumCheckedSender {
+    pub fn new(sender: MsgSender) -> Self {
+        Self {
+            sender,
+            #[cfg(debug_assertions)]
+            last_seqnums: Arc::new(std::sync::Mutex::new(AHashMap::new())),
+        }
+    }
+
+    pub async fn send(&self, msg: Arc<Msg>) -> Result<()> {
+        #[cfg(debug_assertions)]
+        self.check_seqnum(&msg);
+        self.sender.send(msg).await
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.sender.is_closed()
+    }
+
+    pub async fn send_many(&self, msgs: Vec<Arc<Msg>>) -> Result<()> {
+        #[cfg(debug_assertions)]
+        for msg in msgs.iter() {
+            self.check_seqnum(msg);
+        }
+        self.sender.send_many(msgs).await
+    }
+
+    pub fn close(self) {
+        self.sender.close()
+    }
+
+    pub fn inner(&self) -> &MsgSender {
+        &self.sender
+    }
+
+    #[cfg(debug_assertions)]
+    fn check_seqnum(&self, msg: &Msg) {
+        let seqnum = msg.seqnum();
+        if !msg.typ().is_content() || seqnum == 0 {
+            return;
+        }
+        let last = self
+            .last_seqnums
+            .lock()
+            .unwrap()
+            .insert(msg.sender(), seqnum);
+        if let Some(last) = last {
+            if seqnum <= last {
+                error!(
+                    "seqnum from {} not increasing: {} after {}.",
+                    msg.sender(),
+                    seqnum,
+                    last
+                );
+                panic!(
+                    "seqnum from {} not increasing: {} after {}.",
+                    msg.sender(),
+                    seqnum,
+                    last
+                );
+            }
+        }
+    }
+}
+
+/// log how the stream ended, the orderly ends apart from the failures, and carry it as the
+/// context of the error, so the recv loops can tell it by `downcast_ref::<StreamEnd>()`.
+pub(self) fn stream_end_error(e: &ReadExactError) -> anyhow::Error {
+    let end = StreamEnd::from(e);
+    if end.is_orderly() {
+        info!("stream ended: {}.", end);
+    } else {
+        warn!("stream ended: {}, {}.", end, e);
+    }
+    anyhow!(CrashError::ShouldCrash(end.to_string())).context(end)
+}
+
 /// read bytes from stream, if external_source is not None, read from external_source first,
 /// and return the rest of external_source if remained.
 #[inline(always)]
@@ -448,20 +861,7 @@ pub(self) async fn read_buffer<'a>(
                     .await
                 {
                     Ok(_) => Ok(None),
-                    Err(e) => match e {
-                        ReadExactError::FinishedEarly => {
-                            info!("stream finished.");
-                            Err(anyhow!(CrashError::ShouldCrash(
-                                "stream finished.".to_string()
-                            )))
-                        }
-                        ReadExactError::ReadError(e) => {
-                            debug!("read stream error: {:?}", e);
-                            Err(anyhow!(CrashError::ShouldCrash(
-                                "read stream error.".to_string()
-                            )))
-                        }
-                    },
+                    Err(e) => Err(stream_end_error(&e)),
                 }
             } else {
                 buffer.copy_from_slice(&external_source[0..buffer.len()]);
@@ -470,26 +870,37 @@ pub(self) async fn read_buffer<'a>(
         }
         None => match recv_stream.read_exact(buffer).await {
             Ok(_) => Ok(None),
-            Err(e) => match e {
-                ReadExactError::FinishedEarly => {
-                    info!("stream finished.");
-                    Err(anyhow!(CrashError::ShouldCrash(
-                        "stream finished.".to_string()
-                    )))
-                }
-                ReadExactError::ReadError(e) => {
-                    debug!("read stream error: {:?}", e);
-                    Err(anyhow!(CrashError::ShouldCrash(
-                        "read stream error.".to_string()
-                    )))
-                }
-            },
+            Err(e) => Err(stream_end_error(&e)),
         },
     }
 }
 
 pub(self) struct MsgIOUtil;
 
+/// replace the msg with a payload compressed copy if its payload is long enough and compressible.
+#[inline]
+pub(self) fn compress_msg(msg: Arc<Msg>, compress_threshold: Option<usize>) -> Arc<Msg> {
+    match compress_threshold.and_then(|threshold| msg.compress_payload(threshold)) {
+        Some(compressed) => Arc::new(compressed),
+        None => msg,
+    }
+}
+
+/// msgs with a broken compressed payload are dropped.
+#[inline]
+pub(self) fn decompress_msg(msg: Arc<Msg>) -> Option<Arc<Msg>> {
+    if !msg.is_compressed() {
+        return Some(msg);
+    }
+    match msg.decompress_payload() {
+        Ok(msg) => Some(Arc::new(msg)),
+        Err(e) => {
+            error!("decompress msg error: {}", e);
+            None
+        }
+    }
+}
+
 impl MsgIOUtil {
     /// the only error returned should cause the stream crashed.
     ///
@@ -499,6 +910,7 @@ impl MsgIOUtil {
         buffer: &mut Box<[u8; HEAD_LEN]>,
         recv_stream: &mut RecvStream,
         mut external_source: Option<&'a [u8]>,
+        max_payload_bytes: usize,
     ) -> Result<Arc<Msg>> {
         #[cfg(not(feature = "no-check"))]
         {
@@ -574,15 +986,14 @@ impl MsgIOUtil {
                     }
                 }
                 external.extend_from_slice(&buffer[index..]);
-                let res = MsgIOUtil::recv_msg(buffer, recv_stream, Some(&external)).await;
+                let res =
+                    MsgIOUtil::recv_msg(buffer, recv_stream, Some(&external), max_payload_bytes)
+                        .await;
                 return res;
             }
         }
-        if (Head::extension_length(&buffer[..]) + Head::payload_length(&buffer[..])) > BODY_SIZE {
-            return Err(anyhow!(CrashError::ShouldCrash(
-                "message size too large.".to_string()
-            )));
-        }
+        MsgIOUtil::check_head_version(&buffer[..])?;
+        MsgIOUtil::check_body_size(&buffer[..], max_payload_bytes)?;
         let mut head = Head::from(&buffer[..]);
         let mut msg = Msg::pre_alloc(&mut head);
         match read_buffer(
@@ -617,82 +1028,113 @@ impl MsgIOUtil {
                     }
                 }
                 external.extend_from_slice(&msg.as_slice()[index..]);
-                let res = MsgIOUtil::recv_msg(buffer, recv_stream, Some(&external)).await;
+                let res =
+                    MsgIOUtil::recv_msg(buffer, recv_stream, Some(&external), max_payload_bytes)
+                        .await;
                 return res;
             }
         }
+        record_payload(msg.typ(), msg.payload_length());
         Ok(Arc::new(msg))
     }
 
-    #[inline(always)]
-    pub(self) async fn recv_msgs(
-        buffer: &mut Box<[u8; HEAD_LEN]>,
-        recv_stream: &mut ReadHalf<tls_server::TlsStream<TcpStream>>,
-    ) -> Result<Arc<Msg>> {
-        match recv_stream.read_exact(&mut buffer[..]).await {
-            Ok(_) => {}
-            Err(_) => {
-                return Err(anyhow!(CrashError::ShouldCrash(
-                    "read stream error.".to_string()
-                )));
-            }
+    /// a head of unknown version can't be told where its body ends, so the stream can't go on.
+    #[inline]
+    pub(self) fn check_head_version(head: &[u8]) -> Result<()> {
+        if let Err(e) = Head::head_len(head) {
+            return Err(anyhow!(CrashError::ShouldCrash(e.to_string())));
         }
-        let mut head = Head::from(&buffer[..]);
-        if (Head::extension_length(&buffer[..]) + Head::payload_length(&buffer[..])) > BODY_SIZE {
+        Ok(())
+    }
+
+    /// reject a head declaring a body larger than allowed, before any allocation for it.
+    #[inline]
+    pub(self) fn check_body_size(head: &[u8], max_payload_bytes: usize) -> Result<()> {
+        let payload_length = Head::payload_length(head);
+        if payload_length > max_payload_bytes
+            || Head::extension_length(head) + payload_length > BODY_SIZE
+        {
             return Err(anyhow!(CrashError::ShouldCrash(
                 "message size too large.".to_string()
             )));
         }
-        let mut msg = Msg::pre_alloc(&mut head);
-        match recv_stream
-            .read_exact(&mut (msg.as_mut_slice()[HEAD_LEN..]))
-            .await
-        {
-            Ok(_) => {}
-            Err(_) => {
-                return Err(anyhow!(CrashError::ShouldCrash(
-                    "read stream error.".to_string()
-                )));
+        Ok(())
+    }
+
+    /// fill `buffer` unless the stream ends first, return how many bytes are read.
+    ///
+    /// a tcp stream may return any part of what was written in one read, so it's read until full.
+    pub(self) async fn read_full<R: AsyncRead + Unpin>(
+        recv_stream: &mut R,
+        buffer: &mut [u8],
+    ) -> Result<usize> {
+        let mut filled = 0;
+        while filled < buffer.len() {
+            match recv_stream.read(&mut buffer[filled..]).await {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    debug!("read stream error: {:?}", e);
+                    return Err(anyhow!(CrashError::ShouldCrash(
+                        "read stream error.".to_string()
+                    )));
+                }
             }
         }
-        debug!("read msg: {}", msg);
-        Ok(Arc::new(msg))
+        Ok(filled)
     }
 
-    #[allow(unused)]
-    #[inline]
-    pub(self) async fn recv_msgc(
+    /// read a msg from a tls stream, `None` means the peer has closed the stream.
+    ///
+    /// a msg cut by the close is dropped, the peer is gone with it anyway.
+    pub(self) async fn recv_framed<R: AsyncRead + Unpin>(
         buffer: &mut Box<[u8; HEAD_LEN]>,
-        recv_stream: &mut ReadHalf<tls_client::TlsStream<TcpStream>>,
-    ) -> Result<Arc<Msg>> {
-        match recv_stream.read_exact(&mut buffer[..]).await {
-            Ok(_) => {}
-            Err(e) => {
-                return Err(anyhow!(CrashError::ShouldCrash(
-                    "read stream error.".to_string()
-                )));
+        recv_stream: &mut R,
+        max_payload_bytes: usize,
+    ) -> Result<Option<Arc<Msg>>> {
+        let n = MsgIOUtil::read_full(recv_stream, &mut buffer[..]).await?;
+        if n < HEAD_LEN {
+            if n > 0 {
+                debug!("stream closed with a partial head of {} bytes.", n);
             }
+            return Ok(None);
         }
         let mut head = Head::from(&buffer[..]);
-        if (Head::extension_length(&buffer[..]) + Head::payload_length(&buffer[..])) > BODY_SIZE {
-            return Err(anyhow!(CrashError::ShouldCrash(
-                "message size too large.".to_string()
-            )));
-        }
+        MsgIOUtil::check_head_version(&buffer[..])?;
+        MsgIOUtil::check_body_size(&buffer[..], max_payload_bytes)?;
         let mut msg = Msg::pre_alloc(&mut head);
-        match recv_stream
-            .read_exact(&mut (msg.as_mut_slice()[HEAD_LEN..]))
-            .await
-        {
-            Ok(_) => {}
-            Err(e) => {
-                return Err(anyhow!(CrashError::ShouldCrash(
-                    "read stream error.".to_string()
-                )));
-            }
+        let body = &mut msg.as_mut_slice()[HEAD_LEN..];
+        let n = MsgIOUtil::read_full(recv_stream, body).await?;
+        if n < body.len() {
+            debug!(
+                "stream closed with a partial body of {}/{} bytes.",
+                n,
+                body.len()
+            );
+            return Ok(None);
         }
         debug!("read msg: {}", msg);
-        Ok(Arc::new(msg))
+        record_payload(msg.typ(), msg.payload_length());
+        Ok(Some(Arc::new(msg)))
+    }
+
+    #[inline(always)]
+    pub(self) async fn recv_msgs(
+        buffer: &mut Box<[u8; HEAD_LEN]>,
+        recv_stream: &mut ReadHalf<tls_server::TlsStream<TcpStream>>,
+        max_payload_bytes: usize,
+    ) -> Result<Option<Arc<Msg>>> {
+        MsgIOUtil::recv_framed(buffer, recv_stream, max_payload_bytes).await
+    }
+
+    #[inline]
+    pub(self) async fn recv_msgc(
+        buffer: &mut Box<[u8; HEAD_LEN]>,
+        recv_stream: &mut ReadHalf<tls_client::TlsStream<TcpStream>>,
+        max_payload_bytes: usize,
+    ) -> Result<Option<Arc<Msg>>> {
+        MsgIOUtil::recv_framed(buffer, recv_stream, max_payload_bytes).await
     }
 
     /// the only error returned should cause the stream crashed.
@@ -763,6 +1205,10 @@ impl MsgIOUtil {
 pub struct MsgIOWrapper {
     pub(self) send_channel: Option<MsgMpscSender>,
     pub(self) recv_channel: Option<MsgMpscReceiver>,
+    /// resolved once the send channel is closed and all msgs are acknowledged by peer.
+    pub(self) flushed: Option<oneshot::Receiver<()>>,
+    /// resolved with why the stream stopped receiving, dropped if it's the handler that stopped.
+    pub(self) ended: Option<oneshot::Receiver<StreamEnd>>,
 }
 
 impl MsgIOWrapper {
@@ -770,26 +1216,32 @@ impl MsgIOWrapper {
         mut send_stream: SendStream,
         mut recv_stream: RecvStream,
         node_id: u32,
+        max_payload_bytes: usize,
+        compress_threshold: Option<usize>,
     ) -> Self {
         // actually channel buffer size set to 1 is more intuitive.
         let (send_sender, mut send_receiver): (MsgMpscSender, MsgMpscReceiver) =
             mpsc::channel(16384);
         let (recv_sender, recv_receiver): (MsgMpscSender, MsgMpscReceiver) = mpsc::channel(16284);
+        let (flushed_sender, flushed) = oneshot::channel();
+        let (ended_sender, ended) = oneshot::channel();
         tokio::spawn(async move {
             let task1 = async {
+                let _flushed_sender = flushed_sender;
                 loop {
                     match send_receiver.recv().await {
                         Some(msg) => {
+                            let msg = compress_msg(msg, compress_threshold);
                             // if there are more msgs in the channel buffer, try to compress them for send.
                             // which will reduce the network traffic.
                             if let Ok(next) = send_receiver.try_recv() {
                                 let mut list = vec![];
                                 list.push(msg);
-                                list.push(next);
+                                list.push(compress_msg(next, compress_threshold));
                                 loop {
                                     match send_receiver.try_recv() {
                                         Ok(msg) => {
-                                            list.push(msg);
+                                            list.push(compress_msg(msg, compress_threshold));
                                         }
                                         Err(_e) => {
                                             break;
@@ -851,6 +1303,7 @@ impl MsgIOWrapper {
                             }
                         }
                         None => {
+                            _ = send_stream.finish().await;
                             break;
                         }
                     }
@@ -861,16 +1314,25 @@ impl MsgIOWrapper {
             let task2 = async {
                 let mut buffer = Box::new([0u8; HEAD_LEN]);
                 loop {
-                    match MsgIOUtil::recv_msg(&mut buffer, &mut recv_stream, None).await {
+                    match MsgIOUtil::recv_msg(
+                        &mut buffer,
+                        &mut recv_stream,
+                        None,
+                        max_payload_bytes,
+                    )
+                    .await
+                    {
                         Ok(msg) => {
                             if msg.typ() == Type::Compressed {
-                                for msg in msg.with_compressed().iter() {
-                                    if let Err(e) = recv_sender.send(msg.clone()).await {
-                                        error!("send msg error: {:?}", e);
-                                        break;
+                                for msg in msg.with_compressed().into_iter() {
+                                    if let Some(msg) = decompress_msg(msg) {
+                                        if let Err(e) = recv_sender.send(msg).await {
+                                            error!("send msg error: {:?}", e);
+                                            break;
+                                        }
                                     }
                                 }
-                            } else {
+                            } else if let Some(msg) = decompress_msg(msg) {
                                 if let Err(e) = recv_sender.send(msg).await {
                                     error!("send msg error: {:?}", e);
                                     break;
@@ -879,6 +1341,12 @@ impl MsgIOWrapper {
                         }
                         Err(e) => {
                             debug!("recv msg error {}.", e);
+                            // a msg too long or broken ends it as well.
+                            let end = e
+                                .downcast_ref::<StreamEnd>()
+                                .copied()
+                                .unwrap_or(StreamEnd::Failed);
+                            _ = ended_sender.send(end);
                             // try to notice receiver to stop.
                             drop(recv_sender);
                             break;
@@ -923,6 +1391,8 @@ impl MsgIOWrapper {
         Self {
             send_channel: Some(send_sender),
             recv_channel: Some(recv_receiver),
+            flushed: Some(flushed),
+            ended: Some(ended),
         }
     }
 
@@ -931,6 +1401,137 @@ impl MsgIOWrapper {
         let recv = self.recv_channel.take().unwrap();
         (send, recv)
     }
+
+    /// close the receive channel once `shutdown` is cancelled, so the handler can finish the msgs
+    /// already delivered and exit, while the send channel keeps working for the replies.
+    pub(self) fn stop_recv_on(&mut self, shutdown: CancellationToken) {
+        let mut recv = self.recv_channel.take().unwrap();
+        let (relay_sender, relay_receiver) = mpsc::channel(16);
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    biased;
+                    _ = shutdown.cancelled() => break,
+                    msg = recv.recv() => match msg {
+                        Some(msg) => {
+                            if relay_sender.send(msg).await.is_err() {
+                                break;
+                            }
+                        }
+                        None => break,
+                    },
+                }
+            }
+        });
+        self.recv_channel = Some(relay_receiver);
+    }
+
+    /// ask the peer to pause sending once `high_water` msgs wait for the handler, and to resume
+    /// once they drop to `low_water`, see [`Msg::flow_control`].
+    pub(self) fn signal_flow_on(&mut self, high_water: usize, low_water: usize) {
+        let recv = self.recv_channel.take().unwrap();
+        // a strong one would keep the send channel open after the handler is gone.
+        let signal_sender = self.send_channel.as_ref().unwrap().downgrade();
+        self.recv_channel = Some(flow_relay(recv, signal_sender, high_water, low_water));
+    }
+}
+
+/// how often a paused stream looks at the handler's progress.
+pub(self) const FLOW_CONTROL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// relay msgs from `recv` to the returned receiver, and send the flow control signals through
+/// `signal_sender` as the msgs waiting in between cross the water marks.
+pub(self) fn flow_relay(
+    mut recv: MsgMpscReceiver,
+    signal_sender: mpsc::WeakSender<Arc<Msg>>,
+    high_water: usize,
+    low_water: usize,
+) -> MsgMpscReceiver {
+    let (relay_sender, relay_receiver) = mpsc::channel(16384.max(high_water * 2));
+    tokio::spawn(async move {
+        let mut paused = false;
+        let mut ticker = tokio::time::interval(FLOW_CONTROL_INTERVAL);
+        loop {
+            tokio::select! {
+                msg = recv.recv() => match msg {
+                    Some(msg) => {
+                        if relay_sender.send(msg).await.is_err() {
+                            break;
+                        }
+                    }
+                    None => break,
+                },
+                _ = ticker.tick(), if paused => {},
+            }
+            let waiting = relay_sender.max_capacity() - relay_sender.capacity();
+            let pause = if !paused && waiting >= high_water {
+                true
+            } else if paused && waiting <= low_water {
+                false
+            } else {
+                continue;
+            };
+            let signal_sender = match signal_sender.upgrade() {
+                Some(signal_sender) => signal_sender,
+                None => break,
+            };
+            // tried again on the next msg or tick if the send channel is full.
+            if signal_sender
+                .try_send(Arc::new(Msg::flow_control(pause)))
+                .is_ok()
+            {
+                debug!("flow control: {}.", if pause { "pause" } else { "resume" });
+                paused = pause;
+            }
+        }
+    });
+    relay_receiver
+}
+
+/// the send-only counterpart of [`MsgIOWrapper`] over a uni stream, for one-way notifications.
+pub(self) struct MsgIOWrapperUni {
+    pub(self) send_channel: Option<MsgMpscSender>,
+}
+
+impl MsgIOWrapperUni {
+    pub(self) fn new(
+        mut send_stream: SendStream,
+        node_id: u32,
+        compress_threshold: Option<usize>,
+    ) -> Self {
+        let (send_sender, mut send_receiver): (MsgMpscSender, MsgMpscReceiver) =
+            mpsc::channel(16384);
+        tokio::spawn(async move {
+            loop {
+                match send_receiver.recv().await {
+                    Some(msg) => {
+                        let msg = compress_msg(msg, compress_threshold);
+                        if let Err(e) = MsgIOUtil::send_msg(msg, &mut send_stream).await {
+                            error!("send msg error: {:?}", e);
+                            send_receiver.close();
+                            let mut list = Vec::new();
+                            while let Ok(msg) = send_receiver.try_recv() {
+                                list.push(msg);
+                            }
+                            crushed_log(list, node_id);
+                            break;
+                        }
+                    }
+                    None => {
+                        _ = send_stream.finish().await;
+                        break;
+                    }
+                }
+            }
+        });
+        Self {
+            send_channel: Some(send_sender),
+        }
+    }
+
+    pub(self) fn channel(&mut self) -> MsgMpscSender {
+        self.send_channel.take().unwrap()
+    }
 }
 
 pub struct MsgIOWrapperTcpS {
@@ -943,6 +1544,7 @@ impl MsgIOWrapperTcpS {
         stream: tls_server::TlsStream<TcpStream>,
         idle_timeout: Duration,
         node_id: u32,
+        max_payload_bytes: usize,
     ) -> Self {
         let (send_sender, mut send_receiver): (MsgMpscSender, MsgMpscReceiver) =
             mpsc::channel(16384);
@@ -999,8 +1601,10 @@ impl MsgIOWrapperTcpS {
 
             let task2 = async {
                 loop {
-                    match MsgIOUtil::recv_msgs(&mut buffer, &mut recv_stream).await {
-                        Ok(msg) => {
+                    match MsgIOUtil::recv_msgs(&mut buffer, &mut recv_stream, max_payload_bytes)
+                        .await
+                    {
+                        Ok(Some(msg)) => {
                             timer_setter
                                 .set(tokio::time::Instant::now() + idle_timeout)
                                 .await;
@@ -1013,6 +1617,11 @@ impl MsgIOWrapperTcpS {
                                 break;
                             }
                         }
+                        Ok(None) => {
+                            debug!("stream closed by peer.");
+                            drop(recv_sender);
+                            break;
+                        }
                         Err(e) => {
                             debug!("recv msg error {}.", e);
                             drop(recv_sender);
@@ -1050,6 +1659,138 @@ impl MsgIOWrapperTcpS {
     }
 }
 
+/// the last time in milliseconds a msg was sent or received on a connection, pings and pongs
+/// included.
+pub struct ConnectionActivity {
+    last_send: AtomicU64,
+    last_recv: AtomicU64,
+}
+
+impl ConnectionActivity {
+    /// a new connection counts as active in both directions.
+    pub fn new() -> Self {
+        let now = timestamp();
+        Self {
+            last_send: AtomicU64::new(now),
+            last_recv: AtomicU64::new(now),
+        }
+    }
+
+    #[inline]
+    pub fn record_send(&self) {
+        self.last_send.store(timestamp(), Ordering::Release);
+    }
+
+    #[inline]
+    pub fn record_recv(&self) {
+        self.last_recv.store(timestamp(), Ordering::Release);
+    }
+
+    #[inline]
+    pub fn last_send(&self) -> u64 {
+        self.last_send.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    pub fn last_recv(&self) -> u64 {
+        self.last_recv.load(Ordering::Acquire)
+    }
+
+    /// nothing has been received for longer than `threshold`. the peer answers every ping, so
+    /// with keep alive on, a silent connection is likely dead even if sending still succeeds.
+    pub fn is_stale(&self, threshold: Duration) -> bool {
+        timestamp().saturating_sub(self.last_recv()) > threshold.as_millis() as u64
+    }
+}
+
+impl Default for ConnectionActivity {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// sent on the timeout channel of a client connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutEvent {
+    /// nothing received within the stale window since `last_recv` in milliseconds, reported
+    /// once until something is received again.
+    Stale { last_recv: u64 },
+    /// a stream has stopped receiving, see [`StreamEnd::is_orderly`] to tell whether the peer
+    /// meant it.
+    Ended(StreamEnd),
+}
+
+/// why a quic stream stopped receiving.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamEnd {
+    /// the peer finished the stream.
+    Finished,
+    /// the connection was closed by the application of the peer, with its error code.
+    ApplicationClosed(u64),
+    /// the connection was closed by the quic layer of the peer, e.g. for a protocol violation.
+    ConnectionClosed,
+    /// the stream or the connection was reset.
+    Reset,
+    /// nothing was heard from the peer within the idle timeout.
+    TimedOut,
+    /// the connection was closed by this side.
+    LocallyClosed,
+    /// the others, not expected from a peer speaking the same protocol.
+    Failed,
+}
+
+impl StreamEnd {
+    /// ended on purpose rather than by the network.
+    pub fn is_orderly(&self) -> bool {
+        matches!(
+            self,
+            StreamEnd::Finished | StreamEnd::ApplicationClosed(_) | StreamEnd::LocallyClosed
+        )
+    }
+}
+
+impl std::fmt::Display for StreamEnd {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StreamEnd::Finished => write!(f, "finished by peer"),
+            StreamEnd::ApplicationClosed(code) => write!(f, "application closed with {}", code),
+            StreamEnd::ConnectionClosed => write!(f, "connection closed by quic"),
+            StreamEnd::Reset => write!(f, "reset"),
+            StreamEnd::TimedOut => write!(f, "connection timed out"),
+            StreamEnd::LocallyClosed => write!(f, "closed locally"),
+            StreamEnd::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+impl From<&ConnectionError> for StreamEnd {
+    fn from(e: &ConnectionError) -> Self {
+        match e {
+            ConnectionError::ApplicationClosed(close) => {
+                StreamEnd::ApplicationClosed(close.error_code.into_inner())
+            }
+            ConnectionError::ConnectionClosed(_) => StreamEnd::ConnectionClosed,
+            ConnectionError::Reset => StreamEnd::Reset,
+            ConnectionError::TimedOut => StreamEnd::TimedOut,
+            ConnectionError::LocallyClosed => StreamEnd::LocallyClosed,
+            ConnectionError::VersionMismatch | ConnectionError::TransportError(_) => {
+                StreamEnd::Failed
+            }
+        }
+    }
+}
+
+impl From<&ReadExactError> for StreamEnd {
+    fn from(e: &ReadExactError) -> Self {
+        match e {
+            ReadExactError::FinishedEarly => StreamEnd::Finished,
+            ReadExactError::ReadError(ReadError::ConnectionLost(e)) => StreamEnd::from(e),
+            ReadExactError::ReadError(ReadError::Reset(_)) => StreamEnd::Reset,
+            ReadExactError::ReadError(_) => StreamEnd::Failed,
+        }
+    }
+}
+
 pub(self) struct MsgIOWrapperTcpC {
     pub(self) send_channel: Option<MsgMpscSender>,
     pub(self) recv_channel: Option<MsgMpscReceiver>,
@@ -1060,12 +1801,18 @@ impl MsgIOWrapperTcpC {
         stream: tls_client::TlsStream<TcpStream>,
         keep_alive_interval: Duration,
         node_id: u32,
+        max_payload_bytes: usize,
+        activity: Arc<ConnectionActivity>,
+        stale_window: Option<(Duration, TimeoutEventSender)>,
     ) -> Self {
         let (send_sender, mut send_receiver): (MsgMpscSender, MsgMpscReceiver) =
             mpsc::channel(16384);
         let (recv_sender, recv_receiver) = mpsc::channel(16384);
         let (mut recv_stream, mut send_stream) = split(stream);
         let tick_sender = send_sender.clone();
+        let watch_sender = send_sender.clone();
+        let send_activity = activity.clone();
+        let recv_activity = activity.clone();
         tokio::spawn(async move {
             let mut ticker = tokio::time::interval(keep_alive_interval);
 
@@ -1092,6 +1839,7 @@ impl MsgIOWrapperTcpC {
                                 crushed_log(list, node_id);
                                 break;
                             }
+                            send_activity.record_send();
                         }
                         None => {
                             break;
@@ -1104,13 +1852,21 @@ impl MsgIOWrapperTcpC {
             let task2 = async move {
                 let mut buffer = Box::new([0u8; HEAD_LEN]);
                 loop {
-                    match MsgIOUtil::recv_msgc(&mut buffer, &mut recv_stream).await {
-                        Ok(msg) => {
+                    match MsgIOUtil::recv_msgc(&mut buffer, &mut recv_stream, max_payload_bytes)
+                        .await
+                    {
+                        Ok(Some(msg)) => {
+                            recv_activity.record_recv();
                             if let Err(e) = recv_sender.send(msg).await {
                                 error!("send msg error: {:?}", e);
                                 break;
                             }
                         }
+                        Ok(None) => {
+                            debug!("stream closed by peer.");
+                            drop(recv_sender);
+                            break;
+                        }
                         Err(e) => {
                             error!("recv msg error {}.", e);
                             drop(recv_sender);
@@ -1133,13 +1889,50 @@ impl MsgIOWrapperTcpC {
             }
             .fuse();
 
-            pin_mut!(task1, task2, task3);
+            let task4 = async move {
+                let (window, timeout_sender) = match stale_window {
+                    Some(stale_window) => stale_window,
+                    None => return,
+                };
+                // checked several times a window, so it's reported no later than a quarter of it.
+                let mut ticker = tokio::time::interval((window / 4).max(Duration::from_millis(10)));
+                let mut reported = false;
+                loop {
+                    ticker.tick().await;
+                    if watch_sender.is_closed() {
+                        break;
+                    }
+                    if !activity.is_stale(window) {
+                        reported = false;
+                    } else if !reported {
+                        reported = true;
+                        let event = TimeoutEvent::Stale {
+                            last_recv: activity.last_recv(),
+                        };
+                        // never fatal to the connection, the caller may poll `is_stale()` instead
+                        // of listening, or fall behind in draining the events.
+                        match timeout_sender.try_send(event) {
+                            Ok(_) => {}
+                            Err(mpsc::error::TrySendError::Full(event)) => {
+                                warn!("timeout channel full, {:?} dropped.", event);
+                            }
+                            Err(mpsc::error::TrySendError::Closed(event)) => {
+                                debug!("timeout channel closed, {:?} dropped.", event);
+                            }
+                        }
+                    }
+                }
+            }
+            .fuse();
+
+            pin_mut!(task1, task2, task3, task4);
 
             loop {
                 select! {
                     _ = task1 => {},
                     _ = task2 => {},
                     _ = task3 => {},
+                    _ = task4 => {},
                     complete => {
                         break;
                     }
@@ -1192,6 +1985,7 @@ impl ReqwestMsgIOUtil {
     pub(self) async fn recv_msg<'a: 'async_recursion, 'b: 'async_recursion>(
         recv_stream: &mut RecvStream,
         #[allow(unused_variables)] mut external_source: Option<&'b [u8]>,
+        max_reqwest_bytes: usize,
     ) -> Result<ReqwestMsg> {
         #[cfg(not(feature = "no-check"))]
         {
@@ -1254,6 +2048,7 @@ impl ReqwestMsgIOUtil {
             }
         };
         let len = BigEndian::read_u16(&len_buf[..]);
+        check_reqwest_length(len, max_reqwest_bytes)?;
         let mut msg = ReqwestMsg::pre_alloc(len);
         match read_buffer(recv_stream, external_source, &mut msg.body_mut()).await {
             Ok(_external_source) => {
@@ -1281,7 +2076,9 @@ impl ReqwestMsgIOUtil {
                     }
                 }
                 external.extend_from_slice(&msg.as_slice()[index..]);
-                let res = ReqwestMsgIOUtil::recv_msg(recv_stream, Some(&external)).await;
+                let res =
+                    ReqwestMsgIOUtil::recv_msg(recv_stream, Some(&external), max_reqwest_bytes)
+                        .await;
                 return res;
             }
         }
@@ -1320,9 +2117,10 @@ impl ReqwestMsgIOUtil {
         Ok(())
     }
 
-    #[inline(always)]
-    pub(self) async fn recv_msgc(
-        recv_stream: &mut ReadHalf<tls_client::TlsStream<TcpStream>>,
+    /// read a reqwest msg from a tls stream, its length is checked before the body allocated.
+    pub(self) async fn recv_framed<R: AsyncRead + Unpin>(
+        recv_stream: &mut R,
+        max_reqwest_bytes: usize,
     ) -> Result<ReqwestMsg> {
         let mut len_buf: [u8; 2] = [0u8; 2];
         match recv_stream.read_exact(&mut len_buf).await {
@@ -1335,6 +2133,7 @@ impl ReqwestMsgIOUtil {
             }
         };
         let len = BigEndian::read_u16(&len_buf[..]);
+        check_reqwest_length(len, max_reqwest_bytes)?;
         let mut msg = ReqwestMsg::pre_alloc(len);
         match recv_stream.read_exact(&mut msg.body_mut()).await {
             Ok(_) => {}
@@ -1348,32 +2147,19 @@ impl ReqwestMsgIOUtil {
         Ok(msg)
     }
 
+    #[inline(always)]
+    pub(self) async fn recv_msgc(
+        recv_stream: &mut ReadHalf<tls_client::TlsStream<TcpStream>>,
+    ) -> Result<ReqwestMsg> {
+        ReqwestMsgIOUtil::recv_framed(recv_stream, DEFAULT_MAX_REQWEST_BYTES).await
+    }
+
     #[inline(always)]
     pub(self) async fn recv_msgs(
         recv_stream: &mut ReadHalf<tls_server::TlsStream<TcpStream>>,
+        max_reqwest_bytes: usize,
     ) -> Result<ReqwestMsg> {
-        let mut len_buf: [u8; 2] = [0u8; 2];
-        match recv_stream.read_exact(&mut len_buf).await {
-            Ok(_) => {}
-            Err(e) => {
-                error!("read stream error: {:?}", e);
-                return Err(anyhow!(CrashError::ShouldCrash(
-                    "read stream error.".to_string()
-                )));
-            }
-        };
-        let len = BigEndian::read_u16(&len_buf[..]);
-        let mut msg = ReqwestMsg::pre_alloc(len);
-        match recv_stream.read_exact(&mut msg.body_mut()).await {
-            Ok(_) => {}
-            Err(e) => {
-                error!("read stream error: {:?}", e);
-                return Err(anyhow!(CrashError::ShouldCrash(
-                    "read stream error.".to_string()
-                )));
-            }
-        };
-        Ok(msg)
+        ReqwestMsgIOUtil::recv_framed(recv_stream, max_reqwest_bytes).await
     }
 }
 
@@ -1394,7 +2180,13 @@ impl ReqwestMsgIOWrapper {
         tokio::spawn(async move {
             let task1 = async {
                 loop {
-                    match ReqwestMsgIOUtil::recv_msg(&mut recv_stream, None).await {
+                    match ReqwestMsgIOUtil::recv_msg(
+                        &mut recv_stream,
+                        None,
+                        DEFAULT_MAX_REQWEST_BYTES,
+                    )
+                    .await
+                    {
                         Ok(msg) => {
                             if let Err(e) = recv_sender.send(msg).await {
                                 error!("send msg error: {}", e.to_string());
@@ -1465,7 +2257,9 @@ impl ReqwestMsgIOWrapper {
         #[cfg(feature = "no-select")]
         tokio::spawn(async move {
             loop {
-                match ReqwestMsgIOUtil::recv_msg(&mut recv_stream, None).await {
+                match ReqwestMsgIOUtil::recv_msg(&mut recv_stream, None, DEFAULT_MAX_REQWEST_BYTES)
+                    .await
+                {
                     Ok(msg) => {
                         if let Err(e) = recv_sender.send(msg).await {
                             error!("send msg error: {}", e.to_string());
@@ -1594,7 +2388,11 @@ pub struct ReqwestMsgIOWrapperTcpS {
 }
 
 impl ReqwestMsgIOWrapperTcpS {
-    pub fn new(stream: tls_server::TlsStream<TcpStream>, idle_timeout: Duration) -> Self {
+    pub fn new(
+        stream: tls_server::TlsStream<TcpStream>,
+        idle_timeout: Duration,
+        max_reqwest_bytes: usize,
+    ) -> Self {
         let (send_sender, mut send_receiver): (
             mpsc::Sender<ReqwestMsg>,
             mpsc::Receiver<ReqwestMsg>,
@@ -1617,14 +2415,15 @@ impl ReqwestMsgIOWrapperTcpS {
 
             let task1 = async {
                 loop {
-                    match ReqwestMsgIOUtil::recv_msgs(&mut recv_stream).await {
+                    match ReqwestMsgIOUtil::recv_msgs(&mut recv_stream, max_reqwest_bytes).await {
                         Ok(msg) => {
                             let new_timeout = Instant::now() + idle_timeout;
                             timer_setter.set(new_timeout).await;
                             if msg.resource_id() == ReqwestResourceID::Ping {
+                                // the pong carries the health of this node for probes.
                                 let msg = ReqwestMsg::with_resource_id_payload(
                                     ReqwestResourceID::Pong,
-                                    b"",
+                                    &current_health().to_bytes(),
                                 );
                                 _ = send_sender0.send(msg).await;
                                 continue;
@@ -1690,3 +2489,405 @@ impl ReqwestMsgIOWrapperTcpS {
         (send, recv)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use ahash::AHashMap;
+    use anyhow::anyhow;
+    use async_trait::async_trait;
+    use lib::{
+        entity::{Head, Msg, ReqwestMsg, ReqwestResourceID, Type, HEAD_LEN, PAYLOAD_THRESHOLD},
+        error::{ClientError, UnsupportedResource},
+        net::InnerStates,
+        util::histogram::{payload_histogram, PAYLOAD_BUCKETS},
+        Result,
+    };
+
+    use super::{
+        AckTracker, Handler, HandlerList, Interceptor, MsgIOUtil, MsgMpmcSenderExt, MsgSender,
+        ReqwestDispatcher, ReqwestHandler, ReqwestMsgIOUtil, ReqwestOperator,
+        ReqwestOperatorManager, SeqnumCheckedSender, AUTH_HANDLER_PRIORITY,
+    };
+
+    #[tokio::test]
+    async fn test_send_many() {
+        let (sender, receiver) = async_channel::bounded(64);
+        tokio::spawn(async move {
+            for i in 0..100 {
+                let batch = (0..100)
+                    .map(|j| {
+                        let mut msg = Msg::text(1, 2, 0, "batch");
+                        msg.set_seqnum(i * 100 + j);
+                        Arc::new(msg)
+                    })
+                    .collect::<Vec<_>>();
+                sender.send_many(batch).await.unwrap();
+            }
+        });
+        for i in 0..10000 {
+            let msg = receiver.recv().await.unwrap();
+            assert_eq!(msg.seqnum(), i);
+        }
+    }
+
+    struct Recorder {
+        name: &'static str,
+        priority: i32,
+        calls: Arc<Mutex<Vec<&'static str>>>,
+    }
+
+    #[async_trait]
+    impl Handler for Recorder {
+        async fn run(&self, msg: &mut Arc<Msg>, _states: &mut InnerStates) -> Result<Msg> {
+            self.calls.lock().unwrap().push(self.name);
+            Ok((**msg).clone())
+        }
+
+        fn priority(&self) -> i32 {
+            self.priority
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handler_priority() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let recorder = |name, priority| -> Box<dyn Handler> {
+            Box::new(Recorder {
+                name,
+                priority,
+                calls: calls.clone(),
+            })
+        };
+        let handler_list = HandlerList::new(vec![
+            recorder("text", 0),
+            recorder("ack", 0),
+            recorder("auth", AUTH_HANDLER_PRIORITY),
+        ]);
+        let mut msg = Arc::new(Msg::text(1, 2, 0, "hello"));
+        let mut states = InnerStates::new();
+        for handler in handler_list.iter() {
+            handler.run(&mut msg, &mut states).await.unwrap();
+        }
+        assert_eq!(*calls.lock().unwrap(), vec!["auth", "text", "ack"]);
+        assert_eq!(msg.typ(), Type::Text);
+    }
+
+    /// moderation rejecting the text msgs with the word.
+    struct BannedWord {
+        word: &'static str,
+        calls: Arc<Mutex<Vec<&'static str>>>,
+    }
+
+    #[async_trait]
+    impl Interceptor for BannedWord {
+        async fn before(&self, msg: &Msg) -> Result<()> {
+            self.calls.lock().unwrap().push("before");
+            if String::from_utf8_lossy(msg.payload()).contains(self.word) {
+                return Err(anyhow!("banned word"));
+            }
+            Ok(())
+        }
+
+        async fn after(&self, _msg: &Msg, res: &Result<()>) {
+            assert!(res.is_ok());
+            self.calls.lock().unwrap().push("after");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_interceptors() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let handler_list = HandlerList::new(vec![Box::new(Recorder {
+            name: "text",
+            priority: 0,
+            calls: calls.clone(),
+        })])
+        .with_interceptors(vec![Box::new(BannedWord {
+            word: "spam",
+            calls: calls.clone(),
+        })]);
+        let mut states = InnerStates::new();
+        // what handler_func of the message node does with every msg.
+        for text in ["hello", "buy spam now"] {
+            let mut msg = Arc::new(Msg::text(1, 2, 0, text));
+            if handler_list.interceptors().before(&msg).await.is_err() {
+                continue;
+            }
+            let mut res = Ok(());
+            for handler in handler_list.iter() {
+                res = handler.run(&mut msg, &mut states).await.map(|_| ());
+            }
+            handler_list.interceptors().after(&msg, &res).await;
+        }
+        // the second one never reaches the handler.
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec!["before", "text", "after", "before"]
+        );
+    }
+
+    struct Echo;
+
+    #[async_trait]
+    impl ReqwestHandler for Echo {
+        async fn run(&self, req: &mut ReqwestMsg, _states: &mut InnerStates) -> Result<ReqwestMsg> {
+            Ok(ReqwestMsg::with_resource_id_payload(
+                req.resource_id(),
+                req.payload(),
+            ))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reqwest_dispatcher() {
+        let mut handler_map: AHashMap<ReqwestResourceID, Box<dyn ReqwestHandler>> = AHashMap::new();
+        handler_map.insert(ReqwestResourceID::Seqnum, Box::new(Echo));
+        let dispatcher = ReqwestDispatcher::new(Arc::new(handler_map))
+            .with_unsupported(&[ReqwestResourceID::Noop, ReqwestResourceID::Unsupported]);
+        let mut states = InnerStates::new();
+
+        let mut req = ReqwestMsg::with_resource_id_payload(ReqwestResourceID::Seqnum, b"key");
+        let resp = dispatcher.dispatch(&mut req, &mut states).await.unwrap();
+        assert_eq!(resp.payload(), b"key");
+        // marked or not, an id nobody handles gets the same answer.
+        for resource_id in [ReqwestResourceID::Noop, ReqwestResourceID::Metrics] {
+            let mut req = ReqwestMsg::with_resource_id_payload(resource_id, b"");
+            let e = dispatcher
+                .dispatch(&mut req, &mut states)
+                .await
+                .unwrap_err();
+            assert_eq!(
+                e.downcast_ref::<UnsupportedResource>(),
+                Some(&UnsupportedResource(resource_id))
+            );
+        }
+        let unclaimed = dispatcher.unclaimed();
+        assert!(unclaimed.contains(&ReqwestResourceID::Metrics));
+        assert!(!unclaimed.contains(&ReqwestResourceID::Seqnum));
+        assert!(!unclaimed.contains(&ReqwestResourceID::Noop));
+        assert_eq!(unclaimed.len(), ReqwestResourceID::all().count() - 3);
+    }
+
+    #[test]
+    fn test_check_body_size() {
+        let msg = Msg::text(1, 2, 0, "hello");
+        let mut head = msg.as_slice()[..HEAD_LEN].to_vec();
+        assert!(MsgIOUtil::check_body_size(&head, 1024).is_ok());
+        // only the head is given, so a rejection can't come from reading the body.
+        Head::set_payload_length(&mut head, PAYLOAD_THRESHOLD);
+        assert!(MsgIOUtil::check_body_size(&head, 1024).is_err());
+        assert!(MsgIOUtil::check_body_size(&head, PAYLOAD_THRESHOLD).is_ok());
+    }
+
+    #[cfg(debug_assertions)]
+    #[tokio::test]
+    #[should_panic(expected = "not increasing")]
+    async fn test_seqnum_checked_sender() {
+        let (sender, _receiver) = tokio::sync::mpsc::channel(16);
+        let sender = SeqnumCheckedSender::new(MsgSender::Server(sender));
+        let msg = |sender: u64, typ: Type, seqnum: u64| {
+            let mut msg = Msg::text(sender, 10, 0, "seqnum");
+            msg.set_type(typ);
+            msg.set_seqnum(seqnum);
+            Arc::new(msg)
+        };
+        sender.send(msg(1, Type::Text, 5)).await.unwrap();
+        sender.send(msg(1, Type::Text, 6)).await.unwrap();
+        // the senders are tracked apart, and only content msgs are checked.
+        sender.send(msg(2, Type::Text, 1)).await.unwrap();
+        sender.send(msg(1, Type::Edit, 5)).await.unwrap();
+        sender.send(msg(1, Type::Ack, 0)).await.unwrap();
+        sender.send(msg(1, Type::Text, 4)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ack_tracker() {
+        let tracker = AckTracker::new();
+        let msg = Msg::text(1, 2, 0, "hello");
+        let waiter = tracker.await_ack(&msg, Duration::from_millis(100));
+        assert_eq!(tracker.len(), 1);
+        // an ack of another msg is not taken, nor a flow control one.
+        let mut other = msg.clone();
+        other.set_timestamp(msg.timestamp() + 1);
+        assert!(!tracker.ack(Arc::new(other.generate_server_ack(9, other.timestamp(), 8))));
+        assert!(!tracker.ack(Arc::new(Msg::flow_control(true))));
+        // shaped as the server makes it, to the sender, with the seq_num assigned.
+        let ack = msg.generate_server_ack(9, msg.timestamp(), 7);
+        assert_eq!((ack.sender(), ack.receiver()), (9, 1));
+        assert!(tracker.ack(Arc::new(ack)));
+        assert_eq!(waiter.await.unwrap().seqnum(), 7);
+        assert!(tracker.is_empty());
+
+        let waiter = tracker.await_ack(&msg, Duration::from_millis(10));
+        assert!(matches!(waiter.await, Err(ClientError::Timeout(_))));
+        assert!(tracker.is_empty());
+        // a waiter never polled is removed too.
+        drop(tracker.await_ack(&msg, Duration::from_millis(10)));
+        assert!(tracker.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_max_in_flight() {
+        // nobody answers, so every request stays in flight.
+        let (sender, _receiver) = tokio::sync::mpsc::channel(64);
+        let mut manager = ReqwestOperatorManager::new_directly(vec![ReqwestOperator(1, sender)]);
+        manager.set_max_in_flight(2);
+        let req = || ReqwestMsg::with_resource_id_payload(ReqwestResourceID::Seqnum, b"");
+        let first = manager.call(req());
+        let _second = manager.call(req());
+        assert_eq!(manager.in_flight(), 2);
+        let err = manager.call(req()).await.unwrap_err();
+        match err.downcast::<ClientError>() {
+            Ok(ClientError::Overloaded(2)) => {}
+            other => panic!("expected overloaded, got: {:?}", other),
+        }
+        assert_eq!(manager.in_flight(), 2);
+        // a request given up frees its slot.
+        drop(first);
+        assert_eq!(manager.in_flight(), 1);
+        let _third = manager.call(req());
+        assert_eq!(manager.in_flight(), 2);
+    }
+
+    /// yields at most 3 bytes a read, and every other read is pending.
+    struct ChunkedReader {
+        data: Vec<u8>,
+        offset: usize,
+        pending: bool,
+    }
+
+    impl tokio::io::AsyncRead for ChunkedReader {
+        fn poll_read(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            self.pending = !self.pending;
+            if self.pending {
+                cx.waker().wake_by_ref();
+                return std::task::Poll::Pending;
+            }
+            let end = (self.offset + 3)
+                .min(self.data.len())
+                .min(self.offset + buf.remaining());
+            buf.put_slice(&self.data[self.offset..end]);
+            self.offset = end;
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_recv_framed_partial_reads() {
+        let first = Msg::text(1, 2, 0, "the first one split into tiny reads");
+        let mut second = Msg::text(2, 1, 0, "second");
+        second.set_seqnum(9);
+        let mut data = first.as_slice().to_vec();
+        data.extend_from_slice(second.as_slice());
+        let mut reader = ChunkedReader {
+            data: data.clone(),
+            offset: 0,
+            pending: false,
+        };
+        let mut buffer = Box::new([0u8; HEAD_LEN]);
+        let msg = MsgIOUtil::recv_framed(&mut buffer, &mut reader, PAYLOAD_THRESHOLD)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(msg.as_slice(), first.as_slice());
+        let msg = MsgIOUtil::recv_framed(&mut buffer, &mut reader, PAYLOAD_THRESHOLD)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(msg.payload(), b"second");
+        assert_eq!(msg.seqnum(), 9);
+        // closed on a msg boundary.
+        assert!(
+            MsgIOUtil::recv_framed(&mut buffer, &mut reader, PAYLOAD_THRESHOLD)
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        // closed in the middle of the head and of the body.
+        for len in [HEAD_LEN / 2, first.as_slice().len() - 1] {
+            let mut reader = ChunkedReader {
+                data: data[..len].to_vec(),
+                offset: 0,
+                pending: false,
+            };
+            assert!(
+                MsgIOUtil::recv_framed(&mut buffer, &mut reader, PAYLOAD_THRESHOLD)
+                    .await
+                    .unwrap()
+                    .is_none()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_recv_reqwest_length() {
+        let msg = ReqwestMsg::with_resource_id_payload(ReqwestResourceID::Ping, b"ping");
+        let mut reader = ChunkedReader {
+            data: msg.as_slice().to_vec(),
+            offset: 0,
+            pending: false,
+        };
+        let received = ReqwestMsgIOUtil::recv_framed(&mut reader, 1024)
+            .await
+            .unwrap();
+        assert_eq!(received.as_slice(), msg.as_slice());
+
+        // no body follows, so only the declared length can be the reason.
+        for (len, reason) in [(u16::MAX, "too large"), (0, "too short"), (9, "too short")] {
+            let mut reader = ChunkedReader {
+                data: len.to_be_bytes().to_vec(),
+                offset: 0,
+                pending: false,
+            };
+            let err = ReqwestMsgIOUtil::recv_framed(&mut reader, 1024)
+                .await
+                .unwrap_err();
+            assert!(err.to_string().contains(reason), "{}", err);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_recv_payload_histogram() {
+        let video_buckets = || {
+            payload_histogram()
+                .snapshot()
+                .into_iter()
+                .find(|sizes| sizes.typ == "video")
+                .map(|sizes| sizes.buckets)
+                .unwrap_or(vec![0; PAYLOAD_BUCKETS.len() + 1])
+        };
+        let before = video_buckets();
+        let mut data = Vec::new();
+        for len in [10, 16, 100, 2000, 6000] {
+            let mut msg = Msg::raw(1, 2, 0, &vec![b'v'; len]);
+            msg.set_type(Type::Video);
+            data.extend_from_slice(msg.as_slice());
+        }
+        let mut reader = ChunkedReader {
+            data,
+            offset: 0,
+            pending: false,
+        };
+        let mut buffer = Box::new([0u8; HEAD_LEN]);
+        while let Some(_) = MsgIOUtil::recv_framed(&mut buffer, &mut reader, PAYLOAD_THRESHOLD)
+            .await
+            .unwrap()
+        {}
+        let delta = video_buckets()
+            .iter()
+            .zip(before.iter())
+            .map(|(after, before)| after - before)
+            .collect::<Vec<u64>>();
+        assert_eq!(delta, vec![2, 0, 1, 0, 1, 1]);
+    }
+}
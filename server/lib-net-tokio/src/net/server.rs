@@ -1,6 +1,6 @@
 use std::{
     sync::{
-        atomic::{AtomicUsize, Ordering},
+        atomic::{AtomicU64, AtomicUsize, Ordering},
         Arc,
     },
     task::Waker,
@@ -17,21 +17,27 @@ use crate::net::{
 };
 
 use anyhow::anyhow;
+use arc_swap::ArcSwapOption;
 use async_trait::async_trait;
 use dashmap::DashMap;
 use futures::{pin_mut, FutureExt};
 use lib::{
     entity::ReqwestMsg,
-    net::{server::ServerConfig, GenericParameter, ALPN_PRIM},
+    net::{server::ServerConfig, GenericParameter, SERVER_FULL_CODE},
     Result,
 };
 use quinn::{Connection, RecvStream, SendStream};
+use rustls::{
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+};
 use tokio::{
     io::{split, AsyncWriteExt},
     net::TcpStream,
     sync::mpsc,
 };
 use tokio_rustls::{server::TlsStream, TlsAcceptor};
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info};
 
 pub type NewConnectionHandlerGenerator =
@@ -85,69 +91,251 @@ impl ReqwestCaller {
 /// use for client-server communication
 pub struct Server {
     config: Option<ServerConfig>,
+    shutdown: ServerShutdown,
+    metrics: ServerMetrics,
+    cert: CertReloader,
+}
+
+/// handle to stop a running [`Server`] gracefully.
+#[derive(Clone)]
+pub struct ServerShutdown {
+    shutdown: CancellationToken,
+    done: CancellationToken,
+}
+
+impl ServerShutdown {
+    /// stop accepting connections and streams, let the in-flight streams drain within the grace period,
+    /// then close the endpoint. return after `run` has returned, so `run` must have been started.
+    pub async fn shutdown(&self) {
+        self.shutdown.cancel();
+        self.done.cancelled().await;
+    }
+}
+
+/// counters of a running [`Server`], shared by the accept loop and the connection tasks.
+#[derive(Clone, Default)]
+pub struct ServerMetrics {
+    streams_opened: Arc<AtomicU64>,
+    streams_closed: Arc<AtomicU64>,
+    connections_active: Arc<AtomicUsize>,
+}
+
+impl ServerMetrics {
+    /// bi streams accepted since the server started.
+    pub fn streams_opened_total(&self) -> u64 {
+        self.streams_opened.load(Ordering::Acquire)
+    }
+
+    /// bi streams whose handler has returned and whose replies are flushed.
+    pub fn streams_closed_total(&self) -> u64 {
+        self.streams_closed.load(Ordering::Acquire)
+    }
+
+    /// connections established and not gone yet, the ones refused by `max_connections` excluded.
+    pub fn connections_active(&self) -> usize {
+        self.connections_active.load(Ordering::Acquire)
+    }
+}
+
+/// handle to swap the cert of a running server.
+///
+/// only new handshakes present the new cert, the connections established keep theirs.
+#[derive(Clone, Default)]
+pub struct CertReloader {
+    resolver: Arc<CertResolver>,
+}
+
+#[derive(Default)]
+pub(self) struct CertResolver {
+    certified_key: ArcSwapOption<CertifiedKey>,
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        self.certified_key.load_full()
+    }
+}
+
+impl CertReloader {
+    pub fn new(cert: rustls::Certificate, key: rustls::PrivateKey) -> Result<Self> {
+        let reloader = Self::default();
+        reloader.reload_cert(cert, key)?;
+        Ok(reloader)
+    }
+
+    /// the server starts with the cert from its config, so reload after `run` has been started.
+    pub fn reload_cert(&self, cert: rustls::Certificate, key: rustls::PrivateKey) -> Result<()> {
+        let key = rustls::sign::any_supported_type(&key)
+            .map_err(|_| anyhow!("unsupported private key type."))?;
+        self.resolver
+            .certified_key
+            .store(Some(Arc::new(CertifiedKey::new(vec![cert], key))));
+        Ok(())
+    }
 }
 
 impl Server {
     pub fn new(config: ServerConfig) -> Self {
         Self {
             config: Some(config),
+            shutdown: ServerShutdown {
+                shutdown: CancellationToken::new(),
+                done: CancellationToken::new(),
+            },
+            metrics: ServerMetrics::default(),
+            cert: CertReloader::default(),
         }
     }
 
+    pub fn shutdown_handle(&self) -> ServerShutdown {
+        self.shutdown.clone()
+    }
+
+    pub fn metrics(&self) -> ServerMetrics {
+        self.metrics.clone()
+    }
+
+    pub fn cert_reloader(&self) -> CertReloader {
+        self.cert.clone()
+    }
+
     pub async fn run(&mut self, generator: NewConnectionHandlerGenerator) -> Result<()> {
+        let config = self.config.take().unwrap();
+        let transport = transport_config(&config);
         // deconstruct ServerConfig
         let ServerConfig {
             address,
             cert,
             key,
             max_connections,
-            connection_idle_timeout,
-            max_bi_streams,
-        } = self.config.take().unwrap();
-        // set crypto for server
-        let mut server_crypto = rustls::ServerConfig::builder()
-            .with_safe_defaults()
-            .with_no_client_auth()
-            .with_single_cert(vec![cert], key)?;
-        // set custom alpn protocol
-        server_crypto.alpn_protocols = ALPN_PRIM.iter().map(|&x| x.into()).collect();
+            max_payload_bytes,
+            shutdown_grace,
+            compress_threshold,
+            enable_0rtt,
+            client_ca_certs,
+            alpn_protocols,
+            flow_control,
+            ..
+        } = config;
+        self.cert.reload_cert(cert, key)?;
+        let mut server_crypto = server_crypto(&self.cert, client_ca_certs, alpn_protocols)?;
+        if enable_0rtt {
+            // quic only allows 0 or u32::MAX here.
+            server_crypto.max_early_data_size = u32::MAX;
+        }
         let mut quinn_server_config = quinn::ServerConfig::with_crypto(Arc::new(server_crypto));
-        // set max concurrent connections
-        quinn_server_config.concurrent_connections(max_connections as u32);
+        // quinn also counts the connections closed but still draining, the room left for them
+        // is taken back by the counter in the accept loop.
+        quinn_server_config
+            .concurrent_connections((max_connections as u32).saturating_mul(2).max(2));
         quinn_server_config.use_retry(true);
-        // set quic transport parameters
-        Arc::get_mut(&mut quinn_server_config.transport)
-            .unwrap()
-            .max_concurrent_bidi_streams(quinn::VarInt::from_u64(max_bi_streams as u64).unwrap())
-            // the keep-alive interval should set on client.
-            .max_idle_timeout(Some(quinn::IdleTimeout::from(
-                quinn::VarInt::from_u64(connection_idle_timeout).unwrap(),
-            )));
+        // clients changing networks keep their connections.
+        quinn_server_config.migration(true);
+        quinn_server_config.transport_config(Arc::new(transport));
         let endpoint = quinn::Endpoint::server(quinn_server_config, address)?;
         let generator = Arc::new(generator);
-        while let Some(conn) = endpoint.accept().await {
+        let shutdown = self.shutdown.shutdown.clone();
+        // every connection and stream task holds a clone, all dropped means all drained.
+        let (drain_guard, mut drained) = mpsc::channel::<()>(1);
+        // the established connections, decreased once a connection is gone.
+        let connection_counter = self.metrics.connections_active.clone();
+        loop {
+            let conn = tokio::select! {
+                conn = endpoint.accept() => match conn {
+                    Some(conn) => conn,
+                    None => break,
+                },
+                _ = shutdown.cancelled() => break,
+            };
             let conn = conn.await?;
+            if connection_counter.fetch_add(1, Ordering::AcqRel) >= max_connections {
+                connection_counter.fetch_sub(1, Ordering::AcqRel);
+                conn.close(SERVER_FULL_CODE.into(), b"server full.");
+                error!("too many connections, {} refused.", conn.remote_address());
+                continue;
+            }
             info!("new connection: {}", conn.remote_address().to_string());
             let generator = generator.clone();
+            let shutdown = shutdown.clone();
+            let drain_guard = drain_guard.clone();
+            let counter = connection_counter.clone();
+            let metrics = self.metrics.clone();
             tokio::spawn(async move {
-                let _ = Self::handle_new_connection(conn, generator).await;
+                let _ = Self::handle_new_connection(
+                    conn,
+                    generator,
+                    max_payload_bytes,
+                    compress_threshold,
+                    flow_control,
+                    shutdown,
+                    drain_guard,
+                    metrics,
+                )
+                .await;
+                counter.fetch_sub(1, Ordering::AcqRel);
             });
         }
+        if shutdown.is_cancelled() {
+            info!("server shutting down, draining in-flight streams.");
+            endpoint.set_server_config(None);
+            drop(drain_guard);
+            if tokio::time::timeout(shutdown_grace, drained.recv())
+                .await
+                .is_err()
+            {
+                info!("drain timeout, remaining streams are closed.");
+            }
+            endpoint.close(0u32.into(), b"server shutdown.");
+        }
         endpoint.wait_idle().await;
+        self.shutdown.done.cancel();
         Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn handle_new_connection(
         conn: Connection,
         generator: Arc<NewConnectionHandlerGenerator>,
+        max_payload_bytes: usize,
+        compress_threshold: Option<usize>,
+        flow_control: Option<(usize, usize)>,
+        shutdown: CancellationToken,
+        drain_guard: mpsc::Sender<()>,
+        metrics: ServerMetrics,
     ) -> Result<()> {
         loop {
-            match conn.accept_bi().await {
+            let io_streams = tokio::select! {
+                io_streams = conn.accept_bi() => io_streams,
+                // the in-flight streams keep the connection alive until they finish.
+                _ = shutdown.cancelled() => return Ok(()),
+            };
+            match io_streams {
                 Ok(io_streams) => {
+                    metrics.streams_opened.fetch_add(1, Ordering::AcqRel);
                     let mut handler = generator();
-                    let io_operators = MsgIOWrapper::new(io_streams.0, io_streams.1, 0);
+                    let mut io_operators = MsgIOWrapper::new(
+                        io_streams.0,
+                        io_streams.1,
+                        0,
+                        max_payload_bytes,
+                        compress_threshold,
+                    );
+                    io_operators.stop_recv_on(shutdown.clone());
+                    if let Some((high_water, low_water)) = flow_control {
+                        io_operators.signal_flow_on(high_water, low_water);
+                    }
+                    let flushed = io_operators.flushed.take();
+                    let drain_guard = drain_guard.clone();
+                    let metrics = metrics.clone();
                     tokio::spawn(async move {
                         _ = handler.handle(io_operators).await;
+                        // the handler may hold the send channel, replies are flushed after it's gone.
+                        drop(handler);
+                        if let Some(flushed) = flushed {
+                            _ = flushed.await;
+                        }
+                        metrics.streams_closed.fetch_add(1, Ordering::AcqRel);
+                        drop(drain_guard);
                     });
                 }
                 Err(e) => {
@@ -186,15 +374,21 @@ impl Server {
 
 pub struct ServerTcp {
     config: Option<ServerConfig>,
+    cert: CertReloader,
 }
 
 impl ServerTcp {
     pub fn new(config: ServerConfig) -> Self {
         Self {
             config: Some(config),
+            cert: CertReloader::default(),
         }
     }
 
+    pub fn cert_reloader(&self) -> CertReloader {
+        self.cert.clone()
+    }
+
     pub async fn run(&mut self, generator: NewConnectionHandlerGeneratorTcp) -> Result<()> {
         let ServerConfig {
             address,
@@ -202,18 +396,26 @@ impl ServerTcp {
             key,
             connection_idle_timeout,
             max_connections,
+            max_payload_bytes,
+            client_ca_certs,
+            alpn_protocols,
             ..
         } = self.config.take().unwrap();
-        let mut config = rustls::ServerConfig::builder()
-            .with_safe_defaults()
-            .with_no_client_auth()
-            .with_single_cert(vec![cert], key)?;
-        config.alpn_protocols = ALPN_PRIM.iter().map(|&x| x.into()).collect();
+        self.cert.reload_cert(cert, key)?;
+        let config = server_crypto(&self.cert, client_ca_certs, alpn_protocols)?;
         let connection_counter = Arc::new(AtomicUsize::new(0));
         let acceptor = TlsAcceptor::from(Arc::new(config));
         let listener = tokio::net::TcpListener::bind(address).await?;
         while let Ok((stream, addr)) = listener.accept().await {
-            let tls_stream = acceptor.accept(stream).await?;
+            // a failed handshake, e.g. a client without an accepted certificate, only ends its
+            // own connection.
+            let tls_stream = match acceptor.accept(stream).await {
+                Ok(tls_stream) => tls_stream,
+                Err(e) => {
+                    error!("tls handshake with {} failed: {}", addr, e);
+                    continue;
+                }
+            };
             let handler = generator();
             let number = connection_counter.fetch_add(1, Ordering::AcqRel);
             if number > max_connections {
@@ -232,6 +434,7 @@ impl ServerTcp {
                     handler,
                     counter,
                     connection_idle_timeout,
+                    max_payload_bytes,
                 )
                 .await;
             });
@@ -244,9 +447,10 @@ impl ServerTcp {
         mut handler: Box<dyn NewConnectionHandlerTcp>,
         connection_counter: Arc<AtomicUsize>,
         connection_idle_timeout: u64,
+        max_payload_bytes: usize,
     ) -> Result<()> {
         let idle_timeout = Duration::from_millis(connection_idle_timeout);
-        let io_operators = MsgIOWrapperTcpS::new(stream, idle_timeout, 0);
+        let io_operators = MsgIOWrapperTcpS::new(stream, idle_timeout, 0, max_payload_bytes);
         _ = handler.handle(io_operators).await;
         debug!("connection closed.");
         connection_counter.fetch_sub(1, Ordering::AcqRel);
@@ -266,28 +470,23 @@ impl ServerReqwest0 {
     }
 
     pub(self) async fn run(&mut self, generator: ReqwestHandlerGenerator0) -> Result<()> {
+        let config = self.config.take().unwrap();
+        let transport = transport_config(&config);
         let ServerConfig {
             address,
             cert,
             key,
             max_connections,
-            connection_idle_timeout,
-            max_bi_streams,
-        } = self.config.take().unwrap();
-        let mut server_crypto = rustls::ServerConfig::builder()
-            .with_safe_defaults()
-            .with_no_client_auth()
-            .with_single_cert(vec![cert], key)?;
-        server_crypto.alpn_protocols = ALPN_PRIM.iter().map(|&x| x.into()).collect();
+            client_ca_certs,
+            alpn_protocols,
+            ..
+        } = config;
+        let cert = CertReloader::new(cert, key)?;
+        let server_crypto = server_crypto(&cert, client_ca_certs, alpn_protocols)?;
         let mut quinn_server_config = quinn::ServerConfig::with_crypto(Arc::new(server_crypto));
         quinn_server_config.concurrent_connections(max_connections as u32);
         quinn_server_config.use_retry(true);
-        Arc::get_mut(&mut quinn_server_config.transport)
-            .unwrap()
-            .max_concurrent_bidi_streams(quinn::VarInt::from_u64(max_bi_streams as u64).unwrap())
-            .max_idle_timeout(Some(quinn::IdleTimeout::from(
-                quinn::VarInt::from_u64(connection_idle_timeout).unwrap(),
-            )));
+        quinn_server_config.transport_config(Arc::new(transport));
         let endpoint = quinn::Endpoint::server(quinn_server_config, address)?;
         let generator = Arc::new(generator);
         while let Some(conn) = endpoint.accept().await {
@@ -356,13 +555,16 @@ impl ServerReqwest0 {
 pub struct ServerReqwest {
     server: ServerReqwest0,
     timeout: Duration,
+    max_reqwest_bytes: usize,
 }
 
 impl ServerReqwest {
     pub fn new(config: ServerConfig, timeout: Duration) -> Self {
+        let max_reqwest_bytes = config.max_reqwest_bytes;
         Self {
             server: ServerReqwest0::new(config),
             timeout,
+            max_reqwest_bytes,
         }
     }
 
@@ -370,6 +572,7 @@ impl ServerReqwest {
         struct Generator0 {
             generator: Arc<ReqwestHandlerGenerator>,
             timeout: Duration,
+            max_reqwest_bytes: usize,
         }
 
         #[async_trait]
@@ -392,6 +595,7 @@ impl ServerReqwest {
                 let stream_id = recv_stream.id().0;
                 let sender_clone = sender.clone();
                 let timeout = self.timeout;
+                let max_reqwest_bytes = self.max_reqwest_bytes;
 
                 tokio::spawn(async move {
                     let waker_map = resp_waker_map0.clone();
@@ -440,7 +644,13 @@ impl ServerReqwest {
 
                     let task2 = async {
                         loop {
-                            match ReqwestMsgIOUtil::recv_msg(&mut recv_stream, None).await {
+                            match ReqwestMsgIOUtil::recv_msg(
+                                &mut recv_stream,
+                                None,
+                                max_reqwest_bytes,
+                            )
+                            .await
+                            {
                                 Ok(msg) => {
                                     let req_id = msg.req_id();
                                     // a response from client
@@ -546,10 +756,12 @@ impl ServerReqwest {
         }
 
         let timeout = self.timeout;
+        let max_reqwest_bytes = self.max_reqwest_bytes;
         let generator0: ReqwestHandlerGenerator0 = Box::new(move || {
             Box::new(Generator0 {
                 generator: generator.clone(),
                 timeout,
+                max_reqwest_bytes,
             })
         });
         self.server.run(generator0).await
@@ -574,13 +786,13 @@ impl ServerReqwestTcp {
             key,
             connection_idle_timeout,
             max_connections,
+            client_ca_certs,
+            alpn_protocols,
+            max_reqwest_bytes,
             ..
         } = self.config.take().unwrap();
-        let mut config = rustls::ServerConfig::builder()
-            .with_safe_defaults()
-            .with_no_client_auth()
-            .with_single_cert(vec![cert], key)?;
-        config.alpn_protocols = ALPN_PRIM.iter().map(|&x| x.into()).collect();
+        let cert = CertReloader::new(cert, key)?;
+        let config = server_crypto(&cert, client_ca_certs, alpn_protocols)?;
         let connection_counter = Arc::new(AtomicUsize::new(0));
         let acceptor = TlsAcceptor::from(Arc::new(config));
         let listener = tokio::net::TcpListener::bind(address).await?;
@@ -608,6 +820,7 @@ impl ServerReqwestTcp {
                     handler,
                     counter,
                     connection_idle_timeout,
+                    max_reqwest_bytes,
                 )
                 .await;
             });
@@ -621,12 +834,566 @@ impl ServerReqwestTcp {
         mut handler: Box<dyn NewReqwestConnectionHandler>,
         connection_counter: Arc<AtomicUsize>,
         connection_idle_timeout: u64,
+        max_reqwest_bytes: usize,
     ) -> Result<()> {
         let idle_timeout = Duration::from_millis(connection_idle_timeout);
-        let mut io_operators = ReqwestMsgIOWrapperTcpS::new(stream, idle_timeout);
+        let mut io_operators =
+            ReqwestMsgIOWrapperTcpS::new(stream, idle_timeout, max_reqwest_bytes);
         _ = handler.handle(io_operators.io_channels()).await;
         debug!("connection closed.");
         connection_counter.fetch_sub(1, Ordering::AcqRel);
         Ok(())
     }
 }
+
+/// the quic transport parameters shared by all quic servers.
+pub(self) fn transport_config(config: &ServerConfig) -> quinn::TransportConfig {
+    let mut transport_config = quinn::TransportConfig::default();
+    transport_config
+        .max_concurrent_bidi_streams(quinn::VarInt::from_u64(config.max_bi_streams as u64).unwrap())
+        // the keep-alive interval should set on client.
+        .max_idle_timeout(Some(quinn::IdleTimeout::from(
+            quinn::VarInt::from_u64(config.connection_idle_timeout).unwrap(),
+        )));
+    // checked by the config builder to fit in a varint, quinn's defaults are kept when unset.
+    if let Some(stream_receive_window) = config.stream_receive_window {
+        transport_config
+            .stream_receive_window(quinn::VarInt::from_u64(stream_receive_window).unwrap());
+    }
+    if let Some(receive_window) = config.receive_window {
+        transport_config.receive_window(quinn::VarInt::from_u64(receive_window).unwrap());
+    }
+    transport_config
+}
+
+/// the tls config shared by all servers, with client certificates required if `client_ca_certs`
+/// is given.
+pub(self) fn server_crypto(
+    cert: &CertReloader,
+    client_ca_certs: Option<Vec<rustls::Certificate>>,
+    alpn_protocols: Vec<Vec<u8>>,
+) -> Result<rustls::ServerConfig> {
+    let builder = rustls::ServerConfig::builder().with_safe_defaults();
+    let builder = match client_ca_certs {
+        Some(client_ca_certs) => {
+            let mut roots = rustls::RootCertStore::empty();
+            for cert in client_ca_certs.iter() {
+                roots.add(cert)?;
+            }
+            builder.with_client_cert_verifier(
+                rustls::server::AllowAnyAuthenticatedClient::new(roots).boxed(),
+            )
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut server_crypto = builder.with_cert_resolver(cert.resolver.clone());
+    server_crypto.alpn_protocols = alpn_protocols;
+    Ok(server_crypto)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use async_trait::async_trait;
+    use lib::{
+        entity::{msg::MSG_DELIMITER, Msg, ReqwestMsg, ReqwestResourceID, Type, PAYLOAD_THRESHOLD},
+        net::{ALPN_PRIM, SERVER_FULL_CODE},
+        util::cipher::AesGcmCipher,
+        Result,
+    };
+    use tokio::sync::mpsc;
+
+    use super::{transport_config, NewConnectionHandler, ReqwestCaller, Server, ServerReqwest};
+    use crate::net::{
+        client::{Client, ClientReqwest},
+        mock::config_pair,
+        MsgIOWrapper, NewReqwestConnectionHandler, ReqwestHandlerGenerator,
+    };
+
+    /// echo every msg slowly, report when a msg arrives and when the loop exits.
+    struct SlowEcho {
+        received: mpsc::Sender<()>,
+        finished: mpsc::Sender<()>,
+    }
+
+    #[async_trait]
+    impl NewConnectionHandler for SlowEcho {
+        async fn handle(&mut self, mut io_operators: MsgIOWrapper) -> Result<()> {
+            let (sender, mut receiver) = io_operators.channels();
+            while let Some(msg) = receiver.recv().await {
+                _ = self.received.send(()).await;
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                sender.send(msg).await?;
+            }
+            _ = self.finished.send(()).await;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_drain() {
+        let (mut server_config, mut client_config) = config_pair();
+        server_config.with_shutdown_grace(Duration::from_secs(10));
+        let mut server = Server::new(server_config.build().unwrap());
+        let shutdown = server.shutdown_handle();
+        let (received_sender, mut received) = mpsc::channel(16);
+        let (finished_sender, mut finished) = mpsc::channel(16);
+        tokio::spawn(async move {
+            server
+                .run(Box::new(move || {
+                    Box::new(SlowEcho {
+                        received: received_sender.clone(),
+                        finished: finished_sender.clone(),
+                    })
+                }))
+                .await
+                .unwrap();
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        client_config
+            .with_max_bi_streams(1)
+            .with_connect_timeout(Duration::from_millis(500));
+        let client_config = client_config.build().unwrap();
+        let mut client = Client::new(client_config.clone());
+        client.run().await.unwrap();
+        let (_io_sender, mut io_receiver) =
+            client.io_channel_token(1, 0, 0, "token").await.unwrap();
+        received.recv().await.unwrap();
+
+        // the msg in process when shutting down still gets its reply.
+        let start = tokio::time::Instant::now();
+        let shutdown = tokio::spawn(async move { shutdown.shutdown().await });
+        let echo = tokio::time::timeout(Duration::from_secs(3), io_receiver.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(echo.sender(), 1);
+
+        let mut late_client = Client::new(client_config);
+        assert!(late_client.run().await.is_err());
+
+        tokio::time::timeout(Duration::from_secs(3), finished.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(3), shutdown)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    /// echo every msg, and tell whether it arrived compressed.
+    struct Echo {
+        compressed: mpsc::Sender<bool>,
+    }
+
+    #[async_trait]
+    impl NewConnectionHandler for Echo {
+        async fn handle(&mut self, mut io_operators: MsgIOWrapper) -> Result<()> {
+            let (sender, mut receiver) = io_operators.channels();
+            while let Some(msg) = receiver.recv().await {
+                _ = self.compressed.send(msg.is_compressed()).await;
+                sender.send(msg).await?;
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_compress_payload() {
+        let (mut server_config, mut client_config) = config_pair();
+        server_config.with_compress_threshold(1024);
+        let mut server = Server::new(server_config.build().unwrap());
+        let (compressed_sender, mut compressed) = mpsc::channel(16);
+        tokio::spawn(async move {
+            server
+                .run(Box::new(move || {
+                    Box::new(Echo {
+                        compressed: compressed_sender.clone(),
+                    })
+                }))
+                .await
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        client_config
+            .with_max_bi_streams(1)
+            .with_compress_threshold(1024);
+        let mut client = Client::new(client_config.build().unwrap());
+        client.run().await.unwrap();
+        let (io_sender, mut io_receiver) = client.io_channel_token(1, 0, 0, "token").await.unwrap();
+        // the auth msg is too short to be compressed.
+        io_receiver.recv().await.unwrap();
+        assert!(!compressed.recv().await.unwrap());
+
+        let payload = b"image ".repeat(PAYLOAD_THRESHOLD / 6);
+        let mut msg = Msg::raw(1, 2, 0, &payload);
+        msg.set_type(Type::Image);
+        io_sender.send(std::sync::Arc::new(msg)).await.unwrap();
+        let echo = tokio::time::timeout(Duration::from_secs(3), io_receiver.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        // the handler sees the decompressed msg, the compressed one only lives on the wire.
+        assert!(!compressed.recv().await.unwrap());
+        assert!(!echo.is_compressed());
+        assert_eq!(echo.payload(), &payload[..]);
+    }
+
+    /// echo every msg, and keep a copy of it like the msglogger does.
+    struct Logger {
+        logged: mpsc::Sender<Arc<Msg>>,
+    }
+
+    #[async_trait]
+    impl NewConnectionHandler for Logger {
+        async fn handle(&mut self, mut io_operators: MsgIOWrapper) -> Result<()> {
+            let (sender, mut receiver) = io_operators.channels();
+            while let Some(msg) = receiver.recv().await {
+                _ = self.logged.send(msg.clone()).await;
+                sender.send(msg).await?;
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_payload() {
+        let (server_config, mut client_config) = config_pair();
+        let mut server = Server::new(server_config.build().unwrap());
+        let (logged_sender, mut logged) = mpsc::channel(16);
+        tokio::spawn(async move {
+            server
+                .run(Box::new(move || {
+                    Box::new(Logger {
+                        logged: logged_sender.clone(),
+                    })
+                }))
+                .await
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        client_config.with_max_bi_streams(1);
+        let cipher = Arc::new(AesGcmCipher::new());
+        cipher.set_key(1, 2, &[7u8; 32]);
+        let mut client = Client::new(client_config.build().unwrap());
+        client.set_cipher(cipher);
+        client.run().await.unwrap();
+        let (io_sender, mut io_receiver) = client.io_channel_token(1, 0, 0, "token").await.unwrap();
+        // the auth msg is read by the node, so it's never encrypted.
+        io_receiver.recv().await.unwrap();
+        assert!(!logged.recv().await.unwrap().is_encrypted());
+
+        io_sender
+            .send(Arc::new(Msg::text(1, 2, 0, "top secret")))
+            .await
+            .unwrap();
+        let echo = tokio::time::timeout(Duration::from_secs(3), io_receiver.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let seen = logged.recv().await.unwrap();
+        assert!(seen.is_encrypted());
+        assert_eq!(seen.typ(), Type::Text);
+        assert!(!seen
+            .payload()
+            .windows(b"top secret".len())
+            .any(|window| window == b"top secret"));
+        assert!(!echo.is_encrypted());
+        assert_eq!(echo.payload(), b"top secret");
+    }
+
+    /// hold `batch` requests, then answer them in the reverse order.
+    struct Reverse {
+        batch: usize,
+    }
+
+    #[async_trait]
+    impl NewReqwestConnectionHandler for Reverse {
+        async fn handle(
+            &mut self,
+            msg_operators: (mpsc::Sender<ReqwestMsg>, mpsc::Receiver<ReqwestMsg>),
+        ) -> Result<()> {
+            let (send, mut recv) = msg_operators;
+            let mut held = Vec::with_capacity(self.batch);
+            while let Some(req) = recv.recv().await {
+                held.push(req);
+                if held.len() < self.batch {
+                    continue;
+                }
+                while let Some(req) = held.pop() {
+                    let mut resp =
+                        ReqwestMsg::with_resource_id_payload(req.resource_id(), req.payload());
+                    resp.set_req_id(req.req_id());
+                    send.send(resp).await?;
+                }
+            }
+            Ok(())
+        }
+
+        fn set_reqwest_caller(&mut self, _reqwest_caller: ReqwestCaller) {}
+    }
+
+    #[tokio::test]
+    async fn test_reqwest_pipelining() {
+        let (mut server_config, mut client_config) = config_pair();
+        server_config.with_max_bi_streams(1);
+        let mut server = ServerReqwest::new(server_config.build().unwrap(), Duration::from_secs(3));
+        let generator: ReqwestHandlerGenerator = Box::new(|| Box::new(Reverse { batch: 3 }));
+        tokio::spawn(async move { server.run(Arc::new(generator)).await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        client_config.with_max_bi_streams(1);
+        let mut client = ClientReqwest::new(client_config.build().unwrap(), Duration::from_secs(3));
+        let generator: ReqwestHandlerGenerator = Box::new(|| Box::new(Reverse { batch: 1 }));
+        let operator = client.build(Arc::new(generator)).await.unwrap();
+        // all three are in flight on the one stream before any reply, which come back reversed.
+        let (first, second, third) = tokio::join!(
+            operator.call(ReqwestMsg::with_resource_id_payload(
+                ReqwestResourceID::Ping,
+                b"first"
+            )),
+            operator.call(ReqwestMsg::with_resource_id_payload(
+                ReqwestResourceID::Ping,
+                b"second"
+            )),
+            operator.call(ReqwestMsg::with_resource_id_payload(
+                ReqwestResourceID::Ping,
+                b"third"
+            )),
+        );
+        assert_eq!(first.unwrap().payload(), b"first");
+        assert_eq!(second.unwrap().payload(), b"second");
+        assert_eq!(third.unwrap().payload(), b"third");
+    }
+
+    #[test]
+    fn test_receive_windows() {
+        let (builder, _) = config_pair();
+        let default = format!("{:?}", transport_config(&builder.build().unwrap()));
+        assert!(!default.contains("stream_receive_window: 4194304"));
+
+        let (mut builder, _) = config_pair();
+        builder
+            .with_stream_receive_window(4194304)
+            .with_receive_window(33554432);
+        let transport = format!("{:?}", transport_config(&builder.build().unwrap()));
+        assert!(transport.contains("stream_receive_window: 4194304"));
+        assert!(transport.contains(" receive_window: 33554432"));
+    }
+
+    #[tokio::test]
+    async fn test_streams_opened_on_build() {
+        let (server_config, client_config) = config_pair();
+        let mut server = ServerReqwest::new(server_config.build().unwrap(), Duration::from_secs(3));
+        let generator: ReqwestHandlerGenerator = Box::new(|| Box::new(Reverse { batch: 1 }));
+        tokio::spawn(async move { server.run(Arc::new(generator)).await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut client = ClientReqwest::new(client_config.build().unwrap(), Duration::from_secs(3));
+        let generator: ReqwestHandlerGenerator = Box::new(|| Box::new(Reverse { batch: 1 }));
+        let operator = client.build(Arc::new(generator)).await.unwrap();
+        // every stream is up before the first call, the calls only pick among them.
+        assert_eq!(operator.stream_count(), 4);
+        let calls = (0..4u8).map(|i| {
+            operator.call(ReqwestMsg::with_resource_id_payload(
+                ReqwestResourceID::Ping,
+                &[i],
+            ))
+        });
+        let resps = futures::future::join_all(calls).await;
+        for (i, resp) in resps.into_iter().enumerate() {
+            assert_eq!(resp.unwrap().payload(), &[i as u8]);
+        }
+        assert_eq!(operator.stream_count(), 4);
+        assert_eq!(operator.in_flight(), 0);
+    }
+
+    /// a raw quic connection, so how it's closed can be told.
+    async fn quic_connect(
+        address: std::net::SocketAddr,
+        cert: &rustls::Certificate,
+    ) -> quinn::Connection {
+        try_quic_connect(address, cert).await.unwrap()
+    }
+
+    async fn try_quic_connect(
+        address: std::net::SocketAddr,
+        cert: &rustls::Certificate,
+    ) -> std::result::Result<quinn::Connection, quinn::ConnectionError> {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(cert).unwrap();
+        let mut crypto = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        crypto.alpn_protocols = ALPN_PRIM.iter().map(|&x| x.into()).collect();
+        let mut endpoint = quinn::Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+        endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(crypto)));
+        endpoint.connect(address, "localhost").unwrap().await
+    }
+
+    #[tokio::test]
+    async fn test_max_connections() {
+        let (mut server_config, _) = config_pair();
+        server_config.with_max_connections(1);
+        let (address, cert) = (
+            server_config.address.unwrap(),
+            server_config.cert.clone().unwrap(),
+        );
+        let mut server = Server::new(server_config.build().unwrap());
+        let (compressed_sender, _compressed) = mpsc::channel(16);
+        tokio::spawn(async move {
+            server
+                .run(Box::new(move || {
+                    Box::new(Echo {
+                        compressed: compressed_sender.clone(),
+                    })
+                }))
+                .await
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let first = quic_connect(address, &cert).await;
+        let second = quic_connect(address, &cert).await;
+        match tokio::time::timeout(Duration::from_secs(3), second.closed())
+            .await
+            .unwrap()
+        {
+            quinn::ConnectionError::ApplicationClosed(close) => {
+                assert_eq!(close.error_code, SERVER_FULL_CODE.into());
+            }
+            e => panic!("expected server full, got: {}", e),
+        }
+        assert!(first.close_reason().is_none());
+
+        // the room is given back once the first one is gone.
+        first.close(0u32.into(), b"bye");
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let third = quic_connect(address, &cert).await;
+        assert!(
+            tokio::time::timeout(Duration::from_millis(300), third.closed())
+                .await
+                .is_err()
+        );
+    }
+
+    /// `msg` as it's put on the wire, after the delimiter.
+    fn framed(msg: &Msg) -> Vec<u8> {
+        let mut bytes = MSG_DELIMITER.to_vec();
+        bytes.extend_from_slice(msg.as_slice());
+        bytes
+    }
+
+    /// wait up to 3 seconds for `f` to hold.
+    async fn eventually(f: impl Fn() -> bool) -> bool {
+        let start = tokio::time::Instant::now();
+        while start.elapsed() < Duration::from_secs(3) {
+            if f() {
+                return true;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        false
+    }
+
+    #[tokio::test]
+    async fn test_stream_metrics() {
+        let (server_config, _) = config_pair();
+        let (address, cert) = (
+            server_config.address.unwrap(),
+            server_config.cert.clone().unwrap(),
+        );
+        let mut server = Server::new(server_config.build().unwrap());
+        let metrics = server.metrics();
+        let (compressed_sender, _compressed) = mpsc::channel(16);
+        tokio::spawn(async move {
+            server
+                .run(Box::new(move || {
+                    Box::new(Echo {
+                        compressed: compressed_sender.clone(),
+                    })
+                }))
+                .await
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let conn = quic_connect(address, &cert).await;
+        assert!(eventually(|| metrics.connections_active() == 1).await);
+        assert_eq!(metrics.streams_opened_total(), 0);
+
+        // the server sees the stream once something is sent on it.
+        let (mut send, mut recv) = conn.open_bi().await.unwrap();
+        let msg = framed(&Msg::text(1, 2, 0, "hello"));
+        send.write_all(&msg).await.unwrap();
+        let mut echo = vec![0u8; msg.len()];
+        recv.read_exact(&mut echo).await.unwrap();
+        assert_eq!(echo, msg);
+        assert_eq!(metrics.streams_opened_total(), 1);
+        assert_eq!(metrics.streams_closed_total(), 0);
+
+        send.finish().await.unwrap();
+        assert!(eventually(|| metrics.streams_closed_total() == 1).await);
+        assert_eq!(metrics.streams_opened_total(), 1);
+
+        conn.close(0u32.into(), b"bye");
+        assert!(eventually(|| metrics.connections_active() == 0).await);
+        assert_eq!(metrics.streams_opened_total(), 1);
+        assert_eq!(metrics.streams_closed_total(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_reload_cert() {
+        let self_signed = || {
+            let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+            (
+                rustls::Certificate(cert.serialize_der().unwrap()),
+                rustls::PrivateKey(cert.serialize_private_key_der()),
+            )
+        };
+        let (new_cert, new_key) = self_signed();
+        let (server_config, _) = config_pair();
+        let (address, old_cert) = (
+            server_config.address.unwrap(),
+            server_config.cert.clone().unwrap(),
+        );
+        let mut server = Server::new(server_config.build().unwrap());
+        let reloader = server.cert_reloader();
+        let (compressed_sender, _compressed) = mpsc::channel(16);
+        tokio::spawn(async move {
+            server
+                .run(Box::new(move || {
+                    Box::new(Echo {
+                        compressed: compressed_sender.clone(),
+                    })
+                }))
+                .await
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let established = quic_connect(address, &old_cert).await;
+        assert!(try_quic_connect(address, &new_cert).await.is_err());
+        assert!(reloader
+            .reload_cert(new_cert.clone(), rustls::PrivateKey(vec![1, 2, 3]))
+            .is_err());
+        reloader.reload_cert(new_cert.clone(), new_key).unwrap();
+
+        // new handshakes only trust the new cert.
+        let conn = quic_connect(address, &new_cert).await;
+        let presented = conn.peer_identity().unwrap();
+        let presented = presented.downcast::<Vec<rustls::Certificate>>().unwrap();
+        assert_eq!(presented[0], new_cert);
+        assert!(try_quic_connect(address, &old_cert).await.is_err());
+
+        // the one established still works.
+        let (mut send, mut recv) = established.open_bi().await.unwrap();
+        let msg = framed(&Msg::text(1, 2, 0, "hello"));
+        send.write_all(&msg).await.unwrap();
+        let mut echo = vec![0u8; msg.len()];
+        recv.read_exact(&mut echo).await.unwrap();
+        assert_eq!(echo, msg);
+    }
+}
@@ -0,0 +1,146 @@
+//! the keys of redis shared by all the services, build them here rather than formatting the
+//! prefixes inline, so a key is always built the same way wherever it is used.
+
+use crate::util::who_we_are;
+
+pub const ACCOUNT_ID_COUNTER: &str = "ACCOUNT_ID_COUNTER";
+pub const MESSAGE_NODE_ID: &str = "NODE_ID_MESSAGE_";
+pub const SCHEDULER_NODE_ID: &str = "NODE_ID_SCHEDULER_";
+pub const MSGPROCESSOR_NODE_ID: &str = "NODE_ID_MSGPROCESSOR_";
+
+pub(self) const USER_TOKEN: &str = "USER_TOKEN_";
+pub(self) const JOIN_GROUP: &str = "JOIN_GROUP_";
+pub(self) const CHECK_CODE: &str = "CHECK_CODE_";
+pub(self) const ADD_FRIEND: &str = "ADD_FRIEND_";
+pub(self) const LAST_ONLINE_TIME: &str = "LAST_ONLINE_TIME_";
+pub(self) const LAST_READ: &str = "LAST_READ_";
+pub(self) const USER_INBOX: &str = "USER_INBOX_";
+pub(self) const MSG_CACHE: &str = "MSG_CACHE_";
+pub(self) const OFFLINE_MSG: &str = "OFFLINE_MSG_";
+pub(self) const QUOTA: &str = "QUOTA_";
+pub(self) const QUOTA_GROUP: &str = "QUOTA_GROUP_";
+/// the hash tag keeps all the marks in one slot, so those of many users are read by one MGET.
+pub(self) const PRESENCE: &str = "{PRESENCE}_";
+pub(self) const DEAD_LETTER: &str = "DEAD_LETTER_";
+pub(self) const USER_NODE_MAP: &str = "USER_NODE_MAP_";
+pub(self) const SIGNUP_IDEMPOTENCY: &str = "SIGNUP_IDEMPOTENCY_";
+pub(self) const CONTACTS: &str = "CONTACTS_";
+
+#[inline]
+pub fn user_token_key(account_id: u64) -> String {
+    format!("{}{}", USER_TOKEN, account_id)
+}
+
+/// the join request of `user_id` waiting for the admin of `group_id`.
+#[inline]
+pub fn join_group_key(user_id: u64, group_id: u64) -> String {
+    format!("{}{}-{}", JOIN_GROUP, user_id, group_id)
+}
+
+#[inline]
+pub fn check_code_key(group_id: u64) -> String {
+    format!("{}{}", CHECK_CODE, group_id)
+}
+
+/// the friend request sent by `user_id` to `peer_id`.
+#[inline]
+pub fn add_friend_key(user_id: u64, peer_id: u64) -> String {
+    format!("{}{}-{}", ADD_FRIEND, user_id, peer_id)
+}
+
+#[inline]
+pub fn last_online_time_key(user_id: u64) -> String {
+    format!("{}{}", LAST_ONLINE_TIME, user_id)
+}
+
+#[inline]
+pub fn last_read_key(user_id: u64, peer_id: u64) -> String {
+    format!("{}{}-{}", LAST_READ, user_id, peer_id)
+}
+
+#[inline]
+pub fn user_inbox_key(user_id: u64) -> String {
+    format!("{}{}", USER_INBOX, user_id)
+}
+
+/// the recent msgs between two users, the same for both orders of them. a group uses its id as
+/// both of them.
+#[inline]
+pub fn msg_cache_key(id1: u64, id2: u64) -> String {
+    format!("{}{}", MSG_CACHE, who_we_are(id1, id2))
+}
+
+#[inline]
+pub fn offline_msg_key(user_id: u64) -> String {
+    format!("{}{}", OFFLINE_MSG, user_id)
+}
+
+#[inline]
+pub fn quota_key(user_id: u64) -> String {
+    format!("{}{}", QUOTA, user_id)
+}
+
+#[inline]
+pub fn quota_group_key(user_id: u64) -> String {
+    format!("{}{}", QUOTA_GROUP, user_id)
+}
+
+#[inline]
+pub fn presence_key(user_id: u64) -> String {
+    format!("{}{}", PRESENCE, user_id)
+}
+
+#[inline]
+pub fn dead_letter_key(node_id: u32) -> String {
+    format!("{}{}", DEAD_LETTER, node_id)
+}
+
+#[inline]
+pub fn user_node_map_key(user_id: u64) -> String {
+    format!("{}{}", USER_NODE_MAP, user_id)
+}
+
+/// the signup of `account_id` retried with `idempotency_key`, scoped by the account so
+/// the keys of different clients never collide.
+#[inline]
+pub fn signup_idempotency_key(account_id: u64, idempotency_key: &str) -> String {
+    format!("{}{}-{}", SIGNUP_IDEMPOTENCY, account_id, idempotency_key)
+}
+
+/// the friends of `user_id`, mirrored from the relationships by api for the other services.
+#[inline]
+pub fn contacts_key(user_id: u64) -> String {
+    format!("{}{}", CONTACTS, user_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        add_friend_key, check_code_key, contacts_key, dead_letter_key, join_group_key,
+        last_online_time_key, last_read_key, msg_cache_key, offline_msg_key, presence_key,
+        quota_group_key, quota_key, signup_idempotency_key, user_inbox_key, user_node_map_key,
+        user_token_key,
+    };
+
+    #[test]
+    fn test_keys() {
+        assert_eq!(user_token_key(123), "USER_TOKEN_123");
+        assert_eq!(join_group_key(1, 2), "JOIN_GROUP_1-2");
+        assert_eq!(check_code_key(2), "CHECK_CODE_2");
+        assert_eq!(add_friend_key(1, 2), "ADD_FRIEND_1-2");
+        assert_eq!(last_online_time_key(1), "LAST_ONLINE_TIME_1");
+        assert_eq!(last_read_key(2, 1), "LAST_READ_2-1");
+        assert_eq!(user_inbox_key(1), "USER_INBOX_1");
+        assert_eq!(msg_cache_key(2, 1), "MSG_CACHE_1-2");
+        assert_eq!(msg_cache_key(1, 2), msg_cache_key(2, 1));
+        assert_eq!(msg_cache_key(3, 3), "MSG_CACHE_3-3");
+        assert_eq!(offline_msg_key(1), "OFFLINE_MSG_1");
+        assert_eq!(quota_key(1), "QUOTA_1");
+        assert_eq!(quota_group_key(1), "QUOTA_GROUP_1");
+        assert_eq!(presence_key(1), "{PRESENCE}_1");
+        assert_eq!(dead_letter_key(7), "DEAD_LETTER_7");
+        assert_eq!(user_node_map_key(1), "USER_NODE_MAP_1");
+        assert_eq!(signup_idempotency_key(1, "abc"), "SIGNUP_IDEMPOTENCY_1-abc");
+        assert_eq!(contacts_key(1), "CONTACTS_1");
+    }
+}
@@ -1 +1,2 @@
-pub mod redis_ops;
\ No newline at end of file
+pub mod keys;
+pub mod redis_ops;
@@ -1,19 +1,61 @@
-use std::{any::Any, net::SocketAddr};
+use std::{
+    any::Any,
+    future::Future,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
-use crate::{net::GenericParameter, Result};
+use crate::{net::GenericParameter, util::backoff::Backoff, Result};
 
-use anyhow::anyhow;
-use redis::{FromRedisValue, RedisResult, ToRedisArgs};
+use redis::{ErrorKind, FromRedisValue, RedisError, RedisResult, ToRedisArgs};
 use redis_cluster_async::{Client, Connection};
+use tokio::sync::Mutex;
+use tracing::{error, warn};
 
 /// the clone costs for Connection is cheap.
 #[derive(Clone)]
 pub struct RedisOps {
     connection: Connection,
+    /// set when the underlying link is found broken, so the pool can replace it.
+    broken: Arc<AtomicBool>,
 }
 
 impl RedisOps {
     pub async fn connect(addrs: Vec<SocketAddr>, password_list: Option<Vec<String>>) -> Result<RedisOps> {
+        let connection = Client::open(Self::addresses(addrs, password_list))?
+            .get_connection()
+            .await?;
+        Ok(RedisOps::new(connection))
+    }
+
+    pub(self) fn new(connection: Connection) -> Self {
+        RedisOps {
+            connection,
+            broken: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn is_broken(&self) -> bool {
+        self.broken.load(Ordering::Acquire)
+    }
+
+    /// the redis error is kept inside, so [`is_connection_error`] can tell it.
+    pub(self) fn error(&self, e: RedisError) -> anyhow::Error {
+        self.check(&e);
+        anyhow::Error::new(e)
+    }
+
+    pub(self) fn check(&self, e: &RedisError) {
+        if is_link_error(e) {
+            self.broken.store(true, Ordering::Release);
+        }
+    }
+
+    pub(self) fn addresses(addrs: Vec<SocketAddr>, password_list: Option<Vec<String>>) -> Vec<String> {
         let mut addresses = vec![];
         if password_list.is_some() && password_list.as_ref().unwrap().len() == addrs.len() {
             let passwords = password_list.as_ref().unwrap();
@@ -27,8 +69,7 @@ impl RedisOps {
                 addresses.push(format!("redis://{}", address));
             }
         }
-        let connection = Client::open(addresses)?.get_connection().await?;
-        Ok(RedisOps { connection })
+        addresses
     }
 
     pub async fn set<T: ToRedisArgs>(&mut self, key: &str, value: &T) -> Result<()> {
@@ -39,7 +80,7 @@ impl RedisOps {
             .await;
         match res {
             Ok(_) => Ok(()),
-            Err(e) => Err(anyhow!(e.to_string())),
+            Err(e) => Err(self.error(e)),
         }
     }
 
@@ -57,7 +98,36 @@ impl RedisOps {
             .await;
         match res {
             Ok(_) => Ok(()),
-            Err(e) => Err(anyhow!(e.to_string())),
+            Err(e) => Err(self.error(e)),
+        }
+    }
+
+    /// set only when the key doesn't exist, return whether it's set.
+    pub async fn set_nx_exp<T: ToRedisArgs>(
+        &mut self,
+        key: &str,
+        value: &T,
+        exp: std::time::Duration,
+    ) -> Result<bool> {
+        let res: RedisResult<Option<String>> = redis::cmd("SET")
+            .arg(key)
+            .arg(value)
+            .arg("NX")
+            .arg("PX")
+            .arg(exp.as_millis() as u64)
+            .query_async(&mut self.connection)
+            .await;
+        match res {
+            Ok(v) => Ok(v.is_some()),
+            Err(e) => Err(self.error(e)),
+        }
+    }
+
+    pub async fn ping(&mut self) -> Result<()> {
+        let res: RedisResult<String> = redis::cmd("PING").query_async(&mut self.connection).await;
+        match res {
+            Ok(_) => Ok(()),
+            Err(e) => Err(self.error(e)),
         }
     }
 
@@ -68,7 +138,22 @@ impl RedisOps {
             .await;
         match res {
             Ok(v) => Ok(v),
-            Err(e) => Err(anyhow!(e.to_string())),
+            Err(e) => Err(self.error(e)),
+        }
+    }
+
+    /// the values of `keys` in order, on a cluster they must share a slot by a hash tag.
+    pub async fn mget<T: FromRedisValue>(&mut self, keys: &[String]) -> Result<Vec<T>> {
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+        let res: RedisResult<Vec<T>> = redis::cmd("MGET")
+            .arg(keys)
+            .query_async(&mut self.connection)
+            .await;
+        match res {
+            Ok(v) => Ok(v),
+            Err(e) => Err(self.error(e)),
         }
     }
 
@@ -79,7 +164,7 @@ impl RedisOps {
             .await;
         match res {
             Ok(_) => Ok(()),
-            Err(e) => Err(anyhow!(e.to_string())),
+            Err(e) => Err(self.error(e)),
         }
     }
 
@@ -97,7 +182,7 @@ impl RedisOps {
             .await;
         match res {
             Ok(_) => Ok(()),
-            Err(e) => Err(anyhow!(e.to_string())),
+            Err(e) => Err(self.error(e)),
         }
     }
 
@@ -113,7 +198,7 @@ impl RedisOps {
             .await;
         match res {
             Ok(v) => Ok(v),
-            Err(e) => Err(anyhow!(e.to_string())),
+            Err(e) => Err(self.error(e)),
         }
     }
 
@@ -164,7 +249,7 @@ impl RedisOps {
         };
         match res {
             Ok(v) => Ok(v),
-            Err(e) => Err(anyhow!(e.to_string())),
+            Err(e) => Err(self.error(e)),
         }
     }
 
@@ -218,7 +303,7 @@ impl RedisOps {
         };
         match res {
             Ok(v) => Ok(v),
-            Err(e) => Err(anyhow!(e.to_string())),
+            Err(e) => Err(self.error(e)),
         }
     }
 
@@ -231,7 +316,7 @@ impl RedisOps {
             .await;
         match res {
             Ok(_) => Ok(()),
-            Err(e) => Err(anyhow!(e.to_string())),
+            Err(e) => Err(self.error(e)),
         }
     }
 
@@ -244,7 +329,7 @@ impl RedisOps {
             .await;
         match res {
             Ok(_) => Ok(()),
-            Err(e) => Err(anyhow!(e.to_string())),
+            Err(e) => Err(self.error(e)),
         }
     }
 
@@ -256,7 +341,30 @@ impl RedisOps {
             .await;
         match res {
             Ok(_) => Ok(()),
-            Err(e) => Err(anyhow!(e.to_string())),
+            Err(e) => Err(self.error(e)),
+        }
+    }
+
+    pub async fn remove_set<T: ToRedisArgs>(&mut self, key: &str, val: &T) -> Result<()> {
+        let res: RedisResult<()> = redis::cmd("SREM")
+            .arg(key)
+            .arg(val)
+            .query_async(&mut self.connection)
+            .await;
+        match res {
+            Ok(_) => Ok(()),
+            Err(e) => Err(self.error(e)),
+        }
+    }
+
+    pub async fn set_members<T: FromRedisValue>(&mut self, key: &str) -> Result<T> {
+        let res: RedisResult<T> = redis::cmd("SMEMBERS")
+            .arg(key)
+            .query_async(&mut self.connection)
+            .await;
+        match res {
+            Ok(v) => Ok(v),
+            Err(e) => Err(self.error(e)),
         }
     }
 
@@ -267,7 +375,10 @@ impl RedisOps {
             .await;
         match res {
             Ok(_) => Ok(()),
-            Err(e) => Err(e),
+            Err(e) => {
+                self.check(&e);
+                Err(e)
+            }
         }
     }
 
@@ -278,7 +389,7 @@ impl RedisOps {
             .await;
         match res {
             Ok(v) => Ok(v),
-            Err(e) => Err(anyhow!(e.to_string())),
+            Err(e) => Err(self.error(e)),
         }
     }
 
@@ -289,7 +400,7 @@ impl RedisOps {
             .await;
         match res {
             Ok(v) => Ok(v),
-            Err(e) => Err(anyhow!(e.to_string())),
+            Err(e) => Err(self.error(e)),
         }
     }
 
@@ -312,7 +423,7 @@ impl RedisOps {
             .await;
         match res {
             Ok(v) => Ok(v),
-            Err(e) => Err(anyhow!(e.to_string())),
+            Err(e) => Err(self.error(e)),
         }
     }
 }
@@ -327,11 +438,231 @@ impl GenericParameter for RedisOps {
     }
 }
 
+/// a fixed size pool of multiplexed connections, broken ones are replaced when borrowed.
+pub struct RedisPool {
+    client: Client,
+    slots: Vec<Mutex<RedisOps>>,
+    next: AtomicUsize,
+}
+
+impl RedisPool {
+    pub async fn new(
+        addrs: Vec<SocketAddr>,
+        password_list: Option<Vec<String>>,
+        size: usize,
+    ) -> Result<RedisPool> {
+        let client = Client::open(RedisOps::addresses(addrs, password_list))?;
+        let mut slots = Vec::with_capacity(size.max(1));
+        for _ in 0..size.max(1) {
+            slots.push(Mutex::new(RedisOps::new(client.get_connection().await?)));
+        }
+        Ok(RedisPool {
+            client,
+            slots,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    /// the returned ops can be dropped freely, it never holds the slot.
+    pub async fn get(&self) -> RedisOps {
+        let index = next_slot(&self.next, self.slots.len());
+        let mut slot = self.slots[index].lock().await;
+        if slot.is_broken() {
+            match self.client.get_connection().await {
+                Ok(connection) => *slot = RedisOps::new(connection),
+                // keep the broken one, the caller will get an error from it rather than a panic here.
+                Err(e) => error!("redis reconnect failed: {}", e),
+            }
+        }
+        slot.clone()
+    }
+
+    pub fn size(&self) -> usize {
+        self.slots.len()
+    }
+}
+
+pub(self) fn next_slot(next: &AtomicUsize, size: usize) -> usize {
+    next.fetch_add(1, Ordering::Relaxed) % size
+}
+
+/// the cluster client reports a lost link by the kind only, without an io error inside.
+pub(self) fn is_link_error(e: &RedisError) -> bool {
+    e.kind() == ErrorKind::IoError
+        || e.is_io_error()
+        || e.is_connection_dropped()
+        || e.is_connection_refusal()
+        || e.is_timeout()
+}
+
+/// true if `e` came from the link to redis rather than from the command, a nil result or a
+/// wrong type is never one.
+pub fn is_connection_error(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<RedisError>().map_or(false, is_link_error)
+}
+
+/// true if the same command may succeed later, a broken link or a cluster being failed over.
+pub fn is_transient_error(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<RedisError>().map_or(false, |e| {
+        is_link_error(e)
+            || matches!(
+                e.kind(),
+                ErrorKind::TryAgain
+                    | ErrorKind::ClusterDown
+                    | ErrorKind::MasterDown
+                    | ErrorKind::BusyLoadingError
+            )
+    })
+}
+
+/// run `op` up to `attempts` times while it fails by [`is_transient_error`], with a short
+/// backoff between, other errors are returned at once.
+///
+/// `op` should borrow its ops from the pool each time, so a broken link is replaced on retry.
+pub async fn redis_retry<T, F, Fut>(mut op: F, attempts: usize) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut backoff = Backoff::new(
+        Duration::from_millis(20),
+        Duration::from_millis(200),
+        Duration::from_secs(2),
+    );
+    let mut attempt = 1;
+    loop {
+        match op().await {
+            Err(e) if attempt < attempts && is_transient_error(&e) => {
+                let delay = match backoff.next_delay() {
+                    Some(delay) => delay,
+                    None => return Err(e),
+                };
+                warn!("redis failed: {}, retry in {:?}.", e, delay);
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            res => return res,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::cache::redis_ops::RedisOps;
+    use crate::cache::redis_ops::{
+        is_connection_error, next_slot, redis_retry, RedisOps, RedisPool,
+    };
     use crate::Result;
+    use redis::{ErrorKind, RedisError};
     use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_next_slot() {
+        let next = AtomicUsize::new(usize::MAX - 3);
+        let mut used = [0usize; 4];
+        for _ in 0..10000 {
+            used[next_slot(&next, 4)] += 1;
+        }
+        assert!(used.iter().all(|x| *x > 0));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the redis cluster of docker-compose"]
+    async fn test_pool_not_exhausted() -> Result<()> {
+        let addresses = vec!["127.0.0.1:16379", "127.0.0.1:16380", "127.0.0.1:16381"]
+            .iter()
+            .map(|x| x.parse().expect("parse error"))
+            .collect::<Vec<SocketAddr>>();
+        let pool = Arc::new(
+            RedisPool::new(addresses, Some(vec!["Redis.123456".to_string(); 3]), 2).await?,
+        );
+        let mut tasks = Vec::new();
+        for i in 0..64 {
+            let pool = pool.clone();
+            tasks.push(tokio::spawn(async move {
+                for j in 0..64 {
+                    // the ops borrowed are held across the call, more than the pool size at once.
+                    let mut redis_ops = pool.get().await;
+                    redis_ops
+                        .set(&format!("test-pool-{}", i), &j.to_string())
+                        .await?;
+                }
+                Result::Ok(())
+            }));
+        }
+        tokio::time::timeout(Duration::from_secs(30), async {
+            for task in tasks {
+                task.await??;
+            }
+            Result::Ok(())
+        })
+        .await??;
+        assert_eq!(pool.size(), 2);
+        Ok(())
+    }
+
+    /// a set against a link which drops the first call.
+    struct FlakyRedis {
+        calls: AtomicUsize,
+        kind: ErrorKind,
+    }
+
+    impl FlakyRedis {
+        async fn set(&self) -> Result<()> {
+            if self.calls.fetch_add(1, Ordering::AcqRel) == 0 {
+                return Err(RedisError::from((self.kind, "flaky")).into());
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_redis_retry() {
+        let redis = FlakyRedis {
+            calls: AtomicUsize::new(0),
+            kind: ErrorKind::IoError,
+        };
+        redis_retry(|| redis.set(), 3).await.unwrap();
+        assert_eq!(redis.calls.load(Ordering::Acquire), 2);
+
+        // a real io error inside.
+        let calls = AtomicUsize::new(0);
+        let set = || async {
+            if calls.fetch_add(1, Ordering::AcqRel) == 0 {
+                let e = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+                return Err(RedisError::from(e).into());
+            }
+            Ok(())
+        };
+        redis_retry(set, 3).await.unwrap();
+        assert_eq!(calls.load(Ordering::Acquire), 2);
+
+        // the cluster is failing over, the link is fine.
+        let redis = FlakyRedis {
+            calls: AtomicUsize::new(0),
+            kind: ErrorKind::ClusterDown,
+        };
+        redis_retry(|| redis.set(), 3).await.unwrap();
+        assert_eq!(redis.calls.load(Ordering::Acquire), 2);
+
+        // not a link failure, so never retried.
+        let redis = FlakyRedis {
+            calls: AtomicUsize::new(0),
+            kind: ErrorKind::TypeError,
+        };
+        let e = redis_retry(|| redis.set(), 3).await.unwrap_err();
+        assert!(!is_connection_error(&e));
+        assert_eq!(redis.calls.load(Ordering::Acquire), 1);
+
+        let redis = FlakyRedis {
+            calls: AtomicUsize::new(0),
+            kind: ErrorKind::IoError,
+        };
+        assert!(redis_retry(|| redis.set(), 1).await.is_err());
+        assert_eq!(redis.calls.load(Ordering::Acquire), 1);
+    }
 
     #[tokio::test]
     async fn test() -> Result<()> {
@@ -50,6 +50,11 @@ pub enum Type {
     Error = 100,
     BeOffline = 101,
     InternalError = 102,
+    /// sent by client after connected, asking for msgs stored when it was offline.
+    Sync = 103,
+    /// sent by server to the contacts of a user who comes online or goes offline,
+    /// the sender is the user and the payload is `online` or `offline`.
+    Presence = 104,
     /// business part
     /// some types may derived by user but send between server, those types are also viewed as business type.
     SystemMessage = 128,
@@ -90,6 +95,11 @@ pub struct Head {
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
 pub struct Msg(pub Vec<u8>);
 
+/// a borrowed view of a msg laid out the same as [`Msg`], for reading a received buffer
+/// without copying it, see [`MsgRef::parse`].
+#[derive(Debug, Clone, Copy)]
+pub struct MsgRef<'a>(pub(crate) &'a [u8]);
+
 #[derive(
     serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive,
 )]
@@ -119,6 +129,29 @@ pub enum ReqwestResourceID {
     MessageConfigHotReload = 16,
     AssignMQProcessor = 17,
     UnassignMQProcessor = 18,
+    /// use for acquire a contiguous block of seqnum from `seqnum` service in one round trip.
+    SeqnumAllocBlock = 19,
+    /// use for operators to inspect the recent dead letters of `message` service.
+    DeadLetters = 20,
+    /// use for `scheduler` to push a delta of the runtime adjustable settings to a node,
+    /// applied without restarting it.
+    ConfigPush = 21,
+    /// use for operators to inspect the metrics of a node, answered with a json object.
+    Metrics = 22,
+    /// answered by `seqnum` service instead of the requested resource id when it fails,
+    /// see `SeqnumError` for each of them.
+    SeqnumNotRegistered = 23,
+    SeqnumExhausted = 24,
+    SeqnumInvalidRequest = 25,
+    /// use for `seqnum` service to refresh its status and load on scheduler periodically,
+    /// answered with `SeqnumNodeRegister` if the node is not registered.
+    SeqnumNodeHeartbeat = 26,
+    /// answered instead of the requested resource id when no handler serves it, with the
+    /// requested id as payload, see `UnsupportedResource`.
+    Unsupported = 27,
+    /// use for `message` service to tell scheduler it's alive periodically, answered with
+    /// `MessageNodeRegister` if the node is not registered.
+    MessageNodeHeartbeat = 28,
 }
 
 /// a reqwest's layout may look like:
@@ -155,7 +188,12 @@ pub enum ServerType {
 
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
 pub struct ServerLoad {
+    /// number of connections currently served.
+    #[serde(default)]
+    pub connections: u32,
+    /// (cpu number, usage in percent)
     pub cpu: (u32, f32),
+    /// (total memory in MiB, usage in percent)
     pub mem: (u32, f32),
     pub net: (u32, f32),
     pub disk: (u32, f32),
@@ -181,3 +219,11 @@ pub struct ServerInfo {
     pub typ: ServerType,
     pub load: Option<ServerLoad>,
 }
+
+/// the part of `ServerInfo` that changes after registration.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct ServerHeartbeat {
+    pub id: u32,
+    pub status: ServerStatus,
+    pub load: Option<ServerLoad>,
+}
@@ -1,6 +1,9 @@
 use std::{
+    convert::Infallible,
     fmt::{Display, Formatter},
-    io::Read, sync::Arc,
+    io::Read,
+    str::FromStr,
+    sync::Arc,
 };
 
 use byteorder::{BigEndian, ByteOrder};
@@ -9,10 +12,18 @@ use num_traits::FromPrimitive;
 use redis::{ErrorKind, FromRedisValue, RedisError, RedisResult, RedisWrite, ToRedisArgs, Value};
 use rusqlite::{types::ToSqlOutput, ToSql};
 
-use crate::{Result, util::timestamp};
+use crate::{
+    util::{
+        compress::{compress, decompress},
+        time::now_millis,
+    },
+    Result,
+};
 
 
-use super::{Head, Msg, ReqwestMsg, ReqwestResourceID, Type, HEAD_LEN};
+use super::{
+    Head, Msg, MsgRef, ReqwestMsg, ReqwestResourceID, Type, GROUP_ID_THRESHOLD, HEAD_LEN,
+};
 
 pub(self) const BIT_MASK_LEFT_46: u64 = 0xFFFF_C000_0000_0000;
 pub(self) const BIT_MASK_RIGHT_46: u64 = 0x0000_3FFF_FFFF_FFFF;
@@ -22,6 +33,18 @@ pub(self) const BIT_MASK_LEFT_12: u64 = 0xFFF0_0000_0000_0000;
 pub(self) const BIT_MASK_RIGHT_12: u64 = 0x000F_FFFF_FFFF_FFFF;
 
 pub const MSG_DELIMITER: [u8; 4] = [255, 255, 255, 255];
+/// the highest bit of version marks the payload as compressed, see [`Msg::compress_payload`].
+pub const VERSION_COMPRESSED: u32 = 1 << 17;
+/// the second highest bit of version marks the payload as encrypted end to end, see
+/// [`crate::util::cipher::PayloadCipher`].
+pub const VERSION_ENCRYPTED: u32 = 1 << 16;
+/// the version of the head laid out in [`HEAD_LEN`] bytes, the flag bits above are not part of it.
+pub const HEAD_VERSION: u32 = 0;
+/// the most bytes the 14 bit payload length can describe.
+pub(self) const PAYLOAD_LENGTH_MAX: usize = (1 << 14) - 1;
+/// payloads of the acks made by [`Msg::flow_control`], the real ones carry digits only.
+pub(self) const FLOW_CONTROL_PAUSE: &[u8] = b"pause";
+pub(self) const FLOW_CONTROL_RESUME: &[u8] = b"resume";
 
 impl From<u16> for Type {
     #[inline]
@@ -61,36 +84,46 @@ impl Default for Type {
 
 impl Display for Type {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                Type::Ack => "Ack",
-                Type::Text => "Text",
-                Type::Meme => "Meme",
-                Type::File => "File",
-                Type::Image => "Image",
-                Type::Video => "Video",
-                Type::Audio => "Audio",
-                Type::Edit => "Edit",
-                Type::Withdraw => "Withdraw",
-                Type::Auth => "Auth",
-                Type::Ping => "Ping",
-                Type::Echo => "Echo",
-                Type::Error => "Error",
-                Type::BeOffline => "Offline",
-                Type::InternalError => "InternalError",
-                Type::SystemMessage => "SysNotification",
-                Type::AddFriend => "AddFriend",
-                Type::RemoveFriend => "RemoveFriend",
-                Type::JoinGroup => "JoinGroup",
-                Type::LeaveGroup => "LeaveGroup",
-                Type::Noop => "Noop",
-                Type::Close => "Close",
-                Type::Compressed => "Compressed",
-                _ => "NA",
-            }
-        )
+        f.write_str(self.as_str())
+    }
+}
+
+/// unknown names fall back to `Type::NA`, the same as unknown values in `From<u16>`.
+impl FromStr for Type {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match s {
+            "ack" => Type::Ack,
+            "text" => Type::Text,
+            "meme" => Type::Meme,
+            "file" => Type::File,
+            "image" => Type::Image,
+            "video" => Type::Video,
+            "audio" => Type::Audio,
+            "edit" => Type::Edit,
+            "withdraw" => Type::Withdraw,
+            "auth" => Type::Auth,
+            "ping" => Type::Ping,
+            "pong" => Type::Pong,
+            "echo" => Type::Echo,
+            "error" => Type::Error,
+            "be_offline" => Type::BeOffline,
+            "internal_error" => Type::InternalError,
+            "sync" => Type::Sync,
+            "presence" => Type::Presence,
+            "system_message" => Type::SystemMessage,
+            "add_friend" => Type::AddFriend,
+            "remove_friend" => Type::RemoveFriend,
+            "join_group" => Type::JoinGroup,
+            "leave_group" => Type::LeaveGroup,
+            "remote_invoke" => Type::RemoteInvoke,
+            "set_relationship" => Type::SetRelationship,
+            "noop" => Type::Noop,
+            "close" => Type::Close,
+            "compressed" => Type::Compressed,
+            _ => Type::NA,
+        })
     }
 }
 
@@ -99,6 +132,111 @@ impl Type {
     pub fn value(&self) -> u16 {
         *self as u16
     }
+
+    /// what users send each other, forwarded as it is and kept for offline receivers.
+    ///
+    /// every variant is listed, so a new one can't be left out by accident.
+    pub fn is_content(&self) -> bool {
+        match self {
+            Type::Text | Type::Meme | Type::File | Type::Image | Type::Video | Type::Audio => true,
+            Type::NA
+            | Type::Ack
+            | Type::Edit
+            | Type::Withdraw
+            | Type::Auth
+            | Type::Ping
+            | Type::Pong
+            | Type::Echo
+            | Type::Error
+            | Type::BeOffline
+            | Type::InternalError
+            | Type::Sync
+            | Type::Presence
+            | Type::SystemMessage
+            | Type::AddFriend
+            | Type::RemoveFriend
+            | Type::JoinGroup
+            | Type::LeaveGroup
+            | Type::RemoteInvoke
+            | Type::SetRelationship
+            | Type::Noop
+            | Type::Close
+            | Type::Compressed => false,
+        }
+    }
+
+    /// the ack and the logic part, read by the nodes to keep the connection going rather than
+    /// shown to users.
+    ///
+    /// edits, withdraws and the business part are neither content nor control.
+    pub fn is_control(&self) -> bool {
+        match self {
+            Type::Ack
+            | Type::Auth
+            | Type::Ping
+            | Type::Pong
+            | Type::Echo
+            | Type::Error
+            | Type::BeOffline
+            | Type::InternalError
+            | Type::Sync
+            | Type::Presence => true,
+            Type::NA
+            | Type::Text
+            | Type::Meme
+            | Type::File
+            | Type::Image
+            | Type::Video
+            | Type::Audio
+            | Type::Edit
+            | Type::Withdraw
+            | Type::SystemMessage
+            | Type::AddFriend
+            | Type::RemoveFriend
+            | Type::JoinGroup
+            | Type::LeaveGroup
+            | Type::RemoteInvoke
+            | Type::SetRelationship
+            | Type::Noop
+            | Type::Close
+            | Type::Compressed => false,
+        }
+    }
+
+    /// stable lowercase name, used for logging and config.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Type::NA => "na",
+            Type::Ack => "ack",
+            Type::Text => "text",
+            Type::Meme => "meme",
+            Type::File => "file",
+            Type::Image => "image",
+            Type::Video => "video",
+            Type::Audio => "audio",
+            Type::Edit => "edit",
+            Type::Withdraw => "withdraw",
+            Type::Auth => "auth",
+            Type::Ping => "ping",
+            Type::Pong => "pong",
+            Type::Echo => "echo",
+            Type::Error => "error",
+            Type::BeOffline => "be_offline",
+            Type::InternalError => "internal_error",
+            Type::Sync => "sync",
+            Type::Presence => "presence",
+            Type::SystemMessage => "system_message",
+            Type::AddFriend => "add_friend",
+            Type::RemoveFriend => "remove_friend",
+            Type::JoinGroup => "join_group",
+            Type::LeaveGroup => "leave_group",
+            Type::RemoteInvoke => "remote_invoke",
+            Type::SetRelationship => "set_relationship",
+            Type::Noop => "noop",
+            Type::Close => "close",
+            Type::Compressed => "compressed",
+        }
+    }
 }
 
 impl ToSql for Type {
@@ -206,6 +344,16 @@ impl Head {
         (version_with_sender >> 46) as u32
     }
 
+    /// the length of head laid out by its version, a version not known is an error rather than
+    /// being parsed by a wrong layout.
+    pub fn head_len(buf: &[u8]) -> Result<usize> {
+        match Self::version(buf) & !(VERSION_COMPRESSED | VERSION_ENCRYPTED) {
+            HEAD_VERSION => Ok(HEAD_LEN),
+            // a new layout is dispatched here, and the current one kept for the old peers.
+            version => Err(anyhow!("unknown msg head version: {}", version)),
+        }
+    }
+
     #[inline]
     pub fn set_version(buf: &mut [u8], version: u32) {
         let version_with_sender = BigEndian::read_u64(&buf[0..8]);
@@ -342,6 +490,99 @@ impl From<&[u8]> for Msg {
     }
 }
 
+impl<'a> MsgRef<'a> {
+    /// borrow `buf` as a msg, the lengths in head are checked against the buffer so the
+    /// accessors never go out of bounds.
+    pub fn parse(buf: &'a [u8]) -> Result<Self> {
+        if buf.len() < HEAD_LEN {
+            return Err(anyhow!("msg buffer too short: {}", buf.len()));
+        }
+        let len = Head::head_len(buf)? + Head::payload_length(buf) + Head::extension_length(buf);
+        if buf.len() < len {
+            return Err(anyhow!(
+                "msg buffer too short: expected {}, got {}",
+                len,
+                buf.len()
+            ));
+        }
+        Ok(Self(&buf[..len]))
+    }
+
+    #[inline]
+    pub fn as_slice(&self) -> &'a [u8] {
+        self.0
+    }
+
+    #[inline]
+    pub fn extension_length(&self) -> usize {
+        Head::extension_length(self.0)
+    }
+
+    #[inline]
+    pub fn payload_length(&self) -> usize {
+        Head::payload_length(self.0)
+    }
+
+    #[inline]
+    pub fn typ(&self) -> Type {
+        Head::typ(self.0)
+    }
+
+    #[inline]
+    pub fn sender(&self) -> u64 {
+        Head::sender(self.0)
+    }
+
+    #[inline]
+    pub fn receiver(&self) -> u64 {
+        Head::receiver(self.0)
+    }
+
+    #[inline]
+    pub fn node_id(&self) -> u32 {
+        Head::node_id(self.0)
+    }
+
+    #[inline]
+    pub fn timestamp(&self) -> u64 {
+        Head::timestamp(self.0)
+    }
+
+    #[inline]
+    pub fn seqnum(&self) -> u64 {
+        Head::seq_num(self.0)
+    }
+
+    #[inline]
+    pub fn version(&self) -> u32 {
+        Head::version(self.0)
+    }
+
+    #[inline]
+    pub fn payload(&self) -> &'a [u8] {
+        &self.0[HEAD_LEN..HEAD_LEN + self.payload_length()]
+    }
+
+    #[inline]
+    pub fn extension(&self) -> &'a [u8] {
+        let payload_length = self.payload_length();
+        &self.0[HEAD_LEN + payload_length..HEAD_LEN + payload_length + self.extension_length()]
+    }
+
+    /// copy into an owned msg, for when it has to outlive the buffer.
+    #[inline]
+    pub fn to_msg(&self) -> Msg {
+        Msg::from(self.0)
+    }
+}
+
+impl<'a> From<&'a Msg> for MsgRef<'a> {
+    #[inline]
+    fn from(msg: &'a Msg) -> Self {
+        Self(msg.as_slice())
+    }
+}
+
 impl ToRedisArgs for Msg {
     fn write_redis_args<W>(&self, out: &mut W)
     where
@@ -402,7 +643,7 @@ impl Msg {
             sender: 0,
             receiver: 0,
             node_id: 0,
-            timestamp: timestamp(),
+            timestamp: now_millis(),
             seqnum: 0,
             version: 0,
         };
@@ -450,6 +691,18 @@ impl Msg {
         Head::typ(self.as_slice())
     }
 
+    /// see [`Type::is_content`].
+    #[inline]
+    pub fn is_content(&self) -> bool {
+        self.typ().is_content()
+    }
+
+    /// see [`Type::is_control`].
+    #[inline]
+    pub fn is_control(&self) -> bool {
+        self.typ().is_control()
+    }
+
     #[inline]
     pub fn sender(&self) -> u64 {
         Head::sender(self.as_slice())
@@ -460,6 +713,20 @@ impl Msg {
         Head::receiver(self.as_slice())
     }
 
+    /// the conversation the seq_num is allocated in, the group for a group msg, or both users
+    /// in ascending order, so the two directions share it.
+    #[inline]
+    pub fn conversation(&self) -> (u64, u64) {
+        let (sender, receiver) = (self.sender(), self.receiver());
+        if receiver >= GROUP_ID_THRESHOLD {
+            (receiver, receiver)
+        } else if sender < receiver {
+            (sender, receiver)
+        } else {
+            (receiver, sender)
+        }
+    }
+
     #[inline]
     pub fn node_id(&self) -> u32 {
         Head::node_id(self.as_slice())
@@ -559,6 +826,17 @@ impl Msg {
         }
     }
 
+    /// the payload as text, only msgs carrying text are accepted.
+    pub fn payload_str(&self) -> Result<&str> {
+        let typ = self.typ();
+        match typ {
+            Type::Text | Type::Edit | Type::Error => {}
+            _ => return Err(anyhow!("payload of {} msg is not text.", typ)),
+        }
+        std::str::from_utf8(self.payload())
+            .map_err(|e| anyhow!("payload of {} msg is not valid utf-8: {}", typ, e))
+    }
+
     #[inline]
     pub fn payload_mut(&mut self) -> &mut [u8] {
         let payload_length = self.payload_length();
@@ -603,7 +881,7 @@ impl Msg {
             sender,
             receiver,
             node_id,
-            timestamp: timestamp(),
+            timestamp: now_millis(),
             seqnum: 0,
             version: 0,
         };
@@ -617,6 +895,14 @@ impl Msg {
         Self(buf)
     }
 
+    /// tell `receiver` that `user_id` comes online or goes offline.
+    pub fn presence(user_id: u64, receiver: u64, node_id: u32, online: bool) -> Self {
+        let payload: &[u8] = if online { b"online" } else { b"offline" };
+        let mut msg = Self::raw(user_id, receiver, node_id, payload);
+        msg.set_type(Type::Presence);
+        msg
+    }
+
     #[inline]
     pub fn pong(sender: u64, receiver: u64, node_id: u32) -> Self {
         let inner_head = InnerHead {
@@ -626,7 +912,7 @@ impl Msg {
             sender,
             receiver,
             node_id,
-            timestamp: timestamp(),
+            timestamp: now_millis(),
             seqnum: 0,
             version: 0,
         };
@@ -649,7 +935,7 @@ impl Msg {
             sender,
             receiver,
             node_id,
-            timestamp: timestamp(),
+            timestamp: now_millis(),
             seqnum: 0,
             version: 0,
         };
@@ -672,7 +958,7 @@ impl Msg {
             sender,
             receiver,
             node_id,
-            timestamp: timestamp(),
+            timestamp: now_millis(),
             seqnum: 0,
             version: 0,
         };
@@ -695,7 +981,7 @@ impl Msg {
             sender,
             receiver,
             node_id,
-            timestamp: timestamp(),
+            timestamp: now_millis(),
             seqnum: 0,
             version: 0,
         };
@@ -722,7 +1008,7 @@ impl Msg {
             sender: self.sender(),
             receiver: self.receiver(),
             node_id,
-            timestamp: timestamp(),
+            timestamp: now_millis(),
             seqnum: self.seqnum(),
             version: 0,
         };
@@ -736,6 +1022,49 @@ impl Msg {
         Self(buf)
     }
 
+    /// the ack a node answers a msg of a client with, addressed back to the client and carrying
+    /// the seq_num assigned to the msg.
+    pub fn generate_server_ack(&self, node_id: u32, client_timestamp: u64, seqnum: u64) -> Self {
+        let mut ack = self.generate_ack(node_id, client_timestamp);
+        ack.set_sender(node_id as u64);
+        ack.set_receiver(self.sender());
+        ack.set_seqnum(seqnum);
+        ack
+    }
+
+    /// the client timestamp echoed by an ack made by [`Msg::generate_ack`] or [`Msg::ack`], the
+    /// only field telling which msg of the client it acknowledges.
+    pub fn acked_timestamp(&self) -> Option<u64> {
+        if self.typ() != Type::Ack {
+            return None;
+        }
+        std::str::from_utf8(self.payload()).ok()?.parse().ok()
+    }
+
+    /// an ack asking the peer to pause sending on the stream if `pause`, or to resume it.
+    pub fn flow_control(pause: bool) -> Self {
+        let payload = if pause {
+            FLOW_CONTROL_PAUSE
+        } else {
+            FLOW_CONTROL_RESUME
+        };
+        let mut msg = Msg::raw(0, 0, 0, payload);
+        msg.set_type(Type::Ack);
+        msg
+    }
+
+    /// `Some(true)` to pause and `Some(false)` to resume if made by [`Msg::flow_control`].
+    pub fn flow_control_signal(&self) -> Option<bool> {
+        if self.typ() != Type::Ack {
+            return None;
+        }
+        match self.payload() {
+            FLOW_CONTROL_PAUSE => Some(true),
+            FLOW_CONTROL_RESUME => Some(false),
+            _ => None,
+        }
+    }
+
     #[inline]
     pub fn ack(client_timestamp: u64) -> Self {
         let time = client_timestamp.to_string();
@@ -746,7 +1075,7 @@ impl Msg {
             sender: 0,
             receiver: 0,
             node_id: 0,
-            timestamp: timestamp(),
+            timestamp: now_millis(),
             seqnum: 0,
             version: 0,
         };
@@ -769,7 +1098,7 @@ impl Msg {
             sender: 0,
             receiver: 0,
             node_id: 0,
-            timestamp: timestamp(),
+            timestamp: now_millis(),
             seqnum: 0,
             version: 0,
         };
@@ -792,7 +1121,7 @@ impl Msg {
             sender,
             receiver,
             node_id,
-            timestamp: timestamp(),
+            timestamp: now_millis(),
             seqnum: 0,
             version: 0,
         };
@@ -815,7 +1144,7 @@ impl Msg {
             sender: 0,
             receiver: 0,
             node_id: 0,
-            timestamp: timestamp(),
+            timestamp: now_millis(),
             seqnum: 0,
             version: 0,
         };
@@ -837,7 +1166,7 @@ impl Msg {
             sender,
             receiver,
             node_id,
-            timestamp: timestamp(),
+            timestamp: now_millis(),
             seqnum: 0,
             version: 0,
         };
@@ -865,7 +1194,7 @@ impl Msg {
             sender,
             receiver,
             node_id,
-            timestamp: timestamp(),
+            timestamp: now_millis(),
             seqnum: 0,
             version: 0,
         };
@@ -896,7 +1225,7 @@ impl Msg {
             sender: 0,
             receiver: 0,
             node_id: 0,
-            timestamp: timestamp(),
+            timestamp: now_millis(),
             seqnum: 0,
             version: 0,
         };
@@ -929,11 +1258,11 @@ impl Msg {
         let inner_head = InnerHead {
             extension_length: 0,
             payload_length: size as u16,
-            typ: Type::NA,
+            typ: Type::Compressed,
             sender: 0,
             receiver: 0,
             node_id: 0,
-            timestamp: timestamp(),
+            timestamp: now_millis(),
             seqnum: 0,
             version: 0,
         };
@@ -954,7 +1283,7 @@ impl Msg {
         let mut list = vec![];
         let mut index = 0;
         loop {
-            let mut head = Head::from(&self.payload()[index..HEAD_LEN]);
+            let mut head = Head::from(&self.payload()[index..index + HEAD_LEN]);
             let mut msg = Msg::pre_alloc(&mut head);
             let body_len = msg.as_mut_body().len();
             msg.as_mut_body().copy_from_slice(&self.payload()[index + HEAD_LEN..index + HEAD_LEN + body_len]);
@@ -966,6 +1295,58 @@ impl Msg {
         }
         list
     }
+
+    #[inline]
+    pub fn is_compressed(&self) -> bool {
+        self.version() & VERSION_COMPRESSED != 0
+    }
+
+    /// `None` if the payload is not longer than `threshold` or compressing doesn't make it shorter.
+    pub fn compress_payload(&self, threshold: usize) -> Option<Self> {
+        if self.is_compressed() || self.payload_length() <= threshold {
+            return None;
+        }
+        let payload = compress(self.payload()).ok()?;
+        if payload.len() >= self.payload_length() {
+            return None;
+        }
+        Some(self.with_payload(&payload, self.version() | VERSION_COMPRESSED))
+    }
+
+    pub fn decompress_payload(&self) -> Result<Self> {
+        let payload = decompress(self.payload(), PAYLOAD_LENGTH_MAX)?;
+        Ok(self.with_payload(&payload, self.version() & !VERSION_COMPRESSED))
+    }
+
+    #[inline]
+    pub fn is_encrypted(&self) -> bool {
+        self.version() & VERSION_ENCRYPTED != 0
+    }
+
+    /// a copy with the payload replaced by `ciphertext` and marked encrypted.
+    pub fn encrypted_with(&self, ciphertext: &[u8]) -> Result<Self> {
+        if ciphertext.len() > PAYLOAD_LENGTH_MAX {
+            return Err(anyhow!("encrypted payload too long: {}", ciphertext.len()));
+        }
+        Ok(self.with_payload(ciphertext, self.version() | VERSION_ENCRYPTED))
+    }
+
+    /// a copy with the payload replaced by `plaintext` and the encrypted mark cleared.
+    pub fn decrypted_with(&self, plaintext: &[u8]) -> Self {
+        self.with_payload(plaintext, self.version() & !VERSION_ENCRYPTED)
+    }
+
+    /// a copy with head and extension kept but payload replaced.
+    pub(self) fn with_payload(&self, payload: &[u8], version: u32) -> Self {
+        let mut buf = Vec::with_capacity(HEAD_LEN + payload.len() + self.extension_length());
+        buf.extend_from_slice(&self.as_slice()[..HEAD_LEN]);
+        buf.extend_from_slice(payload);
+        buf.extend_from_slice(self.extension());
+        let mut msg = Self(buf);
+        msg.set_payload_length(payload.len());
+        msg.set_version(version);
+        msg
+    }
 }
 
 impl Default for ReqwestMsg {
@@ -1109,6 +1490,16 @@ impl Display for ReqwestResourceID {
                 ReqwestResourceID::MessageConfigHotReload => "MessageConfigHotReload",
                 ReqwestResourceID::AssignMQProcessor => "AssignMQProcessor",
                 ReqwestResourceID::UnassignMQProcessor => "UnassignMQProcessor",
+                ReqwestResourceID::SeqnumAllocBlock => "SeqnumAllocBlock",
+                ReqwestResourceID::DeadLetters => "DeadLetters",
+                ReqwestResourceID::ConfigPush => "ConfigPush",
+                ReqwestResourceID::Metrics => "Metrics",
+                ReqwestResourceID::SeqnumNotRegistered => "SeqnumNotRegistered",
+                ReqwestResourceID::SeqnumExhausted => "SeqnumExhausted",
+                ReqwestResourceID::SeqnumInvalidRequest => "SeqnumInvalidRequest",
+                ReqwestResourceID::SeqnumNodeHeartbeat => "SeqnumNodeHeartbeat",
+                ReqwestResourceID::Unsupported => "Unsupported",
+                ReqwestResourceID::MessageNodeHeartbeat => "MessageNodeHeartbeat",
             }
         )
     }
@@ -1119,13 +1510,52 @@ impl ReqwestResourceID {
     pub fn value(&self) -> u16 {
         *self as u16
     }
+
+    /// every resource id, in the order of value.
+    pub fn all() -> impl Iterator<Item = ReqwestResourceID> {
+        (0..=u16::MAX).filter_map(FromPrimitive::from_u16)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::io::Read;
+    use std::{
+        alloc::{GlobalAlloc, Layout, System},
+        cell::Cell,
+        hint::black_box,
+        io::Read,
+        sync::Arc,
+    };
+
+    use crate::entity::{
+        msg::{InnerHead, HEAD_VERSION, VERSION_COMPRESSED, VERSION_ENCRYPTED},
+        Head, Msg, MsgRef, Type, HEAD_LEN, PAYLOAD_THRESHOLD,
+    };
+
+    /// count the allocations of the current thread, so tests running in parallel don't interfere.
+    struct CountingAlloc;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAlloc {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+            System.alloc(layout)
+        }
 
-    use crate::entity::{msg::InnerHead, Head, Msg, Type};
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAlloc = CountingAlloc;
+
+    fn allocations() -> usize {
+        ALLOCATIONS.with(|n| n.get())
+    }
 
     #[test]
     fn test() {
@@ -1177,4 +1607,182 @@ mod tests {
         let msg = Msg::text(1, 2, 3, "一只狗");
         println!("{:?}", msg.as_bytes());
     }
+
+    #[test]
+    fn test_compressed() {
+        let list = (0..100)
+            .map(|i| {
+                let mut msg = Msg::text(1, 2, 3, &format!("msg-{}", i));
+                msg.set_seqnum(i);
+                Arc::new(msg)
+            })
+            .collect::<Vec<_>>();
+        let (compressed, remain) = Msg::with_uncompressed(&list).unwrap();
+        assert_eq!(compressed.typ(), Type::Compressed);
+        assert!(remain.is_empty());
+        let unpacked = compressed.with_compressed();
+        assert_eq!(unpacked.len(), list.len());
+        for (i, msg) in unpacked.iter().enumerate() {
+            assert_eq!(msg.seqnum(), i as u64);
+            assert_eq!(msg.payload(), list[i].payload());
+        }
+    }
+
+    #[test]
+    fn test_type_name_round_trip() {
+        for value in 0..=u16::from(u8::MAX) {
+            let typ = Type::from(value);
+            if typ == Type::NA && value != 0 {
+                continue;
+            }
+            assert_eq!(typ.as_str().parse::<Type>().unwrap(), typ);
+            assert_eq!(typ.to_string(), typ.as_str());
+        }
+        assert_eq!(Type::NA.as_str(), "na");
+        assert_eq!("heartbeat".parse::<Type>().unwrap(), Type::NA);
+    }
+
+    #[test]
+    fn test_compress_payload() {
+        let payload = b"image ".repeat(PAYLOAD_THRESHOLD / 6);
+        let mut msg = Msg::raw2(1, 2, 3, &payload, b"ext");
+        msg.set_type(Type::Image);
+        msg.set_version(1);
+        assert!(msg.compress_payload(payload.len()).is_none());
+        let compressed = msg.compress_payload(1024).unwrap();
+        assert!(compressed.is_compressed());
+        assert!(compressed.as_slice().len() < msg.as_slice().len() / 10);
+        assert_eq!(compressed.extension(), b"ext");
+        let decompressed = compressed.decompress_payload().unwrap();
+        assert!(!decompressed.is_compressed());
+        assert_eq!(decompressed.as_slice(), msg.as_slice());
+        assert!(Msg::text(1, 2, 3, "hi").compress_payload(1).is_none());
+    }
+
+    #[test]
+    fn test_payload_str() {
+        let msg = Msg::text(1, 2, 0, "你好, prim");
+        assert_eq!(msg.payload_str().unwrap(), "你好, prim");
+        let mut msg = Msg::raw_payload(&vec![0xff, 0xfe, 0x41]);
+        msg.set_type(Type::Text);
+        assert!(msg
+            .payload_str()
+            .unwrap_err()
+            .to_string()
+            .contains("not valid utf-8"));
+        let mut msg = Msg::text(1, 2, 0, "hello");
+        msg.set_type(Type::Image);
+        assert!(msg.payload_str().unwrap_err().to_string().contains("not text"));
+    }
+
+    #[test]
+    fn test_type_category() {
+        let cases = [
+            (Type::NA, false, false),
+            (Type::Ack, false, true),
+            (Type::Text, true, false),
+            (Type::Meme, true, false),
+            (Type::File, true, false),
+            (Type::Image, true, false),
+            (Type::Video, true, false),
+            (Type::Audio, true, false),
+            (Type::Edit, false, false),
+            (Type::Withdraw, false, false),
+            (Type::Auth, false, true),
+            (Type::Ping, false, true),
+            (Type::Pong, false, true),
+            (Type::Echo, false, true),
+            (Type::Error, false, true),
+            (Type::BeOffline, false, true),
+            (Type::InternalError, false, true),
+            (Type::Sync, false, true),
+            (Type::Presence, false, true),
+            (Type::SystemMessage, false, false),
+            (Type::AddFriend, false, false),
+            (Type::RemoveFriend, false, false),
+            (Type::JoinGroup, false, false),
+            (Type::LeaveGroup, false, false),
+            (Type::RemoteInvoke, false, false),
+            (Type::SetRelationship, false, false),
+            (Type::Noop, false, false),
+            (Type::Close, false, false),
+            (Type::Compressed, false, false),
+        ];
+        for (typ, content, control) in cases {
+            assert_eq!(typ.is_content(), content, "{}", typ.as_str());
+            assert_eq!(typ.is_control(), control, "{}", typ.as_str());
+        }
+        let mut msg = Msg::text(1, 2, 0, "hi");
+        assert!(msg.is_content() && !msg.is_control());
+        msg.set_type(Type::Sync);
+        assert!(msg.is_control() && !msg.is_content());
+    }
+
+    #[test]
+    fn test_msg_ref() {
+        let mut msg = Msg::text(1, 2, 3, "一只狗");
+        msg.set_seqnum(4);
+        msg.set_version(VERSION_ENCRYPTED);
+        let mut buf = msg.as_bytes();
+        let msg_ref = MsgRef::parse(&buf).unwrap();
+        assert_eq!(msg_ref.typ(), msg.typ());
+        assert_eq!(msg_ref.sender(), msg.sender());
+        assert_eq!(msg_ref.receiver(), msg.receiver());
+        assert_eq!(msg_ref.node_id(), msg.node_id());
+        assert_eq!(msg_ref.timestamp(), msg.timestamp());
+        assert_eq!(msg_ref.seqnum(), msg.seqnum());
+        assert_eq!(msg_ref.version(), msg.version());
+        assert_eq!(msg_ref.payload(), msg.payload());
+        assert_eq!(msg_ref.extension(), msg.extension());
+        assert_eq!(msg_ref.to_msg().as_slice(), msg.as_slice());
+        assert_eq!(MsgRef::from(&msg).payload(), msg.payload());
+        // trailing bytes of the next msg are not part of this one.
+        buf.extend_from_slice(b"next");
+        assert_eq!(MsgRef::parse(&buf).unwrap().as_slice(), msg.as_slice());
+        assert!(MsgRef::parse(&buf[..HEAD_LEN - 1]).is_err());
+        assert!(MsgRef::parse(&msg.as_slice()[..msg.as_slice().len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_head_version() {
+        let mut msg = Msg::text(1, 2, 3, "hello");
+        assert_eq!(msg.version(), HEAD_VERSION);
+        assert_eq!(Head::head_len(msg.as_slice()).unwrap(), HEAD_LEN);
+        // the flags don't change the layout.
+        msg.set_version(HEAD_VERSION | VERSION_COMPRESSED | VERSION_ENCRYPTED);
+        assert_eq!(MsgRef::parse(msg.as_slice()).unwrap().payload(), b"hello");
+        msg.set_version(99);
+        assert!(Head::head_len(msg.as_slice()).is_err());
+        assert!(MsgRef::parse(msg.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_flow_control() {
+        assert_eq!(Msg::flow_control(true).flow_control_signal(), Some(true));
+        assert_eq!(Msg::flow_control(false).flow_control_signal(), Some(false));
+        assert_eq!(Msg::ack(1234).flow_control_signal(), None);
+        let text = Msg::text(1, 2, 3, "pause");
+        assert_eq!(text.flow_control_signal(), None);
+    }
+
+    #[test]
+    fn test_msg_ref_allocations() {
+        let msg = Msg::text(1, 2, 3, &"a".repeat(1024));
+        let buf = msg.as_slice();
+        let rounds = 1000;
+
+        let before = allocations();
+        for _ in 0..rounds {
+            let msg_ref = MsgRef::parse(black_box(buf)).unwrap();
+            black_box(msg_ref.payload());
+        }
+        assert_eq!(allocations() - before, 0);
+
+        let before = allocations();
+        for _ in 0..rounds {
+            let msg = Msg::from(black_box(buf));
+            black_box(msg.payload());
+        }
+        assert_eq!(allocations() - before, rounds);
+    }
 }
@@ -1,6 +1,18 @@
-use std::fmt::{Display, Formatter};
+use std::{
+    cmp::Ordering,
+    fmt::{Display, Formatter},
+    time::{Duration, Instant},
+};
+use anyhow::anyhow;
 use tracing::error;
-use crate::entity::{ServerInfo, ServerLoad, ServerStatus, ServerType};
+use crate::{
+    entity::{ServerHeartbeat, ServerInfo, ServerLoad, ServerStatus, ServerType},
+    Result,
+};
+
+pub const SERVER_INFO_VERSION: u8 = 1;
+/// version(1 byte) + payload length(4 bytes, big endian).
+pub(self) const SERVER_INFO_HEAD_LEN: usize = 5;
 
 impl Display for ServerStatus {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
@@ -35,6 +47,7 @@ impl Display for ServerType {
 impl Default for ServerLoad {
     fn default() -> Self {
         ServerLoad {
+            connections: 0,
             cpu: (0, 0.0),
             mem: (0, 0.0),
             net: (0, 0.0),
@@ -54,8 +67,18 @@ impl Default for ServerLoad {
 
 impl Display for ServerLoad {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "cpu: {:?}, mem: {:?}, net: {:?}, disk: {:?}, thread_num: {}, process_num: {}, physical_mem: {}, virtual_mem: {}, swap_disk: {}, disk_write: {}, disk_read: {}, net_write: {}, net_read: {}",
-               self.cpu, self.mem, self.net, self.disk, self.thread_num, self.process_num, self.physical_mem, self.virtual_mem, self.swap_disk, self.disk_write, self.disk_read, self.net_write, self.net_read)
+        write!(f, "connections: {}, cpu: {:?}, mem: {:?}, net: {:?}, disk: {:?}, thread_num: {}, process_num: {}, physical_mem: {}, virtual_mem: {}, swap_disk: {}, disk_write: {}, disk_read: {}, net_write: {}, net_read: {}",
+               self.connections, self.cpu, self.mem, self.net, self.disk, self.thread_num, self.process_num, self.physical_mem, self.virtual_mem, self.swap_disk, self.disk_write, self.disk_read, self.net_write, self.net_read)
+    }
+}
+
+impl ServerLoad {
+    /// compare by connections first, then cpu usage and memory usage.
+    pub fn cmp_load(&self, other: &ServerLoad) -> Ordering {
+        self.connections
+            .cmp(&other.connections)
+            .then_with(|| self.cpu.1.total_cmp(&other.cpu.1))
+            .then_with(|| self.mem.1.total_cmp(&other.mem.1))
     }
 }
 
@@ -75,8 +98,7 @@ impl Default for ServerInfo {
 
 impl From<&[u8]> for ServerInfo {
     fn from(value: &[u8]) -> Self {
-        let res: serde_json::Result<ServerInfo> = serde_json::from_slice(value);
-        match res {
+        match ServerInfo::from_bytes(value) {
             Ok(v) => v,
             Err(e) => {
                 error!("failed to deserialize ServerInfo from bytes: {}", e);
@@ -97,18 +119,127 @@ impl ServerInfo {
     pub fn to_bytes(&self) -> Vec<u8> {
         let result = serde_json::to_vec(self);
         match result {
-            Ok(v) => v,
+            Ok(v) => {
+                let mut bytes = Vec::with_capacity(SERVER_INFO_HEAD_LEN + v.len());
+                bytes.push(SERVER_INFO_VERSION);
+                bytes.extend_from_slice(&(v.len() as u32).to_be_bytes());
+                bytes.extend_from_slice(&v);
+                bytes
+            }
             Err(e) => {
                 error!("failed to serialize ServerInfo to bytes: {}", e);
                 Vec::new()
             }
         }
     }
+
+    pub fn from_bytes(value: &[u8]) -> Result<Self> {
+        if value.is_empty() {
+            return Err(anyhow!("server info buffer is empty"));
+        }
+        let payload = match value[0] {
+            // nodes not upgraded yet send plain json without any head.
+            b'{' => value,
+            SERVER_INFO_VERSION => {
+                if value.len() < SERVER_INFO_HEAD_LEN {
+                    return Err(anyhow!("server info buffer too short: {}", value.len()));
+                }
+                let len = u32::from_be_bytes([value[1], value[2], value[3], value[4]]) as usize;
+                if value.len() < SERVER_INFO_HEAD_LEN + len {
+                    return Err(anyhow!(
+                        "server info buffer too short: expected {}, got {}",
+                        SERVER_INFO_HEAD_LEN + len,
+                        value.len()
+                    ));
+                }
+                &value[SERVER_INFO_HEAD_LEN..SERVER_INFO_HEAD_LEN + len]
+            }
+            version => return Err(anyhow!("unknown server info version: {}", version)),
+        };
+        Ok(serde_json::from_slice(payload)?)
+    }
+
+    /// the heartbeat to keep this registration fresh.
+    pub fn heartbeat(&self) -> ServerHeartbeat {
+        ServerHeartbeat {
+            id: self.id,
+            status: self.status,
+            load: self.load,
+        }
+    }
+
+    /// merge a heartbeat of the same node, addresses and type are kept as registered, and so is
+    /// the load if the heartbeat carries none.
+    pub fn apply_heartbeat(&mut self, heartbeat: &ServerHeartbeat) {
+        self.status = heartbeat.status;
+        if heartbeat.load.is_some() {
+            self.load = heartbeat.load;
+        }
+    }
+}
+
+impl ServerHeartbeat {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap()
+    }
+
+    pub fn from_bytes(value: &[u8]) -> Result<Self> {
+        Ok(serde_json::from_slice(value)?)
+    }
+}
+
+/// pick the server with the lowest load, servers without load reported are the last choice.
+pub fn pick_least_loaded(list: &[ServerInfo]) -> Option<&ServerInfo> {
+    list.iter().min_by(|a, b| match (&a.load, &b.load) {
+        (Some(a), Some(b)) => a.cmp_load(b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    })
+}
+
+/// keep the servers heard from within `ttl` at `now`, `last_heartbeat` gives when a server sent
+/// its last heartbeat, servers never sending any are judged by their connections only and kept.
+pub fn retain_healthy(
+    list: &mut Vec<ServerInfo>,
+    last_heartbeat: impl Fn(u32) -> Option<Instant>,
+    ttl: Duration,
+    now: Instant,
+) {
+    list.retain(|server_info| match last_heartbeat(server_info.id) {
+        Some(last_heartbeat) => now.saturating_duration_since(last_heartbeat) <= ttl,
+        None => true,
+    });
+}
+
+/// keep a user on the `assigned` node while it's still a candidate, so its streams are not
+/// spread over nodes. otherwise pick the least loaded one, or by hash when no load is reported.
+pub fn pick_sticky(assigned: Option<u32>, candidates: &[ServerInfo], user_id: u64) -> Option<u32> {
+    if let Some(assigned) = assigned {
+        if candidates
+            .iter()
+            .any(|server_info| server_info.id == assigned)
+        {
+            return Some(assigned);
+        }
+    }
+    if candidates.is_empty() {
+        return None;
+    }
+    match pick_least_loaded(candidates) {
+        Some(server_info) if server_info.load.is_some() => Some(server_info.id),
+        _ => Some(candidates[(user_id % candidates.len() as u64) as usize].id),
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::entity::ServerInfo;
+    use std::time::{Duration, Instant};
+
+    use crate::entity::{
+        server::{pick_least_loaded, pick_sticky, retain_healthy, SERVER_INFO_VERSION},
+        ServerHeartbeat, ServerInfo, ServerLoad, ServerStatus,
+    };
 
     #[test]
     fn test() {
@@ -118,4 +249,153 @@ mod tests {
         println!("{}", server_info2);
         assert_eq!(server_info, server_info2);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_from_bytes() {
+        let server_info = ServerInfo::default();
+        let bytes = server_info.to_bytes();
+        assert_eq!(bytes[0], SERVER_INFO_VERSION);
+        assert!(ServerInfo::from_bytes(&[]).is_err());
+        assert!(ServerInfo::from_bytes(&bytes[..3]).is_err());
+        assert!(ServerInfo::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        let mut unknown = bytes.clone();
+        unknown[0] = SERVER_INFO_VERSION + 1;
+        assert!(ServerInfo::from_bytes(&unknown).is_err());
+        // legacy format without head.
+        let legacy = serde_json::to_vec(&server_info).unwrap();
+        assert_eq!(ServerInfo::from_bytes(&legacy).unwrap(), server_info);
+    }
+
+    #[test]
+    fn test_pick_least_loaded() {
+        assert!(pick_least_loaded(&[]).is_none());
+        let mut list = vec![];
+        for (id, connections, cpu) in [(1, 300, 10.0), (2, 100, 80.0), (3, 100, 20.0)] {
+            list.push(ServerInfo {
+                id,
+                load: Some(ServerLoad {
+                    connections,
+                    cpu: (8, cpu),
+                    ..ServerLoad::default()
+                }),
+                ..ServerInfo::default()
+            });
+        }
+        list.push(ServerInfo {
+            id: 4,
+            ..ServerInfo::default()
+        });
+        assert_eq!(pick_least_loaded(&list).unwrap().id, 3);
+    }
+
+    #[test]
+    fn test_retain_healthy() {
+        let now = Instant::now();
+        let ttl = Duration::from_secs(15);
+        let mut list = vec![];
+        for (id, connections) in [(1, 100), (2, 300), (3, 200)] {
+            list.push(ServerInfo {
+                id,
+                load: Some(ServerLoad {
+                    connections,
+                    ..ServerLoad::default()
+                }),
+                ..ServerInfo::default()
+            });
+        }
+        // 1 is the least loaded but has gone silent, 3 never sends heartbeats.
+        let last_heartbeat = |id| match id {
+            1 => Some(now - Duration::from_secs(20)),
+            2 => Some(now - Duration::from_secs(5)),
+            _ => None,
+        };
+        retain_healthy(&mut list, last_heartbeat, ttl, now);
+        let ids = list.iter().map(|server_info| server_info.id);
+        assert_eq!(ids.collect::<Vec<_>>(), vec![2, 3]);
+        assert_eq!(pick_least_loaded(&list).unwrap().id, 3);
+        retain_healthy(&mut list, |_| Some(now - ttl * 2), ttl, now);
+        assert!(pick_least_loaded(&list).is_none());
+    }
+
+    #[test]
+    fn test_pick_sticky() {
+        let now = Instant::now();
+        let ttl = Duration::from_secs(15);
+        let mut list = vec![];
+        for (id, connections) in [(1, 100), (2, 300), (3, 200)] {
+            list.push(ServerInfo {
+                id,
+                load: Some(ServerLoad {
+                    connections,
+                    ..ServerLoad::default()
+                }),
+                ..ServerInfo::default()
+            });
+        }
+        let first = pick_sticky(None, &list, 7).unwrap();
+        assert_eq!(first, 1);
+        // the loads move, the user stays.
+        list[0].load.as_mut().unwrap().connections = 1000;
+        let mut assigned = Some(first);
+        for _ in 0..10 {
+            assigned = pick_sticky(assigned, &list, 7);
+            assert_eq!(assigned, Some(first));
+        }
+
+        // until the node goes silent.
+        let dead = |id| match id {
+            1 => Some(now - ttl * 2),
+            _ => Some(now),
+        };
+        retain_healthy(&mut list, dead, ttl, now);
+        assert_eq!(pick_sticky(assigned, &list, 7), Some(3));
+        list.clear();
+        assert!(pick_sticky(assigned, &list, 7).is_none());
+
+        // by hash without any load reported.
+        let list = (1..=3)
+            .map(|id| ServerInfo {
+                id,
+                ..ServerInfo::default()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(pick_sticky(None, &list, 7), Some(2));
+        assert_eq!(pick_sticky(Some(3), &list, 7), Some(3));
+    }
+
+    #[test]
+    fn test_apply_heartbeat() {
+        let mut server_info = ServerInfo {
+            id: 1,
+            cluster_address: Some("127.0.0.1:11222".to_string()),
+            status: ServerStatus::Online,
+            ..ServerInfo::default()
+        };
+        let registered = server_info.clone();
+        let load = ServerLoad {
+            connections: 42,
+            ..ServerLoad::default()
+        };
+        let heartbeat = ServerHeartbeat {
+            id: 1,
+            status: ServerStatus::Online,
+            load: Some(load),
+        };
+        let heartbeat = ServerHeartbeat::from_bytes(&heartbeat.to_bytes()).unwrap();
+        server_info.apply_heartbeat(&heartbeat);
+        assert_eq!(
+            server_info,
+            ServerInfo {
+                load: Some(load),
+                ..registered
+            }
+        );
+        assert_eq!(server_info.heartbeat(), heartbeat);
+        // liveness only, the load reported before stays.
+        server_info.apply_heartbeat(&ServerHeartbeat {
+            load: None,
+            ..heartbeat
+        });
+        assert_eq!(server_info.load, Some(load));
+    }
+}
@@ -1,5 +1,7 @@
 use thiserror::Error;
 
+use crate::entity::{ReqwestMsg, ReqwestResourceID};
+
 #[allow(unused)]
 #[derive(Debug, Error)]
 pub enum HandlerError {
@@ -32,3 +34,133 @@ pub enum CrashError {
     #[error("crash error: `{0}`")]
     ShouldCrash(String),
 }
+
+/// failures of the net clients, so the callers can decide whether to retry.
+#[derive(Debug, Error)]
+pub enum ClientError {
+    /// the remote can not be reached, or the local endpoint can not be set up.
+    #[error("connect error: `{0}`")]
+    Connect(String),
+    /// the connection is reached, but the tls handshake failed.
+    #[error("handshake error: `{0}`")]
+    Handshake(String),
+    #[error("alpn mismatch: expected `{expected}`, negotiated `{negotiated}`")]
+    AlpnMismatch {
+        expected: String,
+        negotiated: String,
+    },
+    #[error("auth rejected: `{0}`")]
+    AuthRejected(String),
+    #[error("stream closed: `{0}`")]
+    StreamClosed(String),
+    #[error("timeout after {0:?}")]
+    Timeout(std::time::Duration),
+    /// too many requests are waiting for responses, try again later.
+    #[error("overloaded: {0} requests in flight")]
+    Overloaded(u64),
+}
+
+pub type ClientResult<T> = std::result::Result<T, ClientError>;
+
+/// failures of `seqnum` service, answered with their own resource id and the detail as payload,
+/// so the callers can tell them from a seqnum.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum SeqnumError {
+    /// the node has not registered itself to scheduler yet, so it may not own the key.
+    #[error("seqnum node not registered")]
+    NotRegistered,
+    /// the seqnum of the key would go beyond `SEQNUM_MAX`.
+    #[error("seqnum exhausted")]
+    Exhausted,
+    #[error("invalid seqnum request: `{0}`")]
+    InvalidRequest(String),
+}
+
+impl SeqnumError {
+    /// the largest seqnum, limited by the bits of it in a msg head.
+    pub const SEQNUM_MAX: u64 = (1 << 50) - 1;
+
+    pub fn resource_id(&self) -> ReqwestResourceID {
+        match self {
+            SeqnumError::NotRegistered => ReqwestResourceID::SeqnumNotRegistered,
+            SeqnumError::Exhausted => ReqwestResourceID::SeqnumExhausted,
+            SeqnumError::InvalidRequest(_) => ReqwestResourceID::SeqnumInvalidRequest,
+        }
+    }
+
+    pub fn to_reqwest(&self) -> ReqwestMsg {
+        let detail = match self {
+            SeqnumError::InvalidRequest(detail) => detail.as_bytes(),
+            _ => &[],
+        };
+        ReqwestMsg::with_resource_id_payload(self.resource_id(), detail)
+    }
+
+    /// `None` if `resp` is a normal response.
+    pub fn from_reqwest(resp: &ReqwestMsg) -> Option<Self> {
+        match resp.resource_id() {
+            ReqwestResourceID::SeqnumNotRegistered => Some(SeqnumError::NotRegistered),
+            ReqwestResourceID::SeqnumExhausted => Some(SeqnumError::Exhausted),
+            ReqwestResourceID::SeqnumInvalidRequest => Some(SeqnumError::InvalidRequest(
+                String::from_utf8_lossy(resp.payload()).into_owned(),
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// answered with `ReqwestResourceID::Unsupported` when no handler serves the requested resource
+/// id, so the caller knows at once rather than waiting for a response never coming.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("unsupported resource: {0}")]
+pub struct UnsupportedResource(pub ReqwestResourceID);
+
+impl UnsupportedResource {
+    pub fn to_reqwest(&self) -> ReqwestMsg {
+        ReqwestMsg::with_resource_id_payload(
+            ReqwestResourceID::Unsupported,
+            &self.0.value().to_be_bytes(),
+        )
+    }
+
+    /// `None` if `resp` is not an answer of unsupported resource.
+    pub fn from_reqwest(resp: &ReqwestMsg) -> Option<Self> {
+        match (resp.resource_id(), resp.payload()) {
+            (ReqwestResourceID::Unsupported, [high, low, ..]) => Some(UnsupportedResource(
+                u16::from_be_bytes([*high, *low]).into(),
+            )),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SeqnumError, UnsupportedResource};
+    use crate::entity::{ReqwestMsg, ReqwestResourceID};
+
+    #[test]
+    fn test_seqnum_error_reqwest() {
+        for err in [
+            SeqnumError::NotRegistered,
+            SeqnumError::Exhausted,
+            SeqnumError::InvalidRequest("payload too short: 3".to_string()),
+        ] {
+            let mut resp = err.to_reqwest();
+            resp.set_req_id(7);
+            assert_eq!(SeqnumError::from_reqwest(&resp), Some(err));
+        }
+        let resp = ReqwestMsg::with_resource_id_payload(ReqwestResourceID::Seqnum, &[0u8; 8]);
+        assert_eq!(SeqnumError::from_reqwest(&resp), None);
+    }
+
+    #[test]
+    fn test_unsupported_resource_reqwest() {
+        let err = UnsupportedResource(ReqwestResourceID::DeadLetters);
+        let resp = err.to_reqwest();
+        assert_eq!(resp.resource_id(), ReqwestResourceID::Unsupported);
+        assert_eq!(UnsupportedResource::from_reqwest(&resp), Some(err));
+        let resp = ReqwestMsg::with_resource_id_payload(ReqwestResourceID::Unsupported, b"");
+        assert_eq!(UnsupportedResource::from_reqwest(&resp), None);
+    }
+}
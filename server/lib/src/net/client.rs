@@ -1,19 +1,82 @@
-use std::{net::SocketAddr, time::Duration};
+use std::{fmt, net::SocketAddr, sync::Arc, time::Duration};
 
-use crate::Result;
+use crate::{
+    entity::PAYLOAD_THRESHOLD,
+    net::{check_alpn_protocols, check_receive_windows, default_alpn_protocols},
+    Result,
+};
 
 use anyhow::anyhow;
 
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// session tickets kept by the default [`SessionStore`].
+pub const DEFAULT_SESSION_TICKETS: usize = 256;
+
+/// where the session tickets of 0-rtt are kept, shared by the clients built from the same
+/// [`ClientConfig`], by clones of it. a store keeping them somewhere durable can be given to
+/// resume across restarts.
+#[derive(Clone)]
+pub struct SessionStore(pub Arc<dyn rustls::client::ClientSessionStore>);
+
+impl Default for SessionStore {
+    fn default() -> Self {
+        Self(Arc::new(rustls::client::ClientSessionMemoryCache::new(
+            DEFAULT_SESSION_TICKETS,
+        )))
+    }
+}
+
+impl fmt::Debug for SessionStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SessionStore")
+    }
+}
+
 #[allow(unused)]
 #[derive(Clone, Debug)]
 pub struct ClientConfig {
     pub remote_address: SocketAddr,
     pub ipv4_type: bool,
     pub domain: String,
-    pub cert: rustls::Certificate,
+    /// all of them are trusted, so old and new ca can coexist during rotation.
+    pub certs: Vec<rustls::Certificate>,
     /// should be set only on client.
     pub keep_alive_interval: Duration,
     pub max_bi_streams: usize,
+    /// connection without any activity longer than this will be closed, `None` for quinn's default.
+    pub idle_timeout: Option<Duration>,
+    /// a tcp connection receiving nothing longer than this, pongs included, is reported stale on
+    /// its timeout channel, `None` to never report.
+    pub stale_window: Option<Duration>,
+    /// bound of establishing a connection, including the tls handshake.
+    pub connect_timeout: Duration,
+    /// payloads longer than this are compressed before sent, `None` to never compress.
+    pub compress_threshold: Option<usize>,
+    /// msgs from the server declaring a longer payload are rejected before any allocation.
+    pub max_payload_bytes: usize,
+    /// resume with a session ticket from an earlier connection of this config, see
+    /// `session_store`, so the auth msg goes with the first flight.
+    ///
+    /// 0-rtt data can be replayed by anyone on the path, so only the idempotent auth msg is sent
+    /// before the handshake finishes, and the other msgs wait for it.
+    pub enable_0rtt: bool,
+    pub session_store: SessionStore,
+    /// tls versions offered to the server, `None` for rustls's defaults.
+    pub tls_versions: Option<Vec<&'static rustls::SupportedProtocolVersion>>,
+    /// cipher suites offered to the server in order of preference, `None` for rustls's defaults
+    /// which work with `tls_versions`.
+    pub cipher_suites: Option<Vec<rustls::SupportedCipherSuite>>,
+    /// certificate chain and key presented to servers asking for client auth, `None` to present
+    /// nothing.
+    pub client_identity: Option<(Vec<rustls::Certificate>, rustls::PrivateKey)>,
+    /// application protocols offered in order of preference, the one negotiated should be
+    /// among them.
+    pub alpn_protocols: Vec<Vec<u8>>,
+    /// bytes the server may send on one stream before it's read, `None` for quinn's default.
+    pub stream_receive_window: Option<u64>,
+    /// bytes the server may send on all streams before they are read, `None` for quinn's
+    /// default.
+    pub receive_window: Option<u64>,
 }
 
 pub struct ClientConfigBuilder {
@@ -24,11 +87,37 @@ pub struct ClientConfigBuilder {
     #[allow(unused)]
     pub domain: Option<String>,
     #[allow(unused)]
-    pub cert: Option<rustls::Certificate>,
+    pub certs: Option<Vec<rustls::Certificate>>,
     #[allow(unused)]
     pub keep_alive_interval: Option<Duration>,
     #[allow(unused)]
     pub max_bi_streams: Option<usize>,
+    #[allow(unused)]
+    pub idle_timeout: Option<Duration>,
+    #[allow(unused)]
+    pub stale_window: Option<Duration>,
+    #[allow(unused)]
+    pub connect_timeout: Option<Duration>,
+    #[allow(unused)]
+    pub compress_threshold: Option<usize>,
+    #[allow(unused)]
+    pub max_payload_bytes: Option<usize>,
+    #[allow(unused)]
+    pub enable_0rtt: Option<bool>,
+    #[allow(unused)]
+    pub session_store: Option<SessionStore>,
+    #[allow(unused)]
+    pub tls_versions: Option<Vec<&'static rustls::SupportedProtocolVersion>>,
+    #[allow(unused)]
+    pub cipher_suites: Option<Vec<rustls::SupportedCipherSuite>>,
+    #[allow(unused)]
+    pub client_identity: Option<(Vec<rustls::Certificate>, rustls::PrivateKey)>,
+    #[allow(unused)]
+    pub alpn_protocols: Option<Vec<Vec<u8>>>,
+    #[allow(unused)]
+    pub stream_receive_window: Option<u64>,
+    #[allow(unused)]
+    pub receive_window: Option<u64>,
 }
 
 impl Default for ClientConfigBuilder {
@@ -37,9 +126,22 @@ impl Default for ClientConfigBuilder {
             remote_address: None,
             ipv4_type: None,
             domain: None,
-            cert: None,
+            certs: None,
             keep_alive_interval: None,
             max_bi_streams: None,
+            idle_timeout: None,
+            stale_window: None,
+            connect_timeout: None,
+            compress_threshold: None,
+            max_payload_bytes: None,
+            enable_0rtt: None,
+            session_store: None,
+            tls_versions: None,
+            cipher_suites: None,
+            client_identity: None,
+            alpn_protocols: None,
+            stream_receive_window: None,
+            receive_window: None,
         }
     }
 }
@@ -61,7 +163,12 @@ impl ClientConfigBuilder {
     }
 
     pub fn with_cert(&mut self, cert: rustls::Certificate) -> &mut Self {
-        self.cert = Some(cert);
+        self.certs.get_or_insert_with(Vec::new).push(cert);
+        self
+    }
+
+    pub fn with_certs(&mut self, certs: Vec<rustls::Certificate>) -> &mut Self {
+        self.certs.get_or_insert_with(Vec::new).extend(certs);
         self
     }
 
@@ -75,6 +182,85 @@ impl ClientConfigBuilder {
         self
     }
 
+    pub fn with_idle_timeout(&mut self, idle_timeout: Duration) -> &mut Self {
+        self.idle_timeout = Some(idle_timeout);
+        self
+    }
+
+    pub fn with_stale_window(&mut self, stale_window: Duration) -> &mut Self {
+        self.stale_window = Some(stale_window);
+        self
+    }
+
+    pub fn with_connect_timeout(&mut self, connect_timeout: Duration) -> &mut Self {
+        self.connect_timeout = Some(connect_timeout);
+        self
+    }
+
+    pub fn with_compress_threshold(&mut self, compress_threshold: usize) -> &mut Self {
+        self.compress_threshold = Some(compress_threshold);
+        self
+    }
+
+    pub fn with_max_payload_bytes(&mut self, max_payload_bytes: usize) -> &mut Self {
+        self.max_payload_bytes = Some(max_payload_bytes);
+        self
+    }
+
+    pub fn with_enable_0rtt(&mut self, enable_0rtt: bool) -> &mut Self {
+        self.enable_0rtt = Some(enable_0rtt);
+        self
+    }
+
+    pub fn with_session_store(&mut self, session_store: SessionStore) -> &mut Self {
+        self.session_store = Some(session_store);
+        self
+    }
+
+    pub fn with_tls_versions(
+        &mut self,
+        tls_versions: Vec<&'static rustls::SupportedProtocolVersion>,
+    ) -> &mut Self {
+        self.tls_versions = Some(tls_versions);
+        self
+    }
+
+    pub fn with_cipher_suites(
+        &mut self,
+        cipher_suites: Vec<rustls::SupportedCipherSuite>,
+    ) -> &mut Self {
+        self.cipher_suites = Some(cipher_suites);
+        self
+    }
+
+    /// the chain starts with the certificate of the client itself.
+    pub fn with_client_identity(
+        &mut self,
+        cert_chain: Vec<rustls::Certificate>,
+        key: rustls::PrivateKey,
+    ) -> &mut Self {
+        self.client_identity = Some((cert_chain, key));
+        self
+    }
+
+    /// [`crate::net::ALPN_PRIM`] by default.
+    pub fn with_alpn_protocols(&mut self, alpn_protocols: Vec<Vec<u8>>) -> &mut Self {
+        self.alpn_protocols = Some(alpn_protocols);
+        self
+    }
+
+    /// a large window speeds up large downloads on links with a large bandwidth-delay product,
+    /// at the cost of buffering that much per stream.
+    pub fn with_stream_receive_window(&mut self, stream_receive_window: u64) -> &mut Self {
+        self.stream_receive_window = Some(stream_receive_window);
+        self
+    }
+
+    pub fn with_receive_window(&mut self, receive_window: u64) -> &mut Self {
+        self.receive_window = Some(receive_window);
+        self
+    }
+
     pub fn build(self) -> Result<ClientConfig> {
         let remote_address = self
             .remote_address
@@ -83,20 +269,57 @@ impl ClientConfigBuilder {
             .ipv4_type
             .ok_or_else(|| anyhow!("ipv4_type is required"))?;
         let domain = self.domain.ok_or_else(|| anyhow!("domain is required"))?;
-        let cert = self.cert.ok_or_else(|| anyhow!("cert is required"))?;
+        let certs = self
+            .certs
+            .filter(|certs| !certs.is_empty())
+            .ok_or_else(|| anyhow!("cert is required"))?;
         let keep_alive_interval = self
             .keep_alive_interval
             .ok_or_else(|| anyhow!("keep_alive_interval is required"))?;
         let max_bi_streams = self
             .max_bi_streams
             .ok_or_else(|| anyhow!("max_bi_streams is required"))?;
+        if let Some((cert_chain, _)) = self.client_identity.as_ref() {
+            if cert_chain.is_empty() {
+                return Err(anyhow!("cert chain of client identity should not be empty"));
+            }
+        }
+        if let Some(tls_versions) = self.tls_versions.as_ref() {
+            if tls_versions.is_empty() {
+                return Err(anyhow!("tls_versions should not be empty"));
+            }
+            if let Some(cipher_suites) = self.cipher_suites.as_ref() {
+                if !cipher_suites
+                    .iter()
+                    .any(|suite| tls_versions.contains(&suite.version()))
+                {
+                    return Err(anyhow!("no cipher suite works with tls_versions"));
+                }
+            }
+        }
+        let alpn_protocols = self.alpn_protocols.unwrap_or_else(default_alpn_protocols);
+        check_alpn_protocols(&alpn_protocols)?;
+        check_receive_windows(self.stream_receive_window, self.receive_window)?;
         Ok(ClientConfig {
             remote_address,
             ipv4_type,
             domain,
-            cert,
+            certs,
             keep_alive_interval,
             max_bi_streams,
+            idle_timeout: self.idle_timeout,
+            stale_window: self.stale_window,
+            connect_timeout: self.connect_timeout.unwrap_or(DEFAULT_CONNECT_TIMEOUT),
+            compress_threshold: self.compress_threshold,
+            max_payload_bytes: self.max_payload_bytes.unwrap_or(PAYLOAD_THRESHOLD),
+            enable_0rtt: self.enable_0rtt.unwrap_or(false),
+            session_store: self.session_store.unwrap_or_default(),
+            tls_versions: self.tls_versions,
+            cipher_suites: self.cipher_suites,
+            client_identity: self.client_identity,
+            alpn_protocols,
+            stream_receive_window: self.stream_receive_window,
+            receive_window: self.receive_window,
         })
     }
 }
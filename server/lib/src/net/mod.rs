@@ -2,13 +2,92 @@ use std::sync::Arc;
 
 use ahash::AHashMap;
 
-use crate::entity::{Msg, EXTENSION_THRESHOLD, PAYLOAD_THRESHOLD};
+use anyhow::anyhow;
+
+use crate::{
+    entity::{Msg, EXTENSION_THRESHOLD, PAYLOAD_THRESHOLD},
+    error::CrashError,
+};
 
 pub mod client;
 pub mod server;
 
 pub const BODY_SIZE: usize = EXTENSION_THRESHOLD + PAYLOAD_THRESHOLD;
 pub const ALPN_PRIM: &[&[u8]] = &[b"prim"];
+/// the body of a reqwest msg holds at least the req_id and the resource_id.
+pub const REQWEST_MIN_LENGTH: usize = 10;
+/// the length of a reqwest msg is a u16, so it's also the largest one can declare.
+pub const DEFAULT_MAX_REQWEST_BYTES: usize = u16::MAX as usize;
+
+/// [`ALPN_PRIM`] as the default alpn list of client and server configs.
+pub fn default_alpn_protocols() -> Vec<Vec<u8>> {
+    ALPN_PRIM.iter().map(|&x| x.to_vec()).collect()
+}
+
+/// an alpn list offered in tls handshake should not be empty, nor any entry of it empty or
+/// longer than 255 bytes.
+pub(crate) fn check_alpn_protocols(alpn_protocols: &[Vec<u8>]) -> crate::Result<()> {
+    if alpn_protocols.is_empty() {
+        return Err(anyhow::anyhow!("alpn_protocols should not be empty"));
+    }
+    if alpn_protocols
+        .iter()
+        .any(|protocol| protocol.is_empty() || protocol.len() > 255)
+    {
+        return Err(anyhow::anyhow!(
+            "every alpn protocol should be 1 to 255 bytes long"
+        ));
+    }
+    Ok(())
+}
+/// the largest flow control window quic can announce, a 62 bit varint.
+pub const MAX_RECEIVE_WINDOW: u64 = (1 << 62) - 1;
+
+/// flow control windows are announced as quic varints, so they can't be larger than
+/// [`MAX_RECEIVE_WINDOW`].
+pub(crate) fn check_receive_windows(
+    stream_receive_window: Option<u64>,
+    receive_window: Option<u64>,
+) -> crate::Result<()> {
+    if stream_receive_window.map_or(false, |window| window > MAX_RECEIVE_WINDOW) {
+        return Err(anyhow!(
+            "stream_receive_window should not be larger than {}",
+            MAX_RECEIVE_WINDOW
+        ));
+    }
+    if receive_window.map_or(false, |window| window > MAX_RECEIVE_WINDOW) {
+        return Err(anyhow!(
+            "receive_window should not be larger than {}",
+            MAX_RECEIVE_WINDOW
+        ));
+    }
+    Ok(())
+}
+
+/// reject a reqwest msg declaring a body too short to hold its head or larger than allowed,
+/// before any allocation for it.
+///
+/// reading on after a short one would take the following msgs apart, so the stream should be
+/// closed on the error.
+pub fn check_reqwest_length(length: u16, max_reqwest_bytes: usize) -> crate::Result<()> {
+    let length = length as usize;
+    if length < REQWEST_MIN_LENGTH {
+        return Err(anyhow!(CrashError::ShouldCrash(format!(
+            "reqwest msg length {} too short.",
+            length
+        ))));
+    }
+    if length > max_reqwest_bytes {
+        return Err(anyhow!(CrashError::ShouldCrash(format!(
+            "reqwest msg length {} too large.",
+            length
+        ))));
+    }
+    Ok(())
+}
+
+/// the application error code a server closes a connection with when it has no room for it.
+pub const SERVER_FULL_CODE: u32 = 1;
 pub type InnerStates = AHashMap<String, InnerStatesValue>;
 
 pub struct GenericParameterMap(pub AHashMap<&'static str, Box<dyn GenericParameter>>);
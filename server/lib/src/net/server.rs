@@ -1,9 +1,22 @@
-use std::net::SocketAddr;
+use std::{net::SocketAddr, time::Duration};
 
-use crate::Result;
+use crate::{
+    entity::PAYLOAD_THRESHOLD,
+    net::{
+        check_alpn_protocols, check_receive_windows, default_alpn_protocols,
+        DEFAULT_MAX_REQWEST_BYTES, REQWEST_MIN_LENGTH,
+    },
+    Result,
+};
 
 use anyhow::anyhow;
 
+pub const DEFAULT_SHUTDOWN_GRACE: Duration = Duration::from_secs(30);
+pub const DEFAULT_MAX_CONNECTIONS: usize = 1024;
+/// in milliseconds.
+pub const DEFAULT_CONNECTION_IDLE_TIMEOUT: u64 = 3000;
+pub const DEFAULT_MAX_BI_STREAMS: usize = 8;
+
 #[derive(Debug, Clone)]
 pub struct ServerConfig {
     pub address: SocketAddr,
@@ -13,6 +26,35 @@ pub struct ServerConfig {
     /// the client and server should be the same value.
     pub connection_idle_timeout: u64,
     pub max_bi_streams: usize,
+    /// msgs declaring a longer payload are rejected before any allocation.
+    pub max_payload_bytes: usize,
+    /// reqwest msgs declaring a longer body are rejected before any allocation, and their
+    /// connections closed.
+    pub max_reqwest_bytes: usize,
+    /// how long in-flight streams are waited for on shutdown before the endpoint is closed.
+    pub shutdown_grace: Duration,
+    /// payloads longer than this are compressed before sent, `None` to never compress.
+    pub compress_threshold: Option<usize>,
+    /// accept 0-rtt data from clients resuming a session, see `ClientConfig::enable_0rtt`.
+    pub enable_0rtt: bool,
+    /// trust anchors of client certificates, clients without a certificate issued by them are
+    /// rejected during the handshake, `None` to not ask for client certificates.
+    pub client_ca_certs: Option<Vec<rustls::Certificate>>,
+    /// application protocols accepted in order of preference, clients offering none of them
+    /// are rejected during the handshake.
+    pub alpn_protocols: Vec<Vec<u8>>,
+    /// bytes a peer may send on one stream before it's read, `None` for quinn's default.
+    ///
+    /// every stream of every connection may buffer this much, so a large window on links with a
+    /// large bandwidth-delay product trades memory for throughput.
+    pub stream_receive_window: Option<u64>,
+    /// bytes a peer may send on all streams of a connection before they are read, `None` for
+    /// quinn's default. it bounds the memory a single connection can take.
+    pub receive_window: Option<u64>,
+    /// `(high, low)` water marks in msgs, a stream whose msgs waiting for the handler reach
+    /// `high` asks the client to pause, and to resume once they drop to `low`. `None` to never
+    /// ask.
+    pub flow_control: Option<(usize, usize)>,
 }
 
 pub struct ServerConfigBuilder {
@@ -28,6 +70,26 @@ pub struct ServerConfigBuilder {
     pub connection_idle_timeout: Option<u64>,
     #[allow(unused)]
     pub max_bi_streams: Option<usize>,
+    #[allow(unused)]
+    pub max_payload_bytes: Option<usize>,
+    #[allow(unused)]
+    pub max_reqwest_bytes: Option<usize>,
+    #[allow(unused)]
+    pub shutdown_grace: Option<Duration>,
+    #[allow(unused)]
+    pub compress_threshold: Option<usize>,
+    #[allow(unused)]
+    pub enable_0rtt: Option<bool>,
+    #[allow(unused)]
+    pub client_ca_certs: Option<Vec<rustls::Certificate>>,
+    #[allow(unused)]
+    pub alpn_protocols: Option<Vec<Vec<u8>>>,
+    #[allow(unused)]
+    pub stream_receive_window: Option<u64>,
+    #[allow(unused)]
+    pub receive_window: Option<u64>,
+    #[allow(unused)]
+    pub flow_control: Option<(usize, usize)>,
 }
 
 impl Default for ServerConfigBuilder {
@@ -39,6 +101,16 @@ impl Default for ServerConfigBuilder {
             max_connections: None,
             connection_idle_timeout: None,
             max_bi_streams: None,
+            max_payload_bytes: None,
+            max_reqwest_bytes: None,
+            shutdown_grace: None,
+            compress_threshold: None,
+            enable_0rtt: None,
+            client_ca_certs: None,
+            alpn_protocols: None,
+            stream_receive_window: None,
+            receive_window: None,
+            flow_control: None,
         }
     }
 }
@@ -74,19 +146,107 @@ impl ServerConfigBuilder {
         self
     }
 
+    pub fn with_max_payload_bytes(&mut self, max_payload_bytes: usize) -> &mut Self {
+        self.max_payload_bytes = Some(max_payload_bytes);
+        self
+    }
+
+    pub fn with_max_reqwest_bytes(&mut self, max_reqwest_bytes: usize) -> &mut Self {
+        self.max_reqwest_bytes = Some(max_reqwest_bytes);
+        self
+    }
+
+    pub fn with_shutdown_grace(&mut self, shutdown_grace: Duration) -> &mut Self {
+        self.shutdown_grace = Some(shutdown_grace);
+        self
+    }
+
+    pub fn with_compress_threshold(&mut self, compress_threshold: usize) -> &mut Self {
+        self.compress_threshold = Some(compress_threshold);
+        self
+    }
+
+    pub fn with_enable_0rtt(&mut self, enable_0rtt: bool) -> &mut Self {
+        self.enable_0rtt = Some(enable_0rtt);
+        self
+    }
+
+    /// enable mutual tls, can be called more than once to trust more than one ca.
+    pub fn with_client_ca_cert(&mut self, client_ca_cert: rustls::Certificate) -> &mut Self {
+        self.client_ca_certs
+            .get_or_insert_with(Vec::new)
+            .push(client_ca_cert);
+        self
+    }
+
+    /// [`crate::net::ALPN_PRIM`] by default, list more than one to roll out a new protocol
+    /// version while the old clients are still around.
+    pub fn with_alpn_protocols(&mut self, alpn_protocols: Vec<Vec<u8>>) -> &mut Self {
+        self.alpn_protocols = Some(alpn_protocols);
+        self
+    }
+
+    /// see [`ServerConfig::stream_receive_window`] for the memory it takes.
+    pub fn with_stream_receive_window(&mut self, stream_receive_window: u64) -> &mut Self {
+        self.stream_receive_window = Some(stream_receive_window);
+        self
+    }
+
+    /// see [`ServerConfig::receive_window`] for the memory it takes.
+    pub fn with_receive_window(&mut self, receive_window: u64) -> &mut Self {
+        self.receive_window = Some(receive_window);
+        self
+    }
+
+    /// see [`ServerConfig::flow_control`].
+    pub fn with_flow_control(&mut self, high_water: usize, low_water: usize) -> &mut Self {
+        self.flow_control = Some((high_water, low_water));
+        self
+    }
+
+    /// `address`, `cert` and `key` are required, the others fall back to the defaults.
     pub fn build(self) -> Result<ServerConfig> {
-        let address = self.address.ok_or_else(|| anyhow!("address is required"))?;
-        let cert = self.cert.ok_or_else(|| anyhow!("cert is required"))?;
-        let key = self.key.ok_or_else(|| anyhow!("key is required"))?;
-        let max_connections = self
-            .max_connections
-            .ok_or_else(|| anyhow!("max_connections is required"))?;
+        let address = self
+            .address
+            .ok_or_else(|| anyhow!("address is required, set it by `with_address`"))?;
+        let cert = self
+            .cert
+            .filter(|cert| !cert.0.is_empty())
+            .ok_or_else(|| anyhow!("cert is required, set it by `with_cert`"))?;
+        let key = self
+            .key
+            .filter(|key| !key.0.is_empty())
+            .ok_or_else(|| anyhow!("key is required, set it by `with_key`"))?;
+        let max_connections = self.max_connections.unwrap_or(DEFAULT_MAX_CONNECTIONS);
         let connection_idle_timeout = self
             .connection_idle_timeout
-            .ok_or_else(|| anyhow!("connection_idle_timeout is required"))?;
-        let max_bi_streams = self
-            .max_bi_streams
-            .ok_or_else(|| anyhow!("max_bi_streams is required"))?;
+            .unwrap_or(DEFAULT_CONNECTION_IDLE_TIMEOUT);
+        let max_bi_streams = self.max_bi_streams.unwrap_or(DEFAULT_MAX_BI_STREAMS);
+        if max_connections == 0 {
+            return Err(anyhow!("max_connections should not be 0"));
+        }
+        if max_bi_streams == 0 {
+            return Err(anyhow!("max_bi_streams should not be 0"));
+        }
+        let max_reqwest_bytes = self.max_reqwest_bytes.unwrap_or(DEFAULT_MAX_REQWEST_BYTES);
+        if max_reqwest_bytes < REQWEST_MIN_LENGTH {
+            return Err(anyhow!(
+                "max_reqwest_bytes should not be less than {}",
+                REQWEST_MIN_LENGTH
+            ));
+        }
+        let alpn_protocols = self.alpn_protocols.unwrap_or_else(default_alpn_protocols);
+        check_alpn_protocols(&alpn_protocols)?;
+        check_receive_windows(self.stream_receive_window, self.receive_window)?;
+        if let Some((high_water, low_water)) = self.flow_control {
+            if low_water >= high_water {
+                return Err(anyhow!(
+                    "low water {} should be less than high water {}",
+                    low_water,
+                    high_water
+                ));
+            }
+        }
         Ok(ServerConfig {
             address,
             cert,
@@ -94,6 +254,93 @@ impl ServerConfigBuilder {
             max_connections,
             connection_idle_timeout,
             max_bi_streams,
+            max_payload_bytes: self.max_payload_bytes.unwrap_or(PAYLOAD_THRESHOLD),
+            max_reqwest_bytes,
+            shutdown_grace: self.shutdown_grace.unwrap_or(DEFAULT_SHUTDOWN_GRACE),
+            compress_threshold: self.compress_threshold,
+            enable_0rtt: self.enable_0rtt.unwrap_or(false),
+            client_ca_certs: self.client_ca_certs,
+            alpn_protocols,
+            stream_receive_window: self.stream_receive_window,
+            receive_window: self.receive_window,
+            flow_control: self.flow_control,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ServerConfigBuilder, DEFAULT_CONNECTION_IDLE_TIMEOUT, DEFAULT_MAX_BI_STREAMS,
+        DEFAULT_MAX_CONNECTIONS, DEFAULT_SHUTDOWN_GRACE,
+    };
+    use crate::{
+        entity::PAYLOAD_THRESHOLD,
+        net::{ALPN_PRIM, DEFAULT_MAX_REQWEST_BYTES, MAX_RECEIVE_WINDOW},
+    };
+
+    #[test]
+    fn test_build() {
+        let mut builder = ServerConfigBuilder::default();
+        builder
+            .with_address("127.0.0.1:11122".parse().unwrap())
+            .with_key(rustls::PrivateKey(vec![1]));
+        let err = builder.build().unwrap_err();
+        assert!(err.to_string().contains("cert is required"), "{}", err);
+
+        let mut builder = ServerConfigBuilder::default();
+        builder
+            .with_address("127.0.0.1:11122".parse().unwrap())
+            .with_cert(rustls::Certificate(vec![1]))
+            .with_key(rustls::PrivateKey(vec![1]));
+        let config = builder.build().unwrap();
+        assert_eq!(config.max_connections, DEFAULT_MAX_CONNECTIONS);
+        assert_eq!(
+            config.connection_idle_timeout,
+            DEFAULT_CONNECTION_IDLE_TIMEOUT
+        );
+        assert_eq!(config.max_bi_streams, DEFAULT_MAX_BI_STREAMS);
+        assert_eq!(config.max_payload_bytes, PAYLOAD_THRESHOLD);
+        assert_eq!(config.max_reqwest_bytes, DEFAULT_MAX_REQWEST_BYTES);
+        assert_eq!(config.shutdown_grace, DEFAULT_SHUTDOWN_GRACE);
+        assert!(config.client_ca_certs.is_none());
+        assert_eq!(config.alpn_protocols, vec![ALPN_PRIM[0].to_vec()]);
+        assert!(config.stream_receive_window.is_none());
+        assert!(config.receive_window.is_none());
+        assert!(config.flow_control.is_none());
+
+        let mut builder = ServerConfigBuilder::default();
+        builder
+            .with_address("127.0.0.1:11122".parse().unwrap())
+            .with_cert(rustls::Certificate(vec![1]))
+            .with_key(rustls::PrivateKey(vec![1]))
+            .with_flow_control(8, 8);
+        assert!(builder.build().is_err());
+
+        let mut builder = ServerConfigBuilder::default();
+        builder
+            .with_address("127.0.0.1:11122".parse().unwrap())
+            .with_cert(rustls::Certificate(vec![1]))
+            .with_key(rustls::PrivateKey(vec![1]))
+            .with_receive_window(MAX_RECEIVE_WINDOW + 1);
+        assert!(builder.build().is_err());
+
+        let mut builder = ServerConfigBuilder::default();
+        builder
+            .with_address("127.0.0.1:11122".parse().unwrap())
+            .with_cert(rustls::Certificate(vec![1]))
+            .with_key(rustls::PrivateKey(vec![1]))
+            .with_max_reqwest_bytes(0);
+        assert!(builder.build().is_err());
+
+        for alpn_protocols in [vec![], vec![b"prim".to_vec(), vec![]]] {
+            let mut builder = ServerConfigBuilder::default();
+            builder
+                .with_address("127.0.0.1:11122".parse().unwrap())
+                .with_cert(rustls::Certificate(vec![1]))
+                .with_key(rustls::PrivateKey(vec![1]))
+                .with_alpn_protocols(alpn_protocols);
+            assert!(builder.build().is_err());
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use std::time::{Duration, Instant};
+
+/// delays between attempts, doubled every time with jitter, until `max_elapsed` is used up.
+///
+/// sleeping is left to the caller, so it works with any runtime.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    interval: Duration,
+    max_interval: Duration,
+    max_elapsed: Duration,
+    start: Instant,
+}
+
+impl Backoff {
+    pub fn new(initial_interval: Duration, max_interval: Duration, max_elapsed: Duration) -> Self {
+        Self {
+            interval: initial_interval,
+            max_interval,
+            max_elapsed,
+            start: Instant::now(),
+        }
+    }
+
+    /// `None` once the next attempt would start after `max_elapsed`.
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        // half of the interval is random, so nodes started together won't retry in lockstep.
+        let half = self.interval / 2;
+        let delay = half + Duration::from_millis(fastrand::u64(0..=half.as_millis() as u64));
+        if self.start.elapsed() + delay > self.max_elapsed {
+            return None;
+        }
+        self.interval = (self.interval * 2).min(self.max_interval);
+        Some(delay)
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(
+            Duration::from_millis(200),
+            Duration::from_secs(10),
+            Duration::from_secs(300),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Backoff;
+
+    #[test]
+    fn test_next_delay() {
+        let mut backoff = Backoff::new(
+            Duration::from_millis(100),
+            Duration::from_millis(400),
+            Duration::from_secs(60),
+        );
+        let delays = (0..5)
+            .map(|_| backoff.next_delay().unwrap())
+            .collect::<Vec<Duration>>();
+        for (delay, interval) in delays.iter().zip([100, 200, 400, 400, 400]) {
+            assert!(*delay >= Duration::from_millis(interval / 2));
+            assert!(*delay <= Duration::from_millis(interval));
+        }
+        let mut backoff = Backoff::new(
+            Duration::from_millis(100),
+            Duration::from_millis(400),
+            Duration::from_millis(10),
+        );
+        assert!(backoff.next_delay().is_none());
+    }
+}
@@ -0,0 +1,140 @@
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Nonce,
+};
+use anyhow::anyhow;
+use dashmap::DashMap;
+
+use crate::{
+    entity::{Msg, Type, GROUP_ID_THRESHOLD},
+    util::who_we_are,
+    Result,
+};
+
+pub(self) const NONCE_LEN: usize = 12;
+
+/// encrypts the payload of msgs end to end, so the nodes on the way forward them without being
+/// able to read them, and the logs keep only the ciphertext.
+///
+/// the head and extension stay in plaintext, they are needed by the nodes.
+pub trait PayloadCipher: Send + Sync {
+    fn encrypt(&self, msg: &Msg) -> Result<Msg>;
+
+    fn decrypt(&self, msg: &Msg) -> Result<Msg>;
+}
+
+/// the contents of users, the other msgs are read by the nodes and never encrypted.
+#[inline]
+pub fn is_encryptable(msg: &Msg) -> bool {
+    matches!(
+        msg.typ(),
+        Type::Text | Type::Meme | Type::File | Type::Image | Type::Video | Type::Audio | Type::Edit
+    )
+}
+
+/// the same for both sides of a conversation, a group is keyed by its id alone.
+pub fn conversation_of(msg: &Msg) -> String {
+    if msg.receiver() >= GROUP_ID_THRESHOLD {
+        who_we_are(msg.receiver(), msg.receiver())
+    } else {
+        who_we_are(msg.sender(), msg.receiver())
+    }
+}
+
+/// aes-256-gcm with a key per conversation, the payload is sent as `nonce(12) + ciphertext`.
+///
+/// the head is not authenticated, since the nodes rewrite parts of it like seqnum.
+#[derive(Default)]
+pub struct AesGcmCipher {
+    keys: DashMap<String, Aes256Gcm>,
+}
+
+impl AesGcmCipher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// the key of the conversation between `id1` and `id2`, a group's is set with its id as both.
+    pub fn set_key(&self, id1: u64, id2: u64, key: &[u8; 32]) {
+        self.keys
+            .insert(who_we_are(id1, id2), Aes256Gcm::new(key.into()));
+    }
+
+    pub(self) fn key_of(
+        &self,
+        msg: &Msg,
+    ) -> Result<dashmap::mapref::one::Ref<'_, String, Aes256Gcm>> {
+        let conversation = conversation_of(msg);
+        self.keys
+            .get(&conversation)
+            .ok_or_else(|| anyhow!("no key for conversation {}", conversation))
+    }
+}
+
+impl PayloadCipher for AesGcmCipher {
+    fn encrypt(&self, msg: &Msg) -> Result<Msg> {
+        let key = self.key_of(msg)?;
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = key
+            .encrypt(&nonce, msg.payload())
+            .map_err(|_| anyhow!("encrypt payload failed"))?;
+        let mut payload = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+        payload.extend_from_slice(&nonce);
+        payload.extend_from_slice(&ciphertext);
+        msg.encrypted_with(&payload)
+    }
+
+    fn decrypt(&self, msg: &Msg) -> Result<Msg> {
+        if msg.payload_length() < NONCE_LEN {
+            return Err(anyhow!(
+                "encrypted payload too short: {}",
+                msg.payload_length()
+            ));
+        }
+        let key = self.key_of(msg)?;
+        let (nonce, ciphertext) = msg.payload().split_at(NONCE_LEN);
+        let plaintext = key
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow!("decrypt payload failed"))?;
+        Ok(msg.decrypted_with(&plaintext))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_encryptable, AesGcmCipher, PayloadCipher};
+    use crate::entity::{Msg, GROUP_ID_THRESHOLD};
+
+    #[test]
+    fn test_aes_gcm_cipher() {
+        let cipher = AesGcmCipher::new();
+        cipher.set_key(1, 2, &[7u8; 32]);
+        let msg = Msg::text2(1, 2, 0, "hello", "ext");
+        assert!(is_encryptable(&msg));
+        let encrypted = cipher.encrypt(&msg).unwrap();
+        assert!(encrypted.is_encrypted());
+        assert_ne!(encrypted.payload(), msg.payload());
+        assert_eq!(encrypted.extension(), b"ext");
+        // the other side decrypts it with the same key.
+        let mut reply = encrypted.clone();
+        reply.set_seqnum(10);
+        let decrypted = cipher.decrypt(&reply).unwrap();
+        assert!(!decrypted.is_encrypted());
+        assert_eq!(decrypted.payload(), b"hello");
+        assert_eq!(decrypted.seqnum(), 10);
+
+        let other = AesGcmCipher::new();
+        other.set_key(1, 2, &[8u8; 32]);
+        assert!(other.decrypt(&encrypted).is_err());
+        assert!(other.encrypt(&Msg::text(1, 3, 0, "hello")).is_err());
+
+        let group_id = GROUP_ID_THRESHOLD + 1;
+        cipher.set_key(group_id, group_id, &[9u8; 32]);
+        let encrypted = cipher.encrypt(&Msg::text(1, group_id, 0, "all")).unwrap();
+        assert!(!is_encryptable(&Msg::ping(1, 0, 0)));
+        // the members get it with the group as sender.
+        let mut broadcast = encrypted.clone();
+        broadcast.set_sender(group_id);
+        assert_eq!(cipher.decrypt(&broadcast).unwrap().payload(), b"all");
+    }
+}
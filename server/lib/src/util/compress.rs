@@ -0,0 +1,27 @@
+use crate::Result;
+
+/// a balance between ratio and speed, payloads are compressed on every forward.
+pub(self) const COMPRESSION_LEVEL: i32 = 3;
+
+pub fn compress(data: &[u8]) -> Result<Vec<u8>> {
+    Ok(zstd::bulk::compress(data, COMPRESSION_LEVEL)?)
+}
+
+/// data decompressed to more than `capacity` bytes is treated as an error.
+pub fn decompress(data: &[u8], capacity: usize) -> Result<Vec<u8>> {
+    Ok(zstd::bulk::decompress(data, capacity)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compress, decompress};
+
+    #[test]
+    fn test_round_trip() {
+        let data = b"prim compress ".repeat(40 * 1024 / 14);
+        let compressed = compress(&data).unwrap();
+        assert!(compressed.len() < data.len() / 10);
+        assert_eq!(decompress(&compressed, data.len()).unwrap(), data);
+        assert!(decompress(&compressed, data.len() - 1).is_err());
+    }
+}
@@ -0,0 +1,264 @@
+use anyhow::anyhow;
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::{
+    entity::{Msg, Type},
+    Result,
+};
+
+/// the first byte of the payload of a structured type, telling how the rest is laid out.
+pub const CONTENT_SCHEMA_V1: u8 = 1;
+
+/// a sticker picked from a pack the clients ship with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemeMeta {
+    pub pack: u32,
+    pub index: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageMeta {
+    pub width: u32,
+    pub height: u32,
+    pub size: u64,
+    pub mime: String,
+    pub url: String,
+}
+
+/// for both video and audio.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaMeta {
+    pub duration_ms: u32,
+    pub size: u64,
+    pub mime: String,
+    pub url: String,
+}
+
+/// the payload of a content msg, decoded by its type and schema version.
+///
+/// v1 lays the fields out in big endian, strings are prefixed by a u16 length.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Content<'a> {
+    /// no schema byte, the payload is the text as it is.
+    Text(&'a [u8]),
+    Meme(MemeMeta),
+    Image(ImageMeta),
+    Video(MediaMeta),
+    Audio(MediaMeta),
+}
+
+/// whether the payload of `typ` starts with a schema version.
+#[inline]
+pub fn is_structured(typ: Type) -> bool {
+    matches!(typ, Type::Meme | Type::Image | Type::Video | Type::Audio)
+}
+
+impl<'a> Content<'a> {
+    pub fn parse(msg: &'a Msg) -> Result<Self> {
+        Self::parse_payload(msg.typ(), msg.payload())
+    }
+
+    pub fn parse_payload(typ: Type, payload: &'a [u8]) -> Result<Self> {
+        if typ == Type::Text {
+            return Ok(Content::Text(payload));
+        }
+        if !is_structured(typ) {
+            return Err(anyhow!("not a content type: {}", typ));
+        }
+        let (&version, body) = payload
+            .split_first()
+            .ok_or_else(|| anyhow!("empty {} payload", typ))?;
+        match version {
+            CONTENT_SCHEMA_V1 => Self::parse_v1(typ, &mut Reader(body)),
+            version => Err(anyhow!(
+                "unknown schema version {} of {} payload",
+                version,
+                typ
+            )),
+        }
+    }
+
+    fn parse_v1(typ: Type, reader: &mut Reader) -> Result<Self> {
+        let content = match typ {
+            Type::Meme => Content::Meme(MemeMeta {
+                pack: reader.u32()?,
+                index: reader.u32()?,
+            }),
+            Type::Image => Content::Image(ImageMeta {
+                width: reader.u32()?,
+                height: reader.u32()?,
+                size: reader.u64()?,
+                mime: reader.string()?,
+                url: reader.string()?,
+            }),
+            _ => {
+                let meta = MediaMeta {
+                    duration_ms: reader.u32()?,
+                    size: reader.u64()?,
+                    mime: reader.string()?,
+                    url: reader.string()?,
+                };
+                if typ == Type::Video {
+                    Content::Video(meta)
+                } else {
+                    Content::Audio(meta)
+                }
+            }
+        };
+        if !reader.0.is_empty() {
+            return Err(anyhow!(
+                "{} trailing bytes of {} payload",
+                reader.0.len(),
+                typ
+            ));
+        }
+        Ok(content)
+    }
+
+    pub fn typ(&self) -> Type {
+        match self {
+            Content::Text(_) => Type::Text,
+            Content::Meme(_) => Type::Meme,
+            Content::Image(_) => Type::Image,
+            Content::Video(_) => Type::Video,
+            Content::Audio(_) => Type::Audio,
+        }
+    }
+
+    /// the payload in the latest schema.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        match self {
+            Content::Text(text) => {
+                buf.extend_from_slice(text);
+                return buf;
+            }
+            Content::Meme(meta) => {
+                buf.push(CONTENT_SCHEMA_V1);
+                buf.extend_from_slice(&meta.pack.to_be_bytes());
+                buf.extend_from_slice(&meta.index.to_be_bytes());
+            }
+            Content::Image(meta) => {
+                buf.push(CONTENT_SCHEMA_V1);
+                buf.extend_from_slice(&meta.width.to_be_bytes());
+                buf.extend_from_slice(&meta.height.to_be_bytes());
+                buf.extend_from_slice(&meta.size.to_be_bytes());
+                put_string(&mut buf, &meta.mime);
+                put_string(&mut buf, &meta.url);
+            }
+            Content::Video(meta) | Content::Audio(meta) => {
+                buf.push(CONTENT_SCHEMA_V1);
+                buf.extend_from_slice(&meta.duration_ms.to_be_bytes());
+                buf.extend_from_slice(&meta.size.to_be_bytes());
+                put_string(&mut buf, &meta.mime);
+                put_string(&mut buf, &meta.url);
+            }
+        }
+        buf
+    }
+
+    pub fn to_msg(&self, sender: u64, receiver: u64, node_id: u32) -> Msg {
+        let mut msg = Msg::raw(sender, receiver, node_id, &self.encode());
+        msg.set_type(self.typ());
+        msg
+    }
+}
+
+/// strings longer than u16::MAX are cut, far beyond what a payload holds anyway.
+fn put_string(buf: &mut Vec<u8>, s: &str) {
+    let s = &s.as_bytes()[..s.len().min(u16::MAX as usize)];
+    buf.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    buf.extend_from_slice(s);
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(anyhow!("payload truncated"));
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(BigEndian::read_u32(self.take(4)?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(BigEndian::read_u64(self.take(8)?))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let len = BigEndian::read_u16(self.take(2)?) as usize;
+        Ok(String::from_utf8(self.take(len)?.to_vec())?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::entity::{Msg, Type};
+
+    use super::{Content, ImageMeta, MediaMeta, CONTENT_SCHEMA_V1};
+
+    #[test]
+    fn test_parse_v1() {
+        let image = ImageMeta {
+            width: 1920,
+            height: 1080,
+            size: 345_678,
+            mime: "image/png".to_string(),
+            url: "https://example.com/a.png".to_string(),
+        };
+        let msg = Content::Image(image.clone()).to_msg(1, 2, 0);
+        assert_eq!(msg.typ(), Type::Image);
+        assert_eq!(msg.payload()[0], CONTENT_SCHEMA_V1);
+        assert_eq!(Content::parse(&msg).unwrap(), Content::Image(image));
+
+        // laid out by hand, as an old client would.
+        let mut payload = vec![CONTENT_SCHEMA_V1];
+        payload.extend_from_slice(&3000u32.to_be_bytes());
+        payload.extend_from_slice(&1024u64.to_be_bytes());
+        payload.extend_from_slice(&[0, 9]);
+        payload.extend_from_slice(b"audio/ogg");
+        payload.extend_from_slice(&[0, 0]);
+        let audio = Content::parse_payload(Type::Audio, &payload).unwrap();
+        let expected = MediaMeta {
+            duration_ms: 3000,
+            size: 1024,
+            mime: "audio/ogg".to_string(),
+            url: String::new(),
+        };
+        assert_eq!(audio, Content::Audio(expected));
+
+        // text has no schema byte.
+        let text = Msg::text(1, 2, 0, "\x01hello");
+        assert_eq!(Content::parse(&text).unwrap(), Content::Text(b"\x01hello"));
+    }
+
+    #[test]
+    fn test_reject() {
+        let mut msg = Content::Image(ImageMeta {
+            width: 1,
+            height: 1,
+            size: 1,
+            mime: "image/png".to_string(),
+            url: String::new(),
+        })
+        .to_msg(1, 2, 0);
+        let mut payload = msg.payload().to_vec();
+        payload[0] = 9;
+        msg.set_payload(&payload);
+        let e = Content::parse(&msg).unwrap_err();
+        assert!(e.to_string().contains("unknown schema version 9"));
+
+        payload[0] = CONTENT_SCHEMA_V1;
+        assert!(Content::parse_payload(Type::Image, &payload[..payload.len() - 1]).is_err());
+        payload.push(0);
+        assert!(Content::parse_payload(Type::Image, &payload).is_err());
+        assert!(Content::parse_payload(Type::Meme, &[]).is_err());
+        assert!(Content::parse_payload(Type::Ack, &payload).is_err());
+    }
+}
@@ -0,0 +1,118 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use ahash::AHashMap;
+
+use crate::entity::Msg;
+
+/// drop messages redelivered by reconnection or retry.
+///
+/// a bounded lru of recently seen `(conversation, seq_num)` keys, entries older than `ttl` are
+/// forgotten. seq_num is allocated per conversation, see [`Msg::conversation`], so the same
+/// sender may use the same seq_num towards different peers and groups.
+/// keys are only compared for equality, so seq_num wraparound needs no special care.
+pub struct Deduplicator {
+    capacity: usize,
+    ttl: Duration,
+    seen: AHashMap<((u64, u64), u64), Instant>,
+    order: VecDeque<(((u64, u64), u64), Instant)>,
+}
+
+impl Deduplicator {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            ttl,
+            seen: AHashMap::with_capacity(capacity),
+            order: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// return true if the same seq_num of the same conversation has been seen within the ttl.
+    /// messages without seq_num assigned(such as ack and ping) are never treated as duplicate.
+    pub fn seen_before(&mut self, msg: &Msg) -> bool {
+        let seqnum = msg.seqnum();
+        if seqnum == 0 {
+            return false;
+        }
+        let key = (msg.conversation(), seqnum);
+        let now = Instant::now();
+        self.evict(now);
+        let duplicate = self.seen.contains_key(&key);
+        // refresh the key to keep it as the most recently used one.
+        self.seen.insert(key, now);
+        self.order.push_back((key, now));
+        self.evict(now);
+        duplicate
+    }
+
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+
+    fn evict(&mut self, now: Instant) {
+        while let Some(&(key, time)) = self.order.front() {
+            if self.order.len() <= self.capacity && now.duration_since(time) < self.ttl {
+                break;
+            }
+            self.order.pop_front();
+            // the key may have been refreshed later, only the latest record owns it.
+            if self.seen.get(&key) == Some(&time) {
+                self.seen.remove(&key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::entity::{Msg, GROUP_ID_THRESHOLD};
+
+    use super::Deduplicator;
+
+    #[test]
+    fn test_seen_before() {
+        let mut deduplicator = Deduplicator::new(2, Duration::from_secs(60));
+        let mut msg = Msg::text(1, 2, 0, "hello");
+        msg.set_seqnum(1);
+        assert!(!deduplicator.seen_before(&msg));
+        assert!(deduplicator.seen_before(&msg));
+        let mut msg2 = Msg::text(1, 2, 0, "world");
+        msg2.set_seqnum(u64::MAX);
+        assert!(!deduplicator.seen_before(&msg2));
+        let mut msg3 = Msg::text(3, 2, 0, "hello");
+        msg3.set_seqnum(1);
+        assert!(!deduplicator.seen_before(&msg3));
+        // capacity exceeded, the oldest one has been evicted.
+        assert_eq!(deduplicator.len(), 2);
+        assert!(!deduplicator.seen_before(&msg));
+    }
+
+    #[test]
+    fn test_seen_before_per_conversation() {
+        let mut deduplicator = Deduplicator::new(16, Duration::from_secs(60));
+        let group_id = GROUP_ID_THRESHOLD + 1;
+        // the same seq_num from the same sender, but to different peers and groups.
+        for receiver in [2, 3, group_id, group_id + 1] {
+            let mut msg = Msg::text(1, receiver, 0, "hello");
+            msg.set_seqnum(7);
+            assert!(!deduplicator.seen_before(&msg));
+        }
+        // the reply shares the conversation with the msg it answers.
+        let mut reply = Msg::text(2, 1, 0, "hi");
+        reply.set_seqnum(7);
+        assert!(deduplicator.seen_before(&reply));
+        // and a group shares it among all members.
+        let mut msg = Msg::text(5, group_id, 0, "hello");
+        msg.set_seqnum(7);
+        assert!(deduplicator.seen_before(&msg));
+    }
+}
@@ -0,0 +1,173 @@
+use std::fs;
+
+use anyhow::{anyhow, Context};
+use toml::{Table, Value};
+
+use crate::Result;
+
+pub const ENV_PREFIX: &str = "PRIM";
+
+/// the env var overriding `key`, e.g. `server.cluster_address` is overridden by
+/// `PRIM_SERVER_CLUSTER_ADDRESS`.
+pub fn env_name(prefix: &str, key: &str) -> String {
+    format!("{}_{}", prefix, key.replace('.', "_").to_uppercase())
+}
+
+/// overlay the env vars found by `lookup` on a parsed config file, for each of `keys` which are
+/// dotted paths like `server.cluster_address`, with a `[]` suffix for lists of strings like
+/// `redis.addresses[]`. return the keys overridden.
+///
+/// a value is taken as the type of the one in the file, so a string never needs quoting, and a
+/// list can be given as `a,b`. a key missing in the file is parsed as a toml value, and taken as
+/// a string if it's not one.
+pub fn apply_env_overrides<F: Fn(&str) -> Option<String>>(
+    table: &mut Table,
+    prefix: &str,
+    keys: &[&str],
+    lookup: F,
+) -> Result<Vec<String>> {
+    let mut overridden = Vec::new();
+    for key in keys.iter() {
+        let (key, is_list) = match key.strip_suffix("[]") {
+            Some(key) => (key, true),
+            None => (*key, false),
+        };
+        let raw = match lookup(&env_name(prefix, key)) {
+            Some(raw) => raw,
+            None => continue,
+        };
+        let mut path = key.split('.').collect::<Vec<&str>>();
+        let name = path.pop().unwrap();
+        let mut section = &mut *table;
+        for part in path {
+            section = match section
+                .entry(part)
+                .or_insert_with(|| Value::Table(Table::new()))
+            {
+                Value::Table(inner) => inner,
+                _ => return Err(anyhow!("{} is not a table, can't override {}", part, key)),
+            };
+        }
+        let value = parse_env_value(&raw, section.get(name), is_list);
+        section.insert(name.to_owned(), value);
+        overridden.push(key.to_string());
+    }
+    Ok(overridden)
+}
+
+/// read the config file at `config_path` and overlay the env vars on it by
+/// [`apply_env_overrides`]. a missing file fails rather than leaving every field to env, so a
+/// mistyped path is not taken for an empty config.
+pub fn load_config_table<F: Fn(&str) -> Option<String>>(
+    config_path: &str,
+    prefix: &str,
+    keys: &[&str],
+    lookup: F,
+) -> Result<Table> {
+    let toml_str = fs::read_to_string(config_path)
+        .with_context(|| format!("read config file {} failed.", config_path))?;
+    let mut table = toml::from_str::<Table>(&toml_str)
+        .with_context(|| format!("parse config file {} failed.", config_path))?;
+    apply_env_overrides(&mut table, prefix, keys, lookup)?;
+    Ok(table)
+}
+
+pub(self) fn parse_env_value(raw: &str, existing: Option<&Value>, is_list: bool) -> Value {
+    let is_list = is_list || matches!(existing, Some(Value::Array(_)));
+    match existing {
+        Some(Value::String(_)) => Value::String(raw.to_owned()),
+        _ if is_list && !raw.trim_start().starts_with('[') => Value::Array(
+            raw.split(',')
+                .map(|item| Value::String(item.trim().to_owned()))
+                .collect(),
+        ),
+        _ => toml::from_str::<Table>(&format!("value = {}", raw))
+            .ok()
+            .and_then(|mut parsed| parsed.remove("value"))
+            .unwrap_or_else(|| Value::String(raw.to_owned())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use toml::{Table, Value};
+
+    use super::{apply_env_overrides, env_name, load_config_table, ENV_PREFIX};
+
+    #[test]
+    fn test_apply_env_overrides() {
+        assert_eq!(
+            env_name(ENV_PREFIX, "server.cluster_address"),
+            "PRIM_SERVER_CLUSTER_ADDRESS"
+        );
+        let mut table = toml::from_str::<Table>(
+            r#"
+log_level = "info"
+[server]
+cluster_address = "127.0.0.1:11220"
+max_connections = 100
+[redis]
+addresses = ["127.0.0.1:16379"]
+"#,
+        )
+        .unwrap();
+        let env = |name: &str| match name {
+            "PRIM_LOG_LEVEL" => Some("debug".to_string()),
+            "PRIM_SERVER_CLUSTER_ADDRESS" => Some("10.0.0.1:11220".to_string()),
+            "PRIM_SERVER_MAX_CONNECTIONS" => Some("200".to_string()),
+            "PRIM_REDIS_ADDRESSES" => Some("10.0.0.2:16379, 10.0.0.3:16379".to_string()),
+            "PRIM_MESSAGE_QUEUE_ADDRESS" => Some("10.0.0.4:9092".to_string()),
+            _ => None,
+        };
+        let keys = [
+            "log_level",
+            "server.cluster_address",
+            "server.service_address",
+            "server.max_connections",
+            "redis.addresses[]",
+            "message_queue.address",
+        ];
+        let overridden = apply_env_overrides(&mut table, ENV_PREFIX, &keys, env).unwrap();
+        assert_eq!(overridden.len(), 5);
+        assert_eq!(table["log_level"].as_str(), Some("debug"));
+        assert_eq!(
+            table["server"]["cluster_address"].as_str(),
+            Some("10.0.0.1:11220")
+        );
+        assert_eq!(table["server"]["max_connections"].as_integer(), Some(200));
+        assert!(table["server"].get("service_address").is_none());
+        assert_eq!(
+            table["redis"]["addresses"],
+            Value::Array(vec![
+                Value::String("10.0.0.2:16379".to_string()),
+                Value::String("10.0.0.3:16379".to_string()),
+            ])
+        );
+        // not in the file at all.
+        assert_eq!(
+            table["message_queue"]["address"].as_str(),
+            Some("10.0.0.4:9092")
+        );
+    }
+
+    #[test]
+    fn test_load_config_table() {
+        let keys = ["server.cluster_address"];
+        let env = |name: &str| match name {
+            "PRIM_SERVER_CLUSTER_ADDRESS" => Some("10.0.0.1:11220".to_string()),
+            _ => None,
+        };
+        let e = load_config_table("./not-exist-config.toml", ENV_PREFIX, &keys, env).unwrap_err();
+        assert!(e.to_string().contains("read config file"));
+
+        let path =
+            std::env::temp_dir().join(format!("prim-lib-env-config-{}.toml", std::process::id()));
+        std::fs::write(&path, "[server]\ncluster_address = \"127.0.0.1:11220\"\n").unwrap();
+        let table = load_config_table(path.to_str().unwrap(), ENV_PREFIX, &keys, env).unwrap();
+        assert_eq!(
+            table["server"]["cluster_address"].as_str(),
+            Some("10.0.0.1:11220")
+        );
+        _ = std::fs::remove_file(path);
+    }
+}
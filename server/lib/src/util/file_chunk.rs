@@ -0,0 +1,408 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use ahash::AHashMap;
+use anyhow::anyhow;
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::{
+    entity::{Msg, Type, PAYLOAD_THRESHOLD},
+    Result,
+};
+
+/// file id(8 bytes) + chunk index(4 bytes) + chunk count(4 bytes), all big endian, ahead of the
+/// data in the payload of every chunk.
+pub const FILE_CHUNK_HEAD_LEN: usize = 16;
+/// the most bytes of a file one chunk carries.
+pub const FILE_CHUNK_MAX: usize = PAYLOAD_THRESHOLD - FILE_CHUNK_HEAD_LEN;
+/// the index marking a resend request, whose data are the big endian u32 indexes missing, and
+/// whose count is the number of them.
+pub const RESEND_REQUEST_INDEX: u32 = u32::MAX;
+/// the most indexes one resend request carries.
+pub const RESEND_INDEXES_MAX: usize = FILE_CHUNK_MAX / 4;
+
+/// a chunk of a file, or a resend request, borrowed from the payload of a [`Type::File`] msg.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileChunk<'a> {
+    pub file_id: u64,
+    pub index: u32,
+    pub count: u32,
+    pub data: &'a [u8],
+}
+
+impl<'a> FileChunk<'a> {
+    pub fn parse(msg: &'a Msg) -> Result<Self> {
+        if msg.typ() != Type::File {
+            return Err(anyhow!("not a file chunk: {}", msg.typ()));
+        }
+        let payload = msg.payload();
+        if payload.len() < FILE_CHUNK_HEAD_LEN {
+            return Err(anyhow!("file chunk too short: {}", payload.len()));
+        }
+        Ok(Self {
+            file_id: BigEndian::read_u64(&payload[0..8]),
+            index: BigEndian::read_u32(&payload[8..12]),
+            count: BigEndian::read_u32(&payload[12..16]),
+            data: &payload[FILE_CHUNK_HEAD_LEN..],
+        })
+    }
+
+    #[inline]
+    pub fn is_resend_request(&self) -> bool {
+        self.index == RESEND_REQUEST_INDEX
+    }
+
+    /// the indexes asked by a resend request.
+    pub fn resend_indexes(&self) -> Vec<u32> {
+        self.data
+            .chunks_exact(4)
+            .take(self.count as usize)
+            .map(BigEndian::read_u32)
+            .collect()
+    }
+}
+
+fn chunk_msg(
+    sender: u64,
+    receiver: u64,
+    node_id: u32,
+    file_id: u64,
+    index: u32,
+    count: u32,
+    data: &[u8],
+) -> Msg {
+    let mut payload = Vec::with_capacity(FILE_CHUNK_HEAD_LEN + data.len());
+    payload.extend_from_slice(&file_id.to_be_bytes());
+    payload.extend_from_slice(&index.to_be_bytes());
+    payload.extend_from_slice(&count.to_be_bytes());
+    payload.extend_from_slice(data);
+    let mut msg = Msg::raw(sender, receiver, node_id, &payload);
+    msg.set_type(Type::File);
+    msg
+}
+
+/// split `file` into [`Type::File`] msgs of `chunk_size` bytes at most, in the order of index.
+///
+/// the sender keeps them until the file is received, to answer the resend requests.
+pub fn split_file(
+    sender: u64,
+    receiver: u64,
+    node_id: u32,
+    file_id: u64,
+    file: &[u8],
+    chunk_size: usize,
+) -> Result<Vec<Msg>> {
+    if chunk_size == 0 || chunk_size > FILE_CHUNK_MAX {
+        return Err(anyhow!(
+            "chunk size should be 1 to {}, got {}",
+            FILE_CHUNK_MAX,
+            chunk_size
+        ));
+    }
+    let mut data_list = file.chunks(chunk_size).collect::<Vec<_>>();
+    // an empty file is still a chunk, so the receiver knows it's done.
+    if data_list.is_empty() {
+        data_list.push(&[]);
+    }
+    if data_list.len() >= RESEND_REQUEST_INDEX as usize {
+        return Err(anyhow!("file too large: {}", file.len()));
+    }
+    let count = data_list.len() as u32;
+    let msgs = data_list
+        .into_iter()
+        .enumerate()
+        .map(|(index, data)| {
+            chunk_msg(
+                sender,
+                receiver,
+                node_id,
+                file_id,
+                index as u32,
+                count,
+                data,
+            )
+        })
+        .collect();
+    Ok(msgs)
+}
+
+/// ask the sender of a file for the chunks missing, as many as one msg can carry, the rest are
+/// asked by the next request.
+pub fn resend_request(
+    sender: u64,
+    receiver: u64,
+    node_id: u32,
+    file_id: u64,
+    missing: &[u32],
+) -> Msg {
+    let missing = &missing[..missing.len().min(RESEND_INDEXES_MAX)];
+    let data = missing
+        .iter()
+        .flat_map(|index| index.to_be_bytes())
+        .collect::<Vec<_>>();
+    chunk_msg(
+        sender,
+        receiver,
+        node_id,
+        file_id,
+        RESEND_REQUEST_INDEX,
+        missing.len() as u32,
+        &data,
+    )
+}
+
+/// buffer the chunks of files until all of them arrive, in whatever order.
+///
+/// a file is keyed by its sender and file id. one getting no chunk for `timeout` is reported by
+/// [`FileReassembler::expire`] with the chunks still missing, so they can be asked again.
+pub struct FileReassembler {
+    timeout: Duration,
+    max_file_bytes: usize,
+    max_files: usize,
+    files: AHashMap<(u64, u64), PartialFile>,
+}
+
+struct PartialFile {
+    count: u32,
+    chunks: BTreeMap<u32, Vec<u8>>,
+    bytes: usize,
+    last_arrived: Instant,
+}
+
+impl PartialFile {
+    /// as many as one resend request carries.
+    fn missing(&self) -> Vec<u32> {
+        (0..self.count)
+            .filter(|index| !self.chunks.contains_key(index))
+            .take(RESEND_INDEXES_MAX)
+            .collect()
+    }
+}
+
+impl FileReassembler {
+    /// files larger than `max_file_bytes` are dropped, and no more than `max_files` are received
+    /// at a time, so senders can't take all the memory. a file should be split into chunks of
+    /// [`FILE_CHUNK_MAX`] bytes to be received as large as `max_file_bytes`.
+    pub fn new(timeout: Duration, max_file_bytes: usize, max_files: usize) -> Self {
+        Self {
+            timeout,
+            max_file_bytes,
+            max_files: max_files.max(1),
+            files: AHashMap::new(),
+        }
+    }
+
+    /// return the file id and the whole file once its last chunk arrives, the chunks arrived
+    /// twice are ignored.
+    pub fn push(&mut self, msg: &Msg) -> Result<Option<(u64, Vec<u8>)>> {
+        self.push_at(msg, Instant::now())
+    }
+
+    pub fn push_at(&mut self, msg: &Msg, now: Instant) -> Result<Option<(u64, Vec<u8>)>> {
+        let chunk = FileChunk::parse(msg)?;
+        if chunk.is_resend_request() {
+            return Err(anyhow!("resend request is not a chunk"));
+        }
+        if chunk.index >= chunk.count {
+            return Err(anyhow!(
+                "chunk index {} out of count {}",
+                chunk.index,
+                chunk.count
+            ));
+        }
+        let key = (msg.sender(), chunk.file_id);
+        if !self.files.contains_key(&key) {
+            // the count is checked before anything is kept for it.
+            let max_count = self.max_file_bytes.div_ceil(FILE_CHUNK_MAX);
+            if chunk.count as usize > max_count.max(1) {
+                return Err(anyhow!(
+                    "file {} of {} chunks larger than {} bytes",
+                    chunk.file_id,
+                    chunk.count,
+                    self.max_file_bytes
+                ));
+            }
+            if self.files.len() >= self.max_files {
+                return Err(anyhow!(
+                    "too many files being received: {}",
+                    self.files.len()
+                ));
+            }
+        }
+        let file = self.files.entry(key).or_insert_with(|| PartialFile {
+            count: chunk.count,
+            chunks: BTreeMap::new(),
+            bytes: 0,
+            last_arrived: now,
+        });
+        if file.count != chunk.count {
+            return Err(anyhow!(
+                "chunk count {} mismatches {} of file {}",
+                chunk.count,
+                file.count,
+                chunk.file_id
+            ));
+        }
+        file.last_arrived = now;
+        if file.chunks.contains_key(&chunk.index) {
+            return Ok(None);
+        }
+        file.bytes += chunk.data.len();
+        if file.bytes > self.max_file_bytes {
+            self.files.remove(&key);
+            return Err(anyhow!(
+                "file {} larger than {} bytes",
+                chunk.file_id,
+                self.max_file_bytes
+            ));
+        }
+        file.chunks.insert(chunk.index, chunk.data.to_vec());
+        if file.chunks.len() < file.count as usize {
+            return Ok(None);
+        }
+        let file = self.files.remove(&key).unwrap();
+        let mut whole = Vec::with_capacity(file.bytes);
+        for data in file.chunks.into_values() {
+            whole.extend_from_slice(&data);
+        }
+        Ok(Some((chunk.file_id, whole)))
+    }
+
+    /// the indexes not arrived yet of a file being received, as many as one resend request
+    /// carries.
+    pub fn missing(&self, sender: u64, file_id: u64) -> Vec<u32> {
+        self.files
+            .get(&(sender, file_id))
+            .map(|file| file.missing())
+            .unwrap_or_default()
+    }
+
+    /// return `(sender, file_id, missing)` of the files getting no chunk for the timeout at
+    /// `now`, and wait another timeout before reporting them again.
+    pub fn expire(&mut self, now: Instant) -> Vec<(u64, u64, Vec<u32>)> {
+        let mut expired = Vec::new();
+        for ((sender, file_id), file) in self.files.iter_mut() {
+            if now.duration_since(file.last_arrived) >= self.timeout {
+                file.last_arrived = now;
+                expired.push((*sender, *file_id, file.missing()));
+            }
+        }
+        expired
+    }
+
+    /// when [`FileReassembler::expire`] should be called next, `None` if no file is received.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.files
+            .values()
+            .map(|file| file.last_arrived + self.timeout)
+            .min()
+    }
+
+    /// give up a file, e.g. after asking for its chunks too many times.
+    pub fn remove(&mut self, sender: u64, file_id: u64) {
+        self.files.remove(&(sender, file_id));
+    }
+
+    /// number of files being received.
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::entity::{Msg, Type};
+
+    use super::{
+        chunk_msg, resend_request, split_file, FileChunk, FileReassembler, FILE_CHUNK_HEAD_LEN,
+        FILE_CHUNK_MAX,
+    };
+
+    #[test]
+    fn test_reassemble() {
+        let file = (0..1 << 20).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        // a payload can't be as large as 16 KiB, so the chunks are as large as they can be.
+        assert!(split_file(1, 2, 0, 7, &file, 16 << 10).is_err());
+        let chunk_size = FILE_CHUNK_MAX;
+        let chunks = split_file(1, 2, 0, 7, &file, chunk_size).unwrap();
+        assert_eq!(chunks.len(), file.len().div_ceil(chunk_size));
+        assert!(chunks.iter().all(|chunk| chunk.typ() == Type::File));
+        assert!(chunks
+            .iter()
+            .all(|chunk| chunk.payload_length() <= FILE_CHUNK_HEAD_LEN + chunk_size));
+
+        let mut reassembler = FileReassembler::new(Duration::from_millis(100), 2 << 20, 1);
+        let start = Instant::now();
+        // out of order, with a duplicate and one lost.
+        let lost = 5;
+        for (i, chunk) in chunks.iter().enumerate().rev() {
+            if i != lost {
+                assert!(reassembler.push_at(chunk, start).unwrap().is_none());
+            }
+        }
+        assert!(reassembler.push_at(&chunks[0], start).unwrap().is_none());
+        assert_eq!(reassembler.missing(1, 7), vec![lost as u32]);
+        assert!(reassembler.expire(start).is_empty());
+        assert_eq!(
+            reassembler.next_deadline(),
+            Some(start + Duration::from_millis(100))
+        );
+        let expired = reassembler.expire(start + Duration::from_millis(100));
+        assert_eq!(expired, vec![(1, 7, vec![lost as u32])]);
+
+        // the sender answers the request with the chunks kept.
+        let request = resend_request(2, 1, 0, 7, &expired[0].2);
+        let request = FileChunk::parse(&request).unwrap();
+        assert!(request.is_resend_request());
+        for index in request.resend_indexes() {
+            let (file_id, whole) = reassembler.push(&chunks[index as usize]).unwrap().unwrap();
+            assert_eq!(file_id, 7);
+            assert_eq!(whole, file);
+        }
+        assert!(reassembler.is_empty());
+    }
+
+    #[test]
+    fn test_reject() {
+        let mut reassembler = FileReassembler::new(Duration::from_secs(1), FILE_CHUNK_MAX + 8, 1);
+        assert!(reassembler.push(&Msg::text(1, 2, 0, "hello")).is_err());
+        let chunks = split_file(1, 2, 0, 7, &[0u8; FILE_CHUNK_MAX + 16], FILE_CHUNK_MAX).unwrap();
+        assert!(reassembler.push(&chunks[0]).unwrap().is_none());
+        // beyond `max_file_bytes`, the file is given up.
+        assert!(reassembler.push(&chunks[1]).is_err());
+        assert!(reassembler.is_empty());
+
+        // too many chunks to fit, rejected before anything is kept.
+        let huge = chunk_msg(1, 2, 0, 8, 0, u32::MAX - 1, &[0u8; 4]);
+        assert!(reassembler.push(&huge).is_err());
+        let chunks = split_file(1, 2, 0, 8, &[0u8; 16], 4).unwrap();
+        assert!(reassembler.push(&chunks[0]).is_err());
+        assert!(reassembler.is_empty());
+
+        // one file at a time.
+        let single = split_file(1, 2, 0, 9, &[0u8; 16], 16).unwrap();
+        assert_eq!(
+            reassembler.push(&single[0]).unwrap(),
+            Some((9, vec![0u8; 16]))
+        );
+        let chunks = split_file(1, 2, 0, 10, &[0u8; FILE_CHUNK_MAX + 1], FILE_CHUNK_MAX).unwrap();
+        assert!(reassembler.push(&chunks[0]).unwrap().is_none());
+        let other = split_file(3, 2, 0, 10, &[0u8; FILE_CHUNK_MAX + 1], FILE_CHUNK_MAX).unwrap();
+        assert!(reassembler.push(&other[0]).is_err());
+        assert!(reassembler.push(&chunks[1]).unwrap().is_some());
+        assert!(reassembler.push(&other[0]).unwrap().is_none());
+
+        let mut reassembler = FileReassembler::new(Duration::from_secs(1), 8, 1);
+        let empty = split_file(1, 2, 0, 8, &[], 4).unwrap();
+        assert_eq!(empty.len(), 1);
+        assert_eq!(reassembler.push(&empty[0]).unwrap(), Some((8, vec![])));
+    }
+}
@@ -0,0 +1,55 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+static REGISTERED: AtomicBool = AtomicBool::new(false);
+static SCHEDULER_CONNECTED: AtomicBool = AtomicBool::new(false);
+static REDIS_REACHABLE: AtomicBool = AtomicBool::new(false);
+static CLUSTER_PEERS: AtomicU32 = AtomicU32::new(0);
+
+/// what a node reports to liveness/readiness probes.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Health {
+    /// false until the node has registered itself to scheduler.
+    pub ready: bool,
+    pub scheduler_connected: bool,
+    pub redis_reachable: bool,
+    pub cluster_peers: u32,
+}
+
+impl Health {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        serde_json::from_slice(bytes).ok()
+    }
+}
+
+/// set after the register request has been answered by scheduler.
+pub fn set_registered(registered: bool) {
+    REGISTERED.store(registered, Ordering::Release);
+    if registered {
+        SCHEDULER_CONNECTED.store(true, Ordering::Release);
+    }
+}
+
+pub fn set_scheduler_connected(connected: bool) {
+    SCHEDULER_CONNECTED.store(connected, Ordering::Release);
+}
+
+pub fn set_redis_reachable(reachable: bool) {
+    REDIS_REACHABLE.store(reachable, Ordering::Release);
+}
+
+pub fn set_cluster_peers(peers: u32) {
+    CLUSTER_PEERS.store(peers, Ordering::Release);
+}
+
+pub fn current_health() -> Health {
+    Health {
+        ready: REGISTERED.load(Ordering::Acquire),
+        scheduler_connected: SCHEDULER_CONNECTED.load(Ordering::Acquire),
+        redis_reachable: REDIS_REACHABLE.load(Ordering::Acquire),
+        cluster_peers: CLUSTER_PEERS.load(Ordering::Acquire),
+    }
+}
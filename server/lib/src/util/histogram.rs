@@ -0,0 +1,119 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    OnceLock,
+};
+
+use dashmap::DashMap;
+
+use crate::entity::Type;
+
+/// inclusive upper bounds of the buckets in bytes, longer payloads fall into one more bucket.
+pub const PAYLOAD_BUCKETS: [usize; 5] = [16, 64, 256, 1024, 4096];
+
+#[derive(Default)]
+pub(self) struct Counts {
+    buckets: [AtomicU64; PAYLOAD_BUCKETS.len() + 1],
+    sum: AtomicU64,
+}
+
+/// the payload sizes of one msg type, `buckets[i]` counts the payloads no longer than
+/// `PAYLOAD_BUCKETS[i]` but longer than the bound before, the last one counts the rest.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PayloadSizes {
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub buckets: Vec<u64>,
+    pub count: u64,
+    /// in bytes.
+    pub sum: u64,
+}
+
+/// payload sizes of received msgs by type, for tuning `max_payload_bytes` and the compression
+/// threshold.
+#[derive(Default)]
+pub struct PayloadHistogram {
+    map: DashMap<Type, Counts>,
+}
+
+impl PayloadHistogram {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, typ: Type, payload_len: usize) {
+        let index = PAYLOAD_BUCKETS
+            .iter()
+            .position(|bound| payload_len <= *bound)
+            .unwrap_or(PAYLOAD_BUCKETS.len());
+        let record = |counts: &Counts| {
+            counts.buckets[index].fetch_add(1, Ordering::Relaxed);
+            counts.sum.fetch_add(payload_len as u64, Ordering::Relaxed);
+        };
+        // the write lock of the shard is only taken by the first msg of a type.
+        match self.map.get(&typ) {
+            Some(counts) => record(&counts),
+            None => record(&self.map.entry(typ).or_default()),
+        }
+    }
+
+    /// sorted by the type value.
+    pub fn snapshot(&self) -> Vec<PayloadSizes> {
+        let mut list = self
+            .map
+            .iter()
+            .map(|entry| {
+                let buckets = entry
+                    .value()
+                    .buckets
+                    .iter()
+                    .map(|count| count.load(Ordering::Relaxed))
+                    .collect::<Vec<u64>>();
+                (
+                    entry.key().value(),
+                    PayloadSizes {
+                        typ: entry.key().as_str().to_owned(),
+                        count: buckets.iter().sum(),
+                        buckets,
+                        sum: entry.value().sum.load(Ordering::Relaxed),
+                    },
+                )
+            })
+            .collect::<Vec<(u16, PayloadSizes)>>();
+        list.sort_by_key(|(value, _)| *value);
+        list.into_iter().map(|(_, sizes)| sizes).collect()
+    }
+}
+
+/// the histogram of this node, fed by the receive path of the net crates.
+pub fn payload_histogram() -> &'static PayloadHistogram {
+    static HISTOGRAM: OnceLock<PayloadHistogram> = OnceLock::new();
+    HISTOGRAM.get_or_init(PayloadHistogram::new)
+}
+
+#[inline]
+pub fn record_payload(typ: Type, payload_len: usize) {
+    payload_histogram().record(typ, payload_len);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PayloadHistogram;
+    use crate::entity::Type;
+
+    #[test]
+    fn test_payload_histogram() {
+        let histogram = PayloadHistogram::new();
+        for len in [0, 16, 17, 1024, 5000] {
+            histogram.record(Type::Text, len);
+        }
+        histogram.record(Type::File, 4096);
+        let snapshot = histogram.snapshot();
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(snapshot[0].typ, "text");
+        assert_eq!(snapshot[0].buckets, vec![2, 1, 0, 1, 0, 1]);
+        assert_eq!(snapshot[0].count, 5);
+        assert_eq!(snapshot[0].sum, 16 + 17 + 1024 + 5000);
+        assert_eq!(snapshot[1].typ, "file");
+        assert_eq!(snapshot[1].buckets, vec![0, 0, 0, 0, 1, 0]);
+    }
+}
@@ -0,0 +1,315 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use tracing::warn;
+
+use crate::{
+    entity::{Msg, Type},
+    Result,
+};
+
+use super::{
+    file_chunk::{resend_request, split_file, FileChunk, FileReassembler, FILE_CHUNK_MAX},
+    reorder::ReorderBuffer,
+    seq_tracker::{resync_msg, SeqTracker},
+};
+
+/// how many msgs waiting for the ack are remembered, the oldest are forgotten beyond it.
+pub(self) const MAX_UNACKED: usize = 4096;
+/// the largest file received.
+pub(self) const MAX_FILE_BYTES: usize = 64 << 20;
+/// how many files are received at a time.
+pub(self) const MAX_FILES: usize = 16;
+/// how many files sent are kept to answer the resend requests, the oldest are dropped beyond it.
+pub(self) const MAX_SENT_FILES: usize = 16;
+/// how long a file getting no chunk waits before the missing ones are asked again.
+pub(self) const FILE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// the receive path of a client, every msg from the node goes through it before being shown.
+///
+/// msgs are put in the order of seq_num by a [`ReorderBuffer`], and gaps are detected by a
+/// [`SeqTracker`], a resync is asked for when msgs are lost. [`Inbox::expire`] should be called by
+/// [`Inbox::next_deadline`] to release the msgs waiting too long. the msgs sent by the client
+/// should be told by [`Inbox::sent`], so the seq_num coming back with the ack is counted in the
+/// conversation.
+///
+/// files are sent by [`Inbox::send_file`] and reassembled from the [`Type::File`] chunks on
+/// receiving, see [`super::file_chunk`].
+pub struct Inbox {
+    user_id: u64,
+    node_id: u32,
+    reorder: ReorderBuffer,
+    tracker: SeqTracker,
+    /// the conversation of the msgs waiting for the ack and how many, by the client timestamp.
+    unacked: BTreeMap<u64, ((u64, u64), usize)>,
+    files: FileReassembler,
+    /// the chunks of the files sent, by file id.
+    sent_files: VecDeque<(u64, Vec<Msg>)>,
+}
+
+#[derive(Default)]
+pub struct Received {
+    /// msgs to show, in order.
+    pub ready: Vec<Arc<Msg>>,
+    /// `(sender, file_id, file)` of the files whose chunks all arrived.
+    pub files: Vec<(u64, u64, Vec<u8>)>,
+    /// to be sent to the node, the resync and the resend requests, and the chunks asked again.
+    pub reply: Vec<Msg>,
+}
+
+impl Inbox {
+    /// `window` and `timeout` are how long a missing msg is waited for, see [`ReorderBuffer`].
+    pub fn new(user_id: u64, node_id: u32, window: usize, timeout: Duration) -> Self {
+        Self {
+            user_id,
+            node_id,
+            reorder: ReorderBuffer::new(window, timeout),
+            tracker: SeqTracker::new(window),
+            unacked: BTreeMap::new(),
+            files: FileReassembler::new(FILE_TIMEOUT, MAX_FILE_BYTES, MAX_FILES),
+            sent_files: VecDeque::new(),
+        }
+    }
+
+    /// remember a msg sent, so its seq_num can be told from the ack.
+    pub fn sent(&mut self, msg: &Msg) {
+        if !msg.typ().is_content() {
+            return;
+        }
+        self.unacked
+            .entry(msg.timestamp())
+            .or_insert((msg.conversation(), 0))
+            .1 += 1;
+        while self.unacked.len() > MAX_UNACKED {
+            self.unacked.pop_first();
+        }
+    }
+
+    /// the chunks to send `file` in, kept to answer the resend requests of the receiver.
+    pub fn send_file(&mut self, receiver: u64, file_id: u64, file: &[u8]) -> Result<Vec<Msg>> {
+        let chunks = split_file(
+            self.user_id,
+            receiver,
+            self.node_id,
+            file_id,
+            file,
+            FILE_CHUNK_MAX,
+        )?;
+        for chunk in chunks.iter() {
+            self.sent(chunk);
+        }
+        self.sent_files.push_back((file_id, chunks.clone()));
+        while self.sent_files.len() > MAX_SENT_FILES {
+            self.sent_files.pop_front();
+        }
+        Ok(chunks)
+    }
+
+    pub fn receive(&mut self, msg: Arc<Msg>) -> Received {
+        let mut received = Received::default();
+        let (ready, lost) = if msg.typ() == Type::Ack {
+            match msg
+                .acked_timestamp()
+                .and_then(|timestamp| self.acked(timestamp))
+            {
+                Some(conversation) => {
+                    let lost = self.tracker.observe_seqnum(conversation, msg.seqnum());
+                    let mut ready = vec![msg.clone()];
+                    ready.extend(self.reorder.fill(conversation, msg.seqnum()));
+                    (ready, lost)
+                }
+                None => (vec![msg], None),
+            }
+        } else {
+            let lost = self.tracker.observe(&msg);
+            (self.reorder.push(msg), lost)
+        };
+        if lost.is_some() {
+            received.reply.push(resync_msg(self.user_id, self.node_id));
+        }
+        self.deliver(ready, &mut received);
+        received
+    }
+
+    /// the msgs waited for the missing ones before them too long at `now`, and the resend
+    /// requests of the files getting no chunk for a while.
+    pub fn expire(&mut self, now: Instant) -> Received {
+        let mut received = Received::default();
+        let ready = self.reorder.expire(now);
+        self.deliver(ready, &mut received);
+        for (sender, file_id, missing) in self.files.expire(now) {
+            received.reply.push(resend_request(
+                self.user_id,
+                sender,
+                self.node_id,
+                file_id,
+                &missing,
+            ));
+        }
+        received
+    }
+
+    /// when [`Inbox::expire`] should be called next, `None` if nothing waits.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        match (self.reorder.next_deadline(), self.files.next_deadline()) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    fn acked(&mut self, timestamp: u64) -> Option<(u64, u64)> {
+        let (conversation, count) = self.unacked.get_mut(&timestamp)?;
+        let conversation = *conversation;
+        *count -= 1;
+        if *count == 0 {
+            self.unacked.remove(&timestamp);
+        }
+        Some(conversation)
+    }
+
+    /// file chunks are taken out of the msgs to show.
+    fn deliver(&mut self, msgs: Vec<Arc<Msg>>, received: &mut Received) {
+        for msg in msgs {
+            if msg.typ() != Type::File {
+                received.ready.push(msg);
+                continue;
+            }
+            let chunk = match FileChunk::parse(&msg) {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    warn!("invalid file chunk: {}", e);
+                    continue;
+                }
+            };
+            if chunk.is_resend_request() {
+                let indexes = chunk.resend_indexes();
+                let chunks = match self
+                    .sent_files
+                    .iter()
+                    .find(|(file_id, _)| *file_id == chunk.file_id)
+                {
+                    Some((_, chunks)) => indexes
+                        .into_iter()
+                        .filter_map(|index| chunks.get(index as usize).cloned())
+                        .collect::<Vec<_>>(),
+                    None => continue,
+                };
+                for chunk in chunks {
+                    self.sent(&chunk);
+                    received.reply.push(chunk);
+                }
+                continue;
+            }
+            match self.files.push(&msg) {
+                Ok(Some((file_id, file))) => received.files.push((msg.sender(), file_id, file)),
+                Ok(None) => {}
+                Err(e) => warn!("file from {} dropped: {}", msg.sender(), e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        entity::{Msg, Type},
+        util::file_chunk::FILE_CHUNK_MAX,
+    };
+
+    use super::Inbox;
+
+    fn msg(sender: u64, receiver: u64, seqnum: u64) -> Arc<Msg> {
+        let mut msg = Msg::text(sender, receiver, 0, "hello");
+        msg.set_seqnum(seqnum);
+        Arc::new(msg)
+    }
+
+    #[test]
+    fn test_inbox_resync() {
+        let mut inbox = Inbox::new(1, 7, 1, Duration::from_secs(1));
+        assert!(inbox.receive(msg(2, 1, 1)).reply.is_empty());
+        // the one sent by the owner in between is not lost.
+        let sent = Msg::text(1, 2, 0, "hi");
+        inbox.sent(&sent);
+        let ack = sent.generate_server_ack(7, sent.timestamp(), 2);
+        let received = inbox.receive(Arc::new(ack));
+        assert_eq!(received.ready.len(), 1);
+        assert!(received.reply.is_empty());
+        assert!(inbox.receive(msg(2, 1, 3)).reply.is_empty());
+
+        let received = inbox.receive(msg(2, 1, 5));
+        assert_eq!(received.ready.len(), 1);
+        assert_eq!(received.reply.len(), 1);
+        let resync = &received.reply[0];
+        assert_eq!(resync.typ(), Type::Sync);
+        assert_eq!((resync.sender(), resync.node_id()), (1, 7));
+    }
+
+    #[test]
+    fn test_inbox_order() {
+        let mut inbox = Inbox::new(1, 7, 8, Duration::from_millis(100));
+        let seqnums = |msgs: Vec<Arc<Msg>>| msgs.iter().map(|msg| msg.seqnum()).collect::<Vec<_>>();
+        assert_eq!(seqnums(inbox.receive(msg(2, 1, 1)).ready), vec![1]);
+        let sent = Msg::text(1, 2, 0, "hi");
+        inbox.sent(&sent);
+        // the reply overtakes the ack of the msg it replies to.
+        assert!(inbox.receive(msg(2, 1, 3)).ready.is_empty());
+        let ack = Arc::new(sent.generate_server_ack(7, sent.timestamp(), 2));
+        assert_eq!(seqnums(inbox.receive(ack).ready), vec![2, 3]);
+
+        assert!(inbox.receive(msg(2, 1, 5)).ready.is_empty());
+        let deadline = inbox.next_deadline().unwrap();
+        assert!(deadline <= Instant::now() + Duration::from_millis(100));
+        assert_eq!(seqnums(inbox.expire(deadline).ready), vec![5]);
+        assert_eq!(inbox.next_deadline(), None);
+    }
+
+    #[test]
+    fn test_inbox_file() {
+        let mut sender = Inbox::new(1, 7, 8, Duration::from_secs(1));
+        let mut receiver = Inbox::new(2, 7, 8, Duration::from_secs(1));
+        let file = (0..FILE_CHUNK_MAX * 2 + 1)
+            .map(|i| i as u8)
+            .collect::<Vec<_>>();
+        let chunks = sender.send_file(2, 9, &file).unwrap();
+        assert_eq!(chunks.len(), 3);
+
+        // the node assigns the seq_num, and the second chunk is lost.
+        for (i, chunk) in chunks.iter().enumerate() {
+            let ack = chunk.generate_server_ack(7, chunk.timestamp(), i as u64 + 1);
+            assert!(sender.receive(Arc::new(ack)).reply.is_empty());
+            if i == 1 {
+                continue;
+            }
+            let mut chunk = chunk.clone();
+            chunk.set_seqnum(i as u64 + 1);
+            let received = receiver.receive(Arc::new(chunk));
+            // chunks are not shown as msgs.
+            assert!(received.ready.is_empty());
+            assert!(received.files.is_empty());
+        }
+
+        let received = receiver.expire(Instant::now() + Duration::from_secs(10));
+        let request = received
+            .reply
+            .into_iter()
+            .find(|msg| msg.typ() == Type::File)
+            .unwrap();
+        assert_eq!(request.receiver(), 1);
+        let mut request = request;
+        request.set_seqnum(4);
+        let resent = sender.receive(Arc::new(request)).reply;
+        assert_eq!(resent.len(), 1);
+
+        let received = receiver.receive(Arc::new(resent[0].clone()));
+        assert_eq!(received.files, vec![(1, 9, file)]);
+    }
+}
@@ -1,15 +1,24 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
 use anyhow::anyhow;
 use base64::Engine;
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
 
-use super::timestamp;
 use crate::Result;
 
+/// lifetime of the token used to access the api and message server.
+pub const ACCESS_TOKEN_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// lifetime of the token used to issue new access tokens, the token key in redis lives as long as it.
+pub const REFRESH_TOKEN_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+pub(self) const SUBJECT_ACCESS: &str = "";
+pub(self) const SUBJECT_REFRESH: &str = "refresh";
+
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 struct Claims {
     /// Optional. Audience
     aud: u64,
-    /// Required (validate_exp defaults to true in validation). Expiration time (as UTC timestamp)
+    /// Required (validate_exp defaults to true in validation). Expiration time (as UTC timestamp in seconds)
     exp: u64,
     /// Optional. Issued at (as UTC timestamp)
     iat: u64,
@@ -22,26 +31,58 @@ struct Claims {
 }
 
 #[inline]
-pub fn simple_token(key: &[u8], audience: u64) -> String {
-    let t = timestamp();
+pub(self) fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs()
+}
+
+pub(self) fn issue(key: &[u8], audience: u64, subject: &str, exp: u64) -> String {
+    let t = now_secs();
     encode(
         &Header::default(),
         &Claims {
             aud: audience,
-            exp: t + 7 * 24 * 60 * 60,
+            exp,
             iat: t,
             iss: "PRIM".to_string(),
             nbf: t,
-            sub: "".to_string(),
+            sub: subject.to_string(),
         },
         &EncodingKey::from_secret(key),
     )
     .unwrap()
 }
 
+/// issue an access token expiring after [`ACCESS_TOKEN_TTL`].
+#[inline]
+pub fn simple_token(key: &[u8], audience: u64) -> String {
+    issue(
+        key,
+        audience,
+        SUBJECT_ACCESS,
+        now_secs() + ACCESS_TOKEN_TTL.as_secs(),
+    )
+}
+
+/// issue a refresh token expiring after [`REFRESH_TOKEN_TTL`].
+#[inline]
+pub fn refresh_token(key: &[u8], audience: u64) -> String {
+    issue(
+        key,
+        audience,
+        SUBJECT_REFRESH,
+        now_secs() + REFRESH_TOKEN_TTL.as_secs(),
+    )
+}
+
 #[inline]
 pub fn audience_of_token(token: &str) -> Result<u64> {
-    let payload = token.split('.').nth(1).unwrap();
+    let payload = token
+        .split('.')
+        .nth(1)
+        .ok_or_else(|| anyhow!("malformed token"))?;
     let engine = base64::engine::GeneralPurpose::new(
         &base64::alphabet::URL_SAFE,
         base64::engine::general_purpose::NO_PAD,
@@ -51,8 +92,7 @@ pub fn audience_of_token(token: &str) -> Result<u64> {
     Ok(claim.aud)
 }
 
-#[inline]
-pub fn verify_token(token: &str, key: &[u8], audience: u64) -> anyhow::Result<()> {
+pub(self) fn verify(token: &str, key: &[u8], audience: u64, subject: &str) -> Result<()> {
     let res = decode::<Claims>(
         &token,
         &DecodingKey::from_secret(key),
@@ -61,19 +101,43 @@ pub fn verify_token(token: &str, key: &[u8], audience: u64) -> anyhow::Result<()
     if res.claims.aud != audience {
         return Err(anyhow!("invalid token"));
     }
-    if res.claims.exp < timestamp() {
+    if res.claims.exp < now_secs() {
         return Err(anyhow!("token expired"));
     }
     if res.claims.iss != "PRIM".to_string() {
         return Err(anyhow!("invalid token"));
     }
+    if res.claims.sub != subject {
+        return Err(anyhow!("invalid token"));
+    }
     Ok(())
 }
 
+/// verify an access token, refresh tokens are rejected.
+#[inline]
+pub fn verify_token(token: &str, key: &[u8], audience: u64) -> anyhow::Result<()> {
+    verify(token, key, audience, SUBJECT_ACCESS)
+}
+
+#[inline]
+pub fn verify_refresh_token(token: &str, key: &[u8], audience: u64) -> Result<()> {
+    verify(token, key, audience, SUBJECT_REFRESH)
+}
+
+/// issue a new access token from a valid refresh token, signed by the same key.
+pub fn refresh_access_token(refresh: &str, key: &[u8], audience: u64) -> Result<String> {
+    verify_refresh_token(refresh, key, audience)?;
+    Ok(simple_token(key, audience))
+}
+
 #[cfg(test)]
 mod tests {
     use base64::Engine;
 
+    use super::{
+        issue, now_secs, refresh_access_token, refresh_token, simple_token, verify_refresh_token,
+        verify_token, SUBJECT_ACCESS,
+    };
 
     #[test]
     fn test() {
@@ -84,4 +148,24 @@ mod tests {
         );
         println!("{:?}", engine.decode(str));
     }
+
+    #[test]
+    fn test_expired_token() {
+        let token = issue(b"key", 1, SUBJECT_ACCESS, now_secs() - 120);
+        assert!(verify_token(&token, b"key", 1).is_err());
+        assert!(verify_token(&simple_token(b"key", 1), b"key", 1).is_ok());
+    }
+
+    #[test]
+    fn test_refresh_token() {
+        let refresh = refresh_token(b"key", 1);
+        assert!(verify_refresh_token(&refresh, b"key", 1).is_ok());
+        // refresh tokens can't be used as access tokens, and vice versa.
+        assert!(verify_token(&refresh, b"key", 1).is_err());
+        assert!(verify_refresh_token(&simple_token(b"key", 1), b"key", 1).is_err());
+        let access = refresh_access_token(&refresh, b"key", 1).unwrap();
+        assert!(verify_token(&access, b"key", 1).is_ok());
+        assert!(refresh_access_token(&refresh, b"other", 1).is_err());
+        assert!(refresh_access_token(&refresh, b"key", 2).is_err());
+    }
 }
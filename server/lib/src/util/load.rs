@@ -0,0 +1,94 @@
+use std::{sync::RwLock, time::Duration};
+
+use sysinfo::{CpuExt, System, SystemExt};
+
+use tracing::warn;
+
+use crate::entity::ServerLoad;
+
+static SERVER_LOAD: RwLock<Option<ServerLoad>> = RwLock::new(None);
+
+/// sample current load of this node.
+pub fn sample_load(sys: &mut System, connections: u32) -> ServerLoad {
+    sys.refresh_cpu();
+    sys.refresh_memory();
+    let total_memory = sys.total_memory();
+    let mem_usage = if total_memory == 0 {
+        0.0
+    } else {
+        sys.used_memory() as f32 / total_memory as f32 * 100.0
+    };
+    ServerLoad {
+        connections,
+        cpu: (sys.cpus().len() as u32, sys.global_cpu_info().cpu_usage()),
+        mem: ((total_memory >> 20) as u32, mem_usage),
+        physical_mem: (sys.used_memory() >> 20) as f32,
+        swap_disk: (sys.used_swap() >> 20) as f32,
+        ..ServerLoad::default()
+    }
+}
+
+/// the latest sampled load, `None` until the updater has run once.
+pub fn current_load() -> Option<ServerLoad> {
+    *SERVER_LOAD.read().unwrap()
+}
+
+/// refresh the load on a dedicated thread, so it works under both tokio and monoio runtime.
+pub fn start_load_updater<F>(interval: Duration, connections: F)
+where
+    F: Fn() -> u32 + Send + 'static,
+{
+    let mut sys = System::new();
+    *SERVER_LOAD.write().unwrap() = Some(sample_load(&mut sys, connections()));
+    std::thread::spawn(move || loop {
+        std::thread::sleep(interval);
+        let load = sample_load(&mut sys, connections());
+        *SERVER_LOAD.write().unwrap() = Some(load);
+    });
+}
+
+/// the env var fixing how many receiver runtimes the msglogger runs, each listening on
+/// `/tmp/msglogger-{id}.sock`, the message node reads it too to know how many to connect.
+pub const MSGLOGGER_RUNTIMES_ENV: &str = "PRIM_MSGLOGGER_RUNTIMES";
+
+/// the number of msglogger receiver runtimes, one per cpu unless overridden by
+/// [`MSGLOGGER_RUNTIMES_ENV`].
+pub fn msglogger_runtimes() -> usize {
+    let mut sys = System::new();
+    sys.refresh_cpu();
+    runtime_count(
+        sys.cpus().len(),
+        std::env::var(MSGLOGGER_RUNTIMES_ENV).ok().as_deref(),
+    )
+}
+
+/// at least one, since containers with cpu limits may get a miscounted `cpus` of 0. an override
+/// which is not a positive number is ignored.
+pub fn runtime_count(cpus: usize, overridden: Option<&str>) -> usize {
+    if let Some(overridden) = overridden {
+        match overridden.trim().parse::<usize>() {
+            Ok(count) if count > 0 => return count,
+            _ => warn!(
+                "invalid {}: {}, fall back to the cpu count",
+                MSGLOGGER_RUNTIMES_ENV, overridden
+            ),
+        }
+    }
+    cpus.max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::runtime_count;
+
+    #[test]
+    fn test_runtime_count() {
+        assert_eq!(runtime_count(0, None), 1);
+        assert_eq!(runtime_count(1, None), 1);
+        assert_eq!(runtime_count(8, None), 8);
+        assert_eq!(runtime_count(0, Some("4")), 4);
+        assert_eq!(runtime_count(8, Some(" 2 ")), 2);
+        assert_eq!(runtime_count(8, Some("0")), 8);
+        assert_eq!(runtime_count(0, Some("many")), 1);
+    }
+}
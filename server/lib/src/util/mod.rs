@@ -1,17 +1,27 @@
+pub mod backoff;
+pub mod cipher;
+pub mod compress;
+pub mod content;
+pub mod dedup;
+pub mod env;
+pub mod file_chunk;
+pub mod health;
+pub mod histogram;
+pub mod inbox;
 pub mod jwt;
+pub mod load;
 pub mod map;
+pub mod reorder;
+pub mod seq_tracker;
+pub mod time;
 
-use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::Level;
 
+/// the same as [`time::now_millis`], the clock offset of the node included.
 #[allow(unused)]
 #[inline]
 pub fn timestamp() -> u64 {
-    let start = SystemTime::now();
-    let since_the_epoch = start
-        .duration_since(UNIX_EPOCH)
-        .expect("Time went backwards");
-    let millis = since_the_epoch.as_millis() as u64;
-    millis
+    time::now_millis()
 }
 
 #[allow(unused)]
@@ -49,3 +59,58 @@ pub fn should_connect_to_peer(self_id: u32, peer_id: u32, new_peer: bool) -> boo
         new_peer
     }
 }
+
+/// the level named by `log_level` of a config file, case insensitive, numbers 0-4 run from
+/// trace to error like tracing itself.
+///
+/// an unknown value falls back to info with a warning, printed to stderr since tracing is
+/// not initialized before the config is loaded.
+pub fn parse_log_level(log_level: Option<String>) -> Level {
+    let (level, warning) = log_level_or_warning(log_level.as_deref().unwrap_or("info"));
+    if let Some(warning) = warning {
+        eprintln!("{}", warning);
+    }
+    level
+}
+
+pub(self) fn log_level_or_warning(log_level: &str) -> (Level, Option<String>) {
+    let level = match log_level.trim().to_lowercase().as_str() {
+        "trace" | "0" => Level::TRACE,
+        "debug" | "1" => Level::DEBUG,
+        "info" | "2" => Level::INFO,
+        "warn" | "3" => Level::WARN,
+        "error" | "4" => Level::ERROR,
+        _ => {
+            return (
+                Level::INFO,
+                Some(format!(
+                    "warning: unknown log_level \"{}\", fall back to {}",
+                    log_level,
+                    Level::INFO
+                )),
+            )
+        }
+    };
+    (level, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing::Level;
+
+    use super::{log_level_or_warning, parse_log_level};
+
+    #[test]
+    fn test_parse_log_level() {
+        assert_eq!(parse_log_level(Some("Debug".to_string())), Level::DEBUG);
+        assert_eq!(parse_log_level(Some("ERROR".to_string())), Level::ERROR);
+        assert_eq!(parse_log_level(Some("0".to_string())), Level::TRACE);
+        assert_eq!(parse_log_level(None), Level::INFO);
+        assert!(log_level_or_warning("warn").1.is_none());
+        let (level, warning) = log_level_or_warning("nonsense");
+        assert_eq!(level, Level::INFO);
+        let warning = warning.unwrap();
+        assert!(warning.contains("nonsense"));
+        assert!(warning.contains("INFO"));
+    }
+}
@@ -0,0 +1,261 @@
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use ahash::AHashMap;
+
+use crate::entity::Msg;
+
+/// hold msgs arrived early until the ones before them come, so they are delivered in the order
+/// of seq_num even when they go through different streams.
+///
+/// like [`super::seq_tracker::SeqTracker`], seq_num is allocated per conversation, so the seq_num
+/// of the msgs sent by the buffer's owner should be filled in by [`ReorderBuffer::fill`], or they
+/// are waited for until the timeout. a conversation starts from the first seq_num seen.
+/// a msg is held for `timeout` at most, and `window` msgs per conversation, after that the
+/// missing ones are skipped to avoid stalling.
+pub struct ReorderBuffer {
+    window: usize,
+    timeout: Duration,
+    conversations: AHashMap<(u64, u64), Conversation>,
+}
+
+struct Conversation {
+    /// the seq_num to deliver next, 0 until the first one is seen.
+    next: u64,
+    /// msgs above `next` with the time they arrived, `None` for the filled ones.
+    held: BTreeMap<u64, (Instant, Option<Arc<Msg>>)>,
+}
+
+impl ReorderBuffer {
+    pub fn new(window: usize, timeout: Duration) -> Self {
+        Self {
+            window: window.max(1),
+            timeout,
+            conversations: AHashMap::new(),
+        }
+    }
+
+    /// start the conversation of `msg` from `next` rather than the first seq_num seen, e.g.
+    /// after a sync.
+    pub fn expect(&mut self, msg: &Msg, next: u64) {
+        self.conversation(msg.conversation()).next = next;
+    }
+
+    /// return the msgs ready to deliver in order, which may be empty if `msg` comes early.
+    ///
+    /// msgs without seq_num assigned, and the ones later than the skipped, go through as is.
+    pub fn push(&mut self, msg: Arc<Msg>) -> Vec<Arc<Msg>> {
+        self.push_at(msg, Instant::now())
+    }
+
+    pub fn push_at(&mut self, msg: Arc<Msg>, now: Instant) -> Vec<Arc<Msg>> {
+        let seqnum = msg.seqnum();
+        if seqnum == 0 {
+            return vec![msg];
+        }
+        let conversation = msg.conversation();
+        self.hold(conversation, seqnum, now, Some(msg))
+    }
+
+    /// mark `seqnum` of `conversation` as arrived without a msg to deliver, e.g. the one sent by
+    /// the owner, return the msgs following it ready to deliver.
+    pub fn fill(&mut self, conversation: (u64, u64), seqnum: u64) -> Vec<Arc<Msg>> {
+        self.fill_at(conversation, seqnum, Instant::now())
+    }
+
+    pub fn fill_at(
+        &mut self,
+        conversation: (u64, u64),
+        seqnum: u64,
+        now: Instant,
+    ) -> Vec<Arc<Msg>> {
+        if seqnum == 0 {
+            return vec![];
+        }
+        self.hold(conversation, seqnum, now, None)
+    }
+
+    fn hold(
+        &mut self,
+        conversation: (u64, u64),
+        seqnum: u64,
+        now: Instant,
+        msg: Option<Arc<Msg>>,
+    ) -> Vec<Arc<Msg>> {
+        let window = self.window;
+        let conversation = self.conversation(conversation);
+        if conversation.next == 0 {
+            // the first one seen, nothing before it is known.
+            conversation.next = seqnum;
+        }
+        if seqnum < conversation.next {
+            return msg.into_iter().collect();
+        }
+        conversation.held.insert(seqnum, (now, msg));
+        let mut ready = conversation.release();
+        if conversation.held.len() >= window {
+            conversation.skip();
+            ready.extend(conversation.release());
+        }
+        ready
+    }
+
+    /// return the msgs held longer than the timeout at `now`, with the ones following them.
+    pub fn expire(&mut self, now: Instant) -> Vec<Arc<Msg>> {
+        let mut ready = Vec::new();
+        for conversation in self.conversations.values_mut() {
+            // the ones before an expired msg are released along with it.
+            while conversation
+                .held
+                .values()
+                .any(|(arrived, _)| now.duration_since(*arrived) >= self.timeout)
+            {
+                conversation.skip();
+                ready.extend(conversation.release());
+            }
+        }
+        ready
+    }
+
+    /// when [`ReorderBuffer::expire`] should be called next, `None` if nothing is held.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.conversations
+            .values()
+            .flat_map(|conversation| conversation.held.values())
+            .map(|(arrived, _)| *arrived + self.timeout)
+            .min()
+    }
+
+    /// number of msgs held.
+    pub fn len(&self) -> usize {
+        self.conversations
+            .values()
+            .map(|conversation| conversation.held.len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn conversation(&mut self, conversation: (u64, u64)) -> &mut Conversation {
+        self.conversations
+            .entry(conversation)
+            .or_insert_with(|| Conversation {
+                next: 0,
+                held: BTreeMap::new(),
+            })
+    }
+}
+
+impl Conversation {
+    fn release(&mut self) -> Vec<Arc<Msg>> {
+        let mut ready = Vec::new();
+        while let Some((_, msg)) = self.held.remove(&self.next) {
+            ready.extend(msg);
+            self.next += 1;
+        }
+        ready
+    }
+
+    /// give up the missing ones before the first held.
+    fn skip(&mut self) {
+        if let Some(first) = self.held.keys().next() {
+            self.next = *first;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use crate::entity::{Msg, GROUP_ID_THRESHOLD};
+
+    use super::ReorderBuffer;
+
+    fn msg(seqnum: u64) -> Arc<Msg> {
+        let mut msg = Msg::text(1, 2, 0, "hello");
+        msg.set_seqnum(seqnum);
+        Arc::new(msg)
+    }
+
+    fn group_msg(sender: u64, seqnum: u64) -> Arc<Msg> {
+        let mut msg = Msg::text(sender, GROUP_ID_THRESHOLD + 1, 0, "hello");
+        msg.set_seqnum(seqnum);
+        Arc::new(msg)
+    }
+
+    fn seqnums(msgs: Vec<Arc<Msg>>) -> Vec<u64> {
+        msgs.iter().map(|msg| msg.seqnum()).collect()
+    }
+
+    #[test]
+    fn test_push() {
+        let mut buffer = ReorderBuffer::new(8, Duration::from_secs(1));
+        buffer.expect(&msg(1), 1);
+        assert!(buffer.push(msg(2)).is_empty());
+        assert_eq!(buffer.len(), 1);
+        assert_eq!(seqnums(buffer.push(msg(1))), vec![1, 2]);
+        assert!(buffer.is_empty());
+        // a late one goes through as is.
+        assert_eq!(seqnums(buffer.push(msg(1))), vec![1]);
+
+        // the window is full, the missing 4 is skipped.
+        let mut buffer = ReorderBuffer::new(2, Duration::from_secs(1));
+        buffer.expect(&msg(3), 3);
+        assert!(buffer.push(msg(5)).is_empty());
+        assert_eq!(seqnums(buffer.push(msg(6))), vec![5, 6]);
+    }
+
+    #[test]
+    fn test_expire() {
+        let mut buffer = ReorderBuffer::new(8, Duration::from_millis(100));
+        buffer.expect(&msg(1), 1);
+        let start = Instant::now();
+        assert!(buffer.push_at(msg(3), start).is_empty());
+        assert!(buffer
+            .push_at(msg(4), start + Duration::from_millis(50))
+            .is_empty());
+        assert_eq!(
+            buffer.next_deadline(),
+            Some(start + Duration::from_millis(100))
+        );
+        assert!(buffer.expire(start + Duration::from_millis(99)).is_empty());
+        assert_eq!(
+            seqnums(buffer.expire(start + Duration::from_millis(100))),
+            vec![3, 4]
+        );
+        assert_eq!(buffer.next_deadline(), None);
+        assert_eq!(seqnums(buffer.push(msg(5))), vec![5]);
+    }
+
+    #[test]
+    fn test_first_seen() {
+        // joined in the middle of a conversation, the first one seen is not held.
+        let mut buffer = ReorderBuffer::new(8, Duration::from_secs(1));
+        assert_eq!(seqnums(buffer.push(msg(41))), vec![41]);
+        assert!(buffer.push(msg(43)).is_empty());
+        assert_eq!(seqnums(buffer.push(msg(42))), vec![42, 43]);
+    }
+
+    #[test]
+    fn test_group_and_fill() {
+        let mut buffer = ReorderBuffer::new(8, Duration::from_secs(1));
+        // members of a group share the seq_num.
+        assert_eq!(seqnums(buffer.push(group_msg(3, 1))), vec![1]);
+        assert!(buffer.push(group_msg(4, 3)).is_empty());
+        assert_eq!(seqnums(buffer.push(group_msg(3, 2))), vec![2, 3]);
+        // 4 is the owner's own, its seq_num comes with the ack.
+        assert!(buffer.push(group_msg(4, 5)).is_empty());
+        let group_id = GROUP_ID_THRESHOLD + 1;
+        assert_eq!(seqnums(buffer.fill((group_id, group_id), 4)), vec![5]);
+        assert!(buffer.is_empty());
+    }
+}
@@ -0,0 +1,153 @@
+use std::{collections::BTreeSet, ops::RangeInclusive};
+
+use ahash::AHashMap;
+
+use crate::entity::{Msg, Type};
+
+/// detect lost messages by the gap of seq_num on the receive side.
+///
+/// seq_num is allocated per conversation, see [`Msg::conversation`], so every msg of the
+/// conversation should be observed, including the ones sent by the tracker's owner, whose seq_num
+/// comes with the ack, see [`SeqTracker::observe_seqnum`], otherwise they are reported as lost.
+/// msgs arriving out of order are tolerated until `window` later msgs have been seen.
+pub struct SeqTracker {
+    window: usize,
+    conversations: AHashMap<(u64, u64), Conversation>,
+}
+
+#[derive(Default)]
+struct Conversation {
+    /// the seq_num expected next, all below it have been received or reported.
+    next: u64,
+    /// received seq_nums above `next`.
+    pending: BTreeSet<u64>,
+}
+
+impl SeqTracker {
+    pub fn new(window: usize) -> Self {
+        Self {
+            window: window.max(1),
+            conversations: AHashMap::new(),
+        }
+    }
+
+    /// return the range of seq_num regarded as lost, which should be asked for by a resync.
+    /// msgs without seq_num assigned are ignored.
+    pub fn observe(&mut self, msg: &Msg) -> Option<RangeInclusive<u64>> {
+        self.observe_seqnum(msg.conversation(), msg.seqnum())
+    }
+
+    /// the same as [`SeqTracker::observe`], for a msg known only by its conversation and seq_num.
+    pub fn observe_seqnum(
+        &mut self,
+        conversation: (u64, u64),
+        seqnum: u64,
+    ) -> Option<RangeInclusive<u64>> {
+        if seqnum == 0 {
+            return None;
+        }
+        let conversation = self.conversations.entry(conversation).or_default();
+        if conversation.next == 0 {
+            // the first one seen, nothing before it is known.
+            conversation.next = seqnum + 1;
+            return None;
+        }
+        if seqnum < conversation.next {
+            return None;
+        }
+        conversation.pending.insert(seqnum);
+        conversation.advance();
+        if conversation.pending.len() < self.window {
+            return None;
+        }
+        // waited long enough, give up the missing ones and move on.
+        let first_received = *conversation.pending.iter().next().unwrap();
+        let lost = conversation.next..=first_received - 1;
+        conversation.next = first_received;
+        conversation.advance();
+        Some(lost)
+    }
+
+    pub fn len(&self) -> usize {
+        self.conversations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.conversations.is_empty()
+    }
+}
+
+impl Conversation {
+    fn advance(&mut self) {
+        while self.pending.remove(&self.next) {
+            self.next += 1;
+        }
+    }
+}
+
+/// ask the node of `user_id` to replay the msgs it kept for the user, the same `Sync` a client
+/// sends after reconnecting. the node replays all it kept rather than the lost range, since
+/// that's where the lost ones end up.
+pub fn resync_msg(user_id: u64, node_id: u32) -> Msg {
+    let mut resync = Msg::raw(user_id, 0, node_id, &[]);
+    resync.set_type(Type::Sync);
+    resync
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::entity::{Msg, Type, GROUP_ID_THRESHOLD};
+
+    use super::{resync_msg, SeqTracker};
+
+    fn msg(seqnum: u64) -> Msg {
+        let mut msg = Msg::text(1, 2, 0, "hello");
+        msg.set_seqnum(seqnum);
+        msg
+    }
+
+    #[test]
+    fn test_observe() {
+        let mut tracker = SeqTracker::new(1);
+        assert_eq!(tracker.observe(&msg(1)), None);
+        assert_eq!(tracker.observe(&msg(2)), None);
+        let lost = tracker.observe(&msg(4)).unwrap();
+        assert_eq!(lost, 3..=3);
+        assert_eq!(tracker.observe(&msg(5)), None);
+        let resync = resync_msg(2, 7);
+        assert_eq!(resync.typ(), Type::Sync);
+        assert_eq!((resync.sender(), resync.node_id()), (2, 7));
+        assert!(resync.payload().is_empty());
+
+        // reordering within the window is not a gap.
+        let mut tracker = SeqTracker::new(2);
+        for seqnum in [1, 2, 4, 3, 5] {
+            assert_eq!(tracker.observe(&msg(seqnum)), None);
+        }
+        assert_eq!(tracker.observe(&msg(7)), None);
+        assert_eq!(tracker.observe(&msg(8)), Some(6..=6));
+    }
+
+    #[test]
+    fn test_observe_conversation() {
+        let mut tracker = SeqTracker::new(1);
+        // both directions of a conversation share the seq_num.
+        assert_eq!(tracker.observe(&msg(1)), None);
+        let mut reply = Msg::text(2, 1, 0, "hi");
+        reply.set_seqnum(2);
+        assert_eq!(tracker.observe(&reply), None);
+        // so do the members of a group.
+        let group_id = GROUP_ID_THRESHOLD + 1;
+        for (sender, seqnum) in [(1, 1), (3, 2), (4, 3)] {
+            let mut msg = Msg::text(sender, group_id, 0, "hello");
+            msg.set_seqnum(seqnum);
+            assert_eq!(tracker.observe(&msg), None);
+        }
+        // the seq_num of the owner's msg comes with the ack.
+        assert_eq!(tracker.observe_seqnum((group_id, group_id), 4), None);
+        let mut msg = Msg::text(3, group_id, 0, "hello");
+        msg.set_seqnum(5);
+        assert_eq!(tracker.observe(&msg), None);
+        assert_eq!(tracker.len(), 2);
+    }
+}
@@ -0,0 +1,43 @@
+use std::{
+    sync::atomic::{AtomicI64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// milliseconds added to the local clock, measured against ntp by the operator of the node.
+pub(self) static CLOCK_OFFSET: AtomicI64 = AtomicI64::new(0);
+
+/// set once at startup, every timestamp taken after that is corrected by `offset`.
+pub fn set_clock_offset(offset: i64) {
+    CLOCK_OFFSET.store(offset, Ordering::Release);
+}
+
+pub fn clock_offset() -> i64 {
+    CLOCK_OFFSET.load(Ordering::Acquire)
+}
+
+/// the authoritative clock of a node, all msgs are stamped by it so the timestamps of different
+/// nodes can be compared.
+#[inline]
+pub fn now_millis() -> u64 {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_millis() as u64;
+    millis.saturating_add_signed(clock_offset())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{clock_offset, now_millis, set_clock_offset};
+
+    #[test]
+    fn test_clock_offset() {
+        assert_eq!(clock_offset(), 0);
+        let local = now_millis();
+        set_clock_offset(60_000);
+        let corrected = now_millis();
+        set_clock_offset(0);
+        assert!(corrected >= local + 60_000);
+        assert!(corrected < local + 61_000);
+    }
+}
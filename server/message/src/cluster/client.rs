@@ -18,6 +18,10 @@ use super::{
     MsgSender,
 };
 
+/// the streams to a peer node dying on their own are replaced up to this many times, a flaky
+/// peer is left to the reconnect after that.
+pub(self) const MAX_STREAM_REPLENISHMENTS: usize = 16;
+
 pub(super) struct Client {
     multi_client: ClientMultiConnection,
 }
@@ -32,6 +36,9 @@ impl Client {
             .with_cert(config().server.cert.clone())
             .with_keep_alive_interval(config().transport.keep_alive_interval)
             .with_max_bi_streams(config().transport.max_bi_streams);
+        if let Some(compress_threshold) = config().transport.compress_threshold {
+            client_config.with_compress_threshold(compress_threshold);
+        }
         let client_config = client_config.build().unwrap();
         let multi_client = ClientMultiConnection::new(client_config).unwrap();
         Self { multi_client }
@@ -42,7 +49,10 @@ impl Client {
             remote_address,
             domain: config().server.domain.clone(),
             opened_bi_streams_number: config().transport.max_bi_streams,
+            opened_uni_streams_number: 0,
             timeout: std::time::Duration::from_millis(3000),
+            priority: 0,
+            max_stream_replenishments: MAX_STREAM_REPLENISHMENTS,
         };
         let server_info = ServerInfo {
             id: my_id(),
@@ -64,6 +74,8 @@ impl Client {
         let mut handler_list: Vec<Box<dyn Handler>> = Vec::new();
         handler_list.push(Box::new(logic::ClientAuth {}));
         handler_list.push(Box::new(logger::Ack {}));
+        handler_list.push(Box::new(logic::Ping {}));
+        handler_list.push(Box::new(logic::Presence {}));
         handler_list.push(Box::new(pure_text::Text {}));
         let handler_list = HandlerList::new(handler_list);
         let io_task_sender = get_io_task_sender().clone();
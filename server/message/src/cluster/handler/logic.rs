@@ -8,11 +8,11 @@ use lib::{
     net::InnerStates,
     Result,
 };
-use lib_net_tokio::net::{Handler, MsgSender};
+use lib_net_tokio::net::{Handler, MsgSender, AUTH_HANDLER_PRIORITY};
 use tracing::info;
 
 use crate::util::my_id;
-use crate::{cluster::ClusterConnectionMap, config::config};
+use crate::{cluster::ClusterConnectionMap, config::config, service::ClientConnectionMap};
 
 pub(crate) struct ServerAuth {}
 
@@ -36,7 +36,7 @@ impl Handler for ServerAuth {
             .unwrap()
             .get_parameter::<MsgSender>()
             .unwrap();
-        let server_info = ServerInfo::from(msg.payload());
+        let server_info = ServerInfo::from_bytes(msg.payload())?;
         info!("cluster server {} connected", server_info.id);
 
         let res_server_info = ServerInfo {
@@ -55,6 +55,10 @@ impl Handler for ServerAuth {
         cluster_map.insert(server_info.id, sender.clone());
         Ok(res_msg)
     }
+
+    fn priority(&self) -> i32 {
+        AUTH_HANDLER_PRIORITY
+    }
 }
 
 pub(crate) struct ClientAuth {}
@@ -83,8 +87,59 @@ impl Handler for ClientAuth {
             .unwrap()
             .get_parameter::<MsgSender>()
             .unwrap();
-        let res_server_info = ServerInfo::from(msg.payload());
+        let res_server_info = ServerInfo::from_bytes(msg.payload())?;
         cluster_map.insert(res_server_info.id, sender.clone());
         Ok(msg.generate_ack(my_id(), msg.timestamp()))
     }
+
+    fn priority(&self) -> i32 {
+        AUTH_HANDLER_PRIORITY
+    }
+}
+
+/// answer the pings of the idle connection reaper on the peer node.
+pub(crate) struct Ping;
+
+#[async_trait]
+impl Handler for Ping {
+    async fn run(&self, msg: &mut Arc<Msg>, inner_states: &mut InnerStates) -> Result<Msg> {
+        if Type::Ping != msg.typ() {
+            return Err(anyhow!(HandlerError::NotMine));
+        }
+        let sender = inner_states
+            .get("generic_map")
+            .unwrap()
+            .as_generic_parameter_map()
+            .unwrap()
+            .get_parameter::<MsgSender>()
+            .unwrap();
+        // sent directly, the reply of a handler is followed by an ack which a pong never needs.
+        sender
+            .send(Arc::new(Msg::pong(my_id() as u64, msg.sender(), my_id())))
+            .await?;
+        Ok(Msg::noop())
+    }
+}
+
+/// deliver the presence forwarded by a peer node, nobody is told if the receiver has gone.
+pub(crate) struct Presence;
+
+#[async_trait]
+impl Handler for Presence {
+    async fn run(&self, msg: &mut Arc<Msg>, inner_states: &mut InnerStates) -> Result<Msg> {
+        if Type::Presence != msg.typ() {
+            return Err(anyhow!(HandlerError::NotMine));
+        }
+        let client_map = inner_states
+            .get("generic_map")
+            .unwrap()
+            .as_generic_parameter_map()
+            .unwrap()
+            .get_parameter::<ClientConnectionMap>()
+            .unwrap();
+        if let Some(client_sender) = client_map.get(&msg.receiver()) {
+            client_sender.send(msg.clone()).await?;
+        }
+        Ok(Msg::noop())
+    }
 }
@@ -18,12 +18,12 @@ use crate::{
     cache::get_redis_ops,
     service::{
         get_client_connection_map,
-        handler::{call_handler_list, IOTaskSender},
+        handler::{call_handler_list, router::get_router, IOTaskSender},
     },
     util::my_id,
 };
 
-use super::{get_cluster_connection_map, MsgSender};
+use super::{get_cluster_connection_map, is_duplicate, touch, MsgSender, LAST_SEEN};
 
 pub(super) async fn handler_func(
     sender: MsgSender,
@@ -33,6 +33,7 @@ pub(super) async fn handler_func(
     inner_states: &mut InnerStates,
 ) -> Result<()> {
     let cluster_map = get_cluster_connection_map().0;
+    let router = get_router();
     let mut generic_map = GenericParameterMap(AHashMap::new());
     generic_map.put_parameter(get_redis_ops().await);
     generic_map.put_parameter(get_client_connection_map());
@@ -54,6 +55,7 @@ pub(super) async fn handler_func(
                 Ok(res_msg) => {
                     sender.send(Arc::new(res_msg)).await?;
                     cluster_id = auth_msg.sender() as u32;
+                    touch(cluster_id);
                 }
                 Err(_) => {
                     let err_msg = Msg::err_msg(my_id() as u64, auth_msg.sender(), 0, "auth failed");
@@ -71,6 +73,17 @@ pub(super) async fn handler_func(
         let msg = receiver.recv().await;
         match msg {
             Some(mut msg) => {
+                touch(cluster_id);
+                if is_duplicate(&msg) {
+                    debug!(
+                        "duplicate msg dropped: sender {} seqnum {}",
+                        msg.sender(),
+                        msg.seqnum()
+                    );
+                    continue;
+                }
+                // the sender must be connected to the peer which forwards its msg.
+                router.learn(&msg, cluster_id);
                 call_handler_list(&sender, &mut msg, handler_list, inner_states).await?;
             }
             None => {
@@ -88,5 +101,88 @@ pub(super) async fn handler_func(
         }
     }
     cluster_map.remove(&cluster_id);
+    LAST_SEEN.remove(&cluster_id);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use lib::{
+        entity::{Msg, ServerInfo, ServerStatus, ServerType, Type},
+        net::{InnerStates, InnerStatesValue},
+    };
+    use lib_net_tokio::net::{mock::MsgIOWrapperMem, Handler, HandlerList, MsgSender};
+
+    use super::{handler_func, logic, pure_text};
+    use crate::{
+        config::load_test_config,
+        service::{
+            get_client_connection_map,
+            handler::{IOTaskMsg, IOTaskSender},
+        },
+    };
+
+    #[tokio::test]
+    #[ignore = "needs the redis cluster of docker-compose"]
+    async fn test_auth_and_text() {
+        load_test_config();
+        let mut handler_list: Vec<Box<dyn Handler>> = Vec::new();
+        handler_list.push(Box::new(logic::ServerAuth {}));
+        handler_list.push(Box::new(pure_text::Text {}));
+        let handler_list = HandlerList::new(handler_list);
+        let (io_task_sender, mut io_task_receiver) = tokio::sync::mpsc::channel(16);
+        let io_task_sender = IOTaskSender(io_task_sender);
+        let (mut peer, mut node) = MsgIOWrapperMem::pair();
+        let (peer_sender, mut peer_receiver) = peer.channels();
+        let (node_sender, node_receiver) = node.channels();
+        let task = tokio::spawn(async move {
+            let mut inner_states = InnerStates::new();
+            inner_states.insert("client_timestamp".to_owned(), InnerStatesValue::Num(0));
+            handler_func(
+                MsgSender::Server(node_sender),
+                node_receiver,
+                &io_task_sender,
+                &handler_list,
+                &mut inner_states,
+            )
+            .await
+        });
+
+        let peer_id = 7;
+        let server_info = ServerInfo {
+            id: peer_id,
+            service_address: "127.0.0.1:11222".to_string(),
+            cluster_address: Some("127.0.0.1:11220".to_string()),
+            connection_id: 0,
+            status: ServerStatus::Online,
+            typ: ServerType::MessageCluster,
+            load: None,
+        };
+        let mut auth_msg = Msg::raw_payload(&server_info.to_bytes());
+        auth_msg.set_type(Type::Auth);
+        auth_msg.set_sender(peer_id as u64);
+        peer_sender.send(Arc::new(auth_msg)).await.unwrap();
+        let res_msg = peer_receiver.recv().await.unwrap();
+        assert_eq!(res_msg.typ(), Type::Auth);
+        assert_eq!(res_msg.receiver(), peer_id as u64);
+
+        let (client_sender, mut client_receiver) = tokio::sync::mpsc::channel(16);
+        let receiver = (1 << 33) + 1;
+        get_client_connection_map().insert(receiver, MsgSender::Server(client_sender));
+        let mut text_msg = Msg::text(1 << 33, receiver, peer_id, "hello");
+        text_msg.set_seqnum(1);
+        peer_sender.send(Arc::new(text_msg)).await.unwrap();
+        let msg = client_receiver.recv().await.unwrap();
+        assert_eq!(msg.payload(), b"hello");
+        assert_eq!(peer_receiver.recv().await.unwrap().typ(), Type::Ack);
+        match io_task_receiver.recv().await.unwrap() {
+            IOTaskMsg::Direct(msg) => assert_eq!(msg.receiver(), receiver),
+            _ => panic!("unexpected io task msg"),
+        }
+
+        drop(peer_sender);
+        assert!(task.await.unwrap().is_ok());
+    }
+}
@@ -6,10 +6,15 @@ use lib::{entity::Msg, error::HandlerError, net::InnerStates, Result};
 use lib_net_tokio::net::Handler;
 use tracing::debug;
 
+use crate::cache::get_redis_ops;
 use crate::service::handler::IOTaskMsg::Direct;
 use crate::service::handler::IOTaskSender;
 use crate::service::{
-    handler::{is_group_msg, push_group_msg},
+    handler::{
+        dead_letter::{dead_letter, store_offline_or_dead_letter, DeadLetterReason},
+        is_group_msg, push_group_msg,
+        router::{get_router, RouteDecision},
+    },
     ClientConnectionMap,
 };
 use crate::util::my_id;
@@ -41,13 +46,32 @@ impl Handler for Text {
         if is_group_msg(receiver) {
             push_group_msg(msg.clone(), false).await?;
         } else {
-            match client_map.get(&receiver) {
-                Some(client_sender) => {
-                    client_sender.send(msg.clone()).await?;
+            match get_router().route(msg) {
+                RouteDecision::Local => match client_map.get(&receiver) {
+                    Some(client_sender) => {
+                        client_sender.send(msg.clone()).await?;
+                    }
+                    None => {
+                        debug!("receiver {} gone, store it for sync.", receiver);
+                        store_offline_or_dead_letter(&mut get_redis_ops().await, msg).await?;
+                    }
+                },
+                // msgs from peers are never forwarded again, so they can't go around in circles.
+                RouteDecision::Forward(_)
+                | RouteDecision::Unreachable(_)
+                | RouteDecision::Offline => {
+                    debug!("receiver {} not found, store it for sync.", receiver);
+                    store_offline_or_dead_letter(&mut get_redis_ops().await, msg).await?;
                 }
-                None => {
-                    debug!("receiver {} not found", receiver);
+                RouteDecision::Drop => {
+                    dead_letter(
+                        &mut get_redis_ops().await,
+                        DeadLetterReason::UnknownReceiver,
+                        msg,
+                    )
+                    .await;
                 }
+                RouteDecision::Group => {}
             }
             io_task_sender.send(Direct(msg.clone())).await?;
         }
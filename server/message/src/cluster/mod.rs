@@ -1,12 +1,22 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
 use dashmap::{mapref::one::Ref, DashMap};
+use futures::future::join_all;
 use lazy_static::lazy_static;
-use lib::{entity::Msg, net::GenericParameter, util::should_connect_to_peer, Result};
+use lib::{
+    entity::Msg,
+    net::GenericParameter,
+    util::{dedup::Deduplicator, should_connect_to_peer},
+    Result,
+};
 use lib_net_tokio::net::MsgSender;
-use tracing::warn;
+use tracing::{error, info, warn};
 
-use crate::{cluster::client::Client, util::my_id};
+use crate::{cluster::client::Client, config::config, util::my_id};
 
 mod client;
 mod handler;
@@ -18,6 +28,11 @@ lazy_static! {
     static ref CLUSTER_CONNECTION_MAP: ClusterConnectionMap =
         ClusterConnectionMap(Arc::new(DashMap::new()));
     static ref CLUSTER_CLIENT: Client = Client::new();
+    /// shared by all cluster connections, so redelivery after reconnection is caught too.
+    static ref DEDUPLICATOR: Mutex<Deduplicator> =
+        Mutex::new(Deduplicator::new(config().dedup.capacity, config().dedup.ttl));
+    /// when each peer node was heard from last time.
+    static ref LAST_SEEN: DashMap<u32, Instant> = DashMap::new();
 }
 
 impl GenericParameter for ClusterConnectionMap {
@@ -44,6 +59,111 @@ pub(crate) fn get_cluster_connection_map() -> ClusterConnectionMap {
     ClusterConnectionMap(CLUSTER_CONNECTION_MAP.0.clone())
 }
 
+/// send the msg to every peer node concurrently, return the number of nodes succeeded.
+/// failed nodes are logged and pruned from the map instead of aborting the whole broadcast.
+#[allow(unused)]
+pub(crate) async fn broadcast(msg: Arc<Msg>) -> Result<usize> {
+    broadcast_to(&get_cluster_connection_map(), msg).await
+}
+
+pub(self) async fn broadcast_to(map: &ClusterConnectionMap, msg: Arc<Msg>) -> Result<usize> {
+    let senders = map
+        .0
+        .iter()
+        .map(|entry| (*entry.key(), entry.value().clone()))
+        .collect::<Vec<(u32, MsgSender)>>();
+    let results = join_all(senders.into_iter().map(|(node_id, sender)| {
+        let msg = msg.clone();
+        async move { (node_id, sender.send(msg).await) }
+    }))
+    .await;
+    let mut succeeded = 0;
+    for (node_id, res) in results {
+        match res {
+            Ok(_) => succeeded += 1,
+            Err(e) => {
+                error!("broadcast to node[{}] failed: {}", node_id, e);
+                map.0.remove(&node_id);
+            }
+        }
+    }
+    Ok(succeeded)
+}
+
+/// ping every cluster connection at `interval`, and evict the ones whose channel is closed or
+/// who stay silent for longer than `deadline`.
+pub(crate) async fn reap_idle_connections(interval: Duration, deadline: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let evicted = reap(&get_cluster_connection_map(), &LAST_SEEN, deadline).await;
+        if evicted > 0 {
+            info!("{} idle cluster connections evicted", evicted);
+        }
+    }
+}
+
+/// one reap cycle, return the number of connections evicted.
+pub(self) async fn reap(
+    map: &ClusterConnectionMap,
+    last_seen: &DashMap<u32, Instant>,
+    deadline: Duration,
+) -> usize {
+    let senders = map
+        .0
+        .iter()
+        .map(|entry| (*entry.key(), entry.value().clone()))
+        .collect::<Vec<(u32, MsgSender)>>();
+    let mut alive = Vec::with_capacity(senders.len());
+    let mut evicted = 0;
+    for (node_id, sender) in senders {
+        // a connection never heard from starts counting now.
+        let silent = last_seen
+            .entry(node_id)
+            .or_insert_with(Instant::now)
+            .elapsed()
+            > deadline;
+        if sender.is_closed() {
+            warn!("evict node[{}]: channel closed", node_id);
+        } else if silent {
+            warn!("evict node[{}]: no response within {:?}", node_id, deadline);
+        } else {
+            alive.push((node_id, sender));
+            continue;
+        }
+        map.0.remove(&node_id);
+        last_seen.remove(&node_id);
+        evicted += 1;
+    }
+    // the pong is recorded by the handler of the connection, and checked at the next cycle.
+    let results = join_all(alive.into_iter().map(|(node_id, sender)| async move {
+        let ping = Msg::ping(my_id() as u64, node_id as u64, my_id());
+        let res = tokio::time::timeout(deadline, sender.send(Arc::new(ping))).await;
+        (node_id, res)
+    }))
+    .await;
+    for (node_id, res) in results {
+        if let Ok(Ok(_)) = res {
+            continue;
+        }
+        warn!("evict node[{}]: ping failed", node_id);
+        map.0.remove(&node_id);
+        last_seen.remove(&node_id);
+        evicted += 1;
+    }
+    evicted
+}
+
+/// remember a msg has just arrived from `node_id`.
+pub(self) fn touch(node_id: u32) {
+    LAST_SEEN.insert(node_id, Instant::now());
+}
+
+/// true if the msg has been received from the cluster before.
+pub(self) fn is_duplicate(msg: &Msg) -> bool {
+    DEDUPLICATOR.lock().unwrap().seen_before(msg)
+}
+
 pub(crate) async fn node_online(address: SocketAddr, node_id: u32, new_peer: bool) -> Result<()> {
     if should_connect_to_peer(my_id(), node_id, new_peer) {
         CLUSTER_CLIENT.new_connection(address).await?;
@@ -54,6 +174,7 @@ pub(crate) async fn node_online(address: SocketAddr, node_id: u32, new_peer: boo
 pub(crate) async fn node_offline(node_id: u32) -> Result<()> {
     warn!("node[{}] offline", node_id);
     CLUSTER_CONNECTION_MAP.0.remove(&node_id);
+    LAST_SEEN.remove(&node_id);
     Ok(())
 }
 
@@ -64,6 +185,60 @@ pub(crate) async fn node_crash(msg: Arc<Msg>) -> Result<()> {
 
 #[allow(unused)]
 pub(crate) async fn start() -> Result<()> {
+    tokio::spawn(reap_idle_connections(
+        config().transport.reap_interval,
+        Duration::from_millis(config().transport.connection_idle_timeout),
+    ));
     server::Server::run().await?;
     Ok(())
 }
+
+pub(crate) async fn shutdown() {
+    server::Server::shutdown().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use dashmap::DashMap;
+    use lib::entity::{Msg, Type};
+    use lib_net_tokio::net::MsgSender;
+
+    use super::{broadcast_to, reap, ClusterConnectionMap};
+
+    #[tokio::test]
+    async fn test_broadcast() {
+        let map = ClusterConnectionMap(Arc::new(DashMap::new()));
+        let (sender1, _receiver1) = async_channel::bounded(8);
+        let (sender2, _receiver2) = tokio::sync::mpsc::channel(8);
+        let (sender3, receiver3) = async_channel::bounded(8);
+        drop(receiver3);
+        map.insert(1, MsgSender::Client(sender1));
+        map.insert(2, MsgSender::Server(sender2));
+        map.insert(3, MsgSender::Client(sender3));
+        let succeeded = broadcast_to(&map, Arc::new(Msg::text(1, 2, 0, "hello")))
+            .await
+            .unwrap();
+        assert_eq!(succeeded, 2);
+        assert_eq!(map.0.len(), 2);
+        assert!(map.get(&3).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_reap_closed() {
+        let map = ClusterConnectionMap(Arc::new(DashMap::new()));
+        let last_seen = DashMap::new();
+        let (sender1, mut receiver1) = tokio::sync::mpsc::channel(8);
+        let (sender2, receiver2) = tokio::sync::mpsc::channel(8);
+        drop(receiver2);
+        map.insert(1, MsgSender::Server(sender1));
+        map.insert(2, MsgSender::Server(sender2));
+        let evicted = reap(&map, &last_seen, Duration::from_secs(3)).await;
+        assert_eq!(evicted, 1);
+        assert!(map.get(&1).is_some());
+        assert!(map.get(&2).is_none());
+        assert!(last_seen.get(&2).is_none());
+        assert_eq!(receiver1.recv().await.unwrap().typ(), Type::Ping);
+    }
+}
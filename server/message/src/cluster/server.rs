@@ -4,10 +4,14 @@ use lib::{
     Result,
 };
 use lib_net_tokio::net::{
-    server::{NewConnectionHandler, NewConnectionHandlerGenerator, Server as UdpServer},
+    server::{
+        NewConnectionHandler, NewConnectionHandlerGenerator, Server as UdpServer, ServerShutdown,
+    },
     Handler, HandlerList, MsgIOWrapper,
 };
 
+use tokio::sync::OnceCell;
+
 use super::handler::{logger, logic, pure_text};
 
 use crate::{
@@ -51,6 +55,9 @@ impl NewConnectionHandler for ClusterConnectionHandler {
     }
 }
 
+/// set once the server is up, so it can be stopped on shutdown.
+pub(self) static SERVER_SHUTDOWN: OnceCell<ServerShutdown> = OnceCell::const_new();
+
 pub(crate) struct Server {}
 
 impl Server {
@@ -84,12 +91,18 @@ impl Server {
             .with_max_connections(config().server.max_connections)
             .with_connection_idle_timeout(config().transport.connection_idle_timeout)
             .with_max_bi_streams(config().transport.max_bi_streams);
-        let server_config = server_config_builder.build().unwrap();
+        if let Some(compress_threshold) = config().transport.compress_threshold {
+            server_config_builder.with_compress_threshold(compress_threshold);
+        }
+        let server_config = server_config_builder.build()?;
         // todo("timeout set")!
         let mut server = UdpServer::new(server_config);
+        _ = SERVER_SHUTDOWN.set(server.shutdown_handle());
         let mut handler_list: Vec<Box<dyn Handler>> = Vec::new();
         handler_list.push(Box::new(logic::ServerAuth {}));
         handler_list.push(Box::new(logger::Ack {}));
+        handler_list.push(Box::new(logic::Ping {}));
+        handler_list.push(Box::new(logic::Presence {}));
         handler_list.push(Box::new(pure_text::Text {}));
         let handler_list = HandlerList::new(handler_list);
         let io_task_sender = get_io_task_sender().clone();
@@ -102,4 +115,10 @@ impl Server {
         server.run(generator).await?;
         Ok(())
     }
+    /// stop accepting peers and let the in-flight streams drain, no-op if not running.
+    pub(crate) async fn shutdown() {
+        if let Some(shutdown) = SERVER_SHUTDOWN.get() {
+            shutdown.shutdown().await;
+        }
+    }
 }
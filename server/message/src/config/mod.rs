@@ -6,8 +6,19 @@ use std::{
 };
 
 use anyhow::Context;
+use lib::{
+    entity::PAYLOAD_THRESHOLD,
+    util::{
+        env::{load_config_table, ENV_PREFIX},
+        parse_log_level,
+        time::set_clock_offset,
+    },
+    Result,
+};
 use tracing::Level;
 
+pub(crate) mod runtime;
+
 #[derive(serde::Deserialize, Debug)]
 struct Config0 {
     log_level: Option<String>,
@@ -18,6 +29,8 @@ struct Config0 {
     rpc: Option<Rpc0>,
     seqnum: Option<Seqnum0>,
     message_queue: Option<MessageQueue0>,
+    dedup: Option<Dedup0>,
+    quota: Option<Quota0>,
 }
 
 #[derive(Debug)]
@@ -30,6 +43,10 @@ pub(crate) struct Config {
     pub(crate) rpc: Rpc,
     pub(crate) seqnum: Seqnum,
     pub(crate) message_queue: MessageQueue,
+    pub(crate) dedup: Dedup,
+    /// send rate limit of each account, no limit if not configured.
+    /// only the initial one, the live one is `runtime::quota()`.
+    pub(crate) quota: Option<Quota>,
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -42,6 +59,8 @@ struct Server0 {
     cert_path: Option<String>,
     key_path: Option<String>,
     max_connections: Option<usize>,
+    max_payload_bytes: Option<usize>,
+    clock_offset: Option<i64>,
 }
 
 #[derive(Debug)]
@@ -57,7 +76,12 @@ pub(crate) struct Server {
     pub(crate) domain: String,
     pub(crate) cert: rustls::Certificate,
     pub(crate) key: rustls::PrivateKey,
+    pub(crate) cert_path: PathBuf,
+    pub(crate) key_path: PathBuf,
     pub(crate) max_connections: usize,
+    pub(crate) max_payload_bytes: usize,
+    /// in milliseconds, added to the local clock to correct its skew from ntp.
+    pub(crate) clock_offset: i64,
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -65,6 +89,10 @@ struct Transport0 {
     keep_alive_interval: Option<u64>,
     connection_idle_timeout: Option<u64>,
     max_bi_streams: Option<usize>,
+    compress_threshold: Option<usize>,
+    reap_interval: Option<u64>,
+    high_water: Option<usize>,
+    low_water: Option<usize>,
 }
 
 #[derive(Debug)]
@@ -72,18 +100,27 @@ pub(crate) struct Transport {
     pub(crate) keep_alive_interval: Duration,
     pub(crate) connection_idle_timeout: u64,
     pub(crate) max_bi_streams: usize,
+    /// payloads forwarded between nodes longer than this are compressed, never if not configured.
+    pub(crate) compress_threshold: Option<usize>,
+    /// how often cluster connections are pinged and the dead ones evicted.
+    pub(crate) reap_interval: Duration,
+    /// `(high, low)` water marks in msgs, clients are asked to pause sending on a stream whose
+    /// msgs waiting for the handlers reach `high`, never if not configured.
+    pub(crate) flow_control: Option<(usize, usize)>,
 }
 
 #[derive(serde::Deserialize, Debug)]
 struct Redis0 {
     addresses: Option<Vec<String>>,
     passwords: Option<Vec<String>>,
+    pool_size: Option<usize>,
 }
 
 #[derive(Debug)]
 pub(crate) struct Redis {
     pub(crate) addresses: Vec<SocketAddr>,
     pub(crate) passwords: Vec<String>,
+    pub(crate) pool_size: usize,
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -143,11 +180,15 @@ pub(crate) struct Rpc {
 #[derive(serde::Deserialize, Debug)]
 struct Seqnum0 {
     cert_path: Option<String>,
+    max_in_flight: Option<u64>,
 }
 
 #[derive(Debug)]
 pub(crate) struct Seqnum {
     pub(crate) cert: rustls::Certificate,
+    /// seqnum requests waiting for responses from one node, the ones beyond it fail at once.
+    /// no limit if not configured.
+    pub(crate) max_in_flight: Option<u64>,
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -160,16 +201,38 @@ pub(crate) struct MessageQueue {
     pub(crate) address: String,
 }
 
+#[derive(serde::Deserialize, Debug)]
+struct Dedup0 {
+    capacity: Option<usize>,
+    ttl: Option<u64>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Dedup {
+    pub(crate) capacity: usize,
+    pub(crate) ttl: Duration,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct Quota0 {
+    rate: Option<f64>,
+    burst: Option<u64>,
+    group_rate: Option<f64>,
+    group_burst: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Quota {
+    /// msgs per second.
+    pub(crate) rate: f64,
+    pub(crate) burst: u64,
+    pub(crate) group_rate: f64,
+    pub(crate) group_burst: u64,
+}
+
 impl Config {
     fn from_config0(config0: Config0) -> Config {
-        let log_level = match config0.log_level.unwrap_or("info".to_string()).as_ref() {
-            "trace" => Level::TRACE,
-            "debug" => Level::DEBUG,
-            "info" => Level::INFO,
-            "warn" => Level::WARN,
-            "error" => Level::ERROR,
-            _ => Level::INFO,
-        };
+        let log_level = parse_log_level(config0.log_level);
         Config {
             log_level,
             server: Server::from_server0(config0.server.unwrap()),
@@ -179,16 +242,23 @@ impl Config {
             rpc: Rpc::from_rpc0(config0.rpc.unwrap()),
             seqnum: Seqnum::from_seqnum0(config0.seqnum.unwrap()),
             message_queue: MessageQueue::from_message_queue0(config0.message_queue.unwrap()),
+            dedup: Dedup::from_dedup0(config0.dedup.unwrap_or(Dedup0 {
+                capacity: None,
+                ttl: None,
+            })),
+            quota: config0.quota.map(Quota::from_quota0),
         }
     }
 }
 
 impl Server {
     fn from_server0(server0: Server0) -> Self {
-        let cert = fs::read(PathBuf::from(server0.cert_path.as_ref().unwrap()))
+        let cert_path = PathBuf::from(server0.cert_path.as_ref().unwrap());
+        let key_path = PathBuf::from(server0.key_path.as_ref().unwrap());
+        let cert = fs::read(&cert_path)
             .context("read cert file failed.")
             .unwrap();
-        let key = fs::read(PathBuf::from(server0.key_path.as_ref().unwrap()))
+        let key = fs::read(&key_path)
             .context("read key file failed.")
             .unwrap();
         Server {
@@ -199,9 +269,20 @@ impl Server {
             domain: server0.domain.unwrap(),
             cert: rustls::Certificate(cert),
             key: rustls::PrivateKey(key),
+            cert_path,
+            key_path,
             max_connections: server0.max_connections.unwrap(),
+            max_payload_bytes: server0.max_payload_bytes.unwrap_or(PAYLOAD_THRESHOLD),
+            clock_offset: server0.clock_offset.unwrap_or(0),
         }
     }
+
+    /// read the cert and key again from where they were loaded, e.g. after they are rotated.
+    pub(crate) fn read_cert(&self) -> Result<(rustls::Certificate, rustls::PrivateKey)> {
+        let cert = fs::read(&self.cert_path).context("read cert file failed.")?;
+        let key = fs::read(&self.key_path).context("read key file failed.")?;
+        Ok((rustls::Certificate(cert), rustls::PrivateKey(key)))
+    }
 }
 
 impl Transport {
@@ -210,6 +291,13 @@ impl Transport {
             keep_alive_interval: Duration::from_millis(transport0.keep_alive_interval.unwrap()),
             connection_idle_timeout: transport0.connection_idle_timeout.unwrap(),
             max_bi_streams: transport0.max_bi_streams.unwrap(),
+            compress_threshold: transport0.compress_threshold,
+            reap_interval: Duration::from_millis(transport0.reap_interval.unwrap_or(3000)),
+            flow_control: match (transport0.high_water, transport0.low_water) {
+                (Some(high_water), Some(low_water)) => Some((high_water, low_water)),
+                (None, None) => None,
+                _ => panic!("high_water and low_water should be set together."),
+            },
         }
     }
 }
@@ -225,7 +313,11 @@ impl Redis {
                     .collect::<Vec<SocketAddr>>()[0],
             );
         }
-        Redis { addresses: addr, passwords: redis0.passwords.unwrap_or(vec![]) }
+        Redis {
+            addresses: addr,
+            passwords: redis0.passwords.unwrap_or(vec![]),
+            pool_size: redis0.pool_size.unwrap_or(4),
+        }
     }
 }
 
@@ -303,6 +395,7 @@ impl Seqnum {
             .unwrap();
         Seqnum {
             cert: rustls::Certificate(cert),
+            max_in_flight: seqnum0.max_in_flight,
         }
     }
 }
@@ -315,17 +408,59 @@ impl MessageQueue {
     }
 }
 
-pub(crate) fn load_config(config_path: &str) {
-    let toml_str = fs::read_to_string(config_path).unwrap();
-    let config0: Config0 = toml::from_str(&toml_str).unwrap();
-    let mut config = Config::from_config0(config0);
-    if let Ok(address) = std::env::var("CLUSTER_ADDRESS") {
-        config.server.cluster_address = address;
+impl Dedup {
+    fn from_dedup0(dedup0: Dedup0) -> Self {
+        Dedup {
+            capacity: dedup0.capacity.unwrap_or(65536),
+            ttl: Duration::from_millis(dedup0.ttl.unwrap_or(60000)),
+        }
     }
-    if let Ok(address) = std::env::var("SERVICE_ADDRESS") {
-        config.server.service_address = address;
+}
+
+impl Quota {
+    fn from_quota0(quota0: Quota0) -> Self {
+        let rate = quota0.rate.unwrap_or(10.0);
+        let burst = quota0.burst.unwrap_or(20);
+        Quota {
+            rate,
+            burst,
+            group_rate: quota0.group_rate.unwrap_or(rate),
+            group_burst: quota0.group_burst.unwrap_or(burst),
+        }
     }
+}
+
+/// fields overridable by an env var named after them, e.g. `server.cluster_address` by
+/// `PRIM_SERVER_CLUSTER_ADDRESS`, see `apply_env_overrides`.
+pub(self) const ENV_KEYS: [&str; 13] = [
+    "log_level",
+    "server.cluster_address",
+    "server.service_address",
+    "server.domain",
+    "redis.addresses[]",
+    "redis.passwords[]",
+    "scheduler.address",
+    "scheduler.domain",
+    "rpc.scheduler.address",
+    "rpc.scheduler.domain",
+    "rpc.api.address",
+    "rpc.api.domain",
+    "message_queue.address",
+];
+
+/// env > file > default.
+pub(crate) fn load_config(config_path: &str) -> Result<()> {
+    let table = load_config_table(config_path, ENV_PREFIX, &ENV_KEYS, |name| {
+        std::env::var(name).ok()
+    })?;
+    let config0: Config0 = toml::Value::Table(table)
+        .try_into()
+        .with_context(|| format!("parse config file {} failed.", config_path))?;
+    let config = Config::from_config0(config0);
+    set_clock_offset(config.server.clock_offset);
+    runtime::set_quota(config.quota.clone());
     unsafe { CONFIG.replace(config) };
+    Ok(())
 }
 
 pub(self) static mut CONFIG: Option<Config> = None;
@@ -333,3 +468,28 @@ pub(self) static mut CONFIG: Option<Config> = None;
 pub(crate) fn config() -> &'static Config {
     unsafe { CONFIG.as_ref().unwrap() }
 }
+
+/// load `config-example.toml` for tests, the cert files it points to are replaced by placeholders.
+#[cfg(test)]
+pub(crate) fn load_test_config() {
+    static LOADED: std::sync::Once = std::sync::Once::new();
+    LOADED.call_once(|| {
+        let dir = std::env::temp_dir().join(format!("prim-message-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in [
+            "localhost-server.crt.der",
+            "localhost-server.key.der",
+            "PrimRootCA.crt.der",
+            "PrimRootCA.crt",
+        ] {
+            fs::write(dir.join(name), b"placeholder").unwrap();
+        }
+        let toml_str =
+            fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/config-example.toml"))
+                .unwrap()
+                .replace("<path>/prim/server/cert", dir.to_str().unwrap());
+        let path = dir.join("config.toml");
+        fs::write(&path, toml_str).unwrap();
+        load_config(path.to_str().unwrap()).unwrap();
+    });
+}
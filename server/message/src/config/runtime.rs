@@ -0,0 +1,111 @@
+use std::sync::RwLock;
+
+use anyhow::anyhow;
+use lazy_static::lazy_static;
+use lib::Result;
+use serde_json::Value;
+
+use super::Quota;
+
+/// the settings below can be changed by `scheduler` while the node is running, the ones in
+/// `config()` are fixed after startup.
+pub(crate) const ADJUSTABLE_KEYS: [&str; 4] = [
+    "quota.rate",
+    "quota.burst",
+    "quota.group_rate",
+    "quota.group_burst",
+];
+
+lazy_static! {
+    static ref QUOTA: RwLock<Option<Quota>> = RwLock::new(None);
+}
+
+pub(crate) fn set_quota(quota: Option<Quota>) {
+    *QUOTA.write().unwrap() = quota;
+}
+
+/// the live send quota, no limit if not configured.
+pub(crate) fn quota() -> Option<Quota> {
+    QUOTA.read().unwrap().clone()
+}
+
+/// apply a delta pushed as a flat json object like `{"quota.rate": 20.0}`, return the keys
+/// applied.
+///
+/// nothing is applied if any of the keys is not adjustable or any of the values is invalid.
+/// the quota can only be adjusted but not enabled, since the handler is decided at startup.
+pub(crate) fn apply_config_delta(delta: &[u8]) -> Result<Vec<String>> {
+    let delta: serde_json::Map<String, Value> = serde_json::from_slice(delta)?;
+    if let Some(key) = delta
+        .keys()
+        .find(|key| !ADJUSTABLE_KEYS.contains(&key.as_str()))
+    {
+        return Err(anyhow!("{} is not adjustable at runtime", key));
+    }
+    let mut quota_guard = QUOTA.write().unwrap();
+    let mut quota = match quota_guard.clone() {
+        Some(quota) => quota,
+        None if delta.is_empty() => return Ok(vec![]),
+        None => return Err(anyhow!("quota is not enabled on this node")),
+    };
+    for (key, value) in delta.iter() {
+        match key.as_str() {
+            "quota.rate" => quota.rate = positive_f64(key, value)?,
+            "quota.burst" => quota.burst = positive_u64(key, value)?,
+            "quota.group_rate" => quota.group_rate = positive_f64(key, value)?,
+            "quota.group_burst" => quota.group_burst = positive_u64(key, value)?,
+            _ => unreachable!(),
+        }
+    }
+    quota_guard.replace(quota);
+    let mut applied = delta.keys().cloned().collect::<Vec<String>>();
+    applied.sort();
+    Ok(applied)
+}
+
+pub(self) fn positive_f64(key: &str, value: &Value) -> Result<f64> {
+    match value.as_f64() {
+        Some(value) if value > 0.0 && value.is_finite() => Ok(value),
+        _ => Err(anyhow!("{} must be a positive number", key)),
+    }
+}
+
+pub(self) fn positive_u64(key: &str, value: &Value) -> Result<u64> {
+    match value.as_u64() {
+        Some(value) if value > 0 => Ok(value),
+        _ => Err(anyhow!("{} must be a positive integer", key)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply_config_delta, quota};
+    use crate::config::load_test_config;
+
+    #[test]
+    fn test_apply_config_delta() {
+        load_test_config();
+        let before = quota().unwrap();
+        let applied = apply_config_delta(br#"{"quota.rate": 42.5, "quota.burst": 64}"#).unwrap();
+        assert_eq!(applied, vec!["quota.burst", "quota.rate"]);
+        let after = quota().unwrap();
+        assert_eq!(after.rate, 42.5);
+        assert_eq!(after.burst, 64);
+        assert_eq!(after.group_rate, before.group_rate);
+
+        // rejected as a whole, the valid key is not applied either.
+        assert!(
+            apply_config_delta(br#"{"quota.rate": 1.0, "server.max_connections": 1}"#).is_err()
+        );
+        assert!(apply_config_delta(br#"{"quota.rate": 1.0, "quota.burst": 0}"#).is_err());
+        assert_eq!(quota().unwrap().rate, 42.5);
+        apply_config_delta(
+            &serde_json::to_vec(&serde_json::json!({
+                "quota.rate": before.rate,
+                "quota.burst": before.burst,
+            }))
+            .unwrap(),
+        )
+        .unwrap();
+    }
+}
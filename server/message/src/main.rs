@@ -1,10 +1,19 @@
-use lib::{joy, Result};
+use std::time::Duration;
+
+use common::shutdown::shutdown_signal;
+use lib::{
+    joy,
+    util::{health, load::start_load_updater},
+    Result,
+};
 use structopt::StructOpt;
 use tracing::{error, info};
 
 use crate::{
+    cache::get_redis_ops,
+    cluster::get_cluster_connection_map,
     config::config,
-    service::{load_io_task, load_msglogger},
+    service::{get_client_connection_map, load_io_task, load_msglogger},
 };
 use crate::config::load_config;
 
@@ -86,7 +95,7 @@ async fn main() -> Result<()> {
         Ok(config_path) => config_path,
         Err(_) => opt.config,
     };
-    load_config(&config_path);
+    load_config(&config_path)?;
     tracing_subscriber::fmt()
         .event_format(
             tracing_subscriber::fmt::format()
@@ -107,6 +116,11 @@ async fn main() -> Result<()> {
     );
     load_msglogger().await?;
     load_io_task();
+    // sampled as often as it's reported to scheduler by the heartbeat.
+    start_load_updater(schedule::HEARTBEAT_INTERVAL, || {
+        get_client_connection_map().0.len() as u32
+    });
+    tokio::spawn(update_health(Duration::from_secs(5)));
     tokio::spawn(async move {
         if let Err(e) = cluster::start().await {
             error!("cluster error: {}", e);
@@ -117,6 +131,31 @@ async fn main() -> Result<()> {
             error!("schedule error: {}", e);
         }
     });
-    service::start().await?;
+    let signal = shutdown_signal()?;
+    let service = tokio::spawn(service::start());
+    tokio::select! {
+        res = service => res??,
+        _ = signal => shutdown().await,
+    }
     Ok(())
 }
+
+/// leave the cluster gracefully: stop accepting clients and peers, let the in-flight
+/// streams drain, then deregister from scheduler so nothing is routed here any more.
+pub(self) async fn shutdown() {
+    health::set_registered(false);
+    tokio::join!(service::shutdown(), cluster::shutdown());
+    if let Err(e) = schedule::deregister().await {
+        error!("deregister from scheduler error: {}", e);
+    }
+    info!("prim message[{}] shutdown.", util::my_id());
+}
+
+/// refresh the dependency status reported by health probes, readiness is set by registration.
+pub(self) async fn update_health(interval: Duration) {
+    loop {
+        health::set_redis_reachable(get_redis_ops().await.ping().await.is_ok());
+        health::set_cluster_peers(get_cluster_connection_map().0.len() as u32);
+        tokio::time::sleep(interval).await;
+    }
+}
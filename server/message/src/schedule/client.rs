@@ -5,9 +5,10 @@ use common::scheduler::connect2scheduler;
 use lib::{
     entity::{ReqwestResourceID, ServerInfo, ServerStatus, ServerType},
     net::{client::ClientConfigBuilder, GenericParameterMap, InnerStates, InnerStatesValue},
+    util::load::current_load,
     Result,
 };
-use lib_net_tokio::net::{Handler, ReqwestHandler, ReqwestHandlerMap};
+use lib_net_tokio::net::{Handler, ReqwestHandler, ReqwestHandlerMap, ReqwestOperatorManager};
 
 use crate::service::{get_io_task_sender, get_msglogger_client};
 use crate::{
@@ -32,7 +33,7 @@ use super::handler::{
 pub(super) struct Client {}
 
 impl Client {
-    pub(super) async fn run() -> Result<()> {
+    pub(super) async fn run() -> Result<ReqwestOperatorManager> {
         let address = config().scheduler.address;
         let mut config_builder = ClientConfigBuilder::default();
         config_builder
@@ -66,17 +67,17 @@ impl Client {
             ReqwestResourceID::MessageForward,
             Box::new(internal::MessageForward { handler_list }),
         );
+        handler_map.insert(
+            ReqwestResourceID::DeadLetters,
+            Box::new(internal::DeadLetters {}),
+        );
+        handler_map.insert(
+            ReqwestResourceID::ConfigPush,
+            Box::new(internal::ConfigPush {}),
+        );
+        handler_map.insert(ReqwestResourceID::Metrics, Box::new(internal::Metrics {}));
         let handler_map = ReqwestHandlerMap::new(handler_map);
 
-        let server_info = ServerInfo {
-            id: my_id(),
-            service_address: config().server.service_address.clone(),
-            cluster_address: Some(config().server.cluster_address.clone()),
-            connection_id: 0,
-            status: ServerStatus::Online,
-            typ: ServerType::SeqnumCluster,
-            load: None,
-        };
         let redis_ops = get_redis_ops().await;
         let states_gen = Box::new(move || {
             let mut generic_map = GenericParameterMap(AHashMap::new());
@@ -93,15 +94,28 @@ impl Client {
             );
             states
         });
-        let _operator = connect2scheduler(
+        connect2scheduler(
             client_config,
             Duration::from_millis(3000),
             handler_map,
-            server_info,
+            server_info(),
             states_gen,
             ReqwestResourceID::MessageNodeRegister,
         )
-        .await?;
-        Ok(())
+        .await
+    }
+}
+
+/// what this node registers to scheduler as, also the source of every heartbeat, so the load
+/// scheduler routes users by is the one sampled last.
+pub(super) fn server_info() -> ServerInfo {
+    ServerInfo {
+        id: my_id(),
+        service_address: config().server.service_address.clone(),
+        cluster_address: Some(config().server.cluster_address.clone()),
+        connection_id: 0,
+        status: ServerStatus::Online,
+        typ: ServerType::SeqnumCluster,
+        load: current_load(),
     }
 }
@@ -4,23 +4,31 @@ use std::{
 };
 
 use async_trait::async_trait;
+use byteorder::{BigEndian, ByteOrder};
 use lib_net_tokio::net::{Handler, ReqwestHandler};
-use tracing::error;
+use tracing::{error, info};
 
 use lib::{
+    cache::redis_ops::RedisOps,
     entity::{Msg, ReqwestMsg, ServerInfo, Type},
     error::HandlerError,
     net::InnerStates,
+    util::histogram::payload_histogram,
     Result,
 };
 
+use crate::{
+    config::runtime::apply_config_delta,
+    service::handler::dead_letter::{recent_dead_letters, DEAD_LETTER_DEFAULT_COUNT},
+};
+
 pub(crate) struct NodeRegister {}
 
 #[async_trait]
 impl ReqwestHandler for NodeRegister {
     async fn run(&self, msg: &mut ReqwestMsg, _states: &mut InnerStates) -> Result<ReqwestMsg> {
         let new_peer = msg.payload()[0] == 1;
-        let server_info = ServerInfo::from(&(msg.payload())[1..]);
+        let server_info = ServerInfo::from_bytes(&(msg.payload())[1..])?;
         crate::cluster::node_online(
             server_info
                 .cluster_address
@@ -41,12 +49,90 @@ pub(crate) struct NodeUnregister {}
 #[async_trait]
 impl ReqwestHandler for NodeUnregister {
     async fn run(&self, msg: &mut ReqwestMsg, _states: &mut InnerStates) -> Result<ReqwestMsg> {
-        let node_info = ServerInfo::from(msg.payload());
+        let node_info = ServerInfo::from_bytes(msg.payload())?;
         crate::cluster::node_offline(node_info.id).await?;
         Ok(ReqwestMsg::default())
     }
 }
 
+/// answers with a json array of the latest dead letters, the optional payload is the count
+/// wanted as big endian u16.
+pub(crate) struct DeadLetters {}
+
+#[async_trait]
+impl ReqwestHandler for DeadLetters {
+    async fn run(&self, req: &mut ReqwestMsg, states: &mut InnerStates) -> Result<ReqwestMsg> {
+        let count = if req.payload().len() >= 2 {
+            BigEndian::read_u16(req.payload()) as usize
+        } else {
+            DEAD_LETTER_DEFAULT_COUNT
+        };
+        let redis_ops = states
+            .get_mut("generic_map")
+            .unwrap()
+            .as_mut_generic_parameter_map()
+            .unwrap()
+            .get_parameter_mut::<RedisOps>()
+            .unwrap();
+        let mut summaries = recent_dead_letters(redis_ops, count)
+            .await?
+            .iter()
+            .map(|letter| letter.summary())
+            .collect::<Vec<serde_json::Value>>();
+        let mut payload = serde_json::to_vec(&summaries)?;
+        // the oldest ones are left out if the reqwest can't hold them all.
+        while payload.len() > u16::MAX as usize - 10 {
+            summaries.pop();
+            payload = serde_json::to_vec(&summaries)?;
+        }
+        Ok(ReqwestMsg::with_resource_id_payload(
+            req.resource_id(),
+            &payload,
+        ))
+    }
+}
+
+/// applies the config delta pushed by `scheduler`, answers with `{"applied": [...]}` or
+/// `{"error": "..."}` if the delta is rejected as a whole.
+pub(crate) struct ConfigPush {}
+
+#[async_trait]
+impl ReqwestHandler for ConfigPush {
+    async fn run(&self, req: &mut ReqwestMsg, _states: &mut InnerStates) -> Result<ReqwestMsg> {
+        let res = match apply_config_delta(req.payload()) {
+            Ok(applied) => {
+                info!("config pushed: {:?}", applied);
+                serde_json::json!({ "applied": applied })
+            }
+            Err(e) => {
+                error!("config push rejected: {}", e);
+                serde_json::json!({ "error": e.to_string() })
+            }
+        };
+        Ok(ReqwestMsg::with_resource_id_payload(
+            req.resource_id(),
+            &serde_json::to_vec(&res)?,
+        ))
+    }
+}
+
+/// answers with the metrics of this node as a json object, for now the payload sizes of the
+/// msgs received, see `PayloadSizes`.
+pub(crate) struct Metrics {}
+
+#[async_trait]
+impl ReqwestHandler for Metrics {
+    async fn run(&self, req: &mut ReqwestMsg, _states: &mut InnerStates) -> Result<ReqwestMsg> {
+        let metrics = serde_json::json!({
+            "payload_sizes": payload_histogram().snapshot(),
+        });
+        Ok(ReqwestMsg::with_resource_id_payload(
+            req.resource_id(),
+            &serde_json::to_vec(&metrics)?,
+        ))
+    }
+}
+
 pub(crate) struct MessageForward {
     pub(crate) handler_list: Vec<Box<dyn Handler>>,
 }
@@ -6,7 +6,7 @@ use async_trait::async_trait;
 use byteorder::{BigEndian, ByteOrder};
 use lib::{
     entity::{Msg, ReqwestMsg, ReqwestResourceID},
-    error::HandlerError,
+    error::{HandlerError, SeqnumError},
     net::{client::ClientConfigBuilder, InnerStates, InnerStatesValue},
     util::timestamp,
     Result,
@@ -155,7 +155,7 @@ impl Handler for PreProcess {
                     .with_max_bi_streams(config().transport.max_bi_streams);
                 let client_config = client_config.build().unwrap();
                 let mut client = ClientReqwestTcp::new(client_config, Duration::from_millis(3000));
-                let operator_manager = match client.build().await {
+                let mut operator_manager = match client.build().await {
                     Ok(operator_manager) => operator_manager,
                     Err(e) => {
                         error!("build client failed: {}", e);
@@ -164,6 +164,9 @@ impl Handler for PreProcess {
                         )));
                     }
                 };
+                if let Some(max_in_flight) = config().seqnum.max_in_flight {
+                    operator_manager.set_max_in_flight(max_in_flight);
+                }
                 get_seqnum_client_holder()
                     .write()
                     .await
@@ -183,7 +186,13 @@ impl Handler for PreProcess {
                 ))
                 .await
             {
-                Ok(resp) => BigEndian::read_u64(&resp.payload()[0..8]),
+                Ok(resp) => match SeqnumError::from_reqwest(&resp) {
+                    Some(e) => {
+                        error!("call seqnum failed: {}", e);
+                        return Err(anyhow!(e));
+                    }
+                    None => BigEndian::read_u64(&resp.payload()[0..8]),
+                },
                 Err(e) => {
                     error!("call seqnum failed: {}", e);
                     return Err(anyhow!(HandlerError::Other(
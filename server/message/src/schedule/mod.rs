@@ -1,9 +1,50 @@
 mod client;
 mod handler;
 
-use lib::Result;
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use common::scheduler;
+use lib::{entity::ReqwestResourceID, Result};
+use lib_net_tokio::net::ReqwestOperatorManager;
+use tokio::sync::OnceCell;
+
+/// the load is reported along with every heartbeat.
+pub(crate) const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// the connection to scheduler, kept for deregistering on shutdown.
+pub(self) static SCHEDULER_OPERATOR: OnceCell<ReqwestOperatorManager> = OnceCell::const_new();
+/// set once deregistering starts, so the heartbeat never registers this node again.
+pub(self) static DEREGISTERED: AtomicBool = AtomicBool::new(false);
 
 pub(crate) async fn start() -> Result<()> {
-    client::Client::run().await?;
+    let operator = client::Client::run().await?;
+    _ = SCHEDULER_OPERATOR.set(operator);
+    tokio::spawn(scheduler::heartbeat(
+        SCHEDULER_OPERATOR.get().unwrap(),
+        client::server_info,
+        ReqwestResourceID::MessageNodeHeartbeat,
+        ReqwestResourceID::MessageNodeRegister,
+        HEARTBEAT_INTERVAL,
+        || DEREGISTERED.load(Ordering::Acquire),
+    ));
     Ok(())
 }
+
+/// no-op if this node has not registered yet.
+pub(crate) async fn deregister() -> Result<()> {
+    DEREGISTERED.store(true, Ordering::Release);
+    match SCHEDULER_OPERATOR.get() {
+        Some(operator) => {
+            scheduler::deregister(
+                operator,
+                &client::server_info(),
+                ReqwestResourceID::MessageNodeUnregister,
+            )
+            .await
+        }
+        None => Ok(()),
+    }
+}
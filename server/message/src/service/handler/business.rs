@@ -11,9 +11,14 @@ use lib::{
 use lib_net_tokio::net::Handler;
 use tracing::{debug, error};
 
-use crate::{cluster::ClusterConnectionMap, service::ClientConnectionMap, util::my_id};
+use crate::{
+    cache::get_redis_ops, cluster::ClusterConnectionMap, service::ClientConnectionMap, util::my_id,
+};
 
-use super::IOTaskSender;
+use super::{
+    dead_letter::{dead_letter, DeadLetterReason},
+    IOTaskSender,
+};
 
 #[inline]
 pub(self) async fn forward_only_user(
@@ -47,6 +52,12 @@ pub(self) async fn forward_only_user(
             }
             None => {
                 debug!("receiver {} not found", receiver);
+                dead_letter(
+                    &mut get_redis_ops().await,
+                    DeadLetterReason::UnknownReceiver,
+                    msg,
+                )
+                .await;
             }
         }
         if let Err(_) = io_task_sender
@@ -64,6 +75,12 @@ pub(self) async fn forward_only_user(
             None => {
                 // todo cluster offline error handler.
                 error!("cluster[{}] offline!", node_id);
+                dead_letter(
+                    &mut get_redis_ops().await,
+                    DeadLetterReason::PeerUnreachable,
+                    msg,
+                )
+                .await;
             }
         }
     }
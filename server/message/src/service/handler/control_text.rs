@@ -8,9 +8,14 @@ use tracing::{debug, error};
 
 use crate::service::handler::IOTaskMsg::Direct;
 use crate::service::handler::IOTaskSender;
-use crate::{cluster::ClusterConnectionMap, service::ClientConnectionMap, util::my_id};
+use crate::{
+    cache::get_redis_ops, cluster::ClusterConnectionMap, service::ClientConnectionMap, util::my_id,
+};
 
-use super::{is_group_msg, push_group_msg};
+use super::{
+    dead_letter::{dead_letter, DeadLetterReason},
+    is_group_msg, push_group_msg,
+};
 
 pub(crate) struct ControlText;
 
@@ -52,6 +57,12 @@ impl Handler for ControlText {
                         }
                         None => {
                             debug!("receiver {} not found", receiver);
+                            dead_letter(
+                                &mut get_redis_ops().await,
+                                DeadLetterReason::UnknownReceiver,
+                                msg,
+                            )
+                            .await;
                         }
                     }
                     io_task_sender.send(Direct(msg.clone())).await?;
@@ -64,6 +75,12 @@ impl Handler for ControlText {
                     None => {
                         // todo
                         error!("cluster[{}] offline!", node_id);
+                        dead_letter(
+                            &mut get_redis_ops().await,
+                            DeadLetterReason::PeerUnreachable,
+                            msg,
+                        )
+                        .await;
                     }
                 }
             }
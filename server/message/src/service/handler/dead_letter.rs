@@ -0,0 +1,238 @@
+use byteorder::{BigEndian, ByteOrder};
+use lib::{
+    cache::{keys::dead_letter_key, redis_ops::RedisOps},
+    entity::Msg,
+    util::timestamp,
+    Result,
+};
+use tracing::{error, warn};
+
+use crate::util::my_id;
+
+use super::offline::get_offline_store;
+
+/// the oldest letters are dropped beyond this, the sink is for debugging rather than recovery.
+pub(crate) const DEAD_LETTER_CAPACITY: usize = 10000;
+pub(crate) const DEAD_LETTER_DEFAULT_COUNT: usize = 32;
+/// bounded by the 16 bit length of a reqwest msg.
+pub(crate) const DEAD_LETTER_MAX_COUNT: usize = 256;
+
+/// the letter is `ARGV[1]`, the last index kept is `ARGV[2]`.
+pub(self) const PUSH_SCRIPT: &str =
+    "redis.call('LPUSH', KEYS[1], ARGV[1]) redis.call('LTRIM', KEYS[1], 0, tonumber(ARGV[2])) return 1";
+pub(self) const RANGE_SCRIPT: &str =
+    "return redis.call('LRANGE', KEYS[1], 0, tonumber(ARGV[1]) - 1)";
+
+/// why a msg could not be delivered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum DeadLetterReason {
+    /// neither connected to this node nor to be kept for later.
+    UnknownReceiver = 1,
+    /// the node the receiver is on is not connected.
+    PeerUnreachable = 2,
+    /// the receiver is offline, but the msg can't be stored for it.
+    OfflineStoreFailed = 3,
+    /// the connection of the receiver has gone while sending.
+    SendFailed = 4,
+}
+
+impl DeadLetterReason {
+    pub(crate) fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(DeadLetterReason::UnknownReceiver),
+            2 => Some(DeadLetterReason::PeerUnreachable),
+            3 => Some(DeadLetterReason::OfflineStoreFailed),
+            4 => Some(DeadLetterReason::SendFailed),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            DeadLetterReason::UnknownReceiver => "unknown_receiver",
+            DeadLetterReason::PeerUnreachable => "peer_unreachable",
+            DeadLetterReason::OfflineStoreFailed => "offline_store_failed",
+            DeadLetterReason::SendFailed => "send_failed",
+        }
+    }
+}
+
+/// an undeliverable msg with the reason, the layout is `reason(1) | timestamp(8) | msg`.
+#[derive(Debug, Clone)]
+pub(crate) struct DeadLetter {
+    pub(crate) reason: DeadLetterReason,
+    pub(crate) timestamp: u64,
+    pub(crate) msg: Msg,
+}
+
+impl DeadLetter {
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(9 + self.msg.as_slice().len());
+        buf.push(self.reason as u8);
+        buf.extend_from_slice(&self.timestamp.to_be_bytes());
+        buf.extend_from_slice(self.msg.as_slice());
+        buf
+    }
+
+    pub(crate) fn from_bytes(buf: &[u8]) -> Option<Self> {
+        if buf.len() < 9 {
+            return None;
+        }
+        Some(Self {
+            reason: DeadLetterReason::from_u8(buf[0])?,
+            timestamp: BigEndian::read_u64(&buf[1..9]),
+            msg: Msg::from(&buf[9..]),
+        })
+    }
+
+    /// what operators see, the payload is left out on purpose.
+    pub(crate) fn summary(&self) -> serde_json::Value {
+        serde_json::json!({
+            "reason": self.reason.as_str(),
+            "timestamp": self.timestamp,
+            "sender": self.msg.sender(),
+            "receiver": self.msg.receiver(),
+            "node_id": self.msg.node_id(),
+            "type": self.msg.typ().as_str(),
+            "seqnum": self.msg.seqnum(),
+        })
+    }
+}
+
+/// keep `msg` in the dead letter sink of this node, a failure is only logged since the msg is
+/// lost anyway.
+pub(crate) async fn dead_letter(redis_ops: &mut RedisOps, reason: DeadLetterReason, msg: &Msg) {
+    warn!(
+        "dead letter: {} sender {} receiver {} seqnum {}",
+        reason.as_str(),
+        msg.sender(),
+        msg.receiver(),
+        msg.seqnum()
+    );
+    let letter = DeadLetter {
+        reason,
+        timestamp: timestamp(),
+        msg: msg.clone(),
+    };
+    let res: Result<u64> = redis_ops
+        .lua1(PUSH_SCRIPT, &dead_letter_key(my_id()), push_args(&letter))
+        .await;
+    if let Err(e) = res {
+        error!("store dead letter failed: {}", e);
+    }
+}
+
+/// the `ARGV` of [`PUSH_SCRIPT`], the list is trimmed to [`DEAD_LETTER_CAPACITY`].
+pub(self) fn push_args(letter: &DeadLetter) -> (Vec<u8>, usize) {
+    (letter.to_bytes(), DEAD_LETTER_CAPACITY - 1)
+}
+
+/// store `msg` for sync, if even that fails it ends up as a dead letter.
+pub(crate) async fn store_offline_or_dead_letter(
+    redis_ops: &mut RedisOps,
+    msg: &Msg,
+) -> Result<()> {
+    if let Err(e) = get_offline_store().await.store(msg.receiver(), msg).await {
+        dead_letter(redis_ops, DeadLetterReason::OfflineStoreFailed, msg).await;
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// the latest `count` dead letters of this node, newest first.
+pub(crate) async fn recent_dead_letters(
+    redis_ops: &mut RedisOps,
+    count: usize,
+) -> Result<Vec<DeadLetter>> {
+    let list: Vec<Vec<u8>> = redis_ops
+        .lua1(
+            RANGE_SCRIPT,
+            &dead_letter_key(my_id()),
+            count.min(DEAD_LETTER_MAX_COUNT),
+        )
+        .await?;
+    Ok(list
+        .iter()
+        .filter_map(|buf| DeadLetter::from_bytes(buf))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ahash::AHashMap;
+    use dashmap::DashMap;
+    use lib::{
+        entity::{Msg, Type},
+        net::{GenericParameterMap, InnerStates, InnerStatesValue},
+        util::timestamp,
+        Result,
+    };
+    use lib_net_tokio::net::Handler;
+
+    use super::{
+        push_args, recent_dead_letters, DeadLetter, DeadLetterReason, DEAD_LETTER_CAPACITY,
+        PUSH_SCRIPT,
+    };
+    use crate::{
+        cache::get_redis_ops,
+        cluster::ClusterConnectionMap,
+        config::load_test_config,
+        service::{
+            handler::{business::AddFriend, IOTaskSender},
+            ClientConnectionMap,
+        },
+        util::my_id,
+    };
+
+    #[test]
+    fn test_dead_letter_bytes() {
+        let letter = DeadLetter {
+            reason: DeadLetterReason::PeerUnreachable,
+            timestamp: 42,
+            msg: Msg::text(1, 2, 3, "hello"),
+        };
+        let decoded = DeadLetter::from_bytes(&letter.to_bytes()).unwrap();
+        assert_eq!(decoded.reason, DeadLetterReason::PeerUnreachable);
+        assert_eq!(decoded.timestamp, 42);
+        assert_eq!(decoded.msg.payload(), b"hello");
+        assert_eq!(decoded.summary()["reason"], "peer_unreachable");
+        assert!(decoded.summary().get("payload").is_none());
+        assert!(DeadLetter::from_bytes(&[9; 12]).is_none());
+        // the script trims to the capacity.
+        let (bytes, last_index) = push_args(&letter);
+        assert_eq!(bytes, letter.to_bytes());
+        assert_eq!(last_index, DEAD_LETTER_CAPACITY - 1);
+        assert!(PUSH_SCRIPT.contains("ARGV[2]"));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the redis cluster of docker-compose"]
+    async fn test_unknown_receiver() -> Result<()> {
+        load_test_config();
+        let (io_sender, _io_receiver) = tokio::sync::mpsc::channel(8);
+        let mut generic_map = GenericParameterMap(AHashMap::new());
+        generic_map.put_parameter(ClientConnectionMap(Arc::new(DashMap::new())));
+        generic_map.put_parameter(ClusterConnectionMap(Arc::new(DashMap::new())));
+        generic_map.put_parameter(IOTaskSender(io_sender));
+        let mut states = InnerStates::new();
+        states.insert(
+            "generic_map".to_owned(),
+            InnerStatesValue::GenericParameterMap(generic_map),
+        );
+        let receiver = (1 << 33) + timestamp() % 1_000_000;
+        let mut msg = Msg::text(1 << 33, receiver, my_id(), "hello");
+        msg.set_type(Type::AddFriend);
+        AddFriend.run(&mut Arc::new(msg), &mut states).await?;
+
+        let letters = recent_dead_letters(&mut get_redis_ops().await, 8).await?;
+        let letter = letters
+            .iter()
+            .find(|letter| letter.msg.receiver() == receiver)
+            .unwrap();
+        assert_eq!(letter.reason, DeadLetterReason::UnknownReceiver);
+        assert_eq!(letter.msg.typ(), Type::AddFriend);
+        Ok(())
+    }
+}
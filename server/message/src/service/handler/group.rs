@@ -0,0 +1,64 @@
+use std::collections::HashSet;
+
+use anyhow::anyhow;
+use lib::Result;
+use tracing::error;
+
+use crate::rpc;
+
+/// a member of a group who is assigned to the current node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct GroupMember {
+    pub(crate) group_id: u64,
+    pub(crate) user_id: u64,
+}
+
+impl GroupMember {
+    /// members of `group_id` on the current node, loaded from scheduler every time, since
+    /// nothing tells this node when one joins or leaves.
+    pub(crate) async fn list_members(group_id: u64) -> Result<Vec<GroupMember>> {
+        let mut rpc_client = rpc::get_rpc_client().await;
+        let user_list = match rpc_client.call_curr_node_group_id_user_list(group_id).await {
+            Ok(user_list) => user_list,
+            Err(e) => {
+                error!("load group user list error: {}", e);
+                return Err(anyhow!("load group user list error: {}", e));
+            }
+        };
+        Ok(Self::from_user_list(group_id, &user_list))
+    }
+
+    pub(self) fn from_user_list(group_id: u64, user_list: &[u64]) -> Vec<GroupMember> {
+        user_list
+            .iter()
+            .map(|user_id| GroupMember {
+                group_id,
+                user_id: *user_id,
+            })
+            .collect()
+    }
+}
+
+/// the members a group msg should be forwarded to, the sender and repeated ids are skipped.
+pub(crate) fn recipients(sender: u64, members: &[GroupMember]) -> Vec<u64> {
+    let mut seen = HashSet::with_capacity(members.len());
+    members
+        .iter()
+        .map(|member| member.user_id)
+        .filter(|user_id| *user_id != sender && seen.insert(*user_id))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{recipients, GroupMember};
+
+    #[test]
+    fn test_recipients() {
+        let group_id = 1 << 36;
+        let members = GroupMember::from_user_list(group_id, &[1, 2, 3]);
+        assert_eq!(recipients(1, &members), vec![2, 3]);
+        let members = GroupMember::from_user_list(group_id, &[2, 3, 2]);
+        assert_eq!(recipients(1, &members), vec![2, 3]);
+    }
+}
@@ -5,14 +5,16 @@ use anyhow::anyhow;
 use async_trait::async_trait;
 use byteorder::{BigEndian, ByteOrder};
 use lib::{
-    cache::redis_ops::RedisOps,
+    cache::{keys::user_token_key, redis_ops::RedisOps},
     entity::{Msg, ReqwestMsg, ReqwestResourceID, Type},
-    error::HandlerError,
+    error::{HandlerError, SeqnumError},
     net::{client::ClientConfigBuilder, InnerStates, InnerStatesValue},
     util::{jwt::verify_token, timestamp},
     Result,
 };
-use lib_net_tokio::net::{client::ClientReqwestTcp, Handler, MsgSender, ReqwestOperatorManager};
+use lib_net_tokio::net::{
+    client::ClientReqwestTcp, Handler, MsgSender, ReqwestOperatorManager, AUTH_HANDLER_PRIORITY,
+};
 use rdkafka::{
     producer::{FutureProducer, FutureRecord},
     util::Timeout,
@@ -21,7 +23,6 @@ use tokio::sync::RwLock;
 use tracing::{debug, error};
 
 use crate::{
-    cache::USER_TOKEN,
     config::config,
     rpc::{get_rpc_client, node::RpcClient},
     service::{get_mq_producer, get_seqnum_client_holder, Msglogger},
@@ -65,9 +66,7 @@ impl Handler for Auth {
             .get_parameter::<MsgSender>()
             .unwrap();
         let token = String::from_utf8_lossy(msg.payload()).to_string();
-        let key: String = redis_ops
-            .get(&format!("{}{}", USER_TOKEN, msg.sender()))
-            .await?;
+        let key: String = redis_ops.get(&user_token_key(msg.sender())).await?;
         if let Err(e) = verify_token(&token, key.as_bytes(), msg.sender()) {
             error!("auth failed: {} {}", e, token);
             return Err(anyhow!(HandlerError::Auth(e.to_string())));
@@ -78,6 +77,10 @@ impl Handler for Auth {
         client_map.insert(msg.sender(), sender.clone());
         Ok(res_msg)
     }
+
+    fn priority(&self) -> i32 {
+        AUTH_HANDLER_PRIORITY
+    }
 }
 
 pub(crate) struct Echo;
@@ -238,7 +241,7 @@ impl Handler for PreProcess {
                     .with_max_bi_streams(config().transport.max_bi_streams);
                 let client_config = client_config.build().unwrap();
                 let mut client = ClientReqwestTcp::new(client_config, Duration::from_millis(3000));
-                let operator_manager = match client.build().await {
+                let mut operator_manager = match client.build().await {
                     Ok(operator_manager) => operator_manager,
                     Err(e) => {
                         error!("build client failed: {}", e);
@@ -247,6 +250,9 @@ impl Handler for PreProcess {
                         )));
                     }
                 };
+                if let Some(max_in_flight) = config().seqnum.max_in_flight {
+                    operator_manager.set_max_in_flight(max_in_flight);
+                }
                 seqnum_client = Some(client);
                 seqnum_caller = Some(operator_manager);
             }
@@ -277,7 +283,13 @@ impl Handler for PreProcess {
                     ));
             }
             let seqnum = match reqwest.await {
-                Ok(resp) => BigEndian::read_u64(&resp.payload()[0..8]),
+                Ok(resp) => match SeqnumError::from_reqwest(&resp) {
+                    Some(e) => {
+                        error!("call seqnum failed: {}", e);
+                        return Err(anyhow!(e));
+                    }
+                    None => BigEndian::read_u64(&resp.payload()[0..8]),
+                },
                 Err(e) => {
                     error!("call seqnum failed: {}", e);
                     return Err(anyhow!(HandlerError::Other(
@@ -1,34 +1,39 @@
-use std::{any::Any, sync::Arc};
+use std::{any::Any, sync::Arc, time::Instant};
 
 use ahash::AHashMap;
 use anyhow::anyhow;
 use dashmap::DashMap;
 use lazy_static::lazy_static;
 use lib::{
+    cache::keys::{last_online_time_key, msg_cache_key, user_inbox_key},
     entity::{Msg, Type, GROUP_ID_THRESHOLD},
     error::HandlerError,
     net::{GenericParameter, GenericParameterMap, InnerStates, InnerStatesValue},
-    util::{timestamp, who_we_are},
+    util::timestamp,
     Result,
 };
-use lib_net_tokio::net::{HandlerList, MsgMpscReceiver, MsgSender};
+use lib_net_tokio::net::{HandlerList, MsgMpscReceiver, MsgSender, SeqnumCheckedSender};
 use tracing::{debug, error};
 
 use crate::{
-    cache::{get_redis_ops, LAST_ONLINE_TIME, MSG_CACHE, USER_INBOX},
-    cluster::get_cluster_connection_map,
-    config::config,
-    rpc,
-    service::get_io_task_sender,
-    util::my_id,
+    cache::get_redis_ops, cluster::get_cluster_connection_map, config::config,
+    service::get_io_task_sender, util::my_id,
 };
 
 use super::{get_client_connection_map, get_msglogger_client};
+use group::GroupMember;
+use offline::{get_offline_store, OfflineStore};
 
 pub(crate) mod business;
 pub(crate) mod control_text;
+pub(crate) mod dead_letter;
+pub(crate) mod group;
 pub(crate) mod logic;
+pub(crate) mod offline;
+pub(crate) mod presence;
 pub(crate) mod pure_text;
+pub(crate) mod quota;
+pub(crate) mod router;
 
 pub(self) type GroupTaskSender = tokio::sync::mpsc::Sender<(Arc<Msg>, bool)>;
 pub(self) type GroupTaskReceiver = tokio::sync::mpsc::Receiver<(Arc<Msg>, bool)>;
@@ -81,8 +86,6 @@ impl IOTaskReceiver {
 
 lazy_static! {
     static ref GROUP_SENDER_MAP: Arc<DashMap<u64, GroupTaskSender>> = Arc::new(DashMap::new());
-    /// only represents the current node's group id and user id list
-    static ref GROUP_USER_LIST: Arc<DashMap<u64, Vec<u64>>> = Arc::new(DashMap::new());
 }
 
 /// ```
@@ -166,6 +169,9 @@ pub(super) async fn handler_func(
                 Ok(res_msg) => {
                     sender.send(Arc::new(res_msg)).await?;
                     user_id = auth_msg.sender();
+                    if let Err(e) = presence::notify(&mut redis_ops, user_id, true).await {
+                        error!("notify presence of {} failed: {}", user_id, e);
+                    }
                 }
                 Err(e) => {
                     error!("auth handler error: {}", e);
@@ -180,11 +186,29 @@ pub(super) async fn handler_func(
             return Err(anyhow!("cannot receive auth message"));
         }
     };
+    // the online mark is refreshed well before it expires, but not on every msg.
+    let mut last_heartbeat = Instant::now();
     loop {
         let msg = receiver.recv().await;
         match msg {
             Some(mut msg) => {
-                call_handler_list(&sender, &mut msg, handler_list, states).await?;
+                if last_heartbeat.elapsed() > presence::presence_ttl() / 3 {
+                    last_heartbeat = Instant::now();
+                    if let Err(e) = presence::mark_online(&mut redis_ops, user_id).await {
+                        error!("refresh presence of {} failed: {}", user_id, e);
+                    }
+                }
+                let interceptors = handler_list.interceptors();
+                if let Err(e) = interceptors.before(&msg).await {
+                    debug!("msg from {} rejected: {}", user_id, e);
+                    let res_msg =
+                        Msg::err_msg(my_id() as u64, msg.sender(), my_id(), &e.to_string());
+                    sender.send(Arc::new(res_msg)).await?;
+                    continue;
+                }
+                let res = call_handler_list(&sender, &mut msg, handler_list, states).await;
+                interceptors.after(&msg, &res).await;
+                res?;
             }
             None => {
                 // warn!("io receiver closed");
@@ -194,10 +218,13 @@ pub(super) async fn handler_func(
         }
     }
     client_map.remove(&user_id);
+    if let Err(e) = presence::notify(&mut redis_ops, user_id, false).await {
+        error!("notify presence of {} failed: {}", user_id, e);
+    }
     // we choose to use [now - last idle timeout] to be the last online time.
     redis_ops
         .set(
-            &format!("{}{}", LAST_ONLINE_TIME, user_id),
+            &last_online_time_key(user_id),
             &(timestamp() - config().transport.connection_idle_timeout),
         )
         .await?;
@@ -228,10 +255,7 @@ pub(crate) async fn call_handler_list(
                         sender.send(Arc::new(ok_msg)).await?;
                         let client_timestamp =
                             states.get("client_timestamp").unwrap().as_num().unwrap();
-                        let mut ack_msg = msg.generate_ack(my_id(), client_timestamp);
-                        ack_msg.set_sender(my_id() as u64);
-                        ack_msg.set_receiver(msg.sender());
-                        ack_msg.set_seqnum(seq_num);
+                        let ack_msg = msg.generate_server_ack(my_id(), client_timestamp, seq_num);
                         sender.send(Arc::new(ack_msg)).await?;
                     }
                 }
@@ -295,43 +319,35 @@ pub(super) async fn io_task(mut io_task_receiver: IOTaskReceiver) -> Result<()>
     loop {
         match io_task_receiver.recv().await {
             Some(task_msg) => {
-                let users_identify;
+                let cache_key;
                 let msg: Arc<Msg>;
                 let receiver: u64;
                 match task_msg {
                     IOTaskMsg::Direct(direct_msg) => {
-                        users_identify = who_we_are(direct_msg.sender(), direct_msg.receiver());
+                        cache_key = msg_cache_key(direct_msg.sender(), direct_msg.receiver());
                         receiver = direct_msg.receiver();
                         msg = direct_msg;
                         // todo delete old data
                         redis_ops
-                            .push_sort_queue(
-                                &format!("{}{}", MSG_CACHE, users_identify),
-                                &msg.as_slice(),
-                                msg.seqnum() as f64,
-                            )
+                            .push_sort_queue(&cache_key, &msg.as_slice(), msg.seqnum() as f64)
                             .await?;
                     }
                     IOTaskMsg::Broadcast(broadcast_msg, real_receiver, duplication) => {
-                        users_identify =
-                            who_we_are(broadcast_msg.receiver(), broadcast_msg.receiver());
+                        cache_key =
+                            msg_cache_key(broadcast_msg.receiver(), broadcast_msg.receiver());
                         receiver = real_receiver;
                         msg = broadcast_msg;
                         if !duplication {
                             // todo delete old data
                             redis_ops
-                                .push_sort_queue(
-                                    &format!("{}{}", MSG_CACHE, users_identify),
-                                    &msg.as_slice(),
-                                    msg.seqnum() as f64,
-                                )
+                                .push_sort_queue(&cache_key, &msg.as_slice(), msg.seqnum() as f64)
                                 .await?;
                         }
                     }
                 }
                 redis_ops
                     .push_sort_queue(
-                        &format!("{}{}", USER_INBOX, receiver),
+                        &user_inbox_key(receiver),
                         &msg.sender(),
                         msg.timestamp() as f64,
                     )
@@ -368,24 +384,9 @@ pub(crate) async fn push_group_msg(msg: Arc<Msg>, forward: bool) -> Result<()> {
     Ok(())
 }
 
-/// todo!(new member add)
-async fn load_group_user_list(group_id: u64) -> Result<()> {
-    let mut rpc_client = rpc::get_rpc_client().await;
-    let list = rpc_client.call_curr_node_group_id_user_list(group_id).await;
-    if let Err(e) = list {
-        error!("load group user list error: {}", e);
-        return Err(anyhow!("load group user list error: {}", e));
-    }
-    let list = list.unwrap();
-    GROUP_USER_LIST.insert(group_id, list);
-    Ok(())
-}
-
 pub(self) async fn group_task(group_id: u64, mut io_receiver: GroupTaskReceiver) -> Result<()> {
     debug!("group task {} start", group_id);
-    if let Err(e) = load_group_user_list(group_id).await {
-        error!("load group user list error: {}", e);
-    }
+    let offline_store = get_offline_store().await;
     let client_map = get_client_connection_map().0;
     let cluster_map = get_cluster_connection_map().0;
     let io_task_sender = get_io_task_sender();
@@ -411,39 +412,22 @@ pub(self) async fn group_task(group_id: u64, mut io_receiver: GroupTaskReceiver)
                         }
                     }
                 }
-                // when send to clients, the message need sender set to group id first.
-                // the truly sender will be set in extension part by original client.
-                let mut new_msg = (*msg).clone();
-                new_msg.set_sender(msg.receiver());
-                new_msg.set_receiver(msg.receiver());
-                let msg = Arc::new(new_msg);
-                let mut duplication = false;
-                match GROUP_USER_LIST.get(&group_id) {
-                    Some(user_list) => {
-                        for user_id in user_list.iter() {
-                            if let Err(_) = io_task_sender
-                                .send(IOTaskMsg::Broadcast(msg.clone(), *user_id, duplication))
-                                .await
-                            {
-                                error!("send to io task failed");
-                            }
-                            duplication = true;
-                            // if the user is in this node, send to client directly
-                            if let Some(io_sender) = client_map.get(user_id) {
-                                match io_sender.send(msg.clone()).await {
-                                    Ok(_) => {}
-                                    Err(e) => {
-                                        debug!("send to {} failed: {}", user_id, e);
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    None => {
+                // the members are loaded for every msg, so a join or a leave counts at once.
+                let members = match GroupMember::list_members(group_id).await {
+                    Ok(members) => members,
+                    Err(e) => {
                         error!("group {} not found", group_id);
-                        return Err(anyhow!("group {} not found", group_id));
+                        return Err(anyhow!("group {} not found: {}", group_id, e));
                     }
-                }
+                };
+                deliver_group_msg(
+                    &msg,
+                    &members,
+                    &client_map,
+                    offline_store.as_ref(),
+                    io_task_sender,
+                )
+                .await;
             }
             None => {
                 debug!("group task exit");
@@ -454,10 +438,102 @@ pub(self) async fn group_task(group_id: u64, mut io_receiver: GroupTaskReceiver)
     Ok(())
 }
 
+/// hand a group msg to the members on this node except the sender, the connected ones get it
+/// directly and the others keep it until they come back.
+pub(self) async fn deliver_group_msg(
+    msg: &Msg,
+    members: &[GroupMember],
+    client_map: &DashMap<u64, SeqnumCheckedSender>,
+    offline_store: &dyn OfflineStore,
+    io_task_sender: &IOTaskSender,
+) {
+    let recipients = group::recipients(msg.sender(), members);
+    // when send to clients, the message need sender set to group id first.
+    // the truly sender will be set in extension part by original client.
+    let mut new_msg = msg.clone();
+    new_msg.set_sender(msg.receiver());
+    new_msg.set_receiver(msg.receiver());
+    let msg = Arc::new(new_msg);
+    let mut duplication = false;
+    for user_id in recipients.iter() {
+        if let Err(_) = io_task_sender
+            .send(IOTaskMsg::Broadcast(msg.clone(), *user_id, duplication))
+            .await
+        {
+            error!("send to io task failed");
+        }
+        duplication = true;
+        // if the user is in this node, send to client directly, otherwise keep it until the user comes back.
+        match client_map.get(user_id) {
+            Some(io_sender) => match io_sender.send(msg.clone()).await {
+                Ok(_) => {}
+                Err(e) => {
+                    debug!("send to {} failed: {}", user_id, e);
+                }
+            },
+            None => {
+                if let Err(e) = offline_store.store(*user_id, &msg).await {
+                    error!("store offline msg for {} failed: {}", user_id, e);
+                }
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
 
+    use dashmap::DashMap;
+    use lib::entity::{Msg, GROUP_ID_THRESHOLD};
+    use lib_net_tokio::net::{MsgSender, SeqnumCheckedSender};
+
+    use super::{
+        deliver_group_msg, group::GroupMember, offline::tests::MemOfflineStore, IOTaskMsg,
+        IOTaskSender, OfflineStore,
+    };
+
+    #[tokio::test]
+    async fn test_deliver_group_msg() {
+        let group_id = GROUP_ID_THRESHOLD + 1;
+        // the sender is a member too, and 3 is listed twice.
+        let members = [1, 2, 3, 3]
+            .iter()
+            .map(|user_id| GroupMember {
+                group_id,
+                user_id: *user_id,
+            })
+            .collect::<Vec<_>>();
+        let client_map = DashMap::new();
+        let (client_sender, mut client_receiver) = tokio::sync::mpsc::channel(8);
+        client_map.insert(
+            2,
+            SeqnumCheckedSender::new(MsgSender::Server(client_sender)),
+        );
+        let offline_store = MemOfflineStore::default();
+        let (io_sender, mut io_receiver) = tokio::sync::mpsc::channel(8);
+        let io_task_sender = IOTaskSender(io_sender);
+
+        let mut msg = Msg::text(1, group_id, 0, "hello");
+        msg.set_seqnum(1);
+        deliver_group_msg(&msg, &members, &client_map, &offline_store, &io_task_sender).await;
+
+        // 2 is connected, 3 gets it on sync, and the sender gets nothing.
+        let received = client_receiver.try_recv().unwrap();
+        assert_eq!(
+            (received.sender(), received.receiver()),
+            (group_id, group_id)
+        );
+        assert!(client_receiver.try_recv().is_err());
+        assert_eq!(offline_store.take(3).await.unwrap().len(), 1);
+        assert!(offline_store.take(1).await.unwrap().is_empty());
+        let mut recorded = Vec::new();
+        while let Ok(IOTaskMsg::Broadcast(_, user_id, _)) = io_receiver.try_recv() {
+            recorded.push(user_id);
+        }
+        assert_eq!(recorded, vec![2, 3]);
+    }
+
     #[tokio::test]
     async fn test() {
         #[derive(Debug)]
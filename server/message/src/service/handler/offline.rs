@@ -0,0 +1,265 @@
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use lib::{
+    cache::{keys::offline_msg_key, redis_ops::RedisOps},
+    entity::{Msg, Type},
+    error::HandlerError,
+    net::InnerStates,
+    Result,
+};
+use lib_net_tokio::net::{Handler, MsgSender};
+use tokio::sync::OnceCell;
+use tracing::debug;
+
+use crate::{cache::get_redis_ops, util::my_id};
+
+/// read and delete in one step, so msgs stored during the replay won't be lost.
+pub(self) const TAKE_ALL_SCRIPT: &str =
+    "local msgs = redis.call('ZRANGE', KEYS[1], 0, -1) redis.call('DEL', KEYS[1]) return msgs";
+pub(self) const FETCH_SCRIPT: &str =
+    "return redis.call('ZRANGEBYSCORE', KEYS[1], '(' .. ARGV[1], '+inf')";
+
+/// where msgs whose receiver is not connected are kept until it syncs, so a deployment can
+/// choose the backend.
+#[async_trait]
+pub(crate) trait OfflineStore: Send + Sync + 'static {
+    /// keep `msg` for `receiver`, which is the member rather than the group for group msgs.
+    async fn store(&self, receiver: u64, msg: &Msg) -> Result<()>;
+
+    /// the msgs kept for `receiver` with seqnum greater than `since_seq`, ordered by seqnum.
+    async fn fetch(&self, receiver: u64, since_seq: u64) -> Result<Vec<Msg>>;
+
+    /// drop the msgs kept for `receiver` with seqnum up to `upto_seq`, inclusive.
+    async fn clear(&self, receiver: u64, upto_seq: u64) -> Result<()>;
+
+    /// all msgs kept for `receiver` whatever their seqnum, they are removed from the store in
+    /// the same step, so a msg stored meanwhile is not lost.
+    async fn take(&self, receiver: u64) -> Result<Vec<Msg>>;
+}
+
+/// the msgs are kept in a sorted set per receiver, scored by seqnum.
+pub(crate) struct RedisOfflineStore {
+    redis_ops: RedisOps,
+}
+
+impl RedisOfflineStore {
+    pub(crate) fn new(redis_ops: RedisOps) -> Self {
+        Self { redis_ops }
+    }
+}
+
+#[async_trait]
+impl OfflineStore for RedisOfflineStore {
+    async fn store(&self, receiver: u64, msg: &Msg) -> Result<()> {
+        self.redis_ops
+            .clone()
+            .push_sort_queue(&offline_msg_key(receiver), msg, msg.seqnum() as f64)
+            .await
+    }
+
+    async fn fetch(&self, receiver: u64, since_seq: u64) -> Result<Vec<Msg>> {
+        let mut msgs: Vec<Msg> = self
+            .redis_ops
+            .clone()
+            .lua1(FETCH_SCRIPT, &offline_msg_key(receiver), since_seq)
+            .await?;
+        msgs.sort_by_key(|msg| (msg.seqnum(), msg.timestamp()));
+        Ok(msgs)
+    }
+
+    async fn clear(&self, receiver: u64, upto_seq: u64) -> Result<()> {
+        self.redis_ops
+            .clone()
+            .remove_sort_queue_old_data(&offline_msg_key(receiver), upto_seq as f64)
+            .await
+    }
+
+    async fn take(&self, receiver: u64) -> Result<Vec<Msg>> {
+        let mut msgs: Vec<Msg> = self
+            .redis_ops
+            .clone()
+            .lua1(TAKE_ALL_SCRIPT, &offline_msg_key(receiver), 0)
+            .await?;
+        // same seqnum from different senders keeps the order of arrival.
+        msgs.sort_by_key(|msg| (msg.seqnum(), msg.timestamp()));
+        Ok(msgs)
+    }
+}
+
+pub(self) static OFFLINE_STORE: OnceCell<Arc<dyn OfflineStore>> = OnceCell::const_new();
+
+/// replace the redis backend, must be called before the first msg is stored.
+#[allow(unused)]
+pub(crate) fn set_offline_store(store: Arc<dyn OfflineStore>) -> Result<()> {
+    OFFLINE_STORE
+        .set(store)
+        .map_err(|_| anyhow!("offline store already set"))
+}
+
+/// the store chosen by [`set_offline_store`], redis if none is.
+pub(crate) async fn get_offline_store() -> Arc<dyn OfflineStore> {
+    OFFLINE_STORE
+        .get_or_init(|| async {
+            Arc::new(RedisOfflineStore::new(get_redis_ops().await)) as Arc<dyn OfflineStore>
+        })
+        .await
+        .clone()
+}
+
+/// replay msgs stored when the client was offline.
+pub(crate) struct SyncOffline;
+
+#[async_trait]
+impl Handler for SyncOffline {
+    async fn run(&self, msg: &mut Arc<Msg>, inner_states: &mut InnerStates) -> Result<Msg> {
+        if Type::Sync != msg.typ() {
+            return Err(anyhow!(HandlerError::NotMine));
+        }
+        let sender = inner_states
+            .get("generic_map")
+            .unwrap()
+            .as_generic_parameter_map()
+            .unwrap()
+            .get_parameter::<MsgSender>()
+            .unwrap();
+        let msgs = get_offline_store().await.take(msg.sender()).await?;
+        debug!("replay {} offline msgs to {}.", msgs.len(), msg.sender());
+        sender
+            .send_many(msgs.into_iter().map(Arc::new).collect())
+            .await?;
+        let client_timestamp = inner_states
+            .get("client_timestamp")
+            .unwrap()
+            .as_num()
+            .unwrap();
+        Ok(msg.generate_ack(my_id(), client_timestamp))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::Mutex;
+
+    use ahash::AHashMap;
+    use async_trait::async_trait;
+    use lib::{cache::redis_ops::RedisOps, entity::Msg, Result};
+
+    use super::{OfflineStore, RedisOfflineStore};
+
+    /// keeps the msgs in memory, the way a backend other than redis plugs in.
+    #[derive(Default)]
+    pub(crate) struct MemOfflineStore {
+        msgs: Mutex<AHashMap<u64, Vec<Msg>>>,
+    }
+
+    #[async_trait]
+    impl OfflineStore for MemOfflineStore {
+        async fn store(&self, receiver: u64, msg: &Msg) -> Result<()> {
+            self.msgs
+                .lock()
+                .unwrap()
+                .entry(receiver)
+                .or_default()
+                .push(msg.clone());
+            Ok(())
+        }
+
+        async fn fetch(&self, receiver: u64, since_seq: u64) -> Result<Vec<Msg>> {
+            let mut msgs = self
+                .msgs
+                .lock()
+                .unwrap()
+                .get(&receiver)
+                .map(|msgs| {
+                    msgs.iter()
+                        .filter(|msg| msg.seqnum() > since_seq)
+                        .cloned()
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default();
+            msgs.sort_by_key(|msg| msg.seqnum());
+            Ok(msgs)
+        }
+
+        async fn clear(&self, receiver: u64, upto_seq: u64) -> Result<()> {
+            if let Some(msgs) = self.msgs.lock().unwrap().get_mut(&receiver) {
+                msgs.retain(|msg| msg.seqnum() > upto_seq);
+            }
+            Ok(())
+        }
+
+        async fn take(&self, receiver: u64) -> Result<Vec<Msg>> {
+            let mut msgs = self
+                .msgs
+                .lock()
+                .unwrap()
+                .remove(&receiver)
+                .unwrap_or_default();
+            msgs.sort_by_key(|msg| msg.seqnum());
+            Ok(msgs)
+        }
+    }
+
+    fn msg_with_seqnum(receiver: u64, seqnum: u64) -> Msg {
+        let mut msg = Msg::text(1, receiver, 0, &format!("msg-{}", seqnum));
+        msg.set_seqnum(seqnum);
+        msg
+    }
+
+    #[tokio::test]
+    async fn test_mem_offline_store() -> Result<()> {
+        let store = MemOfflineStore::default();
+        for seqnum in [3, 1, 2] {
+            store.store(1, &msg_with_seqnum(1, seqnum)).await?;
+        }
+        store.store(2, &msg_with_seqnum(2, 7)).await?;
+        let seqnums = |msgs: Vec<Msg>| msgs.iter().map(|msg| msg.seqnum()).collect::<Vec<_>>();
+        assert_eq!(seqnums(store.fetch(1, 0).await?), vec![1, 2, 3]);
+        assert_eq!(seqnums(store.fetch(1, 1).await?), vec![2, 3]);
+        // fetching keeps them.
+        assert_eq!(store.fetch(1, 0).await?.len(), 3);
+
+        store.clear(1, 2).await?;
+        assert_eq!(seqnums(store.fetch(1, 0).await?), vec![3]);
+        // the other receivers are left alone.
+        assert_eq!(seqnums(store.fetch(2, 0).await?), vec![7]);
+
+        assert_eq!(seqnums(store.take(1).await?), vec![3]);
+        assert!(store.fetch(1, 0).await?.is_empty());
+        assert!(store.take(1).await?.is_empty());
+        // a msg not given a seqnum yet is taken too, as redis does.
+        store.store(1, &msg_with_seqnum(1, 0)).await?;
+        assert_eq!(seqnums(store.take(1).await?), vec![0]);
+        assert!(store.take(1).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the redis cluster of docker-compose"]
+    async fn test_offline_replay() -> Result<()> {
+        let addresses = vec!["127.0.0.1:16379", "127.0.0.1:16380", "127.0.0.1:16381"]
+            .iter()
+            .map(|x| x.parse().expect("parse error"))
+            .collect();
+        let redis_ops =
+            RedisOps::connect(addresses, Some(vec!["Redis.123456".to_string(); 3])).await?;
+        let store = RedisOfflineStore::new(redis_ops);
+        let receiver = 1 << 32;
+        for seqnum in [3, 1, 2] {
+            store
+                .store(receiver, &msg_with_seqnum(receiver, seqnum))
+                .await?;
+        }
+        assert_eq!(store.fetch(receiver, 1).await?.len(), 2);
+        let msgs = store.take(receiver).await?;
+        assert_eq!(
+            msgs.iter().map(|msg| msg.seqnum()).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+        assert_eq!(msgs[0].payload(), b"msg-1");
+        assert!(store.take(receiver).await?.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,193 @@
+use std::{sync::Arc, time::Duration};
+
+use lib::{
+    cache::{
+        keys::{contacts_key, presence_key},
+        redis_ops::RedisOps,
+    },
+    entity::Msg,
+    Result,
+};
+use tracing::debug;
+
+use crate::{
+    cluster::{get_cluster_connection_map, ClusterConnectionMap},
+    config::config,
+    service::{get_client_connection_map, ClientConnectionMap},
+    util::my_id,
+};
+
+use super::is_group_msg;
+
+pub(self) const DEL_IF_MINE_SCRIPT: &str =
+    "if redis.call('GET', KEYS[1]) == ARGV[1] then return redis.call('DEL', KEYS[1]) else return 0 end";
+
+/// at most so many friends of a user are told, so a crowded contact list won't flood the cluster.
+pub(self) const CONTACTS_LIMIT: usize = 256;
+
+/// how long an online mark lives without a heartbeat, so a node crashed without teardown
+/// can't leave its users online forever.
+pub(crate) fn presence_ttl() -> Duration {
+    Duration::from_millis(config().transport.connection_idle_timeout * 2)
+}
+
+/// set or refresh the online mark of `user_id`, the value is the node it's connected to.
+pub(crate) async fn mark_online(redis_ops: &mut RedisOps, user_id: u64) -> Result<()> {
+    redis_ops
+        .set_exp(&presence_key(user_id), &my_id(), presence_ttl())
+        .await
+}
+
+/// the mark is kept if the user has connected to another node in the meantime.
+pub(crate) async fn mark_offline(redis_ops: &mut RedisOps, user_id: u64) -> Result<()> {
+    let _: u64 = redis_ops
+        .lua1(DEL_IF_MINE_SCRIPT, &presence_key(user_id), my_id())
+        .await?;
+    Ok(())
+}
+
+/// the friends of `user_id` who are online now, with the nodes they are on. blocked peers are
+/// never in the friends, so they are not told.
+pub(crate) async fn online_contacts(
+    redis_ops: &mut RedisOps,
+    user_id: u64,
+) -> Result<Vec<(u64, u32)>> {
+    let mut contacts: Vec<u64> = redis_ops.set_members(&contacts_key(user_id)).await?;
+    contacts.retain(|contact| !is_group_msg(*contact) && *contact != user_id);
+    contacts.truncate(CONTACTS_LIMIT);
+    let keys = contacts
+        .iter()
+        .map(|contact| presence_key(*contact))
+        .collect::<Vec<_>>();
+    let node_ids: Vec<Option<u32>> = redis_ops.mget(&keys).await?;
+    Ok(contacts
+        .into_iter()
+        .zip(node_ids)
+        .filter_map(|(contact, node_id)| node_id.map(|node_id| (contact, node_id)))
+        .collect())
+}
+
+/// send the presence of `user_id` to `contacts`, return how many of them are reached.
+///
+/// contacts on this node get it directly, the others through the node they are on.
+pub(crate) async fn publish(
+    user_id: u64,
+    online: bool,
+    contacts: &[(u64, u32)],
+    client_map: &ClientConnectionMap,
+    cluster_map: &ClusterConnectionMap,
+) -> usize {
+    let mut reached = 0;
+    for (contact, node_id) in contacts.iter() {
+        let res = match client_map.get(contact) {
+            Some(sender) => {
+                sender
+                    .send(Arc::new(Msg::presence(user_id, *contact, my_id(), online)))
+                    .await
+            }
+            None => match cluster_map.get(node_id) {
+                Some(sender) if *node_id != my_id() => {
+                    sender
+                        .send(Arc::new(Msg::presence(user_id, *contact, *node_id, online)))
+                        .await
+                }
+                _ => continue,
+            },
+        };
+        match res {
+            Ok(_) => reached += 1,
+            Err(e) => debug!("send presence to {} failed: {}", contact, e),
+        }
+    }
+    reached
+}
+
+/// record the transition of `user_id` and tell its online contacts.
+pub(crate) async fn notify(redis_ops: &mut RedisOps, user_id: u64, online: bool) -> Result<usize> {
+    if online {
+        mark_online(redis_ops, user_id).await?;
+    } else {
+        mark_offline(redis_ops, user_id).await?;
+    }
+    let contacts = online_contacts(redis_ops, user_id).await?;
+    Ok(publish(
+        user_id,
+        online,
+        &contacts,
+        &get_client_connection_map(),
+        &get_cluster_connection_map(),
+    )
+    .await)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use dashmap::DashMap;
+    use lib::{
+        cache::{keys::contacts_key, redis_ops::RedisOps},
+        entity::Type,
+        Result,
+    };
+    use lib_net_tokio::net::MsgSender;
+
+    use super::{mark_online, notify, publish};
+    use crate::{
+        cluster::ClusterConnectionMap,
+        config::load_test_config,
+        service::{get_client_connection_map, ClientConnectionMap},
+        util::my_id,
+    };
+
+    #[tokio::test]
+    async fn test_publish_online() {
+        let client_map = ClientConnectionMap(Arc::new(DashMap::new()));
+        let cluster_map = ClusterConnectionMap(Arc::new(DashMap::new()));
+        let (local_sender, mut local_receiver) = tokio::sync::mpsc::channel(8);
+        let (peer_sender, mut peer_receiver) = tokio::sync::mpsc::channel(8);
+        client_map.insert(10, MsgSender::Server(local_sender));
+        let peer_id = my_id() + 1;
+        cluster_map.insert(peer_id, MsgSender::Server(peer_sender));
+        // 10 is on this node, 20 on the peer, 30 on a node not connected.
+        let contacts = vec![(10, my_id()), (20, peer_id), (30, peer_id + 1)];
+        let reached = publish(1, true, &contacts, &client_map, &cluster_map).await;
+        assert_eq!(reached, 2);
+
+        let msg = local_receiver.recv().await.unwrap();
+        assert_eq!(msg.typ(), Type::Presence);
+        assert_eq!(msg.sender(), 1);
+        assert_eq!(msg.receiver(), 10);
+        assert_eq!(msg.payload(), b"online");
+        let msg = peer_receiver.recv().await.unwrap();
+        assert_eq!(msg.receiver(), 20);
+        assert_eq!(msg.node_id(), peer_id);
+        assert!(peer_receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the redis cluster of docker-compose"]
+    async fn test_notify_contacts() -> Result<()> {
+        load_test_config();
+        let addresses = vec!["127.0.0.1:16379", "127.0.0.1:16380", "127.0.0.1:16381"]
+            .iter()
+            .map(|x| x.parse().expect("parse error"))
+            .collect();
+        let mut redis_ops =
+            RedisOps::connect(addresses, Some(vec!["Redis.123456".to_string(); 3])).await?;
+        let user_id = (1 << 33) + 1;
+        let contact = (1 << 33) + 2;
+        redis_ops.push_set(&contacts_key(user_id), &contact).await?;
+        mark_online(&mut redis_ops, contact).await?;
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(8);
+        get_client_connection_map().insert(contact, MsgSender::Server(sender));
+
+        assert_eq!(notify(&mut redis_ops, user_id, true).await?, 1);
+        let msg = receiver.recv().await.unwrap();
+        assert_eq!(msg.typ(), Type::Presence);
+        assert_eq!(msg.sender(), user_id);
+        assert_eq!(msg.payload(), b"online");
+        get_client_connection_map().0.remove(&contact);
+        Ok(())
+    }
+}
@@ -13,6 +13,7 @@ use lib_net_tokio::net::Handler;
 use tracing::{debug, error};
 
 use crate::{
+    cache::get_redis_ops,
     cluster::ClusterConnectionMap,
     rpc::{get_rpc_client, node::RpcClient},
     service::handler::{IOTaskMsg::Direct, IOTaskSender},
@@ -20,7 +21,11 @@ use crate::{
     util::my_id,
 };
 
-use super::{is_group_msg, push_group_msg};
+use super::{
+    dead_letter::{dead_letter, store_offline_or_dead_letter, DeadLetterReason},
+    is_group_msg, push_group_msg,
+    router::{get_router, RouteDecision},
+};
 
 pub(crate) struct PureText;
 
@@ -32,7 +37,6 @@ impl Handler for PureText {
             return Err(anyhow!(HandlerError::NotMine));
         }
         let receiver = msg.receiver();
-        let node_id = msg.node_id();
         if is_group_msg(receiver) {
             if states.get("group_node_list_map").is_none() {
                 states.insert(
@@ -146,39 +150,66 @@ impl Handler for PureText {
             }
         } else {
             io_task_sender.send(Direct(msg.clone())).await?;
-            if node_id == my_id() {
-                match client_map.get(&receiver) {
+            match get_router().route(msg) {
+                RouteDecision::Local => match client_map.get(&receiver) {
                     Some(client_sender) => {
                         if let Err(e) = client_sender.send(msg.clone()).await {
                             error!("send to client[{}] error: {}", receiver, e);
+                            dead_letter(
+                                &mut get_redis_ops().await,
+                                DeadLetterReason::SendFailed,
+                                msg,
+                            )
+                            .await;
                         }
                     }
                     None => {
-                        debug!("receiver {} not found", receiver);
+                        debug!("receiver {} gone, store it for sync.", receiver);
+                        store_offline_or_dead_letter(&mut get_redis_ops().await, msg).await?;
                     }
-                }
-            } else {
-                match cluster_map.get(&node_id) {
-                    Some(sender) => {
-                        if let Err(e) = sender.send(msg.clone()).await {
-                            // this one and the blow error should be handle by scheduler to
-                            // check where remote node is still alive.
-                            // and whether new connection need to be established.
-                            error!("send to cluster[{}] error: {}", node_id, e);
+                },
+                RouteDecision::Forward(node_id) | RouteDecision::Unreachable(node_id) => {
+                    match cluster_map.get(&node_id) {
+                        Some(sender) => {
+                            if let Err(e) = sender.send(msg.clone()).await {
+                                // this one and the blow error should be handle by scheduler to
+                                // check where remote node is still alive.
+                                // and whether new connection need to be established.
+                                error!("send to cluster[{}] error: {}", node_id, e);
+                                return Err(anyhow!(HandlerError::IO(
+                                    "server cluster crashed!".to_string()
+                                )));
+                            }
+                        }
+                        None => {
+                            // same as above.
+                            // todo!() should be handled by scheduler!!!
+                            error!("cluster[{}] offline!", node_id);
+                            dead_letter(
+                                &mut get_redis_ops().await,
+                                DeadLetterReason::PeerUnreachable,
+                                msg,
+                            )
+                            .await;
                             return Err(anyhow!(HandlerError::IO(
                                 "server cluster crashed!".to_string()
                             )));
                         }
                     }
-                    None => {
-                        // same as above.
-                        // todo!() should be handled by scheduler!!!
-                        error!("cluster[{}] offline!", node_id);
-                        return Err(anyhow!(HandlerError::IO(
-                            "server cluster crashed!".to_string()
-                        )));
-                    }
                 }
+                RouteDecision::Offline => {
+                    debug!("receiver {} not found, store it for sync.", receiver);
+                    store_offline_or_dead_letter(&mut get_redis_ops().await, msg).await?;
+                }
+                RouteDecision::Drop => {
+                    dead_letter(
+                        &mut get_redis_ops().await,
+                        DeadLetterReason::UnknownReceiver,
+                        msg,
+                    )
+                    .await;
+                }
+                RouteDecision::Group => {}
             }
         }
         let client_timestamp = states.get("client_timestamp").unwrap().as_num().unwrap();
@@ -0,0 +1,235 @@
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use lib::{
+    cache::{
+        keys::{quota_group_key, quota_key},
+        redis_ops::RedisOps,
+    },
+    entity::{Msg, Type},
+    error::HandlerError,
+    net::InnerStates,
+    util::timestamp,
+    Result,
+};
+use lib_net_tokio::net::Handler;
+use tracing::debug;
+
+use crate::{config::runtime, util::my_id};
+
+use super::is_group_msg;
+
+pub(crate) const QUOTA_EXCEEDED: &str = "quota exceeded";
+
+/// the state of a token bucket, `last` is when it was taken last, in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Bucket {
+    pub(crate) tokens: f64,
+    pub(crate) last: u64,
+}
+
+/// refill `bucket` by the time elapsed until `now` and take one token from it, a bucket not
+/// kept yet is full. the new state is returned with whether a token is taken.
+pub(crate) fn take_at(bucket: Option<Bucket>, rate: f64, burst: u64, now: u64) -> (Bucket, bool) {
+    let burst = burst as f64;
+    let tokens = match bucket {
+        Some(bucket) => {
+            let elapsed = now.saturating_sub(bucket.last) as f64;
+            burst.min(bucket.tokens + elapsed * rate / 1000.0)
+        }
+        None => burst,
+    };
+    if tokens >= 1.0 {
+        (
+            Bucket {
+                tokens: tokens - 1.0,
+                last: now,
+            },
+            true,
+        )
+    } else {
+        (Bucket { tokens, last: now }, false)
+    }
+}
+
+/// the bucket is kept only as long as it takes to be full again.
+#[inline]
+pub(self) fn bucket_ttl(rate: f64, burst: u64) -> u64 {
+    (burst as f64 * 1000.0 / rate).ceil() as u64 + 1000
+}
+
+pub(self) const READ_SCRIPT: &str = "return redis.call('HMGET', KEYS[1], 'tokens', 'last')";
+/// save the bucket only if nobody has taken from it since it was read.
+/// ARGV: last read('' if none), tokens, last, ttl(in milliseconds).
+pub(self) const SAVE_SCRIPT: &str = r"
+local last = redis.call('HGET', KEYS[1], 'last')
+if (last or '') ~= ARGV[1] then
+    return 0
+end
+redis.call('HSET', KEYS[1], 'tokens', ARGV[2], 'last', ARGV[3])
+redis.call('PEXPIRE', KEYS[1], ARGV[4])
+return 1
+";
+/// a sender racing with itself more than this is refused.
+pub(self) const TAKE_ATTEMPTS: usize = 3;
+
+/// take one token from the bucket of `key`, false means the quota is exceeded.
+pub(crate) async fn take_token(
+    redis_ops: &mut RedisOps,
+    key: &str,
+    rate: f64,
+    burst: u64,
+) -> Result<bool> {
+    for _ in 0..TAKE_ATTEMPTS {
+        let (tokens, last): (Option<f64>, Option<u64>) =
+            redis_ops.lua1(READ_SCRIPT, key, 0).await?;
+        let bucket = match (tokens, last) {
+            (Some(tokens), Some(last)) => Some(Bucket { tokens, last }),
+            _ => None,
+        };
+        let (bucket, taken) = take_at(bucket, rate, burst, timestamp());
+        let saved: u64 = redis_ops
+            .lua1(
+                SAVE_SCRIPT,
+                key,
+                vec![
+                    last.map(|last| last.to_string()).unwrap_or_default(),
+                    bucket.tokens.to_string(),
+                    bucket.last.to_string(),
+                    bucket_ttl(rate, burst).to_string(),
+                ],
+            )
+            .await?;
+        if saved == 1 {
+            return Ok(taken);
+        }
+    }
+    Ok(false)
+}
+
+/// limit how fast an account can send, checked before the msg is assigned a seqnum or forwarded.
+/// msgs to groups are counted by a separate bucket.
+pub(crate) struct SendQuota;
+
+#[async_trait]
+impl Handler for SendQuota {
+    async fn run(&self, msg: &mut Arc<Msg>, states: &mut InnerStates) -> Result<Msg> {
+        let quota = match runtime::quota() {
+            Some(quota) => quota,
+            None => return Err(anyhow!(HandlerError::NotMine)),
+        };
+        let type_value = msg.typ().value();
+        if !(type_value >= 32 && type_value < 96 || type_value >= 128 && type_value < 160) {
+            return Err(anyhow!(HandlerError::NotMine));
+        }
+        let mut redis_ops = states
+            .get_mut("generic_map")
+            .unwrap()
+            .as_mut_generic_parameter_map()
+            .unwrap()
+            .get_parameter_mut::<RedisOps>()
+            .unwrap()
+            .clone();
+        let allowed = if is_group_msg(msg.receiver()) {
+            take_token(
+                &mut redis_ops,
+                &quota_group_key(msg.sender()),
+                quota.group_rate,
+                quota.group_burst,
+            )
+            .await?
+        } else {
+            take_token(
+                &mut redis_ops,
+                &quota_key(msg.sender()),
+                quota.rate,
+                quota.burst,
+            )
+            .await?
+        };
+        if allowed {
+            return Err(anyhow!(HandlerError::NotMine));
+        }
+        debug!("quota of {} exceeded.", msg.sender());
+        // the timestamp is the client's one, so the rejection can be matched with the msg sent.
+        let mut res = Msg::raw(
+            msg.sender(),
+            msg.receiver(),
+            my_id(),
+            QUOTA_EXCEEDED.as_bytes(),
+        );
+        res.set_type(Type::Ack);
+        res.set_timestamp(msg.timestamp());
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use lib::{cache::redis_ops::RedisOps, util::timestamp, Result};
+
+    use super::{bucket_ttl, take_at, take_token, Bucket};
+
+    #[test]
+    fn test_take_at() {
+        // 10 tokens per second, 2 at most.
+        let (bucket, taken) = take_at(None, 10.0, 2, 1000);
+        assert!(taken);
+        assert_eq!(
+            bucket,
+            Bucket {
+                tokens: 1.0,
+                last: 1000
+            }
+        );
+        let (bucket, taken) = take_at(Some(bucket), 10.0, 2, 1000);
+        assert!(taken);
+        let (bucket, taken) = take_at(Some(bucket), 10.0, 2, 1050);
+        assert!(!taken);
+        assert_eq!(bucket.tokens, 0.5);
+        // half a token more makes one.
+        let (bucket, taken) = take_at(Some(bucket), 10.0, 2, 1100);
+        assert!(taken);
+        assert!(bucket.tokens.abs() < 1e-9);
+        // never more than the burst however long it rests.
+        let (bucket, _) = take_at(Some(bucket), 10.0, 2, 1_000_000);
+        assert_eq!(bucket.tokens, 1.0);
+        // a clock going back refills nothing.
+        let (bucket, taken) = take_at(
+            Some(Bucket {
+                tokens: 0.0,
+                last: 2000,
+            }),
+            10.0,
+            2,
+            1000,
+        );
+        assert!(!taken);
+        assert_eq!(bucket.tokens, 0.0);
+        assert_eq!(bucket_ttl(10.0, 2), 1200);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the redis cluster of docker-compose"]
+    async fn test_token_bucket_refill() -> Result<()> {
+        let addresses = vec!["127.0.0.1:16379", "127.0.0.1:16380", "127.0.0.1:16381"]
+            .iter()
+            .map(|x| x.parse().expect("parse error"))
+            .collect();
+        let mut redis_ops =
+            RedisOps::connect(addresses, Some(vec!["Redis.123456".to_string(); 3])).await?;
+        let key = format!("QUOTA_TEST_{}", timestamp());
+        // 10 tokens per second, 2 at most.
+        assert!(take_token(&mut redis_ops, &key, 10.0, 2).await?);
+        assert!(take_token(&mut redis_ops, &key, 10.0, 2).await?);
+        assert!(!take_token(&mut redis_ops, &key, 10.0, 2).await?);
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert!(take_token(&mut redis_ops, &key, 10.0, 2).await?);
+        assert!(!take_token(&mut redis_ops, &key, 10.0, 2).await?);
+        redis_ops.del(&key).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,199 @@
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+use lib::entity::{Msg, Type};
+
+use crate::{
+    cluster::{get_cluster_connection_map, ClusterConnectionMap},
+    service::{get_client_connection_map, ClientConnectionMap},
+    util::my_id,
+};
+
+use super::is_group_msg;
+
+lazy_static! {
+    /// the node each remote user was heard from last time, learned from msgs forwarded by peers.
+    static ref USER_NODE_MAP: Arc<DashMap<u64, u32>> = Arc::new(DashMap::new());
+}
+
+/// where a msg should go, the handler does the IO accordingly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RouteDecision {
+    /// the receiver is connected to this node.
+    Local,
+    /// the receiver lives on another node, which is connected.
+    Forward(u32),
+    /// the receiver lives on another node, but no connection to it exists.
+    Unreachable(u32),
+    /// the receiver is not connected, keep the msg until it comes back.
+    Offline,
+    /// the receiver is not connected, and the msg is not worth keeping, it goes to the dead
+    /// letters.
+    Drop,
+    /// the receiver is a group, members are reached by the group task.
+    Group,
+}
+
+/// routing policy separated from IO, it only reads the connection maps and never sends.
+pub(crate) struct Router {
+    node_id: u32,
+    client_map: ClientConnectionMap,
+    cluster_map: ClusterConnectionMap,
+    user_node_map: Arc<DashMap<u64, u32>>,
+}
+
+impl Router {
+    pub(crate) fn new(
+        node_id: u32,
+        client_map: ClientConnectionMap,
+        cluster_map: ClusterConnectionMap,
+        user_node_map: Arc<DashMap<u64, u32>>,
+    ) -> Self {
+        Self {
+            node_id,
+            client_map,
+            cluster_map,
+            user_node_map,
+        }
+    }
+
+    pub(crate) fn route(&self, msg: &Msg) -> RouteDecision {
+        let receiver = msg.receiver();
+        if is_group_msg(receiver) {
+            return RouteDecision::Group;
+        }
+        if self.client_map.get(&receiver).is_some() {
+            return RouteDecision::Local;
+        }
+        // the node given by the sender goes first, the cache only fills the blank.
+        let node_id = match msg.node_id() {
+            0 => self
+                .user_node_map
+                .get(&receiver)
+                .map(|entry| *entry.value()),
+            node_id => Some(node_id),
+        };
+        match node_id {
+            Some(node_id) if node_id != self.node_id => {
+                if self.cluster_map.get(&node_id).is_some() {
+                    RouteDecision::Forward(node_id)
+                } else {
+                    RouteDecision::Unreachable(node_id)
+                }
+            }
+            _ => {
+                if should_store_offline(msg.typ()) {
+                    RouteDecision::Offline
+                } else {
+                    RouteDecision::Drop
+                }
+            }
+        }
+    }
+
+    /// remember that the sender of `msg` is connected to `node_id`, only msgs between users are
+    /// counted, the others may be sent by the node itself.
+    pub(crate) fn learn(&self, msg: &Msg, node_id: u32) {
+        let user_id = msg.sender();
+        if !should_store_offline(msg.typ()) || is_group_msg(user_id) || node_id == self.node_id {
+            return;
+        }
+        self.user_node_map.insert(user_id, node_id);
+    }
+}
+
+/// the router of the current node, built on the shared connection maps.
+pub(crate) fn get_router() -> Router {
+    Router::new(
+        my_id(),
+        get_client_connection_map(),
+        get_cluster_connection_map(),
+        USER_NODE_MAP.clone(),
+    )
+}
+
+/// only msgs between users are replayed on sync, the others make no sense after a while.
+pub(crate) fn should_store_offline(typ: Type) -> bool {
+    let type_value = typ.value();
+    (32..96).contains(&type_value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use dashmap::DashMap;
+    use lib::entity::{Msg, Type};
+    use lib_net_tokio::net::MsgSender;
+
+    use super::{RouteDecision, Router};
+    use crate::{cluster::ClusterConnectionMap, service::ClientConnectionMap};
+
+    const NODE_ID: u32 = 1;
+    const PEER_ID: u32 = 2;
+
+    fn router() -> (Router, Vec<tokio::sync::mpsc::Receiver<Arc<Msg>>>) {
+        let client_map = ClientConnectionMap(Arc::new(DashMap::new()));
+        let cluster_map = ClusterConnectionMap(Arc::new(DashMap::new()));
+        let (client_sender, client_receiver) = tokio::sync::mpsc::channel(8);
+        let (peer_sender, peer_receiver) = tokio::sync::mpsc::channel(8);
+        client_map.insert(10, MsgSender::Server(client_sender));
+        cluster_map.insert(PEER_ID, MsgSender::Server(peer_sender));
+        let router = Router::new(NODE_ID, client_map, cluster_map, Arc::new(DashMap::new()));
+        (router, vec![client_receiver, peer_receiver])
+    }
+
+    #[test]
+    fn test_route_local() {
+        let (router, _receivers) = router();
+        assert_eq!(
+            router.route(&Msg::text(11, 10, NODE_ID, "hello")),
+            RouteDecision::Local
+        );
+        // a connected receiver is served here even the sender thinks otherwise.
+        assert_eq!(
+            router.route(&Msg::text(11, 10, PEER_ID, "hello")),
+            RouteDecision::Local
+        );
+        assert_eq!(
+            router.route(&Msg::text(11, 1 << 36, NODE_ID, "hello")),
+            RouteDecision::Group
+        );
+    }
+
+    #[test]
+    fn test_route_remote() {
+        let (router, _receivers) = router();
+        assert_eq!(
+            router.route(&Msg::text(10, 20, PEER_ID, "hello")),
+            RouteDecision::Forward(PEER_ID)
+        );
+        assert_eq!(
+            router.route(&Msg::text(10, 20, 3, "hello")),
+            RouteDecision::Unreachable(3)
+        );
+        // no node given, the learned one is used.
+        assert_eq!(
+            router.route(&Msg::text(10, 20, 0, "hello")),
+            RouteDecision::Offline
+        );
+        router.learn(&Msg::text(20, 10, NODE_ID, "hello"), PEER_ID);
+        assert_eq!(
+            router.route(&Msg::text(10, 20, 0, "hello")),
+            RouteDecision::Forward(PEER_ID)
+        );
+    }
+
+    #[test]
+    fn test_route_offline() {
+        let (router, _receivers) = router();
+        assert_eq!(
+            router.route(&Msg::text(10, 20, NODE_ID, "hello")),
+            RouteDecision::Offline
+        );
+        let mut msg = Msg::text(10, 20, NODE_ID, "hello");
+        msg.set_type(Type::AddFriend);
+        assert_eq!(router.route(&msg), RouteDecision::Drop);
+    }
+}
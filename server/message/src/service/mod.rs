@@ -15,16 +15,18 @@ use lazy_static::lazy_static;
 use lib::{
     entity::Msg,
     net::{client::ClientConfigBuilder, GenericParameter},
+    util::load::msglogger_runtimes,
     Result,
 };
-use lib_net_tokio::net::{client::ClientReqwestTcp, MsgSender, ReqwestOperatorManager};
+use lib_net_tokio::net::{
+    client::ClientReqwestTcp, MsgSender, ReqwestOperatorManager, SeqnumCheckedSender,
+};
 use rdkafka::{
     admin::{AdminClient, AdminOptions, NewTopic, ResourceSpecifier, TopicReplication},
     client::DefaultClientContext,
     producer::FutureProducer,
     ClientConfig,
 };
-use sysinfo::SystemExt;
 use tokio::sync::RwLock;
 use tracing::{error, info};
 
@@ -40,7 +42,8 @@ pub(crate) mod handler;
 pub(self) mod msglogger;
 pub(crate) mod server;
 
-pub(crate) struct ClientConnectionMap(pub(crate) Arc<DashMap<u64, MsgSender>>);
+/// the senders check the seqnums of live msgs in debug builds, see [`SeqnumCheckedSender`].
+pub(crate) struct ClientConnectionMap(pub(crate) Arc<DashMap<u64, SeqnumCheckedSender>>);
 #[derive(Clone)]
 pub(crate) struct Msglogger(pub(self) Arc<MsgloggerClient>);
 
@@ -119,12 +122,12 @@ impl GenericParameter for Msglogger {
 }
 
 impl ClientConnectionMap {
-    pub(crate) fn get<'a>(&'a self, id: &u64) -> Option<Ref<'a, u64, MsgSender>> {
+    pub(crate) fn get<'a>(&'a self, id: &u64) -> Option<Ref<'a, u64, SeqnumCheckedSender>> {
         self.0.get(id)
     }
 
     pub(crate) fn insert(&self, id: u64, sender: MsgSender) {
-        self.0.insert(id, sender);
+        self.0.insert(id, SeqnumCheckedSender::new(sender));
     }
 }
 
@@ -166,7 +169,10 @@ pub(crate) async fn load_seqnum_map() -> Result<()> {
             .with_max_bi_streams(config().transport.max_bi_streams);
         let client_config = client_config.build().unwrap();
         let mut client = ClientReqwestTcp::new(client_config, Duration::from_millis(3000));
-        let operator_manager = client.build().await.unwrap();
+        let mut operator_manager = client.build().await.unwrap();
+        if let Some(max_in_flight) = config().seqnum.max_in_flight {
+            operator_manager.set_max_in_flight(max_in_flight);
+        }
         info!("seqnum client connected: {}", address);
         let node_id = node_id_list[i];
         holder.insert(node_id, client);
@@ -178,8 +184,7 @@ pub(crate) async fn load_seqnum_map() -> Result<()> {
 pub(crate) async fn load_msglogger() -> Result<()> {
     // create msglogger client
     let mut map = AHashMap::new();
-    let sys = sysinfo::System::new_all();
-    for i in 0..sys.cpus().len() {
+    for i in 0..msglogger_runtimes() {
         let address = format!("/tmp/msglogger-{}.sock", i);
         let client = MsgloggerClient::new(address).await?;
         map.insert(i, Msglogger(Arc::new(client)));
@@ -255,3 +260,7 @@ pub(crate) async fn start() -> Result<()> {
     server::Server::run().await?;
     Ok(())
 }
+
+pub(crate) async fn shutdown() {
+    server::Server::shutdown().await;
+}
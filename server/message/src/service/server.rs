@@ -1,26 +1,32 @@
+use std::time::Duration;
+
 use crate::config::config;
 use ahash::AHashMap;
 use async_trait::async_trait;
+use common::shutdown::reload_signal;
 use lib::{
     net::{server::ServerConfigBuilder, InnerStates},
     Result,
 };
 use lib_net_tokio::net::{
     server::{
-        NewConnectionHandler, NewConnectionHandlerGenerator, NewConnectionHandlerGeneratorTcp,
-        NewConnectionHandlerTcp,
+        CertReloader, NewConnectionHandler, NewConnectionHandlerGenerator,
+        NewConnectionHandlerGeneratorTcp, NewConnectionHandlerTcp, ServerMetrics, ServerShutdown,
     },
     server::{Server as UdpServer, ServerTcp},
     Handler, HandlerList, MsgIOWrapper, MsgIOWrapperTcpS, MsgSender,
 };
-use tracing::error;
+use tokio::sync::OnceCell;
+use tracing::{error, info};
 
 use super::{
     get_seqnum_client_map,
     handler::{
         business::{AddFriend, JoinGroup, LeaveGroup, RemoveFriend, SystemMessage},
         logic::{Auth, Echo, MQPusher, PreProcess},
+        offline::SyncOffline,
         pure_text::PureText,
+        quota::SendQuota,
     },
 };
 use crate::service::{get_io_task_sender, handler::IOTaskSender};
@@ -95,6 +101,12 @@ impl NewConnectionHandlerTcp for MessageConnectionHandlerTcp {
     }
 }
 
+/// set once the server is up, so it can be stopped on shutdown.
+pub(self) static SERVER_SHUTDOWN: OnceCell<ServerShutdown> = OnceCell::const_new();
+
+/// how often the stream and connection counters are logged.
+pub(self) const METRICS_LOG_INTERVAL: Duration = Duration::from_secs(60);
+
 pub(crate) struct Server {}
 
 impl Server {
@@ -127,14 +139,20 @@ impl Server {
             .with_key(config().server.key.clone())
             .with_max_connections(config().server.max_connections)
             .with_connection_idle_timeout(config().transport.connection_idle_timeout)
-            .with_max_bi_streams(config().transport.max_bi_streams);
-        let server_config = config_builder.build().unwrap();
+            .with_max_bi_streams(config().transport.max_bi_streams)
+            .with_max_payload_bytes(config().server.max_payload_bytes);
+        if let Some((high_water, low_water)) = config().transport.flow_control {
+            config_builder.with_flow_control(high_water, low_water);
+        }
+        let server_config = config_builder.build()?;
 
         let mut handler_list: Vec<Box<dyn Handler>> = Vec::new();
         handler_list.push(Box::new(Auth {}));
+        handler_list.push(Box::new(SendQuota {}));
         handler_list.push(Box::new(PreProcess::new(get_seqnum_client_map())));
         handler_list.push(Box::new(MQPusher::new()));
         handler_list.push(Box::new(Echo {}));
+        handler_list.push(Box::new(SyncOffline {}));
         handler_list.push(Box::new(PureText {}));
         handler_list.push(Box::new(JoinGroup {}));
         handler_list.push(Box::new(LeaveGroup {}));
@@ -161,7 +179,16 @@ impl Server {
         });
 
         let mut server = UdpServer::new(server_config.clone());
+        _ = SERVER_SHUTDOWN.set(server.shutdown_handle());
+        tokio::spawn(Self::log_metrics(server.metrics()));
         let mut server_tcp = ServerTcp::new(server_config);
+        let mut hangup = reload_signal()?;
+        let reloaders = [server.cert_reloader(), server_tcp.cert_reloader()];
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                Self::reload_cert(&reloaders);
+            }
+        });
         tokio::spawn(async move {
             if let Err(e) = server_tcp.run(generator_tcp).await {
                 error!("message server error: {}", e);
@@ -170,4 +197,42 @@ impl Server {
         server.run(generator).await?;
         Ok(())
     }
+    /// the opened and closed totals differ by the streams alive, their rates tell the churn.
+    pub(self) async fn log_metrics(metrics: ServerMetrics) {
+        let mut interval = tokio::time::interval(METRICS_LOG_INTERVAL);
+        loop {
+            interval.tick().await;
+            info!(
+                "streams_opened_total: {}, streams_closed_total: {}, connections_active: {}",
+                metrics.streams_opened_total(),
+                metrics.streams_closed_total(),
+                metrics.connections_active()
+            );
+        }
+    }
+
+    /// read the cert files again for the new handshakes, the clients connected are kept.
+    pub(self) fn reload_cert(reloaders: &[CertReloader]) {
+        let (cert, key) = match config().server.read_cert() {
+            Ok(cert) => cert,
+            Err(e) => {
+                error!("reload cert error: {:#}", e);
+                return;
+            }
+        };
+        for reloader in reloaders.iter() {
+            if let Err(e) = reloader.reload_cert(cert.clone(), key.clone()) {
+                error!("reload cert error: {}", e);
+                return;
+            }
+        }
+        info!("cert reloaded.");
+    }
+
+    /// stop accepting clients and let the in-flight streams drain, no-op if not running.
+    pub(crate) async fn shutdown() {
+        if let Some(shutdown) = SERVER_SHUTDOWN.get() {
+            shutdown.shutdown().await;
+        }
+    }
 }
@@ -17,16 +17,3 @@ pub(super) async fn get_redis_ops() -> RedisOps {
         .await)
         .clone()
 }
-
-#[allow(unused)]
-pub(crate) static USER_TOKEN: &str = "USER_TOKEN_";
-#[allow(unused)]
-pub(crate) static NODE_ID: &str = "NODE_ID_MESSAGE_";
-#[allow(unused)]
-pub(crate) static SEQ_NUM: &str = "SEQ_NUM_";
-#[allow(unused)]
-pub(crate) static MSG_CACHE: &str = "MSG_CACHE_";
-#[allow(unused)]
-pub(crate) static LAST_ONLINE_TIME: &str = "LAST_ONLINE_TIME_";
-#[allow(unused)]
-pub(crate) static USER_INBOX: &str = "USER_INBOX_";
@@ -2,6 +2,7 @@ use std::{fs, net::SocketAddr, path::PathBuf, time::Duration};
 
 use anyhow::Context;
 use lazy_static::lazy_static;
+use lib::util::parse_log_level;
 use tracing::Level;
 
 #[derive(serde::Deserialize, Debug)]
@@ -153,14 +154,7 @@ pub(crate) struct Rpc {
 
 impl Config {
     fn from_config0(config0: Config0) -> Config {
-        let log_level = match config0.log_level.unwrap_or("info".to_string()).as_ref() {
-            "trace" => Level::TRACE,
-            "debug" => Level::DEBUG,
-            "info" => Level::INFO,
-            "warn" => Level::WARN,
-            "error" => Level::ERROR,
-            _ => Level::INFO,
-        };
+        let log_level = parse_log_level(config0.log_level);
         Config {
             log_level,
             server: Server::from_server0(config0.server.unwrap()),
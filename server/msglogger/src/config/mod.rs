@@ -0,0 +1,238 @@
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, Context};
+use lib::{
+    util::{
+        env::{apply_env_overrides, ENV_PREFIX},
+        load::msglogger_runtimes,
+    },
+    Result,
+};
+
+/// the default submission queue depth of io_uring, also taken by the legacy driver.
+pub(crate) const DEFAULT_ENTRIES: u32 = 16384;
+
+#[derive(serde::Deserialize, Debug, Default)]
+struct Config0 {
+    runtime: Option<Runtime0>,
+    log: Option<Log0>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Config {
+    pub(crate) runtime: Runtime,
+    pub(crate) log: Log,
+}
+
+#[derive(serde::Deserialize, Debug, Default)]
+struct Runtime0 {
+    driver: Option<String>,
+    entries: Option<u32>,
+    count: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Driver {
+    /// io_uring on linux if the kernel supports it, the legacy driver otherwise.
+    Auto,
+    IoUring,
+    Legacy,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Runtime {
+    pub(crate) driver: Driver,
+    pub(crate) entries: u32,
+    /// one receiver runtime each, listening on `/tmp/msglogger-{id}.sock`. the message node
+    /// connects to as many as `PRIM_MSGLOGGER_RUNTIMES` tells, so set both if not the cpu count.
+    pub(crate) count: usize,
+}
+
+#[derive(serde::Deserialize, Debug, Default)]
+struct Log0 {
+    format: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RecordFormat {
+    /// the msgs as they are on the wire, read by [`crate::reader::LogReader`].
+    Binary,
+    /// length-prefixed json lines with the head decoded, for tools other than the reader.
+    Json,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Log {
+    pub(crate) format: RecordFormat,
+}
+
+impl Config {
+    fn from_config0(config0: Config0) -> Result<Config> {
+        Ok(Config {
+            runtime: Runtime::from_runtime0(config0.runtime.unwrap_or_default())?,
+            log: Log::from_log0(config0.log.unwrap_or_default())?,
+        })
+    }
+}
+
+impl Log {
+    fn from_log0(log0: Log0) -> Result<Self> {
+        let format = match log0.format.as_deref().unwrap_or("binary") {
+            "binary" => RecordFormat::Binary,
+            "json" => RecordFormat::Json,
+            format => {
+                return Err(anyhow!(
+                    "log.format must be one of binary, json, got {}",
+                    format
+                ))
+            }
+        };
+        Ok(Log { format })
+    }
+}
+
+impl Runtime {
+    fn from_runtime0(runtime0: Runtime0) -> Result<Self> {
+        let driver = match runtime0.driver.as_deref().unwrap_or("auto") {
+            "auto" => Driver::Auto,
+            "io_uring" if cfg!(target_os = "linux") => Driver::IoUring,
+            "io_uring" => return Err(anyhow!("runtime.driver io_uring is only on linux")),
+            "legacy" => Driver::Legacy,
+            driver => {
+                return Err(anyhow!(
+                    "runtime.driver must be one of auto, io_uring, legacy, got {}",
+                    driver
+                ))
+            }
+        };
+        let entries = runtime0.entries.unwrap_or(DEFAULT_ENTRIES);
+        if entries == 0 {
+            return Err(anyhow!("runtime.entries must be positive"));
+        }
+        // io_uring sizes its rings by powers of two.
+        if driver != Driver::Legacy && !entries.is_power_of_two() {
+            return Err(anyhow!(
+                "runtime.entries must be a power of two for io_uring, got {}",
+                entries
+            ));
+        }
+        let count = match runtime0.count {
+            Some(0) => return Err(anyhow!("runtime.count must be positive")),
+            Some(count) => count,
+            None => msglogger_runtimes(),
+        };
+        Ok(Runtime {
+            driver,
+            entries,
+            count,
+        })
+    }
+}
+
+pub(self) const ENV_KEYS: [&str; 4] = [
+    "runtime.driver",
+    "runtime.entries",
+    "runtime.count",
+    "log.format",
+];
+
+/// env > file > default, the file can be left out.
+pub(crate) fn load_config(config_path: &str) -> Result<Config> {
+    load_config_with_env(config_path, |name| std::env::var(name).ok())
+}
+
+pub(self) fn load_config_with_env<F: Fn(&str) -> Option<String>>(
+    config_path: &str,
+    env: F,
+) -> Result<Config> {
+    let mut table = if Path::new(config_path).exists() {
+        let toml_str = fs::read_to_string(config_path)
+            .with_context(|| format!("read config file {} failed.", config_path))?;
+        toml::from_str::<toml::Table>(&toml_str)
+            .with_context(|| format!("parse config file {} failed.", config_path))?
+    } else {
+        toml::Table::new()
+    };
+    apply_env_overrides(&mut table, ENV_PREFIX, &ENV_KEYS, env)?;
+    let config0: Config0 = toml::Value::Table(table)
+        .try_into()
+        .with_context(|| format!("parse config file {} failed.", config_path))?;
+    Config::from_config0(config0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_config_with_env, Driver, RecordFormat, DEFAULT_ENTRIES};
+
+    #[test]
+    fn test_runtime_config() {
+        let path = std::env::temp_dir().join(format!(
+            "prim-msglogger-config-{}.toml",
+            std::process::id()
+        ));
+        let path_str = path.to_str().unwrap();
+        let no_env = |_: &str| None;
+
+        std::fs::write(
+            &path,
+            "[runtime]\ndriver = \"legacy\"\nentries = 1000\ncount = 2\n",
+        )
+        .unwrap();
+        let runtime = load_config_with_env(path_str, no_env).unwrap().runtime;
+        assert_eq!(runtime.driver, Driver::Legacy);
+        // only io_uring cares about the power of two.
+        assert_eq!(runtime.entries, 1000);
+        assert_eq!(runtime.count, 2);
+
+        std::fs::write(&path, "[runtime]\nentries = 1000\n").unwrap();
+        let e = load_config_with_env(path_str, no_env).unwrap_err();
+        assert!(e.to_string().contains("power of two"));
+        let env = |name: &str| match name {
+            "PRIM_RUNTIME_ENTRIES" => Some("4096".to_string()),
+            _ => None,
+        };
+        let runtime = load_config_with_env(path_str, env).unwrap().runtime;
+        assert_eq!(runtime.driver, Driver::Auto);
+        assert_eq!(runtime.entries, 4096);
+        assert!(runtime.count >= 1);
+
+        std::fs::write(&path, "[runtime]\ndriver = \"epoll\"\n").unwrap();
+        assert!(load_config_with_env(path_str, no_env).is_err());
+        std::fs::write(&path, "[runtime]\ncount = 0\n").unwrap();
+        assert!(load_config_with_env(path_str, no_env).is_err());
+
+        // no file at all.
+        let runtime = load_config_with_env("./not-exist-config.toml", no_env)
+            .unwrap()
+            .runtime;
+        assert_eq!(runtime.driver, Driver::Auto);
+        assert_eq!(runtime.entries, DEFAULT_ENTRIES);
+        _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_log_config() {
+        let path = std::env::temp_dir().join(format!(
+            "prim-msglogger-log-config-{}.toml",
+            std::process::id()
+        ));
+        let path_str = path.to_str().unwrap();
+        let no_env = |_: &str| None;
+
+        std::fs::write(&path, "[runtime]\ncount = 1\n").unwrap();
+        let log = load_config_with_env(path_str, no_env).unwrap().log;
+        assert_eq!(log.format, RecordFormat::Binary);
+        std::fs::write(&path, "[log]\nformat = \"json\"\n").unwrap();
+        let log = load_config_with_env(path_str, no_env).unwrap().log;
+        assert_eq!(log.format, RecordFormat::Json);
+        let env = |name: &str| match name {
+            "PRIM_LOG_FORMAT" => Some("binary".to_string()),
+            _ => None,
+        };
+        let log = load_config_with_env(path_str, env).unwrap().log;
+        assert_eq!(log.format, RecordFormat::Binary);
+        std::fs::write(&path, "[log]\nformat = \"xml\"\n").unwrap();
+        assert!(load_config_with_env(path_str, no_env).is_err());
+        _ = std::fs::remove_file(path);
+    }
+}
@@ -1,14 +1,89 @@
-use std::ops::Sub;
+use std::{ops::Sub, path::Path};
 
+use anyhow::anyhow;
+use base64::Engine;
+use byteorder::{BigEndian, ByteOrder};
 use lib::{entity::Msg, Result};
 
+use crate::{config::RecordFormat, reader::meta_path};
+
 #[inline(always)]
-pub(crate) async fn logger(msg: Msg, file: &mut monoio::fs::File) -> Result<()> {
-    let (res, _) = file.write_all_at(msg.0, 0).await;
+pub(crate) async fn logger(
+    msg: Msg,
+    format: RecordFormat,
+    file: &mut monoio::fs::File,
+) -> Result<()> {
+    let buf = match format {
+        RecordFormat::Binary => msg.0,
+        RecordFormat::Json => encode_json_record(&msg)?,
+    };
+    let (res, _) = file.write_all_at(buf, 0).await;
     res?;
     Ok(())
 }
 
+/// a msg with its head decoded, the payload and extension are in standard base64.
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Eq)]
+pub(crate) struct JsonRecord {
+    pub(crate) version: u32,
+    pub(crate) sender: u64,
+    pub(crate) receiver: u64,
+    pub(crate) node_id: u32,
+    pub(crate) typ: u16,
+    pub(crate) type_name: String,
+    pub(crate) timestamp: u64,
+    pub(crate) seqnum: u64,
+    pub(crate) payload: String,
+    pub(crate) extension: String,
+}
+
+impl From<&Msg> for JsonRecord {
+    fn from(msg: &Msg) -> Self {
+        let engine = base64::engine::general_purpose::STANDARD;
+        Self {
+            version: msg.version(),
+            sender: msg.sender(),
+            receiver: msg.receiver(),
+            node_id: msg.node_id(),
+            typ: msg.typ().value(),
+            type_name: msg.typ().to_string(),
+            timestamp: msg.timestamp(),
+            seqnum: msg.seqnum(),
+            payload: engine.encode(msg.payload()),
+            extension: engine.encode(msg.extension()),
+        }
+    }
+}
+
+/// the length of the line(4 bytes, big endian, newline included) followed by the json line.
+pub(crate) fn encode_json_record(msg: &Msg) -> Result<Vec<u8>> {
+    let mut line = serde_json::to_vec(&JsonRecord::from(msg))?;
+    line.push(b'\n');
+    let mut buf = vec![0u8; 4];
+    BigEndian::write_u32(&mut buf, line.len() as u32);
+    buf.extend_from_slice(&line);
+    Ok(buf)
+}
+
+/// parse the records written by [`encode_json_record`] back, in the order they are logged.
+pub(crate) fn parse_json_records(buf: &[u8]) -> Result<Vec<JsonRecord>> {
+    let mut list = Vec::new();
+    let mut offset = 0;
+    while offset < buf.len() {
+        if buf.len() - offset < 4 {
+            return Err(anyhow!("truncated length at {}", offset));
+        }
+        let len = BigEndian::read_u32(&buf[offset..offset + 4]) as usize;
+        offset += 4;
+        if buf.len() - offset < len {
+            return Err(anyhow!("truncated record at {}", offset));
+        }
+        list.push(serde_json::from_slice(&buf[offset..offset + len])?);
+        offset += len;
+    }
+    Ok(list)
+}
+
 /// clear log file of the day before 7 days
 pub(crate) fn clear_log(id: usize) -> Result<()> {
     let prefix = chrono::Local::now()
@@ -17,6 +92,46 @@ pub(crate) fn clear_log(id: usize) -> Result<()> {
         .format("%Y-%m-%d")
         .to_string();
     let path = format!("./msglog/{}-{}.log", prefix, id);
+    _ = std::fs::remove_file(meta_path(Path::new(&path)));
     _ = std::fs::remove_file(path);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use base64::Engine;
+    use lib::entity::{Msg, Type};
+
+    use super::{encode_json_record, parse_json_records};
+
+    #[test]
+    fn test_json_record() {
+        let mut msg = Msg::text(1, 2, 3, "hello");
+        msg.set_timestamp(100);
+        msg.set_seqnum(7);
+        let mut buf = encode_json_record(&msg).unwrap();
+        buf.extend(encode_json_record(&Msg::text(2, 1, 3, "world")).unwrap());
+        // one line each, so the file also reads line by line.
+        assert_eq!(buf[4..].iter().filter(|b| **b == b'\n').count(), 2);
+
+        let list = parse_json_records(&buf).unwrap();
+        assert_eq!(list.len(), 2);
+        let record = &list[0];
+        assert_eq!(record.sender, 1);
+        assert_eq!(record.receiver, 2);
+        assert_eq!(record.node_id, 3);
+        assert_eq!(record.timestamp, 100);
+        assert_eq!(record.seqnum, 7);
+        assert_eq!(record.version, msg.version());
+        assert_eq!(Type::from(record.typ), Type::Text);
+        assert_eq!(record.type_name, Type::Text.to_string());
+        let payload = base64::engine::general_purpose::STANDARD
+            .decode(&record.payload)
+            .unwrap();
+        assert_eq!(payload, b"hello");
+        assert!(record.extension.is_empty());
+        assert_eq!(list[1].sender, 2);
+
+        assert!(parse_json_records(&buf[..buf.len() - 1]).is_err());
+    }
+}
@@ -1,9 +1,42 @@
+use structopt::StructOpt;
 use sysinfo::SystemExt;
 use tracing::{info, error, Level};
 
+use crate::{
+    config::{load_config, Driver, RecordFormat, Runtime},
+    reader::LogReader,
+};
+
+mod config;
 mod logger;
+mod reader;
 mod recv;
 
+#[derive(StructOpt, Debug)]
+#[structopt(name = "prim/msglogger")]
+pub(crate) struct Opt {
+    #[structopt(subcommand)]
+    pub(crate) cmd: Option<Command>,
+}
+
+#[derive(StructOpt, Debug)]
+pub(crate) enum Command {
+    /// print the msgs logged for a receiver within a time range instead of logging, only the
+    /// binary format can be read.
+    Query {
+        #[structopt(long)]
+        receiver: u64,
+        /// in milliseconds, inclusive.
+        #[structopt(long)]
+        from: u64,
+        /// in milliseconds, inclusive.
+        #[structopt(long)]
+        to: u64,
+        #[structopt(long, default_value = "./msglog")]
+        dir: String,
+    },
+}
+
 fn main() {
     tracing_subscriber::fmt()
         .event_format(
@@ -15,74 +48,85 @@ fn main() {
         .with_max_level(Level::INFO)
         .try_init()
         .unwrap();
+    if let Some(Command::Query {
+        receiver,
+        from,
+        to,
+        dir,
+    }) = Opt::from_args().cmd
+    {
+        query(&dir, receiver, from, to);
+        return;
+    }
+    let config_path = match std::env::var("CONFIG_PATH") {
+        Ok(path) => path,
+        Err(_) => "./msglogger/config.toml".to_string(),
+    };
+    let (runtime, format) = match load_config(&config_path) {
+        Ok(config) => (config.runtime, config.log.format),
+        Err(e) => {
+            error!("load config failed: {:#}", e);
+            std::process::exit(1);
+        }
+    };
     _ = std::fs::create_dir_all("./msglog");
     let sys = sysinfo::System::new_all();
-    if cfg!(target_os = "linux") {
-        info!("using io_uring driver");
+    if cfg!(target_os = "linux") && runtime.driver != Driver::Legacy {
+        info!("using io_uring driver with {} entries", runtime.entries);
         info!("linux kernel version: {}", sys.kernel_version().unwrap());
     } else {
         info!("using legacy driver");
     }
-    for id in 1..sys.cpus().len() {
-        std::thread::spawn(move || {
-            #[cfg(target_os = "linux")]
-            {
-                let build = monoio::RuntimeBuilder::<monoio::IoUringDriver>::new()
-                    .with_entries(16384)
-                    .enable_timer()
-                    .build();
-                match build {
-                    Ok(mut rt) => {
-                        _ = rt
-                            .block_on(recv::start(id));
-                    }
-                    Err(e) => {
-                        error!("could not build runtime with io_uring on linux: {}", e);
-                        _ = monoio::RuntimeBuilder::<monoio::LegacyDriver>::new()
-                            .with_entries(16384)
-                            .enable_timer()
-                            .build()
-                            .unwrap()
-                            .block_on(recv::start(id));
-                    }
-                };
-            }
-            #[cfg(target_os = "macos")]
-                let _ = monoio::RuntimeBuilder::<monoio::LegacyDriver>::new()
-                .enable_timer()
-                .build()
-                .unwrap()
-                .block_on(recv::start(id));
-        });
+    info!("running {} receiver runtimes", runtime.count);
+    info!("writing records in {:?} format", format);
+    for id in 1..runtime.count {
+        std::thread::spawn(move || run_receiver(id, runtime, format));
     }
     info!("msglogger started.");
+    run_receiver(0, runtime, format);
+    error!("msglogger exited.");
+}
+
+fn query(dir: &str, receiver: u64, from_ts: u64, to_ts: u64) {
+    match LogReader::new(dir).read_time_range(receiver, from_ts, to_ts) {
+        Ok(list) => {
+            for msg in list.iter() {
+                println!("{}", msg);
+            }
+        }
+        Err(e) => {
+            error!("read {} failed: {:#}", dir, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// block on the `id`th receiver with the driver chosen by `runtime`.
+fn run_receiver(id: usize, runtime: Runtime, format: RecordFormat) {
     #[cfg(target_os = "linux")]
-    {
+    if runtime.driver != Driver::Legacy {
         let build = monoio::RuntimeBuilder::<monoio::IoUringDriver>::new()
-            .with_entries(16384)
+            .with_entries(runtime.entries)
             .enable_timer()
             .build();
         match build {
             Ok(mut rt) => {
-                _ = rt
-                    .block_on(recv::start(0));
+                _ = rt.block_on(recv::start(id, format));
+                return;
+            }
+            Err(e) if runtime.driver == Driver::IoUring => {
+                error!("could not build runtime with io_uring on linux: {}", e);
+                std::process::exit(1);
             }
             Err(e) => {
                 error!("could not build runtime with io_uring on linux: {}", e);
-                _ = monoio::RuntimeBuilder::<monoio::LegacyDriver>::new()
-                    .with_entries(16384)
-                    .enable_timer()
-                    .build()
-                    .unwrap()
-                    .block_on(recv::start(0));
             }
-        };
+        }
     }
-    #[cfg(target_os = "macos")]
-        let _ = monoio::RuntimeBuilder::<monoio::LegacyDriver>::new()
+    _ = monoio::RuntimeBuilder::<monoio::LegacyDriver>::new()
+        .with_entries(runtime.entries)
         .enable_timer()
         .build()
         .unwrap()
-        .block_on(recv::start(0));
-    error!("msglogger exited.");
+        .block_on(recv::start(id, format));
 }
@@ -0,0 +1,204 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use byteorder::{BigEndian, ByteOrder};
+use lib::{
+    entity::{Head, Msg, HEAD_LEN},
+    Result,
+};
+use tracing::warn;
+
+/// the time range of a segment, kept beside it as `<segment>.meta` once it is rotated, so readers
+/// can skip it without scanning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SegmentMeta {
+    pub(crate) min_timestamp: u64,
+    pub(crate) max_timestamp: u64,
+}
+
+impl SegmentMeta {
+    pub(crate) fn new(timestamp: u64) -> Self {
+        Self {
+            min_timestamp: timestamp,
+            max_timestamp: timestamp,
+        }
+    }
+
+    pub(crate) fn update(&mut self, timestamp: u64) {
+        self.min_timestamp = self.min_timestamp.min(timestamp);
+        self.max_timestamp = self.max_timestamp.max(timestamp);
+    }
+
+    pub(crate) fn overlaps(&self, from_ts: u64, to_ts: u64) -> bool {
+        self.min_timestamp <= to_ts && self.max_timestamp >= from_ts
+    }
+
+    pub(crate) fn to_bytes(&self) -> [u8; 16] {
+        let mut buf = [0u8; 16];
+        BigEndian::write_u64(&mut buf[0..8], self.min_timestamp);
+        BigEndian::write_u64(&mut buf[8..16], self.max_timestamp);
+        buf
+    }
+
+    pub(crate) fn from_bytes(buf: &[u8]) -> Option<Self> {
+        if buf.len() != 16 {
+            return None;
+        }
+        Some(Self {
+            min_timestamp: BigEndian::read_u64(&buf[0..8]),
+            max_timestamp: BigEndian::read_u64(&buf[8..16]),
+        })
+    }
+}
+
+pub(crate) fn meta_path(segment: &Path) -> PathBuf {
+    let mut path = segment.as_os_str().to_owned();
+    path.push(".meta");
+    PathBuf::from(path)
+}
+
+pub(crate) fn write_segment_meta(segment: &Path, meta: &SegmentMeta) -> Result<()> {
+    fs::write(meta_path(segment), meta.to_bytes())?;
+    Ok(())
+}
+
+/// `None` for a segment still being written, or one rotated by an older version.
+pub(crate) fn read_segment_meta(segment: &Path) -> Option<SegmentMeta> {
+    fs::read(meta_path(segment))
+        .ok()
+        .and_then(|buf| SegmentMeta::from_bytes(&buf))
+}
+
+/// reads the segments under a log directory, they are plain msgs written back to back.
+pub(crate) struct LogReader {
+    dir: PathBuf,
+}
+
+impl LogReader {
+    pub(crate) fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub(crate) fn segments(&self) -> Result<Vec<PathBuf>> {
+        let mut segments = fs::read_dir(&self.dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().map_or(false, |ext| ext == "log"))
+            .collect::<Vec<PathBuf>>();
+        // named by date first, so this is the order they are written in for the same id.
+        segments.sort();
+        Ok(segments)
+    }
+
+    /// msgs to `receiver` stamped within `from_ts..=to_ts` in milliseconds, in the order they are
+    /// logged.
+    ///
+    /// rotated segments not overlapping the range are skipped without being opened.
+    pub(crate) fn read_time_range(
+        &self,
+        receiver: u64,
+        from_ts: u64,
+        to_ts: u64,
+    ) -> Result<Vec<Msg>> {
+        let mut list = Vec::new();
+        for segment in self.segments()? {
+            if let Some(meta) = read_segment_meta(&segment) {
+                if !meta.overlaps(from_ts, to_ts) {
+                    continue;
+                }
+            }
+            let buf = fs::read(&segment)?;
+            for msg in parse_segment(&buf) {
+                let timestamp = msg.timestamp();
+                if msg.receiver() == receiver && timestamp >= from_ts && timestamp <= to_ts {
+                    list.push(msg);
+                }
+            }
+        }
+        Ok(list)
+    }
+}
+
+/// the complete msgs of a segment, a record cut short at the end is left out, since the
+/// segment still being written usually ends in the middle of one.
+pub(self) fn parse_segment(buf: &[u8]) -> Vec<Msg> {
+    let mut list = Vec::new();
+    let mut offset = 0;
+    while offset < buf.len() {
+        if buf.len() - offset < HEAD_LEN {
+            warn!("truncated head at {}", offset);
+            break;
+        }
+        let mut head = Head::from(&buf[offset..offset + HEAD_LEN]);
+        let mut msg = Msg::pre_alloc(&mut head);
+        let len = msg.0.len();
+        if buf.len() - offset < len {
+            warn!("truncated body at {}", offset);
+            break;
+        }
+        msg.0[HEAD_LEN..].copy_from_slice(&buf[offset + HEAD_LEN..offset + len]);
+        list.push(msg);
+        offset += len;
+    }
+    list
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use lib::entity::Msg;
+
+    use super::{write_segment_meta, LogReader, SegmentMeta};
+
+    #[test]
+    fn test_read_time_range() {
+        let dir = std::env::temp_dir().join(format!("prim-msglog-{}", std::process::id()));
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let write_segment = |name: &str, timestamps: &[u64], rotated: bool| {
+            let path = dir.join(name);
+            let mut buf = Vec::new();
+            let mut meta = SegmentMeta::new(timestamps[0]);
+            for (i, timestamp) in timestamps.iter().enumerate() {
+                let mut msg = Msg::text(1, 2, 0, &format!("msg {}", timestamp));
+                msg.set_timestamp(*timestamp);
+                msg.set_seqnum(i as u64);
+                buf.extend_from_slice(msg.as_slice());
+                meta.update(*timestamp);
+            }
+            fs::write(&path, buf).unwrap();
+            if rotated {
+                write_segment_meta(&path, &meta).unwrap();
+            }
+        };
+        // the oldest one is not readable at all, it must be skipped by its meta.
+        fs::write(dir.join("2023-01-01-0.log"), b"garbage").unwrap();
+        write_segment_meta(
+            &dir.join("2023-01-01-0.log"),
+            &SegmentMeta {
+                min_timestamp: 0,
+                max_timestamp: 99,
+            },
+        )
+        .unwrap();
+        write_segment("2023-01-02-0.log", &[100, 110, 120], true);
+        write_segment("2023-01-03-0.log", &[130, 140, 150], false);
+        // the one being written stops in the middle of a msg.
+        let mut msg = Msg::text(1, 2, 0, "msg 160");
+        msg.set_timestamp(160);
+        let mut buf = fs::read(dir.join("2023-01-03-0.log")).unwrap();
+        buf.extend_from_slice(&msg.as_slice()[..msg.as_slice().len() - 1]);
+        fs::write(dir.join("2023-01-03-0.log"), buf).unwrap();
+
+        let reader = LogReader::new(&dir);
+        let list = reader.read_time_range(2, 110, 140).unwrap();
+        let timestamps = list.iter().map(|msg| msg.timestamp()).collect::<Vec<u64>>();
+        assert_eq!(timestamps, vec![110, 120, 130, 140]);
+        let list = reader.read_time_range(2, 100, 200).unwrap();
+        assert_eq!(list.last().unwrap().timestamp(), 150);
+        assert!(reader.read_time_range(3, 100, 200).unwrap().is_empty());
+        _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,4 +1,4 @@
-use std::{os::unix::fs::OpenOptionsExt, fs};
+use std::{fs, os::unix::fs::OpenOptionsExt, path::PathBuf};
 
 use byteorder::{BigEndian, ByteOrder};
 use chrono::{Duration, Local, NaiveTime};
@@ -14,9 +14,13 @@ use monoio::{
 };
 use tracing::{error, info};
 
-use crate::logger;
+use crate::{
+    config::RecordFormat,
+    logger,
+    reader::{write_segment_meta, SegmentMeta},
+};
 
-pub(crate) async fn start(id: usize) -> Result<()> {
+pub(crate) async fn start(id: usize, format: RecordFormat) -> Result<()> {
     let (tx, rx) = mpsc::bounded::channel(1);
     monoio::spawn(async move {
         loop {
@@ -32,7 +36,7 @@ pub(crate) async fn start(id: usize) -> Result<()> {
                 .open(&path)
                 .await
                 .unwrap();
-            _ = tx.send(file).await;
+            _ = tx.send((PathBuf::from(path), file)).await;
             let now = Local::now();
             let one_day = Duration::days(1);
             let target_date = now.date_naive() + one_day;
@@ -96,24 +100,38 @@ pub(crate) async fn start(id: usize) -> Result<()> {
             }
         }
     });
-    handle_connection(send_receiver, recv_sender, rx).await
+    handle_connection(send_receiver, recv_sender, rx, format).await
 }
 
 pub(self) async fn handle_connection(
     mut receiver: mpsc::bounded::Rx<(u64, Msg)>,
     sender: mpsc::bounded::Tx<u64>,
-    mut rx: mpsc::bounded::Rx<File>,
+    mut rx: mpsc::bounded::Rx<(PathBuf, File)>,
+    format: RecordFormat,
 ) -> Result<()> {
-    let mut file: Option<File> = Some(rx.recv().await.unwrap());
+    let (mut path, file) = rx.recv().await.unwrap();
+    let mut file: Option<File> = Some(file);
+    // the time range of the segment being written, saved when it is rotated.
+    let mut meta: Option<SegmentMeta> = None;
     loop {
         let (id, msg) = match receiver.recv().await {
             Some(msg) => msg,
             None => break,
         };
-        if rx.try_recv().is_ok() {
-            file = Some(rx.recv().await.unwrap());
+        if let Ok((new_path, new_file)) = rx.try_recv() {
+            if let Some(meta) = meta.take() {
+                if let Err(e) = write_segment_meta(&path, &meta) {
+                    error!("write segment meta error: {:?}", e);
+                }
+            }
+            path = new_path;
+            file = Some(new_file);
+        }
+        match meta.as_mut() {
+            Some(meta) => meta.update(msg.timestamp()),
+            None => meta = Some(SegmentMeta::new(msg.timestamp())),
         }
-        if let Err(e) = logger::logger(msg, file.as_mut().unwrap()).await {
+        if let Err(e) = logger::logger(msg, format, file.as_mut().unwrap()).await {
             error!("logger error: {:?}", e);
             break;
         };
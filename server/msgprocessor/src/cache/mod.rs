@@ -16,5 +16,3 @@ pub(super) async fn get_redis_ops() -> RedisOps {
         .await)
         .clone()
 }
-
-pub(crate) static NODE_ID: &str = "NODE_ID_MSGPROCESSOR_";
@@ -1,7 +1,14 @@
-use std::{fs, net::SocketAddr, path::PathBuf, time::Duration};
+use std::{fs, net::SocketAddr, path::PathBuf, sync::OnceLock, time::Duration};
 
-use anyhow::Context;
+use anyhow::{anyhow, Context};
 use lazy_static::lazy_static;
+use lib::{
+    util::{
+        env::{load_config_table, ENV_PREFIX},
+        parse_log_level,
+    },
+    Result,
+};
 use tracing::Level;
 
 #[derive(serde::Deserialize, Debug)]
@@ -69,81 +76,211 @@ pub(crate) struct MessageQueue {
 }
 
 impl Config {
-    fn from_config0(config0: Config0) -> Config {
-        let log_level = match config0.log_level.unwrap_or("info".to_string()).as_ref() {
-            "trace" => Level::TRACE,
-            "debug" => Level::DEBUG,
-            "info" => Level::INFO,
-            "warn" => Level::WARN,
-            "error" => Level::ERROR,
-            _ => Level::INFO,
-        };
-        Config {
+    fn from_config0(config0: Config0) -> Result<Config> {
+        let log_level = parse_log_level(config0.log_level);
+        Ok(Config {
             log_level,
-            transport: Transport::from_transport0(config0.transport.unwrap()),
-            redis: Redis::from_redis0(config0.redis.unwrap()),
-            scheduler: Scheduler::from_scheduler0(config0.scheduler.unwrap()),
-            message_queue: MessageQueue::from_message_queue0(config0.message_queue.unwrap()),
-        }
+            transport: Transport::from_transport0(
+                config0
+                    .transport
+                    .ok_or_else(|| anyhow!("transport is required"))?,
+            )?,
+            redis: Redis::from_redis0(config0.redis.ok_or_else(|| anyhow!("redis is required"))?)?,
+            scheduler: Scheduler::from_scheduler0(
+                config0
+                    .scheduler
+                    .ok_or_else(|| anyhow!("scheduler is required"))?,
+            )?,
+            message_queue: MessageQueue::from_message_queue0(
+                config0
+                    .message_queue
+                    .ok_or_else(|| anyhow!("message_queue is required"))?,
+            )?,
+        })
     }
 }
 
 impl Transport {
-    fn from_transport0(transport0: Transport0) -> Self {
-        Transport {
-            keep_alive_interval: Duration::from_millis(transport0.keep_alive_interval.unwrap()),
-            max_bi_streams: transport0.max_bi_streams.unwrap(),
-        }
+    fn from_transport0(transport0: Transport0) -> Result<Self> {
+        Ok(Transport {
+            keep_alive_interval: Duration::from_millis(
+                transport0
+                    .keep_alive_interval
+                    .ok_or_else(|| anyhow!("transport.keep_alive_interval is required"))?,
+            ),
+            max_bi_streams: transport0
+                .max_bi_streams
+                .ok_or_else(|| anyhow!("transport.max_bi_streams is required"))?,
+        })
     }
 }
 
 impl Redis {
-    fn from_redis0(redis0: Redis0) -> Self {
+    fn from_redis0(redis0: Redis0) -> Result<Self> {
         let mut addr = vec![];
-        for address in redis0.addresses.as_ref().unwrap().iter() {
+        for address in redis0
+            .addresses
+            .as_ref()
+            .ok_or_else(|| anyhow!("redis.addresses is required"))?
+            .iter()
+        {
             addr.push(
                 address
                     .parse::<SocketAddr>()
-                    .expect("parse redis address failed"),
+                    .with_context(|| format!("parse redis address {} failed.", address))?,
             );
         }
-        Redis { addresses: addr }
+        Ok(Redis { addresses: addr })
     }
 }
 
 impl Scheduler {
-    fn from_scheduler0(mut scheduler0: Scheduler0) -> Self {
-        let cert = fs::read(PathBuf::from(scheduler0.cert_path.as_ref().unwrap()))
-            .context("read key file failed.")
-            .unwrap();
-        Scheduler {
+    fn from_scheduler0(mut scheduler0: Scheduler0) -> Result<Self> {
+        let cert_path = scheduler0
+            .cert_path
+            .as_ref()
+            .ok_or_else(|| anyhow!("scheduler.cert_path is required"))?;
+        let cert = fs::read(PathBuf::from(cert_path))
+            .with_context(|| format!("read cert file {} failed.", cert_path))?;
+        Ok(Scheduler {
             address: scheduler0
                 .address
-                .unwrap()
+                .ok_or_else(|| anyhow!("scheduler.address is required"))?
                 .parse::<SocketAddr>()
-                .expect("parse scheduler address failed"),
-            domain: scheduler0.domain.take().unwrap(),
+                .context("parse scheduler address failed.")?,
+            domain: scheduler0
+                .domain
+                .take()
+                .ok_or_else(|| anyhow!("scheduler.domain is required"))?,
             cert: rustls::Certificate(cert),
-        }
+        })
     }
 }
 
 impl MessageQueue {
-    fn from_message_queue0(message_queue0: MessageQueue0) -> Self {
-        MessageQueue {
-            address: message_queue0.address.unwrap(),
-        }
+    fn from_message_queue0(message_queue0: MessageQueue0) -> Result<Self> {
+        Ok(MessageQueue {
+            address: message_queue0
+                .address
+                .ok_or_else(|| anyhow!("message_queue.address is required"))?,
+        })
     }
 }
 
-pub(crate) fn load_config() -> Config {
-    let toml_str = fs::read_to_string(unsafe { CONFIG_FILE_PATH }).unwrap();
-    let config0: Config0 = toml::from_str(&toml_str).unwrap();
+/// every field can be overridden by an env var named after it, e.g. `scheduler.address` by
+/// `PRIM_SCHEDULER_ADDRESS`, see `apply_env_overrides`.
+pub(self) const ENV_KEYS: [&str; 8] = [
+    "log_level",
+    "transport.keep_alive_interval",
+    "transport.max_bi_streams",
+    "redis.addresses[]",
+    "scheduler.address",
+    "scheduler.domain",
+    "scheduler.cert_path",
+    "message_queue.address",
+];
+
+/// env > file > default.
+pub(crate) fn load_config(config_path: &str) -> Result<Config> {
+    load_config_with_env(config_path, |name| std::env::var(name).ok())
+}
+
+pub(self) fn load_config_with_env<F: Fn(&str) -> Option<String>>(
+    config_path: &str,
+    env: F,
+) -> Result<Config> {
+    let table = load_config_table(config_path, ENV_PREFIX, &ENV_KEYS, env)?;
+    let config0: Config0 = toml::Value::Table(table)
+        .try_into()
+        .with_context(|| format!("parse config file {} failed.", config_path))?;
     Config::from_config0(config0)
 }
 
 pub(crate) static mut CONFIG_FILE_PATH: &'static str = "./msgprocessor/config.toml";
 
+pub(self) static LOADED: OnceLock<Config> = OnceLock::new();
+
+/// load and validate the config before serving, so a broken one fails the startup with its cause.
+pub(crate) fn init_config(config_path: &str) -> Result<&'static Config> {
+    let config = load_config(config_path)?;
+    // the first loaded one wins.
+    Ok(LOADED.get_or_init(|| config))
+}
+
+pub(crate) fn try_config() -> Result<&'static Config> {
+    match LOADED.get() {
+        Some(config) => Ok(config),
+        None => init_config(unsafe { CONFIG_FILE_PATH }),
+    }
+}
+
 lazy_static! {
-    pub(crate) static ref CONFIG: Config = load_config();
+    /// for hot paths, never panics once [`init_config`] succeeded at startup.
+    pub(crate) static ref CONFIG: &'static Config = try_config().unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{init_config, load_config_with_env};
+
+    #[test]
+    fn test_init_config_broken_file() {
+        assert!(init_config("./not-exist-config.toml").is_err());
+        let path = std::env::temp_dir().join("prim-msgprocessor-broken-config.toml");
+        std::fs::write(&path, "[transport]\nkeep_alive_interval = \"x\"\n").unwrap();
+        let e = init_config(path.to_str().unwrap()).unwrap_err();
+        assert!(e.to_string().contains("parse config file"));
+        std::fs::write(
+            &path,
+            "[transport]\nkeep_alive_interval = 1000\nmax_bi_streams = 8\n",
+        )
+        .unwrap();
+        let e = init_config(path.to_str().unwrap()).unwrap_err();
+        assert_eq!(e.to_string(), "redis is required");
+        _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_env_overrides_file() {
+        let dir =
+            std::env::temp_dir().join(format!("prim-msgprocessor-env-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cert_path = dir.join("PrimRootCA.crt.der");
+        std::fs::write(&cert_path, b"placeholder").unwrap();
+        let path = dir.join("config.toml");
+        let toml_str = r#"
+[transport]
+keep_alive_interval = 1000
+max_bi_streams = 8
+[redis]
+addresses = ["127.0.0.1:16379"]
+[scheduler]
+address = "127.0.0.1:11222"
+domain = "localhost"
+cert_path = "<cert_path>"
+[message_queue]
+address = "127.0.0.1:9092"
+"#;
+        std::fs::write(
+            &path,
+            toml_str.replace("<cert_path>", cert_path.to_str().unwrap()),
+        )
+        .unwrap();
+        let env = |name: &str| match name {
+            "PRIM_TRANSPORT_MAX_BI_STREAMS" => Some("16".to_string()),
+            "PRIM_REDIS_ADDRESSES" => Some("10.0.0.1:16379,10.0.0.2:16379".to_string()),
+            _ => None,
+        };
+        let config = load_config_with_env(path.to_str().unwrap(), env).unwrap();
+        assert_eq!(config.transport.max_bi_streams, 16);
+        assert_eq!(config.redis.addresses.len(), 2);
+        assert_eq!(config.redis.addresses[1].to_string(), "10.0.0.2:16379");
+        // not overridden.
+        assert_eq!(config.message_queue.address, "127.0.0.1:9092");
+
+        // the file is never left out, even if env gives all the required fields.
+        let e = load_config_with_env("./not-exist-config.toml", env).unwrap_err();
+        assert!(e.to_string().contains("read config file"));
+        _ = std::fs::remove_dir_all(dir);
+    }
 }
@@ -1,7 +1,10 @@
 use lib::{Result, joy};
 use tracing::info;
 
-use crate::{config::CONFIG, util::my_id};
+use crate::{
+    config::{init_config, CONFIG, CONFIG_FILE_PATH},
+    util::my_id,
+};
 
 pub(crate) mod cache;
 pub(crate) mod config;
@@ -12,6 +15,8 @@ pub(crate) mod util;
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    // fail fast on a broken config instead of panicking at the first access.
+    init_config(unsafe { CONFIG_FILE_PATH })?;
     tracing_subscriber::fmt()
         .event_format(
             tracing_subscriber::fmt::format()
@@ -0,0 +1,16 @@
+-- Constraint: user_account_id_delete_at
+
+-- for the databases initialized before init.sql had the constraint, run it once. a duplicated
+-- (account_id, delete_at) already there fails it, and has to be cleaned first.
+
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT 1
+                   FROM pg_constraint
+                   WHERE conname = 'user_account_id_delete_at'
+                     AND conrelid = 'api."user"'::regclass) THEN
+        ALTER TABLE api."user"
+            ADD CONSTRAINT user_account_id_delete_at UNIQUE (account_id, delete_at);
+    END IF;
+END
+$$;
@@ -1,27 +1,29 @@
-use lib::cache::redis_ops::RedisOps;
+use lib::cache::redis_ops::{RedisOps, RedisPool};
 use tokio::sync::OnceCell;
 
 use crate::config::config;
 
-/// use singleton instance by it's all clones to share connection between Tasks.
+/// shared pool of connections between Tasks, broken connections are replaced on borrowing.
 #[allow(unused)]
-pub(crate) static REDIS_OPS: OnceCell<RedisOps> = OnceCell::const_new();
+pub(crate) static REDIS_POOL: OnceCell<RedisPool> = OnceCell::const_new();
 
 pub(super) async fn get_redis_ops() -> RedisOps {
-    (REDIS_OPS
+    REDIS_POOL
         .get_or_init(|| async {
             let passwords = if config().redis.passwords.is_empty() {
                 None
             } else {
                 Some(config().redis.passwords.clone())
             };
-            RedisOps::connect(config().redis.addresses.clone(), passwords)
-                .await
-                .unwrap()
+            RedisPool::new(
+                config().redis.addresses.clone(),
+                passwords,
+                config().redis.pool_size,
+            )
+            .await
+            .unwrap()
         })
-        .await)
-        .clone()
+        .await
+        .get()
+        .await
 }
-
-pub(crate) static USER_NODE_MAP: &str = "USER_NODE_MAP_";
-pub(crate) static NODE_ID: &str = "NODE_ID_SCHEDULER_";
@@ -4,6 +4,7 @@ use ahash::AHashMap;
 use anyhow::anyhow;
 use lib::{
     entity::{ReqwestMsg, ReqwestResourceID, ServerInfo, ServerStatus, ServerType},
+    error::ClientError,
     net::client::ClientConfigBuilder,
     Result,
 };
@@ -89,7 +90,10 @@ impl Client {
             );
             let resp = operator.call(auth_msg).await?;
             if resp.payload() != b"true" {
-                return Err(anyhow!("auth failed"));
+                return Err(anyhow!(ClientError::AuthRejected(format!(
+                    "scheduler node {}",
+                    addr
+                ))));
             }
         }
         Ok(())
@@ -38,7 +38,7 @@ impl ReqwestHandler for ServerAuth {
             .get_parameter::<ReqwestCaller>()
             .unwrap();
 
-        let server_info = ServerInfo::from(req.payload());
+        let server_info = ServerInfo::from_bytes(req.payload())?;
         info!("cluster server {} connected", server_info.id);
         cluster_set.insert(
             server_info
@@ -98,7 +98,7 @@ impl ReqwestHandler for ClientAuth {
             .get_parameter::<ReqwestCaller>()
             .unwrap();
 
-        let res_server_info = ServerInfo::from(req.payload());
+        let res_server_info = ServerInfo::from_bytes(req.payload())?;
         cluster_set.insert(
             res_server_info
                 .cluster_address
@@ -25,7 +25,7 @@ impl ReqwestHandler for NodeRegister {
             .get_parameter::<ClientCallerMap>()
             .unwrap();
 
-        let server_info = ServerInfo::from(req.payload());
+        let server_info = ServerInfo::from_bytes(req.payload())?;
         let mut bytes = vec![1u8];
         bytes.extend_from_slice(&server_info.to_bytes());
         let notify_msg =
@@ -52,7 +52,7 @@ impl ReqwestHandler for NodeUnregister {
             .unwrap()
             .get_parameter::<ClientCallerMap>()
             .unwrap();
-        let server_info = ServerInfo::from(req.payload());
+        let server_info = ServerInfo::from_bytes(req.payload())?;
         let notify_msg = ReqwestMsg::with_resource_id_payload(
             ReqwestResourceID::MessageNodeUnregister,
             &server_info.to_bytes(),
@@ -126,7 +126,7 @@ impl Server {
             .with_max_connections(config().server.max_connections)
             .with_connection_idle_timeout(config().transport.connection_idle_timeout)
             .with_max_bi_streams(config().transport.max_bi_streams);
-        let server_config = server_config_builder.build().unwrap();
+        let server_config = server_config_builder.build()?;
 
         let mut handler_map: AHashMap<ReqwestResourceID, Box<dyn ReqwestHandler>> = AHashMap::new();
         handler_map.insert(ReqwestResourceID::NodeAuth, Box::new(logic::ServerAuth {}));
@@ -2,6 +2,14 @@ use std::net::ToSocketAddrs;
 use std::{fs, net::SocketAddr, path::PathBuf, time::Duration};
 
 use anyhow::Context;
+use lib::{
+    util::{
+        env::{load_config_table, ENV_PREFIX},
+        parse_log_level,
+        time::set_clock_offset,
+    },
+    Result,
+};
 use tracing::Level;
 
 #[derive(serde::Deserialize, Debug)]
@@ -34,6 +42,9 @@ struct Server0 {
     cert_path: Option<String>,
     key_path: Option<String>,
     max_connections: Option<usize>,
+    clock_offset: Option<i64>,
+    heartbeat_ttl: Option<u64>,
+    user_node_ttl: Option<u64>,
 }
 
 #[derive(Debug)]
@@ -46,6 +57,12 @@ pub(crate) struct Server {
     pub(crate) cert: rustls::Certificate,
     pub(crate) key: rustls::PrivateKey,
     pub(crate) max_connections: usize,
+    /// in milliseconds, added to the local clock to correct its skew from ntp.
+    pub(crate) clock_offset: i64,
+    /// nodes sending heartbeats are not picked once silent for so long.
+    pub(crate) heartbeat_ttl: Duration,
+    /// a user keeps the node assigned while looked up within so long and the node is alive.
+    pub(crate) user_node_ttl: Duration,
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -66,12 +83,14 @@ pub(crate) struct Transport {
 struct Redis0 {
     addresses: Option<Vec<String>>,
     passwords: Option<Vec<String>>,
+    pool_size: Option<usize>,
 }
 
 #[derive(Debug)]
 pub(crate) struct Redis {
     pub(crate) addresses: Vec<SocketAddr>,
     pub(crate) passwords: Vec<String>,
+    pub(crate) pool_size: usize,
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -118,14 +137,7 @@ pub(crate) struct Rpc {
 
 impl Config {
     fn from_config0(config0: Config0) -> Config {
-        let log_level = match config0.log_level.unwrap_or("info".to_string()).as_ref() {
-            "trace" => Level::TRACE,
-            "debug" => Level::DEBUG,
-            "info" => Level::INFO,
-            "warn" => Level::WARN,
-            "error" => Level::ERROR,
-            _ => Level::INFO,
-        };
+        let log_level = parse_log_level(config0.log_level);
         Config {
             log_level,
             server: Server::from_server0(config0.server.unwrap()),
@@ -154,6 +166,9 @@ impl Server {
             cert: rustls::Certificate(cert),
             key: rustls::PrivateKey(key),
             max_connections: server0.max_connections.unwrap(),
+            clock_offset: server0.clock_offset.unwrap_or(0),
+            heartbeat_ttl: Duration::from_millis(server0.heartbeat_ttl.unwrap_or(15000)),
+            user_node_ttl: Duration::from_millis(server0.user_node_ttl.unwrap_or(1800000)),
         }
     }
 }
@@ -179,7 +194,11 @@ impl Redis {
                     .collect::<Vec<SocketAddr>>()[0],
             );
         }
-        Redis { addresses: addr, passwords: redis0.passwords.unwrap_or(vec![]) }
+        Redis {
+            addresses: addr,
+            passwords: redis0.passwords.unwrap_or(vec![]),
+            pool_size: redis0.pool_size.unwrap_or(4),
+        }
     }
 }
 
@@ -241,17 +260,33 @@ impl RpcAPI {
     }
 }
 
-pub(crate) fn load_config(config_path: &str) {
-    let toml_str = fs::read_to_string(config_path).unwrap();
-    let config0: Config0 = toml::from_str(&toml_str).unwrap();
-    let mut config = Config::from_config0(config0);
-    if let Ok(address) = std::env::var("CLUSTER_ADDRESS") {
-        config.server.cluster_address = address;
-    }
-    if let Ok(address) = std::env::var("SERVICE_ADDRESS") {
-        config.server.service_address = address;
-    }
+/// fields overridable by an env var named after them, e.g. `server.cluster_address` by
+/// `PRIM_SERVER_CLUSTER_ADDRESS`, see `apply_env_overrides`.
+pub(self) const ENV_KEYS: [&str; 10] = [
+    "log_level",
+    "server.cluster_address",
+    "server.service_address",
+    "server.domain",
+    "redis.addresses[]",
+    "redis.passwords[]",
+    "cluster.addresses[]",
+    "rpc.address",
+    "rpc.api.address",
+    "rpc.api.domain",
+];
+
+/// env > file > default.
+pub(crate) fn load_config(config_path: &str) -> Result<()> {
+    let table = load_config_table(config_path, ENV_PREFIX, &ENV_KEYS, |name| {
+        std::env::var(name).ok()
+    })?;
+    let config0: Config0 = toml::Value::Table(table)
+        .try_into()
+        .with_context(|| format!("parse config file {} failed.", config_path))?;
+    let config = Config::from_config0(config0);
+    set_clock_offset(config.server.clock_offset);
     unsafe { CONFIG.replace(config) };
+    Ok(())
 }
 
 pub(self) static mut CONFIG: Option<Config> = None;
@@ -259,3 +294,29 @@ pub(self) static mut CONFIG: Option<Config> = None;
 pub(crate) fn config() -> &'static Config {
     unsafe { CONFIG.as_ref().unwrap() }
 }
+
+/// load `config-example.toml` for tests, the cert files it points to are replaced by placeholders.
+#[cfg(test)]
+pub(crate) fn load_test_config() {
+    static LOADED: std::sync::Once = std::sync::Once::new();
+    LOADED.call_once(|| {
+        let dir = std::env::temp_dir().join(format!("prim-scheduler-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in [
+            "localhost-server.crt.der",
+            "localhost-server.key.der",
+            "localhost-server.crt",
+            "localhost-server.key",
+            "PrimRootCA.crt",
+        ] {
+            fs::write(dir.join(name), b"placeholder").unwrap();
+        }
+        let toml_str =
+            fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/config-example.toml"))
+                .unwrap()
+                .replace("<path>/prim/server/cert", dir.to_str().unwrap());
+        let path = dir.join("config.toml");
+        fs::write(&path, toml_str).unwrap();
+        load_config(path.to_str().unwrap()).unwrap();
+    });
+}
@@ -39,7 +39,7 @@ async fn main() -> Result<()> {
         Ok(config_path) => config_path,
         Err(_) => opt.config,
     };
-    load_config(&config_path);
+    load_config(&config_path)?;
     util::load_my_id(my_id).await?;
     tracing_subscriber::fmt()
         .event_format(
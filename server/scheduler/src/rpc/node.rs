@@ -1,7 +1,10 @@
+use std::time::Instant;
+
 use async_trait::async_trait;
 use base64::Engine;
 use lib::{
-    entity::{Msg, ReqwestMsg, ReqwestResourceID, Type},
+    cache::keys::user_node_map_key,
+    entity::{server::pick_sticky, Msg, ReqwestMsg, ReqwestResourceID, Type},
     Result,
 };
 
@@ -18,15 +21,18 @@ use super::{
         scheduler_server::{Scheduler, SchedulerServer},
         AllGroupNodeListReq, AllGroupNodeListResp, CurrNodeGroupIdUserListReq,
         CurrNodeGroupIdUserListResp, GroupUserListReq, MessageNodeAliveReq, MessageNodeAliveResp,
-        PushMsgReq, PushMsgResp, SeqnumAllNodeReq, SeqnumAllNodeResp, SeqnumNodeAddressReq,
-        SeqnumNodeAddressResp, SeqnumNodeUserSelectReq, SeqnumNodeUserSelectResp, WhichNodeReq,
-        WhichNodeResp,
+        PushConfigReq, PushConfigResp, PushMsgReq, PushMsgResp, SeqnumAllNodeReq,
+        SeqnumAllNodeResp, SeqnumNodeAddressReq, SeqnumNodeAddressResp, SeqnumNodeUserSelectReq,
+        SeqnumNodeUserSelectResp, WhichNodeReq, WhichNodeResp,
     },
 };
 use crate::{
-    cache::{get_redis_ops, USER_NODE_MAP},
+    cache::get_redis_ops,
     config::config,
-    service::{get_client_caller_map, get_message_node_set, get_server_info_map},
+    service::{
+        get_client_caller_map, get_message_node_set, get_server_info_map,
+        handler::message::push_config,
+    },
 };
 use crate::{
     rpc::node_proto::{WhichToConnectReq, WhichToConnectResp},
@@ -100,7 +106,7 @@ impl Scheduler for RpcServer {
         };
         let mut list = vec![];
         for user_id in user_list.iter() {
-            let key = format!("{}{}", USER_NODE_MAP, user_id);
+            let key = user_node_map_key(*user_id);
             let node_id = match redis_ops.get::<u32>(&key).await {
                 Ok(node_id) => node_id,
                 // todo: if user not in redis, we should add it.
@@ -108,9 +114,8 @@ impl Scheduler for RpcServer {
                     let node_id = self
                         .which_node(Request::new(WhichNodeReq { user_id: *user_id }))
                         .await?;
-                    let node_id = node_id.into_inner().node_id;
-                    _ = redis_ops.set(&key, &node_id).await;
-                    node_id
+                    // recorded with its ttl by `which_node` already.
+                    node_id.into_inner().node_id
                 }
             };
             if node_id == request_inner.node_id {
@@ -127,31 +132,33 @@ impl Scheduler for RpcServer {
         request: Request<WhichNodeReq>,
     ) -> std::result::Result<Response<WhichNodeResp>, Status> {
         let user_id = request.into_inner().user_id;
-        let key = format!("{}{}", USER_NODE_MAP, user_id);
+        let key = user_node_map_key(user_id);
         // todo unsafecell optimization.
         let mut redis_ops = get_redis_ops().await;
         let set = get_message_node_set().0;
-        let value: Result<u32> = redis_ops.get(&key).await;
-        let node_id = match value {
-            Ok(value) => value,
-            Err(_) => {
-                let node_size = set.len();
-                if node_size == 0 {
-                    return Err(Status::internal("message cluster all crashed."));
-                }
-                let index = user_id % (node_size as u64);
-                let node_id = *match set.iter().nth(index as usize) {
-                    Some(v) => v,
-                    None => return Err(Status::internal("try again.")),
-                };
-                match redis_ops.set(&key, &node_id).await {
-                    Ok(_) => node_id as u32,
-                    Err(_) => {
-                        return Err(Status::internal("redis set error"));
-                    }
-                }
-            }
+        if set.is_empty() {
+            return Err(Status::internal("message cluster all crashed."));
+        }
+        // users are never directed to the nodes gone silent.
+        let candidates = get_server_info_map().healthy(
+            set.iter().map(|node_id| *node_id),
+            config().server.heartbeat_ttl,
+            Instant::now(),
+        );
+        // the assignment lapsed or never made reads as none.
+        let assigned: Option<u32> = redis_ops.get(&key).await.ok();
+        let node_id = match pick_sticky(assigned, &candidates, user_id) {
+            Some(node_id) => node_id,
+            None => return Err(Status::internal("no healthy message node.")),
         };
+        // every lookup refreshes the ttl, so an active user stays where it is.
+        if redis_ops
+            .set_exp(&key, &node_id, config().server.user_node_ttl)
+            .await
+            .is_err()
+        {
+            return Err(Status::internal("redis set error"));
+        }
         Ok(Response::new(WhichNodeResp { node_id }))
     }
 
@@ -304,4 +311,133 @@ impl Scheduler for RpcServer {
     ) -> std::result::Result<Response<MessageNodeAliveResp>, Status> {
         todo!("message node alive")
     }
+
+    /// push a config delta given as a json object to every message node, see `push_config`.
+    async fn push_config(
+        &self,
+        request: Request<PushConfigReq>,
+    ) -> std::result::Result<Response<PushConfigResp>, Status> {
+        let delta = match serde_json::from_str::<serde_json::Value>(&request.into_inner().delta) {
+            Ok(delta) if delta.is_object() => delta,
+            _ => return Err(Status::invalid_argument("delta should be a json object")),
+        };
+        let res_list = match push_config(&delta).await {
+            Ok(res_list) => res_list,
+            Err(e) => return Err(Status::internal(e.to_string())),
+        };
+        let mut node_id_list = Vec::with_capacity(res_list.len());
+        let mut result_list = Vec::with_capacity(res_list.len());
+        for (node_id, res) in res_list {
+            node_id_list.push(node_id);
+            result_list.push(res.to_string());
+        }
+        Ok(Response::new(PushConfigResp {
+            node_id_list,
+            result_list,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use async_trait::async_trait;
+    use lib::{
+        entity::{ReqwestMsg, ReqwestResourceID, ServerInfo, ServerType},
+        Result, MESSAGE_NODE_ID_BEGINNING,
+    };
+    use lib_net_tokio::net::{
+        client::ClientReqwest,
+        mock::config_pair,
+        server::{ReqwestCaller, ServerReqwest},
+        NewReqwestConnectionHandler, ReqwestHandlerGenerator,
+    };
+    use tokio::sync::mpsc;
+    use tonic::{Code, Request};
+
+    use super::{
+        super::node_proto::{scheduler_server::Scheduler, PushConfigReq},
+        RpcServer,
+    };
+    use crate::{config::load_test_config, service::server::handler_generator};
+
+    /// answers a config push like a message node, with the keys of the delta as applied.
+    struct FakeMessageNode;
+
+    #[async_trait]
+    impl NewReqwestConnectionHandler for FakeMessageNode {
+        async fn handle(
+            &mut self,
+            msg_operators: (mpsc::Sender<ReqwestMsg>, mpsc::Receiver<ReqwestMsg>),
+        ) -> Result<()> {
+            let (send, mut recv) = msg_operators;
+            while let Some(req) = recv.recv().await {
+                let payload = match req.resource_id() {
+                    ReqwestResourceID::ConfigPush => {
+                        let delta: serde_json::Map<String, serde_json::Value> =
+                            serde_json::from_slice(req.payload())?;
+                        let applied = delta.keys().cloned().collect::<Vec<String>>();
+                        serde_json::to_vec(&serde_json::json!({ "applied": applied }))?
+                    }
+                    _ => vec![],
+                };
+                let mut resp = ReqwestMsg::with_resource_id_payload(req.resource_id(), &payload);
+                resp.set_req_id(req.req_id());
+                _ = send.send(resp).await;
+            }
+            Ok(())
+        }
+
+        fn set_reqwest_caller(&mut self, _reqwest_caller: ReqwestCaller) {}
+    }
+
+    #[tokio::test]
+    async fn test_push_config() {
+        load_test_config();
+        let (server_config, client_config) = config_pair();
+        let mut server = ServerReqwest::new(server_config.build().unwrap(), Duration::from_secs(3));
+        let generator = Arc::new(handler_generator());
+        tokio::spawn(async move { server.run(generator).await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut client = ClientReqwest::new(client_config.build().unwrap(), Duration::from_secs(3));
+        let generator: ReqwestHandlerGenerator = Box::new(|| Box::new(FakeMessageNode));
+        let operator = client.build(Arc::new(generator)).await.unwrap();
+        let node_info = ServerInfo {
+            id: MESSAGE_NODE_ID_BEGINNING + 7,
+            typ: ServerType::SchedulerClient,
+            ..Default::default()
+        };
+        operator
+            .call(ReqwestMsg::with_resource_id_payload(
+                ReqwestResourceID::NodeAuth,
+                &node_info.to_bytes(),
+            ))
+            .await
+            .unwrap();
+
+        let resp = RpcServer {}
+            .push_config(Request::new(PushConfigReq {
+                delta: r#"{"quota.rate": 5.0}"#.to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let index = resp
+            .node_id_list
+            .iter()
+            .position(|id| *id == node_info.id)
+            .unwrap();
+        let res: serde_json::Value = serde_json::from_str(&resp.result_list[index]).unwrap();
+        assert_eq!(res, serde_json::json!({ "applied": ["quota.rate"] }));
+
+        let e = RpcServer {}
+            .push_config(Request::new(PushConfigReq {
+                delta: "[1, 2]".to_string(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(e.code(), Code::InvalidArgument);
+    }
 }
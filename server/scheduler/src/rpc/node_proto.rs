@@ -130,6 +130,20 @@ pub struct MessageNodeAliveResp {
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PushConfigReq {
+    #[prost(string, tag = "1")]
+    pub delta: ::prost::alloc::string::String,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PushConfigResp {
+    #[prost(uint32, repeated, tag = "1")]
+    pub node_id_list: ::prost::alloc::vec::Vec<u32>,
+    #[prost(string, repeated, tag = "2")]
+    pub result_list: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
 pub struct GroupUserListReq {
     #[prost(uint64, tag = "1")]
     pub group_id: u64,
@@ -446,6 +460,28 @@ pub mod scheduler_client {
                 .insert(GrpcMethod::new("node_proto.Scheduler", "MessageNodeAlive"));
             self.inner.unary(req, path, codec).await
         }
+        pub async fn push_config(
+            &mut self,
+            request: impl tonic::IntoRequest<super::PushConfigReq>,
+        ) -> std::result::Result<tonic::Response<super::PushConfigResp>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/node_proto.Scheduler/PushConfig",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("node_proto.Scheduler", "PushConfig"));
+            self.inner.unary(req, path, codec).await
+        }
     }
 }
 /// Generated client implementations.
@@ -624,6 +660,10 @@ pub mod scheduler_server {
             tonic::Response<super::MessageNodeAliveResp>,
             tonic::Status,
         >;
+        async fn push_config(
+            &self,
+            request: tonic::Request<super::PushConfigReq>,
+        ) -> std::result::Result<tonic::Response<super::PushConfigResp>, tonic::Status>;
     }
     #[derive(Debug)]
     pub struct SchedulerServer<T: Scheduler> {
@@ -1110,6 +1150,52 @@ pub mod scheduler_server {
                     };
                     Box::pin(fut)
                 }
+                "/node_proto.Scheduler/PushConfig" => {
+                    #[allow(non_camel_case_types)]
+                    struct PushConfigSvc<T: Scheduler>(pub Arc<T>);
+                    impl<
+                        T: Scheduler,
+                    > tonic::server::UnaryService<super::PushConfigReq>
+                    for PushConfigSvc<T> {
+                        type Response = super::PushConfigResp;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::PushConfigReq>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                (*inner).push_config(request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = PushConfigSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
                 _ => {
                     Box::pin(async move {
                         Ok(
@@ -54,7 +54,7 @@ impl ReqwestHandler for ServerAuth {
             .unwrap()
             .get_parameter::<ReqwestCaller>();
 
-        let server_info = ServerInfo::from(req.payload());
+        let server_info = ServerInfo::from_bytes(req.payload())?;
         if server_info.id >= MESSAGE_NODE_ID_BEGINNING
             && server_info.id < SCHEDULER_NODE_ID_BEGINNING
         {
@@ -1,15 +1,18 @@
 use anyhow::anyhow;
 use async_trait::async_trait;
 use lib::{
-    entity::{ReqwestMsg, ReqwestResourceID, ServerInfo},
+    entity::{ReqwestMsg, ReqwestResourceID, ServerHeartbeat, ServerInfo},
     net::InnerStates,
     Result,
 };
 use lib_net_tokio::net::ReqwestHandler;
+use tracing::error;
 
 use crate::{
     cluster::ClusterCallerMap,
-    service::{ClientCallerMap, MessageNodeSet, ServerInfoMap},
+    service::{
+        get_client_caller_map, get_message_node_set, ClientCallerMap, MessageNodeSet, ServerInfoMap,
+    },
 };
 
 pub(crate) struct NodeRegister {}
@@ -46,7 +49,7 @@ impl ReqwestHandler for NodeRegister {
             .get_parameter::<MessageNodeSet>()
             .unwrap();
 
-        let server_info = ServerInfo::from(req.payload());
+        let server_info = ServerInfo::from_bytes(req.payload())?;
         let self_sender = client_map.get(server_info.id);
         if self_sender.is_none() {
             return Err(anyhow!("self sender not found"));
@@ -88,7 +91,7 @@ pub(crate) struct NodeUnregister {}
 #[async_trait]
 impl ReqwestHandler for NodeUnregister {
     async fn run(&self, req: &mut ReqwestMsg, states: &mut InnerStates) -> Result<ReqwestMsg> {
-        let server_info = ServerInfo::from(req.payload());
+        let server_info = ServerInfo::from_bytes(req.payload())?;
         let client_map = states
             .get("generic_map")
             .unwrap()
@@ -141,3 +144,197 @@ impl ReqwestHandler for NodeUnregister {
         Ok(ReqwestMsg::default())
     }
 }
+
+pub(crate) struct NodeHeartbeat {}
+
+#[async_trait]
+impl ReqwestHandler for NodeHeartbeat {
+    async fn run(&self, req: &mut ReqwestMsg, states: &mut InnerStates) -> Result<ReqwestMsg> {
+        let server_info_map = states
+            .get("generic_map")
+            .unwrap()
+            .as_generic_parameter_map()
+            .unwrap()
+            .get_parameter::<ServerInfoMap>()
+            .unwrap();
+        let heartbeat = ServerHeartbeat::from_bytes(req.payload())?;
+        if server_info_map.apply_heartbeat(&heartbeat) {
+            Ok(ReqwestMsg::default())
+        } else {
+            // scheduler may have restarted and lost the registration, ask for a full one.
+            Ok(ReqwestMsg::with_resource_id_payload(
+                ReqwestResourceID::MessageNodeRegister,
+                b"",
+            ))
+        }
+    }
+}
+
+/// push a delta of the runtime adjustable settings to every message node, return the answer of
+/// each node, see `ReqwestResourceID::ConfigPush` for the format.
+///
+/// a node failed to be called is logged and left out, the delta is applied as a whole or not at
+/// all on each node, but not across nodes. called by the `PushConfig` rpc.
+pub(crate) async fn push_config(
+    delta: &serde_json::Value,
+) -> Result<Vec<(u32, serde_json::Value)>> {
+    let payload = serde_json::to_vec(delta)?;
+    if payload.len() > u16::MAX as usize {
+        return Err(anyhow!("config delta too long"));
+    }
+    let req = ReqwestMsg::with_resource_id_payload(ReqwestResourceID::ConfigPush, &payload);
+    let client_map = get_client_caller_map();
+    let node_list = get_message_node_set()
+        .0
+        .iter()
+        .map(|entry| *entry.key())
+        .collect::<Vec<u32>>();
+    let mut res_list = Vec::with_capacity(node_list.len());
+    for node_id in node_list {
+        let res = match client_map.get(node_id) {
+            Some(caller) => caller.call(req.clone()).await,
+            None => continue,
+        };
+        match res.and_then(|res| Ok(serde_json::from_slice(res.payload())?)) {
+            Ok(res) => res_list.push((node_id, res)),
+            Err(e) => error!("push config to {} failed: {}", node_id, e),
+        }
+    }
+    Ok(res_list)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use ahash::AHashMap;
+    use dashmap::DashMap;
+    use lib::{
+        entity::{
+            server::pick_sticky, ReqwestMsg, ReqwestResourceID, ServerInfo, ServerLoad,
+            ServerStatus, ServerType,
+        },
+        net::{GenericParameterMap, InnerStates, InnerStatesValue},
+    };
+    use lib_net_tokio::net::ReqwestHandler;
+
+    use super::NodeHeartbeat;
+    use crate::service::ServerInfoMap;
+
+    fn states(server_info_map: &ServerInfoMap) -> InnerStates {
+        let mut generic_map = GenericParameterMap(AHashMap::new());
+        generic_map.put_parameter(ServerInfoMap(
+            server_info_map.0.clone(),
+            server_info_map.1.clone(),
+        ));
+        let mut states = InnerStates::new();
+        states.insert(
+            "generic_map".to_owned(),
+            InnerStatesValue::GenericParameterMap(generic_map),
+        );
+        states
+    }
+
+    fn message_node(id: u32) -> ServerInfo {
+        ServerInfo {
+            id,
+            service_address: format!("127.0.0.1:1112{}", id),
+            cluster_address: Some(format!("127.0.0.1:1122{}", id)),
+            status: ServerStatus::Online,
+            typ: ServerType::MessageCluster,
+            ..ServerInfo::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_node_heartbeat() {
+        let server_info_map = ServerInfoMap(Arc::new(DashMap::new()), Arc::new(DashMap::new()));
+        let mut states = states(&server_info_map);
+        let server_info = message_node(1);
+        let mut heartbeat = ReqwestMsg::with_resource_id_payload(
+            ReqwestResourceID::MessageNodeHeartbeat,
+            &server_info.heartbeat().to_bytes(),
+        );
+        let ttl = Duration::from_secs(15);
+
+        // a scheduler restarted in between asks for the registration again.
+        let resp = NodeHeartbeat {}
+            .run(&mut heartbeat, &mut states)
+            .await
+            .unwrap();
+        assert_eq!(resp.resource_id(), ReqwestResourceID::MessageNodeRegister);
+
+        server_info_map.insert(server_info.id, server_info.clone());
+        let healthy = server_info_map.healthy([1].into_iter(), ttl, Instant::now());
+        assert_eq!(healthy, vec![server_info.clone()]);
+
+        // gone silent for longer than ttl.
+        server_info_map
+            .1
+            .insert(1, Instant::now().checked_sub(ttl * 2).unwrap());
+        assert!(server_info_map
+            .healthy([1].into_iter(), ttl, Instant::now())
+            .is_empty());
+
+        let resp = NodeHeartbeat {}
+            .run(&mut heartbeat, &mut states)
+            .await
+            .unwrap();
+        assert_eq!(resp.resource_id(), ReqwestResourceID::Noop);
+        let healthy = server_info_map.healthy([1].into_iter(), ttl, Instant::now());
+        assert_eq!(healthy, vec![server_info]);
+    }
+
+    #[tokio::test]
+    async fn test_node_heartbeat_load() {
+        let server_info_map = ServerInfoMap(Arc::new(DashMap::new()), Arc::new(DashMap::new()));
+        let mut states = states(&server_info_map);
+        for id in [1, 2] {
+            server_info_map.insert(id, message_node(id));
+        }
+        let ttl = Duration::from_secs(15);
+        let load = |connections| ServerLoad {
+            connections,
+            ..ServerLoad::default()
+        };
+        for (id, connections) in [(1, 100), (2, 10), (1, 1), (2, 50)] {
+            let heartbeat = ServerInfo {
+                load: Some(load(connections)),
+                ..message_node(id)
+            }
+            .heartbeat();
+            let mut req = ReqwestMsg::with_resource_id_payload(
+                ReqwestResourceID::MessageNodeHeartbeat,
+                &heartbeat.to_bytes(),
+            );
+            NodeHeartbeat {}.run(&mut req, &mut states).await.unwrap();
+            if id == 2 {
+                // new users go to the node reported least loaded by the last heartbeats.
+                let candidates = server_info_map.healthy([1, 2].into_iter(), ttl, Instant::now());
+                let expected = if connections == 10 { 2 } else { 1 };
+                assert_eq!(pick_sticky(None, &candidates, 0), Some(expected));
+            }
+        }
+        assert_eq!(server_info_map.get(1).unwrap().load, Some(load(1)));
+    }
+
+    #[test]
+    fn test_sticky_to_silent_node() {
+        let server_info_map = ServerInfoMap(Arc::new(DashMap::new()), Arc::new(DashMap::new()));
+        for id in [1, 2] {
+            server_info_map.insert(id, message_node(id));
+        }
+        let ttl = Duration::from_secs(15);
+        let candidates = server_info_map.healthy([1, 2].into_iter(), ttl, Instant::now());
+        assert_eq!(pick_sticky(Some(1), &candidates, 0), Some(1));
+        // the assigned node stops sending heartbeats, its users move to the living one.
+        server_info_map
+            .1
+            .insert(1, Instant::now().checked_sub(ttl * 2).unwrap());
+        let candidates = server_info_map.healthy([1, 2].into_iter(), ttl, Instant::now());
+        assert_eq!(pick_sticky(Some(1), &candidates, 0), Some(2));
+    }
+}
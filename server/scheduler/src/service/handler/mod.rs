@@ -1,4 +1,4 @@
 pub(super) mod logic;
-pub(super) mod message;
+pub(crate) mod message;
 pub(super) mod seqnum;
 pub(super) mod msgprocessor;
@@ -31,7 +31,7 @@ impl ReqwestHandler for NodeRegister {
             .get_parameter::<ClusterCallerMap>()
             .unwrap();
 
-        let server_info = ServerInfo::from(req.payload());
+        let server_info = ServerInfo::from_bytes(req.payload())?;
         server_info_map.insert(server_info.id, server_info);
         for entry in cluster_map.0.iter() {
             entry.value().call(req.clone()).await?;
@@ -45,7 +45,7 @@ pub(crate) struct NodeUnregister {}
 #[async_trait]
 impl ReqwestHandler for NodeUnregister {
     async fn run(&self, req: &mut ReqwestMsg, states: &mut InnerStates) -> Result<ReqwestMsg> {
-        let server_info = ServerInfo::from(req.payload());
+        let server_info = ServerInfo::from_bytes(req.payload())?;
         let client_map = states
             .get("generic_map")
             .unwrap()
@@ -1,6 +1,6 @@
 use async_trait::async_trait;
 use lib::{
-    entity::{ReqwestMsg, ServerInfo},
+    entity::{ReqwestMsg, ReqwestResourceID, ServerHeartbeat, ServerInfo},
     net::InnerStates,
     Result,
 };
@@ -45,7 +45,7 @@ impl ReqwestHandler for NodeRegister {
             .get_parameter::<SeqnumNodeSet>()
             .unwrap();
 
-        let server_info = ServerInfo::from(req.payload());
+        let server_info = ServerInfo::from_bytes(req.payload())?;
         server_info_map.insert(server_info.id, server_info);
 
         // code blow used for notify other seqnum nodes.
@@ -88,7 +88,7 @@ pub(crate) struct NodeUnregister {}
 #[async_trait]
 impl ReqwestHandler for NodeUnregister {
     async fn run(&self, req: &mut ReqwestMsg, states: &mut InnerStates) -> Result<ReqwestMsg> {
-        let server_info = ServerInfo::from(req.payload());
+        let server_info = ServerInfo::from_bytes(req.payload())?;
         let client_map = states
             .get("generic_map")
             .unwrap()
@@ -139,3 +139,28 @@ impl ReqwestHandler for NodeUnregister {
         Ok(ReqwestMsg::default())
     }
 }
+
+pub(crate) struct NodeHeartbeat {}
+
+#[async_trait]
+impl ReqwestHandler for NodeHeartbeat {
+    async fn run(&self, req: &mut ReqwestMsg, states: &mut InnerStates) -> Result<ReqwestMsg> {
+        let server_info_map = states
+            .get("generic_map")
+            .unwrap()
+            .as_generic_parameter_map()
+            .unwrap()
+            .get_parameter::<ServerInfoMap>()
+            .unwrap();
+        let heartbeat = ServerHeartbeat::from_bytes(req.payload())?;
+        if server_info_map.apply_heartbeat(&heartbeat) {
+            Ok(ReqwestMsg::default())
+        } else {
+            // scheduler may have restarted and lost the registration, ask for a full one.
+            Ok(ReqwestMsg::with_resource_id_payload(
+                ReqwestResourceID::SeqnumNodeRegister,
+                b"",
+            ))
+        }
+    }
+}
@@ -1,23 +1,35 @@
 pub(crate) mod handler;
-mod server;
+pub(crate) mod server;
 
-use std::sync::Arc;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use dashmap::{mapref::one::Ref, DashMap, DashSet};
 use lazy_static::lazy_static;
-use lib::{Result, entity::ServerInfo, net::GenericParameter};
+use lib::{
+    entity::{server::retain_healthy, ServerHeartbeat, ServerInfo},
+    net::GenericParameter,
+    Result,
+};
 use lib_net_tokio::net::server::ReqwestCaller;
 
 /// we choose to split set and integration map to get minimum split operation.
 pub(crate) struct ClientCallerMap(pub(crate) Arc<DashMap<u32, ReqwestCaller>>);
-pub(crate) struct ServerInfoMap(pub(crate) Arc<DashMap<u32, ServerInfo>>);
+/// registrations, and when each node was last heard from, by registering or a heartbeat.
+pub(crate) struct ServerInfoMap(
+    pub(crate) Arc<DashMap<u32, ServerInfo>>,
+    pub(crate) Arc<DashMap<u32, Instant>>,
+);
 pub(crate) struct MessageNodeSet(pub(crate) Arc<DashSet<u32>>);
 pub(crate) struct SeqnumNodeSet(pub(crate) Arc<DashSet<u32>>);
 pub(crate) struct MsgprocessorSet(pub(crate) Arc<DashSet<u32>>);
 
 lazy_static! {
     static ref CLIENT_CONNECTION_MAP: ClientCallerMap = ClientCallerMap(Arc::new(DashMap::new()));
-    static ref SERVER_INFO_MAP: ServerInfoMap = ServerInfoMap(Arc::new(DashMap::new()));
+    static ref SERVER_INFO_MAP: ServerInfoMap =
+        ServerInfoMap(Arc::new(DashMap::new()), Arc::new(DashMap::new()));
     static ref MESSAGE_NODE_SET: MessageNodeSet = MessageNodeSet(Arc::new(DashSet::new()));
     static ref SEQNUM_NODE_SET: SeqnumNodeSet = SeqnumNodeSet(Arc::new(DashSet::new()));
     static ref MSGPROCESSOR_SET: MsgprocessorSet = MsgprocessorSet(Arc::new(DashSet::new()));
@@ -28,7 +40,7 @@ pub(crate) fn get_client_caller_map() -> ClientCallerMap {
 }
 
 pub(crate) fn get_server_info_map() -> ServerInfoMap {
-    ServerInfoMap(SERVER_INFO_MAP.0.clone())
+    ServerInfoMap(SERVER_INFO_MAP.0.clone(), SERVER_INFO_MAP.1.clone())
 }
 
 pub(crate) fn get_message_node_set() -> MessageNodeSet {
@@ -114,10 +126,42 @@ impl ServerInfoMap {
 
     pub(crate) fn insert(&self, key: u32, value: ServerInfo) {
         self.0.insert(key, value);
+        self.1.insert(key, Instant::now());
     }
 
     pub(crate) fn remove(&self, key: u32) {
         self.0.remove(&key);
+        self.1.remove(&key);
+    }
+
+    /// merge the heartbeat into the registration, false if the node is not registered.
+    pub(crate) fn apply_heartbeat(&self, heartbeat: &ServerHeartbeat) -> bool {
+        match self.0.get_mut(&heartbeat.id) {
+            Some(mut server_info) => {
+                server_info.apply_heartbeat(heartbeat);
+                self.1.insert(heartbeat.id, Instant::now());
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn last_heartbeat(&self, key: u32) -> Option<Instant> {
+        self.1.get(&key).map(|instant| *instant)
+    }
+
+    /// the registrations of `ids` heard from within `ttl` at `now`.
+    pub(crate) fn healthy(
+        &self,
+        ids: impl Iterator<Item = u32>,
+        ttl: Duration,
+        now: Instant,
+    ) -> Vec<ServerInfo> {
+        let mut list = ids
+            .filter_map(|id| self.get(id).map(|server_info| server_info.clone()))
+            .collect::<Vec<ServerInfo>>();
+        retain_healthy(&mut list, |id| self.last_heartbeat(id), ttl, now);
+        list
     }
 }
 
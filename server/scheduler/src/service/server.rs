@@ -5,15 +5,17 @@ use ahash::AHashMap;
 use async_trait::async_trait;
 use lib::{
     entity::{ReqwestMsg, ReqwestResourceID, ServerInfo},
+    error::UnsupportedResource,
     net::{server::ServerConfigBuilder, GenericParameterMap, InnerStates, InnerStatesValue},
     Result, MESSAGE_NODE_ID_BEGINNING, SCHEDULER_NODE_ID_BEGINNING, SEQNUM_NODE_ID_BEGINNING, MSGPROCESSOR_ID_BEGINNING,
 };
 use lib_net_tokio::net::{
     server::{ReqwestCaller, ServerReqwest, ServerReqwestTcp},
-    NewReqwestConnectionHandler, ReqwestHandler, ReqwestHandlerGenerator, ReqwestHandlerMap,
+    NewReqwestConnectionHandler, ReqwestDispatcher, ReqwestHandler, ReqwestHandlerGenerator,
+    ReqwestHandlerMap,
 };
 use tokio::sync::mpsc;
-use tracing::error;
+use tracing::{error, warn};
 
 use super::{
     get_client_caller_map, get_message_node_set, get_seqnum_node_set, get_server_info_map,
@@ -21,15 +23,15 @@ use super::{
 };
 
 pub(super) struct ClientConnectionHandler {
-    handler_map: ReqwestHandlerMap,
+    dispatcher: ReqwestDispatcher,
     states: InnerStates,
     reqwest_caller: Option<ReqwestCaller>,
 }
 
 impl ClientConnectionHandler {
-    pub(self) fn new(handler_map: ReqwestHandlerMap) -> ClientConnectionHandler {
+    pub(self) fn new(dispatcher: ReqwestDispatcher) -> ClientConnectionHandler {
         ClientConnectionHandler {
-            handler_map,
+            dispatcher,
             states: AHashMap::new(),
             reqwest_caller: None,
         }
@@ -72,23 +74,22 @@ impl NewReqwestConnectionHandler for ClientConnectionHandler {
         loop {
             match recv.recv().await {
                 Some(mut req) => {
-                    let resource_id = req.resource_id();
-                    match self.handler_map.get(&resource_id) {
-                        Some(handler) => match handler.run(&mut req, &mut self.states).await {
-                            Ok(mut resp) => {
-                                resp.set_req_id(req.req_id());
-                                let _ = send.send(resp).await;
+                    let mut resp = match self.dispatcher.dispatch(&mut req, &mut self.states).await
+                    {
+                        Ok(resp) => resp,
+                        Err(e) => match e.downcast_ref::<UnsupportedResource>() {
+                            Some(unsupported) => {
+                                warn!("{}", unsupported);
+                                unsupported.to_reqwest()
                             }
-                            Err(e) => {
+                            None => {
                                 error!("handler run error: {}", e);
                                 continue;
                             }
                         },
-                        None => {
-                            error!("no handler for resource_id: {}", resource_id);
-                            continue;
-                        }
                     };
+                    resp.set_req_id(req.req_id());
+                    let _ = send.send(resp).await;
                 }
                 None => {
                     let node_id = self.states.get("node_id").unwrap().as_num().unwrap() as u32;
@@ -100,7 +101,7 @@ impl NewReqwestConnectionHandler for ClientConnectionHandler {
                             ReqwestResourceID::MessageNodeUnregister,
                             &server_info.to_bytes(),
                         );
-                        self.handler_map
+                        self.dispatcher
                             .get(&ReqwestResourceID::MessageNodeUnregister)
                             .unwrap()
                             .run(&mut req, &mut self.states)
@@ -110,7 +111,7 @@ impl NewReqwestConnectionHandler for ClientConnectionHandler {
                             ReqwestResourceID::SchedulerNodeUnregister,
                             &server_info.to_bytes(),
                         );
-                        self.handler_map
+                        self.dispatcher
                             .get(&ReqwestResourceID::SchedulerNodeUnregister)
                             .unwrap()
                             .run(&mut req, &mut self.states)
@@ -120,7 +121,7 @@ impl NewReqwestConnectionHandler for ClientConnectionHandler {
                             ReqwestResourceID::SeqnumNodeUnregister,
                             &server_info.to_bytes(),
                         );
-                        self.handler_map
+                        self.dispatcher
                             .get(&ReqwestResourceID::SeqnumNodeUnregister)
                             .unwrap()
                             .run(&mut req, &mut self.states)
@@ -130,7 +131,7 @@ impl NewReqwestConnectionHandler for ClientConnectionHandler {
                             ReqwestResourceID::SeqnumNodeUnregister,
                             &server_info.to_bytes(),
                         );
-                        self.handler_map
+                        self.dispatcher
                             .get(&ReqwestResourceID::MsgprocessorNodeUnregister)
                             .unwrap()
                             .run(&mut req, &mut self.states)
@@ -181,39 +182,9 @@ impl Server {
             .with_max_connections(config().server.max_connections)
             .with_connection_idle_timeout(config().transport.connection_idle_timeout)
             .with_max_bi_streams(config().transport.max_bi_streams);
-        let server_config = server_config_builder.build().unwrap();
+        let server_config = server_config_builder.build()?;
 
-        let mut handler_map: AHashMap<ReqwestResourceID, Box<dyn ReqwestHandler>> = AHashMap::new();
-        handler_map.insert(ReqwestResourceID::NodeAuth, Box::new(logic::ServerAuth {}));
-        handler_map.insert(
-            ReqwestResourceID::MessageNodeRegister,
-            Box::new(message::NodeRegister {}),
-        );
-        handler_map.insert(
-            ReqwestResourceID::MessageNodeUnregister,
-            Box::new(message::NodeUnregister {}),
-        );
-        handler_map.insert(
-            ReqwestResourceID::SeqnumNodeRegister,
-            Box::new(seqnum::NodeRegister {}),
-        );
-        handler_map.insert(
-            ReqwestResourceID::SeqnumNodeUnregister,
-            Box::new(seqnum::NodeUnregister {}),
-        );
-        handler_map.insert(
-            ReqwestResourceID::MsgprocessorNodeRegister,
-            Box::new(msgprocessor::NodeRegister {}),
-        );
-        handler_map.insert(
-            ReqwestResourceID::MsgprocessorNodeUnregister,
-            Box::new(msgprocessor::NodeUnregister {}),
-        );
-        let handler_map = ReqwestHandlerMap::new(handler_map);
-        let generator: ReqwestHandlerGenerator =
-            Box::new(move || -> Box<dyn NewReqwestConnectionHandler> {
-                Box::new(ClientConnectionHandler::new(handler_map.clone()))
-            });
+        let generator = handler_generator();
 
         let mut server = ServerReqwest::new(server_config.clone(), Duration::from_millis(3000));
         let mut tcp_server = ServerReqwestTcp::new(server_config);
@@ -228,3 +199,84 @@ impl Server {
         Ok(())
     }
 }
+
+/// every connection from the nodes is handled by the same dispatcher.
+pub(crate) fn handler_generator() -> ReqwestHandlerGenerator {
+    let dispatcher = dispatcher();
+    Box::new(move || -> Box<dyn NewReqwestConnectionHandler> {
+        Box::new(ClientConnectionHandler::new(dispatcher.clone()))
+    })
+}
+
+/// every resource id is either handled here or marked unsupported, so a newly added one can't be
+/// dropped silently.
+pub(self) fn dispatcher() -> ReqwestDispatcher {
+    let mut handler_map: AHashMap<ReqwestResourceID, Box<dyn ReqwestHandler>> = AHashMap::new();
+    handler_map.insert(ReqwestResourceID::NodeAuth, Box::new(logic::ServerAuth {}));
+    handler_map.insert(
+        ReqwestResourceID::MessageNodeRegister,
+        Box::new(message::NodeRegister {}),
+    );
+    handler_map.insert(
+        ReqwestResourceID::MessageNodeUnregister,
+        Box::new(message::NodeUnregister {}),
+    );
+    handler_map.insert(
+        ReqwestResourceID::MessageNodeHeartbeat,
+        Box::new(message::NodeHeartbeat {}),
+    );
+    handler_map.insert(
+        ReqwestResourceID::SeqnumNodeRegister,
+        Box::new(seqnum::NodeRegister {}),
+    );
+    handler_map.insert(
+        ReqwestResourceID::SeqnumNodeUnregister,
+        Box::new(seqnum::NodeUnregister {}),
+    );
+    handler_map.insert(
+        ReqwestResourceID::SeqnumNodeHeartbeat,
+        Box::new(seqnum::NodeHeartbeat {}),
+    );
+    handler_map.insert(
+        ReqwestResourceID::MsgprocessorNodeRegister,
+        Box::new(msgprocessor::NodeRegister {}),
+    );
+    handler_map.insert(
+        ReqwestResourceID::MsgprocessorNodeUnregister,
+        Box::new(msgprocessor::NodeUnregister {}),
+    );
+    ReqwestDispatcher::new(ReqwestHandlerMap::new(handler_map)).with_unsupported(&[
+        ReqwestResourceID::Noop,
+        ReqwestResourceID::Ping,
+        ReqwestResourceID::Pong,
+        ReqwestResourceID::Seqnum,
+        ReqwestResourceID::MessageForward,
+        ReqwestResourceID::InterruptSignal,
+        ReqwestResourceID::ConnectionTimeout,
+        // scheduler nodes talk to each other on the cluster server.
+        ReqwestResourceID::SchedulerNodeRegister,
+        ReqwestResourceID::SchedulerNodeUnregister,
+        ReqwestResourceID::MessageConfigHotReload,
+        ReqwestResourceID::AssignMQProcessor,
+        ReqwestResourceID::UnassignMQProcessor,
+        ReqwestResourceID::SeqnumAllocBlock,
+        ReqwestResourceID::DeadLetters,
+        ReqwestResourceID::ConfigPush,
+        ReqwestResourceID::Metrics,
+        ReqwestResourceID::SeqnumNotRegistered,
+        ReqwestResourceID::SeqnumExhausted,
+        ReqwestResourceID::SeqnumInvalidRequest,
+        ReqwestResourceID::Unsupported,
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::dispatcher;
+
+    #[test]
+    fn test_dispatcher_coverage() {
+        let unclaimed = dispatcher().unclaimed();
+        assert!(unclaimed.is_empty(), "unclaimed: {:?}", unclaimed);
+    }
+}
@@ -1,9 +1,9 @@
 use std::path::PathBuf;
 
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use lib::{Result, SCHEDULER_NODE_ID_BEGINNING};
+use lib::{cache::keys::SCHEDULER_NODE_ID, Result, SCHEDULER_NODE_ID_BEGINNING};
 
-use crate::cache::{get_redis_ops, NODE_ID};
+use crate::cache::get_redis_ops;
 
 pub(crate) static mut MY_ID: u32 = 0;
 
@@ -29,7 +29,7 @@ pub(crate) async fn load_my_id(my_id_preload: u32) -> Result<()> {
     } else {
         let mut file = tokio::fs::File::create(path).await?;
         let mut redis_ops = get_redis_ops().await;
-        my_id = redis_ops.lua1("local key = KEYS[1] local increment = tonumber(ARGV[1]) local value = redis.call('GET', key) if not value then redis.call('SET', key, increment) return increment else local newValue = redis.call('INCR', key) return newValue end", NODE_ID, SCHEDULER_NODE_ID_BEGINNING).await?;
+        my_id = redis_ops.lua1("local key = KEYS[1] local increment = tonumber(ARGV[1]) local value = redis.call('GET', key) if not value then redis.call('SET', key, increment) return increment else local newValue = redis.call('INCR', key) return newValue end", SCHEDULER_NODE_ID, SCHEDULER_NODE_ID_BEGINNING).await?;
         let s = my_id.to_string();
         file.write_all(s.as_bytes()).await?;
         file.flush().await?;
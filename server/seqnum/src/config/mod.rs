@@ -6,6 +6,13 @@ use std::{
 };
 
 use anyhow::Context;
+use lib::{
+    util::{
+        env::{load_config_table, ENV_PREFIX},
+        parse_log_level,
+    },
+    Result,
+};
 use tracing::Level;
 
 #[derive(serde::Deserialize, Debug)]
@@ -105,14 +112,7 @@ pub(crate) struct Scheduler {
 
 impl Config {
     fn from_config0(config0: Config0) -> Config {
-        let log_level = match config0.log_level.unwrap_or("info".to_string()).as_ref() {
-            "trace" => Level::TRACE,
-            "debug" => Level::DEBUG,
-            "info" => Level::INFO,
-            "warn" => Level::WARN,
-            "error" => Level::ERROR,
-            _ => Level::INFO,
-        };
+        let log_level = parse_log_level(config0.log_level);
         Config {
             log_level,
             server: Server::from_server0(config0.server.unwrap()),
@@ -189,17 +189,31 @@ impl Scheduler {
     }
 }
 
-pub(crate) fn load_config(config_path: &str) {
-    let toml_str = fs::read_to_string(config_path).unwrap();
-    let config0: Config0 = toml::from_str(&toml_str).unwrap();
-    let mut config = Config::from_config0(config0);
-    if let Ok(address) = std::env::var("CLUSTER_ADDRESS") {
-        config.server.cluster_address = address;
-    }
-    if let Ok(address) = std::env::var("SERVICE_ADDRESS") {
-        config.server.service_address = address;
-    }
+/// fields overridable by an env var named after them, e.g. `server.cluster_address` by
+/// `PRIM_SERVER_CLUSTER_ADDRESS`, see `apply_env_overrides`.
+pub(self) const ENV_KEYS: [&str; 9] = [
+    "log_level",
+    "server.cluster_address",
+    "server.service_address",
+    "server.domain",
+    "server.append_dir",
+    "redis.addresses[]",
+    "redis.passwords[]",
+    "scheduler.address",
+    "scheduler.domain",
+];
+
+/// env > file > default.
+pub(crate) fn load_config(config_path: &str) -> Result<()> {
+    let table = load_config_table(config_path, ENV_PREFIX, &ENV_KEYS, |name| {
+        std::env::var(name).ok()
+    })?;
+    let config0: Config0 = toml::Value::Table(table)
+        .try_into()
+        .with_context(|| format!("parse config file {} failed.", config_path))?;
+    let config = Config::from_config0(config0);
     unsafe { CONFIG.replace(config) };
+    Ok(())
 }
 
 pub(self) static mut CONFIG: Option<Config> = None;
@@ -1,15 +1,21 @@
-use std::{io::Read, sync::atomic::AtomicU64};
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
 
-use ahash::AHashMap;
-use lib::{joy, Result};
+use lib::{joy, util::load::start_load_updater, Result};
 use structopt::StructOpt;
 use sysinfo::SystemExt;
-use tracing::{error, info, warn};
+use tracing::{error, info};
 
 use crate::{
     config::{config, load_config},
-    service::{get_seqnum_map, handler::seqnum::SAVE_THRESHOLD},
-    util::{from_bytes, load_my_id},
+    service::{
+        get_seqnum_map,
+        handler::seqnum::{checkpoint_margin, flush_checkpoint, load_checkpoint},
+        STOP_SIGNAL,
+    },
+    util::load_my_id,
 };
 
 mod config;
@@ -17,6 +23,9 @@ mod scheduler;
 mod service;
 mod util;
 
+/// how long the accepted connections are given to answer in-flight requests on shutdown.
+pub(self) const DRAIN_GRACE: Duration = Duration::from_secs(1);
+
 #[derive(StructOpt, Debug)]
 #[structopt(name = "prim/seqnum")]
 pub(crate) struct Opt {
@@ -44,7 +53,7 @@ fn main() {
         Ok(config_path) => config_path,
         Err(_) => opt.config,
     };
-    load_config(&config_path);
+    load_config(&config_path).unwrap();
     tracing_subscriber::fmt()
         .event_format(
             tracing_subscriber::fmt::format()
@@ -71,12 +80,17 @@ fn main() {
         util::my_id(),
         config().server.service_address
     );
+    // seqnum serves message nodes only, the connection number makes little sense here.
+    start_load_updater(Duration::from_secs(5), || 0);
     info!("loading seqnum...");
     if let Err(e) = load() {
         error!("load seqnum error: {}", e);
     } else {
         info!("load seqnum done.");
     };
+    // SIGTERM and SIGINT stop every runtime, the main one then finishes the shutdown.
+    ctrlc::set_handler(|| STOP_SIGNAL.store(true, Ordering::Release))
+        .expect("set signal handler error");
     for _ in 0..sys.cpus().len() - 1 {
         std::thread::spawn(|| {
             #[cfg(target_os = "linux")]
@@ -108,9 +122,6 @@ fn main() {
                 .block_on(service::start());
         });
     }
-    // todo()! save seqnum to file
-    // ctrlc::set_handler(move || STOP_SIGNAL.store(true, std::sync::atomic::Ordering::Relaxed))
-    //     .expect("Error setting Ctrl-C handler");
     #[cfg(target_os = "linux")]
     {
         let build = monoio::RuntimeBuilder::<monoio::IoUringDriver>::new()
@@ -119,17 +130,7 @@ fn main() {
             .build();
         match build {
             Ok(mut rt) => {
-                _ = rt.block_on(async {
-                    if let Err(e) = scheduler::start().await {
-                        error!("scheduler error: {}", e.to_string());
-                        return Err(e);
-                    }
-                    if let Err(e) = service::start().await {
-                        error!("scheduler error: {}", e);
-                        return Err(e);
-                    }
-                    Ok(())
-                });
+                _ = rt.block_on(serve());
             }
             Err(e) => {
                 error!("could not build runtime with io_uring on linux: {}", e);
@@ -138,17 +139,7 @@ fn main() {
                     .enable_timer()
                     .build()
                     .unwrap()
-                    .block_on(async {
-                        if let Err(e) = scheduler::start().await {
-                            error!("scheduler error: {}", e.to_string());
-                            return Err(e);
-                        }
-                        if let Err(e) = service::start().await {
-                            error!("scheduler error: {}", e);
-                            return Err(e);
-                        }
-                        Ok(())
-                    });
+                    .block_on(serve());
             }
         };
     }
@@ -158,65 +149,44 @@ fn main() {
         .enable_timer()
         .build()
         .unwrap()
-        .block_on(async {
-            if let Err(e) = scheduler::start().await {
-                error!("scheduler error: {}", e.to_string());
-                return Err(e);
-            }
-            if let Err(e) = service::start().await {
-                error!("scheduler error: {}", e);
-                return Err(e);
-            }
-            Ok(())
-        });
+        .block_on(serve());
+}
+
+/// serve until a shutdown signal, then let the in-flight requests drain, deregister from
+/// scheduler and flush the checkpoint.
+pub(self) async fn serve() -> Result<()> {
+    let operator = match scheduler::start().await {
+        Ok(operator) => operator,
+        Err(e) => {
+            error!("scheduler error: {}", e);
+            return Err(e);
+        }
+    };
+    if let Err(e) = service::start().await {
+        error!("service error: {}", e);
+        return Err(e);
+    }
+    info!("shutting down, draining in-flight requests...");
+    monoio::time::sleep(DRAIN_GRACE).await;
+    if let Err(e) = scheduler::deregister(&operator).await {
+        error!("{}", e);
+    }
+    if let Err(e) = flush_checkpoint(&config().server.append_dir, &get_seqnum_map().0) {
+        error!("flush checkpoint error: {}", e);
+        return Err(e);
+    }
+    info!("prim seqnum[{}] shutdown.", util::my_id());
+    Ok(())
 }
 
 pub(self) fn load() -> Result<()> {
-    let mut map = AHashMap::new();
-    let mut buf = vec![0u8; 24];
     // monoio doesn't support async read_dir, but use std is acceptable because
     // this method is only called once at the beginning of the program.
-    _ = std::fs::create_dir_all(&config().server.append_dir);
-    let mut dir = std::fs::read_dir(&config().server.append_dir)?;
-    while let Some(entry) = dir.next() {
-        let file_name = entry?.file_name();
-        if let Some(file_name_str) = file_name.to_str() {
-            if file_name_str.starts_with("seqnum-") {
-                let mut file = std::fs::OpenOptions::new().read(true).open(&format!(
-                    "{}/{}",
-                    config().server.append_dir,
-                    file_name_str
-                ))?;
-                if file.metadata().unwrap().len() == 0 {
-                    continue;
-                }
-                loop {
-                    let res = file.read_exact(buf.as_mut_slice());
-                    if res.is_err() {
-                        warn!("read seqnum file error: {:?}", res);
-                        break;
-                    }
-                    let (key, seq_num) = from_bytes(&buf[..]);
-                    map.entry(key)
-                        .and_modify(|seqnum| {
-                            if *seqnum < seq_num {
-                                *seqnum = seq_num;
-                            }
-                        })
-                        .or_insert(seq_num);
-                }
-            }
-        }
-    }
+    let map = load_checkpoint(&config().server.append_dir)?;
+    let margin = checkpoint_margin(config().server.exactly_mode);
     let seqnum_map = get_seqnum_map();
-    if config().server.exactly_mode {
-        for (key, seqnum) in map {
-            seqnum_map.insert(key, AtomicU64::new(seqnum + 1));
-        }
-    } else {
-        for (key, seqnum) in map {
-            seqnum_map.insert(key, AtomicU64::new(seqnum + SAVE_THRESHOLD));
-        }
+    for (key, seqnum) in map {
+        seqnum_map.insert(key, AtomicU64::new(seqnum + margin));
     }
     Ok(())
 }
@@ -1,13 +1,16 @@
-use std::time::Duration;
+use std::{rc::Rc, sync::atomic::Ordering, time::Duration};
 
+use common::scheduler::retry_with;
 use lib::{
     entity::{ReqwestMsg, ReqwestResourceID, ServerInfo, ServerStatus, ServerType},
-    net::client::ClientConfigBuilder,
+    net::client::{ClientConfig, ClientConfigBuilder},
+    util::{backoff::Backoff, health, load::current_load},
     Result,
 };
 use lib_net_monoio::net::{client::ClientReqwestTcp, ReqwestOperatorManager};
+use tracing::{error, warn};
 
-use crate::{config::config, util::my_id};
+use crate::{config::config, service::STOP_SIGNAL, util::my_id};
 
 pub(super) struct Client {}
 
@@ -25,16 +28,68 @@ impl Client {
             .with_max_bi_streams(config().transport.max_bi_streams);
         let client_config = config_builder.build().unwrap();
 
-        let server_info = ServerInfo {
-            id: my_id(),
-            service_address: config().server.service_address.clone(),
-            cluster_address: Some(config().server.cluster_address.clone()),
-            connection_id: 0,
-            status: ServerStatus::Online,
-            typ: ServerType::SeqnumCluster,
-            load: None,
-        };
+        let server_info = server_info();
+
+        // scheduler may be down for a moment when the whole cluster starts.
+        retry_with(
+            "register to scheduler",
+            Backoff::default(),
+            || Self::register(client_config.clone(), &server_info),
+            monoio::time::sleep,
+        )
+        .await
+    }
+
+    /// keep the status and load on scheduler fresh, register again if scheduler has lost
+    /// this node, e.g. after a restart of scheduler.
+    pub(super) async fn heartbeat(operator: Rc<ReqwestOperatorManager>, interval: Duration) {
+        loop {
+            monoio::time::sleep(interval).await;
+            // never register again once deregistering may have started.
+            if STOP_SIGNAL.load(Ordering::Acquire) {
+                break;
+            }
+            let server_info = server_info();
+            let heartbeat_msg = ReqwestMsg::with_resource_id_payload(
+                ReqwestResourceID::SeqnumNodeHeartbeat,
+                &server_info.heartbeat().to_bytes(),
+            );
+            match operator.call(heartbeat_msg).await {
+                Ok(resp) if resp.resource_id() == ReqwestResourceID::SeqnumNodeRegister => {
+                    warn!("scheduler has lost the registration, register again.");
+                    let register_msg = ReqwestMsg::with_resource_id_payload(
+                        ReqwestResourceID::SeqnumNodeRegister,
+                        &server_info.to_bytes(),
+                    );
+                    if let Err(e) = operator.call(register_msg).await {
+                        error!("register to scheduler error: {}", e.to_string());
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => warn!("heartbeat to scheduler error: {}", e.to_string()),
+            }
+        }
+    }
 
+    /// tell scheduler that this node is leaving, so no more message nodes are routed to it.
+    pub(super) async fn deregister(operator: &ReqwestOperatorManager) -> Result<()> {
+        let unregister_msg = ReqwestMsg::with_resource_id_payload(
+            ReqwestResourceID::SeqnumNodeUnregister,
+            &server_info().to_bytes(),
+        );
+        match operator.call(unregister_msg).await {
+            Ok(_resp) => Ok(()),
+            Err(e) => Err(anyhow::anyhow!(
+                "deregister from scheduler error: {}",
+                e.to_string()
+            )),
+        }
+    }
+
+    pub(self) async fn register(
+        client_config: ClientConfig,
+        server_info: &ServerInfo,
+    ) -> Result<ReqwestOperatorManager> {
         let mut client = ClientReqwestTcp::new(client_config, Duration::from_millis(3000));
         let operator = match client.build().await {
             Ok(operator) => operator,
@@ -60,6 +115,7 @@ impl Client {
                 ))
             }
         };
+        health::set_scheduler_connected(true);
         let register_msg = ReqwestMsg::with_resource_id_payload(
             ReqwestResourceID::SeqnumNodeRegister,
             &server_info.to_bytes(),
@@ -73,7 +129,21 @@ impl Client {
                 ))
             }
         };
+        health::set_registered(true);
         Box::leak(Box::new(client));
         Ok(operator)
     }
 }
+
+/// what this node registers to scheduler as.
+pub(self) fn server_info() -> ServerInfo {
+    ServerInfo {
+        id: my_id(),
+        service_address: config().server.service_address.clone(),
+        cluster_address: Some(config().server.cluster_address.clone()),
+        connection_id: 0,
+        status: ServerStatus::Online,
+        typ: ServerType::SeqnumCluster,
+        load: current_load(),
+    }
+}
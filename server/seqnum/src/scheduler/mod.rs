@@ -1,8 +1,22 @@
+use std::{rc::Rc, time::Duration};
+
 use lib::Result;
 use lib_net_monoio::net::ReqwestOperatorManager;
 
 pub(self) mod client;
 
-pub(crate) async fn start() -> Result<ReqwestOperatorManager> {
-    client::Client::run().await
-}
\ No newline at end of file
+/// the same as the period the load is sampled at.
+pub(self) const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+pub(crate) async fn start() -> Result<Rc<ReqwestOperatorManager>> {
+    let operator = Rc::new(client::Client::run().await?);
+    monoio::spawn(client::Client::heartbeat(
+        operator.clone(),
+        HEARTBEAT_INTERVAL,
+    ));
+    Ok(operator)
+}
+
+pub(crate) async fn deregister(operator: &ReqwestOperatorManager) -> Result<()> {
+    client::Client::deregister(operator).await
+}
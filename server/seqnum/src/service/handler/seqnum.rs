@@ -1,5 +1,6 @@
 use std::{
     cell::UnsafeCell,
+    io::{Read, Write},
     os::unix::fs::OpenOptionsExt,
     path::PathBuf,
     sync::atomic::{AtomicU64, Ordering},
@@ -8,9 +9,11 @@ use std::{
 use ahash::AHashMap;
 use async_trait::async_trait;
 use byteorder::{BigEndian, ByteOrder};
-use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
 use lazy_static::lazy_static;
-use lib::{entity::ReqwestMsg, net::InnerStates, Result};
+use lib::{
+    entity::ReqwestMsg, error::SeqnumError, net::InnerStates, util::health::current_health, Result,
+};
 use lib_net_monoio::net::ReqwestHandler;
 use local_sync::oneshot;
 
@@ -22,6 +25,7 @@ use crate::{
 
 pub(self) const MAX_FILE_SIZE: u64 = 24 << 28;
 pub(crate) const SAVE_THRESHOLD: u64 = 0x4000;
+pub(crate) const MAX_BLOCK_SIZE: u32 = 0x10000;
 
 lazy_static! {
     static ref ID: AtomicU64 = AtomicU64::new(0);
@@ -110,11 +114,13 @@ impl SeqNum {
                     .await
                     .unwrap();
                 let mut map = AHashMap::new();
+                let mut offset = 0;
                 loop {
-                    (res, buf) = old_file.read_exact_at(buf, 0).await;
+                    (res, buf) = old_file.read_exact_at(buf, offset).await;
                     if res.is_err() {
                         break;
                     }
+                    offset += 24;
                     let (key, seq_num) = from_bytes(&buf[..]);
                     map.entry(key)
                         .and_modify(|seqnum| {
@@ -156,31 +162,155 @@ impl SeqNum {
     }
 }
 
+/// the high-water mark of every key recorded in the append files under `dir`.
+pub(crate) fn load_checkpoint(dir: &str) -> Result<AHashMap<u128, u64>> {
+    let mut map = AHashMap::new();
+    let mut buf = vec![0u8; 24];
+    _ = std::fs::create_dir_all(dir);
+    let mut dir_entry = std::fs::read_dir(dir)?;
+    while let Some(entry) = dir_entry.next() {
+        let file_name = entry?.file_name();
+        if let Some(file_name_str) = file_name.to_str() {
+            if file_name_str.starts_with("seqnum-") {
+                let mut file = std::fs::OpenOptions::new()
+                    .read(true)
+                    .open(&format!("{}/{}", dir, file_name_str))?;
+                if file.metadata()?.len() == 0 {
+                    continue;
+                }
+                loop {
+                    // a torn record at the tail is left by a crash during writing, just ignore it.
+                    if file.read_exact(buf.as_mut_slice()).is_err() {
+                        break;
+                    }
+                    let (key, seq_num) = from_bytes(&buf[..]);
+                    map.entry(key)
+                        .and_modify(|seqnum| {
+                            if *seqnum < seq_num {
+                                *seqnum = seq_num;
+                            }
+                        })
+                        .or_insert(seq_num);
+                }
+            }
+        }
+    }
+    Ok(map)
+}
+
+/// append the current value of every key to a new file under `dir`, so the checkpoint
+/// is exact after a graceful shutdown whatever the saving mode is.
+pub(crate) fn flush_checkpoint(dir: &str, map: &DashMap<u128, AtomicU64>) -> Result<()> {
+    _ = std::fs::create_dir_all(dir);
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(format!(
+            "{}/seqnum-{}",
+            dir,
+            ID.fetch_add(1, Ordering::AcqRel)
+        ))?;
+    let mut buf = vec![0u8; 24];
+    for entry in map.iter() {
+        as_bytes(
+            *entry.key(),
+            entry.value().load(Ordering::Acquire),
+            &mut buf[..],
+        );
+        file.write_all(&buf)?;
+    }
+    file.sync_all()?;
+    Ok(())
+}
+
+/// how far a loaded high-water mark should be bumped on startup.
+///
+/// without exactly mode a checkpoint is only appended every `SAVE_THRESHOLD` increments,
+/// so up to `SAVE_THRESHOLD - 1` numbers may have been issued after the last one.
+pub(crate) fn checkpoint_margin(exactly_mode: bool) -> u64 {
+    if exactly_mode {
+        1
+    } else {
+        SAVE_THRESHOLD
+    }
+}
+
+/// take `size` seqnum for `key`, the range returned is inclusive and never overlaps with others.
+///
+/// nothing is taken if the range would go beyond `SEQNUM_MAX`.
+pub(self) fn reserve(
+    map: &DashMap<u128, AtomicU64>,
+    key: u128,
+    size: u64,
+) -> std::result::Result<(u64, u64), SeqnumError> {
+    let counter = map.entry(key).or_insert_with(|| AtomicU64::new(1));
+    let start = counter
+        .fetch_update(Ordering::AcqRel, Ordering::Acquire, |next| {
+            next.checked_add(size)
+                .filter(|end| *end - 1 <= SeqnumError::SEQNUM_MAX)
+        })
+        .map_err(|_| SeqnumError::Exhausted)?;
+    Ok((start, start + size - 1))
+}
+
+/// the key of a `Seqnum` request, refused until the node is registered to scheduler, since the
+/// keys are routed by the nodes registered.
+pub(self) fn parse_seqnum_request(
+    registered: bool,
+    payload: &[u8],
+) -> std::result::Result<u128, SeqnumError> {
+    if !registered {
+        return Err(SeqnumError::NotRegistered);
+    }
+    if payload.len() < 16 {
+        return Err(SeqnumError::InvalidRequest(format!(
+            "invalid seqnum request length: {}",
+            payload.len()
+        )));
+    }
+    Ok(BigEndian::read_u128(&payload[0..16]))
+}
+
+/// the key and size of a `SeqnumAllocBlock` request.
+pub(self) fn parse_block_request(
+    registered: bool,
+    payload: &[u8],
+) -> std::result::Result<(u128, u32), SeqnumError> {
+    if !registered {
+        return Err(SeqnumError::NotRegistered);
+    }
+    if payload.len() < 20 {
+        return Err(SeqnumError::InvalidRequest(format!(
+            "invalid block request length: {}",
+            payload.len()
+        )));
+    }
+    let size = BigEndian::read_u32(&payload[16..20]);
+    if size == 0 || size > MAX_BLOCK_SIZE {
+        return Err(SeqnumError::InvalidRequest(format!(
+            "invalid block size: {}",
+            size
+        )));
+    }
+    Ok((BigEndian::read_u128(&payload[0..16]), size))
+}
+
 #[async_trait(? Send)]
 impl ReqwestHandler for SeqNum {
     async fn run(&self, msg: &mut ReqwestMsg, states: &mut InnerStates) -> Result<ReqwestMsg> {
-        let key = BigEndian::read_u128(msg.payload());
+        let key = match parse_seqnum_request(current_health().ready, msg.payload()) {
+            Ok(key) => key,
+            Err(e) => return Ok(e.to_reqwest()),
+        };
         let generic_map = states
             .get("generic_map")
             .unwrap()
             .as_generic_parameter_map()
             .unwrap();
-        let seqnum;
-        {
-            seqnum = match generic_map
-                .get_parameter::<SeqnumMap>()
-                .unwrap()
-                .0
-                .entry(key)
-            {
-                Entry::Occupied(v) => v.get().fetch_add(1, Ordering::AcqRel),
-                Entry::Vacant(v) => {
-                    let seqnum = AtomicU64::new(2);
-                    v.insert(seqnum);
-                    1
-                }
-            };
-        }
+        let seqnum = match reserve(&generic_map.get_parameter::<SeqnumMap>().unwrap().0, key, 1) {
+            Ok((seqnum, _)) => seqnum,
+            Err(e) => return Ok(e.to_reqwest()),
+        };
         if config().server.exactly_mode {
             self.save(key, seqnum).await?;
         } else {
@@ -197,3 +327,166 @@ impl ReqwestHandler for SeqNum {
         ))
     }
 }
+
+/// hand out a block of seqnum in one request, the payload is `key(u128) + size(u32)`,
+/// and the response is the inclusive range `start(u64) + end(u64)`.
+pub(crate) struct SeqNumBlock {
+    inner: SeqNum,
+}
+
+impl SeqNumBlock {
+    pub(crate) async fn new() -> Self {
+        Self {
+            inner: SeqNum::new().await,
+        }
+    }
+}
+
+#[async_trait(? Send)]
+impl ReqwestHandler for SeqNumBlock {
+    async fn run(&self, msg: &mut ReqwestMsg, states: &mut InnerStates) -> Result<ReqwestMsg> {
+        let (key, size) = match parse_block_request(current_health().ready, msg.payload()) {
+            Ok(request) => request,
+            Err(e) => return Ok(e.to_reqwest()),
+        };
+        let generic_map = states
+            .get("generic_map")
+            .unwrap()
+            .as_generic_parameter_map()
+            .unwrap();
+        let (start, end) = match reserve(
+            &generic_map.get_parameter::<SeqnumMap>().unwrap().0,
+            key,
+            size as u64,
+        ) {
+            Ok(range) => range,
+            Err(e) => return Ok(e.to_reqwest()),
+        };
+        // the end of a block is the high-water mark, it must be saved before the block is handed out,
+        // otherwise a restart may reissue numbers of it.
+        self.inner.save(key, end).await?;
+        let mut buf = [0u8; 16];
+        BigEndian::write_u64(&mut buf[0..8], start);
+        BigEndian::write_u64(&mut buf[8..16], end);
+        Ok(ReqwestMsg::with_resource_id_payload(
+            msg.resource_id(),
+            &buf,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{atomic::AtomicU64, Arc};
+
+    use byteorder::{BigEndian, ByteOrder};
+    use dashmap::DashMap;
+    use lib::error::SeqnumError;
+
+    use super::{
+        checkpoint_margin, flush_checkpoint, load_checkpoint, parse_seqnum_request, reserve,
+        SAVE_THRESHOLD,
+    };
+    use crate::util::as_bytes;
+
+    #[test]
+    fn test_reserve_concurrent_block() {
+        let map: Arc<DashMap<u128, AtomicU64>> = Arc::new(DashMap::new());
+        let handles = (0..2)
+            .map(|_| {
+                let map = map.clone();
+                std::thread::spawn(move || {
+                    (0..1000)
+                        .map(|_| reserve(&map, 1, 64).unwrap())
+                        .collect::<Vec<(u64, u64)>>()
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut ranges = handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect::<Vec<(u64, u64)>>();
+        ranges.sort();
+        assert_eq!(ranges[0], (1, 64));
+        for pair in ranges.windows(2) {
+            assert_eq!(pair[0].1 + 1, pair[1].0);
+        }
+        assert_eq!(reserve(&map, 1, 1), Ok((128001, 128001)));
+    }
+
+    #[test]
+    fn test_restart_never_reissue() {
+        let dir = std::env::temp_dir().join(format!("prim-seqnum-{}", std::process::id()));
+        let dir = dir.to_str().unwrap();
+        _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir).unwrap();
+        // checkpoint every `SAVE_THRESHOLD` increments as `SeqNum` does without exactly mode.
+        let map: DashMap<u128, AtomicU64> = DashMap::new();
+        let mut buf = vec![0u8; 24];
+        let mut file = vec![];
+        let mut last = 0;
+        for _ in 0..(SAVE_THRESHOLD * 2 + 7) {
+            last = reserve(&map, 1, 1).unwrap().0;
+            if last & (SAVE_THRESHOLD - 1) == 0 {
+                as_bytes(1, last, &mut buf[..]);
+                file.extend_from_slice(&buf);
+            }
+        }
+        // a torn record left by the crash.
+        file.extend_from_slice(&buf[..10]);
+        std::fs::write(format!("{}/seqnum-0", dir), &file).unwrap();
+
+        let checkpoint = load_checkpoint(dir).unwrap();
+        let restarted: DashMap<u128, AtomicU64> = DashMap::new();
+        for (key, seqnum) in checkpoint {
+            restarted.insert(key, AtomicU64::new(seqnum + checkpoint_margin(false)));
+        }
+        assert!(reserve(&restarted, 1, 1).unwrap().0 > last);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_flush_checkpoint() {
+        let dir = std::env::temp_dir().join(format!("prim-seqnum-flush-{}", std::process::id()));
+        let dir = dir.to_str().unwrap();
+        _ = std::fs::remove_dir_all(dir);
+        let map: DashMap<u128, AtomicU64> = DashMap::new();
+        map.insert(1, AtomicU64::new(7));
+        map.insert(2, AtomicU64::new(SAVE_THRESHOLD + 3));
+        flush_checkpoint(dir, &map).unwrap();
+        map.get(&1)
+            .unwrap()
+            .store(9, std::sync::atomic::Ordering::Release);
+        flush_checkpoint(dir, &map).unwrap();
+
+        let checkpoint = load_checkpoint(dir).unwrap();
+        assert_eq!(checkpoint.len(), 2);
+        assert_eq!(checkpoint[&1], 9);
+        assert_eq!(checkpoint[&2], SAVE_THRESHOLD + 3);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_seqnum_errors() {
+        let mut payload = [0u8; 16];
+        BigEndian::write_u128(&mut payload, 1);
+        let err = parse_seqnum_request(false, &payload).unwrap_err();
+        assert_eq!(err, SeqnumError::NotRegistered);
+        assert_eq!(SeqnumError::from_reqwest(&err.to_reqwest()), Some(err));
+        assert_eq!(parse_seqnum_request(true, &payload), Ok(1));
+        assert!(matches!(
+            parse_seqnum_request(true, &payload[..8]),
+            Err(SeqnumError::InvalidRequest(_))
+        ));
+
+        let map: DashMap<u128, AtomicU64> = DashMap::new();
+        map.insert(1, AtomicU64::new(SeqnumError::SEQNUM_MAX - 1));
+        assert_eq!(reserve(&map, 1, 3), Err(SeqnumError::Exhausted));
+        // the failed one takes nothing.
+        assert_eq!(
+            reserve(&map, 1, 2),
+            Ok((SeqnumError::SEQNUM_MAX - 1, SeqnumError::SEQNUM_MAX))
+        );
+        assert_eq!(reserve(&map, 1, 1), Err(SeqnumError::Exhausted));
+    }
+}
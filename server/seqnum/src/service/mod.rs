@@ -1,6 +1,13 @@
-use std::sync::{atomic::{AtomicU64, AtomicBool}, Arc};
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use dashmap::DashMap;
+use futures::future::{select, Either};
 use lib::{net::GenericParameter, Result};
 
 use lazy_static::lazy_static;
@@ -40,6 +47,18 @@ impl SeqnumMap {
     }
 }
 
+/// serve until `STOP_SIGNAL` is set, the listener is dropped then, but accepted connections
+/// go on until the process exits.
 pub(crate) async fn start() -> Result<()> {
-    server::Server::run().await
+    match select(Box::pin(server::Server::run()), Box::pin(stopped())).await {
+        Either::Left((res, _)) => res,
+        Either::Right(_) => Ok(()),
+    }
+}
+
+/// resolve once `STOP_SIGNAL` is set by the signal handler.
+pub(crate) async fn stopped() {
+    while !STOP_SIGNAL.load(Ordering::Acquire) {
+        monoio::time::sleep(Duration::from_millis(100)).await;
+    }
 }
@@ -15,7 +15,7 @@ use local_sync::mpsc;
 use tracing::error;
 
 use crate::config::config;
-use super::{get_seqnum_map, handler::seqnum::SeqNum};
+use super::{get_seqnum_map, handler::seqnum::{SeqNum, SeqNumBlock}};
 
 pub(crate) struct ReqwestConnectionHandler {
     states: InnerStates,
@@ -108,10 +108,14 @@ impl Server {
             .with_max_connections(config().server.max_connections)
             .with_connection_idle_timeout(config().transport.connection_idle_timeout)
             .with_max_bi_streams(config().transport.max_bi_streams);
-        let server_config = config_builder.build().unwrap();
+        let server_config = config_builder.build()?;
 
         let mut handler_map: AHashMap<ReqwestResourceID, Box<dyn ReqwestHandler>> = AHashMap::new();
         handler_map.insert(ReqwestResourceID::Seqnum, Box::new(SeqNum::new().await));
+        handler_map.insert(
+            ReqwestResourceID::SeqnumAllocBlock,
+            Box::new(SeqNumBlock::new().await),
+        );
         let handler_map: ReqwestHandlerMap = Arc::new(handler_map);
         let generator: ReqwestHandlerGenerator =
             Box::new(move || -> Box<dyn NewReqwestConnectionHandler> {
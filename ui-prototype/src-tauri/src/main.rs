@@ -3,12 +3,16 @@
     windows_subsystem = "windows"
 )]
 
-use std::sync::Arc;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
 use config::{conf, load_config};
 use lib::{
     entity::{Msg, Type, GROUP_ID_THRESHOLD},
     net::client::ClientConfigBuilder,
+    util::inbox::{Inbox, Received},
 };
 
 use lazy_static::lazy_static;
@@ -33,6 +37,7 @@ mod util;
 lazy_static! {
     static ref MSG_SENDER: Arc<RwLock<Option<MsgSender>>> = Arc::new(RwLock::new(None));
     static ref MSG_RECEIVER: Arc<RwLock<Option<MsgMpscReceiver>>> = Arc::new(RwLock::new(None));
+    static ref INBOX: Mutex<Option<Inbox>> = Mutex::new(None);
     static ref SIGNAL_TX: Mutex<Option<tokio::sync::mpsc::Sender<u8>>> = Mutex::new(None);
     static ref SIGNAL_RX: Mutex<Option<tokio::sync::mpsc::Receiver<u8>>> = Mutex::new(None);
     static ref CLIENT_HOLDER1: Mutex<Option<Client>> = Mutex::new(None);
@@ -42,6 +47,11 @@ lazy_static! {
 const CONNECTED: u8 = 1;
 const DISCONNECTED: u8 = 2;
 
+/// msgs of a conversation arriving after a missing one before it's regarded as lost.
+const RECV_WINDOW: usize = 64;
+/// how long msgs wait for a missing one before it before they are shown anyway.
+const RECV_TIMEOUT: Duration = Duration::from_secs(3);
+
 static mut LOCAL_DATA_DIR: &'static str = ".";
 
 async fn load_signal() {
@@ -75,6 +85,7 @@ async fn main() -> tauri::Result<()> {
             connect,
             disconnect,
             send,
+            send_file,
             set_kv,
             get_kv,
             del_kv,
@@ -211,6 +222,12 @@ async fn connect(params: ConnectParams) -> std::result::Result<(), String> {
             return Err("invalid mode".to_string());
         }
     }
+    INBOX.lock().await.replace(Inbox::new(
+        user_id,
+        node_id as u32,
+        RECV_WINDOW,
+        RECV_TIMEOUT,
+    ));
     let tx = &(*SIGNAL_TX.lock().await);
     let tx = tx.as_ref().unwrap();
     if let Err(e) = tx.send(CONNECTED).await {
@@ -227,6 +244,9 @@ struct SendParams {
 #[tauri::command]
 async fn send(params: SendParams) -> std::result::Result<(), String> {
     let msg = Msg(params.raw);
+    if let Some(inbox) = INBOX.lock().await.as_mut() {
+        inbox.sent(&msg);
+    }
     let msg_sender = MSG_SENDER.read().await;
     match *msg_sender {
         Some(ref sender) => {
@@ -241,6 +261,43 @@ async fn send(params: SendParams) -> std::result::Result<(), String> {
     Ok(())
 }
 
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+struct SendFileParams {
+    receiver: serde_json::Value,
+    file: Vec<u8>,
+}
+
+/// send a file in chunks, return the file id the receiver gets it with.
+#[tauri::command]
+async fn send_file(params: SendFileParams) -> std::result::Result<u64, String> {
+    let receiver = preparse(params.receiver).as_u64().unwrap();
+    let file_id = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_micros() as u64;
+    let chunks = match INBOX.lock().await.as_mut() {
+        Some(inbox) => match inbox.send_file(receiver, file_id, &params.file) {
+            Ok(chunks) => chunks,
+            Err(e) => return Err(e.to_string()),
+        },
+        None => return Err("not connected".to_string()),
+    };
+    let msg_sender = MSG_SENDER.read().await;
+    match *msg_sender {
+        Some(ref sender) => {
+            for chunk in chunks {
+                if let Err(e) = sender.send(Arc::new(chunk)).await {
+                    return Err(e.to_string());
+                }
+            }
+        }
+        None => {
+            return Err("not connected".to_string());
+        }
+    }
+    Ok(file_id)
+}
+
 #[tauri::command]
 async fn disconnect() -> Result<(), String> {
     let tx = &(*SIGNAL_TX.lock().await);
@@ -251,6 +308,29 @@ async fn disconnect() -> Result<(), String> {
     Ok(())
 }
 
+/// show what the inbox has ready, and send its replies, such as the resync asking for the msgs
+/// lost, see `Inbox`.
+async fn handle_received(window: &Window<Wry>, received: Received) {
+    if let Some(ref sender) = *MSG_SENDER.read().await {
+        for reply in received.reply {
+            if let Err(e) = sender.send(Arc::new(reply)).await {
+                error!("reply error: {}", e);
+            }
+        }
+    }
+    for msg in received.ready {
+        window.emit("recv", msg.as_slice()).unwrap();
+    }
+    for (sender, file_id, file) in received.files {
+        window
+            .emit(
+                "recv_file",
+                json!({"sender": sender, "file_id": file_id, "file": file}),
+            )
+            .unwrap();
+    }
+}
+
 fn setup(window: Window<Wry>) {
     tokio::spawn(async move {
         let mut signal_rx = SIGNAL_RX.lock().await.take().unwrap();
@@ -269,10 +349,40 @@ fn setup(window: Window<Wry>) {
                     let window = window.clone();
                     tokio::spawn(async move {
                         loop {
-                            let msg = msg_receiver.recv().await;
+                            let deadline = match INBOX.lock().await.as_ref() {
+                                Some(inbox) => inbox.next_deadline(),
+                                None => None,
+                            };
+                            let expired = async move {
+                                match deadline {
+                                    Some(deadline) => {
+                                        tokio::time::sleep_until(deadline.into()).await
+                                    }
+                                    None => std::future::pending().await,
+                                }
+                            };
+                            let msg = tokio::select! {
+                                msg = msg_receiver.recv() => msg,
+                                _ = expired => {
+                                    // the missing ones are given up.
+                                    let received = match INBOX.lock().await.as_mut() {
+                                        Some(inbox) => inbox.expire(Instant::now()),
+                                        None => Received::default(),
+                                    };
+                                    handle_received(&window, received).await;
+                                    continue;
+                                }
+                            };
                             match msg {
                                 Some(msg) => {
-                                    window.emit("recv", msg.as_slice()).unwrap();
+                                    let received = match INBOX.lock().await.as_mut() {
+                                        Some(inbox) => inbox.receive(msg),
+                                        None => Received {
+                                            ready: vec![msg],
+                                            ..Received::default()
+                                        },
+                                    };
+                                    handle_received(&window, received).await;
                                 }
                                 None => {
                                     break;